@@ -2,15 +2,19 @@
 extern crate criterion;
 extern crate blockstack_lib;
 extern crate rand;
+extern crate rayon;
 
+use blockstack_lib::chainstate::stacks::index::{ClarityMarfTrieId, MARFValue};
 use blockstack_lib::chainstate::stacks::Error;
-use blockstack_lib::types::proof::ClarityMarfTrieId;
 use criterion::Criterion;
 use rand::prelude::*;
 use std::fs;
 
-use blockstack_lib::chainstate::stacks::index::{marf::MARF, storage::TrieFileStorage};
-use blockstack_lib::types::chainstate::{MARFValue, StacksBlockId};
+use blockstack_lib::chainstate::stacks::index::{
+    marf::{MARFOpenOpts, MARF},
+    storage::TrieFileStorage,
+};
+use blockstack_lib::types::chainstate::StacksBlockId;
 
 pub fn begin(
     marf: &mut MARF<StacksBlockId>,
@@ -32,7 +36,7 @@ fn benchmark_marf_usage(
     if fs::metadata(filename).is_ok() {
         fs::remove_file(filename).unwrap();
     };
-    let f = TrieFileStorage::open(filename).unwrap();
+    let f = TrieFileStorage::open(filename, MARFOpenOpts::default()).unwrap();
     let mut block_header = StacksBlockId::from_bytes(&[0u8; 32]).unwrap();
     let mut marf = MARF::from_storage(f);
 
@@ -85,7 +89,7 @@ fn benchmark_marf_usage(
 }
 
 fn benchmark_marf_read(filename: &str, reads: u32, block: u32, writes_per_block: u32) {
-    let f = TrieFileStorage::open(filename).unwrap();
+    let f = TrieFileStorage::open(filename, MARFOpenOpts::default()).unwrap();
     let mut block_header = block.to_le_bytes().to_vec();
     block_header.resize(32, 0);
     let block_header = StacksBlockId::from_bytes(block_header.as_slice()).unwrap();
@@ -102,6 +106,53 @@ fn benchmark_marf_read(filename: &str, reads: u32, block: u32, writes_per_block:
     }
 }
 
+/// Insert `writes_per_block` keys into each of `blocks` tries and commit them, hashing each trie on
+/// a thread pool with `threads` threads (0 means one per CPU).  With one thread, this measures
+/// sequential trie hashing.
+fn benchmark_marf_seal(blocks: u32, writes_per_block: u32, threads: usize) {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    let mut marf = MARF::from_path(":memory:", MARFOpenOpts::default()).unwrap();
+    let mut rng = rand::thread_rng();
+
+    let mut parent = StacksBlockId::sentinel();
+    for i in 0..blocks {
+        let mut block_header = (i + 1).to_le_bytes().to_vec();
+        block_header.resize(32, 0);
+        let block_header = StacksBlockId::from_bytes(block_header.as_slice()).unwrap();
+
+        begin(&mut marf, &parent, &block_header).unwrap();
+
+        let mut batch_keys = Vec::new();
+        let mut batch_vals = Vec::new();
+        for k in 0..writes_per_block {
+            let mut value = [0u8; 40];
+            rng.fill_bytes(&mut value);
+            batch_keys.push(format!("{}::{}", i, k));
+            batch_vals.push(MARFValue(value));
+        }
+        marf.insert_batch(&batch_keys, batch_vals).unwrap();
+
+        // committing seals the trie
+        pool.install(|| marf.commit().unwrap());
+        parent = block_header;
+    }
+}
+
+pub fn seal_benchmark(c: &mut Criterion) {
+    c.bench_function("marf_seal_10b_10kW_1thread", |b| {
+        b.iter(|| benchmark_marf_seal(10, 10000, 1))
+    });
+    c.bench_function("marf_seal_10b_10kW_4threads", |b| {
+        b.iter(|| benchmark_marf_seal(10, 10000, 4))
+    });
+    c.bench_function("marf_seal_10b_10kW_ncpus", |b| {
+        b.iter(|| benchmark_marf_seal(10, 10000, 0))
+    });
+}
+
 pub fn basic_usage_benchmark(c: &mut Criterion) {
     c.bench_function("marf_setup_1000b_5kW", |b| {
         b.iter(|| benchmark_marf_usage("/tmp/db.1k.sqlite", 1000, 5000, 0, false))
@@ -132,5 +183,5 @@ pub fn basic_usage_benchmark(c: &mut Criterion) {
 
 pub fn scaling_read_ratio(_c: &mut Criterion) {}
 
-criterion_group!(benches, basic_usage_benchmark);
+criterion_group!(benches, basic_usage_benchmark, seal_benchmark);
 criterion_main!(benches);
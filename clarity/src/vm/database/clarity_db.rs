@@ -331,6 +331,13 @@ impl<'a> ClarityDatabase<'a> {
         self.store.get_with_proof(key)
     }
 
+    pub fn get_batch_with_proof(
+        &mut self,
+        keys: &[String],
+    ) -> Result<Option<(Vec<Option<String>>, Vec<u8>)>> {
+        self.store.get_batch_with_proof(keys)
+    }
+
     pub fn make_key_for_trip(
         contract_identifier: &QualifiedContractIdentifier,
         data: StoreType,
@@ -62,6 +62,16 @@ pub trait ClarityBackingStore {
     /// fetch K-V out of the committed datastore, along with the byte representation
     ///  of the Merkle proof for that key-value pair
     fn get_with_proof(&mut self, key: &str) -> Option<(String, Vec<u8>)>;
+    /// fetch several K-V pairs out of the committed datastore (None for each absent key), along
+    ///  with the byte representation of a batch Merkle proof of each key's inclusion or
+    ///  non-inclusion.  Returns None if this datastore can't make such proofs, and an error if
+    ///  the datastore failed to read them.
+    fn get_batch_with_proof(
+        &mut self,
+        _keys: &[String],
+    ) -> Result<Option<(Vec<Option<String>>, Vec<u8>)>> {
+        Ok(None)
+    }
     fn has_entry(&mut self, key: &str) -> bool {
         self.get(key).is_some()
     }
@@ -345,6 +345,15 @@ impl<'a> RollbackWrapper<'a> {
             .map(|(value, proof)| (T::deserialize(&value), proof))
     }
 
+    /// this function will only return commitment proofs for values _already_ materialized
+    ///  in the underlying store, and only if the underlying store can make batch proofs.
+    pub fn get_batch_with_proof(
+        &mut self,
+        keys: &[String],
+    ) -> Result<Option<(Vec<Option<String>>, Vec<u8>)>> {
+        self.store.get_batch_with_proof(keys)
+    }
+
     pub fn get<T>(&mut self, key: &str) -> Option<T>
     where
         T: ClarityDeserializable<T>,
@@ -0,0 +1,290 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoder for the consensus encoding of MARF proofs.  This mirrors the `StacksMessageCodec`
+//! implementations for `TrieMerkleProof` and `TrieMerkleBatchProof` in `blockstack_lib`: integers
+//! are big-endian, and lists are prefixed with a 4-byte length.
+
+use crate::{
+    hex_bytes, Error, MARFBatchProof, MARFProof, ProofEntry, ProofTrieLeaf, ProofTrieNode,
+    ProofTriePtr, TrieHash,
+};
+
+/// Proof entry type bytes
+pub const PROOF_TYPE_NODE4: u8 = 0;
+pub const PROOF_TYPE_NODE16: u8 = 1;
+pub const PROOF_TYPE_NODE48: u8 = 2;
+pub const PROOF_TYPE_NODE256: u8 = 3;
+pub const PROOF_TYPE_LEAF: u8 = 4;
+pub const PROOF_TYPE_SHUNT: u8 = 5;
+pub const PROOF_TYPE_TERMINAL: u8 = 6;
+
+/// Cursor over an encoded proof
+struct ProofReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ProofReader<'a> {
+    fn new(bytes: &'a [u8]) -> ProofReader<'a> {
+        ProofReader { bytes, offset: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.offset
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.remaining() < len {
+            return Err(Error::DeserializeError(format!(
+                "Need {} bytes at offset {}, but only {} remain",
+                len,
+                self.offset,
+                self.remaining()
+            )));
+        }
+        let ret = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(ret)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, Error> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.read_bytes(8)?);
+        Ok(i64::from_be_bytes(buf))
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(self.read_bytes(N)?);
+        Ok(buf)
+    }
+
+    /// Read a list's length prefix.  Each item takes at least `min_item_len` bytes, so a length
+    /// that could not possibly fit in the remaining input is rejected before anything is
+    /// allocated.
+    fn read_len(&mut self, min_item_len: usize) -> Result<usize, Error> {
+        let len = self.read_u32()? as usize;
+        if len.saturating_mul(min_item_len) > self.remaining() {
+            return Err(Error::DeserializeError(format!(
+                "List of {} items does not fit in {} remaining bytes",
+                len,
+                self.remaining()
+            )));
+        }
+        Ok(len)
+    }
+
+    fn read_hashes(&mut self, count: usize) -> Result<Vec<TrieHash>, Error> {
+        let mut hashes = Vec::with_capacity(count);
+        for _ in 0..count {
+            hashes.push(self.read_array::<32>()?);
+        }
+        Ok(hashes)
+    }
+
+    fn read_hash_list(&mut self) -> Result<Vec<TrieHash>, Error> {
+        let len = self.read_len(32)?;
+        self.read_hashes(len)
+    }
+
+    fn read_path(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.read_len(1)?;
+        Ok(self.read_bytes(len)?.to_vec())
+    }
+
+    fn read_node(&mut self) -> Result<ProofTrieNode, Error> {
+        let id = self.read_u8()?;
+        let path = self.read_path()?;
+        let num_ptrs = self.read_len(34)?;
+        let mut ptrs = Vec::with_capacity(num_ptrs);
+        for _ in 0..num_ptrs {
+            let id = self.read_u8()?;
+            let chr = self.read_u8()?;
+            let back_block = self.read_array::<32>()?;
+            ptrs.push(ProofTriePtr {
+                id,
+                chr,
+                back_block,
+            });
+        }
+        Ok(ProofTrieNode { id, path, ptrs })
+    }
+
+    fn read_entry(&mut self) -> Result<ProofEntry, Error> {
+        let type_byte = self.read_u8()?;
+        let entry = match type_byte {
+            PROOF_TYPE_NODE4 | PROOF_TYPE_NODE16 | PROOF_TYPE_NODE48 | PROOF_TYPE_NODE256 => {
+                let num_hashes = match type_byte {
+                    PROOF_TYPE_NODE4 => 3,
+                    PROOF_TYPE_NODE16 => 15,
+                    PROOF_TYPE_NODE48 => 47,
+                    _ => 255,
+                };
+                let chr = self.read_u8()?;
+                let node = self.read_node()?;
+                let hashes = self.read_hashes(num_hashes)?;
+                ProofEntry::Node(chr, node, hashes)
+            }
+            PROOF_TYPE_LEAF => {
+                let chr = self.read_u8()?;
+                let path = self.read_path()?;
+                let data = self.read_array::<40>()?;
+                ProofEntry::Leaf(chr, ProofTrieLeaf { path, data })
+            }
+            PROOF_TYPE_SHUNT => {
+                let idx = self.read_i64()?;
+                let hashes = self.read_hash_list()?;
+                ProofEntry::Shunt(idx, hashes)
+            }
+            PROOF_TYPE_TERMINAL => {
+                let node = self.read_node()?;
+                let hashes = self.read_hash_list()?;
+                ProofEntry::Terminal(node, hashes)
+            }
+            _ => {
+                return Err(Error::DeserializeError(format!(
+                    "Bad type byte in proof entry: {}",
+                    type_byte
+                )));
+            }
+        };
+        Ok(entry)
+    }
+
+    fn read_entries(&mut self) -> Result<Vec<ProofEntry>, Error> {
+        let len = self.read_len(1)?;
+        let mut entries = Vec::with_capacity(len);
+        for _ in 0..len {
+            entries.push(self.read_entry()?);
+        }
+        Ok(entries)
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if self.remaining() > 0 {
+            return Err(Error::DeserializeError(format!(
+                "{} trailing bytes after proof",
+                self.remaining()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl MARFProof {
+    /// Decode a proof from its consensus encoding
+    pub fn from_bytes(bytes: &[u8]) -> Result<MARFProof, Error> {
+        let mut reader = ProofReader::new(bytes);
+        let entries = reader.read_entries()?;
+        reader.finish()?;
+        Ok(MARFProof(entries))
+    }
+
+    /// Decode a hex-encoded proof, such as the ones returned by the node's RPC interface
+    pub fn from_hex(hex: &str) -> Result<MARFProof, Error> {
+        MARFProof::from_bytes(&hex_bytes(hex)?)
+    }
+}
+
+impl MARFBatchProof {
+    /// Decode a batch proof from its consensus encoding
+    pub fn from_bytes(bytes: &[u8]) -> Result<MARFBatchProof, Error> {
+        let mut reader = ProofReader::new(bytes);
+        let entries = reader.read_entries()?;
+
+        let num_proofs = reader.read_len(4)?;
+        let mut proofs = Vec::with_capacity(num_proofs);
+        for _ in 0..num_proofs {
+            let num_indexes = reader.read_len(4)?;
+            let mut indexes = Vec::with_capacity(num_indexes);
+            for _ in 0..num_indexes {
+                indexes.push(reader.read_u32()?);
+            }
+            proofs.push(indexes);
+        }
+        reader.finish()?;
+        Ok(MARFBatchProof { entries, proofs })
+    }
+
+    /// Decode a hex-encoded batch proof
+    pub fn from_hex(hex: &str) -> Result<MARFBatchProof, Error> {
+        MARFBatchProof::from_bytes(&hex_bytes(hex)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_shunt_only_proof() {
+        let mut bytes = vec![0, 0, 0, 1, PROOF_TYPE_SHUNT];
+        bytes.extend_from_slice(&0i64.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 1]);
+        bytes.extend_from_slice(&[0xab; 32]);
+
+        let proof = MARFProof::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            proof,
+            MARFProof(vec![ProofEntry::Shunt(0, vec![[0xab; 32]])])
+        );
+
+        // trailing bytes
+        bytes.push(0);
+        assert!(MARFProof::from_bytes(&bytes).is_err());
+
+        // truncated
+        bytes.truncate(bytes.len() - 2);
+        assert!(MARFProof::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_oversized_lengths() {
+        // claims 2^32-1 entries
+        let bytes = vec![0xff, 0xff, 0xff, 0xff, PROOF_TYPE_SHUNT];
+        assert!(MARFProof::from_bytes(&bytes).is_err());
+
+        // bad type byte
+        let bytes = vec![0, 0, 0, 1, 7];
+        assert!(MARFProof::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_decode_batch_proof() {
+        let mut bytes = vec![0, 0, 0, 1, PROOF_TYPE_SHUNT];
+        bytes.extend_from_slice(&0i64.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        // two proofs, both referring to entry 0
+        bytes.extend_from_slice(&[0, 0, 0, 2]);
+        bytes.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0]);
+
+        let proof = MARFBatchProof::from_bytes(&bytes).unwrap();
+        assert_eq!(proof.entries, vec![ProofEntry::Shunt(0, vec![])]);
+        assert_eq!(proof.proofs, vec![vec![0], vec![0]]);
+    }
+}
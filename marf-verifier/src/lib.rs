@@ -0,0 +1,276 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Standalone verifier for MARF proofs produced by a Stacks node.
+//!
+//! This crate decodes the consensus encoding of the proofs that `blockstack_lib` generates
+//! (`TrieMerkleProof` and `TrieMerkleBatchProof`), and checks them against a trie root hash.
+//! It only depends on `sha2`, so light clients can link it without pulling in the node's
+//! storage and networking stack.
+//!
+//! A verifier needs three things besides the proof:
+//! * the key's `TriePath` (see `path_from_key()`),
+//! * the expected `MARFValue` for inclusion proofs (see `value_from_data()`),
+//! * the root hash of the trie at the chain tip, and a map from every ancestor trie's root hash
+//!   to its block ID.  Both can be calculated independently from the chain's block headers.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use sha2::Digest;
+use sha2::Sha512_256 as TrieHasher;
+
+pub mod codec;
+pub mod verify;
+
+pub use crate::verify::{verify_inclusion, verify_noninclusion};
+
+/// Hash of a trie node, or the root hash of a trie
+pub type TrieHash = [u8; 32];
+/// Identifier of the block that a trie belongs to (i.e. a `StacksBlockId`)
+pub type BlockId = [u8; 32];
+/// A MARF key, hashed
+pub type TriePath = [u8; 32];
+/// The value stored in a MARF leaf
+pub type MARFValue = [u8; 40];
+
+pub const TRIEPATH_MAX_LEN: usize = 32;
+
+/// Node IDs, as they appear in a node's consensus encoding
+pub const NODE_ID_EMPTY: u8 = 0;
+pub const NODE_ID_LEAF: u8 = 1;
+pub const NODE_ID_NODE4: u8 = 2;
+pub const NODE_ID_NODE16: u8 = 3;
+pub const NODE_ID_NODE48: u8 = 4;
+pub const NODE_ID_NODE256: u8 = 5;
+
+/// Child pointer of a node in a proof.  `back_block` is the ID of the block that contains the
+/// child if it is a back-pointer, and all 0's otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofTriePtr {
+    pub id: u8,
+    pub chr: u8,
+    pub back_block: BlockId,
+}
+
+/// Interior trie node in a proof
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofTrieNode {
+    pub id: u8,
+    pub path: Vec<u8>,
+    pub ptrs: Vec<ProofTriePtr>,
+}
+
+/// Leaf in a proof
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofTrieLeaf {
+    pub path: Vec<u8>,
+    pub data: MARFValue,
+}
+
+/// One entry in a MARF proof
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProofEntry {
+    /// Interior node on the path to the key.  Carries the path character of the child being
+    /// proven and the hashes of every other child.
+    Node(u8, ProofTrieNode, Vec<TrieHash>),
+    /// Leaf at the end of the path.  Carries the leaf's path character in its parent.
+    Leaf(u8, ProofTrieLeaf),
+    /// Interior node at which a walk down the key's path stops because the key is absent.
+    /// Carries the hashes of all of its children.
+    Terminal(ProofTrieNode, Vec<TrieHash>),
+    /// Shunt proof entry linking a trie to its ancestors' root hashes
+    Shunt(i64, Vec<TrieHash>),
+}
+
+/// A single-key MARF proof
+#[derive(Debug, Clone, PartialEq)]
+pub struct MARFProof(pub Vec<ProofEntry>);
+
+/// A multi-key MARF proof.  Proof entries that several keys have in common (i.e. shared path
+/// segments and shunt proofs) are stored once in `entries`, and each key's proof is the list of
+/// indexes into `entries` in `proofs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MARFBatchProof {
+    pub entries: Vec<ProofEntry>,
+    pub proofs: Vec<Vec<u32>>,
+}
+
+/// Lookup from a trie's root hash to the block that trie belongs to
+pub trait TrieRootMap {
+    fn block_for_root(&self, root_hash: &TrieHash) -> Option<BlockId>;
+}
+
+impl TrieRootMap for HashMap<TrieHash, BlockId> {
+    fn block_for_root(&self, root_hash: &TrieHash) -> Option<BlockId> {
+        self.get(root_hash).cloned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Proof bytes could not be decoded
+    DeserializeError(String),
+    /// Proof is structurally invalid
+    MalformedProof(String),
+    /// Proof does not follow the given key's path
+    PathMismatch,
+    /// Proof is for a different value
+    ValueMismatch,
+    /// Non-inclusion proof shows that the key is present
+    KeyPresent,
+    /// A trie root hash in the proof does not correspond to any known block
+    UnknownTrieRoot(TrieHash),
+    /// Proof does not hash to the expected root
+    RootHashMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DeserializeError(ref s) => write!(f, "Failed to decode proof: {}", s),
+            Error::MalformedProof(ref s) => write!(f, "Malformed proof: {}", s),
+            Error::PathMismatch => write!(f, "Proof does not follow the key's path"),
+            Error::ValueMismatch => write!(f, "Proof is for a different value"),
+            Error::KeyPresent => write!(f, "Proof shows that the key is present"),
+            Error::UnknownTrieRoot(ref h) => {
+                write!(f, "Trie root hash {} does not map to a block", to_hex(h))
+            }
+            Error::RootHashMismatch => write!(f, "Proof does not hash to the root hash"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        None
+    }
+}
+
+/// SHA512/256 of the concatenation of the given byte strings
+pub fn hash_data_array<B: AsRef<[u8]>>(data: &[B]) -> TrieHash {
+    let mut hasher = TrieHasher::new();
+    for item in data.iter() {
+        hasher.update(item);
+    }
+    let mut ret = [0u8; 32];
+    ret.copy_from_slice(hasher.finalize().as_slice());
+    ret
+}
+
+/// Calculate the path of a MARF key
+pub fn path_from_key(key: &str) -> TriePath {
+    hash_data_array(&[key.as_bytes()])
+}
+
+/// Calculate the MARF value that a node stores for the given data string
+pub fn value_from_data(data: &str) -> MARFValue {
+    let hash = hash_data_array(&[data.as_bytes()]);
+    let mut ret = [0u8; 40];
+    ret[0..32].copy_from_slice(&hash);
+    ret
+}
+
+/// Hash of a leaf node
+pub fn leaf_hash(leaf: &ProofTrieLeaf) -> TrieHash {
+    hash_data_array(&[
+        &[NODE_ID_LEAF, leaf.path.len() as u8][..],
+        &leaf.path[..],
+        &leaf.data[..],
+    ])
+}
+
+/// Hash of an interior node, given all of its children's hashes
+pub fn node_hash(node: &ProofTrieNode, child_hashes: &[TrieHash]) -> TrieHash {
+    let mut hasher = TrieHasher::new();
+    hasher.update([node.id]);
+    for ptr in node.ptrs.iter() {
+        hasher.update([ptr.id, ptr.chr]);
+        hasher.update(ptr.back_block);
+    }
+    hasher.update([node.path.len() as u8]);
+    hasher.update(&node.path);
+    for child_hash in child_hashes.iter() {
+        hasher.update(child_hash);
+    }
+    let mut ret = [0u8; 32];
+    ret.copy_from_slice(hasher.finalize().as_slice());
+    ret
+}
+
+/// Number of child pointers a node with the given ID has, if it is an interior node
+pub fn node_ptr_count(id: u8) -> Option<usize> {
+    match id {
+        NODE_ID_NODE4 => Some(4),
+        NODE_ID_NODE16 => Some(16),
+        NODE_ID_NODE48 => Some(48),
+        NODE_ID_NODE256 => Some(256),
+        _ => None,
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes.iter() {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+/// Decode a hex string, with or without a leading `0x`
+pub fn hex_bytes(s: &str) -> Result<Vec<u8>, Error> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if s.len() & 1 == 1 {
+        return Err(Error::DeserializeError("odd-length hex string".into()));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| Error::DeserializeError("invalid hex string".into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_helpers() {
+        // sha512/256 of the empty string
+        assert_eq!(
+            to_hex(&hash_data_array::<&[u8]>(&[])),
+            "c672b8d1ef56ed28ab87c3622c5114069bdd3ad7b8f9737498d0c01ecef0967a"
+        );
+        assert_eq!(path_from_key(""), hash_data_array::<&[u8]>(&[]));
+
+        let value = value_from_data("hello world");
+        assert_eq!(&value[0..32], &hash_data_array(&["hello world".as_bytes()]));
+        assert_eq!(&value[32..40], &[0u8; 8]);
+    }
+
+    #[test]
+    fn test_hex_bytes() {
+        assert_eq!(hex_bytes("0x00ff10").unwrap(), vec![0x00, 0xff, 0x10]);
+        assert_eq!(hex_bytes("00ff10").unwrap(), vec![0x00, 0xff, 0x10]);
+        assert!(hex_bytes("0x0").is_err());
+        assert!(hex_bytes("zz").is_err());
+        assert_eq!(to_hex(&hex_bytes("abcdef").unwrap()), "abcdef");
+    }
+}
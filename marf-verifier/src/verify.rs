@@ -0,0 +1,783 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! MARF proof verification.
+//!
+//! A MARF proof is a sequence of alternating segment proofs and shunt proofs.  Each segment proof
+//! is a path of nodes from a trie's root down to either the node that ends the key's path (for
+//! the first segment), or a node whose child is a back-pointer into an ancestor trie (for all
+//! subsequent segments).  Each shunt proof links a segment's trie root to the root hashes of its
+//! ancestor tries, so the verifier can walk from the oldest trie that the path touches all the way
+//! up to the trie at the chain tip.
+
+use crate::{
+    leaf_hash, node_hash, node_ptr_count, Error, MARFBatchProof, MARFProof, MARFValue, ProofEntry,
+    ProofTrieNode, TrieHash, TriePath, TrieRootMap, NODE_ID_EMPTY, TRIEPATH_MAX_LEN,
+};
+
+/// Find the index of the first shunt proof entry at or after `start`
+fn next_shunt(proof: &[ProofEntry], start: usize) -> usize {
+    let mut j = start;
+    while j < proof.len() {
+        if let ProofEntry::Shunt(..) = proof[j] {
+            break;
+        }
+        j += 1;
+    }
+    j
+}
+
+/// Calculate the next trie root hash in a shunt proof, by inserting `hash` into `hashes` at
+/// `idx - 1`
+fn next_shunt_hash(hash: &TrieHash, idx: i64, hashes: &[TrieHash]) -> Result<TrieHash, Error> {
+    if idx == 0 {
+        return Err(Error::MalformedProof(
+            "intermediate shunt proof entry must have idx > 0".into(),
+        ));
+    }
+    let mut all_hashes = Vec::with_capacity(hashes.len() + 1);
+    let mut hash_idx = 0;
+    for i in 0..hashes.len() + 1 {
+        if idx - 1 == (i as i64) {
+            all_hashes.push(*hash);
+        } else {
+            if hash_idx >= hashes.len() {
+                return Err(Error::MalformedProof(format!(
+                    "shunt proof index {} is out of bounds",
+                    idx
+                )));
+            }
+            all_hashes.push(hashes[hash_idx]);
+            hash_idx += 1;
+        }
+    }
+    Ok(crate::hash_data_array(&all_hashes))
+}
+
+/// Verify the head of a shunt proof, which links the first segment's root node hash to the
+/// trie's ancestor hashes.  Returns the trie's root hash.
+fn verify_shunt_proof_head(
+    node_root_hash: &TrieHash,
+    shunt_proof_head: &ProofEntry,
+) -> Result<TrieHash, Error> {
+    match shunt_proof_head {
+        ProofEntry::Shunt(idx, hashes) => {
+            if *idx != 0 {
+                return Err(Error::MalformedProof(
+                    "first shunt proof entry must have idx == 0".into(),
+                ));
+            }
+            if hashes.is_empty() {
+                // a trie with no ancestors -- its hash is just the root node hash
+                return Ok(*node_root_hash);
+            }
+            let mut all_hashes = Vec::with_capacity(hashes.len() + 1);
+            all_hashes.push(*node_root_hash);
+            all_hashes.extend_from_slice(hashes);
+            Ok(crate::hash_data_array(&all_hashes))
+        }
+        _ => Err(Error::MalformedProof(
+            "shunt proof head is not a shunt proof entry".into(),
+        )),
+    }
+}
+
+/// Verify the tail of a shunt proof, given the backptr's trie root hash.  Returns the
+/// penultimate trie hash.
+fn verify_shunt_proof_tail(
+    initial_hash: &TrieHash,
+    shunt_proof: &[ProofEntry],
+) -> Result<TrieHash, Error> {
+    let mut hash = *initial_hash;
+    for proof_entry in shunt_proof.iter() {
+        hash = match proof_entry {
+            ProofEntry::Shunt(idx, hashes) => next_shunt_hash(&hash, *idx, hashes)?,
+            _ => {
+                return Err(Error::MalformedProof(
+                    "shunt proof item is not a shunt proof entry".into(),
+                ));
+            }
+        };
+    }
+    Ok(hash)
+}
+
+/// Verify a shunt juncture, where a shunt proof tail and a segment proof meet.  Returns the root
+/// hash of the trie at the junction.
+fn verify_shunt_proof_junction(
+    node_root_hash: &TrieHash,
+    penultimate_trie_hash: &TrieHash,
+    shunt_proof_junction: &ProofEntry,
+) -> Result<TrieHash, Error> {
+    match shunt_proof_junction {
+        ProofEntry::Shunt(idx, hashes) => {
+            if *idx == 0 {
+                return Err(Error::MalformedProof(
+                    "shunt proof junction entry must not have idx == 0".into(),
+                ));
+            }
+
+            // the node root hash (from the subsequent segment proof) comes first, and the
+            // penultimate trie hash goes at idx
+            let mut all_hashes = Vec::with_capacity(hashes.len() + 2);
+            let mut hash_idx = 0;
+            all_hashes.push(*node_root_hash);
+            for i in 0..hashes.len() + 1 {
+                if *idx - 1 == (i as i64) {
+                    all_hashes.push(*penultimate_trie_hash);
+                } else {
+                    if hash_idx >= hashes.len() {
+                        return Err(Error::MalformedProof(format!(
+                            "shunt proof junction index {} is out of bounds",
+                            idx
+                        )));
+                    }
+                    all_hashes.push(hashes[hash_idx]);
+                    hash_idx += 1;
+                }
+            }
+            Ok(crate::hash_data_array(&all_hashes))
+        }
+        _ => Err(Error::MalformedProof(
+            "shunt proof junction is not a shunt proof entry".into(),
+        )),
+    }
+}
+
+/// Check that an interior node's pointer list is consistent with its ID
+fn check_node_ptrs(node: &ProofTrieNode) -> Result<usize, Error> {
+    let count = node_ptr_count(node.id)
+        .ok_or_else(|| Error::MalformedProof(format!("invalid interior node ID {}", node.id)))?;
+    if node.ptrs.len() != count {
+        return Err(Error::MalformedProof(format!(
+            "node with ID {} has {} pointers",
+            node.id,
+            node.ptrs.len()
+        )));
+    }
+    if node.path.len() > TRIEPATH_MAX_LEN {
+        return Err(Error::MalformedProof("node path is too long".into()));
+    }
+    Ok(count)
+}
+
+/// Given an interior node in a segment proof, the hash of the child at `chr`, and the hashes
+/// of its other children, calculate the node's hash.
+fn get_segment_proof_hash(
+    node: &ProofTrieNode,
+    hash: &TrieHash,
+    chr: u8,
+    hashes: &[TrieHash],
+) -> Result<TrieHash, Error> {
+    let count = check_node_ptrs(node)?;
+    if hashes.len() + 1 != count {
+        return Err(Error::MalformedProof(format!(
+            "node with ID {} has {} sibling hashes",
+            node.id,
+            hashes.len()
+        )));
+    }
+
+    let mut all_hashes = Vec::with_capacity(count);
+    let mut ih = 0;
+    for child_ptr in node.ptrs.iter() {
+        if child_ptr.id != NODE_ID_EMPTY && child_ptr.chr == chr {
+            all_hashes.push(*hash);
+        } else {
+            if ih >= hashes.len() {
+                return Err(Error::MalformedProof(format!(
+                    "no child at 0x{:02x} in segment proof node",
+                    chr
+                )));
+            }
+            all_hashes.push(hashes[ih]);
+            ih += 1;
+        }
+    }
+    Ok(node_hash(node, &all_hashes))
+}
+
+/// Given a segment proof and the hash of the node below it, calculate the root hash of the
+/// segment.  Only the very first segment of a proof begins with a leaf or terminal node; all
+/// others begin with the node whose child is the back-pointer, whose hash is the ID of the block
+/// the back-pointer refers to.
+fn verify_segment_proof(proof: &[ProofEntry], node_hash_in: &TrieHash) -> Result<TrieHash, Error> {
+    let mut hash = *node_hash_in;
+    for proof_entry in proof.iter() {
+        hash = match proof_entry {
+            ProofEntry::Leaf(_, leaf) => leaf_hash(leaf),
+            ProofEntry::Terminal(node, hashes) => {
+                let count = check_node_ptrs(node)?;
+                if hashes.len() != count {
+                    return Err(Error::MalformedProof(format!(
+                        "terminal node with ID {} has {} child hashes",
+                        node.id,
+                        hashes.len()
+                    )));
+                }
+                node_hash(node, hashes)
+            }
+            ProofEntry::Node(chr, node, hashes) => {
+                get_segment_proof_hash(node, &hash, *chr, hashes)?
+            }
+            ProofEntry::Shunt(..) => {
+                return Err(Error::MalformedProof(
+                    "encountered a shunt proof entry in a segment proof".into(),
+                ));
+            }
+        };
+    }
+    Ok(hash)
+}
+
+/// Given a segment proof, extract the path prefix it encodes.  Also returns the length of the
+/// path of the segment's first node, if it is a leaf or terminal node.
+fn get_segment_proof_path_prefix(segment_proof: &[ProofEntry]) -> Result<(Vec<u8>, usize), Error> {
+    let mut path_parts: Vec<&[u8]> = vec![];
+    let mut end_path_len = 0;
+    for proof_entry in segment_proof.iter() {
+        match proof_entry {
+            ProofEntry::Leaf(_, leaf) => {
+                path_parts.push(&leaf.path);
+                end_path_len = leaf.path.len();
+            }
+            ProofEntry::Terminal(node, _) => {
+                path_parts.push(&node.path);
+                end_path_len = node.path.len();
+            }
+            ProofEntry::Node(chr, node, _) => {
+                path_parts.push(std::slice::from_ref(chr));
+                path_parts.push(&node.path);
+            }
+            ProofEntry::Shunt(..) => {
+                return Err(Error::MalformedProof(
+                    "got a shunt proof entry in a segment proof".into(),
+                ));
+            }
+        }
+    }
+
+    let mut path = vec![];
+    for part in path_parts.iter().rev() {
+        path.extend_from_slice(part);
+    }
+    Ok((path, end_path_len))
+}
+
+/// Check that the first segment of a non-inclusion proof shows that `expected_path` is absent.
+/// The segment's nodes must follow the path down to its first node, and that node must either be
+/// a leaf with a different path, a node whose path diverges from `expected_path`, or a node
+/// with no child at the path's next character.
+/// Returns the number of path bytes that the segment's ancestor nodes consumed.
+fn check_absence(segment_proof: &[ProofEntry], expected_path: &TriePath) -> Result<usize, Error> {
+    let (path_bytes, end_path_len) = get_segment_proof_path_prefix(segment_proof)?;
+    if path_bytes.len() > TRIEPATH_MAX_LEN {
+        return Err(Error::MalformedProof("segment path is too long".into()));
+    }
+
+    let consumed = path_bytes.len() - end_path_len;
+    if path_bytes[0..consumed] != expected_path[0..consumed] {
+        return Err(Error::PathMismatch);
+    }
+
+    match segment_proof[0] {
+        ProofEntry::Leaf(..) => {
+            if path_bytes.len() != TRIEPATH_MAX_LEN {
+                return Err(Error::MalformedProof(
+                    "leaf does not end at the end of the path".into(),
+                ));
+            }
+            if path_bytes[..] == expected_path[..] {
+                return Err(Error::KeyPresent);
+            }
+        }
+        ProofEntry::Terminal(ref node, _) => {
+            if path_bytes.len() >= TRIEPATH_MAX_LEN {
+                return Err(Error::MalformedProof(
+                    "terminal node reaches the end of the path".into(),
+                ));
+            }
+            if path_bytes[consumed..] == expected_path[consumed..path_bytes.len()] {
+                // node path matches, so the next path character must not have a child
+                let chr = expected_path[path_bytes.len()];
+                if node
+                    .ptrs
+                    .iter()
+                    .any(|ptr| ptr.id != NODE_ID_EMPTY && ptr.chr == chr)
+                {
+                    return Err(Error::KeyPresent);
+                }
+            }
+        }
+        _ => {
+            return Err(Error::MalformedProof(
+                "first proof entry is not a leaf or terminal node".into(),
+            ));
+        }
+    }
+    Ok(consumed)
+}
+
+/// Verify that a proof is well-formed:
+/// * it must have alternating segment and shunt proofs, ending in a shunt proof
+/// * segment proof 0 must begin with a leaf (or a terminal node, for non-inclusion proofs), and
+///   no other entry may be a leaf or terminal node
+/// * segment proof 0 must follow `expected_path` (or show its absence)
+/// * all other segment proofs must encode a prefix of the part of `expected_path` that segment
+///   proof 0 follows
+fn check_well_formed(
+    proof: &[ProofEntry],
+    expected_path: &TriePath,
+    absent: bool,
+) -> Result<(), Error> {
+    if proof.is_empty() {
+        return Err(Error::MalformedProof("proof is empty".into()));
+    }
+
+    match proof[0] {
+        ProofEntry::Leaf(..) => {}
+        ProofEntry::Terminal(..) if absent => {}
+        _ => {
+            return Err(Error::MalformedProof(
+                "first proof entry is not a leaf or terminal node".into(),
+            ));
+        }
+    }
+
+    for proof_entry in proof[1..].iter() {
+        match proof_entry {
+            ProofEntry::Leaf(..) | ProofEntry::Terminal(..) => {
+                return Err(Error::MalformedProof(
+                    "only the first proof entry can be a leaf or terminal node".into(),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let mut i = 0;
+    let mut prefix_limit = TRIEPATH_MAX_LEN;
+    while i < proof.len() {
+        // next segment proof
+        let j = next_shunt(proof, i + 1);
+        let segment_proof = &proof[i..j];
+
+        if i == 0 {
+            if absent {
+                prefix_limit = check_absence(segment_proof, expected_path)?;
+            } else {
+                let (path_bytes, _) = get_segment_proof_path_prefix(segment_proof)?;
+                if path_bytes[..] != expected_path[..] {
+                    return Err(Error::PathMismatch);
+                }
+            }
+        } else {
+            // this segment must follow a prefix of the path the first segment followed
+            let (path_bytes, _) = get_segment_proof_path_prefix(segment_proof)?;
+            if path_bytes.len() > prefix_limit
+                || path_bytes[..] != expected_path[0..path_bytes.len()]
+            {
+                return Err(Error::PathMismatch);
+            }
+        }
+
+        // next shunt proof
+        i = j;
+        if i >= proof.len() {
+            return Err(Error::MalformedProof(
+                "proof is incomplete -- must end with a shunt proof".into(),
+            ));
+        }
+        let mut j = i + 1;
+        while j < proof.len() {
+            match proof[j] {
+                ProofEntry::Shunt(..) => {
+                    j += 1;
+                }
+                _ => {
+                    break;
+                }
+            }
+        }
+        i = j;
+    }
+    Ok(())
+}
+
+/// Look up the block that the given trie root belongs to, and return its ID as a node hash
+fn block_hash_for_root<M: TrieRootMap + ?Sized>(
+    root_to_block: &M,
+    trie_hash: &TrieHash,
+) -> Result<TrieHash, Error> {
+    root_to_block
+        .block_for_root(trie_hash)
+        .ok_or(Error::UnknownTrieRoot(*trie_hash))
+}
+
+/// Verify the hashes of a well-formed proof, from its first segment all the way up to the root
+/// hash.
+fn verify_proof_hashes<M: TrieRootMap + ?Sized>(
+    proof: &[ProofEntry],
+    root_hash: &TrieHash,
+    root_to_block: &M,
+) -> Result<(), Error> {
+    // verify the very first segment proof
+    let mut i = 0;
+    let mut j = next_shunt(proof, i + 1);
+    let node_root_hash = verify_segment_proof(&proof[i..j], &[0u8; 32])?;
+
+    i = j;
+    if i >= proof.len() {
+        return Err(Error::MalformedProof(
+            "proof is too short -- needed at least one shunt proof for the first segment".into(),
+        ));
+    }
+
+    // verify the very first shunt proof head
+    let mut trie_hash = verify_shunt_proof_head(&node_root_hash, &proof[i])?;
+
+    i += 1;
+    if i >= proof.len() {
+        // done -- no further shunts
+        if *root_hash == trie_hash {
+            return Ok(());
+        }
+        return Err(Error::RootHashMismatch);
+    }
+
+    // next node hash is the hash of the block from which its root came
+    let mut node_hash = block_hash_for_root(root_to_block, &trie_hash)?;
+
+    // next proof item should be part of a segment proof
+    if let ProofEntry::Shunt(..) = proof[i] {
+        return Err(Error::MalformedProof(
+            "expected segment proof following first shunt proof head".into(),
+        ));
+    }
+
+    while i < proof.len() {
+        // find the next segment proof
+        j = next_shunt(proof, i + 1);
+        let next_node_root_hash = verify_segment_proof(&proof[i..j], &node_hash)?;
+
+        i = j;
+        if i >= proof.len() {
+            return Err(Error::MalformedProof(
+                "proof is too short -- no shunt proof tail".into(),
+            ));
+        }
+
+        // find the tail end
+        j = i;
+        while j < proof.len() {
+            match proof[j] {
+                ProofEntry::Shunt(idx, _) => {
+                    if idx == 0 {
+                        break;
+                    }
+                    j += 1;
+                }
+                _ => {
+                    break;
+                }
+            }
+        }
+        if j == i {
+            return Err(Error::MalformedProof(
+                "proof is malformed -- no tail or junction proof".into(),
+            ));
+        }
+        j -= 1;
+
+        let penultimate_trie_hash = verify_shunt_proof_tail(&trie_hash, &proof[i..j])?;
+
+        i = j;
+        let next_trie_hash =
+            verify_shunt_proof_junction(&next_node_root_hash, &penultimate_trie_hash, &proof[i])?;
+
+        // next node hash is the hash of the block from which its root came
+        trie_hash = next_trie_hash;
+        node_hash = block_hash_for_root(root_to_block, &trie_hash)?;
+
+        i += 1;
+        if trie_hash == *root_hash {
+            break;
+        }
+    }
+
+    if *root_hash == trie_hash {
+        Ok(())
+    } else {
+        Err(Error::RootHashMismatch)
+    }
+}
+
+/// Verify that `proof` shows that the key with path `path` maps to `value` in the trie whose root
+/// hash is `root_hash`.
+pub fn verify_inclusion<M: TrieRootMap + ?Sized>(
+    proof: &[ProofEntry],
+    path: &TriePath,
+    value: &MARFValue,
+    root_hash: &TrieHash,
+    root_to_block: &M,
+) -> Result<(), Error> {
+    check_well_formed(proof, path, false)?;
+    match proof[0] {
+        ProofEntry::Leaf(_, ref leaf) => {
+            if leaf.data[..] != value[..] {
+                return Err(Error::ValueMismatch);
+            }
+        }
+        _ => unreachable!("checked by check_well_formed()"),
+    }
+    verify_proof_hashes(proof, root_hash, root_to_block)
+}
+
+/// Verify that `proof` shows that no key with path `path` exists in the trie whose root hash is
+/// `root_hash`.
+pub fn verify_noninclusion<M: TrieRootMap + ?Sized>(
+    proof: &[ProofEntry],
+    path: &TriePath,
+    root_hash: &TrieHash,
+    root_to_block: &M,
+) -> Result<(), Error> {
+    check_well_formed(proof, path, true)?;
+    verify_proof_hashes(proof, root_hash, root_to_block)
+}
+
+impl MARFProof {
+    /// Verify that this proof shows that `path` maps to `value`
+    pub fn verify<M: TrieRootMap + ?Sized>(
+        &self,
+        path: &TriePath,
+        value: &MARFValue,
+        root_hash: &TrieHash,
+        root_to_block: &M,
+    ) -> Result<(), Error> {
+        verify_inclusion(&self.0, path, value, root_hash, root_to_block)
+    }
+
+    /// Verify that this proof shows that `path` is absent
+    pub fn verify_absent<M: TrieRootMap + ?Sized>(
+        &self,
+        path: &TriePath,
+        root_hash: &TrieHash,
+        root_to_block: &M,
+    ) -> Result<(), Error> {
+        verify_noninclusion(&self.0, path, root_hash, root_to_block)
+    }
+}
+
+impl MARFBatchProof {
+    /// Reassemble the proof for the `i`th key in the batch
+    pub fn get_proof(&self, i: usize) -> Result<Vec<ProofEntry>, Error> {
+        let indexes = self.proofs.get(i).ok_or_else(|| {
+            Error::MalformedProof(format!("batch proof has no proof at index {}", i))
+        })?;
+        indexes
+            .iter()
+            .map(|idx| {
+                self.entries.get(*idx as usize).cloned().ok_or_else(|| {
+                    Error::MalformedProof(format!("batch proof has no entry at index {}", idx))
+                })
+            })
+            .collect()
+    }
+
+    /// Verify the proof for every key in the batch.  `claims` lists each key's path, in the
+    /// order the batch was requested in, along with `Some(value)` if the key is expected to be
+    /// present or `None` if it is expected to be absent.
+    pub fn verify<M: TrieRootMap + ?Sized>(
+        &self,
+        claims: &[(TriePath, Option<MARFValue>)],
+        root_hash: &TrieHash,
+        root_to_block: &M,
+    ) -> Result<(), Error> {
+        if claims.len() != self.proofs.len() {
+            return Err(Error::MalformedProof(format!(
+                "batch proof has {} proofs, but {} keys were given",
+                self.proofs.len(),
+                claims.len()
+            )));
+        }
+        for (i, (path, value_opt)) in claims.iter().enumerate() {
+            let proof = self.get_proof(i)?;
+            match value_opt {
+                Some(value) => verify_inclusion(&proof, path, value, root_hash, root_to_block)?,
+                None => verify_noninclusion(&proof, path, root_hash, root_to_block)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ProofTrieLeaf, ProofTriePtr, NODE_ID_NODE256, NODE_ID_NODE4};
+    use std::collections::HashMap;
+
+    fn make_node256(path: Vec<u8>, children: &[u8]) -> ProofTrieNode {
+        let ptrs = (0..256)
+            .map(|i| ProofTriePtr {
+                id: if children.contains(&(i as u8)) {
+                    NODE_ID_NODE4
+                } else {
+                    NODE_ID_EMPTY
+                },
+                chr: if children.contains(&(i as u8)) {
+                    i as u8
+                } else {
+                    0
+                },
+                back_block: [0u8; 32],
+            })
+            .collect();
+        ProofTrieNode {
+            id: NODE_ID_NODE256,
+            path,
+            ptrs,
+        }
+    }
+
+    /// Build a single-trie proof for a leaf under a Node256 root, and the root hash it should
+    /// verify against.
+    fn make_leaf_proof(path: &TriePath, value: &MARFValue) -> (Vec<ProofEntry>, TrieHash) {
+        let leaf = ProofTrieLeaf {
+            path: path[1..].to_vec(),
+            data: *value,
+        };
+        let root = make_node256(vec![], &[path[0]]);
+        let sibling_hashes = vec![crate::hash_data_array::<&[u8]>(&[]); 255];
+
+        let mut all_hashes = sibling_hashes.clone();
+        all_hashes.insert(path[0] as usize, leaf_hash(&leaf));
+        let root_hash = node_hash(&root, &all_hashes);
+
+        let proof = vec![
+            ProofEntry::Leaf(path[0], leaf),
+            ProofEntry::Node(path[0], root, sibling_hashes),
+            ProofEntry::Shunt(0, vec![]),
+        ];
+        (proof, root_hash)
+    }
+
+    #[test]
+    fn test_verify_single_trie_inclusion() {
+        let path = [0x11u8; 32];
+        let value = [0x22u8; 40];
+        let (proof, root_hash) = make_leaf_proof(&path, &value);
+        let root_to_block: HashMap<TrieHash, TrieHash> = HashMap::new();
+
+        verify_inclusion(&proof, &path, &value, &root_hash, &root_to_block).unwrap();
+
+        assert_eq!(
+            verify_inclusion(&proof, &path, &[0x33u8; 40], &root_hash, &root_to_block),
+            Err(Error::ValueMismatch)
+        );
+        assert_eq!(
+            verify_inclusion(&proof, &[0x12u8; 32], &value, &root_hash, &root_to_block),
+            Err(Error::PathMismatch)
+        );
+        assert_eq!(
+            verify_inclusion(&proof, &path, &value, &[0u8; 32], &root_to_block),
+            Err(Error::RootHashMismatch)
+        );
+
+        // the same proof shows that a different key with a diverging leaf path is absent
+        let mut other_path = path;
+        other_path[31] = 0xff;
+        verify_noninclusion(&proof, &other_path, &root_hash, &root_to_block).unwrap();
+        assert_eq!(
+            verify_noninclusion(&proof, &path, &root_hash, &root_to_block),
+            Err(Error::KeyPresent)
+        );
+    }
+
+    #[test]
+    fn test_verify_single_trie_noninclusion() {
+        let root = make_node256(vec![], &[0x11]);
+        let child_hashes = vec![[0x44u8; 32]; 256];
+        let root_hash = node_hash(&root, &child_hashes);
+        let root_to_block: HashMap<TrieHash, TrieHash> = HashMap::new();
+
+        let proof = vec![
+            ProofEntry::Terminal(root, child_hashes),
+            ProofEntry::Shunt(0, vec![]),
+        ];
+
+        // no child at 0x22
+        verify_noninclusion(&proof, &[0x22u8; 32], &root_hash, &root_to_block).unwrap();
+
+        // there is a child at 0x11
+        assert_eq!(
+            verify_noninclusion(&proof, &[0x11u8; 32], &root_hash, &root_to_block),
+            Err(Error::KeyPresent)
+        );
+
+        // terminal nodes can't prove inclusion
+        assert!(verify_inclusion(
+            &proof,
+            &[0x22u8; 32],
+            &[0u8; 40],
+            &root_hash,
+            &root_to_block
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let path = [0x11u8; 32];
+        let value = [0x22u8; 40];
+        let (proof, root_hash) = make_leaf_proof(&path, &value);
+        let root_to_block: HashMap<TrieHash, TrieHash> = HashMap::new();
+
+        let mut absent_path = path;
+        absent_path[31] = 0;
+
+        let batch = MARFBatchProof {
+            entries: proof,
+            proofs: vec![vec![0, 1, 2], vec![0, 1, 2]],
+        };
+        batch
+            .verify(
+                &[(path, Some(value)), (absent_path, None)],
+                &root_hash,
+                &root_to_block,
+            )
+            .unwrap();
+
+        assert!(batch
+            .verify(&[(path, Some(value))], &root_hash, &root_to_block)
+            .is_err());
+        assert!(batch
+            .verify(
+                &[(path, None), (absent_path, None)],
+                &root_hash,
+                &root_to_block
+            )
+            .is_err());
+
+        let bad_batch = MARFBatchProof {
+            entries: batch.entries.clone(),
+            proofs: vec![vec![0, 1, 3]],
+        };
+        assert!(bad_batch
+            .verify(&[(path, Some(value))], &root_hash, &root_to_block)
+            .is_err());
+    }
+}
@@ -18,6 +18,7 @@ use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::Error as btc_error;
 use crate::burnchains::Address;
 use stacks_common::address::b58 as base58;
+use stacks_common::address::bech32;
 use stacks_common::address::c32::c32_address;
 use stacks_common::deps_common::bitcoin::blockdata::opcodes::All as BtcOp;
 use stacks_common::deps_common::bitcoin::blockdata::script::Builder as BtcScriptBuilder;
@@ -36,13 +37,35 @@ pub enum BitcoinAddressType {
     ScriptHash,
 }
 
+/// A pre-segwit Bitcoin address: a p2pkh or p2sh output
 #[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
-pub struct BitcoinAddress {
+pub struct LegacyBitcoinAddress {
     pub addrtype: BitcoinAddressType,
     pub network_id: BitcoinNetworkType,
     pub bytes: Hash160,
 }
 
+/// A native segwit Bitcoin address: a v0 p2wpkh or p2wsh output, or a v1 (taproot) p2tr output
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum SegwitBitcoinAddress {
+    P2WPKH(BitcoinNetworkType, [u8; 20]),
+    P2WSH(BitcoinNetworkType, [u8; 32]),
+    P2TR(BitcoinNetworkType, [u8; 32]),
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum BitcoinAddress {
+    Legacy(LegacyBitcoinAddress),
+    Segwit(SegwitBitcoinAddress),
+}
+
+pub const SEGWIT_MAINNET_HRP: &'static str = "bc";
+pub const SEGWIT_TESTNET_HRP: &'static str = "tb";
+pub const SEGWIT_REGTEST_HRP: &'static str = "bcrt";
+
+pub const SEGWIT_V0_OPCODE: u8 = 0x00;
+pub const SEGWIT_V1_OPCODE: u8 = 0x51;
+
 pub const ADDRESS_VERSION_MAINNET_SINGLESIG: u8 = 0;
 pub const ADDRESS_VERSION_MAINNET_MULTISIG: u8 = 5;
 pub const ADDRESS_VERSION_TESTNET_SINGLESIG: u8 = 111;
@@ -112,12 +135,12 @@ pub fn to_b58_version_byte(version: u8) -> Option<u8> {
     }
 }
 
-impl BitcoinAddress {
+impl LegacyBitcoinAddress {
     pub fn from_bytes(
         network_id: BitcoinNetworkType,
         addrtype: BitcoinAddressType,
         bytes: &[u8],
-    ) -> Result<BitcoinAddress, btc_error> {
+    ) -> Result<LegacyBitcoinAddress, btc_error> {
         if bytes.len() != 20 {
             return Err(btc_error::InvalidByteSequence);
         }
@@ -126,7 +149,7 @@ impl BitcoinAddress {
         let b = &bytes[..bytes.len()];
         my_bytes.copy_from_slice(b);
 
-        Ok(BitcoinAddress {
+        Ok(LegacyBitcoinAddress {
             network_id: network_id,
             addrtype: addrtype,
             bytes: Hash160(my_bytes),
@@ -135,7 +158,7 @@ impl BitcoinAddress {
 
     /// Instantiate an address from a b58check string
     /// Note that the network type will be 'testnet' if there is a testnet or regtest version byte
-    pub fn from_b58(addrb58: &str) -> Result<BitcoinAddress, btc_error> {
+    pub fn from_b58(addrb58: &str) -> Result<LegacyBitcoinAddress, btc_error> {
         let bytes = base58::from_check(addrb58).map_err(|_e| btc_error::InvalidByteSequence)?;
 
         if bytes.len() != 21 {
@@ -157,19 +180,19 @@ impl BitcoinAddress {
 
         let (addrtype, network_id) = typeinfo_opt.unwrap();
 
-        Ok(BitcoinAddress {
+        Ok(LegacyBitcoinAddress {
             network_id: network_id,
             addrtype: addrtype,
             bytes: Hash160(payload_bytes),
         })
     }
 
-    /// Instantiate an address from a scriptpubkey
+    /// Instantiate an address from a p2pkh or p2sh scriptpubkey
     /// If we don't recognize it, then return None
     pub fn from_scriptpubkey(
         network_id: BitcoinNetworkType,
         scriptpubkey: &Vec<u8>,
-    ) -> Option<BitcoinAddress> {
+    ) -> Option<LegacyBitcoinAddress> {
         if scriptpubkey.len() == 25
             && scriptpubkey[0..3] == [0x76, 0xa9, 0x14]
             && scriptpubkey[23..25] == [0x88, 0xac]
@@ -178,7 +201,7 @@ impl BitcoinAddress {
             let b = &scriptpubkey[3..23];
             my_bytes.copy_from_slice(b);
 
-            Some(BitcoinAddress {
+            Some(LegacyBitcoinAddress {
                 network_id: network_id,
                 addrtype: BitcoinAddressType::PublicKeyHash,
                 bytes: Hash160(my_bytes),
@@ -191,7 +214,7 @@ impl BitcoinAddress {
             let b = &scriptpubkey[2..22];
             my_bytes.copy_from_slice(b);
 
-            Some(BitcoinAddress {
+            Some(LegacyBitcoinAddress {
                 network_id: network_id,
                 addrtype: BitcoinAddressType::ScriptHash,
                 bytes: Hash160(my_bytes),
@@ -252,36 +275,246 @@ impl BitcoinAddress {
     }
 }
 
+/// bech32 human-readable part for a network's segwit addresses
+pub fn network_id_to_segwit_hrp(network_id: BitcoinNetworkType) -> &'static str {
+    match network_id {
+        BitcoinNetworkType::Mainnet => SEGWIT_MAINNET_HRP,
+        BitcoinNetworkType::Testnet => SEGWIT_TESTNET_HRP,
+        BitcoinNetworkType::Regtest => SEGWIT_REGTEST_HRP,
+    }
+}
+
+pub fn segwit_hrp_to_network_id(hrp: &str) -> Option<BitcoinNetworkType> {
+    match hrp {
+        SEGWIT_MAINNET_HRP => Some(BitcoinNetworkType::Mainnet),
+        SEGWIT_TESTNET_HRP => Some(BitcoinNetworkType::Testnet),
+        SEGWIT_REGTEST_HRP => Some(BitcoinNetworkType::Regtest),
+        _ => None,
+    }
+}
+
+impl SegwitBitcoinAddress {
+    /// Instantiate from a witness version and witness program.
+    /// Only v0 programs of 20 or 32 bytes and v1 programs of 32 bytes are recognized.
+    pub fn from_witness_program(
+        network_id: BitcoinNetworkType,
+        version: u8,
+        program: &[u8],
+    ) -> Option<SegwitBitcoinAddress> {
+        match (version, program.len()) {
+            (0, 20) => {
+                let mut bytes = [0u8; 20];
+                bytes.copy_from_slice(program);
+                Some(SegwitBitcoinAddress::P2WPKH(network_id, bytes))
+            }
+            (0, 32) => {
+                let mut bytes = [0u8; 32];
+                bytes.copy_from_slice(program);
+                Some(SegwitBitcoinAddress::P2WSH(network_id, bytes))
+            }
+            (1, 32) => {
+                let mut bytes = [0u8; 32];
+                bytes.copy_from_slice(program);
+                Some(SegwitBitcoinAddress::P2TR(network_id, bytes))
+            }
+            _ => None,
+        }
+    }
+
+    /// Instantiate an address from a bech32 (v0) or bech32m (v1) string
+    pub fn from_bech32(addr: &str) -> Result<SegwitBitcoinAddress, btc_error> {
+        let (hrp, version, program) =
+            bech32::decode_segwit_address(addr).map_err(|_e| btc_error::InvalidByteSequence)?;
+        let network_id = segwit_hrp_to_network_id(&hrp).ok_or_else(|| {
+            test_debug!("Invalid address: unrecognized hrp {}", &hrp);
+            btc_error::InvalidByteSequence
+        })?;
+        SegwitBitcoinAddress::from_witness_program(network_id, version, &program)
+            .ok_or(btc_error::InvalidByteSequence)
+    }
+
+    /// Instantiate an address from a p2wpkh, p2wsh, or p2tr scriptpubkey
+    /// If we don't recognize it, then return None
+    pub fn from_scriptpubkey(
+        network_id: BitcoinNetworkType,
+        scriptpubkey: &[u8],
+    ) -> Option<SegwitBitcoinAddress> {
+        if scriptpubkey.len() < 2 || scriptpubkey[1] as usize != scriptpubkey.len() - 2 {
+            return None;
+        }
+        let version = match scriptpubkey[0] {
+            SEGWIT_V0_OPCODE => 0,
+            SEGWIT_V1_OPCODE => 1,
+            _ => {
+                return None;
+            }
+        };
+        SegwitBitcoinAddress::from_witness_program(network_id, version, &scriptpubkey[2..])
+    }
+
+    pub fn network_id(&self) -> BitcoinNetworkType {
+        match self {
+            SegwitBitcoinAddress::P2WPKH(network_id, _)
+            | SegwitBitcoinAddress::P2WSH(network_id, _)
+            | SegwitBitcoinAddress::P2TR(network_id, _) => *network_id,
+        }
+    }
+
+    pub fn witness_version(&self) -> u8 {
+        match self {
+            SegwitBitcoinAddress::P2WPKH(..) | SegwitBitcoinAddress::P2WSH(..) => 0,
+            SegwitBitcoinAddress::P2TR(..) => 1,
+        }
+    }
+
+    pub fn witness_program(&self) -> &[u8] {
+        match self {
+            SegwitBitcoinAddress::P2WPKH(_, bytes) => bytes,
+            SegwitBitcoinAddress::P2WSH(_, bytes) | SegwitBitcoinAddress::P2TR(_, bytes) => bytes,
+        }
+    }
+
+    pub fn to_bech32(&self) -> String {
+        bech32::encode_segwit_address(
+            network_id_to_segwit_hrp(self.network_id()),
+            self.witness_version(),
+            self.witness_program(),
+        )
+        .expect("BUG: segwit address has an invalid witness program")
+    }
+
+    pub fn to_scriptpubkey(&self) -> Vec<u8> {
+        let program = self.witness_program();
+        let mut ret = Vec::with_capacity(program.len() + 2);
+        ret.push(match self.witness_version() {
+            0 => SEGWIT_V0_OPCODE,
+            v => SEGWIT_V1_OPCODE + v - 1,
+        });
+        ret.push(program.len() as u8);
+        ret.extend_from_slice(program);
+        ret
+    }
+
+    pub fn to_tx_out(&self, value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: self.to_scriptpubkey().into(),
+        }
+    }
+}
+
+impl BitcoinAddress {
+    /// Instantiate a legacy (p2pkh or p2sh) address from its hash bytes
+    pub fn from_bytes(
+        network_id: BitcoinNetworkType,
+        addrtype: BitcoinAddressType,
+        bytes: &[u8],
+    ) -> Result<BitcoinAddress, btc_error> {
+        LegacyBitcoinAddress::from_bytes(network_id, addrtype, bytes).map(BitcoinAddress::Legacy)
+    }
+
+    /// Instantiate a legacy address from a b58check string
+    pub fn from_b58(addrb58: &str) -> Result<BitcoinAddress, btc_error> {
+        LegacyBitcoinAddress::from_b58(addrb58).map(BitcoinAddress::Legacy)
+    }
+
+    /// Instantiate a segwit address from a bech32 or bech32m string
+    pub fn from_bech32(addr: &str) -> Result<BitcoinAddress, btc_error> {
+        SegwitBitcoinAddress::from_bech32(addr).map(BitcoinAddress::Segwit)
+    }
+
+    /// Instantiate an address from a scriptpubkey
+    /// If we don't recognize it, then return None
+    pub fn from_scriptpubkey(
+        network_id: BitcoinNetworkType,
+        scriptpubkey: &Vec<u8>,
+    ) -> Option<BitcoinAddress> {
+        if let Some(addr) = LegacyBitcoinAddress::from_scriptpubkey(network_id, scriptpubkey) {
+            return Some(BitcoinAddress::Legacy(addr));
+        }
+        SegwitBitcoinAddress::from_scriptpubkey(network_id, scriptpubkey)
+            .map(BitcoinAddress::Segwit)
+    }
+
+    pub fn is_segwit(&self) -> bool {
+        match self {
+            BitcoinAddress::Legacy(_) => false,
+            BitcoinAddress::Segwit(_) => true,
+        }
+    }
+
+    pub fn network_id(&self) -> BitcoinNetworkType {
+        match self {
+            BitcoinAddress::Legacy(addr) => addr.network_id,
+            BitcoinAddress::Segwit(addr) => addr.network_id(),
+        }
+    }
+
+    pub fn to_tx_out(&self, value: u64) -> TxOut {
+        match self {
+            BitcoinAddress::Legacy(addr) => match addr.addrtype {
+                BitcoinAddressType::PublicKeyHash => {
+                    LegacyBitcoinAddress::to_p2pkh_tx_out(&addr.bytes, value)
+                }
+                BitcoinAddressType::ScriptHash => {
+                    LegacyBitcoinAddress::to_p2sh_tx_out(&addr.bytes, value)
+                }
+            },
+            BitcoinAddress::Segwit(addr) => addr.to_tx_out(value),
+        }
+    }
+}
+
 impl Address for BitcoinAddress {
     fn to_bytes(&self) -> Vec<u8> {
-        self.bytes.as_bytes().to_vec()
+        match self {
+            BitcoinAddress::Legacy(addr) => addr.bytes.as_bytes().to_vec(),
+            BitcoinAddress::Segwit(addr) => addr.witness_program().to_vec(),
+        }
     }
 
     fn from_string(s: &str) -> Option<BitcoinAddress> {
-        match BitcoinAddress::from_b58(s) {
-            Ok(a) => Some(a),
-            Err(_e) => None,
-        }
+        BitcoinAddress::from_b58(s)
+            .or_else(|_e| BitcoinAddress::from_bech32(s))
+            .ok()
     }
 
     fn is_burn(&self) -> bool {
-        self.bytes == Hash160([0u8; 20])
+        match self {
+            BitcoinAddress::Legacy(addr) => addr.bytes == Hash160([0u8; 20]),
+            BitcoinAddress::Segwit(addr) => addr.witness_program().iter().all(|b| *b == 0),
+        }
     }
 }
 
-impl std::fmt::Display for BitcoinAddress {
+impl std::fmt::Display for LegacyBitcoinAddress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.to_b58().fmt(f)
     }
 }
 
+impl std::fmt::Display for SegwitBitcoinAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_bech32().fmt(f)
+    }
+}
+
+impl std::fmt::Display for BitcoinAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BitcoinAddress::Legacy(addr) => addr.fmt(f),
+            BitcoinAddress::Segwit(addr) => addr.fmt(f),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::burnchains::bitcoin::BitcoinNetworkType;
     use stacks_common::util::hash::{hex_bytes, Hash160};
     use stacks_common::util::log;
 
-    use super::{BitcoinAddress, BitcoinAddressType};
+    use super::{BitcoinAddress, BitcoinAddressType, LegacyBitcoinAddress, SegwitBitcoinAddress};
 
     struct AddressFixture {
         addr: String,
@@ -298,35 +531,35 @@ mod tests {
         let fixtures = vec![
             AddressFixture {
                 addr: "mr6nrMvvh44sR5MiX929mMXP5hqgaTr6fx".to_owned(),
-                result: Some(BitcoinAddress {
+                result: Some(BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     network_id: BitcoinNetworkType::Testnet,
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     bytes: Hash160::from_hex("74178497e927ff3ff1428a241be454d393c3c91c").unwrap(),
-                }),
+                })),
             },
             AddressFixture {
                 addr: "1B5xoFjSwAB3DUum7dxXgj3brnYsXibLbc".to_owned(),
-                result: Some(BitcoinAddress {
+                result: Some(BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     network_id: BitcoinNetworkType::Mainnet,
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     bytes: Hash160::from_hex("6ea17fc39169cdd9f2414a893aa5ce0c4b4c8934").unwrap(),
-                }),
+                })),
             },
             AddressFixture {
                 addr: "2Mxh5a9QxP5jgABfzATLpmFVofbzDeFRJyt".to_owned(),
-                result: Some(BitcoinAddress {
+                result: Some(BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     network_id: BitcoinNetworkType::Testnet,
                     addrtype: BitcoinAddressType::ScriptHash,
                     bytes: Hash160::from_hex("3bbc6b200412398dc98c6eb49d20c6b01715c2c1").unwrap(),
-                }),
+                })),
             },
             AddressFixture {
                 addr: "35idohuiQNndP1xR3FhNVHXgKF9YYPhWo4".to_owned(),
-                result: Some(BitcoinAddress {
+                result: Some(BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     network_id: BitcoinNetworkType::Mainnet,
                     addrtype: BitcoinAddressType::ScriptHash,
                     bytes: Hash160::from_hex("2c2edf39b098e05cf770e6b5a2fcedb54ee4fe05").unwrap(),
-                }),
+                })),
             },
             AddressFixture {
                 // too long
@@ -375,21 +608,21 @@ mod tests {
                 scriptpubkey: hex_bytes("76a9146ea17fc39169cdd9f2414a893aa5ce0c4b4c893488ac")
                     .unwrap()
                     .to_vec(),
-                result: Some(BitcoinAddress {
+                result: Some(BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     network_id: BitcoinNetworkType::Mainnet,
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     bytes: Hash160::from_hex("6ea17fc39169cdd9f2414a893aa5ce0c4b4c8934").unwrap(),
-                }),
+                })),
             },
             ScriptFixture {
                 scriptpubkey: hex_bytes("a9142c2edf39b098e05cf770e6b5a2fcedb54ee4fe0587")
                     .unwrap()
                     .to_vec(),
-                result: Some(BitcoinAddress {
+                result: Some(BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     network_id: BitcoinNetworkType::Mainnet,
                     addrtype: BitcoinAddressType::ScriptHash,
                     bytes: Hash160::from_hex("2c2edf39b098e05cf770e6b5a2fcedb54ee4fe05").unwrap(),
-                }),
+                })),
             },
             ScriptFixture {
                 scriptpubkey: hex_bytes("002c2edf39b098e05cf770e6b5a2fcedb54ee4fe05")
@@ -431,4 +664,113 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_segwit_from_scriptpubkey() {
+        let p2wpkh = hex_bytes("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let p2wsh =
+            hex_bytes("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262")
+                .unwrap();
+        let p2tr =
+            hex_bytes("5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433")
+                .unwrap();
+
+        let fixtures = vec![
+            ScriptFixture {
+                scriptpubkey: p2wpkh.clone(),
+                result: Some(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WPKH(
+                    BitcoinNetworkType::Mainnet,
+                    [
+                        0x75, 0x1e, 0x76, 0xe8, 0x19, 0x91, 0x96, 0xd4, 0x54, 0x94, 0x1c, 0x45,
+                        0xd1, 0xb3, 0xa3, 0x23, 0xf1, 0x43, 0x3b, 0xd6,
+                    ],
+                ))),
+            },
+            ScriptFixture {
+                scriptpubkey: p2wsh.clone(),
+                result: Some(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WSH(
+                    BitcoinNetworkType::Mainnet,
+                    p2wsh[2..].try_into().unwrap(),
+                ))),
+            },
+            ScriptFixture {
+                scriptpubkey: p2tr.clone(),
+                result: Some(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2TR(
+                    BitcoinNetworkType::Mainnet,
+                    p2tr[2..].try_into().unwrap(),
+                ))),
+            },
+            // v1 program of the wrong length
+            ScriptFixture {
+                scriptpubkey: hex_bytes("5114751e76e8199196d454941c45d1b3a323f1433bd6").unwrap(),
+                result: None,
+            },
+            // unknown witness version
+            ScriptFixture {
+                scriptpubkey: hex_bytes(
+                    "5220000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+                )
+                .unwrap(),
+                result: None,
+            },
+            // length byte does not match the program
+            ScriptFixture {
+                scriptpubkey: hex_bytes("0015751e76e8199196d454941c45d1b3a323f1433bd6").unwrap(),
+                result: None,
+            },
+        ];
+
+        for fixture in fixtures {
+            let addr_opt = BitcoinAddress::from_scriptpubkey(
+                BitcoinNetworkType::Mainnet,
+                &fixture.scriptpubkey,
+            );
+            assert_eq!(addr_opt, fixture.result);
+            if let Some(BitcoinAddress::Segwit(addr)) = addr_opt {
+                assert_eq!(addr.to_scriptpubkey(), fixture.scriptpubkey);
+            }
+        }
+    }
+
+    #[test]
+    fn test_segwit_bech32() {
+        let fixtures = vec![
+            (
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                BitcoinNetworkType::Mainnet,
+            ),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+                BitcoinNetworkType::Testnet,
+            ),
+            (
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                "5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+                BitcoinNetworkType::Testnet,
+            ),
+        ];
+
+        for (addr_str, scriptpubkey, network_id) in fixtures {
+            let addr = BitcoinAddress::from_bech32(addr_str).unwrap();
+            assert_eq!(addr.network_id(), network_id);
+            assert!(addr.is_segwit());
+            assert_eq!(addr.to_string(), addr_str);
+            assert_eq!(
+                addr.to_tx_out(1000).script_pubkey.to_bytes(),
+                hex_bytes(scriptpubkey).unwrap()
+            );
+        }
+
+        // v0 address with a bech32m checksum
+        assert!(BitcoinAddress::from_bech32("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh").is_err());
+        // v1 address with a bech32 checksum
+        assert!(BitcoinAddress::from_bech32(
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd"
+        )
+        .is_err());
+        // unknown human-readable part
+        assert!(BitcoinAddress::from_bech32("tc1qw508d6qejxtdg4y5r3zarvary0c5xw7kg3g4ty").is_err());
+    }
 }
@@ -17,7 +17,9 @@
 use sha2::Digest;
 use sha2::Sha256;
 
-use crate::burnchains::bitcoin::address::{BitcoinAddress, BitcoinAddressType};
+use crate::burnchains::bitcoin::address::{
+    BitcoinAddress, BitcoinAddressType, SegwitBitcoinAddress,
+};
 use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
 use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::Error as btc_error;
@@ -545,7 +547,9 @@ impl BitcoinTxOutput {
                 &script_bytes[2..22].to_vec(),
             )
         } else {
-            Err(btc_error::InvalidByteSequence)
+            SegwitBitcoinAddress::from_scriptpubkey(network_id, &script_bytes)
+                .map(BitcoinAddress::Segwit)
+                .ok_or(btc_error::InvalidByteSequence)
         };
 
         match address {
@@ -568,7 +572,9 @@ impl BitcoinTxOutput {
 
 #[cfg(test)]
 mod tests {
-    use crate::burnchains::bitcoin::address::{BitcoinAddress, BitcoinAddressType};
+    use crate::burnchains::bitcoin::address::{
+        BitcoinAddress, BitcoinAddressType, SegwitBitcoinAddress,
+    };
     use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
     use crate::burnchains::bitcoin::BitcoinInputType;
     use crate::burnchains::bitcoin::BitcoinNetworkType;
@@ -1071,15 +1077,22 @@ mod tests {
     }
 
     #[test]
-    fn tx_output_strange() {
-        let tx_fixtures_strange: Vec<ScriptFixture<Option<BitcoinTxOutput>>> = vec![
+    fn tx_output_segwit() {
+        let amount = 123;
+        let tx_fixtures_segwit = vec![
             ScriptFixture {
                 // script pubkey for segwit p2wpkh
                 script: Builder::from(
                     hex_bytes("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap(),
                 )
                 .into_script(),
-                result: None,
+                result: BitcoinTxOutput {
+                    units: amount,
+                    address: BitcoinAddress::from_bech32(
+                        "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+                    )
+                    .unwrap(),
+                },
             },
             ScriptFixture {
                 // script pubkey for a segwit p2wsh
@@ -1090,6 +1103,72 @@ mod tests {
                     .unwrap(),
                 )
                 .into_script(),
+                result: BitcoinTxOutput {
+                    units: amount,
+                    address: BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WSH(
+                        BitcoinNetworkType::Mainnet,
+                        hex_bytes(
+                            "1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+                        )
+                        .unwrap()
+                        .try_into()
+                        .unwrap(),
+                    )),
+                },
+            },
+            ScriptFixture {
+                // script pubkey for a taproot output
+                script: Builder::from(
+                    hex_bytes(
+                        "5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+                    )
+                    .unwrap(),
+                )
+                .into_script(),
+                result: BitcoinTxOutput {
+                    units: amount,
+                    address: BitcoinAddress::Segwit(SegwitBitcoinAddress::P2TR(
+                        BitcoinNetworkType::Mainnet,
+                        hex_bytes(
+                            "000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+                        )
+                        .unwrap()
+                        .try_into()
+                        .unwrap(),
+                    )),
+                },
+            },
+        ];
+
+        for script_fixture in tx_fixtures_segwit {
+            let tx_output_opt = BitcoinTxOutput::from_bitcoin_script_pubkey(
+                BitcoinNetworkType::Mainnet,
+                &script_fixture.script,
+                amount,
+            );
+            assert!(tx_output_opt.is_some());
+            assert_eq!(tx_output_opt.unwrap(), script_fixture.result);
+        }
+    }
+
+    #[test]
+    fn tx_output_strange() {
+        let tx_fixtures_strange: Vec<ScriptFixture<Option<BitcoinTxOutput>>> = vec![
+            ScriptFixture {
+                // script pubkey for a future segwit version
+                script: Builder::from(hex_bytes("5210751e76e8199196d454941c45d1b3a323").unwrap())
+                    .into_script(),
+                result: None,
+            },
+            ScriptFixture {
+                // script pubkey for a p2pk output
+                script: Builder::from(
+                    hex_bytes(
+                        "2102d8015134d9db8178ac93acbc43170a2f20febba5087a5b0437058765ad5133d0ac",
+                    )
+                    .unwrap(),
+                )
+                .into_script(),
                 result: None,
             },
         ];
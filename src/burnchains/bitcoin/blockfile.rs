@@ -0,0 +1,894 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A burnchain indexer that reads bitcoin blocks from local files instead of asking a bitcoin
+//! peer for them.  It understands two layouts:
+//! * a Bitcoin Core `blocks/` directory, with its `blk*.dat` files (and `xor.dat` key, if the
+//!   block files are obfuscated), and
+//! * a directory in which every file is a single serialized block.
+//!
+//! Blocks are indexed by scanning the files for their headers, and the chain with the most work
+//! that descends from the genesis block is used as the canonical chain.  Its headers are stored in
+//! the same SPV headers DB that `BitcoinIndexer` uses (and validated the same way), so the two
+//! indexers can be used interchangeably on the same node.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::burnchains::bitcoin::blocks::{BitcoinBlockIPC, BitcoinBlockParser, BitcoinHeaderIPC};
+use crate::burnchains::bitcoin::indexer::{get_bitcoin_stacks_epochs, network_id_to_bytes};
+use crate::burnchains::bitcoin::spv::SpvClient;
+use crate::burnchains::bitcoin::BitcoinNetworkType;
+use crate::burnchains::bitcoin::Error as btc_error;
+use crate::burnchains::indexer::{
+    BurnchainBlockDownloader, BurnchainBlockParser, BurnchainIndexer,
+};
+use crate::burnchains::BurnchainBlock;
+use crate::burnchains::Error as burnchain_error;
+use crate::burnchains::MagicBytes;
+use crate::core::StacksEpoch;
+use crate::types::chainstate::BurnchainHeaderHash;
+
+use stacks_common::deps_common::bitcoin::blockdata::block::{Block, BlockHeader, LoneBlockHeader};
+use stacks_common::deps_common::bitcoin::blockdata::constants::genesis_block;
+use stacks_common::deps_common::bitcoin::network::constants::Network;
+use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
+use stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
+use stacks_common::deps_common::bitcoin::network::serialize::{deserialize, BitcoinHash};
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+use stacks_common::util::log;
+
+/// Size of a serialized block header
+const BLOCK_HEADER_SIZE: u64 = 80;
+
+/// Each block in a `blk*.dat` file is preceded by the network magic and the block's length
+const BLOCK_RECORD_PREAMBLE_SIZE: u64 = 8;
+
+/// Name of the file holding the key with which Bitcoin Core obfuscates its block files
+const XOR_KEY_FILE: &'static str = "xor.dat";
+
+/// Number of headers to validate at once when storing them to the SPV headers DB
+const HEADERS_BATCH_SIZE: u64 = 2000;
+
+/// Where a block's bytes are on disk
+#[derive(Debug, Clone, PartialEq)]
+struct BlockLocation {
+    path: PathBuf,
+    offset: u64,
+    len: u64,
+}
+
+/// Index of the block headers found in a directory of block files
+#[derive(Debug, Clone)]
+pub struct BlockFileIndex {
+    blocks_dir: PathBuf,
+    network_id: BitcoinNetworkType,
+    /// Key with which every byte of a `blk*.dat` file is XOR'ed, if any
+    xor_key: Option<[u8; 8]>,
+    headers: HashMap<Sha256dHash, (BlockHeader, BlockLocation)>,
+    /// How many bytes of each file have been scanned
+    scanned: HashMap<PathBuf, u64>,
+}
+
+impl BlockFileIndex {
+    pub fn new(blocks_dir: &str, network_id: BitcoinNetworkType) -> BlockFileIndex {
+        BlockFileIndex {
+            blocks_dir: PathBuf::from(blocks_dir),
+            network_id,
+            xor_key: None,
+            headers: HashMap::new(),
+            scanned: HashMap::new(),
+        }
+    }
+
+    fn is_block_file(path: &Path) -> bool {
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.starts_with("blk") && name.ends_with(".dat"),
+            None => false,
+        }
+    }
+
+    /// Read `len` bytes at `offset` in a block file, undoing any obfuscation
+    fn read_at(
+        file: &mut fs::File,
+        offset: u64,
+        len: u64,
+        xor_key: Option<&[u8; 8]>,
+    ) -> Result<Vec<u8>, btc_error> {
+        let mut bytes = vec![0u8; len as usize];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut bytes))
+            .map_err(btc_error::FilesystemError)?;
+        if let Some(key) = xor_key {
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte ^= key[((offset + i as u64) % 8) as usize];
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Add a header to the index, unless its proof-of-work is invalid
+    fn add_header(&mut self, header: BlockHeader, location: BlockLocation) {
+        if header.spv_validate(&header.target()).is_err() {
+            warn!(
+                "Block {} in {} at offset {} has invalid proof-of-work",
+                header.bitcoin_hash(),
+                location.path.display(),
+                location.offset
+            );
+            return;
+        }
+        self.headers
+            .insert(header.bitcoin_hash(), (header, location));
+    }
+
+    /// Index the blocks appended to a `blk*.dat` file since we last looked at it.
+    /// Stops at the first incomplete record, since bitcoind may still be writing it, or at the
+    /// zero-filled space bitcoind preallocates at the end of the file.
+    fn scan_block_file(&mut self, path: &Path) -> Result<(), btc_error> {
+        let mut file = fs::File::open(path).map_err(btc_error::FilesystemError)?;
+        let file_len = file.metadata().map_err(btc_error::FilesystemError)?.len();
+        let magic = network_id_to_bytes(self.network_id);
+        let xor_key = self.xor_key;
+
+        let mut offset = self.scanned.get(path).cloned().unwrap_or(0);
+        while offset + BLOCK_RECORD_PREAMBLE_SIZE + BLOCK_HEADER_SIZE <= file_len {
+            let preamble = BlockFileIndex::read_at(
+                &mut file,
+                offset,
+                BLOCK_RECORD_PREAMBLE_SIZE,
+                xor_key.as_ref(),
+            )?;
+            let record_magic =
+                u32::from_le_bytes([preamble[0], preamble[1], preamble[2], preamble[3]]);
+            if record_magic == 0 {
+                break;
+            }
+            if record_magic != magic {
+                warn!(
+                    "Bad network magic {:08x} in {} at offset {}",
+                    record_magic,
+                    path.display(),
+                    offset
+                );
+                return Err(btc_error::InvalidMagic);
+            }
+
+            let block_len =
+                u32::from_le_bytes([preamble[4], preamble[5], preamble[6], preamble[7]]) as u64;
+            let block_offset = offset + BLOCK_RECORD_PREAMBLE_SIZE;
+            if block_len < BLOCK_HEADER_SIZE || block_offset + block_len > file_len {
+                break;
+            }
+
+            let header_bytes = BlockFileIndex::read_at(
+                &mut file,
+                block_offset,
+                BLOCK_HEADER_SIZE,
+                xor_key.as_ref(),
+            )?;
+            let header: BlockHeader =
+                deserialize(&header_bytes).map_err(btc_error::SerializationError)?;
+            self.add_header(
+                header,
+                BlockLocation {
+                    path: path.to_path_buf(),
+                    offset: block_offset,
+                    len: block_len,
+                },
+            );
+
+            offset = block_offset + block_len;
+        }
+
+        self.scanned.insert(path.to_path_buf(), offset);
+        Ok(())
+    }
+
+    /// Index a file that holds a single serialized block
+    fn scan_raw_block_file(&mut self, path: &Path) -> Result<(), btc_error> {
+        if self.scanned.contains_key(path) {
+            return Ok(());
+        }
+        let mut file = fs::File::open(path).map_err(btc_error::FilesystemError)?;
+        let file_len = file.metadata().map_err(btc_error::FilesystemError)?.len();
+        if file_len < BLOCK_HEADER_SIZE {
+            warn!("Not a block: {}", path.display());
+            self.scanned.insert(path.to_path_buf(), file_len);
+            return Ok(());
+        }
+
+        let header_bytes = BlockFileIndex::read_at(&mut file, 0, BLOCK_HEADER_SIZE, None)?;
+        let header: BlockHeader =
+            deserialize(&header_bytes).map_err(btc_error::SerializationError)?;
+        self.add_header(
+            header,
+            BlockLocation {
+                path: path.to_path_buf(),
+                offset: 0,
+                len: file_len,
+            },
+        );
+
+        self.scanned.insert(path.to_path_buf(), file_len);
+        Ok(())
+    }
+
+    /// Index any blocks that have been written to the blocks directory since the last refresh.
+    /// Returns the number of blocks indexed so far.
+    pub fn refresh(&mut self) -> Result<usize, btc_error> {
+        let xor_path = self.blocks_dir.join(XOR_KEY_FILE);
+        if self.xor_key.is_none() && xor_path.is_file() {
+            let key_bytes = fs::read(&xor_path).map_err(btc_error::FilesystemError)?;
+            if key_bytes.len() != 8 {
+                return Err(btc_error::ConfigError(format!(
+                    "{} must hold an 8-byte key",
+                    xor_path.display()
+                )));
+            }
+            let mut key = [0u8; 8];
+            key.copy_from_slice(&key_bytes);
+            if key != [0u8; 8] {
+                self.xor_key = Some(key);
+            }
+        }
+
+        let mut paths = vec![];
+        for entry in fs::read_dir(&self.blocks_dir).map_err(btc_error::FilesystemError)? {
+            let path = entry.map_err(btc_error::FilesystemError)?.path();
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let bitcoind_layout = paths.iter().any(|path| BlockFileIndex::is_block_file(path));
+        for path in paths.iter() {
+            if bitcoind_layout {
+                if BlockFileIndex::is_block_file(path) {
+                    self.scan_block_file(path)?;
+                }
+            } else {
+                let is_hidden = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| name.starts_with('.'))
+                    .unwrap_or(true);
+                if !is_hidden && *path != xor_path {
+                    self.scan_raw_block_file(path)?;
+                }
+            }
+        }
+
+        debug!(
+            "Indexed {} blocks in {}",
+            self.headers.len(),
+            self.blocks_dir.display()
+        );
+        Ok(self.headers.len())
+    }
+
+    /// Find the chain of indexed headers with the most work that descends from the genesis block.
+    /// The genesis block does not need to be in the blocks directory.
+    /// Ties are broken by the lowest tip hash, so the result does not depend on scan order.
+    /// Returns the headers from the genesis block to the tip.
+    pub fn best_chain(&self) -> Vec<LoneBlockHeader> {
+        let genesis_header = match self.network_id {
+            BitcoinNetworkType::Mainnet => genesis_block(Network::Bitcoin).header,
+            BitcoinNetworkType::Testnet => genesis_block(Network::Testnet).header,
+            BitcoinNetworkType::Regtest => genesis_block(Network::Regtest).header,
+        };
+        let genesis_hash = genesis_header.bitcoin_hash();
+
+        let mut children: HashMap<Sha256dHash, Vec<Sha256dHash>> = HashMap::new();
+        for (hash, (header, _)) in self.headers.iter() {
+            if *hash != genesis_hash {
+                children
+                    .entry(header.prev_blockhash)
+                    .or_default()
+                    .push(*hash);
+            }
+        }
+
+        // walk the tree of descendants of the genesis block
+        let mut best = (genesis_header.work(), genesis_hash);
+        let mut frontier = vec![(genesis_hash, genesis_header.work())];
+        while let Some((hash, work)) = frontier.pop() {
+            if work > best.0 || (work == best.0 && hash < best.1) {
+                best = (work, hash);
+            }
+            if let Some(child_hashes) = children.get(&hash) {
+                for child_hash in child_hashes.iter() {
+                    let child_work = work + self.headers[child_hash].0.work();
+                    frontier.push((*child_hash, child_work));
+                }
+            }
+        }
+
+        let mut chain = vec![];
+        let mut hash = best.1;
+        while hash != genesis_hash {
+            let header = self.headers[&hash].0;
+            hash = header.prev_blockhash;
+            chain.push(header);
+        }
+        chain.push(genesis_header);
+        chain.reverse();
+
+        chain
+            .into_iter()
+            .map(|header| LoneBlockHeader {
+                header,
+                tx_count: VarInt(0),
+            })
+            .collect()
+    }
+
+    /// Read an indexed block
+    pub fn read_block(&self, block_hash: &Sha256dHash) -> Result<Option<Block>, btc_error> {
+        let location = match self.headers.get(block_hash) {
+            Some((_, location)) => location,
+            None => return Ok(None),
+        };
+
+        let xor_key = if BlockFileIndex::is_block_file(&location.path) {
+            self.xor_key.as_ref()
+        } else {
+            None
+        };
+        let mut file = fs::File::open(&location.path).map_err(btc_error::FilesystemError)?;
+        let bytes = BlockFileIndex::read_at(&mut file, location.offset, location.len, xor_key)?;
+        let block: Block = deserialize(&bytes).map_err(btc_error::SerializationError)?;
+        Ok(Some(block))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockFileIndexerConfig {
+    /// Directory holding the block files
+    pub blocks_dir: String,
+    pub spv_headers_path: String,
+    pub first_block: u64,
+    pub magic_bytes: MagicBytes,
+    pub epochs: Option<Vec<StacksEpoch>>,
+}
+
+/// Burnchain indexer that reads blocks from local block files
+pub struct BlockFileIndexer {
+    pub config: BlockFileIndexerConfig,
+    pub network_id: BitcoinNetworkType,
+    index: Arc<RwLock<BlockFileIndex>>,
+}
+
+/// Reads blocks out of a `BlockFileIndexer`'s block files
+pub struct BlockFileDownloader {
+    index: Arc<RwLock<BlockFileIndex>>,
+}
+
+/// Parses the blocks read by a `BlockFileDownloader`
+pub struct BlockFileBlockParser {
+    parser: BitcoinBlockParser,
+}
+
+impl BlockFileIndexer {
+    pub fn new(config: BlockFileIndexerConfig, network_id: BitcoinNetworkType) -> BlockFileIndexer {
+        let index = BlockFileIndex::new(&config.blocks_dir, network_id);
+        BlockFileIndexer {
+            config,
+            network_id,
+            index: Arc::new(RwLock::new(index)),
+        }
+    }
+
+    fn open_spv_client(&self, readwrite: bool) -> Result<SpvClient, btc_error> {
+        SpvClient::new(
+            &self.config.spv_headers_path,
+            0,
+            None,
+            self.network_id,
+            readwrite,
+            false,
+        )
+    }
+
+    /// Index any new blocks, and get the headers of the best chain in the block files
+    fn refresh_best_chain(&mut self) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        let mut index = self
+            .index
+            .write()
+            .expect("FATAL: block file index lock poisoned");
+        index.refresh()?;
+        Ok(index.best_chain())
+    }
+}
+
+impl BurnchainBlockDownloader for BlockFileDownloader {
+    type H = BitcoinHeaderIPC;
+    type B = BitcoinBlockIPC;
+
+    fn download(&mut self, header: &BitcoinHeaderIPC) -> Result<BitcoinBlockIPC, burnchain_error> {
+        let block_hash = header.block_header.header.bitcoin_hash();
+        let block = self
+            .index
+            .read()
+            .expect("FATAL: block file index lock poisoned")
+            .read_block(&block_hash)
+            .map_err(burnchain_error::DownloadError)?
+            .ok_or_else(|| {
+                burnchain_error::UnknownBlock(BurnchainHeaderHash::from_bitcoin_hash(&block_hash))
+            })?;
+
+        if !BitcoinBlockParser::check_block(&block, &header.block_header) {
+            error!(
+                "Block file data for {} does not match its header",
+                &block_hash
+            );
+            return Err(burnchain_error::ParseError);
+        }
+
+        Ok(BitcoinBlockIPC {
+            header_data: header.clone(),
+            block_message: NetworkMessage::Block(block),
+        })
+    }
+}
+
+impl BurnchainBlockParser for BlockFileBlockParser {
+    type D = BlockFileDownloader;
+
+    fn parse(&mut self, ipc_block: &BitcoinBlockIPC) -> Result<BurnchainBlock, burnchain_error> {
+        self.parser.parse(ipc_block)
+    }
+}
+
+impl BurnchainIndexer for BlockFileIndexer {
+    type P = BlockFileBlockParser;
+
+    /// Index the block files
+    fn connect(&mut self) -> Result<(), burnchain_error> {
+        self.index
+            .write()
+            .expect("FATAL: block file index lock poisoned")
+            .refresh()
+            .map_err(burnchain_error::Bitcoin)?;
+        Ok(())
+    }
+
+    fn get_first_block_height(&self) -> u64 {
+        self.config.first_block
+    }
+
+    fn get_first_block_header_hash(&self) -> Result<BurnchainHeaderHash, burnchain_error> {
+        let spv_client = self.open_spv_client(false)?;
+        let first_header = spv_client
+            .read_block_header(self.config.first_block)?
+            .expect("BUG: no first block header hash");
+        Ok(BurnchainHeaderHash::from_bitcoin_hash(
+            &first_header.header.bitcoin_hash(),
+        ))
+    }
+
+    fn get_first_block_header_timestamp(&self) -> Result<u64, burnchain_error> {
+        let spv_client = self.open_spv_client(false)?;
+        let first_header = spv_client
+            .read_block_header(self.config.first_block)?
+            .expect("BUG: no first block header timestamp");
+        Ok(first_header.header.time as u64)
+    }
+
+    fn get_stacks_epochs(&self) -> Vec<StacksEpoch> {
+        match self.config.epochs {
+            Some(ref epochs) => {
+                assert!(self.network_id != BitcoinNetworkType::Mainnet);
+                epochs.clone()
+            }
+            None => get_bitcoin_stacks_epochs(self.network_id),
+        }
+    }
+
+    fn get_headers_path(&self) -> String {
+        self.config.spv_headers_path.clone()
+    }
+
+    fn get_headers_height(&self) -> Result<u64, burnchain_error> {
+        let spv_client = self.open_spv_client(false)?;
+        Ok(spv_client.get_headers_height()?)
+    }
+
+    fn get_highest_header_height(&self) -> Result<u64, burnchain_error> {
+        let spv_client = self.open_spv_client(false)?;
+        Ok(spv_client.get_highest_header_height()?)
+    }
+
+    /// Find the highest block that our headers have in common with the best chain in the block
+    /// files
+    fn find_chain_reorg(&mut self) -> Result<u64, burnchain_error> {
+        let chain = self.refresh_best_chain()?;
+        let spv_client = self.open_spv_client(false)?;
+
+        let mut height = cmp::min(
+            spv_client.get_highest_header_height()?,
+            (chain.len() as u64) - 1,
+        );
+        while height > 0 {
+            if let Some(header) = spv_client.read_block_header(height)? {
+                if header.header.bitcoin_hash() == chain[height as usize].header.bitcoin_hash() {
+                    break;
+                }
+            }
+            height -= 1;
+        }
+        Ok(height)
+    }
+
+    /// Store the headers of the best chain in the block files, after `start_height` and up to and
+    /// including `end_height` (if given).
+    /// Returns the height of the last header stored.
+    fn sync_headers(
+        &mut self,
+        start_height: u64,
+        end_height: Option<u64>,
+    ) -> Result<u64, burnchain_error> {
+        if let Some(end_height) = end_height {
+            if end_height <= start_height {
+                return Ok(end_height);
+            }
+        }
+
+        let chain = self.refresh_best_chain()?;
+        let chain_tip = (chain.len() as u64) - 1;
+        let end_height = match end_height {
+            Some(end_height) => cmp::min(end_height, chain_tip),
+            None => chain_tip,
+        };
+
+        let mut spv_client = self.open_spv_client(true)?;
+        let mut height = start_height;
+        while height < end_height {
+            let batch_end = cmp::min(height + HEADERS_BATCH_SIZE, end_height);
+            spv_client.handle_headers(
+                height,
+                chain[((height + 1) as usize)..((batch_end + 1) as usize)].to_vec(),
+            )?;
+            height = batch_end;
+        }
+
+        debug!(
+            "Synced headers {}-{} from {}",
+            start_height, height, &self.config.blocks_dir
+        );
+        Ok(height)
+    }
+
+    fn drop_headers(&mut self, new_height: u64) -> Result<(), burnchain_error> {
+        let mut spv_client = self.open_spv_client(true)?;
+        Ok(spv_client.drop_headers(new_height)?)
+    }
+
+    fn read_headers(
+        &self,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<Vec<BitcoinHeaderIPC>, burnchain_error> {
+        let spv_client = self.open_spv_client(false)?;
+        let headers = spv_client.read_block_headers(start_block, end_block)?;
+        Ok(headers
+            .into_iter()
+            .enumerate()
+            .map(|(i, block_header)| BitcoinHeaderIPC {
+                block_header,
+                block_height: (i as u64) + start_block,
+            })
+            .collect())
+    }
+
+    fn downloader(&self) -> BlockFileDownloader {
+        BlockFileDownloader {
+            index: self.index.clone(),
+        }
+    }
+
+    fn parser(&self) -> BlockFileBlockParser {
+        BlockFileBlockParser {
+            parser: BitcoinBlockParser::new(self.network_id, self.config.magic_bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    use crate::burnchains::BLOCKSTACK_MAGIC_MAINNET;
+
+    use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{
+        OutPoint, Transaction, TxIn, TxOut,
+    };
+    use stacks_common::deps_common::bitcoin::network::serialize::serialize;
+    use stacks_common::deps_common::bitcoin::util::hash::bitcoin_merkle_root;
+
+    /// Mine a regtest block on top of `parent`, at height `height`.  `fork` distinguishes blocks
+    /// at the same height on different forks.
+    fn make_block(parent: &BlockHeader, height: u32, fork: u8) -> Block {
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::from(vec![
+                    4,
+                    (height & 0xff) as u8,
+                    ((height >> 8) & 0xff) as u8,
+                    0,
+                    fork,
+                ]),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 5_000_000_000,
+                script_pubkey: Script::from(vec![0x51]),
+            }],
+        };
+        let mut header = BlockHeader {
+            version: 0x20000000,
+            prev_blockhash: parent.bitcoin_hash(),
+            merkle_root: bitcoin_merkle_root(vec![coinbase.txid()]),
+            time: parent.time + 600,
+            bits: 0x207fffff,
+            nonce: 0,
+        };
+        while header.spv_validate(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+        Block {
+            header,
+            txdata: vec![coinbase],
+        }
+    }
+
+    /// Mine `count` blocks on top of `parent`
+    fn make_chain(parent: &BlockHeader, start_height: u32, count: u32, fork: u8) -> Vec<Block> {
+        let mut blocks: Vec<Block> = vec![];
+        for i in 0..count {
+            let block = {
+                let parent = blocks.last().map(|b| &b.header).unwrap_or(parent);
+                make_block(parent, start_height + i, fork)
+            };
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    /// Append blocks to a `blk*.dat` file, then zero-fill it like bitcoind does
+    fn append_block_file(path: &Path, blocks: &[Block], xor_key: Option<&[u8; 8]>) {
+        let mut bytes = if path.exists() {
+            // find where the zero-filled tail starts
+            let mut index = BlockFileIndex::new("/dev/null", BitcoinNetworkType::Regtest);
+            index.xor_key = xor_key.cloned();
+            index.scan_block_file(path).unwrap();
+
+            let mut bytes = fs::read(path).unwrap();
+            bytes.truncate(index.scanned[path] as usize);
+            if let Some(key) = xor_key {
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte ^= key[i % 8];
+                }
+            }
+            bytes
+        } else {
+            vec![]
+        };
+        for block in blocks.iter() {
+            let block_bytes = serialize(block).unwrap();
+            bytes
+                .extend_from_slice(&network_id_to_bytes(BitcoinNetworkType::Regtest).to_le_bytes());
+            bytes.extend_from_slice(&(block_bytes.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&block_bytes);
+        }
+        bytes.extend_from_slice(&[0u8; 1000]);
+        if let Some(key) = xor_key {
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte ^= key[i % 8];
+            }
+        }
+        fs::File::create(path).unwrap().write_all(&bytes).unwrap();
+    }
+
+    fn setup_dir(name: &str) -> (String, String) {
+        let blocks_dir = format!("/tmp/test-blockfile-{}", name);
+        let headers_path = format!("/tmp/test-blockfile-{}.headers", name);
+        if fs::metadata(&blocks_dir).is_ok() {
+            fs::remove_dir_all(&blocks_dir).unwrap();
+        }
+        if fs::metadata(&headers_path).is_ok() {
+            fs::remove_file(&headers_path).unwrap();
+        }
+        fs::create_dir_all(&blocks_dir).unwrap();
+        (blocks_dir, headers_path)
+    }
+
+    fn make_indexer(blocks_dir: &str, headers_path: &str) -> BlockFileIndexer {
+        BlockFileIndexer::new(
+            BlockFileIndexerConfig {
+                blocks_dir: blocks_dir.to_string(),
+                spv_headers_path: headers_path.to_string(),
+                first_block: 0,
+                magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
+                epochs: None,
+            },
+            BitcoinNetworkType::Regtest,
+        )
+    }
+
+    fn chain_hashes(headers: &[LoneBlockHeader]) -> Vec<Sha256dHash> {
+        headers.iter().map(|h| h.header.bitcoin_hash()).collect()
+    }
+
+    #[test]
+    fn test_raw_block_dir_best_chain() {
+        let (blocks_dir, _) = setup_dir("raw");
+        let genesis = genesis_block(Network::Regtest).header;
+
+        // main chain of 5 blocks, and a 2-block fork off of block 2
+        let main_chain = make_chain(&genesis, 1, 5, 0);
+        let fork = make_chain(&main_chain[1].header, 3, 2, 1);
+        for (i, block) in main_chain.iter().chain(fork.iter()).enumerate() {
+            fs::write(
+                format!("{}/{:04}.block", &blocks_dir, i),
+                serialize(block).unwrap(),
+            )
+            .unwrap();
+        }
+        // ignored
+        fs::write(format!("{}/.hidden", &blocks_dir), vec![1, 2, 3]).unwrap();
+        fs::write(format!("{}/short", &blocks_dir), vec![1, 2, 3]).unwrap();
+
+        let mut index = BlockFileIndex::new(&blocks_dir, BitcoinNetworkType::Regtest);
+        assert_eq!(index.refresh().unwrap(), 7);
+
+        let best_chain = index.best_chain();
+        let mut expected = vec![genesis.bitcoin_hash()];
+        expected.extend(main_chain.iter().map(|b| b.bitcoin_hash()));
+        assert_eq!(chain_hashes(&best_chain), expected);
+
+        for block in main_chain.iter().chain(fork.iter()) {
+            assert_eq!(
+                index.read_block(&block.bitcoin_hash()).unwrap().unwrap(),
+                *block
+            );
+        }
+        assert!(index.read_block(&genesis.bitcoin_hash()).unwrap().is_none());
+
+        // extend the fork past the main chain
+        let fork_ext = make_chain(&fork[1].header, 5, 2, 1);
+        for (i, block) in fork_ext.iter().enumerate() {
+            fs::write(
+                format!("{}/{:04}.block", &blocks_dir, 7 + i),
+                serialize(block).unwrap(),
+            )
+            .unwrap();
+        }
+        assert_eq!(index.refresh().unwrap(), 9);
+
+        let best_chain = index.best_chain();
+        let mut expected = vec![genesis.bitcoin_hash()];
+        expected.extend(main_chain[0..2].iter().map(|b| b.bitcoin_hash()));
+        expected.extend(fork.iter().chain(fork_ext.iter()).map(|b| b.bitcoin_hash()));
+        assert_eq!(chain_hashes(&best_chain), expected);
+    }
+
+    #[test]
+    fn test_block_file_indexer_bitcoind_layout() {
+        for xor_key in [
+            None,
+            Some([0x11u8, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]),
+        ] {
+            let name = if xor_key.is_some() { "xor" } else { "plain" };
+            let (blocks_dir, headers_path) = setup_dir(name);
+            if let Some(key) = xor_key.as_ref() {
+                fs::write(format!("{}/xor.dat", &blocks_dir), key).unwrap();
+            }
+
+            let genesis = genesis_block(Network::Regtest);
+            let blk0 = PathBuf::from(format!("{}/blk00000.dat", &blocks_dir));
+            let blk1 = PathBuf::from(format!("{}/blk00001.dat", &blocks_dir));
+
+            // 20 blocks, enough to exercise the median-time check when storing headers
+            let chain = make_chain(&genesis.header, 1, 20, 0);
+            let mut blk0_blocks = vec![genesis.clone()];
+            blk0_blocks.extend_from_slice(&chain[0..15]);
+            append_block_file(&blk0, &blk0_blocks, xor_key.as_ref());
+
+            let mut indexer = make_indexer(&blocks_dir, &headers_path);
+            indexer.connect().unwrap();
+            assert_eq!(indexer.sync_headers(0, Some(10)).unwrap(), 10);
+            assert_eq!(indexer.get_highest_header_height().unwrap(), 10);
+            assert_eq!(indexer.find_chain_reorg().unwrap(), 10);
+            assert_eq!(indexer.sync_headers(10, None).unwrap(), 15);
+
+            // bitcoind writes more blocks
+            append_block_file(&blk0, &chain[15..18], xor_key.as_ref());
+            append_block_file(&blk1, &chain[18..20], xor_key.as_ref());
+            assert_eq!(indexer.find_chain_reorg().unwrap(), 15);
+            assert_eq!(indexer.sync_headers(15, None).unwrap(), 20);
+            assert_eq!(
+                indexer.get_first_block_header_hash().unwrap(),
+                BurnchainHeaderHash::from_bitcoin_hash(&genesis.bitcoin_hash())
+            );
+
+            // headers and blocks can be read back
+            let headers = indexer.read_headers(1, 21).unwrap();
+            assert_eq!(headers.len(), 20);
+            let mut downloader = indexer.downloader();
+            let mut parser = indexer.parser();
+            for (header, block) in headers.iter().zip(chain.iter()) {
+                assert_eq!(header.block_header.header, block.header);
+                let ipc_block = downloader.download(header).unwrap();
+                match parser.parse(&ipc_block).unwrap() {
+                    BurnchainBlock::Bitcoin(bitcoin_block) => {
+                        assert_eq!(bitcoin_block.block_height, header.block_height);
+                        assert_eq!(
+                            bitcoin_block.block_hash,
+                            BurnchainHeaderHash::from_bitcoin_hash(&block.bitcoin_hash())
+                        );
+                    }
+                }
+            }
+
+            // a fork with more work off of block 16 reorgs the chain
+            let fork = make_chain(&chain[15].header, 17, 5, 1);
+            append_block_file(&blk1, &fork, xor_key.as_ref());
+            assert_eq!(indexer.find_chain_reorg().unwrap(), 16);
+            indexer.drop_headers(16).unwrap();
+            assert_eq!(indexer.sync_headers(16, None).unwrap(), 21);
+            let headers = indexer.read_headers(17, 22).unwrap();
+            assert_eq!(
+                headers
+                    .iter()
+                    .map(|h| h.block_header.header.bitcoin_hash())
+                    .collect::<Vec<_>>(),
+                fork.iter().map(|b| b.bitcoin_hash()).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_block_file_index_bad_magic() {
+        let (blocks_dir, _) = setup_dir("bad-magic");
+        let genesis = genesis_block(Network::Regtest);
+        let mut bytes = network_id_to_bytes(BitcoinNetworkType::Mainnet)
+            .to_le_bytes()
+            .to_vec();
+        let block_bytes = serialize(&genesis).unwrap();
+        bytes.extend_from_slice(&(block_bytes.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&block_bytes);
+        fs::write(format!("{}/blk00000.dat", &blocks_dir), &bytes).unwrap();
+
+        let mut index = BlockFileIndex::new(&blocks_dir, BitcoinNetworkType::Regtest);
+        match index.refresh() {
+            Err(btc_error::InvalidMagic) => {}
+            x => panic!("Expected invalid magic, got {:?}", &x),
+        }
+    }
+}
@@ -14,9 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fmt;
 use std::ops::Deref;
 
-use crate::burnchains::bitcoin::address::BitcoinAddress;
+use crate::burnchains::bitcoin::address::{BitcoinAddress, SegwitBitcoinAddress};
 use crate::burnchains::bitcoin::bits;
 use crate::burnchains::bitcoin::indexer::BitcoinIndexer;
 use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
@@ -104,6 +105,47 @@ pub struct BitcoinBlockParser {
     magic_bytes: MagicBytes,
 }
 
+/// Reasons why a Bitcoin transaction is not a burnchain transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxParseError {
+    /// The transaction has no outputs
+    NoOutputs,
+    /// Output 0 is not an OP_RETURN
+    NoOpReturn,
+    /// Output 0 is not `OP_RETURN <data>`
+    MalformedOpReturn,
+    /// The OP_RETURN payload is too short to hold the magic bytes and an opcode
+    DataTooShort,
+    /// The OP_RETURN payload starts with these bytes instead of the magic bytes
+    BadMagic(Vec<u8>),
+    /// The input at this index is not a p2pkh or p2sh scriptsig we can parse
+    UnsupportedInput(usize),
+    /// The output at this index is not a p2pkh, p2sh, or native segwit scriptpubkey
+    UnsupportedOutput(usize),
+}
+
+impl fmt::Display for TxParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TxParseError::NoOutputs => write!(f, "Transaction has no outputs"),
+            TxParseError::NoOpReturn => write!(f, "Output 0 is not an OP_RETURN"),
+            TxParseError::MalformedOpReturn => write!(f, "Output 0 is not OP_RETURN <data>"),
+            TxParseError::DataTooShort => {
+                write!(f, "OP_RETURN data is too short to carry an operation")
+            }
+            TxParseError::BadMagic(ref bytes) => {
+                write!(f, "OP_RETURN data has bad magic bytes {}", to_hex(bytes))
+            }
+            TxParseError::UnsupportedInput(i) => {
+                write!(f, "Input {} has an unsupported scriptSig", i)
+            }
+            TxParseError::UnsupportedOutput(i) => {
+                write!(f, "Output {} has an unsupported scriptPubKey", i)
+            }
+        }
+    }
+}
+
 impl BitcoinBlockDownloader {
     pub fn new(indexer: BitcoinIndexer) -> BitcoinBlockDownloader {
         BitcoinBlockDownloader {
@@ -257,147 +299,161 @@ impl BitcoinBlockParser {
     }
 
     /// Parse the data output to get a byte payload
-    fn parse_data(&self, data_output: &Script) -> Option<(u8, Vec<u8>)> {
+    fn parse_data(&self, data_output: &Script) -> Result<(u8, Vec<u8>), TxParseError> {
         if !data_output.is_op_return() {
             test_debug!("Data output is not an OP_RETURN");
-            return None;
+            return Err(TxParseError::NoOpReturn);
         }
 
         if data_output.len() <= self.magic_bytes.len() {
             test_debug!("Data output is too short to carry an operation");
-            return None;
+            return Err(TxParseError::DataTooShort);
         }
 
         let script_pieces = bits::parse_script(&data_output);
         if script_pieces.len() != 2 {
             // not OP_RETURN <data>
             test_debug!("Data output does not encode a valid OP_RETURN");
-            return None;
+            return Err(TxParseError::MalformedOpReturn);
         }
 
         match (&script_pieces[0], &script_pieces[1]) {
             (Instruction::Op(ref opcode), Instruction::PushBytes(ref data)) => {
                 if *opcode != btc_opcodes::OP_RETURN {
                     test_debug!("Data output does not use a standard OP_RETURN");
-                    return None;
+                    return Err(TxParseError::MalformedOpReturn);
                 }
                 if !data.starts_with(self.magic_bytes.as_bytes()) {
                     test_debug!("Data output does not start with magic bytes");
-                    return None;
+                    return Err(TxParseError::BadMagic(
+                        data[0..data.len().min(MAGIC_BYTES_LENGTH)].to_vec(),
+                    ));
+                }
+                if data.len() <= MAGIC_BYTES_LENGTH {
+                    test_debug!("Data output has no opcode");
+                    return Err(TxParseError::DataTooShort);
                 }
 
                 let opcode = data[MAGIC_BYTES_LENGTH];
-                Some((opcode, data[MAGIC_BYTES_LENGTH + 1..data.len()].to_vec()))
+                Ok((opcode, data[MAGIC_BYTES_LENGTH + 1..data.len()].to_vec()))
             }
             (_, _) => {
                 test_debug!("Data output is not OP_RETURN <data>");
-                None
+                Err(TxParseError::MalformedOpReturn)
             }
         }
     }
 
     /// Is this an acceptable transaction?  It must have
     /// * an OP_RETURN output at output 0
-    /// * only p2pkh or p2sh outputs for outputs 1...n
-    fn maybe_burnchain_tx(&self, tx: &Transaction) -> bool {
-        if self.parse_data(&tx.output[0].script_pubkey).is_none() {
-            test_debug!("Tx {:?} has no valid OP_RETURN", tx.txid());
-            return false;
+    /// * only p2pkh, p2sh, p2wpkh, p2wsh, or p2tr outputs for outputs 1...n
+    fn check_burnchain_tx(&self, tx: &Transaction) -> Result<(), TxParseError> {
+        if tx.output.is_empty() {
+            test_debug!("Tx {:?} has no outputs", tx.txid());
+            return Err(TxParseError::NoOutputs);
         }
 
+        self.parse_data(&tx.output[0].script_pubkey)?;
+
         for i in 1..tx.output.len() {
-            if !tx.output[i].script_pubkey.is_p2pkh() && !tx.output[i].script_pubkey.is_p2sh() {
+            let script_pubkey = &tx.output[i].script_pubkey;
+            if !script_pubkey.is_p2pkh()
+                && !script_pubkey.is_p2sh()
+                && SegwitBitcoinAddress::from_scriptpubkey(
+                    self.network_id,
+                    script_pubkey.as_bytes(),
+                )
+                .is_none()
+            {
                 // unrecognized output type
                 test_debug!(
                     "Tx {:?} has unrecognized output type in output {}",
                     tx.txid(),
                     i
                 );
-                return false;
+                return Err(TxParseError::UnsupportedOutput(i));
             }
         }
 
-        return true;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn maybe_burnchain_tx(&self, tx: &Transaction) -> bool {
+        self.check_burnchain_tx(tx).is_ok()
     }
 
     /// Parse a transaction's inputs into burnchain tx inputs.
     /// Succeeds only if we can parse each input.
-    fn parse_inputs(&self, tx: &Transaction) -> Option<Vec<BitcoinTxInput>> {
+    fn parse_inputs(&self, tx: &Transaction) -> Result<Vec<BitcoinTxInput>, TxParseError> {
         let mut ret = vec![];
-        for inp in &tx.input {
+        for (i, inp) in tx.input.iter().enumerate() {
             match BitcoinTxInput::from_bitcoin_txin(&inp) {
                 None => {
                     test_debug!("Failed to parse input");
-                    return None;
+                    return Err(TxParseError::UnsupportedInput(i));
                 }
                 Some(i) => {
                     ret.push(i);
                 }
             };
         }
-        Some(ret)
+        Ok(ret)
     }
 
     /// Parse a transaction's outputs into burnchain tx outputs.
     /// Succeeds only if we can parse each output.
     /// Does not parse the first output -- this is the OP_RETURN
-    fn parse_outputs(&self, tx: &Transaction) -> Option<Vec<BitcoinTxOutput>> {
+    fn parse_outputs(&self, tx: &Transaction) -> Result<Vec<BitcoinTxOutput>, TxParseError> {
         let mut ret = vec![];
-        for outp in &tx.output[1..tx.output.len()] {
+        for (i, outp) in tx.output.iter().enumerate().skip(1) {
             match BitcoinTxOutput::from_bitcoin_txout(self.network_id, &outp) {
                 None => {
                     test_debug!("Failed to parse output");
-                    return None;
+                    return Err(TxParseError::UnsupportedOutput(i));
                 }
                 Some(o) => {
                     ret.push(o);
                 }
             };
         }
-        Some(ret)
+        Ok(ret)
     }
 
     /// Parse a Bitcoin transaction into a Burnchain transaction
     pub fn parse_tx(&self, tx: &Transaction, vtxindex: usize) -> Option<BitcoinTransaction> {
-        if !self.maybe_burnchain_tx(tx) {
-            test_debug!("Not a burnchain tx");
-            return None;
-        }
+        self.try_parse_tx(tx, vtxindex).ok()
+    }
 
-        let data_opt = self.parse_data(&tx.output[0].script_pubkey);
-        if data_opt.is_none() {
-            test_debug!("No OP_RETURN script");
-            return None;
-        }
+    /// Parse a Bitcoin transaction into a Burnchain transaction, and report why it is not one
+    /// if it cannot be parsed.
+    pub fn try_parse_tx(
+        &self,
+        tx: &Transaction,
+        vtxindex: usize,
+    ) -> Result<BitcoinTransaction, TxParseError> {
+        self.check_burnchain_tx(tx)?;
 
+        let (opcode, data) = self.parse_data(&tx.output[0].script_pubkey)?;
         let data_amt = tx.output[0].value;
 
-        let (opcode, data) = data_opt.unwrap();
-        let inputs_opt = self.parse_inputs(tx);
-        let outputs_opt = self.parse_outputs(tx);
-
-        match (inputs_opt, outputs_opt) {
-            (Some(inputs), Some(outputs)) => {
-                Some(BitcoinTransaction {
-                    txid: Txid::from_vec_be(&tx.txid().as_bytes().to_vec()).unwrap(), // this *should* panic if it fails
-                    vtxindex: vtxindex as u32,
-                    opcode,
-                    data,
-                    data_amt,
-                    inputs,
-                    outputs,
-                })
-            }
-            (_, _) => {
-                test_debug!("Failed to parse inputs and/or outputs");
-                None
-            }
-        }
+        let inputs = self.parse_inputs(tx)?;
+        let outputs = self.parse_outputs(tx)?;
+
+        Ok(BitcoinTransaction {
+            txid: Txid::from_vec_be(&tx.txid().as_bytes().to_vec()).unwrap(), // this *should* panic if it fails
+            vtxindex: vtxindex as u32,
+            opcode,
+            data,
+            data_amt,
+            inputs,
+            outputs,
+        })
     }
 
     /// Given a Bitcoin block, extract the transactions that have OP_RETURN <magic>.
-    /// All outputs must also either be p2pkh or p2sh, and all inputs must encode
-    /// eiher a p2pkh or multisig p2sh scriptsig.
+    /// All outputs must also be p2pkh, p2sh, or native segwit (p2wpkh, p2wsh, p2tr), and all
+    /// inputs must encode eiher a p2pkh or multisig p2sh scriptsig.
     pub fn parse_block(&self, block: &Block, block_height: u64) -> BitcoinBlock {
         let mut accepted_txs = vec![];
         for i in 0..block.txdata.len() {
@@ -472,7 +528,9 @@ impl BurnchainBlockParser for BitcoinBlockParser {
 
 #[cfg(test)]
 mod tests {
-    use crate::burnchains::bitcoin::address::{BitcoinAddress, BitcoinAddressType};
+    use crate::burnchains::bitcoin::address::{
+        BitcoinAddress, BitcoinAddressType, SegwitBitcoinAddress,
+    };
     use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
     use crate::burnchains::bitcoin::BitcoinNetworkType;
     use crate::burnchains::bitcoin::{
@@ -480,6 +538,7 @@ mod tests {
     };
     use crate::burnchains::{BurnchainBlock, BurnchainTransaction, MagicBytes, Txid};
     use stacks_common::deps_common::bitcoin::blockdata::block::{Block, LoneBlockHeader};
+    use stacks_common::deps_common::bitcoin::blockdata::script::Script;
     use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
     use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
     use stacks_common::deps_common::bitcoin::network::serialize::deserialize;
@@ -488,7 +547,7 @@ mod tests {
 
     use crate::types::chainstate::BurnchainHeaderHash;
 
-    use super::BitcoinBlockParser;
+    use super::{BitcoinBlockParser, TxParseError};
 
     struct TxFixture {
         txstr: String,
@@ -740,15 +799,83 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_tx_segwit_output() {
+        let vtxindex = 4;
+        // NAMESPACE_REVEAL with a segwit p2wpkh script pubkey
+        let txstr = "0100000001fde2146ec3ecf037ad515c0c1e2ba8abee348bd2b3c6a576bf909d78b0b18cd2010000006a47304402203ec06f11bc5b7e79fad54b2d69a375ba78576a2a0293f531a082fcfe13a9e9e802201afcf0038d9ccb9c88113248faaf812321b65d7b09b4a6e2f04f463d2741101e012103d6fd1ba0effaf1e8d94ea7b7a3d0ef26fea00a14ce5ffcc1495fe588a2c6d0f3ffffffff0300000000000000001a6a186964260000cd73fa046543210000000000aa0001746573747c1500000000000016001482093b62a3699282d926981bed7665e8384caa552076fd29010000001976a91474178497e927ff3ff1428a241be454d393c3c91c88ac00000000";
+
+        let parser = BitcoinBlockParser::new(BitcoinNetworkType::Testnet, MagicBytes([105, 100])); // "id"
+        let tx = make_tx(txstr).unwrap();
+        let burnchain_tx = parser.parse_tx(&tx, vtxindex as usize).unwrap();
+        assert_eq!(burnchain_tx.outputs.len(), 2);
+        assert_eq!(
+            burnchain_tx.outputs[0],
+            BitcoinTxOutput {
+                units: 5500,
+                address: BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WPKH(
+                    BitcoinNetworkType::Testnet,
+                    hex_bytes("82093b62a3699282d926981bed7665e8384caa55")
+                        .unwrap()
+                        .try_into()
+                        .unwrap(),
+                )),
+            }
+        );
+    }
+
+    #[test]
+    fn try_parse_tx_errors() {
+        let parser = BitcoinBlockParser::new(BitcoinNetworkType::Testnet, MagicBytes([105, 100])); // "id"
+
+        // valid
+        let tx = make_tx("010000000320a081bcd1a80d9c1945f863d29dc84278411ed74cb6dcba30541bf8d5770542020000008b483045022100be57031bf2c095945ba2876e97b3f86ee051643a29b908f22ed45ccf58620103022061e056e5f48c5a51c66604a1ca28e4bfaabab1478424c9bbb396cc6afe5c222e0141040fadbbcea0ff3b05f03195b41cd991d7a0af8bd38559943aec99cbdaf0b22cc806b9a4f07579934774cc0c155e781d45c989f94336765e88a66d91cfb9f060b0feffffff20a081bcd1a80d9c1945f863d29dc84278411ed74cb6dcba30541bf8d5770542010000008b483045022100fd9c04b330810694cb4bfef793b193f9cbfaa07325700f217b9cb03e5207005302202f07e7c9c6774c5619a043752444f6da6fd81b9d9d008ec965796d87271598de0141040fadbbcea0ff3b05f03195b41cd991d7a0af8bd38559943aec99cbdaf0b22cc806b9a4f07579934774cc0c155e781d45c989f94336765e88a66d91cfb9f060b0feffffff20a081bcd1a80d9c1945f863d29dc84278411ed74cb6dcba30541bf8d5770542040000008a47304402205e24943a40b8ef876cc218a7e8994f4be7afb7aa02403bb73510fac01b33ead3022033e5fb811c396b2fb50a825cd1d86e82eb83483901a1793d0eb15e3e9f1d1c5b814104c77f262dda02580d65c9069a8a34c56bd77325bba4110b693b90216f5a3edc0bebc8ce28d61aa86b414aa91ecb29823b11aeed06098fcd97fee4bc73d54b1e96feffffff030000000000000000296a2769642bfae543ff5672fb607fe15e16b1c3ef38737c631c7c5d911c6617993c21fba731363f1cfe6c6b0000000000001976a914395f3643cea07ec4eec73b4d9a973dcce56b9bf188acc5120100000000001976a9149f2660e75380675206b6f1e2b4f106ae33266be488ac00000000").unwrap();
+        assert!(parser.try_parse_tx(&tx, 4).is_ok());
+
+        let mut no_outputs = tx.clone();
+        no_outputs.output.clear();
+        assert_eq!(
+            parser.try_parse_tx(&no_outputs, 4),
+            Err(TxParseError::NoOutputs)
+        );
+
+        let mut bad_output = tx.clone();
+        bad_output.output[2].script_pubkey = Script::from(vec![0x51]); // OP_TRUE
+        assert_eq!(
+            parser.try_parse_tx(&bad_output, 4),
+            Err(TxParseError::UnsupportedOutput(2))
+        );
+
+        let mut bad_input = tx.clone();
+        bad_input.input[1].script_sig = Script::from(vec![0x51]); // OP_TRUE
+        assert_eq!(
+            parser.try_parse_tx(&bad_input, 4),
+            Err(TxParseError::UnsupportedInput(1))
+        );
+
+        let mut no_opcode = tx.clone();
+        no_opcode.output[0].script_pubkey = Script::from(vec![0x6a, 0x02, 105, 100]);
+        assert_eq!(
+            parser.try_parse_tx(&no_opcode, 4),
+            Err(TxParseError::DataTooShort)
+        );
+
+        // invalid magic
+        let tx = make_tx("0100000001d8b97932f097b9fbf0c7584f29515862911ac830826fdfd72d06402c21543e38000000006a47304402202801bc5d11eefddc586b1171bf607cc2be1c661d22e215153f2630316f973a200220628cc08858bba3f0cda661dbef2f007e48f8cb531edc0b54edb573226816f253012103d6967618e0159c9bfcd03ea33d368c8b2a98af5a054364c6b5e7215d7d809169ffffffff030000000000000000356a336469240efa29f955c6ae3bb5037039d89dba5e00000000000000000000000000535441434b5300000000000003e854455354217c150000000000001976a914cfd25e09f2d33e1aec73bfcc5b608ec513bbe6c088ac34460200000000001976a9144cb912533a6935880df7647fd5232e40aca07b8088ac00000000").unwrap();
+        assert_eq!(
+            parser.try_parse_tx(&tx, 4),
+            Err(TxParseError::BadMagic(vec![100, 105]))
+        );
+
+        // no OP_RETURN
+        let tx = make_tx("0200000003620f7bc1087b0111f76978ef747001e3ae0a12f254cbfb858f028f891c40e5f6010000006a47304402207f5dfc2f7f7329b7cc731df605c83aa6f48ec2218495324bb4ab43376f313b840220020c769655e4bfcc54e55104f6adc723867d9d819266d27e755e098f646f689d0121038c2d1cbe4d731c69e67d16c52682e01cb70b046ead63e90bf793f52f541dafbdfefffffff15fe7d9e0815853738ce47deadee69339e027a1dfcfb6fa887cce3a72626e7b010000006a47304402203202e6c640c063989623fc782ac1c9dc3c6fcaed996d852ec876749ba63db63b02207ef86e262ad4b4bc9cebfadb609f52c35b0105e15d58a5ecbecc5e536d3a8cd8012103dc526ca188418ab128d998bf80942d66f1b3be585d0c89bd61c533bddbdaa729feffffff84e6431db86833897bab333d844486c183dd01e69862edea442e480c2d8cb549010000006a47304402200320bc83f35ceab4a7ef0f8181eedb5f54e3f617626826cc49c8c86efc9be0b302203705889d6aed50f716b81b0f3f5769d72d1b8a6b59d1b0b73bcf94245c283b8001210263591c21ce8ee0d96a617108d7c278e2e715ac6d8afd3fcd158bee472c590068feffffff02ca780a00000000001976a914811fb695e46e2386501bcd70e5c869fe6c0bb33988ac10f59600000000001976a9140f2408a811f6d24ab1833924d98d884c44ecee8888ac6fce0700").unwrap();
+        assert_eq!(parser.try_parse_tx(&tx, 4), Err(TxParseError::NoOpReturn));
+    }
+
     #[test]
     fn parse_tx_strange() {
         let vtxindex = 4;
         let tx_fixtures_strange : Vec<TxFixture> = vec![
-            TxFixture {
-                // NAMESPACE_REVEAL with a segwit p2wpkh script pubkey (shouldn't parse)
-                txstr: "0100000001fde2146ec3ecf037ad515c0c1e2ba8abee348bd2b3c6a576bf909d78b0b18cd2010000006a47304402203ec06f11bc5b7e79fad54b2d69a375ba78576a2a0293f531a082fcfe13a9e9e802201afcf0038d9ccb9c88113248faaf812321b65d7b09b4a6e2f04f463d2741101e012103d6fd1ba0effaf1e8d94ea7b7a3d0ef26fea00a14ce5ffcc1495fe588a2c6d0f3ffffffff0300000000000000001a6a186964260000cd73fa046543210000000000aa0001746573747c1500000000000016001482093b62a3699282d926981bed7665e8384caa552076fd29010000001976a91474178497e927ff3ff1428a241be454d393c3c91c88ac00000000".to_owned(),
-                result: None
-            },
             TxFixture {
                 // coinbase 
                 txstr: "02000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0502b7020101ffffffff024023b71200000000232103ecfa5bcaa0d2b7dd3a705342be2e144f66293be99488c8e5c9bc3d843036f1bfac0000000000000000266a24aa21a9ed620a2609f2f58ea62134d1c54bf73cb6e0cf194cfbdf25ae32b55dd167ee64bb00000000".to_owned(),
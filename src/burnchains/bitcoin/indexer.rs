@@ -49,6 +49,8 @@ use stacks_common::deps_common::bitcoin::network::serialize::Error as btc_serial
 use stacks_common::util::get_epoch_time_secs;
 use stacks_common::util::log;
 
+use crate::net::socks::{socks5_connect, Socks5Proxy, Socks5Target};
+
 use crate::core::{
     StacksEpoch, STACKS_EPOCHS_MAINNET, STACKS_EPOCHS_REGTEST, STACKS_EPOCHS_TESTNET,
 };
@@ -95,7 +97,7 @@ impl TryFrom<u32> for BitcoinNetworkType {
 /// Get the default epochs definitions for the given BitcoinNetworkType.
 /// Should *not* be used except by the BitcoinIndexer when no epochs vector
 /// was specified.
-fn get_bitcoin_stacks_epochs(network_id: BitcoinNetworkType) -> Vec<StacksEpoch> {
+pub fn get_bitcoin_stacks_epochs(network_id: BitcoinNetworkType) -> Vec<StacksEpoch> {
     match network_id {
         BitcoinNetworkType::Mainnet => STACKS_EPOCHS_MAINNET.to_vec(),
         BitcoinNetworkType::Testnet => STACKS_EPOCHS_TESTNET.to_vec(),
@@ -117,6 +119,10 @@ pub struct BitcoinIndexerConfig {
     pub first_block: u64,
     pub magic_bytes: MagicBytes,
     pub epochs: Option<Vec<StacksEpoch>>,
+    /// SOCKS5 proxy through which to connect to the bitcoin peer
+    pub socks5_proxy: Option<Socks5Proxy>,
+    /// Bundle of pre-validated headers to load before asking the bitcoin peer for any
+    pub spv_headers_bundle: Option<String>,
 }
 
 #[derive(Debug)]
@@ -152,6 +158,8 @@ impl BitcoinIndexerConfig {
             first_block: 0,
             magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
             epochs: None,
+            socks5_proxy: None,
+            spv_headers_bundle: None,
         }
     }
 }
@@ -193,7 +201,25 @@ impl BitcoinIndexer {
     /// Bitcoin peer.  If we fail to connect, this method sets the socket
     /// to None.
     fn reconnect_peer(&mut self) -> Result<(), btc_error> {
-        match net::TcpStream::connect((self.config.peer_host.as_str(), self.config.peer_port)) {
+        let sock_res = match self.config.socks5_proxy {
+            Some(ref proxy) => {
+                let target =
+                    Socks5Target::from_host_port(&self.config.peer_host, self.config.peer_port);
+                socks5_connect(proxy, &target, Duration::from_secs(self.runtime.timeout)).map_err(
+                    |e| {
+                        warn!(
+                            "Failed to connect to bitcoin peer {} through SOCKS5 proxy: {:?}",
+                            &target, &e
+                        );
+                    },
+                )
+            }
+            None => {
+                net::TcpStream::connect((self.config.peer_host.as_str(), self.config.peer_port))
+                    .map_err(|_e| ())
+            }
+        };
+        match sock_res {
             Ok(s) => {
                 // Disable Nagle algorithm
                 s.set_nodelay(true).map_err(|_e| {
@@ -374,6 +400,21 @@ impl BitcoinIndexer {
             true,
             false,
         )?;
+        if let Some(bundle_path) = self.config.spv_headers_bundle.as_ref() {
+            match spv_client.import_headers_bundle(bundle_path) {
+                Ok(height) if height > start_block => {
+                    // only ask the peer for the headers after the bundle
+                    spv_client.set_scan_range(height, last_block);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(
+                        "Failed to import headers bundle {}, will sync from peer instead: {:?}",
+                        bundle_path, &e
+                    );
+                }
+            }
+        }
         if let Some(last_block) = last_block.as_ref() {
             // do we need to do anything?
             let cur_height = spv_client.get_headers_height()?;
@@ -1432,6 +1473,8 @@ mod test {
             first_block: 0,
             magic_bytes: MagicBytes([105, 100]),
             epochs: None,
+            socks5_proxy: None,
+            spv_headers_bundle: None,
         };
 
         if fs::metadata(&indexer_conf.spv_headers_path).is_ok() {
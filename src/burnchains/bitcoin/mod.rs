@@ -35,11 +35,13 @@ use crate::types::chainstate::BurnchainHeaderHash;
 
 pub mod address;
 pub mod bits;
+pub mod blockfile;
 pub mod blocks;
 pub mod indexer;
 pub mod keys;
 pub mod messages;
 pub mod network;
+pub mod op_decoder;
 pub mod spv;
 
 pub type PeerMessage = stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
@@ -81,6 +83,8 @@ pub enum Error {
     InvalidPoW,
     /// Chainwork would decrease by including a given header
     InvalidChainWork,
+    /// A header conflicts with a hard-coded header checkpoint
+    InvalidCheckpoint(u64),
     /// Wrong number of bytes for constructing an address
     InvalidByteSequence,
     /// Configuration error
@@ -110,6 +114,9 @@ impl fmt::Display for Error {
             Error::MissingHeader => write!(f, "Missing header"),
             Error::InvalidPoW => write!(f, "Invalid proof of work"),
             Error::InvalidChainWork => write!(f, "Chain difficulty cannot decrease"),
+            Error::InvalidCheckpoint(height) => {
+                write!(f, "Header conflicts with checkpoint at height {}", height)
+            }
             Error::InvalidByteSequence => write!(f, "Invalid sequence of bytes"),
             Error::ConfigError(ref e_str) => fmt::Display::fmt(e_str, f),
             Error::BlockchainHeight => write!(f, "Value is beyond the end of the blockchain"),
@@ -137,6 +144,7 @@ impl error::Error for Error {
             Error::MissingHeader => None,
             Error::InvalidPoW => None,
             Error::InvalidChainWork => None,
+            Error::InvalidCheckpoint(_) => None,
             Error::InvalidByteSequence => None,
             Error::ConfigError(ref _e_str) => None,
             Error::BlockchainHeight => None,
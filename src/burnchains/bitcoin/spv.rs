@@ -25,6 +25,7 @@ use stacks_common::deps_common::bitcoin::blockdata::constants::genesis_block;
 use stacks_common::deps_common::bitcoin::network::constants::Network;
 use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
 use stacks_common::deps_common::bitcoin::network::message as btc_message;
+use stacks_common::deps_common::bitcoin::network::serialize::Error as btc_serialize_error;
 use stacks_common::deps_common::bitcoin::network::serialize::{
     deserialize, serialize, BitcoinHash,
 };
@@ -37,6 +38,10 @@ use crate::burnchains::bitcoin::messages::BitcoinMessageHandler;
 use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::Error as btc_error;
 use crate::burnchains::bitcoin::PeerMessage;
+use crate::core::{
+    BITCOIN_MAINNET_FIRST_BLOCK_HASH, BITCOIN_MAINNET_FIRST_BLOCK_HEIGHT,
+    BITCOIN_TESTNET_FIRST_BLOCK_HASH, BITCOIN_TESTNET_FIRST_BLOCK_HEIGHT,
+};
 
 use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
 use rusqlite::OptionalExtension;
@@ -65,6 +70,92 @@ const BITCOIN_GENESIS_BLOCK_HASH_TESTNET: &'static str =
 const BITCOIN_GENESIS_BLOCK_HASH_REGTEST: &'static str =
     "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
 
+/// Known-good mainnet headers (height, hash).  Any header chain that disagrees with one of these
+/// is rejected, no matter how much work it claims to have.  The last one is the burnchain block
+/// the Stacks chain starts at.
+const BITCOIN_CHECKPOINTS_MAINNET: &[(u64, &'static str)] = &[
+    (
+        11111,
+        "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d",
+    ),
+    (
+        33333,
+        "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6",
+    ),
+    (
+        74000,
+        "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20",
+    ),
+    (
+        105000,
+        "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97",
+    ),
+    (
+        134444,
+        "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe",
+    ),
+    (
+        168000,
+        "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763",
+    ),
+    (
+        193000,
+        "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317",
+    ),
+    (
+        210000,
+        "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e",
+    ),
+    (
+        216116,
+        "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e",
+    ),
+    (
+        225430,
+        "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932",
+    ),
+    (
+        250000,
+        "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214",
+    ),
+    (
+        279000,
+        "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40",
+    ),
+    (
+        295000,
+        "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983",
+    ),
+    (
+        BITCOIN_MAINNET_FIRST_BLOCK_HEIGHT,
+        BITCOIN_MAINNET_FIRST_BLOCK_HASH,
+    ),
+];
+
+/// Known-good testnet headers (height, hash).  The last one is the burnchain block the Stacks
+/// chain starts at.
+const BITCOIN_CHECKPOINTS_TESTNET: &[(u64, &'static str)] = &[
+    (
+        546,
+        "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70",
+    ),
+    (
+        BITCOIN_TESTNET_FIRST_BLOCK_HEIGHT,
+        BITCOIN_TESTNET_FIRST_BLOCK_HASH,
+    ),
+];
+
+/// Headers bundles start with this magic, a format version byte, and a network byte, and are
+/// followed by consecutive 80-byte headers starting at the genesis block.
+const SPV_HEADERS_BUNDLE_MAGIC: &[u8; 4] = b"SPVH";
+const SPV_HEADERS_BUNDLE_VERSION: u8 = 1;
+const SPV_HEADERS_BUNDLE_PREAMBLE_SIZE: u64 = 6;
+const SPV_HEADERS_BUNDLE_HEADER_SIZE: u64 = 80;
+
+/// Number of headers to validate at once when importing a headers bundle (same as a `headers`
+/// reply from a peer)
+const SPV_HEADERS_BUNDLE_BATCH_SIZE: usize = 2000;
+
 pub const BLOCK_DIFFICULTY_CHUNK_SIZE: u64 = 2016;
 const BLOCK_DIFFICULTY_INTERVAL: u32 = 14 * 24 * 60 * 60; // two weeks, in seconds
 
@@ -105,6 +196,7 @@ pub struct SpvClient {
     readwrite: bool,
     reverse_order: bool,
     headers_db: DBConn,
+    checkpoints: Vec<(u64, Sha256dHash)>,
 }
 
 impl FromColumn<Sha256dHash> for Sha256dHash {
@@ -152,6 +244,7 @@ impl SpvClient {
             readwrite: readwrite,
             reverse_order: reverse_order,
             headers_db: conn,
+            checkpoints: SpvClient::get_checkpoints(network_id),
         };
 
         if readwrite {
@@ -180,6 +273,7 @@ impl SpvClient {
             readwrite: readwrite,
             reverse_order: reverse_order,
             headers_db: conn,
+            checkpoints: SpvClient::get_checkpoints(network_id),
         };
 
         if readwrite {
@@ -189,6 +283,56 @@ impl SpvClient {
         Ok(client)
     }
 
+    /// Get the hard-coded header checkpoints for a network, in ascending height order.
+    /// Regtest has none.
+    pub fn get_checkpoints(network_id: BitcoinNetworkType) -> Vec<(u64, Sha256dHash)> {
+        let checkpoints = match network_id {
+            BitcoinNetworkType::Mainnet => BITCOIN_CHECKPOINTS_MAINNET,
+            BitcoinNetworkType::Testnet => BITCOIN_CHECKPOINTS_TESTNET,
+            BitcoinNetworkType::Regtest => &[],
+        };
+        checkpoints
+            .iter()
+            .map(|(height, hash_str)| {
+                (
+                    *height,
+                    Sha256dHash::from_hex(hash_str).expect("FATAL: invalid checkpoint hash"),
+                )
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    pub fn set_checkpoints(&mut self, checkpoints: Vec<(u64, Sha256dHash)>) {
+        self.checkpoints = checkpoints;
+    }
+
+    /// Verify that a run of headers, the first of which is at height `start_height`, agrees with
+    /// every checkpoint it covers.
+    fn validate_header_checkpoints(
+        &self,
+        start_height: u64,
+        headers: &[LoneBlockHeader],
+    ) -> Result<(), btc_error> {
+        let end_height = start_height + (headers.len() as u64);
+        for (height, hash) in self.checkpoints.iter() {
+            if *height < start_height || *height >= end_height {
+                continue;
+            }
+            let header_hash = headers[(*height - start_height) as usize]
+                .header
+                .bitcoin_hash();
+            if header_hash != *hash {
+                warn!(
+                    "Header at height {} has hash {}, but checkpoint is {}",
+                    height, &header_hash, hash
+                );
+                return Err(btc_error::InvalidCheckpoint(*height));
+            }
+        }
+        Ok(())
+    }
+
     pub fn conn(&self) -> &DBConn {
         &self.headers_db
     }
@@ -747,7 +891,7 @@ impl SpvClient {
     /// -- store them
     /// Can error if there has been a reorg, or if the headers don't correspond to headers we asked
     /// for, or if the new chain has less total work than the old chain.
-    fn handle_headers(
+    pub fn handle_headers(
         &mut self,
         insert_height: u64,
         block_headers: Vec<LoneBlockHeader>,
@@ -864,8 +1008,8 @@ impl SpvClient {
     }
 
     /// Insert block headers into the headers DB.
-    /// Verify that the first header's parent exists and connects with this header chain, and verify that
-    /// the headers are themselves contiguous.
+    /// Verify that the first header's parent exists and connects with this header chain, verify that
+    /// the headers are themselves contiguous, and verify that they agree with any checkpoints.
     /// start_height refers to the _parent block_ of the given header stream.
     pub fn insert_block_headers_after(
         &mut self,
@@ -891,6 +1035,12 @@ impl SpvClient {
             e
         })?;
 
+        self.validate_header_checkpoints(start_height + 1, &block_headers)
+            .map_err(|e| {
+                error!("Received headers that conflict with a checkpoint: {:?}", &e);
+                e
+            })?;
+
         let parent_header = match self.read_block_header(start_height)? {
             Some(header) => header,
             None => {
@@ -917,6 +1067,7 @@ impl SpvClient {
 
     /// Insert block headers into the headers DB.
     /// If the last header's child exists, verify that it connects with the given header chain.
+    /// The headers must agree with any checkpoints.
     /// start_height refers to the _parent block_ of the given header stream
     pub fn insert_block_headers_before(
         &mut self,
@@ -944,6 +1095,12 @@ impl SpvClient {
             e
         })?;
 
+        self.validate_header_checkpoints(start_height + 1, &block_headers)
+            .map_err(|e| {
+                error!("Received headers that conflict with a checkpoint: {:?}", &e);
+                e
+            })?;
+
         match self.read_block_header(end_height)? {
             Some(child_header) => {
                 // contiguous?
@@ -989,6 +1146,202 @@ impl SpvClient {
         Ok(())
     }
 
+    /// Network identifier byte in a headers bundle's preamble
+    fn headers_bundle_network_byte(network_id: BitcoinNetworkType) -> u8 {
+        match network_id {
+            BitcoinNetworkType::Mainnet => 0,
+            BitcoinNetworkType::Testnet => 1,
+            BitcoinNetworkType::Regtest => 2,
+        }
+    }
+
+    /// Decode a headers bundle for the given network into its headers, starting at the genesis
+    /// block.  Does not validate the headers.
+    fn decode_headers_bundle(
+        network_id: BitcoinNetworkType,
+        bytes: &[u8],
+    ) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        let preamble_size = SPV_HEADERS_BUNDLE_PREAMBLE_SIZE as usize;
+        let header_size = SPV_HEADERS_BUNDLE_HEADER_SIZE as usize;
+        if bytes.len() < preamble_size
+            || bytes[0..4] != SPV_HEADERS_BUNDLE_MAGIC[..]
+            || bytes[4] != SPV_HEADERS_BUNDLE_VERSION
+        {
+            return Err(btc_error::SerializationError(
+                btc_serialize_error::ParseFailed("not a headers bundle"),
+            ));
+        }
+        if bytes[5] != SpvClient::headers_bundle_network_byte(network_id) {
+            return Err(btc_error::ConfigError(format!(
+                "Headers bundle is not for {:?}",
+                network_id
+            )));
+        }
+        if bytes.len() == preamble_size || (bytes.len() - preamble_size) % header_size != 0 {
+            return Err(btc_error::SerializationError(
+                btc_serialize_error::ParseFailed("truncated headers bundle"),
+            ));
+        }
+
+        bytes[preamble_size..]
+            .chunks(header_size)
+            .map(|chunk| {
+                deserialize(chunk)
+                    .map(|header| LoneBlockHeader {
+                        header,
+                        tx_count: VarInt(0),
+                    })
+                    .map_err(btc_error::SerializationError)
+            })
+            .collect()
+    }
+
+    /// Write our headers from the genesis block up to and including `end_height` to a headers
+    /// bundle, which a new node can load with `import_headers_bundle()` instead of downloading
+    /// them from its bitcoin peer.
+    /// Returns the height of the last header written.
+    pub fn export_headers_bundle(
+        &self,
+        bundle_path: &str,
+        end_height: u64,
+    ) -> Result<u64, btc_error> {
+        let end_height = cmp::min(end_height, self.get_highest_header_height()?);
+
+        let mut bytes = Vec::with_capacity(
+            (SPV_HEADERS_BUNDLE_PREAMBLE_SIZE + (end_height + 1) * SPV_HEADERS_BUNDLE_HEADER_SIZE)
+                as usize,
+        );
+        bytes.extend_from_slice(&SPV_HEADERS_BUNDLE_MAGIC[..]);
+        bytes.push(SPV_HEADERS_BUNDLE_VERSION);
+        bytes.push(SpvClient::headers_bundle_network_byte(self.network_id));
+
+        let mut height = 0;
+        while height <= end_height {
+            let batch_end = cmp::min(
+                height + (SPV_HEADERS_BUNDLE_BATCH_SIZE as u64),
+                end_height + 1,
+            );
+            let headers = self.read_block_headers(height, batch_end)?;
+            if (headers.len() as u64) != batch_end - height {
+                warn!(
+                    "Missing header {} in {}",
+                    height + (headers.len() as u64),
+                    &self.headers_path
+                );
+                return Err(btc_error::MissingHeader);
+            }
+            for header in headers.iter() {
+                bytes.extend_from_slice(
+                    &serialize(&header.header).map_err(btc_error::SerializationError)?,
+                );
+            }
+            height = batch_end;
+        }
+
+        fs::File::create(bundle_path)
+            .and_then(|mut f| f.write_all(&bytes))
+            .map_err(btc_error::FilesystemError)?;
+
+        debug!(
+            "Exported headers 0-{} from {} to {}",
+            end_height, &self.headers_path, bundle_path
+        );
+        Ok(end_height)
+    }
+
+    /// Import a headers bundle written by `export_headers_bundle()`.
+    /// The bundle must agree with the headers we already have and with our checkpoints.  Headers
+    /// up to the highest checkpoint in the bundle are bound to it by their hashes, so they are
+    /// stored without re-checking their proof-of-work; the headers after it are validated exactly
+    /// like headers received from the bitcoin peer.
+    /// Does nothing if we already have at least as many headers as the bundle.
+    /// Returns the height of the highest header we have afterwards.
+    pub fn import_headers_bundle(&mut self, bundle_path: &str) -> Result<u64, btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+        assert!(
+            !self.reverse_order,
+            "Cannot import a headers bundle in reverse order"
+        );
+
+        let our_height = self.get_highest_header_height()?;
+        let bundle_len = fs::metadata(bundle_path)
+            .map_err(btc_error::FilesystemError)?
+            .len();
+        let bundle_num_headers = bundle_len.saturating_sub(SPV_HEADERS_BUNDLE_PREAMBLE_SIZE)
+            / SPV_HEADERS_BUNDLE_HEADER_SIZE;
+        if bundle_num_headers <= our_height + 1 {
+            debug!(
+                "Headers bundle {} has {} headers, and {} already has headers up to {}",
+                bundle_path, bundle_num_headers, &self.headers_path, our_height
+            );
+            return Ok(our_height);
+        }
+
+        let mut bytes = vec![];
+        fs::File::open(bundle_path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(btc_error::FilesystemError)?;
+
+        let headers = SpvClient::decode_headers_bundle(self.network_id, &bytes)?;
+        SpvClient::validate_header_integrity(0, &headers)?;
+        self.validate_header_checkpoints(0, &headers)?;
+
+        // the bundle must extend our chain.  Since both are hash chains, agreeing on our tip
+        // means agreeing on every header before it.
+        let our_tip = self
+            .read_block_header(our_height)?
+            .ok_or(btc_error::MissingHeader)?;
+        if headers[our_height as usize].header.bitcoin_hash() != our_tip.header.bitcoin_hash() {
+            warn!(
+                "Headers bundle {} does not contain our header {} at height {}",
+                bundle_path,
+                our_tip.header.bitcoin_hash(),
+                our_height
+            );
+            return Err(btc_error::NoncontiguousHeader);
+        }
+
+        let num_headers = headers.len() as u64;
+        let anchor_height = self
+            .checkpoints
+            .iter()
+            .map(|(height, _)| *height)
+            .filter(|height| *height < num_headers)
+            .max()
+            .unwrap_or(0);
+
+        let mut height = our_height;
+        if anchor_height > height {
+            self.write_block_headers(
+                height + 1,
+                headers[((height + 1) as usize)..((anchor_height + 1) as usize)].to_vec(),
+            )?;
+            self.update_chain_work()?;
+            height = anchor_height;
+        }
+
+        while height + 1 < num_headers {
+            let batch_end = cmp::min(
+                height + 1 + (SPV_HEADERS_BUNDLE_BATCH_SIZE as u64),
+                num_headers,
+            );
+            self.handle_headers(
+                height,
+                headers[((height + 1) as usize)..(batch_end as usize)].to_vec(),
+            )?;
+            height = batch_end - 1;
+        }
+
+        info!(
+            "Imported headers {}-{} from {} into {}",
+            our_height + 1,
+            height,
+            bundle_path,
+            &self.headers_path
+        );
+        Ok(height)
+    }
+
     /// Determine the (bits, target) between two headers
     pub fn get_target_between_headers(
         first_header: &LoneBlockHeader,
@@ -1246,6 +1599,7 @@ mod test {
         deserialize, serialize, BitcoinHash,
     };
     use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+    use stacks_common::types::chainstate::BurnchainHeaderHash;
 
     use stacks_common::util::log;
 
@@ -1587,6 +1941,211 @@ mod test {
             .unwrap();
     }
 
+    fn get_regtest_headers() -> Vec<LoneBlockHeader> {
+        let merkle_roots = [
+            "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
+            "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
+            "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
+        ];
+        let nonces = [2, 1, 4];
+        let times = [1587626881, 1587626882, 1587626882];
+
+        let mut prev_blockhash = get_genesis_regtest_header().header.bitcoin_hash();
+        let mut headers = vec![];
+        for i in 0..3 {
+            let header = LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(merkle_roots[i]).unwrap(),
+                    nonce: nonces[i],
+                    prev_blockhash,
+                    time: times[i],
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            };
+            prev_blockhash = header.header.bitcoin_hash();
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_spv_checkpoints() {
+        for (network_id, first_height, stacks_first_height, stacks_first_hash) in [
+            (
+                BitcoinNetworkType::Mainnet,
+                11111,
+                BITCOIN_MAINNET_FIRST_BLOCK_HEIGHT,
+                BITCOIN_MAINNET_FIRST_BLOCK_HASH,
+            ),
+            (
+                BitcoinNetworkType::Testnet,
+                546,
+                BITCOIN_TESTNET_FIRST_BLOCK_HEIGHT,
+                BITCOIN_TESTNET_FIRST_BLOCK_HASH,
+            ),
+        ] {
+            let checkpoints = SpvClient::get_checkpoints(network_id);
+            assert_eq!(checkpoints[0].0, first_height);
+            for i in 1..checkpoints.len() {
+                assert!(checkpoints[i - 1].0 < checkpoints[i].0);
+            }
+
+            // the Stacks chain's first burnchain block is checkpointed, in the same byte order as
+            // the burnchain header hash the rest of the node uses for it
+            let (last_height, last_hash) = checkpoints.last().unwrap();
+            assert_eq!(*last_height, stacks_first_height);
+            assert_eq!(
+                BurnchainHeaderHash::from_bitcoin_hash(last_hash),
+                BurnchainHeaderHash::from_hex(stacks_first_hash).unwrap()
+            );
+        }
+        assert!(SpvClient::get_checkpoints(BitcoinNetworkType::Regtest).is_empty());
+
+        let path = "/tmp/test-spv-checkpoints.dat";
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path).unwrap();
+        }
+
+        let headers = get_regtest_headers();
+        let mut spv_client =
+            SpvClient::new(path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+
+        // conflicting checkpoint at height 2
+        spv_client.set_checkpoints(vec![(2, headers[0].header.bitcoin_hash())]);
+        match spv_client.insert_block_headers_after(0, headers.clone()) {
+            Err(btc_error::InvalidCheckpoint(2)) => {}
+            x => panic!("Expected checkpoint mismatch, got {:?}", &x),
+        }
+        match spv_client.insert_block_headers_before(0, headers.clone()) {
+            Err(btc_error::InvalidCheckpoint(2)) => {}
+            x => panic!("Expected checkpoint mismatch, got {:?}", &x),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 0);
+
+        // checkpoints out of range are ignored
+        spv_client.set_checkpoints(vec![(4, headers[0].header.bitcoin_hash())]);
+        spv_client
+            .insert_block_headers_after(0, headers[0..2].to_vec())
+            .unwrap();
+        spv_client.drop_headers(0).unwrap();
+
+        // agreeing checkpoint
+        spv_client.set_checkpoints(vec![(2, headers[1].header.bitcoin_hash())]);
+        spv_client
+            .insert_block_headers_after(0, headers.clone())
+            .unwrap();
+        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), headers);
+    }
+
+    #[test]
+    fn test_spv_headers_bundle() {
+        let source_path = "/tmp/test-spv-headers-bundle-source.dat";
+        let dest_path = "/tmp/test-spv-headers-bundle-dest.dat";
+        let bundle_path = "/tmp/test-spv-headers-bundle.bin";
+        for path in [source_path, dest_path, bundle_path] {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
+        }
+
+        let headers = get_regtest_headers();
+        let mut source_client = SpvClient::new(
+            source_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            false,
+        )
+        .unwrap();
+        source_client
+            .insert_block_headers_after(0, headers.clone())
+            .unwrap();
+
+        // export is capped at our highest header
+        assert_eq!(
+            source_client
+                .export_headers_bundle(bundle_path, u64::MAX)
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            fs::metadata(bundle_path).unwrap().len(),
+            SPV_HEADERS_BUNDLE_PREAMBLE_SIZE + 4 * SPV_HEADERS_BUNDLE_HEADER_SIZE
+        );
+
+        // import into a node that has some of the headers already
+        let mut dest_client =
+            SpvClient::new(dest_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        dest_client
+            .insert_block_headers_after(0, headers[0..1].to_vec())
+            .unwrap();
+        assert_eq!(dest_client.import_headers_bundle(bundle_path).unwrap(), 3);
+        assert_eq!(
+            dest_client.read_block_headers(0, 10).unwrap(),
+            source_client.read_block_headers(0, 10).unwrap()
+        );
+        assert_eq!(
+            dest_client.update_chain_work().unwrap(),
+            source_client.update_chain_work().unwrap()
+        );
+
+        // no-op once we have all of them
+        assert_eq!(dest_client.import_headers_bundle(bundle_path).unwrap(), 3);
+
+        // headers up to a checkpoint are stored directly
+        dest_client.drop_headers(0).unwrap();
+        dest_client.set_checkpoints(vec![(2, headers[1].header.bitcoin_hash())]);
+        assert_eq!(dest_client.import_headers_bundle(bundle_path).unwrap(), 3);
+        assert_eq!(dest_client.read_block_headers(1, 10).unwrap(), headers);
+
+        // bundle conflicts with a checkpoint
+        dest_client.drop_headers(0).unwrap();
+        dest_client.set_checkpoints(vec![(2, headers[0].header.bitcoin_hash())]);
+        match dest_client.import_headers_bundle(bundle_path) {
+            Err(btc_error::InvalidCheckpoint(2)) => {}
+            x => panic!("Expected checkpoint mismatch, got {:?}", &x),
+        }
+        assert_eq!(dest_client.get_highest_header_height().unwrap(), 0);
+        dest_client.set_checkpoints(vec![]);
+
+        // bundle conflicts with our headers
+        let mut other_header = headers[0].clone();
+        other_header.header.nonce += 1;
+        dest_client
+            .insert_block_headers_after(0, vec![other_header])
+            .unwrap();
+        match dest_client.import_headers_bundle(bundle_path) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            x => panic!("Expected noncontiguous header, got {:?}", &x),
+        }
+        dest_client.drop_headers(0).unwrap();
+
+        // truncated bundle
+        let mut bundle = vec![];
+        fs::File::open(bundle_path)
+            .unwrap()
+            .read_to_end(&mut bundle)
+            .unwrap();
+        fs::File::create(bundle_path)
+            .unwrap()
+            .write_all(&bundle[0..bundle.len() - 1])
+            .unwrap();
+        match dest_client.import_headers_bundle(bundle_path) {
+            Err(btc_error::SerializationError(_)) => {}
+            x => panic!("Expected serialization error, got {:?}", &x),
+        }
+
+        // wrong network
+        assert!(SpvClient::decode_headers_bundle(BitcoinNetworkType::Regtest, &bundle).is_ok());
+        match SpvClient::decode_headers_bundle(BitcoinNetworkType::Testnet, &bundle) {
+            Err(btc_error::ConfigError(_)) => {}
+            x => panic!("Expected config error, got {:?}", &x),
+        }
+    }
+
     #[test]
     fn test_spv_check_pow() {
         if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
@@ -31,6 +31,7 @@ use crate::burnchains::bitcoin::address::address_type_to_version_byte;
 use crate::burnchains::bitcoin::address::to_c32_version_byte;
 use crate::burnchains::bitcoin::address::BitcoinAddress;
 use crate::burnchains::bitcoin::address::BitcoinAddressType;
+use crate::burnchains::bitcoin::indexer::{BITCOIN_MAINNET_NAME, BITCOIN_TESTNET_NAME};
 use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::{BitcoinInputType, BitcoinTxInput, BitcoinTxOutput};
 use crate::burnchains::db::BurnchainDB;
@@ -43,23 +44,30 @@ use crate::burnchains::PublicKey;
 use crate::burnchains::Txid;
 use crate::burnchains::{
     BurnchainBlock, BurnchainBlockHeader, BurnchainParameters, BurnchainRecipient, BurnchainSigner,
-    BurnchainStateTransition, BurnchainStateTransitionOps, BurnchainTransaction,
+    BurnchainStateTransition, BurnchainStateTransitionOps, BurnchainTransaction, ClassifyError,
     Error as burnchain_error, PoxConstants,
 };
 use crate::chainstate::burn::db::sortdb::{SortitionDB, SortitionHandleConn, SortitionHandleTx};
 use crate::chainstate::burn::distribution::BurnSamplePoint;
 use crate::chainstate::burn::operations::{
-    leader_block_commit::MissedBlockCommit, BlockstackOperationType, LeaderBlockCommitOp,
-    LeaderKeyRegisterOp, PreStxOp, StackStxOp, TransferStxOp, UserBurnSupportOp,
+    leader_block_commit::MissedBlockCommit, BlockstackOperationType, DelegateStxOp,
+    LeaderBlockCommitOp, LeaderKeyRegisterOp, PreStxOp, StackStxOp, TransferStxOp,
+    UserBurnSupportOp,
 };
 use crate::chainstate::burn::{BlockSnapshot, Opcodes};
 use crate::chainstate::coordinator::comm::CoordinatorChannels;
+use crate::chainstate::stacks::address::PoxAddress;
 use crate::chainstate::stacks::StacksPublicKey;
 use crate::core::MINING_COMMITMENT_WINDOW;
 use crate::core::NETWORK_ID_MAINNET;
 use crate::core::NETWORK_ID_TESTNET;
 use crate::core::PEER_VERSION_MAINNET;
 use crate::core::PEER_VERSION_TESTNET;
+use crate::core::{
+    BITCOIN_MAINNET_DELEGATE_STX_HEIGHT, BITCOIN_MAINNET_SEGWIT_OUTPUTS_HEIGHT,
+    BITCOIN_REGTEST_DELEGATE_STX_HEIGHT, BITCOIN_REGTEST_SEGWIT_OUTPUTS_HEIGHT,
+    BITCOIN_TESTNET_DELEGATE_STX_HEIGHT, BITCOIN_TESTNET_SEGWIT_OUTPUTS_HEIGHT,
+};
 use crate::deps;
 use crate::monitoring::update_burnchain_height;
 use crate::types::chainstate::StacksAddress;
@@ -134,6 +142,9 @@ impl BurnchainStateTransition {
                 BlockstackOperationType::StackStx(_) => {
                     accepted_ops.push(block_ops[i].clone());
                 }
+                BlockstackOperationType::DelegateStx(_) => {
+                    accepted_ops.push(block_ops[i].clone());
+                }
                 BlockstackOperationType::TransferStx(_) => {
                     accepted_ops.push(block_ops[i].clone());
                 }
@@ -355,9 +366,8 @@ impl BurnchainSigner {
 
 impl BurnchainRecipient {
     pub fn from_bitcoin_output(o: &BitcoinTxOutput) -> BurnchainRecipient {
-        let stacks_addr = StacksAddress::from_bitcoin_address(&o.address);
         BurnchainRecipient {
-            address: stacks_addr,
+            address: PoxAddress::from_bitcoin_address(&o.address),
             amount: o.units,
         }
     }
@@ -458,6 +468,24 @@ impl Burnchain {
         self.network_id == NETWORK_ID_MAINNET
     }
 
+    /// Burnchain height at which transactions with native segwit outputs start being considered
+    pub fn segwit_outputs_height(&self) -> u64 {
+        match self.network_name.as_str() {
+            BITCOIN_MAINNET_NAME => BITCOIN_MAINNET_SEGWIT_OUTPUTS_HEIGHT,
+            BITCOIN_TESTNET_NAME => BITCOIN_TESTNET_SEGWIT_OUTPUTS_HEIGHT,
+            _ => BITCOIN_REGTEST_SEGWIT_OUTPUTS_HEIGHT,
+        }
+    }
+
+    /// Burnchain height at which DelegateStx operations start being accepted
+    pub fn delegate_stx_height(&self) -> u64 {
+        match self.network_name.as_str() {
+            BITCOIN_MAINNET_NAME => BITCOIN_MAINNET_DELEGATE_STX_HEIGHT,
+            BITCOIN_TESTNET_NAME => BITCOIN_TESTNET_DELEGATE_STX_HEIGHT,
+            _ => BITCOIN_REGTEST_DELEGATE_STX_HEIGHT,
+        }
+    }
+
     /// the expected sunset burn is:
     ///   total_commit * (progress through sunset phase) / (sunset phase duration)
     pub fn expected_sunset_burn(&self, burn_height: u64, total_commit: u64) -> u64 {
@@ -685,127 +713,127 @@ impl Burnchain {
         burn_tx: &BurnchainTransaction,
         pre_stx_op_map: &HashMap<Txid, PreStxOp>,
     ) -> Option<BlockstackOperationType> {
+        match Burnchain::try_classify_transaction(
+            burnchain,
+            Some(burnchain_db),
+            block_header,
+            burn_tx,
+            pre_stx_op_map,
+        ) {
+            Ok(op) => Some(op),
+            Err(ClassifyError::SegwitBeforeActivation(height)) => {
+                debug!(
+                    "Ignoring tx with native segwit outputs before height {}",
+                    height;
+                    "txid" => %burn_tx.txid(),
+                );
+                None
+            }
+            Err(ClassifyError::UnknownOpcode(_)) | Err(ClassifyError::BadPreStxInput(_)) => None,
+            Err(ClassifyError::MissingPreStx(pre_stx_txid)) => {
+                warn!(
+                    "Failed to find corresponding PreStxOp input";
+                    "txid" => %burn_tx.txid(),
+                    "opcode" => burn_tx.opcode(),
+                    "pre_stx_txid" => %pre_stx_txid
+                );
+                None
+            }
+            Err(ClassifyError::InvalidOp(e)) => {
+                warn!(
+                    "Failed to parse burnchain operation";
+                    "txid" => %burn_tx.txid(),
+                    "opcode" => burn_tx.opcode(),
+                    "data" => %to_hex(&burn_tx.data()),
+                    "error" => ?e,
+                );
+                None
+            }
+        }
+    }
+
+    /// Classify a burnchain transaction as a Blockstack operation, or report why it is not one.
+    /// TransferStx and StackStx operations are checked against the PreStxOp they spend, which is
+    /// looked up in `pre_stx_op_map` and then in the burnchain DB, if given.
+    pub fn try_classify_transaction(
+        burnchain: &Burnchain,
+        burnchain_db: Option<&BurnchainDB>,
+        block_header: &BurnchainBlockHeader,
+        burn_tx: &BurnchainTransaction,
+        pre_stx_op_map: &HashMap<Txid, PreStxOp>,
+    ) -> Result<BlockstackOperationType, ClassifyError> {
+        if burn_tx.has_segwit_outputs()
+            && block_header.block_height < burnchain.segwit_outputs_height()
+        {
+            return Err(ClassifyError::SegwitBeforeActivation(
+                burnchain.segwit_outputs_height(),
+            ));
+        }
+
+        let find_pre_stx = |pre_stx_txid: &Txid| match pre_stx_op_map.get(pre_stx_txid) {
+            Some(pre_stx) => Ok(pre_stx.clone()),
+            None => match burnchain_db.and_then(|db| db.get_burnchain_op(pre_stx_txid)) {
+                Some(BlockstackOperationType::PreStx(pre_stx)) => Ok(pre_stx),
+                _ => Err(ClassifyError::MissingPreStx(*pre_stx_txid)),
+            },
+        };
+
         match burn_tx.opcode() {
             x if x == Opcodes::LeaderKeyRegister as u8 => {
-                match LeaderKeyRegisterOp::from_tx(block_header, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::LeaderKeyRegister(op)),
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse leader key register tx";
-                            "txid" => %burn_tx.txid(),
-                            "data" => %to_hex(&burn_tx.data()),
-                            "error" => ?e,
-                        );
-                        None
-                    }
-                }
+                LeaderKeyRegisterOp::from_tx(block_header, burn_tx)
+                    .map(BlockstackOperationType::LeaderKeyRegister)
+                    .map_err(ClassifyError::InvalidOp)
             }
             x if x == Opcodes::LeaderBlockCommit as u8 => {
-                match LeaderBlockCommitOp::from_tx(burnchain, block_header, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::LeaderBlockCommit(op)),
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse leader block commit tx";
-                            "txid" => %burn_tx.txid(),
-                            "data" => %to_hex(&burn_tx.data()),
-                            "error" => ?e,
-                        );
-                        None
-                    }
-                }
+                LeaderBlockCommitOp::from_tx(burnchain, block_header, burn_tx)
+                    .map(BlockstackOperationType::LeaderBlockCommit)
+                    .map_err(ClassifyError::InvalidOp)
             }
             x if x == Opcodes::UserBurnSupport as u8 => {
-                match UserBurnSupportOp::from_tx(block_header, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::UserBurnSupport(op)),
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse user burn support tx";
-                            "txid" => %burn_tx.txid(),
-                            "data" => %to_hex(&burn_tx.data()),
-                            "error" => ?e,
-                        );
-                        None
-                    }
-                }
+                UserBurnSupportOp::from_tx(block_header, burn_tx)
+                    .map(BlockstackOperationType::UserBurnSupport)
+                    .map_err(ClassifyError::InvalidOp)
             }
             x if x == Opcodes::PreStx as u8 => {
-                match PreStxOp::from_tx(block_header, burn_tx, burnchain.pox_constants.sunset_end) {
-                    Ok(op) => Some(BlockstackOperationType::PreStx(op)),
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse pre stack stx tx";
-                            "txid" => %burn_tx.txid(),
-                            "data" => %to_hex(&burn_tx.data()),
-                            "error" => ?e,
-                        );
-                        None
-                    }
-                }
+                PreStxOp::from_tx(block_header, burn_tx, burnchain.pox_constants.sunset_end)
+                    .map(BlockstackOperationType::PreStx)
+                    .map_err(ClassifyError::InvalidOp)
             }
             x if x == Opcodes::TransferStx as u8 => {
-                let pre_stx_txid = TransferStxOp::get_sender_txid(burn_tx).ok()?;
-                let pre_stx_tx = match pre_stx_op_map.get(&pre_stx_txid) {
-                    Some(tx_ref) => Some(BlockstackOperationType::PreStx(tx_ref.clone())),
-                    None => burnchain_db.get_burnchain_op(pre_stx_txid),
-                };
-                if let Some(BlockstackOperationType::PreStx(pre_stx)) = pre_stx_tx {
-                    let sender = &pre_stx.output;
-                    match TransferStxOp::from_tx(block_header, burn_tx, sender) {
-                        Ok(op) => Some(BlockstackOperationType::TransferStx(op)),
-                        Err(e) => {
-                            warn!(
-                                "Failed to parse transfer stx tx";
-                                "txid" => %burn_tx.txid(),
-                                "data" => %to_hex(&burn_tx.data()),
-                                "error" => ?e,
-                            );
-                            None
-                        }
-                    }
-                } else {
-                    warn!(
-                        "Failed to find corresponding input to TransferStxOp";
-                        "txid" => %burn_tx.txid(),
-                        "pre_stx_txid" => %pre_stx_txid
-                    );
-                    None
-                }
+                let pre_stx_txid = TransferStxOp::get_sender_txid(burn_tx)
+                    .map_err(ClassifyError::BadPreStxInput)?;
+                let pre_stx = find_pre_stx(pre_stx_txid)?;
+                TransferStxOp::from_tx(block_header, burn_tx, &pre_stx.output)
+                    .map(BlockstackOperationType::TransferStx)
+                    .map_err(ClassifyError::InvalidOp)
             }
             x if x == Opcodes::StackStx as u8 => {
-                let pre_stx_txid = StackStxOp::get_sender_txid(burn_tx).ok()?;
-                let pre_stx_tx = match pre_stx_op_map.get(&pre_stx_txid) {
-                    Some(tx_ref) => Some(BlockstackOperationType::PreStx(tx_ref.clone())),
-                    None => burnchain_db.get_burnchain_op(pre_stx_txid),
-                };
-                if let Some(BlockstackOperationType::PreStx(pre_stack_stx)) = pre_stx_tx {
-                    let sender = &pre_stack_stx.output;
-                    match StackStxOp::from_tx(
-                        block_header,
-                        burn_tx,
-                        sender,
-                        burnchain.pox_constants.sunset_end,
-                    ) {
-                        Ok(op) => Some(BlockstackOperationType::StackStx(op)),
-                        Err(e) => {
-                            warn!(
-                                "Failed to parse stack stx tx";
-                                "txid" => %burn_tx.txid(),
-                                "data" => %to_hex(&burn_tx.data()),
-                                "error" => ?e,
-                            );
-                            None
-                        }
-                    }
-                } else {
-                    warn!(
-                        "Failed to find corresponding input to StackStxOp";
-                        "txid" => %burn_tx.txid().to_string(),
-                        "pre_stx_txid" => %pre_stx_txid.to_string()
-                    );
-                    None
-                }
+                let pre_stx_txid =
+                    StackStxOp::get_sender_txid(burn_tx).map_err(ClassifyError::BadPreStxInput)?;
+                let pre_stx = find_pre_stx(pre_stx_txid)?;
+                StackStxOp::from_tx(
+                    block_header,
+                    burn_tx,
+                    &pre_stx.output,
+                    burnchain.pox_constants.sunset_end,
+                )
+                .map(BlockstackOperationType::StackStx)
+                .map_err(ClassifyError::InvalidOp)
+            }
+            x if x == Opcodes::DelegateStx as u8 => {
+                let pre_stx_txid = DelegateStxOp::get_sender_txid(burn_tx)
+                    .map_err(ClassifyError::BadPreStxInput)?;
+                let pre_stx = find_pre_stx(pre_stx_txid)?;
+                DelegateStxOp::from_tx(
+                    block_header,
+                    burn_tx,
+                    &pre_stx.output,
+                    burnchain.pox_constants.sunset_end,
+                )
+                .map(BlockstackOperationType::DelegateStx)
+                .map_err(ClassifyError::InvalidOp)
             }
-            _ => None,
+            x => Err(ClassifyError::UnknownOpcode(x)),
         }
     }
 
@@ -1579,8 +1607,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a914306231b2782b5f80d944bf69f9d46a1453a0a0eb88ac").unwrap(),
                 )
@@ -1608,8 +1636,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a914306231b2782b5f80d944bf69f9d46a1453a0a0eb88ac").unwrap(),
                 )
@@ -1637,8 +1665,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a914f464a593895cd58c74a7352dd4a65c491d0c0bf688ac").unwrap(),
                 )
@@ -2414,7 +2442,7 @@ pub mod tests {
             };
 
             leader_bitcoin_addresses.push(
-                BitcoinAddress::from_bytes(
+                LegacyBitcoinAddress::from_bytes(
                     BitcoinNetworkType::Testnet,
                     BitcoinAddressType::PublicKeyHash,
                     &btc_input.to_address_bits(),
@@ -2543,7 +2571,7 @@ pub mod tests {
                 )
                 .unwrap(),
                 memo: vec![0, 0, 0, 0, i],
-                address: StacksAddress::from_bitcoin_address(
+                address: StacksAddress::from_legacy_bitcoin_address(
                     &leader_bitcoin_addresses[i as usize].clone(),
                 ),
 
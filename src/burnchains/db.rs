@@ -395,14 +395,18 @@ impl BurnchainDB {
 
 #[cfg(test)]
 mod tests {
-    use crate::chainstate::stacks::address::StacksAddressExtensions;
+    use crate::chainstate::stacks::address::{PoxAddress, StacksAddressExtensions};
     use std::convert::TryInto;
 
     use crate::burnchains::bitcoin::address::*;
     use crate::burnchains::bitcoin::blocks::*;
+    use crate::burnchains::bitcoin::indexer::BITCOIN_MAINNET_NAME;
     use crate::burnchains::bitcoin::*;
+    use crate::burnchains::BurnchainTransaction;
+    use crate::burnchains::ClassifyError;
     use crate::burnchains::PoxConstants;
     use crate::burnchains::BLOCKSTACK_MAGIC_MAINNET;
+    use crate::chainstate::burn::operations::{Error as op_error, PreStxOp};
     use crate::chainstate::burn::*;
     use crate::chainstate::stacks::*;
     use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction as BtcTx;
@@ -589,11 +593,11 @@ mod tests {
             }],
             outputs: vec![BitcoinTxOutput {
                 units: 10,
-                address: BitcoinAddress {
+                address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     network_id: BitcoinNetworkType::Mainnet,
                     bytes: Hash160([1; 20]),
-                },
+                }),
             }],
         };
 
@@ -612,11 +616,11 @@ mod tests {
             }],
             outputs: vec![BitcoinTxOutput {
                 units: 10,
-                address: BitcoinAddress {
+                address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     network_id: BitcoinNetworkType::Mainnet,
                     bytes: Hash160([1; 20]),
-                },
+                }),
             }],
         };
 
@@ -635,11 +639,11 @@ mod tests {
             }],
             outputs: vec![BitcoinTxOutput {
                 units: 10,
-                address: BitcoinAddress {
+                address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     network_id: BitcoinNetworkType::Mainnet,
                     bytes: Hash160([2; 20]),
-                },
+                }),
             }],
         };
 
@@ -658,11 +662,11 @@ mod tests {
             }],
             outputs: vec![BitcoinTxOutput {
                 units: 10,
-                address: BitcoinAddress {
+                address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     network_id: BitcoinNetworkType::Mainnet,
                     bytes: Hash160([1; 20]),
-                },
+                }),
             }],
         };
 
@@ -681,11 +685,11 @@ mod tests {
             }],
             outputs: vec![BitcoinTxOutput {
                 units: 10,
-                address: BitcoinAddress {
+                address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     network_id: BitcoinNetworkType::Mainnet,
                     bytes: Hash160([1; 20]),
-                },
+                }),
             }],
         };
 
@@ -734,17 +738,19 @@ mod tests {
             "Only one stack_stx op should have been accepted"
         );
 
-        let expected_pre_stack_addr = StacksAddress::from_bitcoin_address(&BitcoinAddress {
-            addrtype: BitcoinAddressType::PublicKeyHash,
-            network_id: BitcoinNetworkType::Mainnet,
-            bytes: Hash160([1; 20]),
-        });
+        let expected_pre_stack_addr =
+            StacksAddress::from_legacy_bitcoin_address(&LegacyBitcoinAddress {
+                addrtype: BitcoinAddressType::PublicKeyHash,
+                network_id: BitcoinNetworkType::Mainnet,
+                bytes: Hash160([1; 20]),
+            });
 
-        let expected_reward_addr = StacksAddress::from_bitcoin_address(&BitcoinAddress {
-            addrtype: BitcoinAddressType::PublicKeyHash,
-            network_id: BitcoinNetworkType::Mainnet,
-            bytes: Hash160([2; 20]),
-        });
+        let expected_reward_addr =
+            StacksAddress::from_legacy_bitcoin_address(&LegacyBitcoinAddress {
+                addrtype: BitcoinAddressType::PublicKeyHash,
+                network_id: BitcoinNetworkType::Mainnet,
+                bytes: Hash160([2; 20]),
+            });
 
         if let BlockstackOperationType::PreStx(op) = &processed_ops_0[0] {
             assert_eq!(&op.output, &expected_pre_stack_addr);
@@ -754,11 +760,183 @@ mod tests {
 
         if let BlockstackOperationType::StackStx(op) = &processed_ops_1[0] {
             assert_eq!(&op.sender, &expected_pre_stack_addr);
-            assert_eq!(&op.reward_addr, &expected_reward_addr);
+            assert_eq!(&op.reward_addr, &PoxAddress::Standard(expected_reward_addr));
             assert_eq!(op.stacked_ustx, u128::from_be_bytes([1; 16]));
             assert_eq!(op.num_cycles, 1);
         } else {
             panic!("EXPECTED to parse a stack stx op");
         }
     }
+
+    #[test]
+    fn test_classify_segwit_outputs() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let mut burnchain = Burnchain::regtest(":memory:");
+        burnchain.pox_constants = PoxConstants::test_default();
+        burnchain.pox_constants.sunset_start = 999;
+        burnchain.pox_constants.sunset_end = 1000;
+
+        // a pre-stx whose change goes to a p2wpkh output
+        let pre_stx = BurnchainTransaction::Bitcoin(BitcoinTransaction {
+            txid: Txid([5; 32]),
+            vtxindex: 0,
+            opcode: Opcodes::PreStx as u8,
+            data: vec![0; 80],
+            data_amt: 0,
+            inputs: vec![BitcoinTxInput {
+                keys: vec![],
+                num_required: 0,
+                in_type: BitcoinInputType::Standard,
+                tx_ref: (Txid([0; 32]), 1),
+            }],
+            outputs: vec![
+                BitcoinTxOutput {
+                    units: 10,
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
+                        addrtype: BitcoinAddressType::PublicKeyHash,
+                        network_id: BitcoinNetworkType::Mainnet,
+                        bytes: Hash160([1; 20]),
+                    }),
+                },
+                BitcoinTxOutput {
+                    units: 20,
+                    address: BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WPKH(
+                        BitcoinNetworkType::Mainnet,
+                        [2; 20],
+                    )),
+                },
+            ],
+        });
+        assert!(pre_stx.has_segwit_outputs());
+
+        let header = BurnchainBlockHeader {
+            block_height: 501,
+            block_hash: BurnchainHeaderHash([2; 32]),
+            parent_block_hash: first_bhh.clone(),
+            num_txs: 1,
+            timestamp: 321,
+        };
+
+        // segwit outputs are always accepted on regtest
+        match Burnchain::classify_transaction(
+            &burnchain,
+            &burnchain_db,
+            &header,
+            &pre_stx,
+            &HashMap::new(),
+        ) {
+            Some(BlockstackOperationType::PreStx(op)) => {
+                assert_eq!(op.output, StacksAddress::new(22, Hash160([1; 20])));
+            }
+            x => panic!("Expected a PreStx, got {:?}", &x),
+        }
+
+        // ...but ignored on mainnet until they activate
+        burnchain.network_name = BITCOIN_MAINNET_NAME.to_string();
+        assert!(header.block_height < burnchain.segwit_outputs_height());
+        assert!(Burnchain::classify_transaction(
+            &burnchain,
+            &burnchain_db,
+            &header,
+            &pre_stx,
+            &HashMap::new(),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_try_classify_transaction_errors() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let mut burnchain = Burnchain::regtest(":memory:");
+        burnchain.pox_constants = PoxConstants::test_default();
+        burnchain.pox_constants.sunset_start = 999;
+        burnchain.pox_constants.sunset_end = 1000;
+
+        let header = BurnchainBlockHeader {
+            block_height: 501,
+            block_hash: BurnchainHeaderHash([2; 32]),
+            parent_block_hash: first_bhh.clone(),
+            num_txs: 1,
+            timestamp: 321,
+        };
+
+        let make_tx = |opcode: u8, data: Vec<u8>, tx_ref: (Txid, u32)| {
+            BurnchainTransaction::Bitcoin(BitcoinTransaction {
+                txid: Txid([3; 32]),
+                vtxindex: 1,
+                opcode,
+                data,
+                data_amt: 0,
+                inputs: vec![BitcoinTxInput {
+                    keys: vec![],
+                    num_required: 0,
+                    in_type: BitcoinInputType::Standard,
+                    tx_ref,
+                }],
+                outputs: vec![BitcoinTxOutput {
+                    units: 10,
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
+                        addrtype: BitcoinAddressType::PublicKeyHash,
+                        network_id: BitcoinNetworkType::Mainnet,
+                        bytes: Hash160([1; 20]),
+                    }),
+                }],
+            })
+        };
+        let classify = |tx: &BurnchainTransaction, pre_stx_ops: &HashMap<Txid, PreStxOp>| {
+            Burnchain::try_classify_transaction(
+                &burnchain,
+                Some(&burnchain_db),
+                &header,
+                tx,
+                pre_stx_ops,
+            )
+        };
+
+        match classify(
+            &make_tx(b'?', vec![1; 80], (Txid([0; 32]), 1)),
+            &HashMap::new(),
+        ) {
+            Err(ClassifyError::UnknownOpcode(opcode)) => assert_eq!(opcode, b'?'),
+            x => panic!("Expected an unknown opcode, got {:?}", &x),
+        }
+
+        // stack-stx must spend output 1 of a pre-stx
+        let stack_stx = make_tx(Opcodes::StackStx as u8, vec![1; 80], (Txid([5; 32]), 2));
+        match classify(&stack_stx, &HashMap::new()) {
+            Err(ClassifyError::BadPreStxInput(op_error::InvalidInput)) => {}
+            x => panic!("Expected a bad PreStx input, got {:?}", &x),
+        }
+
+        // ...and the pre-stx must be known
+        let stack_stx = make_tx(Opcodes::StackStx as u8, vec![1; 80], (Txid([5; 32]), 1));
+        match classify(&stack_stx, &HashMap::new()) {
+            Err(ClassifyError::MissingPreStx(txid)) => assert_eq!(txid, Txid([5; 32])),
+            x => panic!("Expected a missing PreStx, got {:?}", &x),
+        }
+
+        // a known pre-stx gets the stack-stx as far as parsing its payload
+        let mut pre_stx_ops = HashMap::new();
+        pre_stx_ops.insert(
+            Txid([5; 32]),
+            PreStxOp {
+                output: StacksAddress::new(22, Hash160([1; 20])),
+                txid: Txid([5; 32]),
+                vtxindex: 0,
+                block_height: 501,
+                burn_header_hash: BurnchainHeaderHash([2; 32]),
+            },
+        );
+        assert!(classify(&stack_stx, &pre_stx_ops).is_ok());
+
+        let short_stack_stx = make_tx(Opcodes::StackStx as u8, vec![1; 4], (Txid([5; 32]), 1));
+        match classify(&short_stack_stx, &pre_stx_ops) {
+            Err(ClassifyError::InvalidOp(op_error::ParseError)) => {}
+            x => panic!("Expected an invalid op, got {:?}", &x),
+        }
+    }
 }
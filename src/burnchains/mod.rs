@@ -30,6 +30,7 @@ use crate::chainstate::burn::operations::BlockstackOperationType;
 use crate::chainstate::burn::operations::Error as op_error;
 use crate::chainstate::burn::operations::LeaderKeyRegisterOp;
 use crate::chainstate::burn::ConsensusHash;
+use crate::chainstate::stacks::address::PoxAddress;
 use crate::chainstate::stacks::StacksPublicKey;
 use crate::core::*;
 use crate::net::neighbors::MAX_NEIGHBOR_BLOCK_DELAY;
@@ -166,7 +167,7 @@ pub struct BurnchainSigner {
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct BurnchainRecipient {
-    pub address: StacksAddress,
+    pub address: PoxAddress,
     pub amount: u64,
 }
 
@@ -244,6 +245,15 @@ impl BurnchainTransaction {
         }
     }
 
+    /// Does this transaction pay to any native segwit outputs?
+    pub fn has_segwit_outputs(&self) -> bool {
+        match *self {
+            BurnchainTransaction::Bitcoin(ref btc) => {
+                btc.outputs.iter().any(|o| o.address.is_segwit())
+            }
+        }
+    }
+
     pub fn get_burn_amount(&self) -> u64 {
         match *self {
             BurnchainTransaction::Bitcoin(ref btc) => btc.data_amt,
@@ -514,6 +524,39 @@ impl From<btc_error> for Error {
     }
 }
 
+/// Reasons why a burnchain transaction does not classify as a Blockstack operation
+#[derive(Debug)]
+pub enum ClassifyError {
+    /// The transaction has native segwit outputs, but was mined before they were allowed
+    SegwitBeforeActivation(u64),
+    /// The transaction's opcode is not a known operation
+    UnknownOpcode(u8),
+    /// The operation must spend an output of a PreStxOp, but its first input does not
+    BadPreStxInput(op_error),
+    /// The PreStxOp that the operation spends is not known
+    MissingPreStx(Txid),
+    /// The operation failed to parse or validate
+    InvalidOp(op_error),
+}
+
+impl fmt::Display for ClassifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClassifyError::SegwitBeforeActivation(height) => write!(
+                f,
+                "Native segwit outputs are not allowed before height {}",
+                height
+            ),
+            ClassifyError::UnknownOpcode(opcode) => write!(f, "Unknown opcode {:#04x}", opcode),
+            ClassifyError::BadPreStxInput(ref e) => {
+                write!(f, "First input does not spend a PreStxOp output: {}", e)
+            }
+            ClassifyError::MissingPreStx(txid) => write!(f, "No PreStxOp found for {}", txid),
+            ClassifyError::InvalidOp(ref e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
 impl BurnchainView {
     #[cfg(test)]
     pub fn make_test_data(&mut self) {
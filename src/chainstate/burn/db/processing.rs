@@ -88,6 +88,15 @@ impl<'a> SortitionHandleTx<'a> {
                 );
                 BurnchainError::OpError(e)
             }),
+            BlockstackOperationType::DelegateStx(ref op) => {
+                op.check(burnchain, self).map_err(|e| {
+                    warn!(
+                        "REJECTED({}) delegate stx op {} at {},{}: {:?}",
+                        op.block_height, &op.txid, op.block_height, op.vtxindex, &e
+                    );
+                    BurnchainError::OpError(e)
+                })
+            }
             BlockstackOperationType::PreStx(_) => {
                 // no check() required for PreStx
                 Ok(())
@@ -365,7 +374,10 @@ impl<'a> SortitionHandleTx<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::burnchains::bitcoin::{address::BitcoinAddress, BitcoinNetworkType};
+    use crate::burnchains::bitcoin::{
+        address::{BitcoinAddress, LegacyBitcoinAddress},
+        BitcoinNetworkType,
+    };
     use crate::burnchains::*;
     use crate::chainstate::burn::db::sortdb::{tests::test_append_snapshot, SortitionDB};
     use crate::chainstate::burn::operations::{
@@ -393,8 +405,8 @@ mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -42,8 +42,8 @@ use crate::burnchains::{
 };
 use crate::chainstate::burn::operations::{
     leader_block_commit::{MissedBlockCommit, RewardSetInfo, OUTPUTS_PER_COMMIT},
-    BlockstackOperationType, LeaderBlockCommitOp, LeaderKeyRegisterOp, PreStxOp, StackStxOp,
-    TransferStxOp, UserBurnSupportOp,
+    BlockstackOperationType, DelegateStxOp, LeaderBlockCommitOp, LeaderKeyRegisterOp, PreStxOp,
+    StackStxOp, TransferStxOp, UserBurnSupportOp,
 };
 use crate::chainstate::burn::Opcodes;
 use crate::chainstate::burn::{BlockSnapshot, ConsensusHash, OpsHash, SortitionHash};
@@ -84,7 +84,7 @@ use stacks_common::util::secp256k1::MessageSignature;
 use stacks_common::util::vrf::*;
 
 use crate::chainstate::burn::ConsensusHashExtensions;
-use crate::chainstate::stacks::address::StacksAddressExtensions;
+use crate::chainstate::stacks::address::{PoxAddress, StacksAddressExtensions};
 use crate::chainstate::stacks::index::{ClarityMarfTrieId, MARFValue};
 use stacks_common::types::chainstate::StacksAddress;
 use stacks_common::types::chainstate::TrieHash;
@@ -340,7 +340,9 @@ impl FromRow<StackStxOp> for StackStxOp {
         let burn_header_hash = BurnchainHeaderHash::from_column(row, "burn_header_hash")?;
 
         let sender = StacksAddress::from_column(row, "sender_addr")?;
-        let reward_addr = StacksAddress::from_column(row, "reward_addr")?;
+        let reward_addr_str: String = row.get_unwrap("reward_addr");
+        let reward_addr =
+            PoxAddress::from_db_string(&reward_addr_str).ok_or_else(|| db_error::ParseError)?;
         let stacked_ustx_str: String = row.get_unwrap("stacked_ustx");
         let stacked_ustx = u128::from_str_radix(&stacked_ustx_str, 10)
             .expect("CORRUPTION: bad u128 written to sortdb");
@@ -359,6 +361,47 @@ impl FromRow<StackStxOp> for StackStxOp {
     }
 }
 
+impl FromRow<DelegateStxOp> for DelegateStxOp {
+    fn from_row<'a>(row: &'a Row) -> Result<DelegateStxOp, db_error> {
+        let txid = Txid::from_column(row, "txid")?;
+        let vtxindex: u32 = row.get_unwrap("vtxindex");
+        let block_height = u64::from_column(row, "block_height")?;
+        let burn_header_hash = BurnchainHeaderHash::from_column(row, "burn_header_hash")?;
+
+        let sender = StacksAddress::from_column(row, "sender_addr")?;
+        let delegate_to = StacksAddress::from_column(row, "delegate_to")?;
+        let reward_addr_index: Option<u32> = row.get_unwrap("reward_addr_index");
+        let reward_addr_str: Option<String> = row.get_unwrap("reward_addr");
+        let reward_addr = match (reward_addr_index, reward_addr_str) {
+            (Some(index), Some(addr_str)) => Some((
+                index,
+                PoxAddress::from_db_string(&addr_str).ok_or_else(|| db_error::ParseError)?,
+            )),
+            (None, None) => None,
+            _ => return Err(db_error::Corruption),
+        };
+        let delegated_ustx_str: String = row.get_unwrap("delegated_ustx");
+        let delegated_ustx = u128::from_str_radix(&delegated_ustx_str, 10)
+            .expect("CORRUPTION: bad u128 written to sortdb");
+        let until_burn_height = match row.get_unwrap::<_, Option<i64>>("until_burn_height") {
+            Some(height) => Some(u64::try_from(height).map_err(|_| db_error::ParseError)?),
+            None => None,
+        };
+
+        Ok(DelegateStxOp {
+            txid,
+            vtxindex,
+            block_height,
+            burn_header_hash,
+            sender,
+            delegate_to,
+            reward_addr,
+            delegated_ustx,
+            until_burn_height,
+        })
+    }
+}
+
 impl FromRow<TransferStxOp> for TransferStxOp {
     fn from_row<'a>(row: &'a Row) -> Result<TransferStxOp, db_error> {
         let txid = Txid::from_column(row, "txid")?;
@@ -452,7 +495,7 @@ impl FromRow<StacksEpoch> for StacksEpoch {
     }
 }
 
-pub const SORTITION_DB_VERSION: &'static str = "4";
+pub const SORTITION_DB_VERSION: &'static str = "5";
 
 const SORTITION_DB_INITIAL_SCHEMA: &'static [&'static str] = &[
     r#"
@@ -636,8 +679,25 @@ const SORTITION_DB_SCHEMA_4: &'static [&'static str] = &[r#"
         block_height INTEGER NOT NULL
     );"#];
 
+const SORTITION_DB_SCHEMA_5: &'static [&'static str] = &[r#"
+    CREATE TABLE delegate_stx (
+        txid TEXT NOT NULL,
+        vtxindex INTEGER NOT NULL,
+        block_height INTEGER NOT NULL,
+        burn_header_hash TEXT NOT NULL,
+
+        sender_addr TEXT NOT NULL,
+        delegate_to TEXT NOT NULL,
+        reward_addr_index INTEGER,
+        reward_addr TEXT,
+        delegated_ustx TEXT NOT NULL,
+        until_burn_height INTEGER,
+
+        PRIMARY KEY(txid)
+    );"#];
+
 // update this to add new indexes
-const LAST_SORTITION_DB_INDEX: &'static str = "index_parent_sortition_id";
+const LAST_SORTITION_DB_INDEX: &'static str = "index_delegate_stx_burn_header_hash";
 
 const SORTITION_DB_INDEXES: &'static [&'static str] = &[
     "CREATE INDEX IF NOT EXISTS snapshots_block_hashes ON snapshots(block_height,index_root,winning_stacks_block_hash);",
@@ -658,6 +718,7 @@ const SORTITION_DB_INDEXES: &'static [&'static str] = &[
     "CREATE INDEX IF NOT EXISTS index_missed_commits_intended_sortition_id ON missed_commits(intended_sortition_id);",
     "CREATE INDEX IF NOT EXISTS canonical_stacks_blocks ON canonical_accepted_stacks_blocks(tip_consensus_hash,stacks_block_hash);",
     "CREATE INDEX IF NOT EXISTS index_parent_sortition_id ON block_commit_parents(parent_sortition_id);",
+    "CREATE INDEX IF NOT EXISTS index_delegate_stx_burn_header_hash ON delegate_stx(burn_header_hash);",
 ];
 
 pub struct SortitionDB {
@@ -1222,7 +1283,7 @@ impl<'a> SortitionHandleTx<'a> {
                         .map(|ix| {
                             let recipient = reward_set[ix as usize].clone();
                             info!("PoX recipient chosen";
-                                   "recipient" => recipient.clone().to_b58(),
+                                   "recipient" => recipient.to_burnchain_repr(),
                                    "block_height" => block_height);
                             (recipient, u16::try_from(ix).unwrap())
                         })
@@ -1251,7 +1312,7 @@ impl<'a> SortitionHandleTx<'a> {
                         let ix = u16::try_from(ix).unwrap();
                         let recipient = self.get_reward_set_entry(ix)?;
                         info!("PoX recipient chosen";
-                               "recipient" => recipient.clone().to_b58(),
+                               "recipient" => recipient.to_burnchain_repr(),
                                "block_height" => block_height);
                         recipients.push((recipient, ix));
                     }
@@ -1268,7 +1329,7 @@ impl<'a> SortitionHandleTx<'a> {
         }
     }
 
-    fn get_reward_set_entry(&mut self, entry_ix: u16) -> Result<StacksAddress, db_error> {
+    fn get_reward_set_entry(&mut self, entry_ix: u16) -> Result<PoxAddress, db_error> {
         let chain_tip = self.context.chain_tip.clone();
         let entry_str = self
             .get_indexed(&chain_tip, &db_keys::pox_reward_set_entry(entry_ix))?
@@ -1276,7 +1337,7 @@ impl<'a> SortitionHandleTx<'a> {
                 "CORRUPTION: expected reward set entry at index={}, but not found",
                 entry_ix
             ));
-        Ok(StacksAddress::from_string(&entry_str).expect(&format!(
+        Ok(PoxAddress::from_db_string(&entry_str).expect(&format!(
             "CORRUPTION: bad address formatting in database: {}",
             &entry_str
         )))
@@ -2316,6 +2377,7 @@ impl SortitionDB {
         SortitionDB::apply_schema_2(&db_tx, epochs_ref)?;
         SortitionDB::apply_schema_3(&db_tx)?;
         SortitionDB::apply_schema_4(&db_tx)?;
+        SortitionDB::apply_schema_5(&db_tx)?;
 
         db_tx.instantiate_index()?;
 
@@ -2477,7 +2539,9 @@ impl SortitionDB {
         match epoch {
             StacksEpochId::Epoch10 => false,
             StacksEpochId::Epoch20 => version == "1" || version == "2" || version == "3",
-            StacksEpochId::Epoch2_05 => version == "2" || version == "3" || version == "4",
+            StacksEpochId::Epoch2_05 => {
+                version == "2" || version == "3" || version == "4" || version == "5"
+            }
         }
     }
 
@@ -2546,6 +2610,17 @@ impl SortitionDB {
         Ok(())
     }
 
+    fn apply_schema_5(tx: &DBTx) -> Result<(), db_error> {
+        for sql_exec in SORTITION_DB_SCHEMA_5 {
+            tx.execute_batch(sql_exec)?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO db_config (version) VALUES (?1)",
+            &["5"],
+        )?;
+        Ok(())
+    }
+
     fn check_schema_version_or_error(&mut self) -> Result<(), db_error> {
         match SortitionDB::get_schema_version(self.conn()) {
             Ok(Some(version)) => {
@@ -2584,6 +2659,10 @@ impl SortitionDB {
                         let tx = self.tx_begin()?;
                         SortitionDB::apply_schema_4(&tx.deref())?;
                         tx.commit()?;
+                    } else if version == "4" {
+                        let tx = self.tx_begin()?;
+                        SortitionDB::apply_schema_5(tx.deref())?;
+                        tx.commit()?;
                     } else if version == expected_version {
                         return Ok(());
                     } else {
@@ -3218,6 +3297,17 @@ impl SortitionDB {
         )
     }
 
+    pub fn get_delegate_stx_ops(
+        conn: &Connection,
+        burn_header_hash: &BurnchainHeaderHash,
+    ) -> Result<Vec<DelegateStxOp>, db_error> {
+        query_rows(
+            conn,
+            "SELECT * FROM delegate_stx WHERE burn_header_hash = ?",
+            &[burn_header_hash],
+        )
+    }
+
     pub fn get_transfer_stx_ops(
         conn: &Connection,
         burn_header_hash: &BurnchainHeaderHash,
@@ -3671,8 +3761,8 @@ impl SortitionDB {
         debug!("Block header cache has {} items", cache.len());
     }
 
-    /// Get a blockstack burnchain operation by txid
-    #[cfg(test)]
+    /// Get a blockstack burnchain operation by txid, in any fork.
+    /// PreStx operations are not stored in the sortition DB, so they are never returned.
     pub fn get_burnchain_transaction(
         conn: &Connection,
         txid: &Txid,
@@ -3710,9 +3800,40 @@ impl SortitionDB {
             return Ok(Some(BlockstackOperationType::UserBurnSupport(user_burn)));
         }
 
+        // stack stx?
+        let stack_stx_sql = "SELECT * FROM stack_stx WHERE txid = ?1 LIMIT 1";
+        let stack_stx_res = query_row(conn, stack_stx_sql, &args)?;
+        if let Some(stack_stx) = stack_stx_res {
+            return Ok(Some(BlockstackOperationType::StackStx(stack_stx)));
+        }
+
+        // transfer stx?
+        let transfer_stx_sql = "SELECT * FROM transfer_stx WHERE txid = ?1 LIMIT 1";
+        let transfer_stx_res = query_row(conn, transfer_stx_sql, &args)?;
+        if let Some(transfer_stx) = transfer_stx_res {
+            return Ok(Some(BlockstackOperationType::TransferStx(transfer_stx)));
+        }
+
+        // delegate stx?
+        let delegate_stx_sql = "SELECT * FROM delegate_stx WHERE txid = ?1 LIMIT 1";
+        let delegate_stx_res = query_row(conn, delegate_stx_sql, &args)?;
+        if let Some(delegate_stx) = delegate_stx_res {
+            return Ok(Some(BlockstackOperationType::DelegateStx(delegate_stx)));
+        }
+
         Ok(None)
     }
 
+    /// Get the sortitions, in any fork, for which a block commit with this txid was missed
+    /// because it landed in a different burn block than the one it was sent for.
+    pub fn get_missed_commits_by_txid(
+        conn: &Connection,
+        txid: &Txid,
+    ) -> Result<Vec<MissedBlockCommit>, db_error> {
+        let qry = "SELECT * FROM missed_commits WHERE txid = ?1";
+        query_rows(conn, qry, &[txid])
+    }
+
     /// Get the StacksEpoch for a given burn block height
     pub fn get_stacks_epoch(
         conn: &DBConn,
@@ -3911,6 +4032,13 @@ impl<'a> SortitionHandleTx<'a> {
                 );
                 self.insert_transfer_stx(op)
             }
+            BlockstackOperationType::DelegateStx(ref op) => {
+                info!(
+                    "ACCEPTED({}) delegate stx op {} at {},{}",
+                    op.block_height, &op.txid, op.block_height, op.vtxindex
+                );
+                self.insert_delegate_stx(op)
+            }
             BlockstackOperationType::PreStx(ref op) => {
                 info!(
                     "ACCEPTED({}) pre stack stx op {} at {},{}",
@@ -3958,7 +4086,7 @@ impl<'a> SortitionHandleTx<'a> {
             &u64_to_sql(op.block_height)?,
             &op.burn_header_hash,
             &op.sender.to_string(),
-            &op.reward_addr.to_string(),
+            &op.reward_addr.to_db_string(),
             &op.stacked_ustx.to_string(),
             &op.num_cycles,
         ];
@@ -3968,6 +4096,30 @@ impl<'a> SortitionHandleTx<'a> {
         Ok(())
     }
 
+    /// Insert a delegate-stx op
+    fn insert_delegate_stx(&mut self, op: &DelegateStxOp) -> Result<(), db_error> {
+        let until_burn_height = match op.until_burn_height {
+            Some(height) => Some(u64_to_sql(height)?),
+            None => None,
+        };
+        let args: &[&dyn ToSql] = &[
+            &op.txid,
+            &op.vtxindex,
+            &u64_to_sql(op.block_height)?,
+            &op.burn_header_hash,
+            &op.sender.to_string(),
+            &op.delegate_to.to_string(),
+            &op.reward_addr.as_ref().map(|(index, _)| *index),
+            &op.reward_addr.as_ref().map(|(_, addr)| addr.to_db_string()),
+            &op.delegated_ustx.to_string(),
+            &until_burn_height,
+        ];
+
+        self.execute("REPLACE INTO delegate_stx (txid, vtxindex, block_height, burn_header_hash, sender_addr, delegate_to, reward_addr_index, reward_addr, delegated_ustx, until_burn_height) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", args)?;
+
+        Ok(())
+    }
+
     /// Insert a transfer-stx op
     fn insert_transfer_stx(&mut self, op: &TransferStxOp) -> Result<(), db_error> {
         let args: &[&dyn ToSql] = &[
@@ -4298,7 +4450,7 @@ impl<'a> SortitionHandleTx<'a> {
                     values.push(db_keys::reward_set_size_to_string(reward_set.len()));
                     for (ix, address) in reward_set.iter().enumerate() {
                         keys.push(db_keys::pox_reward_set_entry(ix as u16));
-                        values.push(address.to_string());
+                        values.push(address.to_db_string());
                     }
                 } else {
                     keys.push(db_keys::pox_reward_set_size().to_string());
@@ -4349,7 +4501,7 @@ impl<'a> SortitionHandleTx<'a> {
                     values.push(db_keys::reward_set_size_to_string(current_len as usize));
                     for (recipient_index, replace_with) in remapped_entries.into_iter() {
                         keys.push(db_keys::pox_reward_set_entry(recipient_index));
-                        values.push(replace_with.to_string())
+                        values.push(replace_with.to_db_string())
                     }
                 }
             }
@@ -4498,7 +4650,7 @@ pub mod tests {
     use std::sync::mpsc::sync_channel;
     use std::thread;
 
-    use crate::burnchains::bitcoin::address::BitcoinAddress;
+    use crate::burnchains::bitcoin::address::{BitcoinAddress, LegacyBitcoinAddress};
     use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
     use crate::burnchains::bitcoin::BitcoinNetworkType;
     use crate::burnchains::*;
@@ -4657,8 +4809,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -4743,8 +4895,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -4969,8 +5121,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -5045,6 +5197,76 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_insert_delegate_stx() {
+        let block_height = 123;
+        let first_burn_hash = BurnchainHeaderHash::from_hex(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+
+        let delegate_with_options = DelegateStxOp {
+            sender: StacksAddress::new(26, Hash160([1u8; 20])),
+            delegate_to: StacksAddress::new(26, Hash160([2u8; 20])),
+            reward_addr: Some((
+                2,
+                PoxAddress::Standard(StacksAddress::new(22, Hash160([3u8; 20]))),
+            )),
+            delegated_ustx: 123_000_000_000,
+            until_burn_height: Some(200),
+
+            txid: Txid([0x11; 32]),
+            vtxindex: 1,
+            block_height: block_height + 1,
+            burn_header_hash: BurnchainHeaderHash([0x01; 32]),
+        };
+
+        let delegate_without_options = DelegateStxOp {
+            reward_addr: None,
+            until_burn_height: None,
+            txid: Txid([0x12; 32]),
+            vtxindex: 2,
+            ..delegate_with_options.clone()
+        };
+
+        let mut db = SortitionDB::connect_test(block_height, &first_burn_hash).unwrap();
+
+        test_append_snapshot(
+            &mut db,
+            BurnchainHeaderHash([0x01; 32]),
+            &vec![
+                BlockstackOperationType::DelegateStx(delegate_with_options.clone()),
+                BlockstackOperationType::DelegateStx(delegate_without_options.clone()),
+            ],
+        );
+
+        let mut ops =
+            SortitionDB::get_delegate_stx_ops(db.conn(), &BurnchainHeaderHash([0x01; 32])).unwrap();
+        ops.sort_by_key(|op| op.vtxindex);
+        assert_eq!(
+            ops,
+            vec![
+                delegate_with_options.clone(),
+                delegate_without_options.clone()
+            ]
+        );
+
+        match SortitionDB::get_burnchain_transaction(db.conn(), &delegate_with_options.txid)
+            .unwrap()
+        {
+            Some(BlockstackOperationType::DelegateStx(op)) => {
+                assert_eq!(op, delegate_with_options)
+            }
+            x => panic!("expected a DelegateStx op, got {:?}", x),
+        }
+
+        assert!(
+            SortitionDB::get_delegate_stx_ops(db.conn(), &BurnchainHeaderHash([0x02; 32]))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
     #[test]
     fn has_VRF_public_key() {
         let public_key = VRFPublicKey::from_bytes(
@@ -5065,8 +5287,8 @@ pub mod tests {
             .unwrap(),
             public_key: public_key.clone(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -5565,8 +5787,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -7618,8 +7840,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -23,13 +23,16 @@ use crate::burnchains::Burnchain;
 use crate::burnchains::PublicKey;
 use crate::burnchains::Txid;
 use crate::burnchains::{BurnchainRecipient, BurnchainSigner, BurnchainTransaction};
+use crate::chainstate::burn::db::sortdb::SortitionDB;
 use crate::chainstate::burn::operations::{
     leader_block_commit::MissedBlockCommit, BlockstackOperationType, LeaderBlockCommitOp,
     LeaderKeyRegisterOp, UserBurnSupportOp,
 };
+use crate::chainstate::burn::BlockSnapshot;
 use crate::chainstate::stacks::StacksPublicKey;
 use crate::core::MINING_COMMITMENT_WINDOW;
 use crate::monitoring;
+use crate::util_lib::db::Error as db_error;
 use stacks_common::address::AddressHashMode;
 use stacks_common::util::hash::Hash160;
 use stacks_common::util::log;
@@ -397,9 +400,89 @@ impl BurnSamplePoint {
     }
 }
 
+/// The block-commits and missed block-commits in the mining commitment window that ends at a
+/// given sortition, in ascending block height order.  This is the same window the burnchain
+/// state transition assembles when it computes a sortition's burn distribution, but loaded from
+/// the sortition DB after the fact, so miners and tooling can inspect it.
+#[derive(Debug, Clone)]
+pub struct CommitWindow {
+    /// Block-commits at each height in the window; the last entry is the tip's
+    pub block_commits: Vec<Vec<LeaderBlockCommitOp>>,
+    /// Missed block-commits intended for each height in the window, except the tip
+    pub missed_commits: Vec<Vec<MissedBlockCommit>>,
+    /// Whether or not each height in the window was a PoB-only sortition
+    pub burn_blocks: Vec<bool>,
+}
+
+impl CommitWindow {
+    /// Load the commitment window ending at `tip` from the sortition DB.
+    pub fn load(
+        sortdb: &SortitionDB,
+        burnchain: &Burnchain,
+        tip: &BlockSnapshot,
+    ) -> Result<CommitWindow, db_error> {
+        let ic = sortdb.index_handle(&tip.sortition_id);
+        let mut block_commits = vec![SortitionDB::get_block_commits_by_block(
+            ic.conn(),
+            &tip.sortition_id,
+        )?];
+        let mut missed_commits = vec![];
+
+        if !burnchain.is_in_prepare_phase(tip.block_height)
+            && tip.block_height <= burnchain.pox_constants.sunset_end
+        {
+            for blocks_back in 1..(MINING_COMMITMENT_WINDOW as u64) {
+                if tip.block_height < blocks_back {
+                    break;
+                }
+                let sortition_id =
+                    match ic.get_block_snapshot_by_height(tip.block_height - blocks_back)? {
+                        Some(sn) => sn.sortition_id,
+                        None => break,
+                    };
+                block_commits.push(SortitionDB::get_block_commits_by_block(
+                    ic.conn(),
+                    &sortition_id,
+                )?);
+                missed_commits.push(SortitionDB::get_missed_commits_by_intended(
+                    ic.conn(),
+                    &sortition_id,
+                )?);
+            }
+        }
+
+        block_commits.reverse();
+        missed_commits.reverse();
+
+        let window_start_height = tip.block_height + 1 - (block_commits.len() as u64);
+        let burn_blocks = (0..block_commits.len())
+            .map(|i| {
+                let height = window_start_height + (i as u64);
+                burnchain.pox_constants.sunset_end <= height
+                    || burnchain.is_in_prepare_phase(height)
+            })
+            .collect();
+
+        Ok(CommitWindow {
+            block_commits,
+            missed_commits,
+            burn_blocks,
+        })
+    }
+
+    /// Compute the burn distribution for this window, exactly as the sortition did.
+    pub fn make_distribution(&self) -> Vec<BurnSamplePoint> {
+        BurnSamplePoint::make_min_median_distribution(
+            self.block_commits.clone(),
+            self.missed_commits.clone(),
+            self.burn_blocks.clone(),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::burnchains::bitcoin::address::BitcoinAddress;
+    use crate::burnchains::bitcoin::address::{BitcoinAddress, LegacyBitcoinAddress};
     use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
     use crate::burnchains::bitcoin::BitcoinNetworkType;
     use crate::burnchains::Address;
@@ -845,8 +928,8 @@ mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -877,8 +960,8 @@ mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a91432b6c66189da32bd0a9f00ee4927f569957d71aa88ac").unwrap(),
                 )
@@ -909,8 +992,8 @@ mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a91432b6c66189da32bd0a9f00ee4927f569957d71aa88ac").unwrap(),
                 )
@@ -49,6 +49,7 @@ pub use stacks_common::types::chainstate::ConsensusHash;
 pub mod db;
 pub mod distribution;
 pub mod operations;
+pub mod simulation;
 pub mod sortition;
 
 pub const CONSENSUS_HASH_LIFETIME: u32 = 24;
@@ -74,6 +75,7 @@ pub enum Opcodes {
     StackStx = 'x' as u8,
     PreStx = 'p' as u8,
     TransferStx = '$' as u8,
+    DelegateStx = '#' as u8,
 }
 
 // a burnchain block snapshot
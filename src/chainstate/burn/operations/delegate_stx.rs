@@ -0,0 +1,540 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{Read, Write};
+
+use crate::burnchains::Burnchain;
+use crate::burnchains::BurnchainBlockHeader;
+use crate::burnchains::BurnchainTransaction;
+use crate::burnchains::Txid;
+use crate::chainstate::burn::db::sortdb::{SortitionDB, SortitionHandleTx};
+use crate::chainstate::burn::operations::Error as op_error;
+use crate::chainstate::burn::operations::{
+    parse_u128_from_be, parse_u32_from_be, parse_u64_from_be, DelegateStxOp,
+};
+use crate::chainstate::burn::Opcodes;
+use crate::chainstate::stacks::address::PoxAddress;
+use crate::codec::{write_next, Error as codec_error, StacksMessageCodec};
+use crate::core::StacksEpochId;
+
+use crate::types::chainstate::{BurnchainHeaderHash, StacksAddress};
+
+// return type from parse_data below
+struct ParsedData {
+    delegated_ustx: u128,
+    reward_addr_index: Option<u32>,
+    until_burn_height: Option<u64>,
+}
+
+impl DelegateStxOp {
+    #[cfg(test)]
+    pub fn new(
+        sender: &StacksAddress,
+        delegate_to: &StacksAddress,
+        reward_addr: Option<(u32, PoxAddress)>,
+        delegated_ustx: u128,
+        until_burn_height: Option<u64>,
+    ) -> DelegateStxOp {
+        DelegateStxOp {
+            sender: sender.clone(),
+            delegate_to: delegate_to.clone(),
+            reward_addr,
+            delegated_ustx,
+            until_burn_height,
+            // to be filled in
+            txid: Txid([0u8; 32]),
+            vtxindex: 0,
+            block_height: 0,
+            burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+        }
+    }
+
+    /// Decode an optional field: a 1-byte flag (0x00 for none, 0x01 for some), followed by the
+    /// value bytes, which must be present either way.
+    fn parse_optional<T>(
+        flag: u8,
+        bytes: &[u8],
+        parse: fn(&[u8]) -> Option<T>,
+    ) -> Option<Option<T>> {
+        match flag {
+            0 => Some(None),
+            1 => parse(bytes).map(Some),
+            _ => None,
+        }
+    }
+
+    fn parse_data(data: &Vec<u8>) -> Option<ParsedData> {
+        /*
+            Wire format:
+            0      2  3                     19        20                 24        25                      33
+            |------|--|---------------------|---------|------------------|---------|-----------------------|
+             magic  op  uSTX to delegate     reward     reward addr        until     until burn height (u64)
+                        (u128)               addr flag  output index (u32) flag
+
+             Note that `data` is missing the first 3 bytes -- the magic and op have been stripped
+
+             All integers are in big-endian order.  A flag byte of 0x00 means the field that
+             follows it is unset (its bytes are ignored), and 0x01 means it is set.
+
+             The reward address output index refers to the transaction's outputs, where output 0
+             is the OP_RETURN output.
+        */
+
+        if data.len() < 30 {
+            // too short
+            warn!(
+                "DelegateStxOp payload is malformed ({} bytes, expected {})",
+                data.len(),
+                30
+            );
+            return None;
+        }
+
+        let delegated_ustx = parse_u128_from_be(&data[0..16]).unwrap();
+        let reward_addr_index =
+            DelegateStxOp::parse_optional(data[16], &data[17..21], parse_u32_from_be)?;
+        let until_burn_height =
+            DelegateStxOp::parse_optional(data[21], &data[22..30], parse_u64_from_be)?;
+
+        Some(ParsedData {
+            delegated_ustx,
+            reward_addr_index,
+            until_burn_height,
+        })
+    }
+
+    pub fn get_sender_txid(tx: &BurnchainTransaction) -> Result<&Txid, op_error> {
+        match tx.get_input_tx_ref(0) {
+            Some((ref txid, vout)) => {
+                if *vout != 1 {
+                    warn!("Invalid tx: DelegateStxOp must spend the second output of the PreStxOp");
+                    Err(op_error::InvalidInput)
+                } else {
+                    Ok(txid)
+                }
+            }
+            None => {
+                warn!("Invalid tx: DelegateStxOp must have at least one input");
+                Err(op_error::InvalidInput)
+            }
+        }
+    }
+
+    pub fn from_tx(
+        block_header: &BurnchainBlockHeader,
+        tx: &BurnchainTransaction,
+        sender: &StacksAddress,
+        pox_sunset_ht: u64,
+    ) -> Result<DelegateStxOp, op_error> {
+        DelegateStxOp::parse_from_tx(
+            block_header.block_height,
+            &block_header.block_hash,
+            tx,
+            sender,
+            pox_sunset_ht,
+        )
+    }
+
+    /// parse a DelegateStxOp
+    /// `pox_sunset_ht` is the height at which PoX *disables*
+    pub fn parse_from_tx(
+        block_height: u64,
+        block_hash: &BurnchainHeaderHash,
+        tx: &BurnchainTransaction,
+        sender: &StacksAddress,
+        pox_sunset_ht: u64,
+    ) -> Result<DelegateStxOp, op_error> {
+        // can't be too careful...
+        let outputs = tx.get_recipients();
+
+        if tx.num_signers() == 0 {
+            warn!(
+                "Invalid tx: inputs: {}, outputs: {}",
+                tx.num_signers(),
+                outputs.len()
+            );
+            return Err(op_error::InvalidInput);
+        }
+
+        if outputs.len() == 0 {
+            warn!(
+                "Invalid tx: inputs: {}, outputs: {}",
+                tx.num_signers(),
+                outputs.len()
+            );
+            return Err(op_error::InvalidInput);
+        }
+
+        if tx.opcode() != Opcodes::DelegateStx as u8 {
+            warn!("Invalid tx: invalid opcode {}", tx.opcode());
+            return Err(op_error::InvalidInput);
+        };
+
+        let data = DelegateStxOp::parse_data(&tx.data()).ok_or_else(|| {
+            warn!("Invalid tx data");
+            op_error::ParseError
+        })?;
+
+        // check if we've reached PoX disable
+        if block_height >= pox_sunset_ht {
+            debug!(
+                "DelegateStxOp broadcasted after sunset. Ignoring. txid={}",
+                tx.txid()
+            );
+            return Err(op_error::InvalidInput);
+        }
+
+        let delegate_to = match outputs[0].address.as_standard() {
+            Some(addr) => *addr,
+            None => {
+                warn!("Invalid tx: DelegateStx delegate output is not a standard address");
+                return Err(op_error::InvalidInput);
+            }
+        };
+
+        // the recipients list omits the OP_RETURN output, so output `i` is recipient `i - 1`
+        let reward_addr = match data.reward_addr_index {
+            Some(index) => {
+                let recipient = (index as usize)
+                    .checked_sub(1)
+                    .and_then(|i| outputs.get(i))
+                    .ok_or_else(|| {
+                        warn!(
+                            "Invalid tx: DelegateStx reward address output index {} is out of range",
+                            index
+                        );
+                        op_error::InvalidInput
+                    })?;
+
+                // the PoX contract only takes p2pkh and p2sh reward addresses
+                if recipient.address.as_standard().is_none() {
+                    warn!(
+                        "Invalid tx: DelegateStx reward address {} is not a p2pkh or p2sh address",
+                        &recipient.address
+                    );
+                    return Err(op_error::InvalidInput);
+                }
+                Some((index, recipient.address))
+            }
+            None => None,
+        };
+
+        Ok(DelegateStxOp {
+            sender: *sender,
+            delegate_to,
+            reward_addr,
+            delegated_ustx: data.delegated_ustx,
+            until_burn_height: data.until_burn_height,
+            txid: tx.txid(),
+            vtxindex: tx.vtxindex(),
+            block_height,
+            burn_header_hash: *block_hash,
+        })
+    }
+}
+
+impl StacksMessageCodec for DelegateStxOp {
+    /*
+            Wire format:
+            0      2  3                     19        20                 24        25                      33
+            |------|--|---------------------|---------|------------------|---------|-----------------------|
+             magic  op  uSTX to delegate     reward     reward addr        until     until burn height (u64)
+                        (u128)               addr flag  output index (u32) flag
+    */
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &(Opcodes::DelegateStx as u8))?;
+        fd.write_all(&self.delegated_ustx.to_be_bytes())
+            .map_err(codec_error::WriteError)?;
+        match self.reward_addr {
+            Some((index, _)) => {
+                write_next(fd, &1u8)?;
+                write_next(fd, &index)?;
+            }
+            None => {
+                write_next(fd, &0u8)?;
+                write_next(fd, &0u32)?;
+            }
+        }
+        match self.until_burn_height {
+            Some(height) => {
+                write_next(fd, &1u8)?;
+                write_next(fd, &height)?;
+            }
+            None => {
+                write_next(fd, &0u8)?;
+                write_next(fd, &0u64)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(_fd: &mut R) -> Result<DelegateStxOp, codec_error> {
+        // Op deserialized through burchain indexer
+        unimplemented!();
+    }
+}
+
+impl DelegateStxOp {
+    pub fn check(&self, burnchain: &Burnchain, tx: &mut SortitionHandleTx) -> Result<(), op_error> {
+        if self.delegated_ustx == 0 {
+            warn!("Invalid DelegateStxOp, must have positive ustx");
+            return Err(op_error::DelegateStxMustBePositive);
+        }
+
+        if self.block_height < burnchain.delegate_stx_height() {
+            warn!(
+                "Invalid DelegateStxOp, not active until burnchain height {}",
+                burnchain.delegate_stx_height()
+            );
+            return Err(op_error::DelegateStxNotActive);
+        }
+
+        let epoch = SortitionDB::get_stacks_epoch(tx, self.block_height)?.expect(&format!(
+            "FATAL: impossible block height: no epoch defined for {}",
+            self.block_height
+        ));
+        if epoch.epoch_id < StacksEpochId::Epoch2_05 {
+            warn!(
+                "Invalid DelegateStxOp, not supported in epoch {}",
+                epoch.epoch_id
+            );
+            return Err(op_error::DelegateStxBadEpoch);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::burnchains::bitcoin::address::*;
+    use crate::burnchains::bitcoin::indexer::BITCOIN_REGTEST_NAME;
+    use crate::burnchains::bitcoin::*;
+    use crate::burnchains::*;
+    use crate::chainstate::burn::db::sortdb::*;
+    use crate::chainstate::burn::operations::*;
+    use crate::chainstate::burn::*;
+    use crate::chainstate::stacks::address::PoxAddress;
+    use crate::core::{StacksEpoch, StacksEpochExtension};
+    use stacks_common::util::get_epoch_time_secs;
+    use stacks_common::util::hash::*;
+
+    use crate::types::chainstate::StacksAddress;
+
+    use super::*;
+
+    fn legacy_output(units: u64, byte: u8) -> BitcoinTxOutput {
+        BitcoinTxOutput {
+            units,
+            address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
+                addrtype: BitcoinAddressType::PublicKeyHash,
+                network_id: BitcoinNetworkType::Mainnet,
+                bytes: Hash160([byte; 20]),
+            }),
+        }
+    }
+
+    fn make_delegate_tx(data: Vec<u8>) -> BitcoinTransaction {
+        BitcoinTransaction {
+            txid: Txid([0; 32]),
+            vtxindex: 0,
+            opcode: Opcodes::DelegateStx as u8,
+            data,
+            data_amt: 0,
+            inputs: vec![BitcoinTxInput {
+                keys: vec![],
+                num_required: 0,
+                in_type: BitcoinInputType::Standard,
+                tx_ref: (Txid([0; 32]), 1),
+            }],
+            outputs: vec![
+                legacy_output(10, 1),
+                legacy_output(10, 2),
+                legacy_output(30, 0),
+            ],
+        }
+    }
+
+    fn make_data(
+        ustx: u128,
+        reward_addr_index: Option<u32>,
+        until_burn_height: Option<u64>,
+    ) -> Vec<u8> {
+        let mut data = ustx.to_be_bytes().to_vec();
+        data.push(reward_addr_index.is_some() as u8);
+        data.extend_from_slice(&reward_addr_index.unwrap_or(0).to_be_bytes());
+        data.push(until_burn_height.is_some() as u8);
+        data.extend_from_slice(&until_burn_height.unwrap_or(0).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse_delegate_stx() {
+        let sender = StacksAddress {
+            version: 0,
+            bytes: Hash160([0; 20]),
+        };
+
+        let tx = make_delegate_tx(make_data(1000, Some(2), Some(200)));
+        let op = DelegateStxOp::parse_from_tx(
+            100,
+            &BurnchainHeaderHash([0; 32]),
+            &BurnchainTransaction::Bitcoin(tx.clone()),
+            &sender,
+            1000,
+        )
+        .unwrap();
+
+        assert_eq!(&op.sender, &sender);
+        assert_eq!(
+            PoxAddress::Standard(op.delegate_to),
+            PoxAddress::from_bitcoin_address(&tx.outputs[0].address)
+        );
+        assert_eq!(
+            op.reward_addr,
+            Some((2, PoxAddress::from_bitcoin_address(&tx.outputs[1].address)))
+        );
+        assert_eq!(op.delegated_ustx, 1000);
+        assert_eq!(op.until_burn_height, Some(200));
+
+        // the serialized op is the opcode followed by the payload
+        let mut bytes = vec![];
+        op.consensus_serialize(&mut bytes).unwrap();
+        assert_eq!(bytes[0], Opcodes::DelegateStx as u8);
+        assert_eq!(&bytes[1..], &tx.data[..]);
+
+        // optional fields can be unset
+        let tx = make_delegate_tx(make_data(1000, None, None));
+        let op = DelegateStxOp::parse_from_tx(
+            100,
+            &BurnchainHeaderHash([0; 32]),
+            &BurnchainTransaction::Bitcoin(tx),
+            &sender,
+            1000,
+        )
+        .unwrap();
+        assert_eq!(op.reward_addr, None);
+        assert_eq!(op.until_burn_height, None);
+
+        let bad_txs = vec![
+            // reward address index 0 is the OP_RETURN output
+            make_delegate_tx(make_data(1000, Some(0), None)),
+            // reward address index past the last output
+            make_delegate_tx(make_data(1000, Some(4), None)),
+            // bad flag byte
+            make_delegate_tx({
+                let mut data = make_data(1000, None, None);
+                data[21] = 2;
+                data
+            }),
+            // too short
+            make_delegate_tx(make_data(1000, None, None)[0..29].to_vec()),
+            // native segwit reward addresses can't be stacked
+            {
+                let mut tx = make_delegate_tx(make_data(1000, Some(2), None));
+                tx.outputs[1].address = BitcoinAddress::Segwit(SegwitBitcoinAddress::P2TR(
+                    BitcoinNetworkType::Mainnet,
+                    [1; 32],
+                ));
+                tx
+            },
+        ];
+        for tx in bad_txs.into_iter() {
+            assert!(DelegateStxOp::parse_from_tx(
+                100,
+                &BurnchainHeaderHash([0; 32]),
+                &BurnchainTransaction::Bitcoin(tx),
+                &sender,
+                1000,
+            )
+            .is_err());
+        }
+
+        // ignored after the PoX sunset
+        let tx = make_delegate_tx(make_data(1000, None, None));
+        assert!(DelegateStxOp::parse_from_tx(
+            1000,
+            &BurnchainHeaderHash([0; 32]),
+            &BurnchainTransaction::Bitcoin(tx),
+            &sender,
+            1000,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_check_delegate_stx() {
+        let first_block_height = 120;
+        let first_burn_hash = BurnchainHeaderHash::from_hex(
+            "0000000000000000000000000000000000000000000000000000000000000123",
+        )
+        .unwrap();
+
+        let db_path_dir = format!(
+            "/tmp/stacks-node-tests/unit-tests-sortdb/db-{}",
+            to_hex(&rand::random::<[u8; 32]>())
+        );
+        let mut db = SortitionDB::connect(
+            &db_path_dir,
+            first_block_height,
+            &first_burn_hash,
+            get_epoch_time_secs(),
+            &StacksEpoch::unit_test_2_05(first_block_height),
+            true,
+        )
+        .unwrap();
+
+        let mut burnchain = Burnchain::default_unittest(first_block_height, &first_burn_hash);
+        burnchain.network_name = BITCOIN_REGTEST_NAME.to_string();
+
+        let sender = StacksAddress {
+            version: 26,
+            bytes: Hash160([1; 20]),
+        };
+        let delegate_to = StacksAddress {
+            version: 26,
+            bytes: Hash160([2; 20]),
+        };
+
+        let mut op = DelegateStxOp::new(&sender, &delegate_to, None, 1000, None);
+
+        // Stacks 2.05 starts 4 blocks after the first block
+        let mut tx = db.tx_begin_at_tip();
+        op.block_height = first_block_height + 4;
+        op.check(&burnchain, &mut tx).unwrap();
+
+        op.block_height = first_block_height + 3;
+        assert!(matches!(
+            op.check(&burnchain, &mut tx),
+            Err(op_error::DelegateStxBadEpoch)
+        ));
+
+        op.block_height = first_block_height + 4;
+        op.delegated_ustx = 0;
+        assert!(matches!(
+            op.check(&burnchain, &mut tx),
+            Err(op_error::DelegateStxMustBePositive)
+        ));
+
+        // not yet active on mainnet
+        let burnchain = Burnchain::default_unittest(first_block_height, &first_burn_hash);
+        op.delegated_ustx = 1000;
+        assert!(matches!(
+            op.check(&burnchain, &mut tx),
+            Err(op_error::DelegateStxNotActive)
+        ));
+    }
+}
@@ -32,6 +32,7 @@ use crate::chainstate::burn::operations::{
 use crate::chainstate::burn::ConsensusHash;
 use crate::chainstate::burn::Opcodes;
 use crate::chainstate::burn::SortitionId;
+use crate::chainstate::stacks::address::PoxAddress;
 use crate::chainstate::stacks::index::storage::TrieFileStorage;
 use crate::chainstate::stacks::{StacksPrivateKey, StacksPublicKey};
 use crate::codec::{write_next, Error as codec_error, StacksMessageCodec};
@@ -441,7 +442,7 @@ impl StacksMessageCodec for LeaderBlockCommitOp {
 #[derive(Debug)]
 pub struct RewardSetInfo {
     pub anchor_block: BlockHeaderHash,
-    pub recipients: Vec<(StacksAddress, u16)>,
+    pub recipients: Vec<(PoxAddress, u16)>,
 }
 
 #[derive(Debug, Clone)]
@@ -465,7 +466,7 @@ impl RewardSetInfo {
     /// Takes an Option<RewardSetInfo> and produces the commit_outs
     ///   for a corresponding LeaderBlockCommitOp. If RewardSetInfo is none,
     ///   the LeaderBlockCommitOp will use burn addresses.
-    pub fn into_commit_outs(from: Option<RewardSetInfo>, mainnet: bool) -> Vec<StacksAddress> {
+    pub fn into_commit_outs(from: Option<RewardSetInfo>, mainnet: bool) -> Vec<PoxAddress> {
         if let Some(recipient_set) = from {
             let mut outs: Vec<_> = recipient_set
                 .recipients
@@ -473,12 +474,12 @@ impl RewardSetInfo {
                 .map(|(recipient, _)| recipient)
                 .collect();
             while outs.len() < OUTPUTS_PER_COMMIT {
-                outs.push(StacksAddress::burn_address(mainnet));
+                outs.push(PoxAddress::standard_burn_address(mainnet));
             }
             outs
         } else {
             (0..OUTPUTS_PER_COMMIT)
-                .map(|_| StacksAddress::burn_address(mainnet))
+                .map(|_| PoxAddress::standard_burn_address(mainnet))
                 .collect()
         }
     }
@@ -565,7 +566,7 @@ impl LeaderBlockCommitOp {
                             // If the number of recipients in the set was odd, we need to pad
                             // with a burn address
                             check_recipients
-                                .push(StacksAddress::burn_address(burnchain.is_mainnet()))
+                                .push(PoxAddress::standard_burn_address(burnchain.is_mainnet()))
                         }
 
                         if self.commit_outs.len() != check_recipients.len() {
@@ -927,27 +928,27 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 30,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([0; 20]),
-                    },
+                    }),
                 },
             ],
         });
@@ -985,27 +986,27 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([0; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 30,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([0; 20]),
-                    },
+                    }),
                 },
             ],
         });
@@ -1044,27 +1045,27 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 30,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([0; 20]),
-                    },
+                    }),
                 },
             ],
         });
@@ -1102,19 +1103,19 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 9,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([0; 20]),
-                    },
+                    }),
                 },
             ],
         });
@@ -1151,43 +1152,43 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 13,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 13,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 13,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 13,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 13,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
             ],
         });
@@ -1224,11 +1225,11 @@ mod tests {
             }],
             outputs: vec![BitcoinTxOutput {
                 units: 13,
-                address: BitcoinAddress {
+                address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                     addrtype: BitcoinAddressType::PublicKeyHash,
                     network_id: BitcoinNetworkType::Mainnet,
                     bytes: Hash160([1; 20]),
-                },
+                }),
             }],
         });
 
@@ -1264,19 +1265,19 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 13,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
             ],
         });
@@ -1313,43 +1314,43 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 0,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 0,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 0,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 0,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 0,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
             ],
         });
@@ -1397,8 +1398,8 @@ mod tests {
                     memo: vec![0x1f],
 
                     commit_outs: vec![
-                        StacksAddress { version: 26, bytes: Hash160::empty() },
-                        StacksAddress { version: 26, bytes: Hash160::empty() }
+                        PoxAddress::Standard(StacksAddress { version: 26, bytes: Hash160::empty() }),
+                        PoxAddress::Standard(StacksAddress { version: 26, bytes: Hash160::empty() })
                     ],
 
                     burn_fee: 24690,
@@ -1570,8 +1571,8 @@ mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a914306231b2782b5f80d944bf69f9d46a1453a0a0eb88ac").unwrap(),
                 )
@@ -1599,8 +1600,8 @@ mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a914306231b2782b5f80d944bf69f9d46a1453a0a0eb88ac").unwrap(),
                 )
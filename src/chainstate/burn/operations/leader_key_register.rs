@@ -174,7 +174,13 @@ impl LeaderKeyRegisterOp {
             }
         };
 
-        let address = outputs[0].address.clone();
+        let address = match outputs[0].address.as_standard() {
+            Some(addr) => *addr,
+            None => {
+                test_debug!("Invalid tx: key address is not a standard address");
+                return Err(op_error::InvalidInput);
+            }
+        };
 
         Ok(LeaderKeyRegisterOp {
             consensus_hash: data.consensus_hash,
@@ -253,7 +259,7 @@ impl LeaderKeyRegisterOp {
 
 #[cfg(test)]
 pub mod tests {
-    use crate::burnchains::bitcoin::address::BitcoinAddress;
+    use crate::burnchains::bitcoin::address::{BitcoinAddress, LegacyBitcoinAddress};
     use crate::burnchains::bitcoin::blocks::BitcoinBlockParser;
     use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
     use crate::burnchains::bitcoin::BitcoinNetworkType;
@@ -305,7 +311,7 @@ pub mod tests {
                     consensus_hash: ConsensusHash::from_bytes(&hex_bytes("2222222222222222222222222222222222222222").unwrap()).unwrap(),
                     public_key: VRFPublicKey::from_bytes(&hex_bytes("a366b51292bef4edd64063d9145c617fec373bceb0758e98cd72becd84d54c7a").unwrap()).unwrap(),
                     memo: vec![01, 02, 03, 04, 05],
-                    address: StacksAddress::from_bitcoin_address(&BitcoinAddress::from_scriptpubkey(BitcoinNetworkType::Testnet, &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap()).unwrap()),
+                    address: StacksAddress::from_legacy_bitcoin_address(&LegacyBitcoinAddress::from_scriptpubkey(BitcoinNetworkType::Testnet, &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap()).unwrap()),
 
                     txid: Txid::from_bytes_be(&hex_bytes("1bfa831b5fc56c858198acb8e77e5863c1e9d8ac26d49ddb914e24d8d4083562").unwrap()).unwrap(),
                     vtxindex: vtxindex,
@@ -320,7 +326,7 @@ pub mod tests {
                     consensus_hash: ConsensusHash::from_bytes(&hex_bytes("2222222222222222222222222222222222222222").unwrap()).unwrap(),
                     public_key: VRFPublicKey::from_bytes(&hex_bytes("a366b51292bef4edd64063d9145c617fec373bceb0758e98cd72becd84d54c7a").unwrap()).unwrap(),
                     memo: vec![],
-                    address: StacksAddress::from_bitcoin_address(&BitcoinAddress::from_scriptpubkey(BitcoinNetworkType::Testnet, &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap()).unwrap()),
+                    address: StacksAddress::from_legacy_bitcoin_address(&LegacyBitcoinAddress::from_scriptpubkey(BitcoinNetworkType::Testnet, &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap()).unwrap()),
 
                     txid: Txid::from_bytes_be(&hex_bytes("2fbf8d5be32dce49790d203ba59acbb0929d5243413174ff5d26a5c6f23dea65").unwrap()).unwrap(),
                     vtxindex: vtxindex,
@@ -511,8 +517,8 @@ pub mod tests {
             )
             .unwrap(),
             memo: vec![01, 02, 03, 04, 05],
-            address: StacksAddress::from_bitcoin_address(
-                &BitcoinAddress::from_scriptpubkey(
+            address: StacksAddress::from_legacy_bitcoin_address(
+                &LegacyBitcoinAddress::from_scriptpubkey(
                     BitcoinNetworkType::Testnet,
                     &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap(),
                 )
@@ -653,8 +659,8 @@ pub mod tests {
                     )
                     .unwrap(),
                     memo: vec![01, 02, 03, 04, 05],
-                    address: StacksAddress::from_bitcoin_address(
-                        &BitcoinAddress::from_scriptpubkey(
+                    address: StacksAddress::from_legacy_bitcoin_address(
+                        &LegacyBitcoinAddress::from_scriptpubkey(
                             BitcoinNetworkType::Testnet,
                             &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac")
                                 .unwrap(),
@@ -690,8 +696,8 @@ pub mod tests {
                     )
                     .unwrap(),
                     memo: vec![01, 02, 03, 04, 05],
-                    address: StacksAddress::from_bitcoin_address(
-                        &BitcoinAddress::from_scriptpubkey(
+                    address: StacksAddress::from_legacy_bitcoin_address(
+                        &LegacyBitcoinAddress::from_scriptpubkey(
                             BitcoinNetworkType::Testnet,
                             &hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac")
                                 .unwrap(),
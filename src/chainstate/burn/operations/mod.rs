@@ -31,6 +31,7 @@ use crate::chainstate::burn::db::sortdb::SortitionHandleTx;
 use crate::chainstate::burn::operations::leader_block_commit::{
     MissedBlockCommit, BURN_BLOCK_MINED_AT_MODULUS,
 };
+use crate::chainstate::stacks::address::PoxAddress;
 use crate::types::chainstate::BlockHeaderHash;
 use crate::types::chainstate::StacksAddress;
 use crate::types::chainstate::TrieHash;
@@ -48,6 +49,7 @@ use stacks_common::util::vrf::VRFPublicKey;
 
 use crate::types::chainstate::BurnchainHeaderHash;
 
+pub mod delegate_stx;
 pub mod leader_block_commit;
 /// This module contains all burn-chain operations
 pub mod leader_key_register;
@@ -89,6 +91,10 @@ pub enum Error {
 
     StackStxMustBePositive,
     StackStxInvalidCycles,
+
+    DelegateStxMustBePositive,
+    DelegateStxNotActive,
+    DelegateStxBadEpoch,
 }
 
 impl fmt::Display for Error {
@@ -144,6 +150,13 @@ impl fmt::Display for Error {
                 f,
                 "Stack STX must set num cycles between 1 and max num cycles"
             ),
+            Error::DelegateStxMustBePositive => write!(f, "Delegate STX must be positive amount"),
+            Error::DelegateStxNotActive => {
+                write!(f, "Delegate STX is not active at this burnchain height")
+            }
+            Error::DelegateStxBadEpoch => {
+                write!(f, "Delegate STX is not supported in this epoch")
+            }
         }
     }
 }
@@ -181,7 +194,7 @@ pub struct TransferStxOp {
 pub struct StackStxOp {
     pub sender: StacksAddress,
     /// the PoX reward address
-    pub reward_addr: StacksAddress,
+    pub reward_addr: PoxAddress,
     /// how many ustx this transaction locks
     pub stacked_ustx: u128,
     pub num_cycles: u8,
@@ -193,6 +206,26 @@ pub struct StackStxOp {
     pub burn_header_hash: BurnchainHeaderHash, // hash of the burn chain block header
 }
 
+#[derive(Debug, PartialEq, Clone, Eq, Serialize, Deserialize)]
+pub struct DelegateStxOp {
+    pub sender: StacksAddress,
+    /// the address that the sender delegates its STX to
+    pub delegate_to: StacksAddress,
+    /// the PoX reward address the delegate must use, if any, along with the
+    /// index of the transaction output it was read from
+    pub reward_addr: Option<(u32, PoxAddress)>,
+    /// how many ustx the delegate may lock on the sender's behalf
+    pub delegated_ustx: u128,
+    /// the burnchain height at which the delegation expires, if any
+    pub until_burn_height: Option<u64>,
+
+    // common to all transactions
+    pub txid: Txid,                            // transaction ID
+    pub vtxindex: u32,                         // index in the block where this tx occurs
+    pub block_height: u64,                     // block height at which this tx occurs
+    pub burn_header_hash: BurnchainHeaderHash, // hash of the burn chain block header
+}
+
 #[derive(Debug, PartialEq, Clone, Eq, Serialize, Deserialize)]
 pub struct PreStxOp {
     /// the output address
@@ -229,7 +262,7 @@ pub struct LeaderBlockCommitOp {
     pub apparent_sender: BurnchainSigner,
 
     /// PoX/Burn outputs
-    pub commit_outs: Vec<StacksAddress>,
+    pub commit_outs: Vec<PoxAddress>,
     /// how much sunset burn this block performed
     pub sunset_burn: u64,
 
@@ -280,6 +313,7 @@ pub enum BlockstackOperationType {
     PreStx(PreStxOp),
     StackStx(StackStxOp),
     TransferStx(TransferStxOp),
+    DelegateStx(DelegateStxOp),
 }
 
 impl BlockstackOperationType {
@@ -291,6 +325,7 @@ impl BlockstackOperationType {
             BlockstackOperationType::StackStx(_) => Opcodes::StackStx,
             BlockstackOperationType::PreStx(_) => Opcodes::PreStx,
             BlockstackOperationType::TransferStx(_) => Opcodes::TransferStx,
+            BlockstackOperationType::DelegateStx(_) => Opcodes::DelegateStx,
         }
     }
 
@@ -306,6 +341,7 @@ impl BlockstackOperationType {
             BlockstackOperationType::StackStx(ref data) => &data.txid,
             BlockstackOperationType::PreStx(ref data) => &data.txid,
             BlockstackOperationType::TransferStx(ref data) => &data.txid,
+            BlockstackOperationType::DelegateStx(ref data) => &data.txid,
         }
     }
 
@@ -317,6 +353,7 @@ impl BlockstackOperationType {
             BlockstackOperationType::StackStx(ref data) => data.vtxindex,
             BlockstackOperationType::PreStx(ref data) => data.vtxindex,
             BlockstackOperationType::TransferStx(ref data) => data.vtxindex,
+            BlockstackOperationType::DelegateStx(ref data) => data.vtxindex,
         }
     }
 
@@ -328,6 +365,7 @@ impl BlockstackOperationType {
             BlockstackOperationType::StackStx(ref data) => data.block_height,
             BlockstackOperationType::PreStx(ref data) => data.block_height,
             BlockstackOperationType::TransferStx(ref data) => data.block_height,
+            BlockstackOperationType::DelegateStx(ref data) => data.block_height,
         }
     }
 
@@ -339,6 +377,7 @@ impl BlockstackOperationType {
             BlockstackOperationType::StackStx(ref data) => data.burn_header_hash.clone(),
             BlockstackOperationType::PreStx(ref data) => data.burn_header_hash.clone(),
             BlockstackOperationType::TransferStx(ref data) => data.burn_header_hash.clone(),
+            BlockstackOperationType::DelegateStx(ref data) => data.burn_header_hash.clone(),
         }
     }
 
@@ -353,6 +392,7 @@ impl BlockstackOperationType {
             BlockstackOperationType::StackStx(ref mut data) => data.block_height = height,
             BlockstackOperationType::PreStx(ref mut data) => data.block_height = height,
             BlockstackOperationType::TransferStx(ref mut data) => data.block_height = height,
+            BlockstackOperationType::DelegateStx(ref mut data) => data.block_height = height,
         };
     }
 
@@ -369,6 +409,7 @@ impl BlockstackOperationType {
             BlockstackOperationType::StackStx(ref mut data) => data.burn_header_hash = hash,
             BlockstackOperationType::PreStx(ref mut data) => data.burn_header_hash = hash,
             BlockstackOperationType::TransferStx(ref mut data) => data.burn_header_hash = hash,
+            BlockstackOperationType::DelegateStx(ref mut data) => data.burn_header_hash = hash,
         };
     }
 }
@@ -382,6 +423,7 @@ impl fmt::Display for BlockstackOperationType {
             BlockstackOperationType::LeaderBlockCommit(ref op) => write!(f, "{:?}", op),
             BlockstackOperationType::UserBurnSupport(ref op) => write!(f, "{:?}", op),
             BlockstackOperationType::TransferStx(ref op) => write!(f, "{:?}", op),
+            BlockstackOperationType::DelegateStx(ref op) => write!(f, "{:?}", op),
         }
     }
 }
@@ -391,6 +433,10 @@ pub fn parse_u128_from_be(bytes: &[u8]) -> Option<u128> {
     bytes.try_into().ok().map(u128::from_be_bytes)
 }
 
+pub fn parse_u64_from_be(bytes: &[u8]) -> Option<u64> {
+    bytes.try_into().ok().map(u64::from_be_bytes)
+}
+
 pub fn parse_u32_from_be(bytes: &[u8]) -> Option<u32> {
     bytes.try_into().ok().map(u32::from_be_bytes)
 }
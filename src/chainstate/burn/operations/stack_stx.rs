@@ -29,6 +29,7 @@ use crate::chainstate::burn::operations::{
 };
 use crate::chainstate::burn::ConsensusHash;
 use crate::chainstate::burn::Opcodes;
+use crate::chainstate::stacks::address::PoxAddress;
 use crate::chainstate::stacks::index::storage::TrieFileStorage;
 use crate::chainstate::stacks::{StacksPrivateKey, StacksPublicKey};
 use crate::codec::{write_next, Error as codec_error, StacksMessageCodec};
@@ -121,8 +122,16 @@ impl PreStxOp {
             return Err(op_error::InvalidInput);
         }
 
+        let output = match outputs[0].address.as_standard() {
+            Some(addr) => *addr,
+            None => {
+                warn!("Invalid tx: PreStx output is not a standard address");
+                return Err(op_error::InvalidInput);
+            }
+        };
+
         Ok(PreStxOp {
-            output: outputs[0].address,
+            output,
             txid: tx.txid(),
             vtxindex: tx.vtxindex(),
             block_height,
@@ -141,7 +150,7 @@ impl StackStxOp {
     ) -> StackStxOp {
         StackStxOp {
             sender: sender.clone(),
-            reward_addr: reward_addr.clone(),
+            reward_addr: PoxAddress::Standard(reward_addr.clone()),
             stacked_ustx,
             num_cycles,
             // to be filled in
@@ -266,6 +275,16 @@ impl StackStxOp {
             return Err(op_error::InvalidInput);
         }
 
+        // the PoX contract only takes p2pkh and p2sh reward addresses (versions 0x00-0x03), so a
+        // native segwit reward address could never be stacked
+        if outputs[0].address.as_standard().is_none() {
+            warn!(
+                "Invalid tx: StackStxOp reward address {} is not a p2pkh or p2sh address",
+                &outputs[0].address
+            );
+            return Err(op_error::InvalidInput);
+        }
+
         Ok(StackStxOp {
             sender: sender.clone(),
             reward_addr: outputs[0].address,
@@ -341,7 +360,7 @@ mod tests {
     use crate::chainstate::burn::operations::*;
     use crate::chainstate::burn::ConsensusHash;
     use crate::chainstate::burn::*;
-    use crate::chainstate::stacks::address::StacksAddressExtensions;
+    use crate::chainstate::stacks::address::{PoxAddress, StacksAddressExtensions};
     use crate::chainstate::stacks::StacksPublicKey;
     use stacks_common::address::AddressHashMode;
     use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
@@ -389,27 +408,27 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 30,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([0; 20]),
-                    },
+                    }),
                 },
             ],
         };
@@ -427,8 +446,8 @@ mod tests {
         .unwrap();
 
         assert_eq!(
-            &op.output,
-            &StacksAddress::from_bitcoin_address(&tx.outputs[0].address)
+            &PoxAddress::Standard(op.output),
+            &PoxAddress::from_bitcoin_address(&tx.outputs[0].address)
         );
     }
 
@@ -449,27 +468,27 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 30,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([0; 20]),
-                    },
+                    }),
                 },
             ],
         };
@@ -490,9 +509,24 @@ mod tests {
         assert_eq!(&op.sender, &sender);
         assert_eq!(
             &op.reward_addr,
-            &StacksAddress::from_bitcoin_address(&tx.outputs[0].address)
+            &PoxAddress::from_bitcoin_address(&tx.outputs[0].address)
         );
         assert_eq!(op.stacked_ustx, u128::from_be_bytes([1; 16]));
         assert_eq!(op.num_cycles, 1);
+
+        // native segwit reward addresses can't be stacked
+        let mut segwit_tx = tx.clone();
+        segwit_tx.outputs[0].address = BitcoinAddress::Segwit(SegwitBitcoinAddress::P2TR(
+            BitcoinNetworkType::Mainnet,
+            [1; 32],
+        ));
+        assert!(StackStxOp::parse_from_tx(
+            16843022,
+            &BurnchainHeaderHash([0; 32]),
+            &BurnchainTransaction::Bitcoin(segwit_tx),
+            &sender,
+            16843023,
+        )
+        .is_err());
     }
 }
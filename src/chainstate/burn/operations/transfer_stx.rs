@@ -180,9 +180,17 @@ impl TransferStxOp {
             op_error::ParseError
         })?;
 
+        let recipient = match outputs[0].address.as_standard() {
+            Some(addr) => *addr,
+            None => {
+                warn!("Invalid tx: TransferStx recipient is not a standard address");
+                return Err(op_error::InvalidInput);
+            }
+        };
+
         Ok(TransferStxOp {
             sender: sender.clone(),
-            recipient: outputs[0].address,
+            recipient,
             transfered_ustx: data.transfered_ustx,
             memo: data.memo,
             txid: tx.txid(),
@@ -238,7 +246,7 @@ mod tests {
     use crate::chainstate::burn::operations::*;
     use crate::chainstate::burn::ConsensusHash;
     use crate::chainstate::burn::*;
-    use crate::chainstate::stacks::address::StacksAddressExtensions;
+    use crate::chainstate::stacks::address::{PoxAddress, StacksAddressExtensions};
     use crate::chainstate::stacks::StacksPublicKey;
     use stacks_common::address::AddressHashMode;
     use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
@@ -269,27 +277,27 @@ mod tests {
             outputs: vec![
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([1; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 10,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([2; 20]),
-                    },
+                    }),
                 },
                 BitcoinTxOutput {
                     units: 30,
-                    address: BitcoinAddress {
+                    address: BitcoinAddress::Legacy(LegacyBitcoinAddress {
                         addrtype: BitcoinAddressType::PublicKeyHash,
                         network_id: BitcoinNetworkType::Mainnet,
                         bytes: Hash160([0; 20]),
-                    },
+                    }),
                 },
             ],
         };
@@ -309,7 +317,10 @@ mod tests {
         assert_eq!(&op.sender, &sender);
         assert_eq!(
             &op.recipient,
-            &StacksAddress::from_bitcoin_address(&tx.outputs[0].address)
+            &PoxAddress::from_bitcoin_address(&tx.outputs[0].address)
+                .as_standard()
+                .cloned()
+                .unwrap()
         );
         assert_eq!(op.transfered_ustx, u128::from_be_bytes([1; 16]));
         assert_eq!(op.memo, vec![1; 61]);
@@ -0,0 +1,596 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Replays historic sortitions with a hypothetical extra miner, to estimate what a block-commit
+//! schedule would have won before committing real BTC.
+//!
+//! The hypothetical miner's commits are added to the commitment window of each replayed block,
+//! chained to one another through their UTXOs the way a real miner's are, and the burn
+//! distribution is recomputed with `BurnSamplePoint::make_min_median_distribution`.  The other
+//! miners' commits are taken as they were, so the simulation does not model how they would have
+//! reacted to the extra miner.
+
+use std::collections::BTreeMap;
+
+use stacks_common::address::AddressHashMode;
+
+use crate::burnchains::{Burnchain, BurnchainSigner, Txid};
+use crate::chainstate::burn::db::sortdb::SortitionDB;
+use crate::chainstate::burn::distribution::CommitWindow;
+use crate::chainstate::burn::operations::leader_block_commit::MissedBlockCommit;
+use crate::chainstate::burn::operations::LeaderBlockCommitOp;
+use crate::chainstate::stacks::address::PoxAddress;
+use crate::chainstate::stacks::db::StacksChainState;
+use crate::types::chainstate::{BlockHeaderHash, BurnchainHeaderHash, SortitionId, VRFSeed};
+use crate::util_lib::db::Error as db_error;
+
+/// Fraction of commits, in percent, that the sensitivity scenarios skip or send late
+pub const SENSITIVITY_FAULT_RATES: [u64; 3] = [10, 25, 50];
+
+/// How many sats the hypothetical miner commits at each burnchain block height
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CommitSchedule {
+    amounts: BTreeMap<u64, u64>,
+}
+
+impl CommitSchedule {
+    /// Commit `amount` sats at every height from `start_height` to `end_height`, inclusive
+    pub fn constant(start_height: u64, end_height: u64, amount: u64) -> CommitSchedule {
+        CommitSchedule {
+            amounts: (start_height..=end_height).map(|h| (h, amount)).collect(),
+        }
+    }
+
+    /// Parse a schedule with one `HEIGHT AMOUNT` or `START_HEIGHT-END_HEIGHT AMOUNT` entry per
+    /// line.  Blank lines and lines starting with `#` are ignored, and later entries override
+    /// earlier ones.  Heights without an entry get no commit.
+    pub fn parse(text: &str) -> Result<CommitSchedule, String> {
+        let mut amounts = BTreeMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.len() == 0 || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = || format!("Bad schedule entry on line {}: {}", i + 1, line);
+            let mut fields = line.split_whitespace();
+            let (heights, amount) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(heights), Some(amount), None) => (heights, amount),
+                _ => return Err(bad_line()),
+            };
+            let amount: u64 = amount.parse().map_err(|_| bad_line())?;
+            let (start, end) = match heights.split_once('-') {
+                Some((start, end)) => (
+                    start.parse::<u64>().map_err(|_| bad_line())?,
+                    end.parse::<u64>().map_err(|_| bad_line())?,
+                ),
+                None => {
+                    let height = heights.parse::<u64>().map_err(|_| bad_line())?;
+                    (height, height)
+                }
+            };
+            if start > end {
+                return Err(bad_line());
+            }
+            for height in start..=end {
+                amounts.insert(height, amount);
+            }
+        }
+        Ok(CommitSchedule { amounts })
+    }
+
+    /// Sats committed at `height`, if any
+    pub fn amount_at(&self, height: u64) -> Option<u64> {
+        self.amounts.get(&height).copied()
+    }
+
+    /// Heights at which the schedule commits, in ascending order
+    pub fn heights(&self) -> impl Iterator<Item = u64> + '_ {
+        self.amounts.keys().copied()
+    }
+}
+
+/// Something that went wrong with one of the hypothetical miner's commits
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CommitFault {
+    /// The commit was never sent, leaving a gap in the miner's commitment window
+    Skipped,
+    /// The commit was mined one block late, so it became a missed commit.  It still costs its
+    /// amount, and keeps the UTXO chain intact.
+    Late,
+}
+
+/// The outcome of one replayed burnchain block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulatedBlock {
+    pub burn_block_height: u64,
+    /// Sats the hypothetical miner spent on its commit for this block
+    pub commit: u64,
+    pub fault: Option<CommitFault>,
+    /// The hypothetical commit's burn after the min-median rule; 0 if it was not eligible
+    pub effective_burn: u128,
+    /// Sum of all effective burns in the block, including the hypothetical miner's
+    pub total_burn: u128,
+    pub win_probability: f64,
+    /// Coinbase, in uSTX, for winning this block (transaction fees are not counted)
+    pub block_reward_ustx: u128,
+}
+
+/// Totals over a replayed range of burnchain blocks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulationSummary {
+    pub scenario: String,
+    pub blocks: u64,
+    /// Commits sent, including those sent before the first replayed block
+    pub commits_sent: u64,
+    pub expected_wins: f64,
+    pub expected_reward_ustx: f64,
+    /// Sats spent on commits, including those sent before the first replayed block, and not
+    /// counting transaction fees
+    pub btc_spent_sats: u64,
+    /// Sats spent per expected STX earned.  None if no reward is expected.
+    pub sats_per_stx: Option<f64>,
+}
+
+impl SimulationSummary {
+    /// Sum up the replayed `blocks`.  `warm_up_commits` are the amounts of the commits sent
+    /// before the first replayed block, which fill in the miner's first commitment windows: they
+    /// can't win anything in the replayed range, but they still cost BTC.
+    pub fn from_blocks(
+        scenario: &str,
+        warm_up_commits: &[u64],
+        blocks: &[SimulatedBlock],
+    ) -> SimulationSummary {
+        let expected_wins: f64 = blocks.iter().map(|b| b.win_probability).sum();
+        let expected_reward_ustx: f64 = blocks
+            .iter()
+            .map(|b| b.win_probability * (b.block_reward_ustx as f64))
+            .sum();
+        let btc_spent_sats =
+            warm_up_commits.iter().sum::<u64>() + blocks.iter().map(|b| b.commit).sum::<u64>();
+        let sats_per_stx = if expected_reward_ustx > 0.0 {
+            Some((btc_spent_sats as f64) / (expected_reward_ustx / 1_000_000.0))
+        } else {
+            None
+        };
+        SimulationSummary {
+            scenario: scenario.to_string(),
+            blocks: blocks.len() as u64,
+            commits_sent: (warm_up_commits.len() + blocks.iter().filter(|b| b.commit > 0).count())
+                as u64,
+            expected_wins,
+            expected_reward_ustx,
+            btc_spent_sats,
+            sats_per_stx,
+        }
+    }
+}
+
+/// A replay's per-block results, and how its totals change as commits are skipped or late
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulationReport {
+    pub blocks: Vec<SimulatedBlock>,
+    pub scenarios: Vec<SimulationSummary>,
+}
+
+/// A commit the hypothetical miner sent
+struct SentCommit {
+    amount: u64,
+    txid: Txid,
+    input: (Txid, u32),
+    late: bool,
+}
+
+/// Replays a range of burnchain blocks with a hypothetical extra miner
+pub struct MiningSimulator<'a> {
+    burnchain: &'a Burnchain,
+    /// Commitment window of each replayed block, by block height
+    windows: BTreeMap<u64, CommitWindow>,
+}
+
+impl<'a> MiningSimulator<'a> {
+    pub fn new(
+        burnchain: &'a Burnchain,
+        windows: BTreeMap<u64, CommitWindow>,
+    ) -> MiningSimulator<'a> {
+        MiningSimulator { burnchain, windows }
+    }
+
+    /// Load the commitment windows of the canonical fork's blocks from `start_height` to
+    /// `end_height`, inclusive.
+    pub fn load(
+        sortdb: &SortitionDB,
+        burnchain: &'a Burnchain,
+        start_height: u64,
+        end_height: u64,
+    ) -> Result<MiningSimulator<'a>, db_error> {
+        let tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn())?;
+        let ic = sortdb.index_handle(&tip.sortition_id);
+        let mut windows = BTreeMap::new();
+        for height in start_height..=end_height {
+            let snapshot = ic
+                .get_block_snapshot_by_height(height)?
+                .ok_or(db_error::NotFoundError)?;
+            windows.insert(height, CommitWindow::load(sortdb, burnchain, &snapshot)?);
+        }
+        Ok(MiningSimulator::new(burnchain, windows))
+    }
+
+    /// Is a commit at this height a PoB-only commit?
+    fn is_burn_block(&self, height: u64) -> bool {
+        self.burnchain.pox_constants.sunset_end <= height
+            || self.burnchain.is_in_prepare_phase(height)
+    }
+
+    /// Txid of the hypothetical miner's commit at this height
+    fn simulated_txid(height: u64) -> Txid {
+        let mut bytes = [0xff; 32];
+        bytes[24..].copy_from_slice(&height.to_be_bytes());
+        Txid(bytes)
+    }
+
+    fn simulated_signer() -> BurnchainSigner {
+        BurnchainSigner {
+            hash_mode: AddressHashMode::SerializeP2PKH,
+            num_sigs: 0,
+            public_keys: vec![],
+        }
+    }
+
+    /// Work out which commits the schedule sends, and which UTXO each one spends
+    fn sent_commits(
+        &self,
+        schedule: &CommitSchedule,
+        faults: &BTreeMap<u64, CommitFault>,
+    ) -> BTreeMap<u64, SentCommit> {
+        let mut sent = BTreeMap::new();
+        let mut input = (Txid([0; 32]), 0);
+        for height in schedule.heights() {
+            let fault = faults.get(&height);
+            if fault == Some(&CommitFault::Skipped) {
+                continue;
+            }
+            let txid = Self::simulated_txid(height);
+            sent.insert(
+                height,
+                SentCommit {
+                    amount: schedule.amount_at(height).unwrap_or(0),
+                    txid,
+                    input,
+                    late: fault == Some(&CommitFault::Late),
+                },
+            );
+            input = (
+                txid,
+                LeaderBlockCommitOp::expected_chained_utxo(self.is_burn_block(height)),
+            );
+        }
+        sent
+    }
+
+    /// Amounts of the commits the schedule sends before the first replayed block
+    pub fn warm_up_commits(
+        &self,
+        schedule: &CommitSchedule,
+        faults: &BTreeMap<u64, CommitFault>,
+    ) -> Vec<u64> {
+        let first_height = match self.windows.keys().next() {
+            Some(height) => *height,
+            None => return vec![],
+        };
+        self.sent_commits(schedule, faults)
+            .range(..first_height)
+            .map(|(_, commit)| commit.amount)
+            .collect()
+    }
+
+    fn make_commit(height: u64, vtxindex: u32, commit: &SentCommit) -> LeaderBlockCommitOp {
+        LeaderBlockCommitOp {
+            sunset_burn: 0,
+            block_header_hash: BlockHeaderHash([0; 32]),
+            new_seed: VRFSeed([0; 32]),
+            parent_block_ptr: 0,
+            parent_vtxindex: 0,
+            key_block_ptr: 0,
+            key_vtxindex: 0,
+            memo: vec![],
+            commit_outs: vec![PoxAddress::standard_burn_address(true)],
+            burn_fee: commit.amount,
+            input: commit.input,
+            apparent_sender: Self::simulated_signer(),
+            txid: commit.txid,
+            vtxindex,
+            block_height: height,
+            burn_parent_modulus: 0,
+            burn_header_hash: BurnchainHeaderHash([0; 32]),
+        }
+    }
+
+    /// Replay every loaded block with the hypothetical miner following `schedule`, except for
+    /// the commits in `faults`.
+    pub fn simulate(
+        &self,
+        schedule: &CommitSchedule,
+        faults: &BTreeMap<u64, CommitFault>,
+    ) -> Vec<SimulatedBlock> {
+        let sent = self.sent_commits(schedule, faults);
+        let mut blocks = vec![];
+        for (height, window) in self.windows.iter() {
+            let mut window = window.clone();
+            let window_start = height + 1 - (window.block_commits.len() as u64);
+            for (i, commits) in window.block_commits.iter_mut().enumerate() {
+                let commit_height = window_start + (i as u64);
+                let commit = match sent.get(&commit_height) {
+                    Some(commit) => commit,
+                    None => continue,
+                };
+                if !commit.late {
+                    let vtxindex = commits.iter().map(|op| op.vtxindex + 1).max().unwrap_or(0);
+                    commits.push(Self::make_commit(commit_height, vtxindex, commit));
+                } else if let Some(missed) = window.missed_commits.get_mut(i) {
+                    missed.push(MissedBlockCommit {
+                        txid: commit.txid,
+                        input: commit.input,
+                        // not consulted by the burn distribution
+                        intended_sortition: SortitionId([0; 32]),
+                    });
+                }
+            }
+
+            let distribution = window.make_distribution();
+            let total_burn: u128 = distribution.iter().map(|point| point.burns).sum();
+            let effective_burn = distribution
+                .iter()
+                .find(|point| point.candidate.txid == Self::simulated_txid(*height))
+                .map(|point| point.burns)
+                .unwrap_or(0);
+            let win_probability = if total_burn > 0 {
+                (effective_burn as f64) / (total_burn as f64)
+            } else {
+                0.0
+            };
+
+            blocks.push(SimulatedBlock {
+                burn_block_height: *height,
+                commit: sent.get(height).map(|commit| commit.amount).unwrap_or(0),
+                fault: faults.get(height).copied(),
+                effective_burn,
+                total_burn,
+                win_probability,
+                block_reward_ustx: StacksChainState::get_coinbase_reward(
+                    *height,
+                    self.burnchain.first_block_height,
+                ),
+            });
+        }
+        blocks
+    }
+
+    /// Evenly spread faults over `percent` percent of the schedule's commits
+    pub fn spread_faults(
+        schedule: &CommitSchedule,
+        fault: CommitFault,
+        percent: u64,
+    ) -> BTreeMap<u64, CommitFault> {
+        schedule
+            .heights()
+            .enumerate()
+            .filter(|(i, _)| {
+                let i = *i as u64;
+                (i + 1) * percent / 100 > i * percent / 100
+            })
+            .map(|(_, height)| (height, fault))
+            .collect()
+    }
+
+    /// Replay the schedule as given, and again with a growing share of its commits skipped or
+    /// sent late.
+    pub fn run(&self, schedule: &CommitSchedule) -> SimulationReport {
+        let no_faults = BTreeMap::new();
+        let blocks = self.simulate(schedule, &no_faults);
+        let mut scenarios = vec![SimulationSummary::from_blocks(
+            "baseline",
+            &self.warm_up_commits(schedule, &no_faults),
+            &blocks,
+        )];
+        for (fault, name) in [
+            (CommitFault::Skipped, "skipped"),
+            (CommitFault::Late, "late"),
+        ] {
+            for percent in SENSITIVITY_FAULT_RATES {
+                let faults = Self::spread_faults(schedule, fault, percent);
+                scenarios.push(SimulationSummary::from_blocks(
+                    &format!("{}-{}%", name, percent),
+                    &self.warm_up_commits(schedule, &faults),
+                    &self.simulate(schedule, &faults),
+                ));
+            }
+        }
+        SimulationReport { blocks, scenarios }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::core::MINING_COMMITMENT_WINDOW;
+
+    const OTHER_BURN: u64 = 10_000;
+
+    fn other_txid(height: u64) -> Txid {
+        let mut bytes = [0x01; 32];
+        bytes[24..].copy_from_slice(&height.to_be_bytes());
+        Txid(bytes)
+    }
+
+    /// Commitment windows for `start..=end` in which one other miner commits `OTHER_BURN` at
+    /// every height with a properly-chained UTXO
+    fn make_windows(start: u64, end: u64) -> BTreeMap<u64, CommitWindow> {
+        let window_size = MINING_COMMITMENT_WINDOW as u64;
+        let other_commit = |height: u64| {
+            let mut op = MiningSimulator::make_commit(
+                height,
+                0,
+                &SentCommit {
+                    amount: OTHER_BURN,
+                    txid: other_txid(height),
+                    input: (
+                        other_txid(height - 1),
+                        LeaderBlockCommitOp::expected_chained_utxo(false),
+                    ),
+                    late: false,
+                },
+            );
+            op.apparent_sender = BurnchainSigner {
+                hash_mode: AddressHashMode::SerializeP2PKH,
+                num_sigs: 1,
+                public_keys: vec![],
+            };
+            op
+        };
+        (start..=end)
+            .map(|height| {
+                let window = CommitWindow {
+                    block_commits: (height + 1 - window_size..=height)
+                        .map(|h| vec![other_commit(h)])
+                        .collect(),
+                    missed_commits: vec![vec![]; (window_size - 1) as usize],
+                    burn_blocks: vec![false; window_size as usize],
+                };
+                (height, window)
+            })
+            .collect()
+    }
+
+    fn burnchain() -> Burnchain {
+        Burnchain::default_unittest(0, &BurnchainHeaderHash([0; 32]))
+    }
+
+    #[test]
+    fn test_parse_commit_schedule() {
+        let schedule = CommitSchedule::parse(
+            "
+            # a comment
+            100-102 5000
+            101 7000
+
+            ",
+        )
+        .unwrap();
+        assert_eq!(schedule.amount_at(99), None);
+        assert_eq!(schedule.amount_at(100), Some(5000));
+        assert_eq!(schedule.amount_at(101), Some(7000));
+        assert_eq!(schedule.amount_at(102), Some(5000));
+        assert_eq!(schedule.heights().collect::<Vec<_>>(), vec![100, 101, 102]);
+
+        assert!(CommitSchedule::parse("100").is_err());
+        assert!(CommitSchedule::parse("100 abc").is_err());
+        assert!(CommitSchedule::parse("102-100 5000").is_err());
+        assert!(CommitSchedule::parse("100 5000 6000").is_err());
+    }
+
+    #[test]
+    fn test_spread_faults() {
+        let schedule = CommitSchedule::constant(100, 119, 1000);
+        let faults = MiningSimulator::spread_faults(&schedule, CommitFault::Late, 25);
+        assert_eq!(
+            faults.keys().copied().collect::<Vec<_>>(),
+            vec![103, 107, 111, 115, 119]
+        );
+        let faults = MiningSimulator::spread_faults(&schedule, CommitFault::Skipped, 50);
+        assert_eq!(faults.len(), 10);
+        assert!(faults.values().all(|fault| *fault == CommitFault::Skipped));
+    }
+
+    #[test]
+    fn test_simulate_baseline() {
+        let burnchain = burnchain();
+        let simulator = MiningSimulator::new(&burnchain, make_windows(100, 119));
+
+        // an equal, steady commitment wins half the time
+        let schedule = CommitSchedule::constant(90, 119, OTHER_BURN);
+        let blocks = simulator.simulate(&schedule, &BTreeMap::new());
+        assert_eq!(blocks.len(), 20);
+        for block in blocks.iter() {
+            assert_eq!(block.commit, OTHER_BURN);
+            assert_eq!(block.effective_burn, OTHER_BURN as u128);
+            assert_eq!(block.total_burn, 2 * OTHER_BURN as u128);
+            assert_eq!(block.win_probability, 0.5);
+        }
+
+        // the 10 commits before the first replayed block win nothing, but still cost BTC
+        let warm_up = simulator.warm_up_commits(&schedule, &BTreeMap::new());
+        assert_eq!(warm_up, vec![OTHER_BURN; 10]);
+        let summary = SimulationSummary::from_blocks("baseline", &warm_up, &blocks);
+        assert_eq!(summary.commits_sent, 30);
+        assert_eq!(summary.expected_wins, 10.0);
+        assert_eq!(summary.btc_spent_sats, 30 * OTHER_BURN);
+        assert_eq!(
+            summary.expected_reward_ustx,
+            10.0 * StacksChainState::get_coinbase_reward(100, 0) as f64
+        );
+
+        // a miner who only starts committing in the range is held back by the min-median rule
+        let schedule = CommitSchedule::constant(110, 119, OTHER_BURN);
+        let blocks = simulator.simulate(&schedule, &BTreeMap::new());
+        assert_eq!(blocks[9].win_probability, 0.0);
+        assert_eq!(blocks[10].effective_burn, 1);
+        assert_eq!(blocks[11].effective_burn, 1);
+        assert_eq!(blocks[12].effective_burn, (1 + OTHER_BURN as u128) / 2);
+        assert_eq!(blocks[13].effective_burn, OTHER_BURN as u128);
+    }
+
+    #[test]
+    fn test_simulate_faults() {
+        let burnchain = burnchain();
+        let simulator = MiningSimulator::new(&burnchain, make_windows(100, 119));
+        let schedule = CommitSchedule::constant(90, 119, OTHER_BURN);
+
+        // a skipped commit loses its block, and counts as 1 sat in the miner's later windows
+        let mut faults = BTreeMap::new();
+        for height in 108..=110 {
+            faults.insert(height, CommitFault::Skipped);
+        }
+        let blocks = simulator.simulate(&schedule, &faults);
+        let burns: Vec<_> = blocks[8..15].iter().map(|b| b.effective_burn).collect();
+        let half = (1 + OTHER_BURN as u128) / 2;
+        assert_eq!(burns, vec![0, 0, 0, half, half, half, OTHER_BURN as u128]);
+        assert_eq!(blocks[10].commit, 0);
+        assert_eq!(blocks[10].fault, Some(CommitFault::Skipped));
+
+        // a late commit only loses its own block, but still costs its amount
+        let mut faults = BTreeMap::new();
+        faults.insert(110, CommitFault::Late);
+        let blocks = simulator.simulate(&schedule, &faults);
+        assert_eq!(blocks[10].win_probability, 0.0);
+        assert_eq!(blocks[10].commit, OTHER_BURN);
+        for block in blocks[11..].iter() {
+            assert_eq!(block.win_probability, 0.5);
+        }
+
+        let report = simulator.run(&schedule);
+        assert_eq!(report.blocks.len(), 20);
+        assert_eq!(
+            report.scenarios.len(),
+            1 + 2 * SENSITIVITY_FAULT_RATES.len()
+        );
+        assert_eq!(report.scenarios[0].expected_wins, 10.0);
+        assert_eq!(report.scenarios[0].btc_spent_sats, 30 * OTHER_BURN);
+        for scenario in report.scenarios[1..].iter() {
+            assert!(scenario.expected_wins < report.scenarios[0].expected_wins);
+        }
+    }
+}
@@ -34,6 +34,7 @@ use crate::chainstate::burn::{
 use crate::chainstate::coordinator::comm::{
     ArcCounterCoordinatorNotices, CoordinatorEvents, CoordinatorNotices, CoordinatorReceivers,
 };
+use crate::chainstate::stacks::address::PoxAddress;
 use crate::chainstate::stacks::index::MarfTrieId;
 use crate::chainstate::stacks::{
     db::{
@@ -74,7 +75,7 @@ pub mod tests;
 ///  reward cycle's relationship to its PoX anchor
 #[derive(Debug, PartialEq)]
 pub enum PoxAnchorBlockStatus {
-    SelectedAndKnown(BlockHeaderHash, Vec<StacksAddress>),
+    SelectedAndKnown(BlockHeaderHash, Vec<PoxAddress>),
     SelectedAndUnknown(BlockHeaderHash),
     NotSelected,
 }
@@ -99,7 +100,7 @@ impl RewardCycleInfo {
             SelectedAndKnown(_, _) | NotSelected => true,
         }
     }
-    pub fn known_selected_anchor_block(&self) -> Option<&Vec<StacksAddress>> {
+    pub fn known_selected_anchor_block(&self) -> Option<&Vec<PoxAddress>> {
         use self::PoxAnchorBlockStatus::*;
         match self.anchor_status {
             SelectedAndUnknown(_) => None,
@@ -107,7 +108,7 @@ impl RewardCycleInfo {
             NotSelected => None,
         }
     }
-    pub fn known_selected_anchor_block_owned(self) -> Option<Vec<StacksAddress>> {
+    pub fn known_selected_anchor_block_owned(self) -> Option<Vec<PoxAddress>> {
         use self::PoxAnchorBlockStatus::*;
         match self.anchor_status {
             SelectedAndUnknown(_) => None,
@@ -142,9 +143,9 @@ pub trait BlockEventDispatcher {
         &self,
         burn_block: &BurnchainHeaderHash,
         burn_block_height: u64,
-        rewards: Vec<(StacksAddress, u64)>,
+        rewards: Vec<(PoxAddress, u64)>,
         burns: u64,
-        reward_recipients: Vec<StacksAddress>,
+        reward_recipients: Vec<PoxAddress>,
     );
 
     fn dispatch_boot_receipts(&mut self, receipts: Vec<StacksTransactionReceipt>);
@@ -212,7 +213,7 @@ pub trait RewardSetProvider {
         burnchain: &Burnchain,
         sortdb: &SortitionDB,
         block_id: &StacksBlockId,
-    ) -> Result<Vec<StacksAddress>, Error>;
+    ) -> Result<Vec<PoxAddress>, Error>;
 }
 
 pub struct OnChainRewardSetProvider();
@@ -225,7 +226,7 @@ impl RewardSetProvider for OnChainRewardSetProvider {
         burnchain: &Burnchain,
         sortdb: &SortitionDB,
         block_id: &StacksBlockId,
-    ) -> Result<Vec<StacksAddress>, Error> {
+    ) -> Result<Vec<PoxAddress>, Error> {
         let registered_addrs =
             chainstate.get_reward_addresses(burnchain, sortdb, current_burn_height, block_id)?;
 
@@ -489,7 +490,7 @@ pub fn get_reward_cycle_info<U: RewardSetProvider>(
 }
 
 struct PaidRewards {
-    pox: Vec<(StacksAddress, u64)>,
+    pox: Vec<(PoxAddress, u64)>,
     burns: u64,
 }
 
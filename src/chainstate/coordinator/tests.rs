@@ -28,7 +28,7 @@ use rusqlite::Connection;
 use crate::burnchains::{db::*, *};
 use crate::chainstate;
 use crate::chainstate::burn::db::sortdb::SortitionDB;
-use crate::chainstate::burn::distribution::BurnSamplePoint;
+use crate::chainstate::burn::distribution::{BurnSamplePoint, CommitWindow};
 use crate::chainstate::burn::operations::leader_block_commit::*;
 use crate::chainstate::burn::operations::*;
 use crate::chainstate::burn::*;
@@ -51,6 +51,7 @@ use stacks_common::address;
 use stacks_common::util::hash::{to_hex, Hash160};
 use stacks_common::util::vrf::*;
 
+use crate::chainstate::stacks::address::PoxAddress;
 use crate::chainstate::stacks::boot::COSTS_2_NAME;
 use crate::util_lib::boot::boot_code_id;
 use crate::{types, util};
@@ -326,9 +327,9 @@ impl BlockEventDispatcher for NullEventDispatcher {
         &self,
         _burn_block: &BurnchainHeaderHash,
         _burn_block_height: u64,
-        _rewards: Vec<(StacksAddress, u64)>,
+        _rewards: Vec<(PoxAddress, u64)>,
         _burns: u64,
-        _slot_holders: Vec<StacksAddress>,
+        _slot_holders: Vec<PoxAddress>,
     ) {
     }
 
@@ -344,7 +345,7 @@ pub fn make_coordinator<'a>(
     ChainsCoordinator::test_new(&burnchain, 0x80000000, path, OnChainRewardSetProvider(), tx)
 }
 
-struct StubbedRewardSetProvider(Vec<StacksAddress>);
+struct StubbedRewardSetProvider(Vec<PoxAddress>);
 
 impl RewardSetProvider for StubbedRewardSetProvider {
     fn get_reward_set(
@@ -354,7 +355,7 @@ impl RewardSetProvider for StubbedRewardSetProvider {
         burnchain: &Burnchain,
         sortdb: &SortitionDB,
         block_id: &StacksBlockId,
-    ) -> Result<Vec<StacksAddress>, chainstate::coordinator::Error> {
+    ) -> Result<Vec<PoxAddress>, chainstate::coordinator::Error> {
         Ok(self.0.clone())
     }
 }
@@ -369,7 +370,7 @@ fn make_reward_set_coordinator<'a>(
         &get_burnchain(path, pox_consts),
         0x80000000,
         path,
-        StubbedRewardSetProvider(addrs),
+        StubbedRewardSetProvider(addrs.into_iter().map(PoxAddress::Standard).collect()),
         tx,
     )
 }
@@ -487,9 +488,9 @@ fn make_genesis_block_with_recipients(
             .recipients
             .iter()
             .map(|(a, _)| a.clone())
-            .collect::<Vec<StacksAddress>>();
+            .collect::<Vec<PoxAddress>>();
         if commit_outs.len() == 1 {
-            commit_outs.push(StacksAddress::burn_address(false))
+            commit_outs.push(PoxAddress::standard_burn_address(false))
         }
         commit_outs
     } else {
@@ -705,15 +706,15 @@ fn make_stacks_block_with_input(
             .recipients
             .iter()
             .map(|(a, _)| a.clone())
-            .collect::<Vec<StacksAddress>>();
+            .collect::<Vec<PoxAddress>>();
         if commit_outs.len() == 1 {
             // Padding with burn address if required
-            commit_outs.push(StacksAddress::burn_address(false))
+            commit_outs.push(PoxAddress::standard_burn_address(false))
         }
         commit_outs
     } else if post_sunset_burn || burnchain.is_in_prepare_phase(parent_height + 1) {
         test_debug!("block-commit in {} will burn", parent_height + 1);
-        vec![StacksAddress::burn_address(false)]
+        vec![PoxAddress::standard_burn_address(false)]
     } else {
         vec![]
     };
@@ -1037,6 +1038,156 @@ fn missed_block_commits() {
     }
 }
 
+#[test]
+fn test_commit_window_reproduces_sortitions() {
+    let path = "/tmp/stacks-blockchain-commit-window";
+    let _r = std::fs::remove_dir_all(path);
+
+    let vrf_keys: Vec<_> = (0..3).map(|_| VRFPrivateKey::new()).collect();
+    let committers: Vec<_> = (0..3).map(|_| StacksPrivateKey::new()).collect();
+    let burn_fees = [10_000, 20_000, 5_000];
+    // miner 1's block-commit for this block is mined one block late, as a missed commit
+    let missed_ix = 3;
+    let late_miner = 1;
+
+    setup_states(
+        &[path],
+        &vrf_keys,
+        &committers,
+        None,
+        None,
+        StacksEpochId::Epoch20,
+    );
+
+    let mut coord = make_coordinator(path, None);
+    coord.handle_new_burnchain_block().unwrap();
+
+    let sort_db = get_sortition_db(path, None);
+    let b = get_burnchain(path, None);
+
+    let mut parent = BlockHeaderHash([0; 32]);
+    let mut last_inputs = vec![(Txid([0; 32]), 0); committers.len()];
+    let mut late_op = None;
+    let mut late_txid = None;
+    for ix in 0..10 {
+        let mut burnchain = get_burnchain_db(path, None);
+        let mut chainstate = get_chainstate(path);
+        let burnchain_tip = burnchain.get_canonical_chain_tip().unwrap();
+        let block_height = burnchain_tip.block_height + 1;
+        let chained_utxo =
+            LeaderBlockCommitOp::expected_chained_utxo(b.is_in_prepare_phase(block_height));
+
+        let mut ops = vec![];
+        let mut blocks = vec![];
+        for (m, vrf_key) in vrf_keys.iter().enumerate() {
+            // the helpers always mine a coinbase with nonce 0, so use a new key for each block
+            let miner = &StacksPrivateKey::new();
+            let (mut op, block) = if ix == 0 {
+                make_genesis_block(
+                    &sort_db,
+                    &mut chainstate,
+                    &parent,
+                    miner,
+                    burn_fees[m],
+                    vrf_key,
+                    m as u32,
+                )
+            } else {
+                make_stacks_block_with_input(
+                    &sort_db,
+                    &mut chainstate,
+                    &b,
+                    &parent,
+                    burnchain_tip.block_height,
+                    miner,
+                    burn_fees[m],
+                    vrf_key,
+                    m as u32,
+                    None,
+                    0,
+                    false,
+                    last_inputs[m],
+                )
+            };
+            if let BlockstackOperationType::LeaderBlockCommit(ref mut op) = op {
+                // genesis block-commits all have vtxindex 1
+                op.vtxindex = (m + 1) as u32;
+            }
+            op.set_block_height(block_height);
+            last_inputs[m] = (op.txid(), chained_utxo);
+            blocks.push((op.txid(), block));
+            if ix == missed_ix && m == late_miner {
+                late_txid = Some(op.txid());
+                late_op = Some(op);
+            } else {
+                ops.push(op);
+            }
+        }
+        if let Some(BlockstackOperationType::LeaderBlockCommit(mut op)) = late_op.take() {
+            if ix != missed_ix {
+                // intended for the parent of this block
+                op.set_burn_height(block_height);
+                op.burn_parent_modulus = ((block_height - 2) % BURN_BLOCK_MINED_AT_MODULUS) as u8;
+                op.vtxindex = (committers.len() + 1) as u32;
+                ops.push(BlockstackOperationType::LeaderBlockCommit(op));
+            } else {
+                late_op = Some(BlockstackOperationType::LeaderBlockCommit(op));
+            }
+        }
+
+        produce_burn_block_do_not_set_height(
+            &mut burnchain,
+            &burnchain_tip.block_hash,
+            ops,
+            [].iter_mut(),
+        );
+        coord.handle_new_burnchain_block().unwrap();
+
+        let tip = SortitionDB::get_canonical_burn_chain_tip(sort_db.conn()).unwrap();
+        assert!(tip.sortition);
+
+        // the commit window reloaded from the sortition DB reproduces the sortition
+        let window = CommitWindow::load(&sort_db, &b, &tip).unwrap();
+        let burn_dist = window.make_distribution();
+        assert_eq!(
+            burn_dist,
+            get_burn_distribution(sort_db.conn(), &tip.sortition_id)
+        );
+
+        let late_burns = burn_dist
+            .iter()
+            .find(|point| point.candidate.vtxindex == (late_miner + 1) as u32)
+            .map(|point| point.burns);
+        if ix == missed_ix {
+            assert_eq!(burn_dist.len(), committers.len() - 1);
+            assert_eq!(late_burns, None);
+        } else {
+            assert_eq!(burn_dist.len(), committers.len());
+        }
+        if ix == missed_ix + 1 {
+            // the window has the missed commit, and the late miner's commits are linked through
+            // it, so its earlier commits still count towards its min-median burn
+            assert!(!b.is_in_prepare_phase(block_height));
+            let missed: Vec<_> = window.missed_commits.iter().flatten().collect();
+            assert_eq!(missed.len(), 1);
+            assert_eq!(Some(&missed[0].txid), late_txid.as_ref());
+            assert_eq!(late_burns, Some(burn_fees[late_miner] as u128));
+        }
+
+        let (_, block) = blocks
+            .into_iter()
+            .find(|(txid, _)| txid == &tip.winning_block_txid)
+            .unwrap();
+        let block_hash = block.header.block_hash();
+        assert_eq!(&tip.winning_stacks_block_hash, &block_hash);
+
+        preprocess_block(&mut chainstate, &sort_db, &tip, block);
+        coord.handle_new_stacks_block().unwrap();
+
+        parent = block_hash;
+    }
+}
+
 #[test]
 fn test_simple_setup() {
     let path = "/tmp/stacks-blockchain-simple-setup";
@@ -1422,10 +1573,15 @@ fn test_sortition_with_reward_set() {
             // sometime have the wrong _number_ of recipients,
             //   other times just have the wrong set of recipients
             let recipients = if ix % 2 == 0 {
-                vec![(p2pkh_from(miner_wrong_out), 0)]
+                vec![(PoxAddress::Standard(p2pkh_from(miner_wrong_out)), 0)]
             } else {
                 (0..OUTPUTS_PER_COMMIT)
-                    .map(|ix| (p2pkh_from(&StacksPrivateKey::new()), ix as u16))
+                    .map(|ix| {
+                        (
+                            PoxAddress::Standard(p2pkh_from(&StacksPrivateKey::new())),
+                            ix as u16,
+                        )
+                    })
                     .collect()
             };
             let bad_block_recipients = Some(RewardSetInfo {
@@ -1662,10 +1818,15 @@ fn test_sortition_with_burner_reward_set() {
             // sometime have the wrong _number_ of recipients,
             //   other times just have the wrong set of recipients
             let recipients = if ix % 2 == 0 {
-                vec![(p2pkh_from(miner_wrong_out), 0)]
+                vec![(PoxAddress::Standard(p2pkh_from(miner_wrong_out)), 0)]
             } else {
                 (0..OUTPUTS_PER_COMMIT)
-                    .map(|ix| (p2pkh_from(&StacksPrivateKey::new()), ix as u16))
+                    .map(|ix| {
+                        (
+                            PoxAddress::Standard(p2pkh_from(&StacksPrivateKey::new())),
+                            ix as u16,
+                        )
+                    })
                     .collect()
             };
             let bad_block_recipients = Some(RewardSetInfo {
@@ -1894,7 +2055,7 @@ fn test_pox_btc_ops() {
         } else if ix == 1 {
             ops.push(BlockstackOperationType::StackStx(StackStxOp {
                 sender: stacker.clone(),
-                reward_addr: rewards.clone(),
+                reward_addr: PoxAddress::Standard(rewards.clone()),
                 stacked_ustx: stacked_amt,
                 num_cycles: 4,
                 txid: next_txid(),
@@ -2310,6 +2471,203 @@ fn test_stx_transfer_btc_ops() {
     }
 }
 
+// This test checks that DelegateStx burn ops are rejected before Stacks 2.05, and that once
+// accepted, they are applied to the PoX contract's delegation state.
+#[test]
+fn test_delegate_stx_btc_ops() {
+    let path = "/tmp/stacks-blockchain-delegate-stx-btc-ops";
+    let _r = std::fs::remove_dir_all(path);
+
+    let sunset_ht = 8000;
+    let pox_consts = Some(PoxConstants::new(6, 3, 3, 25, 5, 10, sunset_ht));
+    let burnchain_conf = get_burnchain(path, pox_consts.clone());
+
+    let vrf_keys: Vec<_> = (0..8).map(|_| VRFPrivateKey::new()).collect();
+    let committers: Vec<_> = (0..8).map(|_| StacksPrivateKey::new()).collect();
+
+    let stacker = p2pkh_from(&StacksPrivateKey::new());
+    let delegate = p2pkh_from(&StacksPrivateKey::new());
+    let rewards = p2pkh_from(&StacksPrivateKey::new());
+    let balance = 6_000_000_000 * (core::MICROSTACKS_PER_STACKS as u64);
+    let delegated_amt = 1_000_000_000 * (core::MICROSTACKS_PER_STACKS as u128);
+    let initial_balances = vec![(stacker.clone().into(), balance)];
+
+    setup_states(
+        &[path],
+        &vrf_keys,
+        &committers,
+        pox_consts.clone(),
+        Some(initial_balances),
+        StacksEpochId::Epoch2_05,
+    );
+
+    let mut coord = make_coordinator(path, Some(burnchain_conf));
+
+    coord.handle_new_burnchain_block().unwrap();
+
+    let sort_db = get_sortition_db(path, pox_consts.clone());
+
+    let mut stacks_blocks: Vec<(SortitionId, StacksBlock)> = vec![];
+    let mut delegate_txids = vec![];
+
+    for ix in 0..vrf_keys.len() {
+        let vrf_key = &vrf_keys[ix];
+        let miner = &committers[ix];
+
+        let mut burnchain = get_burnchain_db(path, pox_consts.clone());
+        let mut chainstate = get_chainstate(path);
+
+        let parent = if ix == 0 {
+            BlockHeaderHash([0; 32])
+        } else {
+            stacks_blocks[ix - 1].1.header.block_hash()
+        };
+
+        let burnchain_tip = burnchain.get_canonical_chain_tip().unwrap();
+        let b = get_burnchain(path, pox_consts.clone());
+
+        let (good_op, block) = if ix == 0 {
+            make_genesis_block_with_recipients(
+                &sort_db,
+                &mut chainstate,
+                &parent,
+                miner,
+                10000,
+                vrf_key,
+                ix as u32,
+                None,
+            )
+        } else {
+            make_stacks_block_with_recipients(
+                &sort_db,
+                &mut chainstate,
+                &b,
+                &parent,
+                burnchain_tip.block_height,
+                miner,
+                1000,
+                vrf_key,
+                ix as u32,
+                None,
+            )
+        };
+
+        let expected_winner = good_op.txid();
+        let mut ops = vec![good_op];
+
+        if ix == 0 {
+            // add a pre-stack-stx op
+            ops.push(BlockstackOperationType::PreStx(PreStxOp {
+                output: stacker.clone(),
+                txid: next_txid(),
+                vtxindex: 5,
+                block_height: 0,
+                burn_header_hash: BurnchainHeaderHash([0; 32]),
+            }));
+        } else if ix == 1 || ix == 5 {
+            // the first one lands before Stacks 2.05 and should be rejected
+            let txid = next_txid();
+            delegate_txids.push(txid.clone());
+            ops.push(BlockstackOperationType::DelegateStx(DelegateStxOp {
+                sender: stacker.clone(),
+                delegate_to: delegate.clone(),
+                reward_addr: Some((1, PoxAddress::Standard(rewards.clone()))),
+                delegated_ustx: delegated_amt,
+                until_burn_height: Some(100),
+                txid,
+                vtxindex: 5,
+                block_height: 0,
+                burn_header_hash: BurnchainHeaderHash([0; 32]),
+            }));
+        }
+
+        let burnchain_tip = burnchain.get_canonical_chain_tip().unwrap();
+        produce_burn_block(
+            &mut burnchain,
+            &burnchain_tip.block_hash,
+            ops,
+            vec![].iter_mut(),
+        );
+        // handle the sortition
+        coord.handle_new_burnchain_block().unwrap();
+
+        let tip = SortitionDB::get_canonical_burn_chain_tip(sort_db.conn()).unwrap();
+        assert_eq!(&tip.winning_block_txid, &expected_winner);
+
+        // load the block into staging
+        let block_hash = block.header.block_hash();
+
+        assert_eq!(&tip.winning_stacks_block_hash, &block_hash);
+        stacks_blocks.push((tip.sortition_id.clone(), block.clone()));
+
+        preprocess_block(&mut chainstate, &sort_db, &tip, block);
+
+        // handle the stacks block
+        coord.handle_new_stacks_block().unwrap();
+    }
+
+    assert!(
+        SortitionDB::get_burnchain_transaction(sort_db.conn(), &delegate_txids[0])
+            .unwrap()
+            .is_none(),
+        "DelegateStx op should be rejected before Stacks 2.05"
+    );
+    assert!(
+        SortitionDB::get_burnchain_transaction(sort_db.conn(), &delegate_txids[1])
+            .unwrap()
+            .is_some(),
+        "DelegateStx op should be accepted in Stacks 2.05"
+    );
+
+    let stacks_tip = SortitionDB::get_canonical_stacks_chain_tip_hash(sort_db.conn()).unwrap();
+    let mut chainstate = get_chainstate(path);
+    let delegation = chainstate
+        .with_read_only_clarity_tx(
+            &sort_db.index_conn(),
+            &StacksBlockId::new(&stacks_tip.0, &stacks_tip.1),
+            |conn| {
+                conn.with_readonly_clarity_env(
+                    false,
+                    PrincipalData::from(stacker.clone()),
+                    LimitedCostTracker::new_free(),
+                    |env| {
+                        env.eval_read_only(
+                            &boot_code_id("pox", false),
+                            &format!("(map-get? delegation-state {{ stacker: '{} }})", &stacker),
+                        )
+                    },
+                )
+                .unwrap()
+            },
+        )
+        .unwrap()
+        .expect_optional()
+        .expect("Stacker should have delegated its STX")
+        .expect_tuple();
+
+    assert_eq!(
+        delegation.get("amount-ustx").unwrap(),
+        &Value::UInt(delegated_amt)
+    );
+    assert_eq!(
+        delegation.get("delegated-to").unwrap(),
+        &Value::Principal(PrincipalData::from(delegate.clone()))
+    );
+    assert_eq!(
+        delegation.get("until-burn-ht").unwrap(),
+        &Value::some(Value::UInt(100)).unwrap()
+    );
+    assert_eq!(
+        delegation.get("pox-addr").unwrap(),
+        &Value::some(
+            PoxAddress::Standard(rewards.clone())
+                .as_clarity_tuple()
+                .into()
+        )
+        .unwrap()
+    );
+}
+
 #[test]
 fn test_initial_coinbase_reward_distributions() {
     let path = "/tmp/initial_coinbase_reward_distributions";
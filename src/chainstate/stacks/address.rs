@@ -21,8 +21,10 @@ use std::{fmt, io};
 
 use crate::burnchains::bitcoin::address::{
     address_type_to_version_byte, to_b58_version_byte, to_c32_version_byte,
-    version_byte_to_address_type, BitcoinAddress, BitcoinAddressType,
+    version_byte_to_address_type, BitcoinAddress, BitcoinAddressType, LegacyBitcoinAddress,
+    SegwitBitcoinAddress,
 };
+use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::{Address, BurnchainSigner, PublicKey};
 use crate::chainstate::stacks::StacksPublicKey;
 use crate::chainstate::stacks::{
@@ -31,7 +33,7 @@ use crate::chainstate::stacks::{
 };
 use crate::net::Error as net_error;
 use clarity::vm::types::{PrincipalData, StandardPrincipalData};
-use clarity::vm::types::{TupleData, Value};
+use clarity::vm::types::{SequenceData, TupleData, Value};
 use stacks_common::address::b58;
 use stacks_common::address::c32::c32_address;
 use stacks_common::address::c32::c32_address_decode;
@@ -52,7 +54,7 @@ pub trait StacksAddressExtensions {
     fn to_bitcoin_tx_out(&self, value: u64) -> TxOut;
     fn as_clarity_tuple(&self) -> TupleData;
     fn to_b58(self) -> String;
-    fn from_bitcoin_address(addr: &BitcoinAddress) -> StacksAddress;
+    fn from_legacy_bitcoin_address(addr: &LegacyBitcoinAddress) -> StacksAddress;
     fn is_boot_code_addr(&self) -> bool;
 }
 
@@ -71,9 +73,11 @@ impl StacksAddressExtensions for StacksAddress {
             .0;
         match btc_addr_type {
             BitcoinAddressType::PublicKeyHash => {
-                BitcoinAddress::to_p2pkh_tx_out(&self.bytes, value)
+                LegacyBitcoinAddress::to_p2pkh_tx_out(&self.bytes, value)
+            }
+            BitcoinAddressType::ScriptHash => {
+                LegacyBitcoinAddress::to_p2sh_tx_out(&self.bytes, value)
             }
-            BitcoinAddressType::ScriptHash => BitcoinAddress::to_p2sh_tx_out(&self.bytes, value),
         }
     }
 
@@ -88,8 +92,8 @@ impl StacksAddressExtensions for StacksAddress {
         .expect("BUG: StacksAddress byte representation does not fit in Clarity Value")
     }
 
-    /// Convert from a Bitcoin address
-    fn from_bitcoin_address(addr: &BitcoinAddress) -> StacksAddress {
+    /// Convert from a legacy (p2pkh or p2sh) Bitcoin address
+    fn from_legacy_bitcoin_address(addr: &LegacyBitcoinAddress) -> StacksAddress {
         let btc_version = address_type_to_version_byte(addr.addrtype, addr.network_id);
 
         // should not fail by construction
@@ -112,6 +116,290 @@ impl StacksAddressExtensions for StacksAddress {
     }
 }
 
+/// PoX address version byte for a native segwit v0 pubkey hash (p2wpkh) address
+pub const POX_ADDRESS_VERSION_P2WPKH: u8 = 0x04;
+/// PoX address version byte for a native segwit v0 script hash (p2wsh) address
+pub const POX_ADDRESS_VERSION_P2WSH: u8 = 0x05;
+/// PoX address version byte for a segwit v1 (taproot) address
+pub const POX_ADDRESS_VERSION_P2TR: u8 = 0x06;
+
+/// PoX address types whose hash bytes are 20 bytes long
+#[repr(u8)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Serialize, Deserialize, Hash, PartialOrd, Ord)]
+pub enum PoxAddressType20 {
+    P2WPKH = POX_ADDRESS_VERSION_P2WPKH,
+}
+
+/// PoX address types whose hash bytes are 32 bytes long
+#[repr(u8)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Serialize, Deserialize, Hash, PartialOrd, Ord)]
+pub enum PoxAddressType32 {
+    P2WSH = POX_ADDRESS_VERSION_P2WSH,
+    P2TR = POX_ADDRESS_VERSION_P2TR,
+}
+
+/// An address that can receive PoX payouts.
+/// `Standard` addresses are the p2pkh and p2sh(-wrapped segwit) addresses that the PoX contract
+/// has always accepted, and are carried as Stacks addresses exactly as before.  The other
+/// variants are native segwit addresses, which are identified by their PoX version byte
+/// (0x04-0x06) and whether or not they are mainnet addresses.  The PoX contract does not accept
+/// these versions yet, so they never show up in a reward set.
+/// This is serialized untagged, so JSON written for a `StacksAddress` still decodes as a
+/// `Standard` PoX address.
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Serialize, Deserialize, Hash)]
+#[serde(untagged)]
+pub enum PoxAddress {
+    Standard(StacksAddress),
+    Addr20(bool, PoxAddressType20, [u8; 20]),
+    Addr32(bool, PoxAddressType32, [u8; 32]),
+}
+
+impl PartialOrd for PoxAddress {
+    fn partial_cmp(&self, other: &PoxAddress) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PoxAddress {
+    fn cmp(&self, other: &PoxAddress) -> Ordering {
+        match (self, other) {
+            (PoxAddress::Standard(a), PoxAddress::Standard(b)) => a.cmp(b),
+            _ => (self.version(), self.bytes()).cmp(&(other.version(), other.bytes())),
+        }
+    }
+}
+
+impl fmt::Display for PoxAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_db_string())
+    }
+}
+
+impl From<StacksAddress> for PoxAddress {
+    fn from(addr: StacksAddress) -> PoxAddress {
+        PoxAddress::Standard(addr)
+    }
+}
+
+impl PoxAddress {
+    /// The burn address, as a PoX address
+    pub fn standard_burn_address(mainnet: bool) -> PoxAddress {
+        PoxAddress::Standard(StacksAddress::burn_address(mainnet))
+    }
+
+    pub fn is_mainnet(&self) -> bool {
+        match self {
+            PoxAddress::Standard(addr) => addr.is_mainnet(),
+            PoxAddress::Addr20(mainnet, ..) | PoxAddress::Addr32(mainnet, ..) => *mainnet,
+        }
+    }
+
+    /// Only the standard burn address counts as a burn; a zeroed segwit program does not, so
+    /// that there is exactly one way to burn in a block-commit.
+    pub fn is_burn(&self) -> bool {
+        match self {
+            PoxAddress::Standard(addr) => addr.is_burn(),
+            _ => false,
+        }
+    }
+
+    /// The PoX contract's version byte for this address
+    pub fn version(&self) -> u8 {
+        match self {
+            PoxAddress::Standard(addr) => AddressHashMode::from_version(addr.version) as u8,
+            PoxAddress::Addr20(_, addrtype, _) => *addrtype as u8,
+            PoxAddress::Addr32(_, addrtype, _) => *addrtype as u8,
+        }
+    }
+
+    /// The PoX contract's hash bytes for this address
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            PoxAddress::Standard(addr) => addr.bytes.0.to_vec(),
+            PoxAddress::Addr20(_, _, bytes) => bytes.to_vec(),
+            PoxAddress::Addr32(_, _, bytes) => bytes.to_vec(),
+        }
+    }
+
+    /// If this is a standard address, get it as a Stacks address
+    pub fn as_standard(&self) -> Option<&StacksAddress> {
+        match self {
+            PoxAddress::Standard(addr) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Convert from a Bitcoin output address
+    pub fn from_bitcoin_address(addr: &BitcoinAddress) -> PoxAddress {
+        match addr {
+            BitcoinAddress::Legacy(addr) => {
+                PoxAddress::Standard(StacksAddress::from_legacy_bitcoin_address(addr))
+            }
+            BitcoinAddress::Segwit(addr) => {
+                let mainnet = addr.network_id() == BitcoinNetworkType::Mainnet;
+                match addr {
+                    SegwitBitcoinAddress::P2WPKH(_, bytes) => {
+                        PoxAddress::Addr20(mainnet, PoxAddressType20::P2WPKH, *bytes)
+                    }
+                    SegwitBitcoinAddress::P2WSH(_, bytes) => {
+                        PoxAddress::Addr32(mainnet, PoxAddressType32::P2WSH, *bytes)
+                    }
+                    SegwitBitcoinAddress::P2TR(_, bytes) => {
+                        PoxAddress::Addr32(mainnet, PoxAddressType32::P2TR, *bytes)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Convert to a Bitcoin address on the given network.
+    /// Returns None if this address belongs to the other network.
+    pub fn to_bitcoin_address(&self, network_id: BitcoinNetworkType) -> Option<BitcoinAddress> {
+        if self.is_mainnet() != (network_id == BitcoinNetworkType::Mainnet) {
+            return None;
+        }
+        match self {
+            PoxAddress::Standard(addr) => {
+                let btc_version = to_b58_version_byte(addr.version)?;
+                let (addrtype, _) = version_byte_to_address_type(btc_version)?;
+                BitcoinAddress::from_bytes(network_id, addrtype, addr.bytes.as_bytes()).ok()
+            }
+            PoxAddress::Addr20(_, PoxAddressType20::P2WPKH, bytes) => Some(BitcoinAddress::Segwit(
+                SegwitBitcoinAddress::P2WPKH(network_id, *bytes),
+            )),
+            PoxAddress::Addr32(_, PoxAddressType32::P2WSH, bytes) => Some(BitcoinAddress::Segwit(
+                SegwitBitcoinAddress::P2WSH(network_id, *bytes),
+            )),
+            PoxAddress::Addr32(_, PoxAddressType32::P2TR, bytes) => Some(BitcoinAddress::Segwit(
+                SegwitBitcoinAddress::P2TR(network_id, *bytes),
+            )),
+        }
+    }
+
+    /// Render as the Bitcoin address a payout to this PoX address is sent to.
+    /// Native segwit addresses use the mainnet or testnet human-readable part.
+    pub fn to_burnchain_repr(&self) -> String {
+        let network_id = if self.is_mainnet() {
+            BitcoinNetworkType::Mainnet
+        } else {
+            BitcoinNetworkType::Testnet
+        };
+        match self {
+            PoxAddress::Standard(addr) => addr.to_b58(),
+            _ => self
+                .to_bitcoin_address(network_id)
+                .expect("BUG: segwit PoX address does not match its own network")
+                .to_string(),
+        }
+    }
+
+    /// Make a Bitcoin output paying `value` to this address
+    pub fn to_bitcoin_tx_out(&self, value: u64) -> TxOut {
+        match self {
+            PoxAddress::Standard(addr) => addr.to_bitcoin_tx_out(value),
+            _ => {
+                let network_id = if self.is_mainnet() {
+                    BitcoinNetworkType::Mainnet
+                } else {
+                    BitcoinNetworkType::Testnet
+                };
+                self.to_bitcoin_address(network_id)
+                    .expect("BUG: segwit PoX address does not match its own network")
+                    .to_tx_out(value)
+            }
+        }
+    }
+
+    /// Representation in the sortition DB.  Standard addresses are stored as c32 strings, as
+    /// they always have been; native segwit addresses are stored as bech32 strings.
+    pub fn to_db_string(&self) -> String {
+        match self {
+            PoxAddress::Standard(addr) => addr.to_string(),
+            _ => self.to_burnchain_repr(),
+        }
+    }
+
+    pub fn from_db_string(s: &str) -> Option<PoxAddress> {
+        if let Some(addr) = StacksAddress::from_string(s) {
+            return Some(PoxAddress::Standard(addr));
+        }
+        let addr = BitcoinAddress::from_bech32(s).ok()?;
+        Some(PoxAddress::from_bitcoin_address(&addr))
+    }
+
+    /// Clarity tuple representation, as used by the PoX contract
+    pub fn as_clarity_tuple(&self) -> TupleData {
+        let version = Value::buff_from_byte(self.version());
+        let hashbytes = Value::buff_from(self.bytes())
+            .expect("BUG: PoX address hash bytes do not fit in Clarity Value");
+        TupleData::from_data(vec![
+            ("version".into(), version),
+            ("hashbytes".into(), hashbytes),
+        ])
+        .expect("BUG: PoX address byte representation does not fit in Clarity Value")
+    }
+
+    /// Decode the PoX contract's (version, hashbytes) tuple.
+    /// Versions 0x00-0x03 take 20 hash bytes and become standard addresses, 0x04 takes 20 hash
+    /// bytes, and 0x05 and 0x06 take 32 hash bytes.  Returns None for anything else.
+    pub fn try_from_pox_tuple(mainnet: bool, tuple: &TupleData) -> Option<PoxAddress> {
+        let version = match tuple.get("version").ok()? {
+            Value::Sequence(SequenceData::Buffer(buff)) if buff.data.len() == 1 => buff.data[0],
+            _ => {
+                return None;
+            }
+        };
+        let hashbytes = match tuple.get("hashbytes").ok()? {
+            Value::Sequence(SequenceData::Buffer(buff)) => &buff.data,
+            _ => {
+                return None;
+            }
+        };
+
+        match version {
+            POX_ADDRESS_VERSION_P2WPKH => {
+                let mut bytes = [0u8; 20];
+                if hashbytes.len() != bytes.len() {
+                    return None;
+                }
+                bytes.copy_from_slice(hashbytes);
+                Some(PoxAddress::Addr20(mainnet, PoxAddressType20::P2WPKH, bytes))
+            }
+            POX_ADDRESS_VERSION_P2WSH | POX_ADDRESS_VERSION_P2TR => {
+                let mut bytes = [0u8; 32];
+                if hashbytes.len() != bytes.len() {
+                    return None;
+                }
+                bytes.copy_from_slice(hashbytes);
+                let addrtype = if version == POX_ADDRESS_VERSION_P2WSH {
+                    PoxAddressType32::P2WSH
+                } else {
+                    PoxAddressType32::P2TR
+                };
+                Some(PoxAddress::Addr32(mainnet, addrtype, bytes))
+            }
+            v => {
+                let hash_mode = AddressHashMode::try_from(v).ok()?;
+                // the PoX contract pads short hash bytes with zeros
+                if hashbytes.len() > 20 {
+                    return None;
+                }
+                let mut bytes = [0u8; 20];
+                bytes[0..hashbytes.len()].copy_from_slice(hashbytes);
+                let version = if mainnet {
+                    hash_mode.to_version_mainnet()
+                } else {
+                    hash_mode.to_version_testnet()
+                };
+                Some(PoxAddress::Standard(StacksAddress::new(
+                    version,
+                    Hash160(bytes),
+                )))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::chainstate::stacks::*;
@@ -268,4 +556,115 @@ mod test {
                                                                                                  PubKey::from_hex("04ef2340518b5867b23598a9cf74611f8b98064f7d55cdb8c107c67b5efcbc5c771f112f919b00a6c6c5f51f7c63e1762fe9fac9b66ec75a053db7f51f4a52712b").unwrap()]),
                    None);
     }
+
+    #[test]
+    fn test_pox_address_from_tuple() {
+        let make_tuple = |version: u8, hashbytes: Vec<u8>| {
+            TupleData::from_data(vec![
+                ("version".into(), Value::buff_from_byte(version)),
+                ("hashbytes".into(), Value::buff_from(hashbytes).unwrap()),
+            ])
+            .unwrap()
+        };
+
+        // standard addresses come back as Stacks addresses, padded if short
+        let addr = PoxAddress::try_from_pox_tuple(true, &make_tuple(0x00, vec![0x01; 20])).unwrap();
+        assert_eq!(
+            addr,
+            PoxAddress::Standard(StacksAddress::new(
+                C32_ADDRESS_VERSION_MAINNET_SINGLESIG,
+                Hash160([0x01; 20])
+            ))
+        );
+        let addr =
+            PoxAddress::try_from_pox_tuple(false, &make_tuple(0x01, vec![0x01; 19])).unwrap();
+        let mut padded = [0x01; 20];
+        padded[19] = 0x00;
+        assert_eq!(
+            addr,
+            PoxAddress::Standard(StacksAddress::new(
+                C32_ADDRESS_VERSION_TESTNET_MULTISIG,
+                Hash160(padded)
+            ))
+        );
+
+        // native segwit addresses
+        assert_eq!(
+            PoxAddress::try_from_pox_tuple(true, &make_tuple(0x04, vec![0x02; 20])).unwrap(),
+            PoxAddress::Addr20(true, PoxAddressType20::P2WPKH, [0x02; 20])
+        );
+        assert_eq!(
+            PoxAddress::try_from_pox_tuple(false, &make_tuple(0x05, vec![0x03; 32])).unwrap(),
+            PoxAddress::Addr32(false, PoxAddressType32::P2WSH, [0x03; 32])
+        );
+        assert_eq!(
+            PoxAddress::try_from_pox_tuple(true, &make_tuple(0x06, vec![0x04; 32])).unwrap(),
+            PoxAddress::Addr32(true, PoxAddressType32::P2TR, [0x04; 32])
+        );
+
+        // wrong lengths and unknown versions
+        assert!(PoxAddress::try_from_pox_tuple(true, &make_tuple(0x04, vec![0x02; 32])).is_none());
+        assert!(PoxAddress::try_from_pox_tuple(true, &make_tuple(0x05, vec![0x02; 20])).is_none());
+        assert!(PoxAddress::try_from_pox_tuple(true, &make_tuple(0x06, vec![0x02; 20])).is_none());
+        assert!(PoxAddress::try_from_pox_tuple(true, &make_tuple(0x00, vec![0x02; 21])).is_none());
+        assert!(PoxAddress::try_from_pox_tuple(true, &make_tuple(0x07, vec![0x02; 32])).is_none());
+
+        // the tuple representation round-trips
+        for addr in [
+            PoxAddress::Addr20(true, PoxAddressType20::P2WPKH, [0x02; 20]),
+            PoxAddress::Addr32(false, PoxAddressType32::P2TR, [0x04; 32]),
+            PoxAddress::standard_burn_address(true),
+        ] {
+            assert_eq!(
+                PoxAddress::try_from_pox_tuple(addr.is_mainnet(), &addr.as_clarity_tuple()),
+                Some(addr)
+            );
+        }
+    }
+
+    #[test]
+    fn test_pox_address_encodings() {
+        let standard = PoxAddress::Standard(StacksAddress::new(
+            C32_ADDRESS_VERSION_TESTNET_SINGLESIG,
+            Hash160([0x01; 20]),
+        ));
+        let p2wpkh = PoxAddress::Addr20(true, PoxAddressType20::P2WPKH, [0x02; 20]);
+        let p2tr = PoxAddress::Addr32(false, PoxAddressType32::P2TR, [0x04; 32]);
+
+        // standard addresses are stored exactly as before
+        assert_eq!(
+            standard.to_db_string(),
+            standard.as_standard().unwrap().to_string()
+        );
+        assert_eq!(
+            serde_json::to_string(&standard).unwrap(),
+            serde_json::to_string(standard.as_standard().unwrap()).unwrap()
+        );
+        let from_json: PoxAddress =
+            serde_json::from_str(&serde_json::to_string(standard.as_standard().unwrap()).unwrap())
+                .unwrap();
+        assert_eq!(from_json, standard);
+
+        for addr in [standard, p2wpkh, p2tr] {
+            assert_eq!(PoxAddress::from_db_string(&addr.to_db_string()), Some(addr));
+            let btc_addr = BitcoinAddress::from_scriptpubkey(
+                if addr.is_mainnet() {
+                    BitcoinNetworkType::Mainnet
+                } else {
+                    BitcoinNetworkType::Testnet
+                },
+                &addr.to_bitcoin_tx_out(1000).script_pubkey.to_bytes(),
+            )
+            .unwrap();
+            assert_eq!(PoxAddress::from_bitcoin_address(&btc_addr), addr);
+            assert_eq!(btc_addr.to_string(), addr.to_burnchain_repr());
+        }
+
+        assert!(p2wpkh.to_burnchain_repr().starts_with("bc1q"));
+        assert!(p2tr.to_burnchain_repr().starts_with("tb1p"));
+
+        // segwit addresses are never burns
+        assert!(PoxAddress::standard_burn_address(false).is_burn());
+        assert!(!PoxAddress::Addr20(false, PoxAddressType20::P2WPKH, [0x00; 20]).is_burn());
+    }
 }
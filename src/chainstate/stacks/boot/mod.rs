@@ -44,7 +44,7 @@ use clarity::vm::types::{
 use stacks_common::address::AddressHashMode;
 use stacks_common::util::hash::Hash160;
 
-use crate::chainstate::stacks::address::StacksAddressExtensions;
+use crate::chainstate::stacks::address::{PoxAddress, StacksAddressExtensions};
 use crate::clarity_vm::database::HeadersDBConn;
 use crate::types;
 use crate::types::chainstate::StacksAddress;
@@ -112,31 +112,6 @@ pub fn make_contract_id(addr: &StacksAddress, name: &str) -> QualifiedContractId
     )
 }
 
-/// Extract a PoX address from its tuple representation
-fn tuple_to_pox_addr(tuple_data: TupleData) -> (AddressHashMode, Hash160) {
-    let version_value = tuple_data
-        .get("version")
-        .expect("FATAL: no 'version' field in pox-addr")
-        .to_owned();
-    let hashbytes_value = tuple_data
-        .get("hashbytes")
-        .expect("FATAL: no 'hashbytes' field in pox-addr")
-        .to_owned();
-
-    let version_u8 = version_value.expect_buff_padded(1, 0)[0];
-    let version: AddressHashMode = version_u8
-        .try_into()
-        .expect("FATAL: PoX version is not a supported version byte");
-
-    let hashbytes_vec = hashbytes_value.expect_buff_padded(20, 0);
-
-    let mut hashbytes_20 = [0u8; 20];
-    hashbytes_20.copy_from_slice(&hashbytes_vec[0..20]);
-    let hashbytes = Hash160(hashbytes_20);
-
-    (version, hashbytes)
-}
-
 impl StacksChainState {
     fn eval_boot_code_read_only(
         &mut self,
@@ -254,11 +229,11 @@ impl StacksChainState {
     ///   are summed.
     pub fn make_reward_set(
         threshold: u128,
-        mut addresses: Vec<(StacksAddress, u128)>,
-    ) -> Vec<StacksAddress> {
+        mut addresses: Vec<(PoxAddress, u128)>,
+    ) -> Vec<PoxAddress> {
         let mut reward_set = vec![];
         // the way that we sum addresses relies on sorting.
-        addresses.sort_by_key(|k| k.0.bytes.0);
+        addresses.sort_by_key(|k| k.0.bytes());
         while let Some((address, mut stacked_amt)) = addresses.pop() {
             // peak at the next address in the set, and see if we need to sum
             while addresses.last().map(|x| &x.0) == Some(&address) {
@@ -273,7 +248,7 @@ impl StacksChainState {
                 .expect("CORRUPTION: Stacker claimed > u32::max() reward slots");
             info!(
                 "Slots taken by {} = {}, on stacked_amt = {}, threshold = {}",
-                &address.clone().to_b58(),
+                &address.to_burnchain_repr(),
                 slots_taken,
                 stacked_amt,
                 threshold
@@ -307,7 +282,7 @@ impl StacksChainState {
 
     pub fn get_reward_threshold_and_participation(
         pox_settings: &PoxConstants,
-        addresses: &[(StacksAddress, u128)],
+        addresses: &[(PoxAddress, u128)],
         liquid_ustx: u128,
     ) -> (u128, u128) {
         let participation = addresses
@@ -345,7 +320,7 @@ impl StacksChainState {
         sortdb: &SortitionDB,
         current_burn_height: u64,
         block_id: &StacksBlockId,
-    ) -> Result<Vec<(StacksAddress, u128)>, Error> {
+    ) -> Result<Vec<(PoxAddress, u128)>, Error> {
         let reward_cycle = burnchain
             .block_height_to_reward_cycle(current_burn_height)
             .ok_or(Error::PoxNoRewardCycle)?;
@@ -397,7 +372,8 @@ impl StacksChainState {
                 .to_owned()
                 .expect_tuple();
 
-            let (hash_mode, hash) = tuple_to_pox_addr(pox_addr_tuple);
+            let pox_addr = PoxAddress::try_from_pox_tuple(self.mainnet, &pox_addr_tuple)
+                .expect("FATAL: PoX address is not a supported version and hash bytes");
 
             let total_ustx = tuple_data
                 .get("total-ustx")
@@ -405,17 +381,12 @@ impl StacksChainState {
                 .to_owned()
                 .expect_u128();
 
-            let version = match self.mainnet {
-                true => hash_mode.to_version_mainnet(),
-                false => hash_mode.to_version_testnet(),
-            };
-
             test_debug!(
                 "PoX reward address (for {} ustx): {:?}",
                 total_ustx,
-                &StacksAddress::new(version, hash)
+                &pox_addr
             );
-            ret.push((StacksAddress::new(version, hash), total_ustx));
+            ret.push((pox_addr, total_ustx));
         }
 
         Ok(ret)
@@ -462,19 +433,27 @@ pub mod test {
         let threshold = 1_000;
         let addresses = vec![
             (
-                StacksAddress::from_string("STVK1K405H6SK9NKJAP32GHYHDJ98MMNP8Y6Z9N0").unwrap(),
+                PoxAddress::Standard(
+                    StacksAddress::from_string("STVK1K405H6SK9NKJAP32GHYHDJ98MMNP8Y6Z9N0").unwrap(),
+                ),
                 1500,
             ),
             (
-                StacksAddress::from_string("ST76D2FMXZ7D2719PNE4N71KPSX84XCCNCMYC940").unwrap(),
+                PoxAddress::Standard(
+                    StacksAddress::from_string("ST76D2FMXZ7D2719PNE4N71KPSX84XCCNCMYC940").unwrap(),
+                ),
                 500,
             ),
             (
-                StacksAddress::from_string("STVK1K405H6SK9NKJAP32GHYHDJ98MMNP8Y6Z9N0").unwrap(),
+                PoxAddress::Standard(
+                    StacksAddress::from_string("STVK1K405H6SK9NKJAP32GHYHDJ98MMNP8Y6Z9N0").unwrap(),
+                ),
                 1500,
             ),
             (
-                StacksAddress::from_string("ST76D2FMXZ7D2719PNE4N71KPSX84XCCNCMYC940").unwrap(),
+                PoxAddress::Standard(
+                    StacksAddress::from_string("ST76D2FMXZ7D2719PNE4N71KPSX84XCCNCMYC940").unwrap(),
+                ),
                 400,
             ),
         ];
@@ -502,7 +481,7 @@ pub mod test {
         assert_eq!(
             StacksChainState::get_reward_threshold_and_participation(
                 &test_pox_constants,
-                &[(rand_addr(), liquid)],
+                &[(PoxAddress::Standard(rand_addr()), liquid)],
                 liquid
             )
             .0,
@@ -524,7 +503,7 @@ pub mod test {
         assert_eq!(
             StacksChainState::get_reward_threshold_and_participation(
                 &test_pox_constants,
-                &[(rand_addr(), liquid / 4)],
+                &[(PoxAddress::Standard(rand_addr()), liquid / 4)],
                 liquid
             )
             .0,
@@ -535,8 +514,11 @@ pub mod test {
             StacksChainState::get_reward_threshold_and_participation(
                 &test_pox_constants,
                 &[
-                    (rand_addr(), liquid / 4),
-                    (rand_addr(), 10_000_000 * (MICROSTACKS_PER_STACKS as u128))
+                    (PoxAddress::Standard(rand_addr()), liquid / 4),
+                    (
+                        PoxAddress::Standard(rand_addr()),
+                        10_000_000 * (MICROSTACKS_PER_STACKS as u128)
+                    )
                 ],
                 liquid
             )
@@ -549,8 +531,11 @@ pub mod test {
             StacksChainState::get_reward_threshold_and_participation(
                 &test_pox_constants,
                 &[
-                    (rand_addr(), liquid / 4),
-                    (rand_addr(), (MICROSTACKS_PER_STACKS as u128))
+                    (PoxAddress::Standard(rand_addr()), liquid / 4),
+                    (
+                        PoxAddress::Standard(rand_addr()),
+                        (MICROSTACKS_PER_STACKS as u128)
+                    )
                 ],
                 liquid
             )
@@ -562,7 +547,7 @@ pub mod test {
         assert_eq!(
             StacksChainState::get_reward_threshold_and_participation(
                 &test_pox_constants,
-                &[(rand_addr(), liquid)],
+                &[(PoxAddress::Standard(rand_addr()), liquid)],
                 liquid
             )
             .0,
@@ -729,7 +714,13 @@ pub mod test {
         let data = data.expect_tuple();
 
         let amount_ustx = data.get("amount-ustx").unwrap().to_owned().expect_u128();
-        let pox_addr = tuple_to_pox_addr(data.get("pox-addr").unwrap().to_owned().expect_tuple());
+        let pox_addr = PoxAddress::try_from_pox_tuple(
+            false,
+            &data.get("pox-addr").unwrap().to_owned().expect_tuple(),
+        )
+        .and_then(|addr| addr.as_standard().cloned())
+        .map(|addr| (AddressHashMode::from_version(addr.version), addr.bytes))
+        .expect("FATAL: stacker's pox-addr is not a standard address");
         let lock_period = data.get("lock-period").unwrap().to_owned().expect_u128();
         let first_reward_cycle = data
             .get("first-reward-cycle")
@@ -1039,7 +1030,17 @@ pub mod test {
         let burn_block_height = get_par_burn_block_height(state, block_id);
         state
             .get_reward_addresses(burnchain, sortdb, burn_block_height, block_id)
-            .and_then(|mut addrs| {
+            .and_then(|addrs| {
+                let mut addrs: Vec<_> = addrs
+                    .into_iter()
+                    .map(|(addr, amt)| {
+                        let addr = addr
+                            .as_standard()
+                            .cloned()
+                            .expect("FATAL: test reward address is not a standard address");
+                        (addr, amt)
+                    })
+                    .collect();
                 addrs.sort_by_key(|k| k.0.bytes.0);
                 Ok(addrs)
             })
@@ -79,7 +79,7 @@ use stacks_common::util::hash::to_hex;
 use stacks_common::util::retry::BoundReader;
 
 use crate::chainstate::coordinator::BlockEventDispatcher;
-use crate::chainstate::stacks::address::StacksAddressExtensions;
+use crate::chainstate::stacks::address::{PoxAddress, StacksAddressExtensions};
 use crate::chainstate::stacks::StacksBlockHeader;
 use crate::chainstate::stacks::StacksMicroblockHeader;
 use crate::monitoring::{set_last_block_transaction_count, set_last_execution_cost_observed};
@@ -202,9 +202,9 @@ impl BlockEventDispatcher for DummyEventDispatcher {
         &self,
         _burn_block: &BurnchainHeaderHash,
         _burn_block_height: u64,
-        _rewards: Vec<(StacksAddress, u64)>,
+        _rewards: Vec<(PoxAddress, u64)>,
         _burns: u64,
-        _slot_holders: Vec<StacksAddress>,
+        _slot_holders: Vec<PoxAddress>,
     ) {
         assert!(
             false,
@@ -4690,6 +4690,111 @@ impl StacksChainState {
         all_receipts
     }
 
+    /// Process any delegate-STX bitcoin operations
+    ///  that haven't been processed in this Stacks fork yet.
+    /// These are only applied in Stacks 2.05 and later.
+    pub fn process_delegate_ops(
+        clarity_tx: &mut ClarityTx,
+        operations: Vec<DelegateStxOp>,
+    ) -> Vec<StacksTransactionReceipt> {
+        let mut all_receipts = vec![];
+        if operations.is_empty() {
+            return all_receipts;
+        }
+
+        let epoch = clarity_tx.with_clarity_db_readonly(|db| db.get_clarity_epoch_version());
+        if epoch < StacksEpochId::Epoch2_05 {
+            warn!(
+                "Ignoring {} DelegateStx burn ops in epoch {}",
+                operations.len(),
+                epoch
+            );
+            return all_receipts;
+        }
+
+        let mainnet = clarity_tx.config.mainnet;
+        let mut cost_so_far = clarity_tx.cost_so_far();
+        for delegate_stx_op in operations.into_iter() {
+            let DelegateStxOp {
+                sender,
+                delegate_to,
+                reward_addr,
+                delegated_ustx,
+                until_burn_height,
+                txid,
+                burn_header_hash,
+                ..
+            } = delegate_stx_op;
+            let reward_addr_val = match reward_addr {
+                Some((_, addr)) => Value::some(addr.as_clarity_tuple().into()),
+                None => Ok(Value::none()),
+            }
+            .expect("FATAL: could not construct optional PoX address value");
+            let until_burn_height_val = match until_burn_height {
+                Some(height) => Value::some(Value::UInt(u128::from(height))),
+                None => Ok(Value::none()),
+            }
+            .expect("FATAL: could not construct optional burn height value");
+            let result = clarity_tx.connection().as_transaction(|tx| {
+                tx.run_contract_call(
+                    &sender.into(),
+                    &boot_code_id("pox", mainnet),
+                    "delegate-stx",
+                    &[
+                        Value::UInt(delegated_ustx),
+                        Value::Principal(delegate_to.into()),
+                        until_burn_height_val,
+                        reward_addr_val,
+                    ],
+                    |_, _| false,
+                )
+            });
+            match result {
+                Ok((value, _, events)) => {
+                    if let Value::Response(ref resp) = value {
+                        if !resp.committed {
+                            debug!("DelegateStx burn op rejected by PoX contract.";
+                                   "txid" => %txid,
+                                   "burn_block" => %burn_header_hash,
+                                   "contract_call_ecode" => %resp.data);
+                        }
+                        let mut execution_cost = clarity_tx.cost_so_far();
+                        execution_cost
+                            .sub(&cost_so_far)
+                            .expect("BUG: cost declined between executions");
+                        cost_so_far = clarity_tx.cost_so_far();
+
+                        let receipt = StacksTransactionReceipt {
+                            transaction: TransactionOrigin::Burn(txid),
+                            events,
+                            result: value,
+                            post_condition_aborted: false,
+                            stx_burned: 0,
+                            contract_analysis: None,
+                            execution_cost,
+                            microblock_header: None,
+                            tx_index: 0,
+                        };
+
+                        all_receipts.push(receipt);
+                    } else {
+                        unreachable!(
+                            "BUG: Non-response value returned by Delegate STX burnchain op"
+                        )
+                    }
+                }
+                Err(e) => {
+                    info!("DelegateStx burn op processing error.";
+                           "error" => %format!("{:?}", e),
+                           "txid" => %txid,
+                           "burn_block" => %burn_header_hash);
+                }
+            };
+        }
+
+        all_receipts
+    }
+
     /// Process any STX transfer bitcoin operations
     ///  that haven't been processed in this Stacks fork yet.
     pub fn process_transfer_ops(
@@ -4951,6 +5056,7 @@ impl StacksChainState {
 
         let stacking_burn_ops = SortitionDB::get_stack_stx_ops(conn, &burn_tip)?;
         let transfer_burn_ops = SortitionDB::get_transfer_stx_ops(conn, &burn_tip)?;
+        let delegate_burn_ops = SortitionDB::get_delegate_stx_ops(conn, &burn_tip)?;
 
         // load the execution cost of the parent block if the executor is the follower.
         // otherwise, if the executor is the miner, only load the parent cost if the parent
@@ -5066,7 +5172,7 @@ impl StacksChainState {
         let (applied_epoch_transition, mut tx_receipts) =
             StacksChainState::process_epoch_transition(&mut clarity_tx, burn_tip_height)?;
 
-        // process stacking & transfer operations from bitcoin ops
+        // process stacking, transfer & delegate operations from bitcoin ops
         tx_receipts.extend(StacksChainState::process_stacking_ops(
             &mut clarity_tx,
             stacking_burn_ops,
@@ -5075,6 +5181,10 @@ impl StacksChainState {
             &mut clarity_tx,
             transfer_burn_ops,
         ));
+        tx_receipts.extend(StacksChainState::process_delegate_ops(
+            &mut clarity_tx,
+            delegate_burn_ops,
+        ));
 
         Ok(SetupBlockResult {
             clarity_tx,
@@ -0,0 +1,623 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reconstruct the tree of Stacks blocks that were mined over a range of burnchain blocks, so
+//! operators can see how and where the chain forked.  Used by `stacks-inspect fork-tree`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use rusqlite::types::ToSql;
+
+use crate::burnchains::bitcoin::BitcoinNetworkType;
+use crate::chainstate::burn::db::sortdb::SortitionDB;
+use crate::chainstate::burn::ConsensusHash;
+use crate::chainstate::stacks::db::blocks::StagingBlock;
+use crate::chainstate::stacks::db::StacksChainState;
+use crate::chainstate::stacks::Error;
+use crate::core::EMPTY_MICROBLOCK_PARENT_HASH;
+use crate::util_lib::db::query_rows;
+use crate::util_lib::db::Error as db_error;
+
+use stacks_common::types::chainstate::StacksBlockId;
+
+/// A block-commit that was mined in a sortition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForkTreeCommit {
+    pub txid: String,
+    pub vtxindex: u32,
+    pub sender: String,
+    pub block_hash: String,
+    pub parent_block_ptr: u32,
+    pub parent_vtxindex: u16,
+    pub burn_fee: u64,
+    pub won: bool,
+}
+
+/// A block-commit that was intended for a sortition, but was mined too late to count in it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForkTreeMissedCommit {
+    pub txid: String,
+    pub spent_txid: String,
+    pub spent_output: u32,
+}
+
+/// A stream of microblocks produced by a Stacks block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForkTreeMicroblocks {
+    pub count: u64,
+    pub processed: u64,
+    pub orphaned: u64,
+    /// Highest sequence number in the stream
+    pub tail_seq: Option<u16>,
+}
+
+/// A Stacks block that was stored for a sortition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForkTreeBlock {
+    pub index_block_hash: String,
+    pub block_hash: String,
+    pub consensus_hash: String,
+    pub parent_index_block_hash: String,
+    pub parent_microblock_hash: String,
+    pub parent_microblock_seq: u16,
+    pub height: u64,
+    /// One of "processed", "orphaned" (invalid, or descended from an invalid block), or
+    /// "pending" (not yet processed)
+    pub status: String,
+    pub canonical: bool,
+    pub microblocks: ForkTreeMicroblocks,
+}
+
+/// Everything that happened in one sortition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForkTreeSortition {
+    pub burn_height: u64,
+    pub burn_header_hash: String,
+    pub consensus_hash: String,
+    pub sortition_id: String,
+    pub sortition: bool,
+    pub winning_block_txid: Option<String>,
+    pub winning_block_hash: Option<String>,
+    pub commits: Vec<ForkTreeCommit>,
+    pub missed_commits: Vec<ForkTreeMissedCommit>,
+    pub blocks: Vec<ForkTreeBlock>,
+}
+
+/// The tree of Stacks blocks mined over a range of burnchain blocks on the canonical burnchain fork
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForkTree {
+    pub start_burn_height: u64,
+    pub end_burn_height: u64,
+    pub canonical_tip: String,
+    pub sortitions: Vec<ForkTreeSortition>,
+}
+
+impl ForkTree {
+    /// Load the fork tree for the burnchain blocks in `[start_burn_height, end_burn_height]`.
+    pub fn load(
+        sortdb: &SortitionDB,
+        chainstate: &StacksChainState,
+        start_burn_height: u64,
+        end_burn_height: u64,
+    ) -> Result<ForkTree, Error> {
+        let network = if chainstate.mainnet {
+            BitcoinNetworkType::Mainnet
+        } else {
+            BitcoinNetworkType::Testnet
+        };
+
+        let burn_tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn())?;
+        let end_burn_height = end_burn_height.min(burn_tip.block_height);
+        let ic = sortdb.index_conn();
+
+        let mut sortitions = vec![];
+        for burn_height in start_burn_height..(end_burn_height + 1) {
+            let sn =
+                match SortitionDB::get_ancestor_snapshot(&ic, burn_height, &burn_tip.sortition_id)?
+                {
+                    Some(sn) => sn,
+                    None => continue,
+                };
+
+            let commits = SortitionDB::get_block_commits_by_block(sortdb.conn(), &sn.sortition_id)?
+                .into_iter()
+                .map(|commit| ForkTreeCommit {
+                    txid: commit.txid.to_string(),
+                    vtxindex: commit.vtxindex,
+                    sender: commit.apparent_sender.to_bitcoin_address(network),
+                    block_hash: commit.block_header_hash.to_string(),
+                    parent_block_ptr: commit.parent_block_ptr,
+                    parent_vtxindex: commit.parent_vtxindex,
+                    burn_fee: commit.burn_fee,
+                    won: sn.sortition && commit.txid == sn.winning_block_txid,
+                })
+                .collect();
+
+            let missed_commits =
+                SortitionDB::get_missed_commits_by_intended(sortdb.conn(), &sn.sortition_id)?
+                    .into_iter()
+                    .map(|missed| ForkTreeMissedCommit {
+                        txid: missed.txid.to_string(),
+                        spent_txid: missed.input.0.to_string(),
+                        spent_output: missed.input.1,
+                    })
+                    .collect();
+
+            let mut blocks = vec![];
+            for staging_block in load_staging_blocks(chainstate, &sn.consensus_hash)?.into_iter() {
+                let index_block_hash = StacksBlockId::new(
+                    &staging_block.consensus_hash,
+                    &staging_block.anchored_block_hash,
+                );
+                let parent_index_block_hash = StacksBlockId::new(
+                    &staging_block.parent_consensus_hash,
+                    &staging_block.parent_anchored_block_hash,
+                );
+                let status = if staging_block.orphaned {
+                    "orphaned"
+                } else if staging_block.processed {
+                    "processed"
+                } else {
+                    "pending"
+                };
+                blocks.push(ForkTreeBlock {
+                    index_block_hash: index_block_hash.to_string(),
+                    block_hash: staging_block.anchored_block_hash.to_string(),
+                    consensus_hash: staging_block.consensus_hash.to_string(),
+                    parent_index_block_hash: parent_index_block_hash.to_string(),
+                    parent_microblock_hash: staging_block.parent_microblock_hash.to_string(),
+                    parent_microblock_seq: staging_block.parent_microblock_seq,
+                    height: staging_block.height,
+                    status: status.to_string(),
+                    canonical: false,
+                    microblocks: load_microblock_stream(chainstate, &index_block_hash)?,
+                });
+            }
+
+            sortitions.push(ForkTreeSortition {
+                burn_height: sn.block_height,
+                burn_header_hash: sn.burn_header_hash.to_string(),
+                consensus_hash: sn.consensus_hash.to_string(),
+                sortition_id: sn.sortition_id.to_string(),
+                sortition: sn.sortition,
+                winning_block_txid: if sn.sortition {
+                    Some(sn.winning_block_txid.to_string())
+                } else {
+                    None
+                },
+                winning_block_hash: if sn.sortition {
+                    Some(sn.winning_stacks_block_hash.to_string())
+                } else {
+                    None
+                },
+                commits,
+                missed_commits,
+                blocks,
+            });
+        }
+
+        // mark the canonical chain, walking back from the canonical tip until we pass the lowest
+        // block in the range
+        let (tip_consensus_hash, tip_block_hash) =
+            SortitionDB::get_canonical_stacks_chain_tip_hash(sortdb.conn())?;
+        let canonical_tip = StacksBlockId::new(&tip_consensus_hash, &tip_block_hash);
+        let lowest_height = sortitions
+            .iter()
+            .flat_map(|sortition| sortition.blocks.iter().map(|block| block.height))
+            .min()
+            .unwrap_or(u64::MAX);
+
+        let mut canonical = HashSet::new();
+        let mut cursor = canonical_tip.clone();
+        while let Some(staging_block) =
+            StacksChainState::load_staging_block_info(chainstate.db(), &cursor)?
+        {
+            canonical.insert(cursor.to_string());
+            if staging_block.height <= lowest_height {
+                break;
+            }
+            cursor = StacksBlockId::new(
+                &staging_block.parent_consensus_hash,
+                &staging_block.parent_anchored_block_hash,
+            );
+        }
+
+        for sortition in sortitions.iter_mut() {
+            for block in sortition.blocks.iter_mut() {
+                block.canonical = canonical.contains(&block.index_block_hash);
+            }
+        }
+
+        Ok(ForkTree {
+            start_burn_height,
+            end_burn_height,
+            canonical_tip: canonical_tip.to_string(),
+            sortitions,
+        })
+    }
+
+    /// Render the fork tree as a Graphviz DOT digraph.  Each sortition is a rank, and each Stacks
+    /// block points to its parent.  Canonical blocks are green, orphaned blocks are red, pending
+    /// blocks are grey, and sortition winners that were never stored are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        let mut known_blocks = HashSet::new();
+        for sortition in self.sortitions.iter() {
+            for block in sortition.blocks.iter() {
+                known_blocks.insert(block.index_block_hash.clone());
+            }
+        }
+
+        // every block's parent, so parents outside the range get a node too
+        let mut edges: HashMap<String, Vec<(String, String)>> = HashMap::new();
+
+        let _ = writeln!(dot, "digraph fork_tree {{");
+        let _ = writeln!(dot, "  rankdir=BT;");
+        let _ = writeln!(dot, "  node [shape=box, style=filled, fontname=monospace];");
+
+        for sortition in self.sortitions.iter() {
+            let _ = writeln!(dot, "  subgraph \"burn_{}\" {{", sortition.burn_height);
+            let _ = writeln!(dot, "    rank=same;");
+            let _ = writeln!(
+                dot,
+                "    \"burn_{}\" [shape=plaintext, style=\"\", label=\"burn {}\\n{} commit(s), {} missed\"];",
+                sortition.burn_height,
+                sortition.burn_height,
+                sortition.commits.len(),
+                sortition.missed_commits.len()
+            );
+
+            for block in sortition.blocks.iter() {
+                let color = if block.canonical {
+                    "palegreen"
+                } else if block.status == "orphaned" {
+                    "salmon"
+                } else if block.status == "pending" {
+                    "lightgrey"
+                } else {
+                    "white"
+                };
+                let winner = sortition.winning_block_hash.as_ref() == Some(&block.block_hash);
+                let _ = writeln!(
+                    dot,
+                    "    \"{}\" [fillcolor={}, label=\"{}\\nheight {}\\n{}{}\\nmblocks {}\"];",
+                    block.index_block_hash,
+                    color,
+                    &block.block_hash[0..16],
+                    block.height,
+                    block.status,
+                    if winner { " (winner)" } else { "" },
+                    block.microblocks.count
+                );
+
+                let edge_label =
+                    if block.parent_microblock_hash != EMPTY_MICROBLOCK_PARENT_HASH.to_string() {
+                        format!("mblock seq {}", block.parent_microblock_seq)
+                    } else {
+                        "".to_string()
+                    };
+                edges
+                    .entry(block.parent_index_block_hash.clone())
+                    .or_insert_with(Vec::new)
+                    .push((block.index_block_hash.clone(), edge_label));
+            }
+
+            if let Some(winning_block_hash) = sortition.winning_block_hash.as_ref() {
+                if !sortition
+                    .blocks
+                    .iter()
+                    .any(|block| &block.block_hash == winning_block_hash)
+                {
+                    let _ = writeln!(
+                        dot,
+                        "    \"missing_{}\" [style=dashed, label=\"{}\\nnot stored\"];",
+                        sortition.burn_height,
+                        &winning_block_hash[0..16]
+                    );
+                }
+            }
+            let _ = writeln!(dot, "  }}");
+        }
+
+        let mut parents: Vec<_> = edges.keys().cloned().collect();
+        parents.sort();
+        for parent in parents.iter() {
+            if !known_blocks.contains(parent) {
+                let _ = writeln!(
+                    dot,
+                    "  \"{}\" [style=dotted, label=\"{}\\n(outside range)\"];",
+                    parent,
+                    &parent[0..16]
+                );
+            }
+            for (child, label) in edges[parent].iter() {
+                let _ = writeln!(
+                    dot,
+                    "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                    child, parent, label
+                );
+            }
+        }
+
+        let _ = writeln!(dot, "}}");
+        dot
+    }
+}
+
+/// Load every staging block for a sortition, including orphaned ones
+fn load_staging_blocks(
+    chainstate: &StacksChainState,
+    consensus_hash: &ConsensusHash,
+) -> Result<Vec<StagingBlock>, Error> {
+    let sql = "SELECT * FROM staging_blocks WHERE consensus_hash = ?1 ORDER BY height, anchored_block_hash";
+    let args: &[&dyn ToSql] = &[consensus_hash];
+    query_rows::<StagingBlock, _>(chainstate.db(), sql, args).map_err(Error::DBError)
+}
+
+/// Summarize the microblock stream that the given anchored block produced
+fn load_microblock_stream(
+    chainstate: &StacksChainState,
+    index_block_hash: &StacksBlockId,
+) -> Result<ForkTreeMicroblocks, Error> {
+    let sql = "SELECT COUNT(*), IFNULL(SUM(processed), 0), IFNULL(SUM(orphaned), 0), MAX(sequence) FROM staging_microblocks WHERE index_block_hash = ?1";
+    let args: &[&dyn ToSql] = &[index_block_hash];
+    chainstate
+        .db()
+        .query_row(sql, args, |row| {
+            let count: i64 = row.get(0)?;
+            let processed: i64 = row.get(1)?;
+            let orphaned: i64 = row.get(2)?;
+            let tail_seq: Option<i64> = row.get(3)?;
+            Ok(ForkTreeMicroblocks {
+                count: count as u64,
+                processed: processed as u64,
+                orphaned: orphaned as u64,
+                tail_seq: tail_seq.map(|seq| seq as u16),
+            })
+        })
+        .map_err(|e| Error::DBError(db_error::SqliteError(e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chainstate::stacks::db::blocks::test::{set_block_orphaned, store_staging_block};
+    use crate::chainstate::stacks::StacksBlock;
+    use crate::net::test::*;
+
+    /// Mine `num_blocks` tenures on a linear chain, and return the first burn height of the
+    /// chain, and each tenure's block and the consensus hash of the sortition that chose it
+    fn make_linear_chain(
+        peer: &mut TestPeer,
+        num_blocks: u64,
+    ) -> (u64, Vec<(ConsensusHash, StacksBlock)>) {
+        let first_height =
+            SortitionDB::get_canonical_burn_chain_tip(peer.sortdb.as_ref().unwrap().conn())
+                .unwrap()
+                .block_height;
+
+        let mut blocks = vec![];
+        for _ in 0..num_blocks {
+            let (burn_ops, stacks_block, microblocks) = peer.make_default_tenure();
+            let (_, _, consensus_hash) = peer.next_burnchain_block(burn_ops);
+            peer.process_stacks_epoch_at_tip(&stacks_block, &microblocks);
+            blocks.push((consensus_hash, stacks_block));
+        }
+        (first_height, blocks)
+    }
+
+    /// Store a block in the sortition that chose `sibling`, which forks off of `parent`
+    fn store_fork_block(
+        peer: &mut TestPeer,
+        sibling: &(ConsensusHash, StacksBlock),
+        parent: &(ConsensusHash, StacksBlock),
+    ) -> StacksBlock {
+        let mut fork_block = sibling.1.clone();
+        fork_block.header.parent_block = parent.1.block_hash();
+        fork_block.header.total_work.work = parent.1.header.total_work.work + 1;
+        store_staging_block(
+            &mut peer.stacks_node.as_mut().unwrap().chainstate,
+            &sibling.0,
+            &fork_block,
+            &parent.0,
+            1,
+            2,
+        );
+        fork_block
+    }
+
+    #[test]
+    fn test_fork_tree_linear_chain() {
+        let peer_config = TestPeerConfig::new("test_fork_tree_linear_chain", 2040, 2041);
+        let mut peer = TestPeer::new(peer_config);
+
+        let first_height =
+            SortitionDB::get_canonical_burn_chain_tip(peer.sortdb.as_ref().unwrap().conn())
+                .unwrap()
+                .block_height;
+
+        let num_blocks = 5;
+        for _ in 0..num_blocks {
+            let (burn_ops, stacks_block, microblocks) = peer.make_default_tenure();
+            peer.next_burnchain_block(burn_ops);
+            peer.process_stacks_epoch_at_tip(&stacks_block, &microblocks);
+        }
+
+        let fork_tree = ForkTree::load(
+            peer.sortdb.as_ref().unwrap(),
+            &peer.stacks_node.as_ref().unwrap().chainstate,
+            first_height + 1,
+            first_height + num_blocks,
+        )
+        .unwrap();
+
+        assert_eq!(fork_tree.sortitions.len(), num_blocks as usize);
+        for sortition in fork_tree.sortitions.iter() {
+            assert!(sortition.sortition);
+            assert_eq!(sortition.commits.len(), 1);
+            assert!(sortition.commits[0].won);
+            assert_eq!(sortition.blocks.len(), 1);
+
+            let block = &sortition.blocks[0];
+            assert_eq!(
+                Some(&block.block_hash),
+                sortition.winning_block_hash.as_ref()
+            );
+            assert_eq!(block.status, "processed");
+            assert!(block.canonical);
+        }
+
+        let dot = fork_tree.to_dot();
+        assert!(dot.starts_with("digraph fork_tree {"));
+        for sortition in fork_tree.sortitions.iter() {
+            assert!(dot.contains(&sortition.blocks[0].index_block_hash));
+        }
+    }
+
+    #[test]
+    fn test_fork_tree_stacks_fork() {
+        let peer_config = TestPeerConfig::new("test_fork_tree_stacks_fork", 2042, 2043);
+        let mut peer = TestPeer::new(peer_config);
+        let (first_height, blocks) = make_linear_chain(&mut peer, 4);
+
+        // a competing block in the last sortition, which builds on the second block instead of
+        // the third, and has not been processed
+        let fork_block = store_fork_block(&mut peer, &blocks[3], &blocks[1]);
+
+        let fork_tree = ForkTree::load(
+            peer.sortdb.as_ref().unwrap(),
+            &peer.stacks_node.as_ref().unwrap().chainstate,
+            first_height + 1,
+            first_height + 4,
+        )
+        .unwrap();
+
+        assert_eq!(fork_tree.sortitions.len(), 4);
+        for sortition in fork_tree.sortitions[0..3].iter() {
+            assert_eq!(sortition.blocks.len(), 1);
+            assert!(sortition.blocks[0].canonical);
+        }
+
+        let forked = &fork_tree.sortitions[3];
+        assert_eq!(forked.blocks.len(), 2);
+        let canonical: Vec<_> = forked.blocks.iter().filter(|b| b.canonical).collect();
+        let competing: Vec<_> = forked.blocks.iter().filter(|b| !b.canonical).collect();
+        assert_eq!(canonical.len(), 1);
+        assert_eq!(competing.len(), 1);
+
+        assert_eq!(
+            canonical[0].block_hash,
+            blocks[3].1.block_hash().to_string()
+        );
+        assert_eq!(canonical[0].status, "processed");
+        assert_eq!(
+            canonical[0].parent_index_block_hash,
+            fork_tree.sortitions[2].blocks[0].index_block_hash
+        );
+
+        assert_eq!(competing[0].block_hash, fork_block.block_hash().to_string());
+        assert_eq!(competing[0].status, "pending");
+        assert_eq!(
+            competing[0].parent_index_block_hash,
+            fork_tree.sortitions[1].blocks[0].index_block_hash
+        );
+        assert_eq!(
+            fork_tree.canonical_tip,
+            canonical[0].index_block_hash.to_string()
+        );
+
+        // both children point at their own parents
+        let dot = fork_tree.to_dot();
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\"",
+            &canonical[0].index_block_hash, &canonical[0].parent_index_block_hash
+        )));
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\"",
+            &competing[0].index_block_hash, &competing[0].parent_index_block_hash
+        )));
+        assert!(dot.contains(&format!(
+            "\"{}\" [fillcolor=lightgrey",
+            &competing[0].index_block_hash
+        )));
+    }
+
+    #[test]
+    fn test_fork_tree_orphans() {
+        let peer_config = TestPeerConfig::new("test_fork_tree_orphans", 2044, 2045);
+        let mut peer = TestPeer::new(peer_config);
+        let (first_height, blocks) = make_linear_chain(&mut peer, 3);
+
+        // an orphaned block in the last sortition, forking off of the first block
+        let orphan = store_fork_block(&mut peer, &blocks[2], &blocks[0]);
+        set_block_orphaned(
+            &mut peer.stacks_node.as_mut().unwrap().chainstate,
+            &blocks[2].0,
+            &orphan.block_hash(),
+        );
+
+        // a sortition whose winning block was never stored
+        let (burn_ops, unstored_block, _) = peer.make_default_tenure();
+        peer.next_burnchain_block(burn_ops);
+
+        let fork_tree = ForkTree::load(
+            peer.sortdb.as_ref().unwrap(),
+            &peer.stacks_node.as_ref().unwrap().chainstate,
+            first_height + 1,
+            first_height + 4,
+        )
+        .unwrap();
+
+        assert_eq!(fork_tree.sortitions.len(), 4);
+
+        let orphaned: Vec<_> = fork_tree.sortitions[2]
+            .blocks
+            .iter()
+            .filter(|b| b.block_hash == orphan.block_hash().to_string())
+            .collect();
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].status, "orphaned");
+        assert!(!orphaned[0].canonical);
+        assert_eq!(
+            orphaned[0].parent_index_block_hash,
+            fork_tree.sortitions[0].blocks[0].index_block_hash
+        );
+
+        // the canonical tip is still the last stored block
+        let unstored = &fork_tree.sortitions[3];
+        assert!(unstored.sortition);
+        assert_eq!(
+            unstored.winning_block_hash,
+            Some(unstored_block.block_hash().to_string())
+        );
+        assert_eq!(unstored.blocks.len(), 0);
+        assert_eq!(
+            fork_tree.canonical_tip,
+            StacksBlockId::new(&blocks[2].0, &blocks[2].1.block_hash()).to_string()
+        );
+
+        let dot = fork_tree.to_dot();
+        assert!(dot.contains(&format!(
+            "\"{}\" [fillcolor=salmon",
+            &orphaned[0].index_block_hash
+        )));
+        assert!(dot.contains(&format!(
+            "\"missing_{}\" [style=dashed",
+            unstored.burn_height
+        )));
+    }
+}
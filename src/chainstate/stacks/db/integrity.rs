@@ -0,0 +1,624 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Consistency checks between a node's chainstate databases, its block files, and its sortition
+//! DB.  Used by `stacks-inspect verify-chainstate`.
+
+use std::io;
+
+use rusqlite::types::ToSql;
+
+use crate::chainstate::burn::db::sortdb::SortitionDB;
+use crate::chainstate::stacks::db::blocks::StagingBlock;
+use crate::chainstate::stacks::db::{StacksChainState, StacksHeaderInfo};
+use crate::chainstate::stacks::index::marf::MarfConnection;
+use crate::chainstate::stacks::{Error, StacksBlock};
+use crate::codec::StacksMessageCodec;
+use crate::core::FIRST_BURNCHAIN_CONSENSUS_HASH;
+use crate::util_lib::db::query_rows;
+use crate::util_lib::db::Error as db_error;
+
+use stacks_common::types::chainstate::StacksBlockId;
+
+/// Kinds of inconsistencies `verify_chainstate()` can find
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ChainstateIssueKind {
+    /// The canonical chain includes the block, but it has no header
+    MissingHeader,
+    /// The header's `state_index_root` does not match the Clarity MARF's root hash for the block
+    StateRootMismatch,
+    /// The header's `index_root` does not match the headers MARF's root hash for the block
+    IndexRootMismatch,
+    /// The Clarity MARF or the headers MARF has no root hash for the block
+    MissingMarfRoot,
+    /// The block has a header but no staging block
+    MissingStagingBlock,
+    /// The block has a header, but its staging block is not marked as processed, or is orphaned
+    StagingBlockNotAccepted,
+    /// The block's consensus hash does not identify a sortition
+    MissingSortition,
+    /// The block's sortition did not choose this block
+    SortitionMismatch,
+    /// The block's file is missing or empty
+    MissingBlockFile,
+    /// The block's file does not decode (which includes a tx merkle root mismatch), or does not
+    /// hash to the block's header
+    CorruptBlockFile,
+    /// The staging block is marked as processed and accepted, but has no header
+    ProcessedWithoutHeader,
+}
+
+/// An inconsistency found in one block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainstateIssue {
+    pub index_block_hash: String,
+    pub height: u64,
+    pub kind: ChainstateIssueKind,
+    pub detail: String,
+    /// Whether or not the issue was repaired
+    pub repaired: bool,
+}
+
+/// Result of a chainstate integrity check
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainstateReport {
+    pub canonical_tip: String,
+    pub blocks_checked: u64,
+    pub issues: Vec<ChainstateIssue>,
+}
+
+impl ChainstateIssue {
+    fn new(
+        index_block_hash: &StacksBlockId,
+        height: u64,
+        kind: ChainstateIssueKind,
+        detail: String,
+    ) -> ChainstateIssue {
+        ChainstateIssue {
+            index_block_hash: index_block_hash.to_string(),
+            height,
+            kind,
+            detail,
+            repaired: false,
+        }
+    }
+}
+
+/// Walk the canonical Stacks chain back from its tip, and check each block's header against the
+/// MARFs, the staging blocks table, the sortition DB, and the block's stored file.  Checks at
+/// most `max_blocks` blocks if given.
+/// Then, look for staging blocks that were marked as processed but never got a header.  If
+/// `repair` is true, these are re-queued for processing.  The other issues cannot be repaired
+/// from local data, and are only reported.
+pub fn verify_chainstate(
+    sortdb: &SortitionDB,
+    chainstate: &mut StacksChainState,
+    max_blocks: Option<u64>,
+    repair: bool,
+) -> Result<ChainstateReport, Error> {
+    let (tip_consensus_hash, tip_block_hash) =
+        SortitionDB::get_canonical_stacks_chain_tip_hash(sortdb.conn())?;
+    let canonical_tip = StacksBlockId::new(&tip_consensus_hash, &tip_block_hash);
+
+    let mut issues = vec![];
+    let mut blocks_checked = 0;
+    let mut cursor = Some(canonical_tip.clone());
+
+    while let Some(index_block_hash) = cursor.take() {
+        if let Some(max_blocks) = max_blocks {
+            if blocks_checked >= max_blocks {
+                break;
+            }
+        }
+
+        let header_info = match StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+            chainstate.db(),
+            &index_block_hash,
+        )? {
+            Some(header_info) => header_info,
+            None => {
+                if blocks_checked == 0 {
+                    // no blocks processed yet
+                    break;
+                }
+                // keep walking through the staging block, if there is one
+                let staging_block = load_staging_block(chainstate, &index_block_hash)?;
+                issues.push(ChainstateIssue::new(
+                    &index_block_hash,
+                    staging_block.as_ref().map(|b| b.height).unwrap_or(0),
+                    ChainstateIssueKind::MissingHeader,
+                    "no header".to_string(),
+                ));
+                blocks_checked += 1;
+                cursor = staging_block.map(|b| {
+                    StacksBlockId::new(&b.parent_consensus_hash, &b.parent_anchored_block_hash)
+                });
+                continue;
+            }
+        };
+
+        check_block(
+            sortdb,
+            chainstate,
+            &index_block_hash,
+            &header_info,
+            &mut issues,
+        )?;
+        blocks_checked += 1;
+
+        if header_info.consensus_hash != FIRST_BURNCHAIN_CONSENSUS_HASH {
+            cursor = StacksChainState::get_parent_block_id(chainstate.db(), &index_block_hash)?;
+        }
+    }
+
+    check_processed_blocks_have_headers(chainstate, repair, &mut issues)?;
+
+    Ok(ChainstateReport {
+        canonical_tip: canonical_tip.to_string(),
+        blocks_checked,
+        issues,
+    })
+}
+
+/// Check one canonical block's header against the rest of the node's state
+fn check_block(
+    sortdb: &SortitionDB,
+    chainstate: &mut StacksChainState,
+    index_block_hash: &StacksBlockId,
+    header_info: &StacksHeaderInfo,
+    issues: &mut Vec<ChainstateIssue>,
+) -> Result<(), Error> {
+    let height = header_info.stacks_block_height;
+    let header = &header_info.anchored_header;
+
+    // the Clarity MARF's root hash is committed to in the block header, except in the boot
+    // block's header, which is synthesized with an all-zeros state root
+    match chainstate
+        .clarity_state
+        .with_marf(|marf| marf.get_root_hash_at(index_block_hash))
+    {
+        Ok(state_root) => {
+            if header_info.consensus_hash != FIRST_BURNCHAIN_CONSENSUS_HASH
+                && state_root != header.state_index_root
+            {
+                issues.push(ChainstateIssue::new(
+                    index_block_hash,
+                    height,
+                    ChainstateIssueKind::StateRootMismatch,
+                    format!(
+                        "header state_index_root is {}, but MARF root is {}",
+                        &header.state_index_root, &state_root
+                    ),
+                ));
+            }
+        }
+        Err(e) => {
+            issues.push(ChainstateIssue::new(
+                index_block_hash,
+                height,
+                ChainstateIssueKind::MissingMarfRoot,
+                format!("failed to read the Clarity MARF root: {:?}", &e),
+            ));
+        }
+    }
+
+    match chainstate.state_index.get_root_hash_at(index_block_hash) {
+        Ok(index_root) => {
+            if index_root != header_info.index_root {
+                issues.push(ChainstateIssue::new(
+                    index_block_hash,
+                    height,
+                    ChainstateIssueKind::IndexRootMismatch,
+                    format!(
+                        "header index_root is {}, but headers MARF root is {}",
+                        &header_info.index_root, &index_root
+                    ),
+                ));
+            }
+        }
+        Err(e) => {
+            issues.push(ChainstateIssue::new(
+                index_block_hash,
+                height,
+                ChainstateIssueKind::MissingMarfRoot,
+                format!("failed to read the headers MARF root: {:?}", &e),
+            ));
+        }
+    }
+
+    if header_info.consensus_hash == FIRST_BURNCHAIN_CONSENSUS_HASH {
+        // the boot block has no staging block, sortition, or block file
+        return Ok(());
+    }
+
+    let block_hash = header.block_hash();
+
+    match load_staging_block(chainstate, index_block_hash)? {
+        None => {
+            issues.push(ChainstateIssue::new(
+                index_block_hash,
+                height,
+                ChainstateIssueKind::MissingStagingBlock,
+                "no staging block".to_string(),
+            ));
+        }
+        Some(staging_block) => {
+            if !staging_block.processed || staging_block.orphaned {
+                issues.push(ChainstateIssue::new(
+                    index_block_hash,
+                    height,
+                    ChainstateIssueKind::StagingBlockNotAccepted,
+                    format!(
+                        "staging block has processed = {}, orphaned = {}",
+                        staging_block.processed, staging_block.orphaned
+                    ),
+                ));
+            }
+        }
+    }
+
+    match SortitionDB::get_block_snapshot_consensus(sortdb.conn(), &header_info.consensus_hash)? {
+        None => {
+            issues.push(ChainstateIssue::new(
+                index_block_hash,
+                height,
+                ChainstateIssueKind::MissingSortition,
+                format!("no sortition for {}", &header_info.consensus_hash),
+            ));
+        }
+        Some(sn) => {
+            if !sn.sortition || sn.winning_stacks_block_hash != block_hash {
+                issues.push(ChainstateIssue::new(
+                    index_block_hash,
+                    height,
+                    ChainstateIssueKind::SortitionMismatch,
+                    format!(
+                        "sortition at burn height {} chose {}",
+                        sn.block_height, &sn.winning_stacks_block_hash
+                    ),
+                ));
+            }
+        }
+    }
+
+    let block_bytes = match StacksChainState::load_block_bytes(
+        &chainstate.blocks_path,
+        &header_info.consensus_hash,
+        &block_hash,
+    ) {
+        Ok(Some(block_bytes)) => block_bytes,
+        Ok(None) | Err(Error::DBError(db_error::NotFoundError)) => {
+            issues.push(ChainstateIssue::new(
+                index_block_hash,
+                height,
+                ChainstateIssueKind::MissingBlockFile,
+                "block file is missing or empty".to_string(),
+            ));
+            return Ok(());
+        }
+        Err(e) => {
+            issues.push(ChainstateIssue::new(
+                index_block_hash,
+                height,
+                ChainstateIssueKind::CorruptBlockFile,
+                format!("failed to read block file: {:?}", &e),
+            ));
+            return Ok(());
+        }
+    };
+
+    match StacksBlock::consensus_deserialize(&mut io::Cursor::new(&block_bytes)) {
+        Ok(block) => {
+            if block.block_hash() != block_hash {
+                issues.push(ChainstateIssue::new(
+                    index_block_hash,
+                    height,
+                    ChainstateIssueKind::CorruptBlockFile,
+                    format!("block file hashes to {}", &block.block_hash()),
+                ));
+            }
+        }
+        Err(e) => {
+            issues.push(ChainstateIssue::new(
+                index_block_hash,
+                height,
+                ChainstateIssueKind::CorruptBlockFile,
+                format!("failed to decode block file: {:?}", &e),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Find staging blocks that are marked as processed and accepted, but which never got a header
+/// (e.g. because the node crashed between the two writes).  The node will never process them
+/// again on its own.  If `repair` is true, mark them as unprocessed so they get re-queued.
+fn check_processed_blocks_have_headers(
+    chainstate: &mut StacksChainState,
+    repair: bool,
+    issues: &mut Vec<ChainstateIssue>,
+) -> Result<(), Error> {
+    let sql = "SELECT * FROM staging_blocks WHERE processed = 1 AND orphaned = 0 AND index_block_hash NOT IN (SELECT index_block_hash FROM block_headers) ORDER BY height";
+    let headerless: Vec<StagingBlock> =
+        query_rows(chainstate.db(), sql, rusqlite::NO_PARAMS).map_err(Error::DBError)?;
+    if headerless.len() == 0 {
+        return Ok(());
+    }
+
+    let tx = if repair {
+        Some(chainstate.db_tx_begin()?)
+    } else {
+        None
+    };
+
+    for staging_block in headerless.iter() {
+        let index_block_hash = StacksBlockId::new(
+            &staging_block.consensus_hash,
+            &staging_block.anchored_block_hash,
+        );
+        let mut issue = ChainstateIssue::new(
+            &index_block_hash,
+            staging_block.height,
+            ChainstateIssueKind::ProcessedWithoutHeader,
+            "staging block is processed, but has no header".to_string(),
+        );
+
+        if let Some(tx) = tx.as_ref() {
+            let parent_block_id = StacksBlockId::new(
+                &staging_block.parent_consensus_hash,
+                &staging_block.parent_anchored_block_hash,
+            );
+            let parent_processed =
+                StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+                    tx,
+                    &parent_block_id,
+                )?
+                .is_some();
+
+            let sql = "UPDATE staging_blocks SET processed = 0, attachable = ?1 WHERE index_block_hash = ?2";
+            let args: &[&dyn ToSql] = &[&(parent_processed as i64), &index_block_hash];
+            tx.execute(sql, args)
+                .map_err(|e| Error::DBError(db_error::SqliteError(e)))?;
+            issue.repaired = true;
+        }
+        issues.push(issue);
+    }
+
+    if let Some(tx) = tx {
+        tx.commit()
+            .map_err(|e| Error::DBError(db_error::SqliteError(e)))?;
+    }
+    Ok(())
+}
+
+/// Load a block's staging block info, whether or not it was orphaned
+fn load_staging_block(
+    chainstate: &StacksChainState,
+    index_block_hash: &StacksBlockId,
+) -> Result<Option<StagingBlock>, Error> {
+    let sql = "SELECT * FROM staging_blocks WHERE index_block_hash = ?1";
+    let args: &[&dyn ToSql] = &[index_block_hash];
+    let mut rows: Vec<StagingBlock> =
+        query_rows(chainstate.db(), sql, args).map_err(Error::DBError)?;
+    Ok(rows.pop())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::net::test::*;
+
+    #[test]
+    fn test_verify_chainstate_linear_chain() {
+        let peer_config = TestPeerConfig::new("test_verify_chainstate_linear_chain", 2042, 2043);
+        let mut peer = TestPeer::new(peer_config);
+
+        let num_blocks = 5;
+        let mut last_block_id = None;
+        for _ in 0..num_blocks {
+            let (burn_ops, stacks_block, microblocks) = peer.make_default_tenure();
+            let (_, _, consensus_hash) = peer.next_burnchain_block(burn_ops);
+            peer.process_stacks_epoch_at_tip(&stacks_block, &microblocks);
+            last_block_id = Some(StacksBlockId::new(
+                &consensus_hash,
+                &stacks_block.block_hash(),
+            ));
+        }
+        let last_block_id = last_block_id.unwrap();
+
+        let sortdb = peer.sortdb.take().unwrap();
+        let mut node = peer.stacks_node.take().unwrap();
+
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, None, false).unwrap();
+        assert_eq!(report.canonical_tip, last_block_id.to_string());
+        assert!(report.blocks_checked >= num_blocks);
+        assert_eq!(report.issues, vec![]);
+
+        // only look at the tip
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, Some(1), false).unwrap();
+        assert_eq!(report.blocks_checked, 1);
+
+        // clobber the tip's block file
+        let block_path =
+            StacksChainState::get_index_block_path(&node.chainstate.blocks_path, &last_block_id)
+                .unwrap();
+        fs::write(&block_path, &[0xff; 16]).unwrap();
+
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, None, false).unwrap();
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].kind, ChainstateIssueKind::CorruptBlockFile);
+        assert_eq!(report.issues[0].index_block_hash, last_block_id.to_string());
+        assert!(!report.issues[0].repaired);
+
+        peer.sortdb = Some(sortdb);
+        peer.stacks_node = Some(node);
+    }
+
+    #[test]
+    fn test_verify_chainstate_missing_header() {
+        let peer_config = TestPeerConfig::new("test_verify_chainstate_missing_header", 2044, 2045);
+        let mut peer = TestPeer::new(peer_config);
+
+        let num_blocks = 3;
+        let mut block_ids = vec![];
+        for _ in 0..num_blocks {
+            let (burn_ops, stacks_block, microblocks) = peer.make_default_tenure();
+            let (_, _, consensus_hash) = peer.next_burnchain_block(burn_ops);
+            peer.process_stacks_epoch_at_tip(&stacks_block, &microblocks);
+            block_ids.push(StacksBlockId::new(
+                &consensus_hash,
+                &stacks_block.block_hash(),
+            ));
+        }
+
+        let sortdb = peer.sortdb.take().unwrap();
+        let mut node = peer.stacks_node.take().unwrap();
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, None, false).unwrap();
+        let blocks_checked = report.blocks_checked;
+
+        // lose the middle block's header.  Its staging block is now processed without a header.
+        node.chainstate
+            .db()
+            .execute(
+                "DELETE FROM block_headers WHERE index_block_hash = ?1",
+                &[&block_ids[1]],
+            )
+            .unwrap();
+
+        // the check keeps going past the missing header
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, None, false).unwrap();
+        assert_eq!(report.blocks_checked, blocks_checked);
+        let kinds: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.index_block_hash.clone(), issue.kind, issue.repaired))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    block_ids[1].to_string(),
+                    ChainstateIssueKind::MissingHeader,
+                    false
+                ),
+                (
+                    block_ids[1].to_string(),
+                    ChainstateIssueKind::ProcessedWithoutHeader,
+                    false
+                ),
+            ]
+        );
+
+        peer.sortdb = Some(sortdb);
+        peer.stacks_node = Some(node);
+    }
+
+    #[test]
+    fn test_verify_chainstate_repair_processed_without_header() {
+        let peer_config = TestPeerConfig::new(
+            "test_verify_chainstate_repair_processed_without_header",
+            2046,
+            2047,
+        );
+        let mut peer = TestPeer::new(peer_config);
+
+        let mut parent_consensus_hash = None;
+        for _ in 0..2 {
+            let (burn_ops, stacks_block, microblocks) = peer.make_default_tenure();
+            let (_, _, consensus_hash) = peer.next_burnchain_block(burn_ops);
+            peer.process_stacks_epoch_at_tip(&stacks_block, &microblocks);
+            parent_consensus_hash = Some(consensus_hash);
+        }
+
+        // the next block is stored and marked as processed, but never gets a header, as if the
+        // node crashed in between
+        let (burn_ops, stacks_block, _) = peer.make_default_tenure();
+        let (_, _, consensus_hash) = peer.next_burnchain_block(burn_ops);
+        let headerless_id = StacksBlockId::new(&consensus_hash, &stacks_block.block_hash());
+
+        let mut sortdb = peer.sortdb.take().unwrap();
+        let mut node = peer.stacks_node.take().unwrap();
+        assert!(node
+            .chainstate
+            .preprocess_anchored_block(
+                &sortdb.index_conn(),
+                &consensus_hash,
+                &stacks_block,
+                parent_consensus_hash.as_ref().unwrap(),
+                5
+            )
+            .unwrap());
+        node.chainstate
+            .db()
+            .execute(
+                "UPDATE staging_blocks SET processed = 1, attachable = 0 WHERE index_block_hash = ?1",
+                &[&headerless_id],
+            )
+            .unwrap();
+
+        // reported, but left alone
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, None, false).unwrap();
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(
+            report.issues[0].kind,
+            ChainstateIssueKind::ProcessedWithoutHeader
+        );
+        assert_eq!(report.issues[0].index_block_hash, headerless_id.to_string());
+        assert!(!report.issues[0].repaired);
+        let staging_block = load_staging_block(&node.chainstate, &headerless_id)
+            .unwrap()
+            .unwrap();
+        assert!(staging_block.processed);
+
+        // re-queued, and attachable since its parent has a header
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, None, true).unwrap();
+        assert_eq!(report.issues.len(), 1);
+        assert!(report.issues[0].repaired);
+        let staging_block = load_staging_block(&node.chainstate, &headerless_id)
+            .unwrap()
+            .unwrap();
+        assert!(!staging_block.processed);
+        assert!(!staging_block.orphaned);
+        assert!(staging_block.attachable);
+
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, None, false).unwrap();
+        assert_eq!(report.issues, vec![]);
+
+        // the node processes it now
+        let processed = node
+            .chainstate
+            .process_blocks_at_tip(&mut sortdb, 1)
+            .unwrap();
+        assert_eq!(processed.len(), 1);
+        assert!(processed[0].0.is_some());
+        assert!(
+            StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+                node.chainstate.db(),
+                &headerless_id
+            )
+            .unwrap()
+            .is_some()
+        );
+        let report = verify_chainstate(&sortdb, &mut node.chainstate, None, false).unwrap();
+        assert_eq!(report.issues, vec![]);
+
+        peer.sortdb = Some(sortdb);
+        peer.stacks_node = Some(node);
+    }
+}
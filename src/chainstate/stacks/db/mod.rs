@@ -30,7 +30,7 @@ use rusqlite::Row;
 use rusqlite::Transaction;
 use rusqlite::NO_PARAMS;
 
-use crate::burnchains::bitcoin::address::BitcoinAddress;
+use crate::burnchains::bitcoin::address::LegacyBitcoinAddress;
 use crate::burnchains::{Address, Burnchain, BurnchainParameters, PoxConstants};
 use crate::chainstate::burn::db::sortdb::BlockHeaderCache;
 use crate::chainstate::burn::db::sortdb::*;
@@ -97,7 +97,9 @@ use stacks_common::types::chainstate::{StacksAddress, StacksBlockId, TrieHash};
 pub mod accounts;
 pub mod blocks;
 pub mod contracts;
+pub mod fork_tree;
 pub mod headers;
+pub mod integrity;
 pub mod transactions;
 pub mod unconfirmed;
 
@@ -1032,8 +1034,8 @@ impl StacksChainState {
 
     fn parse_genesis_address(addr: &str, mainnet: bool) -> PrincipalData {
         // Typical entries are BTC encoded addresses that need converted to STX
-        let mut stacks_address = match BitcoinAddress::from_b58(&addr) {
-            Ok(addr) => StacksAddress::from_bitcoin_address(&addr),
+        let mut stacks_address = match LegacyBitcoinAddress::from_b58(&addr) {
+            Ok(addr) => StacksAddress::from_legacy_bitcoin_address(&addr),
             // A few addresses (from legacy placeholder accounts) are already STX addresses
             _ => match StacksAddress::from_string(addr) {
                 Some(addr) => addr,
@@ -42,7 +42,9 @@ use stacks_common::util::hash::Sha512Trunc256Sum;
 use stacks_common::util::log;
 
 use crate::chainstate::stacks::index::TrieHashExtension;
-use crate::chainstate::stacks::index::{ClarityMarfTrieId, TrieLeaf, TrieMerkleProof};
+use crate::chainstate::stacks::index::{
+    ClarityMarfTrieId, TrieLeaf, TrieMerkleBatchProof, TrieMerkleProof,
+};
 use stacks_common::types::chainstate::BlockHeaderHash;
 use stacks_common::types::chainstate::TrieHash;
 use stacks_common::types::chainstate::TRIEHASH_ENCODED_SIZE;
@@ -150,6 +152,15 @@ pub trait MarfConnection<T: MarfTrieId> {
         })
     }
 
+    /// Resolve several keys at once, and prove each key's value (or its absence).
+    fn get_batch_with_proof(
+        &mut self,
+        block_hash: &T,
+        keys: &[String],
+    ) -> Result<(Vec<Option<MARFValue>>, TrieMerkleBatchProof<T>), Error> {
+        self.with_conn(|conn| MARF::get_batch_with_proof_at(conn, block_hash, keys))
+    }
+
     fn get_block_at_height(&mut self, height: u32, tip: &T) -> Result<Option<T>, Error> {
         self.with_conn(|c| MARF::get_block_at_height(c, height, tip))
     }
@@ -1245,6 +1256,8 @@ impl<T: MarfTrieId> MARF<T> {
 
     /// Insert a batch of key/value pairs.  More efficient than inserting them individually, since
     /// the trie root hash will only be calculated once (which is an O(log B) operation).
+    /// The keys are inserted in the order given.  They can't be sorted first: Node4, Node16 and
+    /// Node48 keep their children in insertion order, and that order is part of the node hash.
     fn inner_insert_batch(
         conn: &mut TrieStorageTransaction<T>,
         block_hash: &T,
@@ -1326,6 +1339,39 @@ impl<T: MarfTrieId> MARF<T> {
         Ok(Some((marf_value, proof)))
     }
 
+    pub fn get_batch_with_proof(
+        &mut self,
+        block_hash: &T,
+        keys: &[String],
+    ) -> Result<(Vec<Option<MARFValue>>, TrieMerkleBatchProof<T>), Error> {
+        let mut conn = self.storage.connection();
+        MARF::get_batch_with_proof_at(&mut conn, block_hash, keys)
+    }
+
+    /// Look up each key in `keys`, and make a batch proof with a proof of inclusion for each key
+    /// that is present, and a proof of non-inclusion for each key that is absent.
+    fn get_batch_with_proof_at(
+        storage: &mut TrieStorageConnection<T>,
+        block_hash: &T,
+        keys: &[String],
+    ) -> Result<(Vec<Option<MARFValue>>, TrieMerkleBatchProof<T>), Error> {
+        let mut values = Vec::with_capacity(keys.len());
+        let mut proofs = Vec::with_capacity(keys.len());
+        for key in keys.iter() {
+            let path = TriePath::from_key(key);
+            let marf_value_opt = MARF::get_by_key(storage, block_hash, key)?;
+            let proof = match marf_value_opt {
+                Some(ref marf_value) => {
+                    TrieMerkleProof::from_path(storage, &path, marf_value, block_hash)?
+                }
+                None => TrieMerkleProof::from_absent_path(storage, &path, block_hash)?,
+            };
+            values.push(marf_value_opt);
+            proofs.push(proof);
+        }
+        Ok((values, TrieMerkleBatchProof::from_proofs(proofs)))
+    }
+
     pub fn get_bhh_at_height(&mut self, block_hash: &T, height: u32) -> Result<Option<T>, Error> {
         MARF::get_block_at_height(&mut self.storage.connection(), height, block_hash)
     }
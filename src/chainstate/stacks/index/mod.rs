@@ -51,6 +51,14 @@ pub mod test;
 #[derive(Debug)]
 pub struct TrieMerkleProof<T: MarfTrieId>(pub Vec<TrieMerkleProofType<T>>);
 
+/// Merkle proof for several keys at once.  Proof entries that the keys' proofs have in common are
+///   stored only once in `entries`, and each key's proof is a list of indexes into `entries`.
+#[derive(Debug)]
+pub struct TrieMerkleBatchProof<T: MarfTrieId> {
+    pub entries: Vec<TrieMerkleProofType<T>>,
+    pub proofs: Vec<Vec<u32>>,
+}
+
 pub trait ClarityMarfTrieId:
     PartialEq + Clone + std::fmt::Display + std::fmt::Debug + std::convert::From<[u8; 32]>
 {
@@ -68,6 +76,9 @@ pub enum TrieMerkleProofType<T> {
     Node256((u8, ProofTrieNode<T>, [TrieHash; 255])),
     Leaf((u8, TrieLeaf)),
     Shunt((i64, Vec<TrieHash>)),
+    /// Node at which the walk down a path stops because the path is absent, along with the
+    ///   hashes of all of its children.  Only appears at the start of a non-inclusion proof.
+    Terminal((ProofTrieNode<T>, Vec<TrieHash>)),
 }
 
 /// Merkle Proof Trie Pointers have a different structure
@@ -103,6 +114,8 @@ pub trait MarfTrieId:
     + PartialEq
     + Eq
     + Hash
+    + Send
+    + Sync
 {
 }
 
@@ -46,8 +46,8 @@ use stacks_common::util::slice_partialeq;
 
 use crate::chainstate::stacks::index::TrieHashExtension;
 use crate::chainstate::stacks::index::{
-    ClarityMarfTrieId, MARFValue, ProofTrieNode, ProofTriePtr, TrieLeaf, TrieMerkleProof,
-    TrieMerkleProofType,
+    ClarityMarfTrieId, MARFValue, ProofTrieNode, ProofTriePtr, TrieLeaf, TrieMerkleBatchProof,
+    TrieMerkleProof, TrieMerkleProofType,
 };
 use crate::codec::{read_next, Error as codec_error, StacksMessageCodec};
 use stacks_common::types::chainstate::BlockHeaderHash;
@@ -55,6 +55,12 @@ use stacks_common::types::chainstate::BLOCK_HEADER_HASH_ENCODED_SIZE;
 
 use stacks_common::types::chainstate::{TrieHash, TRIEHASH_ENCODED_SIZE};
 
+use marf_verifier::{
+    MARFBatchProof as VerifierBatchProof, ProofEntry as VerifierProofEntry,
+    ProofTrieLeaf as VerifierTrieLeaf, ProofTrieNode as VerifierTrieNode,
+    ProofTriePtr as VerifierTriePtr, TrieRootMap,
+};
+
 impl<T: MarfTrieId> ConsensusSerializable<()> for ProofTrieNode<T> {
     fn write_consensus_bytes<W: Write>(
         &self,
@@ -115,10 +121,104 @@ impl<T: MarfTrieId> ProofTrieNode<T> {
             ptrs: ptrs?,
         })
     }
+
+    fn to_verifier_node(&self) -> VerifierTrieNode {
+        VerifierTrieNode {
+            id: self.id,
+            path: self.path.clone(),
+            ptrs: self
+                .ptrs
+                .iter()
+                .map(|ptr| VerifierTriePtr {
+                    id: ptr.id,
+                    chr: ptr.chr,
+                    back_block: ptr.back_block.clone().to_bytes(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<T: MarfTrieId> TrieMerkleProofType<T> {
+    fn to_verifier_entry(&self) -> VerifierProofEntry {
+        match self {
+            TrieMerkleProofType::Node4((ref chr, ref node, ref hashes)) => {
+                VerifierProofEntry::Node(
+                    *chr,
+                    node.to_verifier_node(),
+                    hashes.iter().map(|h| h.0).collect(),
+                )
+            }
+            TrieMerkleProofType::Node16((ref chr, ref node, ref hashes)) => {
+                VerifierProofEntry::Node(
+                    *chr,
+                    node.to_verifier_node(),
+                    hashes.iter().map(|h| h.0).collect(),
+                )
+            }
+            TrieMerkleProofType::Node48((ref chr, ref node, ref hashes)) => {
+                VerifierProofEntry::Node(
+                    *chr,
+                    node.to_verifier_node(),
+                    hashes.iter().map(|h| h.0).collect(),
+                )
+            }
+            TrieMerkleProofType::Node256((ref chr, ref node, ref hashes)) => {
+                VerifierProofEntry::Node(
+                    *chr,
+                    node.to_verifier_node(),
+                    hashes.iter().map(|h| h.0).collect(),
+                )
+            }
+            TrieMerkleProofType::Leaf((ref chr, ref node)) => VerifierProofEntry::Leaf(
+                *chr,
+                VerifierTrieLeaf {
+                    path: node.path.clone(),
+                    data: node.data.0,
+                },
+            ),
+            TrieMerkleProofType::Shunt((ref idx, ref hashes)) => {
+                VerifierProofEntry::Shunt(*idx, hashes.iter().map(|h| h.0).collect())
+            }
+            TrieMerkleProofType::Terminal((ref node, ref hashes)) => VerifierProofEntry::Terminal(
+                node.to_verifier_node(),
+                hashes.iter().map(|h| h.0).collect(),
+            ),
+        }
+    }
+}
+
+/// Adapter that lets the standalone verifier look up blocks in a root-to-block map
+struct RootToBlockMap<'a, T>(&'a HashMap<TrieHash, T>);
+
+impl<'a, T: MarfTrieId> TrieRootMap for RootToBlockMap<'a, T> {
+    fn block_for_root(&self, root_hash: &[u8; 32]) -> Option<[u8; 32]> {
+        match self.0.get(&TrieHash(*root_hash)) {
+            Some(bhh) => {
+                trace!("Block hash for {:?} is {:?}", root_hash, bhh);
+                Some(bhh.clone().to_bytes())
+            }
+            None => {
+                test_debug!(
+                    "Trie hash not found in root-to-block map: {:?}",
+                    &TrieHash(*root_hash)
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Where a walk down a path within a single trie stopped
+enum ProofWalkEnd<T: MarfTrieId> {
+    /// Reached the path's leaf, or a back-pointer to an ancestor trie
+    LeafOrBackptr(TrieCursor<T>, TrieNodeType, TriePtr),
+    /// The path is absent -- the walk stopped at this node
+    Absent(TrieCursor<T>, TrieNodeType),
 }
 
 define_u8_enum!( TrieMerkleProofTypeIndicator {
-    Node4 = 0, Node16 = 1, Node48 = 2, Node256 = 3, Leaf = 4, Shunt = 5
+    Node4 = 0, Node16 = 1, Node48 = 2, Node256 = 3, Leaf = 4, Shunt = 5, Terminal = 6
 });
 
 impl<T: ClarityMarfTrieId> PartialEq for TrieMerkleProofType<T> {
@@ -148,6 +248,10 @@ impl<T: ClarityMarfTrieId> PartialEq for TrieMerkleProofType<T> {
                 TrieMerkleProofType::Shunt((ref idx_1, ref hashes_1)),
                 TrieMerkleProofType::Shunt((ref idx_2, ref hashes_2)),
             ) => idx_1 == idx_2 && hashes_1 == hashes_2,
+            (
+                TrieMerkleProofType::Terminal((ref node, ref hashes)),
+                TrieMerkleProofType::Terminal((ref other_node, ref other_hashes)),
+            ) => node == other_node && hashes == other_hashes,
             (_, _) => false,
         }
     }
@@ -215,6 +319,12 @@ impl<T: MarfTrieId> fmt::Debug for TrieMerkleProofType<T> {
                 "TrieMerkleProofType::Shunt(idx={}, hashes={:?})",
                 idx, hashes
             ),
+            TrieMerkleProofType::Terminal((ref node, ref hashes)) => write!(
+                f,
+                "TrieMerkleProofType::Terminal(node={:?}, hashes={})",
+                node,
+                hashes_fmt(hashes)
+            ),
         }
     }
 }
@@ -297,6 +407,7 @@ impl<T: MarfTrieId> StacksMessageCodec for TrieMerkleProofType<T> {
             TrieMerkleProofType::Node256(_) => TrieMerkleProofTypeIndicator::Node256,
             TrieMerkleProofType::Leaf(_) => TrieMerkleProofTypeIndicator::Leaf,
             TrieMerkleProofType::Shunt(_) => TrieMerkleProofTypeIndicator::Shunt,
+            TrieMerkleProofType::Terminal(_) => TrieMerkleProofTypeIndicator::Terminal,
         } as u8;
 
         type_byte.consensus_serialize(fd)?;
@@ -322,6 +433,10 @@ impl<T: MarfTrieId> StacksMessageCodec for TrieMerkleProofType<T> {
                 id.consensus_serialize(fd)?;
                 hashes.consensus_serialize(fd)
             }
+            TrieMerkleProofType::Terminal((proof_node, hashes)) => {
+                proof_node.consensus_serialize(fd)?;
+                hashes.consensus_serialize(fd)
+            }
         }
     }
 
@@ -353,6 +468,11 @@ impl<T: MarfTrieId> StacksMessageCodec for TrieMerkleProofType<T> {
                 let hashes = read_next(fd)?;
                 TrieMerkleProofType::Shunt((id, hashes))
             }
+            TrieMerkleProofTypeIndicator::Terminal => {
+                let proof_node = read_next(fd)?;
+                let hashes = read_next(fd)?;
+                TrieMerkleProofType::Terminal((proof_node, hashes))
+            }
         };
 
         Ok(codec)
@@ -370,6 +490,104 @@ impl<T: MarfTrieId> StacksMessageCodec for TrieMerkleProof<T> {
     }
 }
 
+impl<T: MarfTrieId> StacksMessageCodec for TrieMerkleBatchProof<T> {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        self.entries.consensus_serialize(fd)?;
+        self.proofs.consensus_serialize(fd)
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<TrieMerkleBatchProof<T>, codec_error> {
+        let entries: Vec<TrieMerkleProofType<T>> = read_next(fd)?;
+        let proofs: Vec<Vec<u32>> = read_next(fd)?;
+        Ok(TrieMerkleBatchProof { entries, proofs })
+    }
+}
+
+impl<T: MarfTrieId> TrieMerkleBatchProof<T> {
+    pub fn to_hex(&self) -> String {
+        let mut marf_proof = vec![];
+        self.consensus_serialize(&mut marf_proof)
+            .expect("Write error on memory buffer");
+        to_hex(&marf_proof)
+    }
+
+    /// Combine several proofs into a batch proof, storing each distinct proof entry only once.
+    /// The proofs must all be generated from the same block.
+    pub fn from_proofs(proofs: Vec<TrieMerkleProof<T>>) -> TrieMerkleBatchProof<T> {
+        let mut entries = vec![];
+        let mut entry_indexes: HashMap<Vec<u8>, u32> = HashMap::new();
+        let mut batch_proofs = Vec::with_capacity(proofs.len());
+
+        for proof in proofs.into_iter() {
+            let mut indexes = Vec::with_capacity(proof.0.len());
+            for proof_entry in proof.0.into_iter() {
+                let mut entry_bytes = vec![];
+                proof_entry
+                    .consensus_serialize(&mut entry_bytes)
+                    .expect("Write error on memory buffer");
+
+                let idx = match entry_indexes.get(&entry_bytes) {
+                    Some(idx) => *idx,
+                    None => {
+                        let idx = entries.len() as u32;
+                        entries.push(proof_entry);
+                        entry_indexes.insert(entry_bytes, idx);
+                        idx
+                    }
+                };
+                indexes.push(idx);
+            }
+            batch_proofs.push(indexes);
+        }
+
+        TrieMerkleBatchProof {
+            entries,
+            proofs: batch_proofs,
+        }
+    }
+
+    /// Reassemble the proof for the ith key in this batch
+    pub fn get_proof(&self, i: usize) -> Option<TrieMerkleProof<T>> {
+        let indexes = self.proofs.get(i)?;
+        let mut proof = Vec::with_capacity(indexes.len());
+        for idx in indexes.iter() {
+            proof.push(self.entries.get(*idx as usize)?.clone());
+        }
+        Some(TrieMerkleProof(proof))
+    }
+
+    /// Verify the proof for each key in this batch.  `claims` has each key's path, in the order
+    /// in which the proofs were batched, and either the value it is expected to be mapped to or
+    /// None if it is expected to be absent.
+    pub fn verify(
+        &self,
+        claims: &[(TriePath, Option<MARFValue>)],
+        root_hash: &TrieHash,
+        root_to_block: &HashMap<TrieHash, T>,
+    ) -> bool {
+        let batch_proof = VerifierBatchProof {
+            entries: TrieMerkleProof::to_verifier_entries(&self.entries),
+            proofs: self.proofs.clone(),
+        };
+        let verifier_claims: Vec<_> = claims
+            .iter()
+            .map(|(path, value_opt)| (path.to_bytes(), value_opt.as_ref().map(|value| value.0)))
+            .collect();
+
+        match batch_proof.verify(
+            &verifier_claims,
+            &root_hash.0,
+            &RootToBlockMap(root_to_block),
+        ) {
+            Ok(()) => true,
+            Err(_e) => {
+                test_debug!("Invalid batch proof: {}", &_e);
+                false
+            }
+        }
+    }
+}
+
 impl<T: MarfTrieId> TrieMerkleProof<T> {
     pub fn to_hex(&self) -> String {
         let mut marf_proof = vec![];
@@ -675,173 +893,6 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
         Ok(proof)
     }
 
-    fn next_shunt_hash(hash: &TrieHash, idx: i64, hashes: &[TrieHash]) -> Option<TrieHash> {
-        let mut all_hashes = Vec::with_capacity(hashes.len() + 1);
-        let mut hash_idx = 0;
-        for i in 0..hashes.len() + 1 {
-            if idx == 0 {
-                trace!("Intermediate shunt proof entry must have idx > 0");
-                return None;
-            }
-
-            if idx - 1 == (i as i64) {
-                all_hashes.push(hash.clone());
-            } else {
-                if hash_idx >= hashes.len() {
-                    trace!(
-                        "Invalid proof: hash_idx = {}, hashes.len() = {}",
-                        hash_idx,
-                        hashes.len()
-                    );
-                    return None;
-                }
-                all_hashes.push(hashes[hash_idx].clone());
-                hash_idx += 1;
-            }
-        }
-        trace!("Shunt proof node: idx={}, all_hashes={:?}", idx, all_hashes);
-        let next_hash = TrieHash::from_data_array(&all_hashes);
-        Some(next_hash)
-    }
-
-    /// Verify the head of a shunt proof
-    fn verify_shunt_proof_head(
-        node_root_hash: &TrieHash,
-        shunt_proof_head: &TrieMerkleProofType<T>,
-    ) -> Option<TrieHash> {
-        // ancestor hashes are always the first item
-        let hash = match shunt_proof_head {
-            TrieMerkleProofType::Shunt((ref idx, ref hashes)) => {
-                if *idx != 0 {
-                    trace!("First shunt proof entry must have idx == 0");
-                    return None;
-                }
-
-                if hashes.len() == 0 {
-                    // special case -- if this shunt proof has no hashes (i.e. this is a leaf from the first
-                    // block), then we can safely skip this step
-                    trace!(
-                        "Special case for a 0-ancestor node: hash is just the trie hash: {:?}",
-                        node_root_hash
-                    );
-                    node_root_hash.clone()
-                } else {
-                    let mut all_hashes = Vec::with_capacity(hashes.len() + 1);
-                    all_hashes.push(node_root_hash.clone());
-                    for h in hashes {
-                        all_hashes.push(h.clone());
-                    }
-                    let ret = TrieHash::from_data_array(&all_hashes);
-                    trace!(
-                        "Shunt proof head: hash = {:?}, all_hashes = {:?}",
-                        &ret,
-                        &all_hashes
-                    );
-                    ret
-                }
-            }
-            _ => {
-                trace!("Shunt proof head is not a shunt proof node");
-                return None;
-            }
-        };
-
-        Some(hash)
-    }
-
-    /// Verify the tail of a shunt proof, given the backptr root hash.
-    /// Calculate the root hash of the next segment proof.
-    fn verify_shunt_proof_tail(
-        initial_hash: &TrieHash,
-        shunt_proof: &[TrieMerkleProofType<T>],
-    ) -> Option<TrieHash> {
-        let mut hash = initial_hash.clone();
-
-        // walk subsequent legs of a shunt proof, except for the last (since we need the next
-        // segment proof for that)
-        for i in 0..shunt_proof.len() {
-            let proof_node = &shunt_proof[i];
-            hash = match proof_node {
-                TrieMerkleProofType::Shunt((ref idx, ref hashes)) => {
-                    if *idx == 0 {
-                        trace!("Invalid shunt proof tail: idx == 0");
-                        return None;
-                    }
-
-                    match TrieMerkleProof::<T>::next_shunt_hash(&hash, *idx, hashes) {
-                        Some(h) => h,
-                        None => {
-                            return None;
-                        }
-                    }
-                }
-                _ => {
-                    trace!("Shunt proof item is not a shunt proof node");
-                    return None;
-                }
-            };
-        }
-        Some(hash)
-    }
-
-    /// Verify a shunt juncture, where a shunt proof tail and a segment proof meet.
-    /// Returns the hash of the root of the junction
-    fn verify_shunt_proof_junction(
-        node_root_hash: &TrieHash,
-        penultimate_trie_hash: &TrieHash,
-        shunt_proof_junction: &TrieMerkleProofType<T>,
-    ) -> Option<TrieHash> {
-        // at the juncture, we include the node root hash (from the subsequent segment proof) as
-        // the first hash, and include the penultimate trie hash in its idx
-        let hash = match shunt_proof_junction {
-            TrieMerkleProofType::Shunt((ref idx, ref hashes)) => {
-                if *idx == 0 {
-                    trace!("Shunt proof junction entry must not have idx == 0");
-                    return None;
-                }
-
-                let mut all_hashes = Vec::with_capacity(hashes.len() + 1);
-                let mut hash_idx = 0;
-
-                all_hashes.push(node_root_hash.clone());
-
-                for i in 0..hashes.len() + 1 {
-                    if *idx - 1 == (i as i64) {
-                        all_hashes.push(penultimate_trie_hash.clone());
-                    } else {
-                        if hash_idx >= hashes.len() {
-                            trace!(
-                                "ran out of hashes: hash_idx = {}, hashes.len() = {}",
-                                hash_idx,
-                                hashes.len()
-                            );
-                            return None;
-                        }
-
-                        all_hashes.push(hashes[hash_idx].clone());
-                        hash_idx += 1;
-                    }
-                }
-
-                trace!(
-                    "idx = {}, hashes = {:?}, penultimate = {:?}, node root = {:?}",
-                    *idx,
-                    hashes,
-                    penultimate_trie_hash,
-                    node_root_hash
-                );
-                trace!("Shunt proof junction: all_hashes = {:?}", &all_hashes);
-                TrieHash::from_data_array(&all_hashes)
-            }
-            _ => {
-                trace!("Shunt proof junction is not a shunt proof node");
-                return None;
-            }
-        };
-
-        Some(hash)
-    }
-
     /// Given a list of non-backptr ptrs and a root block header hash, calculate a Merkle proof.
     fn make_segment_proof(
         storage: &mut TrieStorageConnection<T>,
@@ -890,237 +941,12 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
         Ok(proof_segment)
     }
 
-    /// Given a node in a segment proof, find the hash
-    fn get_segment_proof_hash(
-        node: &ProofTrieNode<T>,
-        hash: &TrieHash,
-        chr: u8,
-        hashes: &[TrieHash],
-        count: usize,
-    ) -> Option<TrieHash> {
-        let mut all_hashes = vec![];
-        let mut ih = 0;
-
-        assert!(node.ptrs().len() == count);
-        assert!(count > 0 && hashes.len() == count - 1);
-
-        for child_ptr in node.ptrs() {
-            if child_ptr.id != TrieNodeID::Empty as u8 && child_ptr.chr == chr {
-                all_hashes.push(hash.clone());
-            } else {
-                if ih >= hashes.len() {
-                    trace!("verify_get_hash: {} >= {}", ih, hashes.len());
-                    return None;
-                } else {
-                    all_hashes.push(hashes[ih].clone());
-                    ih += 1;
-                }
-            }
-        }
-        if all_hashes.len() != count {
-            trace!("verify_get_hash: {} != {}", all_hashes.len(), count);
-            return None;
-        }
-
-        Some(get_node_hash(node, &all_hashes, &mut ()))
-    }
-
-    /// Given a segment proof, the deepest node's hash, and the hash of the trie root, verify that
-    /// the segment proof is well-formed.
-    /// If so, calculate the root hash of the segment and return it.
-    fn verify_segment_proof(
-        proof: &[TrieMerkleProofType<T>],
-        node_hash: &TrieHash,
-    ) -> Option<TrieHash> {
-        let mut hash = node_hash.clone();
-        for i in 0..proof.len() {
-            let hash_opt = match proof[i] {
-                TrieMerkleProofType::Leaf((ref _chr, ref node)) => {
-                    // special case the leaf hash -- it doesn't
-                    //   have any child hashes to check.
-                    Some(get_leaf_hash(node))
-                }
-                TrieMerkleProofType::Node4((ref chr, ref node, ref hashes)) => {
-                    TrieMerkleProof::get_segment_proof_hash(node, &hash, *chr, hashes, 4)
-                }
-                TrieMerkleProofType::Node16((ref chr, ref node, ref hashes)) => {
-                    TrieMerkleProof::get_segment_proof_hash(node, &hash, *chr, hashes, 16)
-                }
-                TrieMerkleProofType::Node48((ref chr, ref node, ref hashes)) => {
-                    TrieMerkleProof::get_segment_proof_hash(node, &hash, *chr, hashes, 48)
-                }
-                TrieMerkleProofType::Node256((ref chr, ref node, ref hashes)) => {
-                    TrieMerkleProof::get_segment_proof_hash(node, &hash, *chr, hashes, 256)
-                }
-                _ => {
-                    trace!("Invalid proof -- encountered a non-node proof type");
-                    return None;
-                }
-            };
-            hash = match hash_opt {
-                None => {
-                    return None;
-                }
-                Some(h) => h,
-            };
-        }
-
-        trace!("verify segment: calculated root hash = {:?}", hash);
-        Some(hash)
-    }
-
-    /// Given a segment proof, extract the path prefix it encodes
-    fn get_segment_proof_path_prefix(segment_proof: &[TrieMerkleProofType<T>]) -> Option<Vec<u8>> {
-        let mut path_parts = vec![];
-        for proof_node in segment_proof {
-            match proof_node {
-                TrieMerkleProofType::Leaf((ref _chr, ref node)) => {
-                    // path_parts.push(vec![*chr]);
-                    path_parts.push(node.path.clone());
-                }
-                TrieMerkleProofType::Node4((ref chr, ref node, _)) => {
-                    path_parts.push(vec![*chr]);
-                    path_parts.push(node.path.clone());
-                }
-                TrieMerkleProofType::Node16((ref chr, ref node, _)) => {
-                    path_parts.push(vec![*chr]);
-                    path_parts.push(node.path.clone());
-                }
-                TrieMerkleProofType::Node48((ref chr, ref node, _)) => {
-                    path_parts.push(vec![*chr]);
-                    path_parts.push(node.path.clone());
-                }
-                TrieMerkleProofType::Node256((ref chr, ref node, _)) => {
-                    path_parts.push(vec![*chr]);
-                    path_parts.push(node.path.clone());
-                }
-                _ => {
-                    trace!("Not a valid segment proof: got a non-node proof node");
-                    return None;
-                }
-            }
-        }
-
-        let mut path = vec![];
-        for i in 0..path_parts.len() {
-            let idx = path_parts.len() - 1 - i;
-            path.extend_from_slice(&path_parts[idx]);
-        }
-        Some(path)
-    }
-
-    /// Verify that a proof is well-formed:
-    /// * it must have the same number of segment and shunt proofs
-    /// * segment proof i+1 must be a prefix of segment proof i
-    /// * segment proof 0 must end in a leaf
-    /// * all segment proofs must end in a Node256 (a root)
-    fn is_proof_well_formed(proof: &Vec<TrieMerkleProofType<T>>, expected_path: &TriePath) -> bool {
-        if proof.len() == 0 {
-            trace!("Proof is empty");
-            return false;
-        }
-
-        match proof[0] {
-            TrieMerkleProofType::Leaf(_) => {}
-            _ => {
-                trace!("First proof node is not a leaf");
-                return false;
-            }
-        }
-
-        // must be alternating segment and shunt proofs
-        let mut i = 0;
-        let mut path_bytes = vec![];
-
-        while i < proof.len() {
-            // next segment proof
-            let mut j = i + 1;
-            while j < proof.len() {
-                match proof[j] {
-                    TrieMerkleProofType::Shunt(_) => {
-                        break;
-                    }
-                    _ => {
-                        j += 1;
-                    }
-                }
-            }
-
-            let segment_proof = &proof[i..j];
-
-            if i == 0 {
-                // detect the path
-                path_bytes = match TrieMerkleProof::get_segment_proof_path_prefix(segment_proof) {
-                    Some(bytes) => bytes,
-                    None => {
-                        trace!("Failed to get the path from the proof");
-                        return false;
-                    }
-                };
-
-                // first path bytes must be the expected TriePath
-                if expected_path.as_bytes().to_vec() != path_bytes {
-                    trace!(
-                        "Invalid proof -- path bytes {:?} differs from the expected path {:?}",
-                        &path_bytes,
-                        expected_path
-                    );
-                    return false;
-                }
-            } else {
-                // make sure that this segment proof is a prefix of the last
-                let new_path_bytes =
-                    match TrieMerkleProof::get_segment_proof_path_prefix(segment_proof) {
-                        Some(bytes) => bytes,
-                        None => {
-                            trace!("Failed to et the path prefix from the proof");
-                            return false;
-                        }
-                    };
-
-                if path_bytes.len() < new_path_bytes.len() {
-                    trace!("Segment proof path is {}, which is longer than the previous segment proof length {}", path_bytes.len(), new_path_bytes.len());
-                    trace!("path_bytes: {:?}", &path_bytes);
-                    trace!("new path bytes: {:?}", &new_path_bytes);
-                    return false;
-                }
-
-                for i in 0..new_path_bytes.len() {
-                    if path_bytes[i] != new_path_bytes[i] {
-                        trace!(
-                            "Segment path {:?} is not a prefix of previous segment path {:?}",
-                            &new_path_bytes,
-                            &path_bytes
-                        );
-                        return false;
-                    }
-                }
-            }
-
-            // next shunt proof
-            i = j;
-            if i >= proof.len() {
-                trace!("Proof is incomplete -- must end with a shunt proof");
-                return false;
-            }
-
-            j = i + 1;
-            while j < proof.len() {
-                match proof[j] {
-                    TrieMerkleProofType::Shunt(_) => {
-                        j += 1;
-                    }
-                    _ => {
-                        break;
-                    }
-                }
-            }
-
-            // end of shunt proof
-            i = j;
-        }
-
-        true
+    /// Convert this proof's entries into the form that the standalone verifier checks
+    fn to_verifier_entries(proof: &[TrieMerkleProofType<T>]) -> Vec<VerifierProofEntry> {
+        proof
+            .iter()
+            .map(|proof_entry| proof_entry.to_verifier_entry())
+            .collect()
     }
 
     /// Given a value and the root hash from which this proof was
@@ -1129,6 +955,8 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
     /// which block headers.  This can be calculated and verified independently from the blockchain
     /// headers.
     /// NOTE: Trie root hashes are globally unique by design, even if they represent the same contents, so the root_to_block map is bijective with high probability.
+    /// Verification itself is carried out by the `marf_verifier` crate, so light clients check
+    /// proofs the same way the node does.
     pub fn verify_proof(
         proof: &Vec<TrieMerkleProofType<T>>,
         path: &TriePath,
@@ -1136,226 +964,43 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
         root_hash: &TrieHash,
         root_to_block: &HashMap<TrieHash, T>,
     ) -> bool {
-        if !TrieMerkleProof::is_proof_well_formed(&proof, path) {
-            test_debug!("Invalid proof -- proof is not well-formed");
-            return false;
-        }
-
-        let (mut node_hash, node_data) = match proof[0] {
-            TrieMerkleProofType::Leaf((_, ref node)) => (get_leaf_hash(node), node.data.clone()),
-            _ => unreachable!(),
-        };
-
-        // proof must be for this value
-        if node_data != *value {
-            test_debug!(
-                "Invalid proof -- not for value hash {:?}",
-                value.to_value_hash()
-            );
-            return false;
-        }
-
-        let mut i = 0;
-
-        // verify the very first segment proof
-        let mut j = i + 1;
-        while j < proof.len() {
-            match proof[j] {
-                TrieMerkleProofType::Shunt(_) => {
-                    break;
-                }
-                _ => {
-                    j += 1;
-                }
-            }
-        }
-
-        trace!("verify segment proof in range {}..{}", i, j);
-        let node_root_hash = match TrieMerkleProof::verify_segment_proof(&proof[i..j], &node_hash) {
-            Some(h) => h,
-            None => {
-                test_debug!("Unable to verify segment proof in range {}...{}", i, j);
-                return false;
-            }
-        };
-
-        i = j;
-        if i >= proof.len() {
-            test_debug!(
-                "Proof is too short -- needed at least one shunt proof for the first segment"
-            );
-            return false;
-        }
-
-        // verify the very first shunt proof head.
-        trace!("verify shunt proof head at {}: {:?}", i, &proof[i]);
-        let mut trie_hash =
-            match TrieMerkleProof::verify_shunt_proof_head(&node_root_hash, &proof[i]) {
-                Some(h) => h,
-                None => {
-                    test_debug!(
-                        "Unable to verify shunt proof head at {}: {:?}",
-                        i,
-                        &proof[i]
-                    );
-                    return false;
-                }
-            };
-        trace!("shunt proof head hash: {:?}", &trie_hash);
-
-        i += 1;
-        if i >= proof.len() {
-            // done -- no further shunts
-            test_debug!("Verify proof: {:?} =?= {:?}", root_hash, &trie_hash);
-            return *root_hash == trie_hash;
-        }
-
-        // next node hash is the hash of the block from which its root came
-        node_hash = match root_to_block.get(&trie_hash) {
-            Some(bhh) => {
-                trace!("Block hash for {:?} is {:?}", &trie_hash, bhh);
-
-                // safe because block header hashes are 32 bytes long
-                TrieHash(bhh.clone().to_bytes())
-            }
-            None => {
-                test_debug!("Trie hash not found in root-to-block map: {:?}", &trie_hash);
-                trace!("root-to-block map: {:?}", &root_to_block);
-                return false;
-            }
-        };
-
-        // next proof item should be part of a segment proof
-        match proof[i] {
-            TrieMerkleProofType::Shunt(_) => {
-                test_debug!("Malformed proof -- exepcted segment proof following first shunt proof head at {}", i);
-                return false;
+        let entries = TrieMerkleProof::to_verifier_entries(proof);
+        match marf_verifier::verify_inclusion(
+            &entries,
+            path.as_bytes(),
+            &value.0,
+            &root_hash.0,
+            &RootToBlockMap(root_to_block),
+        ) {
+            Ok(()) => true,
+            Err(_e) => {
+                test_debug!("Invalid proof of inclusion of {:?}: {}", path, &_e);
+                false
             }
-            _ => {}
         }
+    }
 
-        while i < proof.len() {
-            // find the next segment proof
-            j = i + 1;
-            while j < proof.len() {
-                match proof[j] {
-                    TrieMerkleProofType::Shunt(_) => {
-                        break;
-                    }
-                    _ => {
-                        j += 1;
-                    }
-                }
-            }
-
-            trace!("verify segment proof in range {}..{}", i, j);
-            let next_node_root_hash =
-                match TrieMerkleProof::verify_segment_proof(&proof[i..j], &node_hash) {
-                    Some(h) => h,
-                    None => {
-                        test_debug!("Unable to verify segment proof in range {}..{}", i, j);
-                        return false;
-                    }
-                };
-
-            i = j;
-            if i >= proof.len() {
-                test_debug!("Proof to short -- no shunt proof tail");
-                return false;
-            }
-
-            // find the tail end
-            j = i;
-            while j < proof.len() {
-                match proof[j] {
-                    TrieMerkleProofType::Shunt((ref idx, _)) => {
-                        if *idx == 0 {
-                            break;
-                        }
-                        j += 1;
-                    }
-                    _ => {
-                        break;
-                    }
-                }
-            }
-            j -= 1;
-
-            if j < i {
-                test_debug!("Proof is malformed -- no tail or junction proof");
-                return false;
-            }
-
-            trace!(
-                "verify shunt proof tail in range {}..{} initial hash = {:?}: {:?}",
-                i,
-                j,
-                &trie_hash,
-                &proof[i..j]
-            );
-            let penultimate_trie_hash =
-                match TrieMerkleProof::verify_shunt_proof_tail(&trie_hash, &proof[i..j]) {
-                    Some(h) => h,
-                    None => {
-                        test_debug!("Unable to verify shunt proof tail");
-                        return false;
-                    }
-                };
-            trace!(
-                "verify shunt proof tail in range {}..{}: penultimate trie hash is {:?}",
-                i,
-                j,
-                &penultimate_trie_hash
-            );
-
-            i = j;
-            if i >= proof.len() {
-                test_debug!("Proof to short -- no junction proof");
-                return false;
-            }
-
-            trace!("verify shunt junction proof at {} next_node_root_hash = {:?} penultimate hash = {:?}: {:?}", i, &next_node_root_hash, &penultimate_trie_hash, &proof[i]);
-            let next_trie_hash = match TrieMerkleProof::verify_shunt_proof_junction(
-                &next_node_root_hash,
-                &penultimate_trie_hash,
-                &proof[i],
-            ) {
-                Some(h) => h,
-                None => {
-                    test_debug!("Unable to verify shunt junction proof at {} next_node_root_hash = {:?} penultimate hash = {:?}: {:?}", i, &next_node_root_hash, &penultimate_trie_hash, &proof[i]);
-                    return false;
-                }
-            };
-
-            // next node hash is the hash of the block from which its root came
-            trie_hash = next_trie_hash;
-            node_hash = match root_to_block.get(&trie_hash) {
-                Some(bhh) => {
-                    trace!("Block hash for {:?} is {:?}", &trie_hash, bhh);
-
-                    // safe because block header hashes are 32 bytes long
-                    TrieHash(bhh.clone().to_bytes())
-                }
-                None => {
-                    test_debug!("Trie hash not found in root-to-block map: {:?}", &trie_hash);
-                    test_debug!("root-to-block map: {:?}", &root_to_block);
-                    return false;
-                }
-            };
-
-            i += 1;
-
-            if trie_hash == *root_hash {
-                trace!(
-                    "Appeared to find the root hash early, with the remaining proof:\n{:?}",
-                    &proof[i..]
-                );
-                break;
+    /// Given a path and the root hash from which this proof was (supposedly) generated, verify
+    /// that the proof shows that the path is not mapped to any value.
+    pub fn verify_noninclusion_proof(
+        proof: &Vec<TrieMerkleProofType<T>>,
+        path: &TriePath,
+        root_hash: &TrieHash,
+        root_to_block: &HashMap<TrieHash, T>,
+    ) -> bool {
+        let entries = TrieMerkleProof::to_verifier_entries(proof);
+        match marf_verifier::verify_noninclusion(
+            &entries,
+            path.as_bytes(),
+            &root_hash.0,
+            &RootToBlockMap(root_to_block),
+        ) {
+            Ok(()) => true,
+            Err(_e) => {
+                test_debug!("Invalid proof of non-inclusion of {:?}: {}", path, &_e);
+                false
             }
         }
-
-        test_debug!("Verify proof: {:?} =?= {:?}", root_hash, &trie_hash);
-        *root_hash == trie_hash
     }
 
     /// Verify this proof
@@ -1369,11 +1014,87 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
         TrieMerkleProof::<T>::verify_proof(&self.0, &path, &marf_value, root_hash, root_to_block)
     }
 
+    /// Verify this proof of non-inclusion
+    pub fn verify_absent(
+        &self,
+        path: &TriePath,
+        root_hash: &TrieHash,
+        root_to_block: &HashMap<TrieHash, T>,
+    ) -> bool {
+        TrieMerkleProof::<T>::verify_noninclusion_proof(&self.0, &path, root_hash, root_to_block)
+    }
+
+    /// Make the first entry of a non-inclusion proof, for the node at which the walk down a path
+    /// stopped.  If it's a leaf, then its path diverged from the walked path.  Otherwise, either
+    /// its path diverged or it has no child for the next path character, so all of its children's
+    /// hashes are included.
+    fn make_terminal_proof_node(
+        storage: &mut TrieStorageConnection<T>,
+        node: &TrieNodeType,
+        chr: u8,
+    ) -> Result<TrieMerkleProofType<T>, Error> {
+        let proof_node = match node {
+            TrieNodeType::Leaf(ref data) => TrieMerkleProofType::Leaf((chr, data.clone())),
+            TrieNodeType::Node4(ref data) => TrieMerkleProofType::Terminal((
+                ProofTrieNode::try_from_trie_node(data, storage)?,
+                Trie::get_children_hashes(storage, node)?,
+            )),
+            TrieNodeType::Node16(ref data) => TrieMerkleProofType::Terminal((
+                ProofTrieNode::try_from_trie_node(data, storage)?,
+                Trie::get_children_hashes(storage, node)?,
+            )),
+            TrieNodeType::Node48(ref data) => TrieMerkleProofType::Terminal((
+                ProofTrieNode::try_from_trie_node(data.as_ref(), storage)?,
+                Trie::get_children_hashes(storage, node)?,
+            )),
+            TrieNodeType::Node256(ref data) => TrieMerkleProofType::Terminal((
+                ProofTrieNode::try_from_trie_node(data.as_ref(), storage)?,
+                Trie::get_children_hashes(storage, node)?,
+            )),
+        };
+        Ok(proof_node)
+    }
+
+    /// Put segment and shunt proofs, which were accumulated from the latest trie back to the
+    /// earliest, into proof order.
+    fn assemble_proof(
+        mut segment_proofs: Vec<Vec<TrieMerkleProofType<T>>>,
+        mut shunt_proofs: Vec<Vec<TrieMerkleProofType<T>>>,
+    ) -> Vec<TrieMerkleProofType<T>> {
+        assert_eq!(shunt_proofs.len(), segment_proofs.len());
+
+        // leaf proof needs to be first
+        segment_proofs.reverse();
+        shunt_proofs.reverse();
+
+        let mut proof = Vec::with_capacity(segment_proofs.len() + shunt_proofs.len());
+        for i in 0..shunt_proofs.len() {
+            trace!("Append segment proof\n{:?}", &segment_proofs[i]);
+            proof.append(&mut segment_proofs[i]);
+
+            trace!("Append shunt proof\n{:?}", &shunt_proofs[i]);
+            proof.append(&mut shunt_proofs[i]);
+        }
+        proof
+    }
+
     /// Walk down the trie pointed to by s until we reach a backptr or a leaf
     fn walk_to_leaf_or_backptr(
         storage: &mut TrieStorageConnection<T>,
         path: &TriePath,
     ) -> Result<(TrieCursor<T>, TrieNodeType, TriePtr), Error> {
+        match TrieMerkleProof::walk_to_end(storage, path)? {
+            ProofWalkEnd::LeafOrBackptr(cursor, node, ptr) => Ok((cursor, node, ptr)),
+            ProofWalkEnd::Absent(..) => Err(Error::NotFoundError),
+        }
+    }
+
+    /// Walk down the trie pointed to by s until we reach a backptr or a leaf, or until the walk
+    /// stops because the path isn't in this trie.
+    fn walk_to_end(
+        storage: &mut TrieStorageConnection<T>,
+        path: &TriePath,
+    ) -> Result<ProofWalkEnd<T>, Error> {
         trace!(
             "Walk path {:?} from {:?} to the first backptr",
             path,
@@ -1398,7 +1119,7 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
                         None => {
                             // end of path.
                             trace!("Found leaf {:?}", &node);
-                            return Ok((cursor, node, node_ptr));
+                            return Ok(ProofWalkEnd::LeafOrBackptr(cursor, node, node_ptr));
                         }
                     }
                 }
@@ -1409,12 +1130,12 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
                                 CursorError::PathDiverged => {
                                     // we're done -- path diverged.  No backptr-walking can help us.
                                     trace!("Path diverged -- we're done.");
-                                    return Err(Error::NotFoundError);
+                                    return Ok(ProofWalkEnd::Absent(cursor, node));
                                 }
                                 CursorError::ChrNotFound => {
                                     // node isn't present
                                     trace!("Failed to walk from {:?}", &node);
-                                    return Err(Error::NotFoundError);
+                                    return Ok(ProofWalkEnd::Absent(cursor, node));
                                 }
                                 CursorError::BackptrEncountered(ptr) => {
                                     // expect backptr
@@ -1427,7 +1148,7 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
 
                                     // we're done -- we found a backptr
                                     trace!("Found backptr {:?}", &ptr);
-                                    return Ok((cursor, node, ptr));
+                                    return Ok(ProofWalkEnd::LeafOrBackptr(cursor, node, ptr));
                                 }
                             }
                         }
@@ -1545,22 +1266,92 @@ impl<T: MarfTrieId> TrieMerkleProof<T> {
                 .clone();
         }
 
-        assert_eq!(shunt_proofs.len(), segment_proofs.len());
+        Ok(TrieMerkleProof(TrieMerkleProof::assemble_proof(
+            segment_proofs,
+            shunt_proofs,
+        )))
+    }
 
-        // leaf proof needs to be first
-        segment_proofs.reverse();
-        shunt_proofs.reverse();
+    /// Make a merkle proof of non-inclusion from a path.
+    /// The proof follows the path from the given block back through its ancestor tries, just like
+    /// a proof of inclusion, until it reaches the node at which the path stops.
+    /// If the path resolves to a leaf, return an error (ExistsError)
+    pub fn from_absent_path(
+        storage: &mut TrieStorageConnection<T>,
+        path: &TriePath,
+        root_block_header: &T,
+    ) -> Result<TrieMerkleProof<T>, Error> {
+        let mut segment_proofs = vec![];
+        let mut shunt_proofs = vec![];
+        let mut block_header = root_block_header.clone();
 
-        let mut proof = Vec::with_capacity(segment_proofs.len() + shunt_proofs.len());
-        for i in 0..shunt_proofs.len() {
-            trace!("Append segment proof\n{:?}", &segment_proofs[i]);
-            proof.append(&mut segment_proofs[i]);
+        loop {
+            storage.open_block(&block_header)?;
 
-            trace!("Append shunt proof\n{:?}", &shunt_proofs[i]);
-            proof.append(&mut shunt_proofs[i]);
+            trace!(
+                "Walk {:?} path {:?} to leaf, backptr, or end of path",
+                &storage.get_cur_block(),
+                path
+            );
+            match TrieMerkleProof::walk_to_end(storage, path)? {
+                ProofWalkEnd::Absent(cursor, reached_node) => {
+                    // make a proof to the node at which the path stops, and then from its parent
+                    // to the root
+                    let node_ptr = cursor.ptr();
+                    let mut segment_proof = vec![TrieMerkleProof::make_terminal_proof_node(
+                        storage,
+                        &reached_node,
+                        node_ptr.chr(),
+                    )?];
+                    if cursor.node_ptrs.len() > 1 {
+                        let parent_ptrs = cursor.node_ptrs[0..cursor.node_ptrs.len() - 1].to_vec();
+                        let mut parent_proof = TrieMerkleProof::make_segment_proof(
+                            storage,
+                            &parent_ptrs,
+                            node_ptr.chr(),
+                        )?;
+                        segment_proof.append(&mut parent_proof);
+                    }
+                    segment_proofs.push(segment_proof);
+
+                    let first_shunt_proof = TrieMerkleProof::make_initial_shunt_proof(storage)?;
+                    shunt_proofs.push(first_shunt_proof);
+                    break;
+                }
+                ProofWalkEnd::LeafOrBackptr(cursor, _, backptr) => {
+                    if !is_backptr(backptr.id()) {
+                        trace!("Path {:?} resolves to a leaf", path);
+                        return Err(Error::ExistsError);
+                    }
+
+                    let segment_proof = TrieMerkleProof::make_segment_proof(
+                        storage,
+                        &cursor.node_ptrs,
+                        cursor.chr().unwrap(),
+                    )?;
+                    segment_proofs.push(segment_proof);
+
+                    let shunt_proof = TrieMerkleProof::make_backptr_shunt_proof(storage, &backptr)?;
+                    shunt_proofs.push(shunt_proof);
+
+                    storage.open_block(&block_header)?;
+
+                    trace!(
+                        "Walk back for {:?} from {:?}",
+                        &backptr,
+                        &storage.get_cur_block()
+                    );
+                    block_header = storage
+                        .get_block_from_local_id(backptr.back_block())?
+                        .clone();
+                }
+            }
         }
 
-        Ok(TrieMerkleProof(proof))
+        Ok(TrieMerkleProof(TrieMerkleProof::assemble_proof(
+            segment_proofs,
+            shunt_proofs,
+        )))
     }
 
     /// Make a merkle proof of inclusion from a key/value pair.
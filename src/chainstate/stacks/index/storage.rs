@@ -31,6 +31,7 @@ use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use std::{cmp, error};
 
+use rayon::prelude::*;
 use rusqlite::{
     types::{FromSql, ToSql},
     Connection, Error as SqliteError, ErrorCode as SqliteErrorCode, OpenFlags, OptionalExtension,
@@ -408,6 +409,43 @@ pub struct TrieRAM<T: MarfTrieId> {
     parent: T,
 }
 
+/// Minimum number of nodes a TrieRAM must hold before its node hashes are calculated in parallel
+/// when it gets sealed.  Smaller tries are hashed faster on the calling thread than they can be
+/// farmed out to the thread pool.
+pub const TRIERAM_PARALLEL_HASH_MIN_NODES: usize = 4096;
+
+/// Read-only map from the block identifiers of a TrieRAM's back-pointers to their block hashes.
+/// These are loaded up front when sealing a TrieRAM, so that its node hashes can be calculated off
+/// of the thread that owns the storage connection.
+struct TrieRAMBlockMap<'a, T: MarfTrieId>(&'a HashMap<u32, T>);
+
+impl<T: MarfTrieId> BlockMap for TrieRAMBlockMap<'_, T> {
+    type TrieId = T;
+
+    fn get_block_hash(&self, id: u32) -> Result<T, Error> {
+        self.0.get(&id).cloned().ok_or(Error::NotFoundError)
+    }
+
+    fn get_block_hash_caching(&mut self, id: u32) -> Result<&T, Error> {
+        self.0.get(&id).ok_or(Error::NotFoundError)
+    }
+
+    fn is_block_hash_cached(&self, id: u32) -> bool {
+        self.0.contains_key(&id)
+    }
+
+    fn get_block_id(&self, bhh: &T) -> Result<u32, Error> {
+        self.0
+            .iter()
+            .find_map(|(id, block_hash)| if block_hash == bhh { Some(*id) } else { None })
+            .ok_or(Error::NotFoundError)
+    }
+
+    fn get_block_id_caching(&mut self, bhh: &T) -> Result<u32, Error> {
+        self.get_block_id(bhh)
+    }
+}
+
 /// Trie in RAM without the serialization overhead
 impl<T: MarfTrieId> TrieRAM<T> {
     pub fn new(block_header: &T, capacity_hint: usize, parent: &T) -> TrieRAM<T> {
@@ -649,7 +687,11 @@ impl<T: MarfTrieId> TrieRAM<T> {
     ) -> Result<TrieHash, Error> {
         // find trie root hash
         debug!("Calculate trie root hash");
-        let root_trie_hash = self.calculate_node_hashes(storage_tx, 0)?;
+        let root_trie_hash = if self.data.len() >= TRIERAM_PARALLEL_HASH_MIN_NODES {
+            self.calculate_node_hashes_parallel(storage_tx)?
+        } else {
+            self.calculate_node_hashes(storage_tx, 0)?
+        };
 
         // find marf root hash -- the hash of the trie root node hash, and the hashes of the
         // geometric series of ancestor tries.  Because the trie is already in the process of
@@ -831,6 +873,136 @@ impl<T: MarfTrieId> TrieRAM<T> {
         }
     }
 
+    /// Calculate all node hashes in this `TrieRAM` like `calculate_node_hashes()` does, but hash
+    /// the subtries under each of the root node's children in parallel on the rayon thread pool.
+    /// The resulting hashes are bit-for-bit identical to those from `calculate_node_hashes()`.
+    /// Returns the node hash of the root node.
+    fn calculate_node_hashes_parallel(
+        &mut self,
+        storage_tx: &mut TrieStorageTransaction<T>,
+    ) -> Result<TrieHash, Error> {
+        let start_time = storage_tx.bench.write_children_hashes_start();
+        let store_hashes =
+            TrieHashCalculationMode::Deferred == storage_tx.deref().hash_calculation_mode;
+
+        // The worker threads can't share the storage connection, so load the block hash of every
+        // back-pointer in this trie beforehand.
+        let mut block_hashes = HashMap::new();
+        for (node, _) in self.data.iter() {
+            if node.is_leaf() {
+                continue;
+            }
+            for ptr in node.ptrs().iter() {
+                if is_backptr(ptr.id()) && !block_hashes.contains_key(&ptr.back_block()) {
+                    let block_hash = storage_tx.get_block_hash_caching(ptr.back_block())?;
+                    block_hashes.insert(ptr.back_block(), block_hash.clone());
+                }
+            }
+        }
+
+        let (root, root_hash) = self.get_nodetype(0)?;
+        if root.is_leaf() {
+            return Ok(root_hash.clone());
+        }
+
+        let data = self.data.as_slice();
+        let child_hashes = root
+            .ptrs()
+            .par_iter()
+            .map(|ptr| {
+                let mut updates = vec![];
+                let child_hash =
+                    TrieRAM::hash_subtrie(data, &block_hashes, ptr, store_hashes, &mut updates)?;
+                Ok((child_hash, updates))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut hasher = TrieHasher::new();
+        root.write_consensus_bytes(&mut TrieRAMBlockMap(&block_hashes), &mut hasher)
+            .expect("IO Failure pushing to hasher.");
+
+        let mut all_updates = vec![];
+        for (child_hash, updates) in child_hashes.into_iter() {
+            hasher.write_all(child_hash.as_bytes())?;
+            all_updates.extend(updates);
+        }
+
+        let node_hash = {
+            let mut buf = [0u8; 32];
+            buf.copy_from_slice(hasher.finalize().as_slice());
+            TrieHash(buf)
+        };
+
+        for (node_ptr, hash) in all_updates.into_iter() {
+            self.write_node_hash(node_ptr, hash)?;
+        }
+
+        storage_tx
+            .bench
+            .write_children_hashes_finish(start_time, true);
+
+        Ok(node_hash)
+    }
+
+    /// Calculate the hash of the child of a node that `ptr` points to, using only the nodes in
+    /// `data` and the preloaded back-pointer block hashes in `block_hashes`.  If `store_hashes` is
+    /// true, then the hash of each non-leaf node in this trie that gets visited is appended to
+    /// `updates` so the caller can write it back.
+    fn hash_subtrie(
+        data: &[(TrieNodeType, TrieHash)],
+        block_hashes: &HashMap<u32, T>,
+        ptr: &TriePtr,
+        store_hashes: bool,
+        updates: &mut Vec<(u32, TrieHash)>,
+    ) -> Result<TrieHash, Error> {
+        if ptr.id() == TrieNodeID::Empty as u8 {
+            // hash of empty string
+            return Ok(TrieHash::from_data(&[]));
+        }
+        if is_backptr(ptr.id()) {
+            // hash is that of the block that contains this node
+            let block_hash = block_hashes
+                .get(&ptr.back_block())
+                .ok_or(Error::NotFoundError)?;
+            return Ok(TrieHash::from_bytes(block_hash.as_bytes()).expect("FATAL: bad block hash"));
+        }
+
+        let (node, node_hash) = data.get(ptr.ptr() as usize).ok_or_else(|| {
+            error!(
+                "TrieRAM hash_subtrie: Failed to read node: {} >= {}",
+                ptr.ptr(),
+                data.len()
+            );
+            Error::NotFoundError
+        })?;
+
+        if node.is_leaf() {
+            // we already have the hash of the leaf
+            return Ok(node_hash.clone());
+        }
+
+        let mut hasher = TrieHasher::new();
+        node.write_consensus_bytes(&mut TrieRAMBlockMap(block_hashes), &mut hasher)
+            .expect("IO Failure pushing to hasher.");
+
+        for child_ptr in node.ptrs().iter() {
+            let child_hash =
+                TrieRAM::hash_subtrie(data, block_hashes, child_ptr, store_hashes, updates)?;
+            hasher.write_all(child_hash.as_bytes())?;
+        }
+
+        let hash = {
+            let mut buf = [0u8; 32];
+            buf.copy_from_slice(hasher.finalize().as_slice());
+            TrieHash(buf)
+        };
+
+        if store_hashes {
+            updates.push((ptr.ptr(), hash.clone()));
+        }
+        Ok(hash)
+    }
+
     /// Walk through the buffered TrieNodes and dump them to f.
     /// This consumes this TrieRAM instance.
     fn dump_consume<F: Write + Seek>(mut self, f: &mut F) -> Result<u64, Error> {
@@ -258,6 +258,42 @@ fn marf_insert_same_leaf_different_block_100() {
     }
 }
 
+#[test]
+fn marf_insert_order_changes_root_hash() {
+    // Node4, Node16 and Node48 store their children in insertion order, and that order is
+    // hashed, so the same leaves inserted in a different order yield a different root hash.
+    // Batched insertion must therefore keep the caller's order.
+    let block_header = BlockHeaderHash::from_bytes(&[0u8; 32]).unwrap();
+    let mut path_1_bytes = [0u8; 32];
+    path_1_bytes[31] = 1;
+    let mut path_2_bytes = [0u8; 32];
+    path_2_bytes[31] = 2;
+    let path_1 = TriePath::from_bytes(&path_1_bytes).unwrap();
+    let path_2 = TriePath::from_bytes(&path_2_bytes).unwrap();
+
+    let root_hash = |paths: &[&TriePath]| {
+        let f = TrieFileStorage::new_memory(MARFOpenOpts::default()).unwrap();
+        let mut marf = MARF::from_storage(f);
+        marf.begin(&BlockHeaderHash::sentinel(), &block_header)
+            .unwrap();
+        for path in paths.iter() {
+            let value = TrieLeaf::new(&vec![], &[path.as_bytes()[31]; 40].to_vec());
+            marf.insert_raw((*path).clone(), value).unwrap();
+        }
+        marf.commit().unwrap();
+        marf.get_root_hash_at(&block_header).unwrap()
+    };
+
+    assert_eq!(
+        root_hash(&[&path_1, &path_2]),
+        root_hash(&[&path_1, &path_2])
+    );
+    assert_ne!(
+        root_hash(&[&path_1, &path_2]),
+        root_hash(&[&path_2, &path_1])
+    );
+}
+
 #[test]
 fn marf_insert_leaf_sequence_2() {
     let mut last_root_hashes = None;
@@ -14,9 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
+
 use crate::chainstate::stacks::index::marf::*;
 use crate::chainstate::stacks::index::test::*;
 use crate::chainstate::stacks::index::*;
+use crate::codec::StacksMessageCodec;
 
 use super::*;
 
@@ -198,3 +201,184 @@ fn ncc_verifier_catches_stale_proof() {
     println!("DEBUG: verify(old_v)");
     assert!(!proof_5.verify(&triepath_4, &marf_value_4, &root_hash_5, &root_to_block));
 }
+
+/// Make a MARF whose keys are spread across several blocks, so that walks down absent paths
+/// have to follow back-pointers into ancestor tries.  Returns the MARF, its blocks, and the keys
+/// and values inserted.
+fn make_proof_test_marf() -> (
+    MARF<BlockHeaderHash>,
+    Vec<BlockHeaderHash>,
+    Vec<(String, String)>,
+) {
+    let marf_opts = MARFOpenOpts::default();
+    let mut m = MARF::from_path(":memory:", marf_opts).unwrap();
+
+    let mut blocks = vec![];
+    let mut entries = vec![];
+    let mut last_block = BlockHeaderHash::sentinel();
+    for i in 0..4u8 {
+        let block = BlockHeaderHash([i + 1; 32]);
+        m.begin(&last_block, &block).unwrap();
+        for j in 0..32 {
+            let key = format!("key-{}-{}", i, j);
+            let value = format!("value-{}-{}", i, j);
+            m.insert(&key, MARFValue::from_value(&value)).unwrap();
+            entries.push((key, value));
+        }
+        m.commit().unwrap();
+        blocks.push(block.clone());
+        last_block = block;
+    }
+    (m, blocks, entries)
+}
+
+#[test]
+fn marf_noninclusion_proofs() {
+    let (mut m, blocks, entries) = make_proof_test_marf();
+    let tip = blocks.last().unwrap().clone();
+    let root_hash = m.get_root_hash_at(&tip).unwrap();
+    let root_to_block = m
+        .borrow_storage_backend()
+        .read_root_to_block_table()
+        .unwrap();
+
+    let mut followed_backptr = false;
+    for i in 0..64 {
+        let absent_key = format!("absent-{}", i);
+        let path = TriePath::from_key(&absent_key);
+        let proof = TrieMerkleProof::from_absent_path(&mut m.borrow_storage_backend(), &path, &tip)
+            .unwrap();
+
+        // a proof that visits more than one trie has more than one initial shunt proof entry
+        if proof
+            .iter()
+            .filter(|entry| match entry {
+                TrieMerkleProofType::Shunt((idx, _)) => *idx > 0,
+                _ => false,
+            })
+            .count()
+            > 0
+        {
+            followed_backptr = true;
+        }
+
+        assert!(proof.verify_absent(&path, &root_hash, &root_to_block));
+
+        // not valid for a different root
+        let old_root_hash = m.get_root_hash_at(&blocks[1]).unwrap();
+        assert!(!proof.verify_absent(&path, &old_root_hash, &root_to_block));
+
+        // not valid for a different key
+        let other_path = TriePath::from_key(&entries[i].0);
+        assert!(!proof.verify_absent(&other_path, &root_hash, &root_to_block));
+
+        // survives a round-trip through the codec
+        let mut proof_bytes = vec![];
+        proof.consensus_serialize(&mut proof_bytes).unwrap();
+        let decoded_proof =
+            TrieMerkleProof::<BlockHeaderHash>::consensus_deserialize(&mut &proof_bytes[..])
+                .unwrap();
+        assert!(decoded_proof.verify_absent(&path, &root_hash, &root_to_block));
+    }
+    assert!(followed_backptr);
+
+    // can't prove that present keys are absent
+    for (key, value) in entries.iter() {
+        let path = TriePath::from_key(key);
+        match TrieMerkleProof::from_absent_path(&mut m.borrow_storage_backend(), &path, &tip) {
+            Err(Error::ExistsError) => {}
+            x => panic!("Expected ExistsError, got {:?}", x),
+        }
+
+        // an inclusion proof is not a proof of non-inclusion
+        let proof =
+            TrieMerkleProof::from_entry(&mut m.borrow_storage_backend(), key, value, &tip).unwrap();
+        assert!(proof.verify(
+            &path,
+            &MARFValue::from_value(value),
+            &root_hash,
+            &root_to_block
+        ));
+        assert!(!proof.verify_absent(&path, &root_hash, &root_to_block));
+    }
+}
+
+#[test]
+fn marf_batch_proofs() {
+    let (mut m, blocks, entries) = make_proof_test_marf();
+    let tip = blocks.last().unwrap().clone();
+    let root_hash = m.get_root_hash_at(&tip).unwrap();
+    let root_to_block = m
+        .borrow_storage_backend()
+        .read_root_to_block_table()
+        .unwrap();
+
+    let mut keys = vec![];
+    for i in 0..16 {
+        keys.push(entries[i * 8].0.clone());
+        keys.push(format!("absent-{}", i));
+    }
+
+    let (values, batch_proof) = m.get_batch_with_proof(&tip, &keys).unwrap();
+    assert_eq!(values.len(), keys.len());
+    assert_eq!(batch_proof.proofs.len(), keys.len());
+
+    let mut claims = vec![];
+    for (i, value_opt) in values.iter().enumerate() {
+        if i % 2 == 0 {
+            assert_eq!(
+                value_opt.as_ref(),
+                Some(&MARFValue::from_value(&entries[(i / 2) * 8].1))
+            );
+        } else {
+            assert!(value_opt.is_none());
+        }
+        claims.push((TriePath::from_key(&keys[i]), value_opt.clone()));
+    }
+
+    // shared entries (e.g. the shunt proofs and the root nodes) are only stored once
+    let total_entries: usize = batch_proof.proofs.iter().map(|p| p.len()).sum();
+    assert!(batch_proof.entries.len() < total_entries);
+
+    assert!(batch_proof.verify(&claims, &root_hash, &root_to_block));
+
+    // each reassembled proof is a valid single-key proof
+    for (i, (path, value_opt)) in claims.iter().enumerate() {
+        let proof = batch_proof.get_proof(i).unwrap();
+        match value_opt {
+            Some(value) => assert!(proof.verify(path, value, &root_hash, &root_to_block)),
+            None => assert!(proof.verify_absent(path, &root_hash, &root_to_block)),
+        }
+    }
+
+    // swapping claims invalidates the batch
+    let mut bad_claims = claims.clone();
+    bad_claims.swap(0, 1);
+    assert!(!batch_proof.verify(&bad_claims, &root_hash, &root_to_block));
+
+    let mut bad_claims = claims.clone();
+    bad_claims[1].1 = Some(MARFValue::from_value(&"nope".to_string()));
+    assert!(!batch_proof.verify(&bad_claims, &root_hash, &root_to_block));
+
+    // the standalone verifier decodes and checks the same bytes
+    let mut proof_bytes = vec![];
+    batch_proof.consensus_serialize(&mut proof_bytes).unwrap();
+    let decoded_proof =
+        TrieMerkleBatchProof::<BlockHeaderHash>::consensus_deserialize(&mut &proof_bytes[..])
+            .unwrap();
+    assert_eq!(decoded_proof.entries, batch_proof.entries);
+    assert_eq!(decoded_proof.proofs, batch_proof.proofs);
+
+    let verifier_proof = marf_verifier::MARFBatchProof::from_hex(&batch_proof.to_hex()).unwrap();
+    let verifier_claims: Vec<_> = claims
+        .iter()
+        .map(|(path, value_opt)| (path.to_bytes(), value_opt.as_ref().map(|v| v.0)))
+        .collect();
+    let verifier_root_to_block: HashMap<[u8; 32], [u8; 32]> = root_to_block
+        .iter()
+        .map(|(root, block)| (root.0, block.0))
+        .collect();
+    verifier_proof
+        .verify(&verifier_claims, &root_hash.0, &verifier_root_to_block)
+        .unwrap();
+}
@@ -337,3 +337,43 @@ fn load_store_trie_4_256_same() {
 fn load_store_trie_4_256_unique() {
     load_store_trie_m_n_same(4, 256, false);
 }
+
+/// Tries big enough to get hashed on the thread pool when sealed must get the same root hashes
+/// as they would if their nodes were hashed as the leaves were inserted.
+#[test]
+fn parallel_seal_matches_immediate_hashing() {
+    let num_keys = TRIERAM_PARALLEL_HASH_MIN_NODES as u64 + 1000;
+    let block_ids = [StacksBlockId([0x01; 32]), StacksBlockId([0x02; 32])];
+
+    let mut root_hashes = vec![];
+    for hash_mode in [
+        TrieHashCalculationMode::Immediate,
+        TrieHashCalculationMode::Deferred,
+    ] {
+        let marf_opts = MARFOpenOpts::new(hash_mode, "noop", false);
+        let mut marf = MARF::from_path(":memory:", marf_opts).unwrap();
+
+        let mut parent = StacksBlockId::sentinel();
+        let mut hashes = vec![];
+        for (i, block_id) in block_ids.iter().enumerate() {
+            marf.begin(&parent, block_id).unwrap();
+
+            // overwrite half of the last block's keys, so the trie has back-pointers
+            let start = (i as u64) * num_keys / 2;
+            let keys: Vec<String> = (start..start + num_keys)
+                .map(|k| format!("parallel-seal-key-{}", k))
+                .collect();
+            let values: Vec<MARFValue> = (start..start + num_keys)
+                .map(|k| MARFValue::from((k as u32) + (i as u32)))
+                .collect();
+            marf.insert_batch(&keys, values).unwrap();
+
+            hashes.push(marf.seal().unwrap());
+            marf.commit().unwrap();
+            parent = block_id.clone();
+        }
+        root_hashes.push(hashes);
+    }
+
+    assert_eq!(root_hashes[0], root_hashes[1]);
+}
@@ -401,6 +401,40 @@ impl<'a> ClarityBackingStore for ReadOnlyMarfStore<'a> {
             })
     }
 
+    fn get_batch_with_proof(
+        &mut self,
+        keys: &[String],
+    ) -> InterpreterResult<Option<(Vec<Option<String>>, Vec<u8>)>> {
+        let (marf_values, proof) = match self.marf.get_batch_with_proof(&self.chain_tip, keys) {
+            Ok(x) => x,
+            Err(Error::NotFoundError) => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(InterpreterError::MarfFailure(e.to_string()).into());
+            }
+        };
+        let values = marf_values
+            .into_iter()
+            .map(|marf_value_opt| match marf_value_opt {
+                Some(marf_value) => {
+                    let side_key = marf_value.to_hex();
+                    SqliteConnection::get(self.get_side_store(), &side_key)
+                        .map(Some)
+                        .ok_or_else(|| {
+                            InterpreterError::MarfFailure(format!(
+                                "MARF contained value_hash not found in side storage: {}",
+                                side_key
+                            ))
+                            .into()
+                        })
+                }
+                None => Ok(None),
+            })
+            .collect::<InterpreterResult<Vec<_>>>()?;
+        Ok(Some((values, proof.serialize_to_vec())))
+    }
+
     fn get(&mut self, key: &str) -> Option<String> {
         trace!("MarfedKV get: {:?} tip={}", key, &self.chain_tip);
         self.marf
@@ -585,6 +619,40 @@ impl<'a> ClarityBackingStore for WritableMarfStore<'a> {
             })
     }
 
+    fn get_batch_with_proof(
+        &mut self,
+        keys: &[String],
+    ) -> InterpreterResult<Option<(Vec<Option<String>>, Vec<u8>)>> {
+        let (marf_values, proof) = match self.marf.get_batch_with_proof(&self.chain_tip, keys) {
+            Ok(x) => x,
+            Err(Error::NotFoundError) => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(InterpreterError::MarfFailure(e.to_string()).into());
+            }
+        };
+        let values = marf_values
+            .into_iter()
+            .map(|marf_value_opt| match marf_value_opt {
+                Some(marf_value) => {
+                    let side_key = marf_value.to_hex();
+                    SqliteConnection::get(self.marf.sqlite_tx(), &side_key)
+                        .map(Some)
+                        .ok_or_else(|| {
+                            InterpreterError::MarfFailure(format!(
+                                "MARF contained value_hash not found in side storage: {}",
+                                side_key
+                            ))
+                            .into()
+                        })
+                }
+                None => Ok(None),
+            })
+            .collect::<InterpreterResult<Vec<_>>>()?;
+        Ok(Some((values, proof.serialize_to_vec())))
+    }
+
     fn get_side_store(&mut self) -> &Connection {
         self.marf.sqlite_tx()
     }
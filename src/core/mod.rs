@@ -112,6 +112,20 @@ pub const BITCOIN_REGTEST_FIRST_BLOCK_TIMESTAMP: u32 = 0;
 pub const BITCOIN_REGTEST_FIRST_BLOCK_HASH: &str =
     "0000000000000000000000000000000000000000000000000000000000000000";
 
+/// Burnchain heights at which transactions with native segwit (p2wpkh, p2wsh, p2tr) outputs
+/// start being considered as burnchain operations.  Such transactions were always ignored
+/// before, so accepting them is a consensus change; it is not yet scheduled on mainnet or testnet.
+pub const BITCOIN_MAINNET_SEGWIT_OUTPUTS_HEIGHT: u64 = u64::MAX;
+pub const BITCOIN_TESTNET_SEGWIT_OUTPUTS_HEIGHT: u64 = u64::MAX;
+pub const BITCOIN_REGTEST_SEGWIT_OUTPUTS_HEIGHT: u64 = 0;
+
+/// Burnchain heights at which DelegateStx operations start being accepted by the sortition DB.
+/// Like the segwit outputs height above, this is a consensus change that is not yet scheduled on
+/// mainnet or testnet.  DelegateStx operations must also occur in Stacks 2.05 or later.
+pub const BITCOIN_MAINNET_DELEGATE_STX_HEIGHT: u64 = u64::MAX;
+pub const BITCOIN_TESTNET_DELEGATE_STX_HEIGHT: u64 = u64::MAX;
+pub const BITCOIN_REGTEST_DELEGATE_STX_HEIGHT: u64 = 0;
+
 pub const FIRST_STACKS_BLOCK_HASH: BlockHeaderHash = BlockHeaderHash([0u8; 32]);
 pub const EMPTY_MICROBLOCK_PARENT_HASH: BlockHeaderHash = BlockHeaderHash([0u8; 32]);
 
@@ -18,6 +18,7 @@ pub mod fee_rate_fuzzer;
 pub mod fee_scalar;
 pub mod metrics;
 pub mod pessimistic;
+pub mod replay_report;
 
 #[cfg(test)]
 pub mod tests;
@@ -0,0 +1,250 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+
+use clarity::vm::costs::ExecutionCost;
+
+use crate::chainstate::stacks::db::StacksEpochReceipt;
+use crate::chainstate::stacks::events::TransactionOrigin;
+use crate::chainstate::stacks::TransactionPayload;
+use crate::core::{StacksEpochId, STACKS_EPOCHS_MAINNET};
+
+use super::metrics::PROPORTION_RESOLUTION;
+
+/// Number of transactions and contracts listed per block, if not otherwise specified
+pub const DEFAULT_COST_REPORT_TOP_N: usize = 5;
+
+/// A transaction's share of its block's execution cost
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TxCostEntry {
+    pub txid: String,
+    /// The contract the transaction called or deployed, if any
+    pub contract: Option<String>,
+    pub cost: ExecutionCost,
+    /// Sum of the proportions of each block limit dimension this transaction consumed, scaled
+    /// by `PROPORTION_RESOLUTION`
+    pub scalar_cost: u64,
+}
+
+/// The execution cost consumed by all of a block's calls to, and deploys of, a single contract
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractCostEntry {
+    pub contract: String,
+    pub tx_count: u64,
+    pub cost: ExecutionCost,
+    /// Sum of the proportions of each block limit dimension this contract consumed, scaled
+    /// by `PROPORTION_RESOLUTION`
+    pub scalar_cost: u64,
+}
+
+/// One replayed block's execution cost, compared against the mainnet block limit for the epoch
+/// it was evaluated in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockCostReport {
+    pub index_block_hash: String,
+    pub block_height: u64,
+    pub burn_block_height: u32,
+    pub evaluated_epoch: String,
+    pub tx_count: u64,
+    /// Cost of the anchored block plus the parent microblock stream it confirmed
+    pub cost: ExecutionCost,
+    pub parent_microblocks_cost: ExecutionCost,
+    pub block_limit: ExecutionCost,
+    pub top_transactions: Vec<TxCostEntry>,
+    pub top_contracts: Vec<ContractCostEntry>,
+}
+
+/// Output format of a cost report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CostReportFormat {
+    /// One row per block, with the top transactions and contracts packed into `;`-separated
+    /// columns
+    Csv,
+    /// One JSON object per line per block
+    Json,
+}
+
+impl FromStr for CostReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CostReportFormat, String> {
+        match s {
+            "csv" => Ok(CostReportFormat::Csv),
+            "json" => Ok(CostReportFormat::Json),
+            _ => Err(format!("Unknown cost report format '{}'", s)),
+        }
+    }
+}
+
+/// Mainnet block limit in effect for blocks evaluated in `epoch_id`
+pub fn mainnet_block_limit(epoch_id: StacksEpochId) -> ExecutionCost {
+    STACKS_EPOCHS_MAINNET
+        .iter()
+        .find(|epoch| epoch.epoch_id == epoch_id)
+        .map(|epoch| epoch.block_limit.clone())
+        .unwrap_or_else(ExecutionCost::max_value)
+}
+
+/// Name of the contract a transaction called or deployed
+fn tx_contract(origin: &TransactionOrigin) -> Option<String> {
+    match origin {
+        TransactionOrigin::Stacks(tx) => match &tx.payload {
+            TransactionPayload::ContractCall(cc) => Some(cc.contract_identifier().to_string()),
+            TransactionPayload::SmartContract(sc) => {
+                Some(format!("{}.{}", &tx.origin_address(), &sc.name))
+            }
+            _ => None,
+        },
+        TransactionOrigin::Burn(_) => None,
+    }
+}
+
+/// Order by descending scalar cost
+fn by_scalar_cost(a: u64, b: u64) -> Ordering {
+    b.cmp(&a)
+}
+
+impl BlockCostReport {
+    /// Summarize a block's receipt, listing at most `top_n` of its most expensive transactions
+    /// and contracts.
+    pub fn from_receipt(receipt: &StacksEpochReceipt, top_n: usize) -> BlockCostReport {
+        let block_limit = mainnet_block_limit(receipt.evaluated_epoch);
+
+        let mut cost = receipt.anchored_block_cost.clone();
+        cost.add(&receipt.parent_microblocks_cost)
+            .expect("BUG: block cost overflow");
+
+        let mut transactions = vec![];
+        let mut contracts: HashMap<String, (u64, ExecutionCost)> = HashMap::new();
+        for tx_receipt in receipt.tx_receipts.iter() {
+            let contract = tx_contract(&tx_receipt.transaction);
+            if let Some(contract) = contract.as_ref() {
+                let (tx_count, contract_cost) = contracts
+                    .entry(contract.clone())
+                    .or_insert_with(|| (0, ExecutionCost::zero()));
+                *tx_count += 1;
+                contract_cost
+                    .add(&tx_receipt.execution_cost)
+                    .expect("BUG: contract cost overflow");
+            }
+            transactions.push(TxCostEntry {
+                txid: tx_receipt.transaction.txid().to_string(),
+                contract,
+                cost: tx_receipt.execution_cost.clone(),
+                scalar_cost: tx_receipt
+                    .execution_cost
+                    .proportion_dot_product(&block_limit, PROPORTION_RESOLUTION),
+            });
+        }
+
+        transactions.sort_by(|a, b| by_scalar_cost(a.scalar_cost, b.scalar_cost));
+        transactions.truncate(top_n);
+
+        let mut top_contracts: Vec<_> = contracts
+            .into_iter()
+            .map(|(contract, (tx_count, cost))| ContractCostEntry {
+                scalar_cost: cost.proportion_dot_product(&block_limit, PROPORTION_RESOLUTION),
+                contract,
+                tx_count,
+                cost,
+            })
+            .collect();
+        top_contracts.sort_by(|a, b| {
+            by_scalar_cost(a.scalar_cost, b.scalar_cost).then_with(|| a.contract.cmp(&b.contract))
+        });
+        top_contracts.truncate(top_n);
+
+        BlockCostReport {
+            index_block_hash: receipt.header.index_block_hash().to_string(),
+            block_height: receipt.header.stacks_block_height,
+            burn_block_height: receipt.header.burn_header_height,
+            evaluated_epoch: receipt.evaluated_epoch.to_string(),
+            tx_count: receipt.tx_receipts.len() as u64,
+            cost,
+            parent_microblocks_cost: receipt.parent_microblocks_cost.clone(),
+            block_limit,
+            top_transactions: transactions,
+            top_contracts,
+        }
+    }
+
+    const CSV_HEADER: &'static str = "index_block_hash,block_height,burn_block_height,evaluated_epoch,tx_count,runtime,runtime_limit,read_count,read_count_limit,read_length,read_length_limit,write_count,write_count_limit,write_length,write_length_limit,top_transactions,top_contracts";
+
+    /// Render as a CSV row matching `CSV_HEADER`.  Each top transaction is listed as
+    /// `txid:scalar_cost`, and each top contract as `contract:tx_count:scalar_cost`.
+    fn to_csv_row(&self) -> String {
+        let top_transactions: Vec<_> = self
+            .top_transactions
+            .iter()
+            .map(|tx| format!("{}:{}", &tx.txid, tx.scalar_cost))
+            .collect();
+        let top_contracts: Vec<_> = self
+            .top_contracts
+            .iter()
+            .map(|c| format!("{}:{}:{}", &c.contract, c.tx_count, c.scalar_cost))
+            .collect();
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            &self.index_block_hash,
+            self.block_height,
+            self.burn_block_height,
+            &self.evaluated_epoch,
+            self.tx_count,
+            self.cost.runtime,
+            self.block_limit.runtime,
+            self.cost.read_count,
+            self.block_limit.read_count,
+            self.cost.read_length,
+            self.block_limit.read_length,
+            self.cost.write_count,
+            self.block_limit.write_count,
+            self.cost.write_length,
+            self.block_limit.write_length,
+            top_transactions.join(";"),
+            top_contracts.join(";")
+        )
+    }
+}
+
+/// Writes a `BlockCostReport` per block to `W` in the given format
+pub struct CostReportWriter<W: Write> {
+    out: W,
+    format: CostReportFormat,
+    wrote_header: bool,
+}
+
+impl<W: Write> CostReportWriter<W> {
+    pub fn new(out: W, format: CostReportFormat) -> CostReportWriter<W> {
+        CostReportWriter {
+            out,
+            format,
+            wrote_header: false,
+        }
+    }
+
+    pub fn write_block(&mut self, report: &BlockCostReport) -> Result<(), io::Error> {
+        match self.format {
+            CostReportFormat::Csv => {
+                if !self.wrote_header {
+                    writeln!(self.out, "{}", BlockCostReport::CSV_HEADER)?;
+                    self.wrote_header = true;
+                }
+                writeln!(self.out, "{}", report.to_csv_row())
+            }
+            CostReportFormat::Json => {
+                serde_json::to_writer(&mut self.out, report)?;
+                writeln!(self.out)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
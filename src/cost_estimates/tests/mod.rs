@@ -6,6 +6,7 @@ pub mod fee_medians;
 pub mod fee_rate_fuzzer;
 pub mod fee_scalar;
 pub mod metrics;
+pub mod replay_report;
 
 #[test]
 fn fee_rate_estimate_math_units() {
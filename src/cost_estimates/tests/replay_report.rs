@@ -0,0 +1,130 @@
+use clarity::vm::costs::ExecutionCost;
+
+use crate::chainstate::stacks::events::StacksTransactionReceipt;
+use crate::chainstate::stacks::{
+    CoinbasePayload, StacksTransaction, TransactionAuth, TransactionContractCall,
+    TransactionPayload, TransactionSpendingCondition, TransactionVersion,
+};
+use crate::core::{StacksEpochId, BLOCK_LIMIT_MAINNET_20, BLOCK_LIMIT_MAINNET_205};
+use crate::cost_estimates::replay_report::*;
+use crate::cost_estimates::tests::common::*;
+use crate::vm::Value;
+use stacks_common::types::chainstate::StacksAddress;
+use stacks_common::util::hash::Hash160;
+
+fn make_dummy_coinbase_tx() -> StacksTransactionReceipt {
+    StacksTransactionReceipt::from_coinbase(StacksTransaction::new(
+        TransactionVersion::Mainnet,
+        TransactionAuth::Standard(TransactionSpendingCondition::new_initial_sighash()),
+        TransactionPayload::Coinbase(CoinbasePayload([0; 32])),
+    ))
+}
+
+fn make_dummy_cc_tx(
+    contract_name: &str,
+    function_name: &str,
+    execution_cost: ExecutionCost,
+) -> StacksTransactionReceipt {
+    let tx = StacksTransaction::new(
+        TransactionVersion::Mainnet,
+        TransactionAuth::Standard(TransactionSpendingCondition::new_initial_sighash()),
+        TransactionPayload::ContractCall(TransactionContractCall {
+            address: StacksAddress::new(0, Hash160([0; 20])),
+            contract_name: contract_name.into(),
+            function_name: function_name.into(),
+            function_args: vec![],
+        }),
+    );
+
+    StacksTransactionReceipt::from_contract_call(
+        tx,
+        vec![],
+        Value::okay(Value::Bool(true)).unwrap(),
+        0,
+        execution_cost,
+    )
+}
+
+fn make_cost(runtime: u64) -> ExecutionCost {
+    ExecutionCost {
+        write_length: 1,
+        write_count: 1,
+        read_length: 1,
+        read_count: 1,
+        runtime,
+    }
+}
+
+#[test]
+fn test_block_cost_report_ranks_txs_and_contracts() {
+    let mut receipt = make_block_receipt(vec![
+        make_dummy_coinbase_tx(),
+        make_dummy_cc_tx("contract-1", "cheap", make_cost(1_000)),
+        make_dummy_cc_tx("contract-2", "pricey", make_cost(1_000_000_000)),
+        make_dummy_cc_tx("contract-1", "medium", make_cost(500_000_000)),
+    ]);
+    receipt.anchored_block_cost = make_cost(1_500_001_000);
+    receipt.parent_microblocks_cost = make_cost(10);
+
+    let report = BlockCostReport::from_receipt(&receipt, 2);
+    assert_eq!(report.tx_count, 4);
+    assert_eq!(report.evaluated_epoch, "2.0");
+    assert_eq!(report.block_limit, BLOCK_LIMIT_MAINNET_20);
+    assert_eq!(report.cost.runtime, 1_500_001_010);
+    assert_eq!(report.cost.write_count, 2);
+
+    assert_eq!(report.top_transactions.len(), 2);
+    assert_eq!(report.top_transactions[0].cost.runtime, 1_000_000_000);
+    assert_eq!(report.top_transactions[1].cost.runtime, 500_000_000);
+    assert!(report.top_transactions[0].scalar_cost > report.top_transactions[1].scalar_cost);
+
+    // contract-1's two calls are summed, but still cost less than contract-2's one
+    assert_eq!(report.top_contracts.len(), 2);
+    assert!(report.top_contracts[0].contract.ends_with(".contract-2"));
+    assert_eq!(report.top_contracts[0].tx_count, 1);
+    assert!(report.top_contracts[1].contract.ends_with(".contract-1"));
+    assert_eq!(report.top_contracts[1].tx_count, 2);
+    assert_eq!(report.top_contracts[1].cost.runtime, 500_001_000);
+    assert_eq!(report.top_contracts[1].cost.write_count, 2);
+}
+
+#[test]
+fn test_block_cost_report_uses_epoch_block_limit() {
+    let mut receipt = make_block_receipt(vec![make_dummy_coinbase_tx()]);
+    receipt.evaluated_epoch = StacksEpochId::Epoch2_05;
+
+    let report = BlockCostReport::from_receipt(&receipt, DEFAULT_COST_REPORT_TOP_N);
+    assert_eq!(report.block_limit, BLOCK_LIMIT_MAINNET_205);
+    assert_eq!(report.top_transactions.len(), 1);
+    assert_eq!(report.top_contracts.len(), 0);
+}
+
+#[test]
+fn test_cost_report_writer_formats() {
+    let receipt = make_block_receipt(vec![
+        make_dummy_coinbase_tx(),
+        make_dummy_cc_tx("contract-1", "func1", make_cost(10)),
+    ]);
+    let report = BlockCostReport::from_receipt(&receipt, DEFAULT_COST_REPORT_TOP_N);
+
+    let mut writer = CostReportWriter::new(vec![], CostReportFormat::Csv);
+    writer.write_block(&report).unwrap();
+    writer.write_block(&report).unwrap();
+    let csv = String::from_utf8(writer.into_inner()).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("index_block_hash,"));
+    assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+    assert!(lines[1].starts_with(&report.index_block_hash));
+    assert_eq!(lines[1], lines[2]);
+
+    let mut writer = CostReportWriter::new(vec![], CostReportFormat::Json);
+    writer.write_block(&report).unwrap();
+    let json = String::from_utf8(writer.into_inner()).unwrap();
+    let parsed: BlockCostReport = serde_json::from_str(json.trim()).unwrap();
+    assert_eq!(parsed, report);
+
+    assert_eq!("csv".parse(), Ok(CostReportFormat::Csv));
+    assert_eq!("json".parse(), Ok(CostReportFormat::Json));
+    assert!("xml".parse::<CostReportFormat>().is_err());
+}
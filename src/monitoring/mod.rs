@@ -305,6 +305,54 @@ pub fn update_outbound_rpc_bandwidth(value: i64) {
     prometheus::OUTBOUND_RPC_BANDWIDTH_GAUGE.add(value);
 }
 
+#[allow(unused_variables)]
+pub fn update_p2p_message_bandwidth(name: &str, direction: &str, num_bytes: u64) {
+    #[cfg(feature = "monitoring_prom")]
+    prometheus::P2P_MSG_BANDWIDTH_VEC
+        .with_label_values(&[direction, name])
+        .inc_by(num_bytes as i64);
+}
+
+#[allow(unused_variables)]
+pub fn update_peer_bandwidth(peer: &str, direction: &str, bytes_per_sec: u64) {
+    #[cfg(feature = "monitoring_prom")]
+    prometheus::PEER_BANDWIDTH_GAUGE_VEC
+        .with_label_values(&[direction, peer])
+        .set(bytes_per_sec as i64);
+}
+
+#[allow(unused_variables)]
+pub fn update_miner_utxo_health(count: i64, balance_sats: i64, uneconomic: i64, locked: i64) {
+    #[cfg(feature = "monitoring_prom")]
+    {
+        prometheus::MINER_UTXOS_GAUGE.set(count);
+        prometheus::MINER_UTXO_BALANCE_GAUGE.set(balance_sats);
+        prometheus::MINER_UNECONOMIC_UTXOS_GAUGE.set(uneconomic);
+        prometheus::MINER_LOCKED_UTXOS_GAUGE.set(locked);
+    }
+}
+
+pub fn increment_miner_utxo_consolidations_counter() {
+    #[cfg(feature = "monitoring_prom")]
+    prometheus::MINER_UTXO_CONSOLIDATIONS_COUNTER.inc();
+}
+
+#[allow(unused_variables)]
+pub fn remove_peer_bandwidth(peer: &str) {
+    #[cfg(feature = "monitoring_prom")]
+    for direction in ["inbound", "outbound"].iter() {
+        let _ = prometheus::PEER_BANDWIDTH_GAUGE_VEC.remove_label_values(&[direction, peer]);
+    }
+}
+
+#[allow(unused_variables)]
+pub fn increment_bandwidth_deferrals(direction: &str) {
+    #[cfg(feature = "monitoring_prom")]
+    prometheus::BANDWIDTH_DEFERRALS_VEC
+        .with_label_values(&[direction])
+        .inc();
+}
+
 #[allow(unused_variables)]
 pub fn increment_msg_counter(name: String) {
     #[cfg(feature = "monitoring_prom")]
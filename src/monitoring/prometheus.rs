@@ -16,6 +16,7 @@
 
 use prometheus::{
     Gauge, Histogram, HistogramTimer, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec,
 };
 
 lazy_static! {
@@ -172,6 +173,24 @@ lazy_static! {
         &["name"]
     ).unwrap();
 
+    pub static ref P2P_MSG_BANDWIDTH_VEC: IntCounterVec = register_int_counter_vec!(
+        "stacks_node_p2p_message_bandwidth",
+        "Total P2P bytes sent and received, by type of message",
+        &["direction", "name"]
+    ).unwrap();
+
+    pub static ref PEER_BANDWIDTH_GAUGE_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "stacks_node_peer_bandwidth",
+        "Current P2P and HTTP bandwidth to and from each peer IP address, in bytes/sec",
+        &["direction", "peer"]
+    ).unwrap();
+
+    pub static ref BANDWIDTH_DEFERRALS_VEC: IntCounterVec = register_int_counter_vec!(
+        "stacks_node_bandwidth_deferrals",
+        "Number of times traffic was held back because a bandwidth budget was used up",
+        &["direction"]
+    ).unwrap();
+
 
     pub static ref STX_MEMPOOL_GC: IntCounter = register_int_counter!(opts!(
         "stacks_node_mempool_gc_count",
@@ -220,6 +239,31 @@ lazy_static! {
         "stacks_node_miner_current_median_commitment_low",
         "Low 64 bits of a miner's median commitment over the mining commitment window."
     )).unwrap();
+
+    pub static ref MINER_UTXOS_GAUGE: IntGauge = register_int_gauge!(opts!(
+        "stacks_node_miner_utxos",
+        "Number of unspent outputs the miner can spend."
+    )).unwrap();
+
+    pub static ref MINER_UTXO_BALANCE_GAUGE: IntGauge = register_int_gauge!(opts!(
+        "stacks_node_miner_utxo_balance_sats",
+        "Total value, in satoshis, of the miner's unspent outputs."
+    )).unwrap();
+
+    pub static ref MINER_UNECONOMIC_UTXOS_GAUGE: IntGauge = register_int_gauge!(opts!(
+        "stacks_node_miner_uneconomic_utxos",
+        "Number of the miner's unspent outputs that cost more in fees to spend than they are worth."
+    )).unwrap();
+
+    pub static ref MINER_LOCKED_UTXOS_GAUGE: IntGauge = register_int_gauge!(opts!(
+        "stacks_node_miner_locked_utxos",
+        "Number of the miner's unspent outputs that are spent by a transaction bitcoind has not reported yet."
+    )).unwrap();
+
+    pub static ref MINER_UTXO_CONSOLIDATIONS_COUNTER: IntCounter = register_int_counter!(opts!(
+        "stacks_node_miner_utxo_consolidations_total",
+        "Total number of UTXO consolidation transactions sent by the miner."
+    )).unwrap();
 }
 
 pub fn new_rpc_call_timer(path: &str) -> HistogramTimer {
@@ -43,8 +43,11 @@ use crate::net::connection::ConnectionP2P;
 use crate::net::connection::ReplyHandleP2P;
 use crate::net::db::PeerDB;
 use crate::net::db::*;
+use crate::net::encryption::P2PSession;
 use crate::net::neighbors::MAX_NEIGHBOR_BLOCK_DELAY;
+use crate::net::p2p::BandwidthSample;
 use crate::net::relay::*;
+use crate::net::reputation::ReputationEvent;
 use crate::net::Error as net_error;
 use crate::net::GetBlocksInv;
 use crate::net::GetPoxInv;
@@ -140,6 +143,12 @@ pub struct NeighborStats {
     pub microblocks_push_rx_counts: VecDeque<(u64, u64)>, // (timestamp, num bytes)
     pub transaction_push_rx_counts: VecDeque<(u64, u64)>, // (timestamp, num bytes)
     pub relayed_messages: HashMap<NeighborAddress, RelayStats>,
+    /// stored reputation score, as of the last time the PeerNetwork synced it with the peer DB
+    pub reputation: Option<i64>,
+    /// reputation changes not yet written to the peer DB
+    pub reputation_delta: i64,
+    /// traffic not yet collected by the PeerNetwork's bandwidth manager
+    pub bandwidth: BandwidthSample,
 }
 
 impl NeighborStats {
@@ -163,6 +172,9 @@ impl NeighborStats {
             microblocks_push_rx_counts: VecDeque::new(),
             transaction_push_rx_counts: VecDeque::new(),
             relayed_messages: HashMap::new(),
+            reputation: None,
+            reputation_delta: 0,
+            bandwidth: BandwidthSample::default(),
         }
     }
 
@@ -177,6 +189,18 @@ impl NeighborStats {
         }
     }
 
+    pub fn add_reputation_event(&mut self, event: ReputationEvent) -> () {
+        self.reputation_delta = self.reputation_delta.saturating_add(event.score_delta());
+    }
+
+    /// Get a peer's reputation -- its stored score, plus whatever has happened since it was last
+    /// stored.
+    pub fn get_reputation(&self) -> i64 {
+        self.reputation
+            .unwrap_or(0)
+            .saturating_add(self.reputation_delta)
+    }
+
     pub fn add_block_push(&mut self, message_size: u64) -> () {
         self.block_push_rx_counts
             .push_back((get_epoch_time_secs(), message_size));
@@ -221,6 +245,11 @@ impl NeighborStats {
         ret
     }
 
+    /// Take the traffic moved since the last call, for the bandwidth manager
+    pub fn take_bandwidth(&mut self) -> BandwidthSample {
+        mem::take(&mut self.bandwidth)
+    }
+
     /// Get a peer's perceived health -- the last $NUM_HEALTH_POINTS successful messages divided by
     /// the total.
     pub fn get_health_score(&self) -> f64 {
@@ -321,6 +350,9 @@ pub struct ConversationP2P {
     // outbound replies
     pub reply_handles: VecDeque<ReplyHandleP2P>,
 
+    // ephemeral private key for a session key exchange we started, until the peer answers
+    session_key: Option<Secp256k1PrivateKey>,
+
     // system epochs
     epochs: Vec<StacksEpoch>,
 }
@@ -522,6 +554,8 @@ impl ConversationP2P {
             stats: NeighborStats::new(outbound),
             reply_handles: VecDeque::new(),
 
+            session_key: None,
+
             epochs: epochs,
         }
     }
@@ -911,9 +945,10 @@ impl ConversationP2P {
         let _seq = msg.request_id();
 
         let mut handle = self.connection.make_relay_handle(self.conn_id)?;
-        msg.consensus_serialize(&mut handle)?;
+        self.connection.send_message(&mut handle, &msg)?;
 
         self.stats.msgs_tx += 1;
+        self.stats.bandwidth.add_message_tx(&msg);
 
         debug!(
             "{:?}: relay-send({}) {} seq {}",
@@ -936,9 +971,10 @@ impl ConversationP2P {
         let mut handle =
             self.connection
                 .make_request_handle(msg.request_id(), ttl, self.conn_id)?;
-        msg.consensus_serialize(&mut handle)?;
+        self.connection.send_message(&mut handle, &msg)?;
 
         self.stats.msgs_tx += 1;
+        self.stats.bandwidth.add_message_tx(&msg);
 
         debug!(
             "{:?}: request-send({}) {} seq {}",
@@ -1071,6 +1107,107 @@ impl ConversationP2P {
         Ok(updated)
     }
 
+    /// Does the remote peer support encrypted payloads?
+    pub fn supports_encryption(peer_services: u16) -> bool {
+        (peer_services & (ServiceFlags::ENCRYPTION as u16)) != 0
+    }
+
+    /// Does the remote peer support compact block relay?
+    pub fn supports_compact_blocks(peer_services: u16) -> bool {
+        (peer_services & (ServiceFlags::COMPACT_BLOCKS as u16)) != 0
+    }
+
+    /// Is this conversation's payload encryption negotiated?
+    pub fn is_encrypted(&self) -> bool {
+        self.connection.protocol.get_session().is_some()
+    }
+
+    /// Can both we and the remote peer encrypt payloads?
+    fn can_encrypt(&self, local_peer: &LocalPeer) -> bool {
+        Self::supports_encryption(local_peer.services)
+            && Self::supports_encryption(self.peer_services)
+    }
+
+    /// Check this conversation's session keys after a handshake.  They are discarded if we or the
+    /// remote peer no longer support encryption, or if the remote peer changed its public key.
+    /// Returns true if a new session key exchange is needed.
+    fn check_session(&mut self, local_peer: &LocalPeer, rekeyed: bool) -> bool {
+        if !self.can_encrypt(local_peer) || rekeyed {
+            self.connection.protocol.clear_session();
+            self.session_key = None;
+        }
+        self.can_encrypt(local_peer) && !self.is_encrypted() && self.session_key.is_none()
+    }
+
+    /// Start a session key exchange by sending the remote peer a fresh ephemeral public key.
+    /// We hold on to the private key until the peer answers with its own.
+    fn send_session_key(
+        &mut self,
+        local_peer: &LocalPeer,
+        burnchain_view: &BurnchainView,
+    ) -> Result<(), net_error> {
+        let session_key = Secp256k1PrivateKey::new();
+        let session_key_data = SessionKeyData {
+            public_key: StacksPublicKeyBuffer::from_public_key(&Secp256k1PublicKey::from_private(
+                &session_key,
+            )),
+        };
+        let msg = self.sign_message(
+            burnchain_view,
+            &local_peer.private_key,
+            StacksMessageType::SessionKey(session_key_data),
+        )?;
+        let handle = self.relay_signed_message(msg)?;
+        self.reply_handles.push_back(handle);
+        self.session_key = Some(session_key);
+        Ok(())
+    }
+
+    /// Handle an inbound SessionKey.  If we started the exchange, derive the session keys from our
+    /// pending ephemeral key; the peer already has them, so we encrypt from now on.  Otherwise,
+    /// derive them from a new ephemeral key and reply with its public key.  Either way, the
+    /// ephemeral private key is dropped once the session keys exist.
+    fn handle_session_key(
+        &mut self,
+        local_peer: &LocalPeer,
+        burnchain_view: &BurnchainView,
+        session_key_data: &SessionKeyData,
+    ) -> Result<Option<StacksMessage>, net_error> {
+        if !self.can_encrypt(local_peer) {
+            debug!(
+                "{:?}: ignoring SessionKey, since encryption is not supported",
+                &self
+            );
+            return Ok(None);
+        }
+        let peer_session_pubkey = session_key_data
+            .public_key
+            .to_public_key()
+            .map_err(|e| net_error::DeserializeError(e.to_string()))?;
+
+        if let Some(session_key) = self.session_key.take() {
+            let session = P2PSession::new(&session_key, &peer_session_pubkey, true)?;
+            self.connection.protocol.set_session(session);
+            return Ok(None);
+        }
+
+        let session_key = Secp256k1PrivateKey::new();
+        let session = P2PSession::new(&session_key, &peer_session_pubkey, false)?;
+        self.connection.protocol.set_session(session);
+
+        let reply_data = SessionKeyData {
+            public_key: StacksPublicKeyBuffer::from_public_key(&Secp256k1PublicKey::from_private(
+                &session_key,
+            )),
+        };
+        Ok(Some(StacksMessage::from_chain_view(
+            self.version,
+            self.network_id,
+            burnchain_view,
+            StacksMessageType::SessionKey(reply_data),
+        )))
+    }
+
     /// Handle an inbound NAT-punch request -- just tell the peer what we think their IP/port are.
     /// No authentication from the peer is necessary.
     fn handle_natpunch_request(&self, chain_view: &BurnchainView, nonce: u32) -> StacksMessage {
@@ -1135,6 +1272,9 @@ impl ConversationP2P {
 
         let old_pubkey_opt = self.connection.get_public_key();
         let updated = self.update_from_handshake_data(&message.preamble, &handshake_data)?;
+
+        // the remote peer starts the session key exchange once it gets our HandshakeAccept
+        self.check_session(local_peer, updated);
         let _authentic_msg = if !updated {
             "same"
         } else if old_pubkey_opt.is_none() {
@@ -1156,6 +1296,7 @@ impl ConversationP2P {
              "services" => &to_hex(&handshake_data.services.to_be_bytes()),
              "expires_block_height" => handshake_data.expire_block_height,
              "supports_mempool_query" => Self::supports_mempool_query(handshake_data.services),
+             "supports_encryption" => Self::supports_encryption(handshake_data.services),
         );
 
         if updated {
@@ -1202,10 +1343,15 @@ impl ConversationP2P {
     /// Called from the p2p network thread.
     fn handle_handshake_accept(
         &mut self,
+        local_peer: &LocalPeer,
+        burnchain_view: &BurnchainView,
         preamble: &Preamble,
         handshake_accept: &HandshakeAcceptData,
     ) -> Result<(), net_error> {
-        self.update_from_handshake_data(preamble, &handshake_accept.handshake)?;
+        let updated = self.update_from_handshake_data(preamble, &handshake_accept.handshake)?;
+        if self.check_session(local_peer, updated) {
+            self.send_session_key(local_peer, burnchain_view)?;
+        }
         self.peer_heartbeat =
             if handshake_accept.heartbeat_interval > (MAX_PEER_HEARTBEAT_INTERVAL as u32) {
                 debug!(
@@ -1510,6 +1656,67 @@ impl ConversationP2P {
         self.sign_and_reply(local_peer, burnchain_view, preamble, response)
     }
 
+    /// Handle an inbound GetCompactBlockTxs request, sent by a peer that is reconstructing a
+    /// compact block we pushed to it.
+    /// Returns a reply handle to the generated message (possibly a nack)
+    fn handle_getcompactblocktxs(
+        &mut self,
+        local_peer: &LocalPeer,
+        chainstate: &StacksChainState,
+        burnchain_view: &BurnchainView,
+        preamble: &Preamble,
+        data: &GetCompactBlockTxsData,
+    ) -> Result<ReplyHandleP2P, net_error> {
+        let block = match StacksChainState::load_block(
+            &chainstate.blocks_path,
+            &data.consensus_hash,
+            &data.block_hash,
+        ) {
+            Ok(Some(block)) => block,
+            Ok(None) | Err(_) => {
+                debug!(
+                    "{:?}: No such block {}/{} for GetCompactBlockTxs",
+                    &local_peer, &data.consensus_hash, &data.block_hash
+                );
+                return self.reply_nack(
+                    local_peer,
+                    burnchain_view,
+                    preamble,
+                    NackErrorCodes::NoSuchBlock,
+                );
+            }
+        };
+
+        let mut txs = Vec::with_capacity(data.indexes.len());
+        for index in data.indexes.iter() {
+            match block.txs.get(*index as usize) {
+                Some(tx) => txs.push(tx.clone()),
+                None => {
+                    debug!(
+                        "{:?}: Invalid GetCompactBlockTxs index {} for block {} with {} transactions",
+                        &local_peer,
+                        index,
+                        &data.block_hash,
+                        block.txs.len()
+                    );
+                    return self.reply_nack(
+                        local_peer,
+                        burnchain_view,
+                        preamble,
+                        NackErrorCodes::InvalidMessage,
+                    );
+                }
+            }
+        }
+
+        let response = StacksMessageType::CompactBlockTxs(CompactBlockTxsData {
+            consensus_hash: data.consensus_hash.clone(),
+            block_hash: data.block_hash.clone(),
+            txs,
+        });
+        self.sign_and_reply(local_peer, burnchain_view, preamble, response)
+    }
+
     /// Create a response an inbound GetPoxInv request, but unsigned.
     /// Returns a reply handle to the generated message (possibly a nack)
     pub fn make_getpoxinv_response(
@@ -1696,6 +1903,8 @@ impl ConversationP2P {
         if !self.process_relayers(local_peer, preamble, &relayers) {
             debug!("Drop pushed blocks -- invalid relayers {:?}", &relayers);
             self.stats.msgs_err += 1;
+            self.stats
+                .add_reputation_event(ReputationEvent::InvalidData);
             return Err(net_error::InvalidMessage);
         }
 
@@ -1711,6 +1920,8 @@ impl ConversationP2P {
                 self.connection.options.max_block_push_bandwidth,
                 self.stats.get_block_push_bandwidth()
             );
+            self.stats
+                .add_reputation_event(ReputationEvent::UnsolicitedData);
             return self
                 .reply_nack(local_peer, chain_view, preamble, NackErrorCodes::Throttled)
                 .and_then(|handle| Ok(Some(handle)));
@@ -1736,6 +1947,8 @@ impl ConversationP2P {
                 &relayers
             );
             self.stats.msgs_err += 1;
+            self.stats
+                .add_reputation_event(ReputationEvent::InvalidData);
             return Err(net_error::InvalidMessage);
         }
 
@@ -1747,6 +1960,8 @@ impl ConversationP2P {
                 > (self.connection.options.max_microblocks_push_bandwidth as f64)
         {
             debug!("Neighbor {:?} exceeded max microblocks-push bandwidth of {} bytes/sec (currently at {})", &self.to_neighbor_key(), self.connection.options.max_microblocks_push_bandwidth, self.stats.get_microblocks_push_bandwidth());
+            self.stats
+                .add_reputation_event(ReputationEvent::UnsolicitedData);
             return self
                 .reply_nack(local_peer, chain_view, preamble, NackErrorCodes::Throttled)
                 .and_then(|handle| Ok(Some(handle)));
@@ -1771,6 +1986,8 @@ impl ConversationP2P {
                 &relayers
             );
             self.stats.msgs_err += 1;
+            self.stats
+                .add_reputation_event(ReputationEvent::InvalidData);
             return Err(net_error::InvalidMessage);
         }
 
@@ -1782,6 +1999,8 @@ impl ConversationP2P {
                 > (self.connection.options.max_transaction_push_bandwidth as f64)
         {
             debug!("Neighbor {:?} exceeded max transaction-push bandwidth of {} bytes/sec (currently at {})", &self.to_neighbor_key(), self.connection.options.max_transaction_push_bandwidth, self.stats.get_transaction_push_bandwidth());
+            self.stats
+                .add_reputation_event(ReputationEvent::UnsolicitedData);
             return self
                 .reply_nack(local_peer, chain_view, preamble, NackErrorCodes::Throttled)
                 .and_then(|handle| Ok(Some(handle)));
@@ -1841,6 +2060,30 @@ impl ConversationP2P {
                     }
                 }
             }
+            StacksMessageType::CompactBlock(_) => {
+                monitoring::increment_stx_blocks_received_counter();
+
+                // compact blocks count against the same limit as full blocks
+                match self.validate_blocks_push(
+                    local_peer,
+                    chain_view,
+                    &msg.preamble,
+                    msg.relayers.clone(),
+                )? {
+                    Some(handle) => Ok(handle),
+                    None => {
+                        // will forward upstream
+                        return Ok(Some(msg));
+                    }
+                }
+            }
+            StacksMessageType::GetCompactBlockTxs(ref data) => self.handle_getcompactblocktxs(
+                local_peer,
+                chainstate,
+                chain_view,
+                &msg.preamble,
+                data,
+            ),
             StacksMessageType::Microblocks(_) => {
                 monitoring::increment_stx_micro_blocks_received_counter();
 
@@ -1906,6 +2149,7 @@ impl ConversationP2P {
                     if num_recved > 0 {
                         self.stats.last_recv_time = get_epoch_time_secs();
                         self.stats.bytes_rx += num_recved as u64;
+                        self.stats.bandwidth.bytes_rx += num_recved as u64;
                     } else {
                         break;
                     }
@@ -1941,6 +2185,7 @@ impl ConversationP2P {
                     if num_sent > 0 {
                         self.stats.last_send_time = get_epoch_time_secs();
                         self.stats.bytes_tx += num_sent as u64;
+                        self.stats.bandwidth.bytes_tx += num_sent as u64;
                     } else {
                         break;
                     }
@@ -2010,6 +2255,8 @@ impl ConversationP2P {
                     );
                     self.stats.msgs_err += 1;
                     self.stats.add_healthpoint(false);
+                    self.stats
+                        .add_reputation_event(ReputationEvent::InvalidData);
                     return Err(e);
                 }
                 _ => {
@@ -2049,9 +2296,16 @@ impl ConversationP2P {
             }
             StacksMessageType::HandshakeAccept(ref data) => {
                 test_debug!("{:?}: Got HandshakeAccept", &self);
-                self.handle_handshake_accept(&msg.preamble, data)
+                self.handle_handshake_accept(local_peer, burnchain_view, &msg.preamble, data)
                     .and_then(|_| Ok(None))
             }
+            StacksMessageType::SessionKey(ref data) => {
+                test_debug!("{:?}: Got SessionKey", &self);
+
+                // only meaningful to this conversation
+                consume = true;
+                self.handle_session_key(local_peer, burnchain_view, data)
+            }
             StacksMessageType::Ping(_) => {
                 test_debug!("{:?}: Got Ping", &self);
 
@@ -2121,7 +2375,7 @@ impl ConversationP2P {
             StacksMessageType::HandshakeAccept(ref data) => {
                 if solicited {
                     test_debug!("{:?}: Got unauthenticated HandshakeAccept", &self);
-                    self.handle_handshake_accept(&msg.preamble, data)
+                    self.handle_handshake_accept(local_peer, burnchain_view, &msg.preamble, data)
                         .and_then(|_| Ok(None))
                 } else {
                     test_debug!("{:?}: Unsolicited unauthenticated HandshakeAccept", &self);
@@ -2224,6 +2478,7 @@ impl ConversationP2P {
                 }
                 Some(m) => m,
             };
+            self.stats.bandwidth.add_message_rx(&msg);
 
             if !self.validate_inbound_message(&msg, burnchain_view)? {
                 continue;
@@ -2372,11 +2627,17 @@ impl ConversationP2P {
         Ok(unsolicited)
     }
 
-    /// Remove all timed-out messages, and ding the remote peer as unhealthy
-    pub fn clear_timeouts(&mut self) -> () {
+    /// Remove all timed-out messages, and ding the remote peer as unhealthy unless `penalize` is
+    /// false (i.e. we may have made it slow by throttling it)
+    pub fn clear_timeouts(&mut self, penalize: bool) -> () {
         let num_drained = self.connection.drain_timeouts();
+        if !penalize {
+            return;
+        }
         for _ in 0..num_drained {
             self.stats.add_healthpoint(false);
+            self.stats
+                .add_reputation_event(ReputationEvent::SlowResponse);
         }
     }
 
@@ -2411,6 +2672,7 @@ mod test {
     use crate::core::*;
     use crate::net::connection::*;
     use crate::net::db::*;
+    use crate::net::encryption::PREAMBLE_ENCRYPTED_PAYLOAD;
     use crate::net::p2p::*;
     use crate::net::test::*;
     use crate::net::*;
@@ -3372,6 +3634,254 @@ mod test {
         }
     }
 
+    #[test]
+    fn convo_encrypted_ping() {
+        let conn_opts = ConnectionOptions::default();
+        let socketaddr_1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let socketaddr_2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 8081);
+
+        let burnchain = testing_burnchain_config();
+
+        let mut chain_view = BurnchainView {
+            burn_block_height: 12348,
+            burn_block_hash: BurnchainHeaderHash([0x11; 32]),
+            burn_stable_block_height: 12341,
+            burn_stable_block_hash: BurnchainHeaderHash([0x22; 32]),
+            last_burn_block_hashes: HashMap::new(),
+        };
+        chain_view.make_test_data();
+
+        let (mut peerdb_1, mut sortdb_1, pox_id_1, mut chainstate_1) = make_test_chain_dbs(
+            "convo_encrypted_ping_1",
+            &burnchain,
+            0x9abcdef0,
+            12350,
+            "http://peer1.com".into(),
+            &vec![],
+            &vec![],
+        );
+        let (mut peerdb_2, mut sortdb_2, pox_id_2, mut chainstate_2) = make_test_chain_dbs(
+            "convo_encrypted_ping_2",
+            &burnchain,
+            0x9abcdef0,
+            12351,
+            "http://peer2.com".into(),
+            &vec![],
+            &vec![],
+        );
+
+        db_setup(&mut peerdb_1, &mut sortdb_1, &socketaddr_1, &chain_view);
+        db_setup(&mut peerdb_2, &mut sortdb_2, &socketaddr_2, &chain_view);
+
+        let services = (ServiceFlags::RELAY as u16)
+            | (ServiceFlags::RPC as u16)
+            | (ServiceFlags::ENCRYPTION as u16);
+        for peerdb in [&mut peerdb_1, &mut peerdb_2] {
+            let mut tx = peerdb.tx_begin().unwrap();
+            PeerDB::set_local_services(&mut tx, services).unwrap();
+            tx.commit().unwrap();
+        }
+
+        let local_peer_1 = PeerDB::get_local_peer(&peerdb_1.conn()).unwrap();
+        let local_peer_2 = PeerDB::get_local_peer(&peerdb_2.conn()).unwrap();
+
+        let mut convo_1 = ConversationP2P::new(
+            123,
+            456,
+            &burnchain,
+            &socketaddr_2,
+            &conn_opts,
+            true,
+            0,
+            StacksEpoch::unit_test_pre_2_05(0),
+        );
+        let mut convo_2 = ConversationP2P::new(
+            123,
+            456,
+            &burnchain,
+            &socketaddr_1,
+            &conn_opts,
+            true,
+            0,
+            StacksEpoch::unit_test_pre_2_05(0),
+        );
+
+        // convo_1 sends a handshake to convo_2
+        let handshake_data_1 = HandshakeData::from_local_peer(&local_peer_1);
+        let handshake_1 = convo_1
+            .sign_message(
+                &chain_view,
+                &local_peer_1.private_key,
+                StacksMessageType::Handshake(handshake_data_1.clone()),
+            )
+            .unwrap();
+        let mut rh_handshake_1 = convo_1.send_signed_request(handshake_1, 1000000).unwrap();
+
+        convo_send_recv(&mut convo_1, vec![&mut rh_handshake_1], &mut convo_2);
+        convo_2
+            .chat(
+                &local_peer_2,
+                &mut peerdb_2,
+                &sortdb_2,
+                &pox_id_2,
+                &mut chainstate_2,
+                &mut BlockHeaderCache::new(),
+                &chain_view,
+            )
+            .unwrap();
+
+        // no keys yet -- they come from a separate exchange of ephemeral keys
+        assert!(!convo_2.is_encrypted());
+
+        convo_send_recv(&mut convo_2, vec![&mut rh_handshake_1], &mut convo_1);
+        convo_1
+            .chat(
+                &local_peer_1,
+                &mut peerdb_1,
+                &sortdb_1,
+                &pox_id_1,
+                &mut chainstate_1,
+                &mut BlockHeaderCache::new(),
+                &chain_view,
+            )
+            .unwrap();
+
+        match rh_handshake_1.recv(0).unwrap().payload {
+            StacksMessageType::HandshakeAccept(..) => {}
+            _ => panic!("expected HandshakeAccept"),
+        }
+
+        // convo_1 got the HandshakeAccept, so it sent its SessionKey
+        assert!(!convo_1.is_encrypted());
+        assert!(convo_1.session_key.is_some());
+
+        convo_send_recv(&mut convo_1, vec![], &mut convo_2);
+        let unhandled_2 = convo_2
+            .chat(
+                &local_peer_2,
+                &mut peerdb_2,
+                &sortdb_2,
+                &pox_id_2,
+                &mut chainstate_2,
+                &mut BlockHeaderCache::new(),
+                &chain_view,
+            )
+            .unwrap();
+        assert_eq!(unhandled_2.len(), 0);
+
+        // convo_2 has the keys, but won't encrypt until convo_1 shows that it has them too
+        assert!(convo_2.is_encrypted());
+        assert!(convo_2.session_key.is_none());
+        assert!(!convo_2
+            .connection
+            .protocol
+            .get_session()
+            .unwrap()
+            .send_encrypted());
+
+        convo_send_recv(&mut convo_2, vec![], &mut convo_1);
+        let unhandled_1 = convo_1
+            .chat(
+                &local_peer_1,
+                &mut peerdb_1,
+                &sortdb_1,
+                &pox_id_1,
+                &mut chainstate_1,
+                &mut BlockHeaderCache::new(),
+                &chain_view,
+            )
+            .unwrap();
+        assert_eq!(unhandled_1.len(), 0);
+
+        // convo_1 got convo_2's SessionKey, so it encrypts from now on
+        assert!(convo_1.is_encrypted());
+        assert!(convo_1.session_key.is_none());
+        assert!(convo_1
+            .connection
+            .protocol
+            .get_session()
+            .unwrap()
+            .send_encrypted());
+
+        let ping_data_1 = PingData::new();
+        let ping_1 = convo_1
+            .sign_message(
+                &chain_view,
+                &local_peer_1.private_key,
+                StacksMessageType::Ping(ping_data_1.clone()),
+            )
+            .unwrap();
+
+        // the ping goes out encrypted
+        let mut ping_bytes = vec![];
+        convo_1
+            .connection
+            .protocol
+            .write_message(&mut ping_bytes, &ping_1)
+            .unwrap();
+        let wire_preamble: Preamble = read_next(&mut &ping_bytes[..]).unwrap();
+        assert!(wire_preamble.additional_data & PREAMBLE_ENCRYPTED_PAYLOAD != 0);
+        assert_eq!(wire_preamble.seq, ping_1.preamble.seq);
+        assert!(wire_preamble.payload_len > ping_1.preamble.payload_len);
+
+        // the session keys do not come from the nodes' long-lived keys
+        let static_session = P2PSession::new(
+            &local_peer_2.private_key,
+            &Secp256k1PublicKey::from_private(&local_peer_1.private_key),
+            false,
+        )
+        .unwrap();
+        assert!(static_session
+            .open(
+                &wire_preamble,
+                &ping_bytes[(ping_bytes.len() - wire_preamble.payload_len as usize)..]
+            )
+            .is_err());
+
+        let mut rh_ping_1 = convo_1.send_signed_request(ping_1, 1000000).unwrap();
+        convo_send_recv(&mut convo_1, vec![&mut rh_ping_1], &mut convo_2);
+        let unhandled_2 = convo_2
+            .chat(
+                &local_peer_2,
+                &mut peerdb_2,
+                &sortdb_2,
+                &pox_id_2,
+                &mut chainstate_2,
+                &mut BlockHeaderCache::new(),
+                &chain_view,
+            )
+            .unwrap();
+        assert_eq!(unhandled_2.len(), 0);
+
+        // convo_2 decrypted the ping, so it now encrypts its replies
+        assert!(convo_2
+            .connection
+            .protocol
+            .get_session()
+            .unwrap()
+            .send_encrypted());
+
+        convo_send_recv(&mut convo_2, vec![&mut rh_ping_1], &mut convo_1);
+        convo_1
+            .chat(
+                &local_peer_1,
+                &mut peerdb_1,
+                &sortdb_1,
+                &pox_id_1,
+                &mut chainstate_1,
+                &mut BlockHeaderCache::new(),
+                &chain_view,
+            )
+            .unwrap();
+
+        match rh_ping_1.recv(0).unwrap().payload {
+            StacksMessageType::Pong(ref data) => {
+                assert_eq!(data.nonce, ping_data_1.nonce);
+            }
+            _ => panic!("expected Pong"),
+        }
+    }
+
     #[test]
     fn convo_handshake_ping_loop() {
         let conn_opts = ConnectionOptions::default();
@@ -31,12 +31,14 @@ use crate::burnchains::PrivateKey;
 use crate::burnchains::PublicKey;
 use crate::chainstate::burn::ConsensusHash;
 use crate::chainstate::stacks::StacksBlock;
+use crate::chainstate::stacks::StacksBlockHeader;
 use crate::chainstate::stacks::StacksMicroblock;
 use crate::chainstate::stacks::StacksPublicKey;
 use crate::chainstate::stacks::StacksTransaction;
 use crate::chainstate::stacks::MAX_BLOCK_LEN;
 use crate::core::PEER_VERSION_TESTNET;
 use crate::net::db::LocalPeer;
+use crate::net::encryption::{is_encryptable, P2PSession, PREAMBLE_ENCRYPTED_PAYLOAD};
 use crate::net::Error as net_error;
 use crate::net::*;
 use stacks_common::codec::{read_next_at_most, read_next_exact, MAX_MESSAGE_LEN};
@@ -500,6 +502,121 @@ impl StacksMessageCodec for MicroblocksData {
     }
 }
 
+impl StacksMessageCodec for PrefilledTransaction {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.index)?;
+        write_next(fd, &self.tx)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<PrefilledTransaction, codec_error> {
+        let index: u32 = read_next(fd)?;
+        let tx: StacksTransaction = read_next(fd)?;
+        Ok(PrefilledTransaction { index, tx })
+    }
+}
+
+impl StacksMessageCodec for CompactBlockData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.consensus_hash)?;
+        write_next(fd, &self.header)?;
+        write_next(fd, &self.short_ids)?;
+        write_next(fd, &self.prefilled_txs)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<CompactBlockData, codec_error> {
+        let consensus_hash: ConsensusHash = read_next(fd)?;
+        let header: StacksBlockHeader = read_next(fd)?;
+        let short_ids: Vec<TxTag> = read_next_at_most(fd, COMPACT_BLOCK_MAX_TXS)?;
+        let prefilled_txs: Vec<PrefilledTransaction> = {
+            // loose upper-bound
+            let mut bound_read = BoundReader::from_reader(fd, MAX_BLOCK_LEN as u64);
+            read_next_at_most(&mut bound_read, COMPACT_BLOCK_MAX_TXS)
+        }?;
+
+        let num_txs = (short_ids.len() as u64) + (prefilled_txs.len() as u64);
+        if num_txs == 0 || num_txs > (COMPACT_BLOCK_MAX_TXS as u64) {
+            return Err(codec_error::DeserializeError(
+                "Invalid CompactBlockData: bad number of transactions".to_string(),
+            ));
+        }
+
+        // prefilled transactions must be in block order, and must be in the block
+        let mut last_index = None;
+        for prefilled in prefilled_txs.iter() {
+            if (prefilled.index as u64) >= num_txs || last_index >= Some(prefilled.index) {
+                return Err(codec_error::DeserializeError(
+                    "Invalid CompactBlockData: bad prefilled transaction index".to_string(),
+                ));
+            }
+            last_index = Some(prefilled.index);
+        }
+
+        Ok(CompactBlockData {
+            consensus_hash,
+            header,
+            short_ids,
+            prefilled_txs,
+        })
+    }
+}
+
+impl StacksMessageCodec for GetCompactBlockTxsData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.consensus_hash)?;
+        write_next(fd, &self.block_hash)?;
+        write_next(fd, &self.indexes)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<GetCompactBlockTxsData, codec_error> {
+        let consensus_hash: ConsensusHash = read_next(fd)?;
+        let block_hash: BlockHeaderHash = read_next(fd)?;
+        let indexes: Vec<u32> = read_next_at_most(fd, COMPACT_BLOCK_MAX_TXS)?;
+
+        // indexes must be sorted and unique
+        for i in 1..indexes.len() {
+            if indexes[i - 1] >= indexes[i] {
+                return Err(codec_error::DeserializeError(
+                    "Invalid GetCompactBlockTxsData: indexes are not sorted".to_string(),
+                ));
+            }
+        }
+
+        Ok(GetCompactBlockTxsData {
+            consensus_hash,
+            block_hash,
+            indexes,
+        })
+    }
+}
+
+impl StacksMessageCodec for CompactBlockTxsData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.consensus_hash)?;
+        write_next(fd, &self.block_hash)?;
+        write_next(fd, &self.txs)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<CompactBlockTxsData, codec_error> {
+        let consensus_hash: ConsensusHash = read_next(fd)?;
+        let block_hash: BlockHeaderHash = read_next(fd)?;
+        let txs: Vec<StacksTransaction> = {
+            // loose upper-bound
+            let mut bound_read = BoundReader::from_reader(fd, MAX_BLOCK_LEN as u64);
+            read_next_at_most(&mut bound_read, COMPACT_BLOCK_MAX_TXS)
+        }?;
+
+        Ok(CompactBlockTxsData {
+            consensus_hash,
+            block_hash,
+            txs,
+        })
+    }
+}
+
 impl NeighborAddress {
     pub fn from_neighbor(n: &Neighbor) -> NeighborAddress {
         NeighborAddress {
@@ -714,6 +831,18 @@ impl StacksMessageCodec for NatPunchData {
     }
 }
 
+impl StacksMessageCodec for SessionKeyData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.public_key)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<SessionKeyData, codec_error> {
+        let public_key: StacksPublicKeyBuffer = read_next(fd)?;
+        Ok(SessionKeyData { public_key })
+    }
+}
+
 impl StacksMessageCodec for MemPoolSyncData {
     fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
         match *self {
@@ -787,6 +916,10 @@ impl StacksMessageType {
             StacksMessageType::Pong(ref _m) => StacksMessageID::Pong,
             StacksMessageType::NatPunchRequest(ref _m) => StacksMessageID::NatPunchRequest,
             StacksMessageType::NatPunchReply(ref _m) => StacksMessageID::NatPunchReply,
+            StacksMessageType::CompactBlock(ref _m) => StacksMessageID::CompactBlock,
+            StacksMessageType::GetCompactBlockTxs(ref _m) => StacksMessageID::GetCompactBlockTxs,
+            StacksMessageType::CompactBlockTxs(ref _m) => StacksMessageID::CompactBlockTxs,
+            StacksMessageType::SessionKey(ref _m) => StacksMessageID::SessionKey,
         }
     }
 
@@ -811,6 +944,10 @@ impl StacksMessageType {
             StacksMessageType::Pong(ref _m) => "Pong",
             StacksMessageType::NatPunchRequest(ref _m) => "NatPunchRequest",
             StacksMessageType::NatPunchReply(ref _m) => "NatPunchReply",
+            StacksMessageType::CompactBlock(ref _m) => "CompactBlock",
+            StacksMessageType::GetCompactBlockTxs(ref _m) => "GetCompactBlockTxs",
+            StacksMessageType::CompactBlockTxs(ref _m) => "CompactBlockTxs",
+            StacksMessageType::SessionKey(ref _m) => "SessionKey",
         }
     }
 
@@ -869,6 +1006,26 @@ impl StacksMessageType {
             StacksMessageType::NatPunchReply(ref m) => {
                 format!("NatPunchReply({},{}:{})", m.nonce, &m.addrbytes, m.port)
             }
+            StacksMessageType::CompactBlock(ref m) => format!(
+                "CompactBlock({}/{},{},{})",
+                &m.consensus_hash,
+                &m.header.block_hash(),
+                m.short_ids.len(),
+                m.prefilled_txs.len()
+            ),
+            StacksMessageType::GetCompactBlockTxs(ref m) => format!(
+                "GetCompactBlockTxs({}/{},{:?})",
+                &m.consensus_hash, &m.block_hash, &m.indexes
+            ),
+            StacksMessageType::CompactBlockTxs(ref m) => format!(
+                "CompactBlockTxs({}/{},{:?})",
+                &m.consensus_hash,
+                &m.block_hash,
+                m.txs.iter().map(|tx| tx.txid()).collect::<Vec<Txid>>()
+            ),
+            StacksMessageType::SessionKey(ref m) => {
+                format!("SessionKey({})", &to_hex(&m.public_key.to_bytes()))
+            }
         }
     }
 }
@@ -902,6 +1059,12 @@ impl StacksMessageCodec for StacksMessageID {
             x if x == StacksMessageID::Pong as u8 => StacksMessageID::Pong,
             x if x == StacksMessageID::NatPunchRequest as u8 => StacksMessageID::NatPunchRequest,
             x if x == StacksMessageID::NatPunchReply as u8 => StacksMessageID::NatPunchReply,
+            x if x == StacksMessageID::CompactBlock as u8 => StacksMessageID::CompactBlock,
+            x if x == StacksMessageID::GetCompactBlockTxs as u8 => {
+                StacksMessageID::GetCompactBlockTxs
+            }
+            x if x == StacksMessageID::CompactBlockTxs as u8 => StacksMessageID::CompactBlockTxs,
+            x if x == StacksMessageID::SessionKey as u8 => StacksMessageID::SessionKey,
             _ => {
                 return Err(codec_error::DeserializeError(
                     "Unknown message ID".to_string(),
@@ -935,6 +1098,10 @@ impl StacksMessageCodec for StacksMessageType {
             StacksMessageType::Pong(ref m) => write_next(fd, m)?,
             StacksMessageType::NatPunchRequest(ref nonce) => write_next(fd, nonce)?,
             StacksMessageType::NatPunchReply(ref m) => write_next(fd, m)?,
+            StacksMessageType::CompactBlock(ref m) => write_next(fd, m)?,
+            StacksMessageType::GetCompactBlockTxs(ref m) => write_next(fd, m)?,
+            StacksMessageType::CompactBlockTxs(ref m) => write_next(fd, m)?,
+            StacksMessageType::SessionKey(ref m) => write_next(fd, m)?,
         }
         Ok(())
     }
@@ -1012,6 +1179,22 @@ impl StacksMessageCodec for StacksMessageType {
                 let m: NatPunchData = read_next(fd)?;
                 StacksMessageType::NatPunchReply(m)
             }
+            StacksMessageID::CompactBlock => {
+                let m: CompactBlockData = read_next(fd)?;
+                StacksMessageType::CompactBlock(m)
+            }
+            StacksMessageID::GetCompactBlockTxs => {
+                let m: GetCompactBlockTxsData = read_next(fd)?;
+                StacksMessageType::GetCompactBlockTxs(m)
+            }
+            StacksMessageID::CompactBlockTxs => {
+                let m: CompactBlockTxsData = read_next(fd)?;
+                StacksMessageType::CompactBlockTxs(m)
+            }
+            StacksMessageID::SessionKey => {
+                let m: SessionKeyData = read_next(fd)?;
+                StacksMessageType::SessionKey(m)
+            }
             StacksMessageID::Reserved => {
                 return Err(codec_error::DeserializeError(
                     "Unsupported message ID 'reserved'".to_string(),
@@ -1202,7 +1385,48 @@ impl MessageSequence for StacksMessage {
 
 impl StacksP2P {
     pub fn new() -> StacksP2P {
-        StacksP2P {}
+        StacksP2P {
+            session: None,
+            opened: None,
+        }
+    }
+
+    /// Install freshly-negotiated session keys, replacing any we had before
+    pub fn set_session(&mut self, session: P2PSession) {
+        self.session = Some(session);
+        self.opened = None;
+    }
+
+    /// Stop encrypting and decrypting payloads
+    pub fn clear_session(&mut self) {
+        self.session = None;
+        self.opened = None;
+    }
+
+    pub fn get_session(&self) -> Option<&P2PSession> {
+        self.session.as_ref()
+    }
+
+    /// Decrypt an encrypted message's payload, if it is encrypted.  Reuses the plaintext from
+    /// `verify_payload_bytes()` if it already decrypted this message.
+    fn open_payload(
+        &mut self,
+        preamble: &Preamble,
+        bytes: &[u8],
+    ) -> Result<Option<(Preamble, Vec<u8>)>, net_error> {
+        if preamble.additional_data & PREAMBLE_ENCRYPTED_PAYLOAD == 0 {
+            return Ok(None);
+        }
+        if let Some((wire_preamble, signed_preamble, body)) = self.opened.take() {
+            if wire_preamble == *preamble {
+                return Ok(Some((signed_preamble, body)));
+            }
+        }
+        let session = self.session.as_ref().ok_or(net_error::DeserializeError(
+            "Got an encrypted payload, but no session keys".to_string(),
+        ))?;
+        let opened = session.open(preamble, &bytes[0..(preamble.payload_len as usize)])?;
+        Ok(Some(opened))
     }
 }
 
@@ -1256,8 +1480,38 @@ impl ProtocolFamily for StacksP2P {
             ));
         }
 
+        if let Some((signed_preamble, body)) = self.open_payload(preamble, bytes)? {
+            let mut cursor = io::Cursor::new(&body[..]);
+            let (relayers, payload) = StacksMessage::deserialize_body(&mut cursor)?;
+            if cursor.position() as usize != body.len() {
+                return Err(net_error::DeserializeError(
+                    "Encrypted payload has trailing bytes".to_string(),
+                ));
+            }
+            let message = StacksMessage {
+                preamble: signed_preamble,
+                relayers: relayers,
+                payload: payload,
+            };
+
+            // the peer has our session keys, so we can encrypt what we send back
+            if let Some(ref mut session) = self.session {
+                session.set_recv_encrypted();
+            }
+            return Ok((message, preamble.payload_len as usize));
+        }
+
         let mut cursor = io::Cursor::new(&bytes[0..(preamble.payload_len as usize)]);
         let (relayers, payload) = StacksMessage::deserialize_body(&mut cursor)?;
+        if let Some(ref session) = self.session {
+            if session.recv_encrypted() && is_encryptable(&payload) {
+                // the peer already encrypts, so this did not come from it
+                return Err(net_error::DeserializeError(format!(
+                    "Got a cleartext {} after encryption was negotiated",
+                    payload.get_message_name()
+                )));
+            }
+        }
         let message = StacksMessage {
             preamble: preamble.clone(),
             relayers: relayers,
@@ -1272,17 +1526,30 @@ impl ProtocolFamily for StacksP2P {
         preamble: &Preamble,
         bytes: &[u8],
     ) -> Result<(), Error> {
+        if let Some((mut signed_preamble, body)) = self.open_payload(preamble, bytes)? {
+            // the signature covers the plaintext message
+            signed_preamble.verify(&body, key)?;
+            self.opened = Some((preamble.clone(), signed_preamble, body));
+            return Ok(());
+        }
         preamble
             .clone()
             .verify(&bytes[0..(preamble.payload_len as usize)], key)
             .and_then(|_m| Ok(()))
     }
 
+    /// Write out a message, encrypting its payload if we have a session with this peer
     fn write_message<W: Write>(
         &mut self,
         fd: &mut W,
         message: &StacksMessage,
     ) -> Result<(), net_error> {
+        if let Some(ref session) = self.session {
+            if session.send_encrypted() && is_encryptable(&message.payload) {
+                let sealed = session.seal(message)?;
+                return fd.write_all(&sealed).map_err(net_error::WriteError);
+            }
+        }
         message.consensus_serialize(fd).map_err(|e| e.into())
     }
 }
@@ -1295,6 +1562,8 @@ pub mod test {
 
     use super::*;
 
+    use crate::chainstate::stacks::test::make_codec_test_block;
+
     fn check_overflow<T>(r: Result<T, net_error>) -> bool {
         match r {
             Ok(_) => {
@@ -1915,6 +2184,72 @@ pub mod test {
         check_codec_and_corruption::<BlocksAvailableData>(&data, &bytes);
     }
 
+    #[test]
+    fn codec_GetCompactBlockTxs() {
+        let data = GetCompactBlockTxsData {
+            consensus_hash: ConsensusHash([0x11; 20]),
+            block_hash: BlockHeaderHash([0x22; 32]),
+            indexes: vec![1, 3],
+        };
+        let bytes = vec![
+            // consensus hash
+            0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+            0x11, 0x11, 0x11, 0x11, 0x11, 0x11, // block hash
+            0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+            0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+            0x22, 0x22, 0x22, 0x22, // length
+            0x00, 0x00, 0x00, 0x02, // indexes
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03,
+        ];
+
+        check_codec_and_corruption::<GetCompactBlockTxsData>(&data, &bytes);
+
+        // indexes must be sorted and unique
+        for indexes in [vec![3, 1], vec![1, 1]].iter() {
+            let bad_data = GetCompactBlockTxsData {
+                indexes: indexes.clone(),
+                ..data.clone()
+            };
+            assert!(check_deserialize_failure(&bad_data));
+        }
+    }
+
+    #[test]
+    fn codec_CompactBlock() {
+        let block = make_codec_test_block(10);
+        let data = CompactBlockData::from_block(ConsensusHash([0x11; 20]), &block);
+        let bytes = data.serialize_to_vec();
+        check_codec_and_corruption::<CompactBlockData>(&data, &bytes);
+
+        // no transactions
+        let bad_data = CompactBlockData {
+            short_ids: vec![],
+            prefilled_txs: vec![],
+            ..data.clone()
+        };
+        assert!(check_deserialize_failure(&bad_data));
+
+        // prefilled transaction isn't in the block
+        let mut bad_data = data.clone();
+        bad_data.prefilled_txs[0].index = data.num_txs() as u32;
+        assert!(check_deserialize_failure(&bad_data));
+
+        // prefilled transactions aren't in block order
+        let mut bad_data = data.clone();
+        let prefilled = bad_data.prefilled_txs[0].clone();
+        bad_data.short_ids.pop();
+        bad_data.prefilled_txs.push(prefilled);
+        assert!(check_deserialize_failure(&bad_data));
+
+        let data = CompactBlockTxsData {
+            consensus_hash: ConsensusHash([0x11; 20]),
+            block_hash: block.block_hash(),
+            txs: block.txs.clone(),
+        };
+        let bytes = data.serialize_to_vec();
+        check_codec_and_corruption::<CompactBlockTxsData>(&data, &bytes);
+    }
+
     #[test]
     fn codec_NatPunch() {
         let data = NatPunchData {
@@ -2032,6 +2367,11 @@ pub mod test {
                     (ConsensusHash([0x33; 20]), BurnchainHeaderHash([0x44; 32])),
                 ],
             }),
+            StacksMessageType::GetCompactBlockTxs(GetCompactBlockTxsData {
+                consensus_hash: ConsensusHash([0x11; 20]),
+                block_hash: BlockHeaderHash([0x22; 32]),
+                indexes: vec![1, 3],
+            }),
             // TODO: Blocks
             // TODO: Microblocks
             // TODO: Transaction
@@ -2049,6 +2389,9 @@ pub mod test {
                 port: 12345,
                 nonce: 0x12345678,
             }),
+            StacksMessageType::SessionKey(SessionKeyData {
+                public_key: StacksPublicKeyBuffer([0x22; 33]),
+            }),
         ];
 
         let mut maximal_relayers: Vec<RelayData> = vec![];
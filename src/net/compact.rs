@@ -0,0 +1,691 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compact block relay.
+//!
+//! Most of the transactions in a freshly-mined anchored block are already in its recipients'
+//! mempools, so instead of pushing the whole block (`BlocksData`), a node can push a
+//! `CompactBlock`: the block header, its coinbase, and a short ID for every other transaction.
+//! The short IDs are `TxTag`s (as used by mempool sync) seeded with the block hash.
+//!
+//! The recipient looks up the short IDs in its `MemPoolDB`, and asks the pusher for any
+//! transactions it couldn't find with a `GetCompactBlockTxs`, which the pusher answers with a
+//! `CompactBlockTxs`.  Once the block is complete, it is handled exactly as if it had been
+//! pushed in full.
+//!
+//! Compact blocks are only pushed to peers that advertise `ServiceFlags::COMPACT_BLOCKS`.
+
+use std::collections::HashMap;
+
+use crate::chainstate::burn::db::sortdb::SortitionDB;
+use crate::chainstate::stacks::db::StacksChainState;
+use crate::chainstate::stacks::StacksBlock;
+use crate::chainstate::stacks::StacksBlockHeader;
+use crate::chainstate::stacks::StacksTransaction;
+use crate::chainstate::stacks::TransactionPayload;
+use crate::codec::Error as codec_error;
+use crate::codec::StacksMessageCodec;
+use crate::core::mempool::MemPoolDB;
+use crate::core::mempool::TxTag;
+use crate::net::chat::ConversationP2P;
+use crate::net::p2p::PeerNetwork;
+use crate::net::reputation::ReputationEvent;
+use crate::net::Error as net_error;
+use crate::net::*;
+use crate::types::chainstate::StacksBlockId;
+
+use stacks_common::util::get_epoch_time_secs;
+
+/// How many compact blocks we'll try to reconstruct at once
+pub const COMPACT_BLOCKS_PENDING_MAX: usize = 32;
+/// How many of those can come from the same peer
+pub const COMPACT_BLOCKS_PENDING_PER_PEER_MAX: usize = 4;
+/// How long we'll wait for a compact block's missing transactions, in seconds
+pub const COMPACT_BLOCK_TIMEOUT: u64 = 30;
+
+impl CompactBlockData {
+    /// Make a compact block out of a block.  Coinbases are always prefilled, since they can't be
+    /// in anyone's mempool.
+    pub fn from_block(consensus_hash: ConsensusHash, block: &StacksBlock) -> CompactBlockData {
+        let seed = block.block_hash();
+        let mut short_ids = vec![];
+        let mut prefilled_txs = vec![];
+        for (i, tx) in block.txs.iter().enumerate() {
+            if let TransactionPayload::Coinbase(_) = tx.payload {
+                prefilled_txs.push(PrefilledTransaction {
+                    index: i as u32,
+                    tx: tx.clone(),
+                });
+            } else {
+                short_ids.push(TxTag::from(seed.as_bytes(), &tx.txid()));
+            }
+        }
+
+        CompactBlockData {
+            consensus_hash,
+            header: block.header.clone(),
+            short_ids,
+            prefilled_txs,
+        }
+    }
+
+    /// Number of transactions in the block
+    pub fn num_txs(&self) -> usize {
+        self.short_ids.len() + self.prefilled_txs.len()
+    }
+}
+
+/// If `payload` pushes a single block, and both we and the recipient support compact blocks, then
+/// make the equivalent CompactBlock payload.
+pub fn make_compact_block_payload(
+    local_services: u16,
+    peer_services: u16,
+    payload: &StacksMessageType,
+) -> Option<StacksMessageType> {
+    if !ConversationP2P::supports_compact_blocks(local_services)
+        || !ConversationP2P::supports_compact_blocks(peer_services)
+    {
+        return None;
+    }
+    match payload {
+        StacksMessageType::Blocks(ref data) if data.blocks.len() == 1 => {
+            let BlocksDatum(ref consensus_hash, ref block) = data.blocks[0];
+            Some(StacksMessageType::CompactBlock(
+                CompactBlockData::from_block(consensus_hash.clone(), block),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// A compact block we're reconstructing
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingCompactBlock {
+    /// conversation that pushed it to us
+    pub event_id: usize,
+    /// the pushed message's preamble and relayers, so the reconstructed block can be handled as
+    /// if it had been pushed in full
+    pub preamble: Preamble,
+    pub relayers: Vec<RelayData>,
+    pub consensus_hash: ConsensusHash,
+    pub header: StacksBlockHeader,
+    /// short ID of each transaction that wasn't prefilled, and its position in the block
+    pub short_ids: Vec<(u32, TxTag)>,
+    /// the block's transactions, as we find them
+    pub txs: Vec<Option<StacksTransaction>>,
+    /// whether or not we've looked for the transactions in our mempool yet
+    pub mempool_checked: bool,
+    /// positions of the transactions we asked the pusher for, if we did
+    pub requested: Option<Vec<u32>>,
+    pub first_seen: u64,
+}
+
+impl PendingCompactBlock {
+    pub fn new(
+        event_id: usize,
+        preamble: Preamble,
+        relayers: Vec<RelayData>,
+        data: CompactBlockData,
+        now: u64,
+    ) -> PendingCompactBlock {
+        let mut txs = vec![None; data.num_txs()];
+        for prefilled in data.prefilled_txs.into_iter() {
+            // index is in range -- checked on deserialization
+            txs[prefilled.index as usize] = Some(prefilled.tx);
+        }
+
+        let mut short_ids = vec![];
+        let mut short_ids_iter = data.short_ids.into_iter();
+        for (i, tx_opt) in txs.iter().enumerate() {
+            if tx_opt.is_none() {
+                // there are exactly as many short IDs as non-prefilled slots
+                if let Some(short_id) = short_ids_iter.next() {
+                    short_ids.push((i as u32, short_id));
+                }
+            }
+        }
+
+        PendingCompactBlock {
+            event_id,
+            preamble,
+            relayers,
+            consensus_hash: data.consensus_hash,
+            header: data.header,
+            short_ids,
+            txs,
+            mempool_checked: false,
+            requested: None,
+            first_seen: now,
+        }
+    }
+
+    pub fn index_block_hash(&self) -> StacksBlockId {
+        StacksBlockHeader::make_index_block_hash(&self.consensus_hash, &self.header.block_hash())
+    }
+
+    /// Positions of the transactions we don't have yet
+    pub fn missing_indexes(&self) -> Vec<u32> {
+        self.txs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, tx_opt)| {
+                if tx_opt.is_none() {
+                    Some(i as u32)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Fill in whatever transactions we can from our mempool.  Short IDs that match more than
+    /// one of our transactions are left for the pusher to send us.
+    pub fn fill_from_mempool(&mut self, mempool: &MemPoolDB) -> Result<(), net_error> {
+        let seed = self.header.block_hash();
+        let mut tagged: HashMap<TxTag, Option<Txid>> = HashMap::new();
+        for txid in mempool.get_bloom_txids()?.into_iter() {
+            let tag = TxTag::from(seed.as_bytes(), &txid);
+            if tagged.insert(tag.clone(), Some(txid)).is_some() {
+                // collision
+                tagged.insert(tag, None);
+            }
+        }
+
+        for (i, short_id) in self.short_ids.iter() {
+            if self.txs[*i as usize].is_some() {
+                continue;
+            }
+            if let Some(Some(txid)) = tagged.get(short_id) {
+                if let Some(tx_info) = MemPoolDB::get_tx(mempool.conn(), txid)? {
+                    self.txs[*i as usize] = Some(tx_info.tx);
+                }
+            }
+        }
+        self.mempool_checked = true;
+        Ok(())
+    }
+
+    /// Fill in the transactions the pusher sent us.
+    /// Returns false if they weren't what we asked for.
+    pub fn fill_from_peer(&mut self, txs: Vec<StacksTransaction>) -> bool {
+        let requested = match self.requested.as_ref() {
+            Some(requested) => requested,
+            None => {
+                return false;
+            }
+        };
+        if requested.len() != txs.len() {
+            return false;
+        }
+        for (i, tx) in requested.iter().zip(txs) {
+            self.txs[*i as usize] = Some(tx);
+        }
+        true
+    }
+
+    /// Did we ask the pusher for every transaction that wasn't prefilled?  If so, the pusher alone
+    /// is to blame if the block doesn't match its header.
+    pub fn requested_all(&self) -> bool {
+        match self.requested.as_ref() {
+            Some(requested) => requested.len() == self.short_ids.len(),
+            None => false,
+        }
+    }
+
+    /// Forget every transaction we took from our mempool, so we can ask the pusher for all of
+    /// them.  Used when the block we reconstructed didn't match its header, which can happen if
+    /// a short ID collides with one of our transactions that isn't in the block.
+    pub fn clear_short_id_txs(&mut self) -> () {
+        for (i, _) in self.short_ids.iter() {
+            self.txs[*i as usize] = None;
+        }
+    }
+
+    /// Assemble the block, if we have all of its transactions.  The block goes through the same
+    /// checks as a block we received in full (including the transaction Merkle root check).
+    pub fn try_into_block(&self) -> Option<Result<StacksBlock, codec_error>> {
+        let mut txs = Vec::with_capacity(self.txs.len());
+        for tx_opt in self.txs.iter() {
+            match tx_opt {
+                Some(tx) => txs.push(tx.clone()),
+                None => {
+                    return None;
+                }
+            }
+        }
+
+        let block = StacksBlock {
+            header: self.header.clone(),
+            txs,
+        };
+        let block_bytes = block.serialize_to_vec();
+        Some(StacksBlock::consensus_deserialize(&mut &block_bytes[..]))
+    }
+}
+
+impl PeerNetwork {
+    /// Can we push compact blocks to this peer?  Both of us need to support them.
+    pub fn can_push_compact_block(&self, recipient: &NeighborKey) -> bool {
+        if !ConversationP2P::supports_compact_blocks(self.local_peer.services) {
+            return false;
+        }
+        match self
+            .events
+            .get(recipient)
+            .and_then(|event_id| self.peers.get(event_id))
+        {
+            Some(convo) => ConversationP2P::supports_compact_blocks(convo.peer_services),
+            None => false,
+        }
+    }
+
+    /// Handle an unsolicited CompactBlock or CompactBlockTxs message.
+    /// Compact blocks are queued up for reconstruction in process_compact_blocks(), unless we
+    /// already have the block, don't know its sortition, or are already reconstructing too many
+    /// blocks (in total, or from this peer).
+    pub fn handle_unsolicited_compact_block_message(
+        &mut self,
+        sortdb: &SortitionDB,
+        chainstate: &StacksChainState,
+        event_id: usize,
+        message: StacksMessage,
+    ) -> () {
+        match self.peers.get(&event_id) {
+            Some(convo) if convo.is_authenticated() => {}
+            _ => {
+                test_debug!(
+                    "{:?}: Drop {} from unauthenticated or unknown event {}",
+                    &self.local_peer,
+                    message.payload.get_message_name(),
+                    event_id
+                );
+                return;
+            }
+        }
+
+        match message.payload {
+            StacksMessageType::CompactBlock(data) => {
+                let pending = PendingCompactBlock::new(
+                    event_id,
+                    message.preamble,
+                    message.relayers,
+                    data,
+                    get_epoch_time_secs(),
+                );
+                let index_block_hash = pending.index_block_hash();
+                if self.pending_compact_blocks.contains_key(&index_block_hash) {
+                    debug!(
+                        "{:?}: Already reconstructing compact block {}",
+                        &self.local_peer, &index_block_hash
+                    );
+                    return;
+                }
+                if self.pending_compact_blocks.len() >= COMPACT_BLOCKS_PENDING_MAX {
+                    debug!(
+                        "{:?}: Drop compact block {} -- already reconstructing {}",
+                        &self.local_peer,
+                        &index_block_hash,
+                        self.pending_compact_blocks.len()
+                    );
+                    return;
+                }
+                let num_from_peer = self
+                    .pending_compact_blocks
+                    .values()
+                    .filter(|pending| pending.event_id == event_id)
+                    .count();
+                if num_from_peer >= COMPACT_BLOCKS_PENDING_PER_PEER_MAX {
+                    debug!(
+                        "{:?}: Drop compact block {} -- already reconstructing {} from event {}",
+                        &self.local_peer, &index_block_hash, num_from_peer, event_id
+                    );
+                    return;
+                }
+                match PeerNetwork::need_block_or_microblock_stream(
+                    sortdb,
+                    chainstate,
+                    &pending.consensus_hash,
+                    false,
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        debug!(
+                            "{:?}: Drop compact block {} -- already have it",
+                            &self.local_peer, &index_block_hash
+                        );
+                        return;
+                    }
+                    Err(e) => {
+                        debug!(
+                            "{:?}: Drop compact block {} -- unknown sortition: {:?}",
+                            &self.local_peer, &index_block_hash, &e
+                        );
+                        return;
+                    }
+                }
+
+                debug!(
+                    "{:?}: Got compact block {} with {} transactions from event {}",
+                    &self.local_peer,
+                    &index_block_hash,
+                    pending.txs.len(),
+                    event_id
+                );
+                self.pending_compact_blocks
+                    .insert(index_block_hash, pending);
+            }
+            StacksMessageType::CompactBlockTxs(data) => {
+                let index_block_hash = StacksBlockHeader::make_index_block_hash(
+                    &data.consensus_hash,
+                    &data.block_hash,
+                );
+                let filled = match self.pending_compact_blocks.get_mut(&index_block_hash) {
+                    Some(pending) if pending.event_id == event_id => {
+                        pending.fill_from_peer(data.txs)
+                    }
+                    _ => {
+                        debug!(
+                            "{:?}: Drop unrequested CompactBlockTxs for {} from event {}",
+                            &self.local_peer, &index_block_hash, event_id
+                        );
+                        return;
+                    }
+                };
+                if !filled {
+                    info!(
+                        "{:?}: Event {} sent the wrong transactions for compact block {}",
+                        &self.local_peer, event_id, &index_block_hash
+                    );
+                    self.pending_compact_blocks.remove(&index_block_hash);
+                    self.add_reputation_event(event_id, ReputationEvent::InvalidData);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Make progress on reconstructing compact blocks:
+    /// * look up their transactions in the mempool,
+    /// * ask their pushers for the ones we don't have,
+    /// * and hand off the ones we've finished as if they had been pushed to us in full.
+    pub fn process_compact_blocks(
+        &mut self,
+        sortdb: &SortitionDB,
+        chainstate: &StacksChainState,
+        mempool: &MemPoolDB,
+        ibd: bool,
+        network_result: &mut NetworkResult,
+    ) -> () {
+        if self.pending_compact_blocks.len() == 0 {
+            return;
+        }
+
+        let now = get_epoch_time_secs();
+        let mut finished: HashMap<usize, Vec<StacksMessage>> = HashMap::new();
+        let mut to_remove = vec![];
+        let mut to_request = vec![];
+        let mut to_punish = vec![];
+
+        for (index_block_hash, pending) in self.pending_compact_blocks.iter_mut() {
+            if !self.peers.contains_key(&pending.event_id) {
+                debug!(
+                    "{:?}: Drop compact block {} -- pusher disconnected",
+                    &self.local_peer, index_block_hash
+                );
+                to_remove.push(index_block_hash.clone());
+                continue;
+            }
+
+            if !pending.mempool_checked {
+                // don't bother if we already have it
+                match PeerNetwork::need_block_or_microblock_stream(
+                    sortdb,
+                    chainstate,
+                    &pending.consensus_hash,
+                    false,
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        debug!(
+                            "{:?}: Drop compact block {} -- already have it",
+                            &self.local_peer, index_block_hash
+                        );
+                        to_remove.push(index_block_hash.clone());
+                        continue;
+                    }
+                    Err(e) => {
+                        debug!(
+                            "{:?}: Drop compact block {} -- failed to check if we need it: {:?}",
+                            &self.local_peer, index_block_hash, &e
+                        );
+                        to_remove.push(index_block_hash.clone());
+                        continue;
+                    }
+                }
+
+                if let Err(e) = pending.fill_from_mempool(mempool) {
+                    warn!(
+                        "{:?}: Failed to look up transactions for compact block {}: {:?}",
+                        &self.local_peer, index_block_hash, &e
+                    );
+                    to_remove.push(index_block_hash.clone());
+                    continue;
+                }
+            }
+
+            match pending.try_into_block() {
+                Some(Ok(block)) => {
+                    debug!(
+                        "{:?}: Reconstructed compact block {} ({} of {} transactions from the pusher)",
+                        &self.local_peer,
+                        index_block_hash,
+                        pending.requested.as_ref().map(|r| r.len()).unwrap_or(0),
+                        block.txs.len()
+                    );
+                    let message = StacksMessage {
+                        preamble: pending.preamble.clone(),
+                        relayers: pending.relayers.clone(),
+                        payload: StacksMessageType::Blocks(BlocksData {
+                            blocks: vec![BlocksDatum(pending.consensus_hash.clone(), block)],
+                        }),
+                    };
+                    if let Some(messages) = finished.get_mut(&pending.event_id) {
+                        messages.push(message);
+                    } else {
+                        finished.insert(pending.event_id, vec![message]);
+                    }
+                    to_remove.push(index_block_hash.clone());
+                }
+                Some(Err(e)) => {
+                    if pending.requested_all() {
+                        // the pusher's transactions don't make up the block it announced
+                        info!(
+                            "{:?}: Invalid compact block {} from event {}: {:?}",
+                            &self.local_peer, index_block_hash, pending.event_id, &e
+                        );
+                        to_punish.push(pending.event_id);
+                        to_remove.push(index_block_hash.clone());
+                    } else {
+                        // one of our mempool transactions might have been mistaken for one of
+                        // the block's, even if we asked the pusher for the rest.  Ask for all of
+                        // them, once.
+                        debug!(
+                            "{:?}: Reconstructed compact block {} is invalid ({:?}); will ask for all of its transactions",
+                            &self.local_peer, index_block_hash, &e
+                        );
+                        pending.clear_short_id_txs();
+                        let missing = pending.missing_indexes();
+                        to_request.push((
+                            pending.event_id,
+                            pending.consensus_hash.clone(),
+                            pending.header.block_hash(),
+                            missing.clone(),
+                        ));
+                        pending.requested = Some(missing);
+                    }
+                }
+                None => {
+                    if pending.requested.is_none() {
+                        let missing = pending.missing_indexes();
+                        debug!(
+                            "{:?}: Compact block {} is missing {} of {} transactions; will ask for them",
+                            &self.local_peer,
+                            index_block_hash,
+                            missing.len(),
+                            pending.txs.len()
+                        );
+                        to_request.push((
+                            pending.event_id,
+                            pending.consensus_hash.clone(),
+                            pending.header.block_hash(),
+                            missing.clone(),
+                        ));
+                        pending.requested = Some(missing);
+                    } else if pending.first_seen + COMPACT_BLOCK_TIMEOUT < now {
+                        debug!(
+                            "{:?}: Timed out waiting for transactions for compact block {}",
+                            &self.local_peer, index_block_hash
+                        );
+                        to_remove.push(index_block_hash.clone());
+                    }
+                }
+            }
+        }
+
+        for index_block_hash in to_remove.into_iter() {
+            self.pending_compact_blocks.remove(&index_block_hash);
+        }
+
+        for event_id in to_punish.into_iter() {
+            self.add_reputation_event(event_id, ReputationEvent::InvalidBlock);
+        }
+
+        for (event_id, consensus_hash, block_hash, indexes) in to_request.into_iter() {
+            let index_block_hash =
+                StacksBlockHeader::make_index_block_hash(&consensus_hash, &block_hash);
+            let request = StacksMessageType::GetCompactBlockTxs(GetCompactBlockTxsData {
+                consensus_hash,
+                block_hash,
+                indexes,
+            });
+            if let Err(e) = self.send_compact_block_request(event_id, request) {
+                debug!(
+                    "{:?}: Failed to ask event {} for transactions in compact block {}: {:?}",
+                    &self.local_peer, event_id, &index_block_hash, &e
+                );
+                self.pending_compact_blocks.remove(&index_block_hash);
+            }
+        }
+
+        if finished.len() > 0 {
+            let unhandled =
+                self.handle_unsolicited_messages(sortdb, chainstate, finished, ibd, true);
+            network_result.consume_unsolicited(unhandled);
+        }
+    }
+
+    /// Ask the pusher of a compact block for the transactions we're missing
+    fn send_compact_block_request(
+        &mut self,
+        event_id: usize,
+        request: StacksMessageType,
+    ) -> Result<(), net_error> {
+        let convo = self
+            .peers
+            .get_mut(&event_id)
+            .ok_or(net_error::PeerNotConnected)?;
+        let message =
+            convo.sign_message(&self.chain_view, &self.local_peer.private_key, request)?;
+        self.relay_signed_message_to_event(event_id, message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::chainstate::stacks::test::make_codec_test_block;
+
+    #[test]
+    fn test_compact_block_reconstruct() {
+        let block = make_codec_test_block(10);
+        let consensus_hash = ConsensusHash([0x11; 20]);
+        let data = CompactBlockData::from_block(consensus_hash.clone(), &block);
+
+        // coinbase is prefilled
+        assert_eq!(data.prefilled_txs.len(), 1);
+        assert_eq!(data.prefilled_txs[0].index, 0);
+        assert_eq!(data.num_txs(), block.txs.len());
+        assert_eq!(data.short_ids.len(), block.txs.len() - 1);
+
+        let mut pending = PendingCompactBlock::new(
+            1,
+            Preamble::new(
+                0,
+                0,
+                0,
+                &BurnchainHeaderHash([0u8; 32]),
+                0,
+                &BurnchainHeaderHash([0u8; 32]),
+                0,
+            ),
+            vec![],
+            data,
+            0,
+        );
+        assert_eq!(
+            pending.index_block_hash(),
+            StacksBlockHeader::make_index_block_hash(&consensus_hash, &block.block_hash())
+        );
+        assert_eq!(pending.missing_indexes().len(), block.txs.len() - 1);
+        assert!(pending.try_into_block().is_none());
+        assert!(!pending.requested_all());
+
+        // a partial request leaves room for a short ID collision with our mempool
+        let missing = pending.missing_indexes();
+        pending.requested = Some(missing[1..].to_vec());
+        assert!(!pending.requested_all());
+
+        // the pusher sends us the wrong number of transactions
+        pending.requested = Some(missing.clone());
+        assert!(pending.requested_all());
+        assert!(!pending.fill_from_peer(vec![]));
+
+        // the pusher sends us the wrong transactions
+        let mut wrong_txs: Vec<StacksTransaction> = missing
+            .iter()
+            .map(|i| block.txs[*i as usize].clone())
+            .collect();
+        wrong_txs.reverse();
+        assert!(pending.fill_from_peer(wrong_txs));
+        match pending.try_into_block() {
+            Some(Err(_)) => {}
+            x => {
+                panic!("expected invalid block, got {:?}", &x);
+            }
+        }
+
+        // the pusher sends us the right transactions
+        pending.clear_short_id_txs();
+        assert_eq!(pending.missing_indexes(), missing);
+        let txs = missing
+            .iter()
+            .map(|i| block.txs[*i as usize].clone())
+            .collect();
+        assert!(pending.fill_from_peer(txs));
+        assert_eq!(pending.try_into_block().unwrap().unwrap(), block);
+    }
+}
@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::io;
@@ -55,6 +57,7 @@ use crate::net::neighbors::{
     NEIGHBOR_REQUEST_TIMEOUT, NEIGHBOR_WALK_INTERVAL, NUM_INITIAL_WALKS, WALK_MAX_DURATION,
     WALK_MIN_DURATION, WALK_RESET_INTERVAL, WALK_RESET_PROB, WALK_RETRY_COUNT, WALK_STATE_TIMEOUT,
 };
+use crate::net::socks::Socks5Proxy;
 
 use clarity::vm::{costs::ExecutionCost, types::BOUND_VALUE_SERIALIZATION_HEX};
 
@@ -262,6 +265,19 @@ impl<P: ProtocolFamily> NetworkReplyHandle<P> {
         self.request_pipe_write = fd_opt;
         Ok(self.request_pipe_write.is_none())
     }
+
+    /// Try to move buffered data along, but keep the pipe open for more.
+    /// Use this instead of try_flush() for replies that are written over time (like event
+    /// streams).
+    /// Return true if all buffered data has been moved along.
+    pub fn try_flush_buffered(&mut self) -> Result<bool, net_error> {
+        match self.request_pipe_write {
+            Some(ref mut fd) => fd.try_flush().map_err(net_error::WriteError),
+            None => Err(net_error::WriteError(io::Error::from(
+                io::ErrorKind::BrokenPipe,
+            ))),
+        }
+    }
 }
 
 impl<P: ProtocolFamily> Write for NetworkReplyHandle<P> {
@@ -380,6 +396,39 @@ pub struct ConnectionOptions {
     pub mempool_max_tx_query: u64,
     /// how long a mempool sync is allowed to take, in total, before timing out
     pub mempool_sync_timeout: u64,
+    /// advertise `ServiceFlags::ENCRYPTION`, and encrypt P2P payloads to peers that do too
+    pub p2p_encryption: bool,
+    /// SOCKS5 proxy through which to make all outbound p2p and HTTP connections
+    pub socks5_proxy: Option<Socks5Proxy>,
+    /// Hostnames to ask the SOCKS5 proxy for (e.g. `.onion` addresses), keyed by the placeholder
+    /// address that the peer is known by
+    pub socks5_hostnames: HashMap<PeerAddress, String>,
+    /// how many `/v2/events/...` streams the RPC server will serve at once.  0 disables them.
+    pub max_event_streams: u64,
+    /// how many requests per minute each client may make to cheap RPC endpoints.  0 means no
+    /// limit.
+    pub rpc_rate_limit_cheap: u64,
+    /// how many requests per minute each client may make to expensive RPC endpoints (read-only
+    /// calls, fee estimates, MARF proofs, mempool queries, and block and microblock downloads).
+    /// 0 means no limit.
+    pub rpc_rate_limit_expensive: u64,
+    /// API keys that RPC clients may present in an `X-API-Key` header.  Each key gets its own
+    /// rate limit budgets instead of sharing those of its IP address.
+    pub rpc_api_keys: HashSet<String>,
+    /// `rpc_rate_limit_cheap`, for clients with an API key
+    pub rpc_api_key_rate_limit_cheap: u64,
+    /// `rpc_rate_limit_expensive`, for clients with an API key
+    pub rpc_api_key_rate_limit_expensive: u64,
+    /// total upload bandwidth budget across all P2P and HTTP peers, in bytes/sec.  Once it's used
+    /// up, block and microblock serving and pushes wait.  0 means no limit.
+    pub max_upload_bandwidth: u64,
+    /// total download bandwidth budget across all P2P and HTTP peers, in bytes/sec.  Once it's
+    /// used up, reads from peers wait.  0 means no limit.
+    pub max_download_bandwidth: u64,
+    /// `max_upload_bandwidth`, for each peer IP address
+    pub max_peer_upload_bandwidth: u64,
+    /// `max_download_bandwidth`, for each peer IP address
+    pub max_peer_download_bandwidth: u64,
 
     // fault injection
     pub disable_neighbor_walk: bool,
@@ -466,6 +515,19 @@ impl std::default::Default for ConnectionOptions {
             mempool_sync_interval: 30, // number of seconds in-between mempool sync
             mempool_max_tx_query: 128, // maximum number of transactions to visit per mempool query
             mempool_sync_timeout: 180, // how long a mempool sync can go for (3 minutes)
+            p2p_encryption: false,     // P2P payload encryption is opt-in
+            socks5_proxy: None,        // connect directly by default
+            socks5_hostnames: HashMap::new(),
+            max_event_streams: 0,    // event streams are opt-in
+            rpc_rate_limit_cheap: 0, // no RPC rate limits by default
+            rpc_rate_limit_expensive: 0,
+            rpc_api_keys: HashSet::new(),
+            rpc_api_key_rate_limit_cheap: 0,
+            rpc_api_key_rate_limit_expensive: 0,
+            max_upload_bandwidth: 0, // no bandwidth budgets by default
+            max_download_bandwidth: 0,
+            max_peer_upload_bandwidth: 0,
+            max_peer_download_bandwidth: 0,
 
             // no faults on by default
             disable_neighbor_walk: false,
@@ -15,6 +15,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::fmt;
+use std::net::IpAddr;
 
 use rusqlite::types::ToSql;
 use rusqlite::Row;
@@ -50,7 +51,11 @@ use rand::thread_rng;
 use rand::Rng;
 use rand::RngCore;
 
+use serde::{Deserialize, Serialize};
+
 use crate::net::asn::ASEntry4;
+use crate::net::reputation::PeerReputation;
+use crate::net::reputation::REPUTATION_DECAY_INTERVAL;
 use crate::net::Neighbor;
 use crate::net::NeighborAddress;
 use crate::net::NeighborKey;
@@ -72,6 +77,31 @@ impl PeerAddress {
     pub fn to_bin(&self) -> String {
         to_bin(&self.0)
     }
+
+    /// Parse a CIDR prefix like `1.2.3.0/24` or `2001:db8::/32` into a prefix and the mask the
+    /// CIDR tables use.  IPv4 addresses are stored IPv6-mapped, so their mask is offset by 96.
+    pub fn from_cidr_str(cidr: &str) -> Option<(PeerAddress, u32)> {
+        let mut parts = cidr.splitn(2, '/');
+        let ip = parts.next()?.parse::<IpAddr>().ok()?;
+        let prefix_len = parts.next()?.parse::<u32>().ok()?;
+        let (max_len, offset) = match ip {
+            IpAddr::V4(..) => (32, 96),
+            IpAddr::V6(..) => (128, 0),
+        };
+        if prefix_len == 0 || prefix_len > max_len {
+            return None;
+        }
+        Some((PeerAddress::from_ip(&ip), prefix_len + offset))
+    }
+
+    /// Inverse of `from_cidr_str()`
+    pub fn to_cidr_string(&self, mask: u32) -> String {
+        if self.is_ipv4() && mask > 96 {
+            format!("{}/{}", self.to_socketaddr(0).ip(), mask - 96)
+        } else {
+            format!("{}/{}", self.to_socketaddr(0).ip(), mask)
+        }
+    }
 }
 
 impl FromColumn<PeerAddress> for PeerAddress {
@@ -293,6 +323,71 @@ impl FromRow<Neighbor> for Neighbor {
     }
 }
 
+/// A frontier peer as written by `stacks-inspect peers export-frontier`, so a new node's peer DB
+/// can be seeded with known-good neighbors.  Only the peer's identity is carried over; the
+/// allow/deny state and degree estimates are left for the importing node to work out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrontierPeer {
+    pub peer_version: u32,
+    pub network_id: u32,
+    #[serde(rename = "ip")]
+    pub addrbytes: PeerAddress,
+    pub port: u16,
+    /// compressed public key, hex-encoded
+    pub public_key: String,
+    pub expire_block: u64,
+    pub last_contact_time: u64,
+}
+
+impl FrontierPeer {
+    pub fn from_neighbor(neighbor: &Neighbor) -> FrontierPeer {
+        FrontierPeer {
+            peer_version: neighbor.addr.peer_version,
+            network_id: neighbor.addr.network_id,
+            addrbytes: neighbor.addr.addrbytes.clone(),
+            port: neighbor.addr.port,
+            public_key: to_hex(&neighbor.public_key.to_bytes_compressed()),
+            expire_block: neighbor.expire_block,
+            last_contact_time: neighbor.last_contact_time,
+        }
+    }
+
+    /// Make a fresh (neither allowed nor denied) neighbor out of this record.
+    /// Returns None if the public key doesn't decode.
+    pub fn to_neighbor(&self) -> Option<Neighbor> {
+        let mut public_key = Secp256k1PublicKey::from_hex(&self.public_key).ok()?;
+        public_key.set_compressed(true);
+        Some(Neighbor {
+            addr: NeighborKey {
+                peer_version: self.peer_version,
+                network_id: self.network_id,
+                addrbytes: self.addrbytes.clone(),
+                port: self.port,
+            },
+            public_key,
+            expire_block: self.expire_block,
+            last_contact_time: self.last_contact_time,
+            allowed: 0,
+            denied: 0,
+            asn: 0,
+            org: 0,
+            in_degree: 0,
+            out_degree: 0,
+        })
+    }
+}
+
+impl FromRow<PeerReputation> for PeerReputation {
+    fn from_row<'a>(row: &'a Row) -> Result<PeerReputation, db_error> {
+        let score: i64 = row.get_unwrap("score");
+        let last_update = u64::from_column(row, "last_update")?;
+        Ok(PeerReputation {
+            score: score,
+            last_update: last_update,
+        })
+    }
+}
+
 // In what is likely an abuse of Sqlite, the peer database is structured such that the `frontier`
 // table stores peers keyed by a deterministically-chosen random "slot," instead of their IP/port.
 // (i.e. the slot is determined by a cryptographic the hash of the IP/port).  The reason for this
@@ -363,6 +458,19 @@ const PEERDB_INITIAL_SCHEMA: &'static [&'static str] = &[
 const PEERDB_INDEXES: &'static [&'static str] =
     &["CREATE INDEX IF NOT EXISTS peer_address_index ON frontier(network_id,addrbytes,port);"];
 
+// Peer reputations are kept apart from the frontier, so a peer's reputation survives it getting
+// evicted from (and later re-inserted into) its slot.
+const PEERDB_REPUTATION_SCHEMA: &'static [&'static str] = &[r#"
+    CREATE TABLE IF NOT EXISTS peer_reputation(
+        network_id INTEGER NOT NULL,
+        addrbytes TEXT NOT NULL,
+        port INTEGER NOT NULL,
+        score INTEGER NOT NULL,
+        last_update INTEGER NOT NULL,
+
+        PRIMARY KEY(network_id,addrbytes,port)
+    );"#];
+
 #[derive(Debug)]
 pub struct PeerDB {
     pub conn: Connection,
@@ -444,6 +552,7 @@ impl PeerDB {
         tx.commit().map_err(db_error::SqliteError)?;
 
         self.add_indexes()?;
+        self.add_reputation_schema()?;
         Ok(())
     }
 
@@ -456,6 +565,16 @@ impl PeerDB {
         Ok(())
     }
 
+    /// Add the peer reputation table, if it's not there already (i.e. this DB predates it)
+    fn add_reputation_schema(&mut self) -> Result<(), db_error> {
+        let tx = self.tx_begin()?;
+        for row_text in PEERDB_REPUTATION_SCHEMA {
+            tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
     fn update_local_peer(
         &mut self,
         network_id: u32,
@@ -607,6 +726,27 @@ impl PeerDB {
         }
         if readwrite {
             db.add_indexes()?;
+            db.add_reputation_schema()?;
+        }
+        Ok(db)
+    }
+
+    /// Open an existing peer database at the given path, without touching the local peer record
+    /// (i.e. for offline inspection and maintenance).  Fails with `NoDBError` if it doesn't exist.
+    pub fn open(path: &String, readwrite: bool) -> Result<PeerDB, db_error> {
+        if fs::metadata(path).is_err() {
+            return Err(db_error::NoDBError);
+        }
+        let open_flags = if readwrite {
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+        } else {
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+        };
+        let conn = sqlite_open(path, open_flags, false)?;
+        let mut db = PeerDB { conn, readwrite };
+        if readwrite {
+            db.add_indexes()?;
+            db.add_reputation_schema()?;
         }
         Ok(db)
     }
@@ -1259,6 +1399,34 @@ impl PeerDB {
         Ok(())
     }
 
+    /// Remove an allowed CIDR prefix.  Peers it covered stay allowed only if another allowed
+    /// prefix also covers them.
+    pub fn remove_allow_cidr<'a>(
+        tx: &mut Transaction<'a>,
+        prefix: &PeerAddress,
+        mask: u32,
+    ) -> Result<(), db_error> {
+        assert!(mask > 0 && mask <= 128);
+        PeerDB::remove_cidr_prefix(tx, "allowed_prefixes", prefix, mask)?;
+
+        debug!("Remove allow {}/{}", &prefix, mask);
+        PeerDB::refresh_allows(tx)
+    }
+
+    /// Remove a denied CIDR prefix.  Peers it covered stay denied only if another denied prefix
+    /// also covers them.
+    pub fn remove_deny_cidr<'a>(
+        tx: &mut Transaction<'a>,
+        prefix: &PeerAddress,
+        mask: u32,
+    ) -> Result<(), db_error> {
+        assert!(mask > 0 && mask <= 128);
+        PeerDB::remove_cidr_prefix(tx, "denied_prefixes", prefix, mask)?;
+
+        debug!("Remove deny {}/{}", &prefix, mask);
+        PeerDB::refresh_denies(tx)
+    }
+
     /// Get random neighbors, optionally always including allowed neighbors
     pub fn get_random_neighbors(
         conn: &DBConn,
@@ -1297,13 +1465,18 @@ impl PeerDB {
             return Ok(ret);
         }
 
-        // fill in with non-allowed, randomly-chosen, fresh peers
+        // fill in with non-allowed, randomly-chosen, fresh peers that don't (still) have a
+        // negative reputation
         let random_peers_qry = if always_include_allowed {
             "SELECT * FROM frontier WHERE network_id = ?1 AND last_contact_time >= 0 AND ?2 < expire_block_height AND denied < ?3 AND \
-                 (allowed >= 0 AND allowed <= ?4) AND (peer_version & 0x000000ff) >= ?5 ORDER BY RANDOM() LIMIT ?6".to_string()
+                 (allowed >= 0 AND allowed <= ?4) AND (peer_version & 0x000000ff) >= ?5 AND \
+                 NOT EXISTS (SELECT 1 FROM peer_reputation r WHERE r.network_id = frontier.network_id AND r.addrbytes = frontier.addrbytes AND r.port = frontier.port AND \
+                 r.score < 0 AND r.last_update + (0 - r.score) * ?7 > ?3) ORDER BY RANDOM() LIMIT ?6".to_string()
         } else {
             "SELECT * FROM frontier WHERE network_id = ?1 AND last_contact_time >= 0 AND ?2 < expire_block_height AND denied < ?3 AND \
-                 (allowed < 0 OR (allowed >= 0 AND allowed <= ?4)) AND (peer_version & 0x000000ff) >= ?5 ORDER BY RANDOM() LIMIT ?6".to_string()
+                 (allowed < 0 OR (allowed >= 0 AND allowed <= ?4)) AND (peer_version & 0x000000ff) >= ?5 AND \
+                 NOT EXISTS (SELECT 1 FROM peer_reputation r WHERE r.network_id = frontier.network_id AND r.addrbytes = frontier.addrbytes AND r.port = frontier.port AND \
+                 r.score < 0 AND r.last_update + (0 - r.score) * ?7 > ?3) ORDER BY RANDOM() LIMIT ?6".to_string()
         };
 
         let random_peers_args: &[&dyn ToSql] = &[
@@ -1313,6 +1486,7 @@ impl PeerDB {
             &u64_to_sql(now_secs)?,
             &network_epoch,
             &(count - (ret.len() as u32)),
+            &u64_to_sql(REPUTATION_DECAY_INTERVAL)?,
         ];
         let mut random_peers =
             query_rows::<Neighbor, _>(conn, &random_peers_qry, random_peers_args)?;
@@ -1364,6 +1538,31 @@ impl PeerDB {
         Ok(())
     }
 
+    /// Load IPv4 prefix-to-ASN entries (i.e. from `ASEntry4::from_file()`), and re-classify the
+    /// peers already in the frontier against the updated table.
+    pub fn import_asn4<'a>(
+        tx: &mut Transaction<'a>,
+        asn4_entries: &[ASEntry4],
+    ) -> Result<(), db_error> {
+        for asn4 in asn4_entries {
+            PeerDB::asn4_insert(tx, asn4)?;
+        }
+
+        for mut neighbor in PeerDB::get_all_peers(tx)?.into_iter() {
+            if let Some(asn) = PeerDB::asn_lookup(tx, &neighbor.addr.addrbytes)? {
+                neighbor.asn = asn;
+                PeerDB::update_peer(tx, &neighbor)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the whole IPv4 prefix-to-ASN table
+    pub fn get_asn4_entries(conn: &DBConn) -> Result<Vec<ASEntry4>, db_error> {
+        let qry = "SELECT * FROM asn4 ORDER BY prefix ASC, mask ASC".to_string();
+        query_rows::<ASEntry4, _>(conn, &qry, NO_PARAMS)
+    }
+
     /// Classify an IPv4 address to its AS number.
     /// This method doesn't have to be particularly efficient since it's off the critical path.
     pub fn asn4_lookup(conn: &DBConn, addrbits: &PeerAddress) -> Result<Option<u32>, db_error> {
@@ -1413,6 +1612,101 @@ impl PeerDB {
         let rows = query_rows::<Neighbor, _>(conn, &qry, NO_PARAMS)?;
         Ok(rows)
     }
+
+    /// Get the frontier in a form that can be loaded into another node's peer DB
+    pub fn export_frontier(conn: &DBConn) -> Result<Vec<FrontierPeer>, db_error> {
+        let peers = PeerDB::get_all_peers(conn)?;
+        Ok(peers.iter().map(FrontierPeer::from_neighbor).collect())
+    }
+
+    /// Seed the frontier with exported peers.  Peers on a different network, peers whose slots
+    /// are all taken, and peers we already have are skipped (the latter are left untouched).
+    /// The allow/deny CIDR lists are applied to the new peers.  Returns how many were inserted.
+    pub fn import_frontier<'a>(
+        tx: &mut Transaction<'a>,
+        peers: &[FrontierPeer],
+    ) -> Result<u64, db_error> {
+        let local_peer = PeerDB::get_local_peer(tx)?;
+        let mut num_inserted = 0;
+        for peer in peers.iter() {
+            if peer.network_id != local_peer.network_id {
+                warn!(
+                    "Skip frontier peer {:?}: network ID {:08x} is not ours ({:08x})",
+                    &peer.addrbytes.to_socketaddr(peer.port),
+                    peer.network_id,
+                    local_peer.network_id
+                );
+                continue;
+            }
+            let mut neighbor = match peer.to_neighbor() {
+                Some(neighbor) => neighbor,
+                None => {
+                    warn!(
+                        "Skip frontier peer {:?}: invalid public key {}",
+                        &peer.addrbytes.to_socketaddr(peer.port),
+                        &peer.public_key
+                    );
+                    continue;
+                }
+            };
+            if PeerDB::get_peer(tx, peer.network_id, &peer.addrbytes, peer.port)?.is_some() {
+                continue;
+            }
+            neighbor.asn = PeerDB::asn_lookup(tx, &peer.addrbytes)?.unwrap_or(0);
+            if PeerDB::try_insert_peer(tx, &neighbor)? {
+                num_inserted += 1;
+            }
+        }
+
+        PeerDB::refresh_allows(tx)?;
+        PeerDB::refresh_denies(tx)?;
+        Ok(num_inserted)
+    }
+
+    /// Get a peer's stored reputation, if we have one.
+    /// The score is as of its last update; use `PeerReputation::score_at()` to decay it.
+    pub fn get_peer_reputation(
+        conn: &DBConn,
+        network_id: u32,
+        peer_addr: &PeerAddress,
+        peer_port: u16,
+    ) -> Result<Option<PeerReputation>, db_error> {
+        let qry =
+            "SELECT * FROM peer_reputation WHERE network_id = ?1 AND addrbytes = ?2 AND port = ?3";
+        let args = [
+            &network_id as &dyn ToSql,
+            &peer_addr.to_bin() as &dyn ToSql,
+            &peer_port as &dyn ToSql,
+        ];
+        query_row::<PeerReputation, _>(conn, qry, &args)
+    }
+
+    /// Apply a change to a peer's reputation as of `now`, creating it if need be.
+    /// Returns the updated reputation.
+    pub fn update_peer_reputation<'a>(
+        tx: &mut Transaction<'a>,
+        network_id: u32,
+        peer_addr: &PeerAddress,
+        peer_port: u16,
+        delta: i64,
+        now: u64,
+    ) -> Result<PeerReputation, db_error> {
+        let mut reputation = PeerDB::get_peer_reputation(tx, network_id, peer_addr, peer_port)?
+            .unwrap_or(PeerReputation::new(now));
+        reputation.apply(delta, now);
+
+        let args: &[&dyn ToSql] = &[
+            &network_id,
+            &peer_addr.to_bin(),
+            &peer_port,
+            &reputation.score,
+            &u64_to_sql(reputation.last_update)?,
+        ];
+        tx.execute("INSERT OR REPLACE INTO peer_reputation (network_id, addrbytes, port, score, last_update) VALUES (?1, ?2, ?3, ?4, ?5)", args)
+            .map_err(db_error::SqliteError)?;
+
+        Ok(reputation)
+    }
 }
 
 #[cfg(test)]
@@ -1698,6 +1992,141 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_peer_reputation() {
+        let mut initial_neighbors = vec![];
+        for i in 0..10 {
+            initial_neighbors.push(Neighbor {
+                addr: NeighborKey {
+                    peer_version: 0x12345678,
+                    network_id: 0x9abcdef0,
+                    addrbytes: PeerAddress([i as u8; 16]),
+                    port: i,
+                },
+                public_key: Secp256k1PublicKey::from_private(&Secp256k1PrivateKey::new()),
+                expire_block: (i + 23456) as u64,
+                last_contact_time: (1552509642 + (i as u64)) as u64,
+                allowed: 0,
+                denied: -1,
+                asn: (34567 + i) as u32,
+                org: (45678 + i) as u32,
+                in_degree: 1,
+                out_degree: 1,
+            });
+        }
+
+        let mut db = PeerDB::connect_memory(
+            0x9abcdef0,
+            12345,
+            0,
+            "http://foo.com".into(),
+            &vec![],
+            &initial_neighbors,
+        )
+        .unwrap();
+
+        let n = &initial_neighbors[0];
+        assert_eq!(
+            PeerDB::get_peer_reputation(
+                db.conn(),
+                n.addr.network_id,
+                &n.addr.addrbytes,
+                n.addr.port
+            )
+            .unwrap(),
+            None
+        );
+
+        let now = util::get_epoch_time_secs();
+        {
+            let mut tx = db.tx_begin().unwrap();
+
+            // peers 0-2 behaved badly just now
+            for n in initial_neighbors[0..3].iter() {
+                let rep = PeerDB::update_peer_reputation(
+                    &mut tx,
+                    n.addr.network_id,
+                    &n.addr.addrbytes,
+                    n.addr.port,
+                    -50,
+                    now,
+                )
+                .unwrap();
+                assert_eq!(rep.score, -50);
+            }
+
+            // peer 3 behaved badly a long time ago
+            let n = &initial_neighbors[3];
+            PeerDB::update_peer_reputation(
+                &mut tx,
+                n.addr.network_id,
+                &n.addr.addrbytes,
+                n.addr.port,
+                -50,
+                now - 50 * REPUTATION_DECAY_INTERVAL,
+            )
+            .unwrap();
+
+            // peer 4 behaved well
+            let n = &initial_neighbors[4];
+            PeerDB::update_peer_reputation(
+                &mut tx,
+                n.addr.network_id,
+                &n.addr.addrbytes,
+                n.addr.port,
+                10,
+                now,
+            )
+            .unwrap();
+
+            tx.commit().unwrap();
+        }
+
+        // updates accumulate
+        {
+            let n = &initial_neighbors[0];
+            let mut tx = db.tx_begin().unwrap();
+            let rep = PeerDB::update_peer_reputation(
+                &mut tx,
+                n.addr.network_id,
+                &n.addr.addrbytes,
+                n.addr.port,
+                -60,
+                now,
+            )
+            .unwrap();
+            tx.commit().unwrap();
+
+            assert_eq!(rep.score, -110);
+            assert!(rep.is_banned(now));
+            assert_eq!(
+                PeerDB::get_peer_reputation(
+                    db.conn(),
+                    n.addr.network_id,
+                    &n.addr.addrbytes,
+                    n.addr.port
+                )
+                .unwrap(),
+                Some(rep)
+            );
+        }
+
+        // peers with (still) negative reputations are not walked
+        let walk_neighbors =
+            PeerDB::get_random_walk_neighbors(db.conn(), 0x9abcdef0, 0x78, 10, 23455).unwrap();
+        assert_eq!(walk_neighbors.len(), 7);
+        for n in initial_neighbors[0..3].iter() {
+            assert!(!walk_neighbors.contains(n));
+        }
+        for n in initial_neighbors[3..10].iter() {
+            assert!(walk_neighbors.contains(n));
+        }
+
+        let initial =
+            PeerDB::get_initial_neighbors(db.conn(), 0x9abcdef0, 0x78, 10, 23455).unwrap();
+        assert_eq!(initial.len(), 7);
+    }
+
     #[test]
     fn test_get_neighbors_in_current_epoch() {
         let mut initial_neighbors = vec![];
@@ -2392,4 +2821,273 @@ mod test {
         let local_peer = PeerDB::get_local_peer(db.conn()).unwrap();
         assert_eq!(local_peer.private_key, key2);
     }
+
+    #[test]
+    fn test_cidr_strings() {
+        assert_eq!(
+            PeerAddress::from_cidr_str("1.2.3.0/24"),
+            Some((PeerAddress::from_ipv4(1, 2, 3, 0), 120))
+        );
+        assert_eq!(
+            PeerAddress::from_cidr_str("::1/128"),
+            Some((
+                PeerAddress([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                128
+            ))
+        );
+        for bad in [
+            "1.2.3.0",
+            "1.2.3.0/33",
+            "1.2.3.0/0",
+            "::1/129",
+            "foo/8",
+            "1.2.3.0/x",
+        ]
+        .iter()
+        {
+            assert_eq!(PeerAddress::from_cidr_str(bad), None);
+        }
+
+        for cidr in ["1.2.3.0/24", "2001:db8::/32", "10.0.0.1/32"].iter() {
+            let (prefix, mask) = PeerAddress::from_cidr_str(cidr).unwrap();
+            assert_eq!(&prefix.to_cidr_string(mask), cidr);
+        }
+    }
+
+    #[test]
+    fn test_peer_remove_cidr() {
+        let neighbor = Neighbor {
+            addr: NeighborKey {
+                peer_version: 0x12345678,
+                network_id: 0x9abcdef0,
+                addrbytes: PeerAddress::from_ipv4(1, 2, 3, 4),
+                port: 12345,
+            },
+            public_key: Secp256k1PublicKey::from_hex(
+                "02fa66b66f8971a8cd4d20ffded09674e030f0f33883f337f34b95ad4935bac0e3",
+            )
+            .unwrap(),
+            expire_block: 23456,
+            last_contact_time: 1552509642,
+            allowed: 0,
+            denied: 0,
+            asn: 34567,
+            org: 45678,
+            in_degree: 1,
+            out_degree: 1,
+        };
+
+        let mut db = PeerDB::connect_memory(
+            0x9abcdef0,
+            12345,
+            0,
+            "http://foo.com".into(),
+            &vec![],
+            &vec![neighbor.clone()],
+        )
+        .unwrap();
+
+        let get_neighbor = |db: &PeerDB| {
+            PeerDB::get_peer(
+                db.conn(),
+                neighbor.addr.network_id,
+                &neighbor.addr.addrbytes,
+                neighbor.addr.port,
+            )
+            .unwrap()
+            .unwrap()
+        };
+
+        // two overlapping deny prefixes, and an allow prefix
+        {
+            let mut tx = db.tx_begin().unwrap();
+            PeerDB::add_deny_cidr(&mut tx, &PeerAddress::from_ipv4(1, 2, 0, 0), 112).unwrap();
+            PeerDB::add_deny_cidr(&mut tx, &PeerAddress::from_ipv4(1, 0, 0, 0), 104).unwrap();
+            PeerDB::add_allow_cidr(&mut tx, &PeerAddress::from_ipv4(1, 2, 3, 0), 120).unwrap();
+            tx.commit().unwrap();
+        }
+
+        let n = get_neighbor(&db);
+        assert!(n.is_denied());
+        assert!(n.is_always_allowed());
+
+        // still denied by the other prefix
+        {
+            let mut tx = db.tx_begin().unwrap();
+            PeerDB::remove_deny_cidr(&mut tx, &PeerAddress::from_ipv4(1, 2, 0, 0), 112).unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(
+            PeerDB::get_denied_cidrs(db.conn()).unwrap(),
+            vec![(PeerAddress::from_ipv4(1, 0, 0, 0), 104)]
+        );
+        assert!(get_neighbor(&db).is_denied());
+
+        {
+            let mut tx = db.tx_begin().unwrap();
+            PeerDB::remove_deny_cidr(&mut tx, &PeerAddress::from_ipv4(1, 0, 0, 0), 104).unwrap();
+            PeerDB::remove_allow_cidr(&mut tx, &PeerAddress::from_ipv4(1, 2, 3, 0), 120).unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(PeerDB::get_denied_cidrs(db.conn()).unwrap(), vec![]);
+        assert_eq!(PeerDB::get_allowed_cidrs(db.conn()).unwrap(), vec![]);
+
+        let n = get_neighbor(&db);
+        assert!(!n.is_denied());
+        assert!(!n.is_allowed());
+    }
+
+    #[test]
+    fn test_import_asn4() {
+        let neighbor = Neighbor {
+            addr: NeighborKey {
+                peer_version: 0x12345678,
+                network_id: 0x9abcdef0,
+                addrbytes: PeerAddress::from_ipv4(1, 2, 3, 4),
+                port: 12345,
+            },
+            public_key: Secp256k1PublicKey::from_hex(
+                "02fa66b66f8971a8cd4d20ffded09674e030f0f33883f337f34b95ad4935bac0e3",
+            )
+            .unwrap(),
+            expire_block: 23456,
+            last_contact_time: 1552509642,
+            allowed: 0,
+            denied: 0,
+            asn: 0,
+            org: 0,
+            in_degree: 1,
+            out_degree: 1,
+        };
+
+        let mut db = PeerDB::connect_memory(
+            0x9abcdef0,
+            12345,
+            0,
+            "http://foo.com".into(),
+            &vec![],
+            &vec![neighbor.clone()],
+        )
+        .unwrap();
+
+        let asn4_table = vec![ASEntry4 {
+            prefix: 0x01020000,
+            mask: 16,
+            asn: 3,
+            org: 0,
+        }];
+        {
+            let mut tx = db.tx_begin().unwrap();
+            PeerDB::import_asn4(&mut tx, &asn4_table).unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert_eq!(PeerDB::get_asn4_entries(db.conn()).unwrap(), asn4_table);
+        let n = PeerDB::get_peer(
+            db.conn(),
+            neighbor.addr.network_id,
+            &neighbor.addr.addrbytes,
+            neighbor.addr.port,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(n.asn, 3);
+    }
+
+    #[test]
+    fn test_export_import_frontier() {
+        let mut neighbors = vec![];
+        for i in 0..3 {
+            let mut public_key = Secp256k1PublicKey::from_private(&Secp256k1PrivateKey::new());
+            public_key.set_compressed(true);
+            neighbors.push(Neighbor {
+                addr: NeighborKey {
+                    peer_version: 0x12345678,
+                    network_id: 0x9abcdef0,
+                    addrbytes: PeerAddress::from_ipv4(1, 2, 3, i as u8),
+                    port: 12345,
+                },
+                public_key,
+                expire_block: 23456,
+                last_contact_time: 1552509642 + (i as u64),
+                allowed: -1,
+                denied: 0,
+                asn: 34567,
+                org: 45678,
+                in_degree: 1,
+                out_degree: 1,
+            });
+        }
+
+        let db = PeerDB::connect_memory(
+            0x9abcdef0,
+            12345,
+            0,
+            "http://foo.com".into(),
+            &vec![],
+            &neighbors,
+        )
+        .unwrap();
+
+        let exported = PeerDB::export_frontier(db.conn()).unwrap();
+        assert_eq!(exported.len(), 3);
+
+        // survives a round-trip through JSON
+        let exported_json = serde_json::to_string(&exported).unwrap();
+        let mut to_import: Vec<FrontierPeer> = serde_json::from_str(&exported_json).unwrap();
+        assert_eq!(to_import, exported);
+
+        // a peer from another network and a peer with a bad key don't get imported
+        let mut foreign = to_import[0].clone();
+        foreign.network_id = 0x12345678;
+        foreign.port = 23456;
+        to_import.push(foreign);
+
+        let mut bad_key = to_import[0].clone();
+        bad_key.public_key = "00".to_string();
+        bad_key.port = 34567;
+        to_import.push(bad_key);
+
+        let asn4_table = vec![ASEntry4 {
+            prefix: 0x01020000,
+            mask: 16,
+            asn: 3,
+            org: 0,
+        }];
+        let mut new_db = PeerDB::connect_memory(
+            0x9abcdef0,
+            12345,
+            0,
+            "http://foo.com".into(),
+            &asn4_table,
+            &vec![],
+        )
+        .unwrap();
+        {
+            let mut tx = new_db.tx_begin().unwrap();
+            PeerDB::add_deny_cidr(&mut tx, &PeerAddress::from_ipv4(1, 2, 3, 1), 128).unwrap();
+            assert_eq!(PeerDB::import_frontier(&mut tx, &to_import).unwrap(), 3);
+
+            // importing again is a no-op
+            assert_eq!(PeerDB::import_frontier(&mut tx, &to_import).unwrap(), 0);
+            tx.commit().unwrap();
+        }
+
+        let imported = PeerDB::get_all_peers(new_db.conn()).unwrap();
+        assert_eq!(imported.len(), 3);
+        for (neighbor, new_neighbor) in neighbors.iter().zip(imported.iter()) {
+            assert_eq!(neighbor.addr, new_neighbor.addr);
+            assert_eq!(neighbor.public_key, new_neighbor.public_key);
+            assert_eq!(neighbor.last_contact_time, new_neighbor.last_contact_time);
+
+            // allow/deny state is the importer's, and the ASN comes from the importer's table
+            assert!(!new_neighbor.is_allowed());
+            assert_eq!(
+                new_neighbor.is_denied(),
+                new_neighbor.addr.addrbytes == PeerAddress::from_ipv4(1, 2, 3, 1)
+            );
+            assert_eq!(new_neighbor.asn, 3);
+            assert_eq!(new_neighbor.in_degree, 0);
+        }
+    }
 }
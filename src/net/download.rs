@@ -158,19 +158,14 @@ impl Requestable for BlockRequestKey {
     }
 
     fn make_request_type(&self, peer_host: PeerHost) -> HttpRequestType {
+        // blocks and microblock streams compress well, so ask for them gzipped
+        let mut md =
+            HttpRequestMetadata::from_host(peer_host, Some(self.canonical_stacks_tip_height));
+        md.accept_gzip = true;
         match self.kind {
-            BlockRequestKeyKind::Block => HttpRequestType::GetBlock(
-                HttpRequestMetadata::from_host(peer_host, Some(self.canonical_stacks_tip_height)),
-                self.index_block_hash,
-            ),
+            BlockRequestKeyKind::Block => HttpRequestType::GetBlock(md, self.index_block_hash),
             BlockRequestKeyKind::ConfirmedMicroblockStream => {
-                HttpRequestType::GetMicroblocksConfirmed(
-                    HttpRequestMetadata::from_host(
-                        peer_host,
-                        Some(self.canonical_stacks_tip_height),
-                    ),
-                    self.index_block_hash,
-                )
+                HttpRequestType::GetMicroblocksConfirmed(md, self.index_block_hash)
             }
         }
     }
@@ -1446,7 +1441,9 @@ impl PeerNetwork {
                 neighbors.len()
             );
 
+            // ask peers with better reputations first
             (&mut neighbors[..]).shuffle(&mut thread_rng());
+            neighbors.sort_by_key(|nk| -self.get_neighbor_reputation(nk));
 
             let mut requests = VecDeque::new();
             for nk in neighbors.drain(..) {
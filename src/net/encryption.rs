@@ -0,0 +1,370 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opt-in payload encryption for the P2P transport.
+//!
+//! If both peers advertise `ServiceFlags::ENCRYPTION` in their handshakes, the handshake initiator
+//! sends a `SessionKey` message with a freshly-generated secp256k1 public key, and the responder
+//! answers with one of its own.  Each side then derives a pair of AES-256-GCM keys (one per
+//! direction) from an ECDH exchange between the two ephemeral keys, and forgets its ephemeral
+//! private key.  `SessionKey` messages are signed with the nodes' long-lived keys like any other
+//! message, so they cannot be forged; but since those keys never feed into the session keys,
+//! leaking them later does not expose recorded traffic.
+//!
+//! An encrypted message keeps its preamble in the clear -- it is needed for framing -- but sets
+//! `PREAMBLE_ENCRYPTED_PAYLOAD` in `additional_data`, and replaces its payload with a random nonce
+//! followed by the sealed relayers and payload.  The serialized preamble is the AEAD's associated
+//! data, so it cannot be altered in transit.  The message signature is computed over the
+//! plaintext message exactly as before, and checked after decryption.
+//!
+//! Handshakes and `SessionKey` messages are never encrypted.  The initiator starts encrypting as
+//! soon as it gets the responder's `SessionKey`; the responder starts encrypting once it receives
+//! its first encrypted message, so it never sends ciphertext that the initiator cannot yet read.
+//! Once a peer has sent us an encrypted message, we reject any encryptable message it sends in
+//! the clear.
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use rand::RngCore;
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::PublicKey as LibSecp256k1PublicKey;
+use secp256k1::SecretKey as LibSecp256k1PrivateKey;
+use sha2::{Digest, Sha256};
+
+use crate::codec::StacksMessageCodec;
+use crate::net::Error as net_error;
+use crate::net::{Preamble, StacksMessage, StacksMessageType};
+use crate::types::PrivateKey;
+use stacks_common::util::secp256k1::{Secp256k1PrivateKey, Secp256k1PublicKey};
+
+/// Bit in `Preamble::additional_data` indicating that the payload is encrypted
+pub const PREAMBLE_ENCRYPTED_PAYLOAD: u32 = 0x00000001;
+
+/// Length of the random nonce prepended to each encrypted payload
+pub const P2P_NONCE_LEN: usize = 12;
+
+/// Length of the AES-GCM authentication tag appended to each encrypted payload
+pub const P2P_TAG_LEN: usize = 16;
+
+/// Domain separator for session key derivation
+const P2P_SESSION_KEY_TAG: &[u8] = b"stacks-p2p-session-key-v1";
+
+/// Symmetric keys for one P2P conversation
+#[derive(Clone, PartialEq)]
+pub struct P2PSession {
+    send_key: [u8; 32],
+    recv_key: [u8; 32],
+    /// Whether or not we encrypt the messages we send
+    send_encrypted: bool,
+    /// Whether or not the peer has sent us an encrypted message
+    recv_encrypted: bool,
+}
+
+impl std::fmt::Debug for P2PSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never log the keys
+        write!(
+            f,
+            "P2PSession(send_encrypted={},recv_encrypted={})",
+            self.send_encrypted, self.recv_encrypted
+        )
+    }
+}
+
+/// Derive the key for messages sent from `from_pubk` to `to_pubk`
+fn derive_key(
+    shared_secret: &SharedSecret,
+    from_pubk: &Secp256k1PublicKey,
+    to_pubk: &Secp256k1PublicKey,
+) -> [u8; 32] {
+    let mut sha2 = Sha256::new();
+    sha2.update(P2P_SESSION_KEY_TAG);
+    sha2.update(shared_secret.as_ref());
+    sha2.update(&from_pubk.to_bytes_compressed());
+    sha2.update(&to_pubk.to_bytes_compressed());
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(sha2.finalize().as_slice());
+    key
+}
+
+/// Can this message type be encrypted?  Handshakes and session key exchanges must stay in the
+/// clear, since the peer may not have derived the session keys yet when it receives them.
+pub fn is_encryptable(payload: &StacksMessageType) -> bool {
+    match payload {
+        StacksMessageType::Handshake(_)
+        | StacksMessageType::HandshakeAccept(_)
+        | StacksMessageType::HandshakeReject
+        | StacksMessageType::SessionKey(_) => false,
+        _ => true,
+    }
+}
+
+impl P2PSession {
+    /// Derive the session keys shared between our ephemeral private key and the peer's ephemeral
+    /// public key.  `send_encrypted` should be true only if the peer is known to have derived them
+    /// as well.
+    pub fn new(
+        local_privkey: &Secp256k1PrivateKey,
+        peer_pubkey: &Secp256k1PublicKey,
+        send_encrypted: bool,
+    ) -> Result<P2PSession, net_error> {
+        let privkey_bytes = local_privkey.to_bytes();
+        let secret_key = LibSecp256k1PrivateKey::from_slice(&privkey_bytes[0..32])
+            .map_err(|e| net_error::SigningError(format!("Invalid private key: {:?}", &e)))?;
+        let public_key = LibSecp256k1PublicKey::from_slice(&peer_pubkey.to_bytes_compressed())
+            .map_err(|e| net_error::VerifyingError(format!("Invalid public key: {:?}", &e)))?;
+        let shared_secret = SharedSecret::new(&public_key, &secret_key);
+
+        let local_pubkey = Secp256k1PublicKey::from_private(local_privkey);
+        Ok(P2PSession {
+            send_key: derive_key(&shared_secret, &local_pubkey, peer_pubkey),
+            recv_key: derive_key(&shared_secret, peer_pubkey, &local_pubkey),
+            send_encrypted,
+            recv_encrypted: false,
+        })
+    }
+
+    pub fn send_encrypted(&self) -> bool {
+        self.send_encrypted
+    }
+
+    pub fn set_send_encrypted(&mut self, send_encrypted: bool) {
+        self.send_encrypted = send_encrypted;
+    }
+
+    pub fn recv_encrypted(&self) -> bool {
+        self.recv_encrypted
+    }
+
+    /// The peer sent us an encrypted message, so it has our session keys.  From now on, we encrypt
+    /// what we send, and expect the same from the peer.
+    pub fn set_recv_encrypted(&mut self) {
+        self.send_encrypted = true;
+        self.recv_encrypted = true;
+    }
+
+    /// Serialize and encrypt a signed message.  The preamble is sent in the clear, with its
+    /// payload length updated to that of the ciphertext.
+    pub fn seal(&self, message: &StacksMessage) -> Result<Vec<u8>, net_error> {
+        let mut body = vec![];
+        message.relayers.consensus_serialize(&mut body)?;
+        message.payload.consensus_serialize(&mut body)?;
+
+        let mut preamble = message.preamble.clone();
+        preamble.additional_data |= PREAMBLE_ENCRYPTED_PAYLOAD;
+        preamble.payload_len = (P2P_NONCE_LEN + body.len() + P2P_TAG_LEN) as u32;
+
+        let mut preamble_bytes = vec![];
+        preamble.consensus_serialize(&mut preamble_bytes)?;
+
+        let mut nonce = [0u8; P2P_NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(&self.send_key));
+        let ciphertext = cipher
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: &body,
+                    aad: &preamble_bytes,
+                },
+            )
+            .map_err(|_| net_error::SerializeError("Failed to encrypt payload".to_string()))?;
+
+        let mut sealed = preamble_bytes;
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt an encrypted payload.  Returns the preamble the sender signed (i.e. with the
+    /// plaintext's length and without the encryption bit), and the plaintext relayers and payload.
+    pub fn open(
+        &self,
+        preamble: &Preamble,
+        payload_bytes: &[u8],
+    ) -> Result<(Preamble, Vec<u8>), net_error> {
+        if preamble.additional_data & PREAMBLE_ENCRYPTED_PAYLOAD == 0 {
+            return Err(net_error::DeserializeError(
+                "Payload is not encrypted".to_string(),
+            ));
+        }
+        if payload_bytes.len() < P2P_NONCE_LEN + P2P_TAG_LEN
+            || payload_bytes.len() != preamble.payload_len as usize
+        {
+            return Err(net_error::DeserializeError(
+                "Encrypted payload has an invalid length".to_string(),
+            ));
+        }
+
+        let mut preamble_bytes = vec![];
+        preamble.consensus_serialize(&mut preamble_bytes)?;
+
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(&self.recv_key));
+        let body = cipher
+            .decrypt(
+                GenericArray::from_slice(&payload_bytes[0..P2P_NONCE_LEN]),
+                Payload {
+                    msg: &payload_bytes[P2P_NONCE_LEN..],
+                    aad: &preamble_bytes,
+                },
+            )
+            .map_err(|_| net_error::DeserializeError("Failed to decrypt payload".to_string()))?;
+
+        let mut signed_preamble = preamble.clone();
+        signed_preamble.additional_data &= !PREAMBLE_ENCRYPTED_PAYLOAD;
+        signed_preamble.payload_len = body.len() as u32;
+        Ok((signed_preamble, body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::burnchains::BurnchainView;
+    use crate::net::{PingData, ProtocolFamily, StacksP2P};
+    use crate::types::chainstate::BurnchainHeaderHash;
+    use stacks_common::codec::read_next;
+
+    fn make_ping(privk: &Secp256k1PrivateKey) -> StacksMessage {
+        let chain_view = BurnchainView {
+            burn_block_height: 12348,
+            burn_block_hash: BurnchainHeaderHash([0x11; 32]),
+            burn_stable_block_height: 12341,
+            burn_stable_block_hash: BurnchainHeaderHash([0x22; 32]),
+            last_burn_block_hashes: Default::default(),
+        };
+        let mut ping = StacksMessage::from_chain_view(
+            0x18000000,
+            0x80000000,
+            &chain_view,
+            StacksMessageType::Ping(PingData::new()),
+        );
+        ping.sign(123, privk).unwrap();
+        ping
+    }
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let privk_1 = Secp256k1PrivateKey::new();
+        let privk_2 = Secp256k1PrivateKey::new();
+        let pubk_1 = Secp256k1PublicKey::from_private(&privk_1);
+        let pubk_2 = Secp256k1PublicKey::from_private(&privk_2);
+
+        let session_1 = P2PSession::new(&privk_1, &pubk_2, true).unwrap();
+        let session_2 = P2PSession::new(&privk_2, &pubk_1, false).unwrap();
+        assert!(session_1.send_key != session_1.recv_key);
+        assert_eq!(session_1.send_key, session_2.recv_key);
+        assert_eq!(session_1.recv_key, session_2.send_key);
+
+        let ping = make_ping(&privk_1);
+        let sealed = session_1.seal(&ping).unwrap();
+
+        let preamble: Preamble = read_next(&mut &sealed[..]).unwrap();
+        let payload_bytes = &sealed[(sealed.len() - preamble.payload_len as usize)..];
+
+        // only the peer can open it
+        assert!(session_1.open(&preamble, payload_bytes).is_err());
+        let (mut signed_preamble, body) = session_2.open(&preamble, payload_bytes).unwrap();
+        assert_eq!(signed_preamble, ping.preamble);
+        signed_preamble.verify(&body, &pubk_1).unwrap();
+
+        // the preamble is authenticated
+        let mut bad_preamble = preamble.clone();
+        bad_preamble.seq += 1;
+        assert!(session_2.open(&bad_preamble, payload_bytes).is_err());
+
+        // so is the payload
+        let mut bad_payload = payload_bytes.to_vec();
+        let last = bad_payload.len() - 1;
+        bad_payload[last] ^= 0x01;
+        assert!(session_2.open(&preamble, &bad_payload).is_err());
+    }
+
+    #[test]
+    fn test_p2p_protocol_encryption() {
+        let privk_1 = Secp256k1PrivateKey::new();
+        let privk_2 = Secp256k1PrivateKey::new();
+        let pubk_1 = Secp256k1PublicKey::from_private(&privk_1);
+        let pubk_2 = Secp256k1PublicKey::from_private(&privk_2);
+
+        let mut protocol_1 = StacksP2P::new();
+        let mut protocol_2 = StacksP2P::new();
+        protocol_1.set_session(P2PSession::new(&privk_1, &pubk_2, true).unwrap());
+        protocol_2.set_session(P2PSession::new(&privk_2, &pubk_1, false).unwrap());
+
+        let ping = make_ping(&privk_1);
+        let mut bytes = vec![];
+        protocol_1.write_message(&mut bytes, &ping).unwrap();
+
+        let (preamble, preamble_len) = protocol_2.read_preamble(&bytes).unwrap();
+        assert!(preamble.additional_data & PREAMBLE_ENCRYPTED_PAYLOAD != 0);
+
+        assert!(protocol_2
+            .verify_payload_bytes(&pubk_2, &preamble, &bytes[preamble_len..])
+            .is_err());
+        assert!(protocol_2.opened.is_none());
+        protocol_2
+            .verify_payload_bytes(&pubk_1, &preamble, &bytes[preamble_len..])
+            .unwrap();
+
+        // the verified plaintext is reused, rather than decrypted again
+        assert!(protocol_2.opened.is_some());
+
+        // receiving an encrypted message lets protocol_2 encrypt too
+        assert!(!protocol_2.get_session().unwrap().send_encrypted());
+        let (message, consumed) = protocol_2
+            .read_payload(&preamble, &bytes[preamble_len..])
+            .unwrap();
+        assert_eq!(consumed, preamble.payload_len as usize);
+        assert_eq!(message, ping);
+        assert!(protocol_2.opened.is_none());
+        assert!(protocol_2.get_session().unwrap().send_encrypted());
+        assert!(protocol_2.get_session().unwrap().recv_encrypted());
+
+        // handshakes are never encrypted, and are still accepted in the clear
+        let mut reject = ping.clone();
+        reject.payload = StacksMessageType::HandshakeReject;
+        reject.sign(124, &privk_1).unwrap();
+        let mut bytes = vec![];
+        protocol_1.write_message(&mut bytes, &reject).unwrap();
+        let (preamble, preamble_len) = protocol_2.read_preamble(&bytes).unwrap();
+        assert_eq!(preamble.additional_data, 0);
+        protocol_2
+            .read_payload(&preamble, &bytes[preamble_len..])
+            .unwrap();
+
+        // but anything else in the clear is rejected, since the peer already encrypts
+        let mut bytes = vec![];
+        ping.consensus_serialize(&mut bytes).unwrap();
+        let (preamble, preamble_len) = protocol_2.read_preamble(&bytes).unwrap();
+        assert_eq!(preamble.additional_data, 0);
+        assert!(protocol_2
+            .read_payload(&preamble, &bytes[preamble_len..])
+            .is_err());
+
+        // can't read encrypted payloads without a session
+        let mut bytes = vec![];
+        protocol_1.write_message(&mut bytes, &ping).unwrap();
+        let mut protocol_3 = StacksP2P::new();
+        let (preamble, preamble_len) = protocol_3.read_preamble(&bytes).unwrap();
+        assert!(protocol_3
+            .read_payload(&preamble, &bytes[preamble_len..])
+            .is_err());
+    }
+}
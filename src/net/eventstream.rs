@@ -0,0 +1,465 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Server-sent event streams of chain events, served by the RPC server.
+//!
+//! The node's event dispatcher publishes the same JSON payloads it POSTs to event observers into
+//! an `EventStreamHub`.  The hub keeps the most recent events in a bounded buffer, and each
+//! `/v2/events/...` subscription holds an `EventStreamCursor` into it.  The HTTP conversation
+//! pulls events out of its cursor whenever it has finished sending the previous ones, so a
+//! subscriber that cannot keep up falls off the end of the buffer and is disconnected instead
+//! of buffering without bound in the node.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use clarity::vm::types::QualifiedContractIdentifier;
+
+use crate::net::Error as net_error;
+
+/// How many published events the hub holds on to for subscribers that are still sending
+pub const EVENT_STREAM_BUFFER_LEN: usize = 1024;
+
+/// The kinds of events that get published, named after the event observer paths they mirror
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventStreamKind {
+    NewBlock,
+    NewMicroblocks,
+    NewMempoolTxs,
+    DropMempoolTxs,
+}
+
+impl EventStreamKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            EventStreamKind::NewBlock => "new_block",
+            EventStreamKind::NewMicroblocks => "new_microblocks",
+            EventStreamKind::NewMempoolTxs => "new_mempool_tx",
+            EventStreamKind::DropMempoolTxs => "drop_mempool_tx",
+        }
+    }
+}
+
+/// What a subscriber asked to be sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventStreamTopic {
+    /// `new_block` payloads
+    Blocks,
+    /// `new_microblocks` payloads
+    Microblocks,
+    /// `new_mempool_tx` and `drop_mempool_tx` payloads
+    Mempool,
+    /// `new_block` and `new_microblocks` payloads whose `events` are trimmed down to this
+    /// contract's `contract_event`s.  Payloads with no such events are not sent.
+    ContractEvents(QualifiedContractIdentifier),
+}
+
+impl EventStreamTopic {
+    /// The payload to send to a subscriber of this topic for a published event, if any
+    pub fn select(
+        &self,
+        kind: EventStreamKind,
+        payload: &serde_json::Value,
+    ) -> Option<serde_json::Value> {
+        match (self, kind) {
+            (EventStreamTopic::Blocks, EventStreamKind::NewBlock)
+            | (EventStreamTopic::Microblocks, EventStreamKind::NewMicroblocks)
+            | (EventStreamTopic::Mempool, EventStreamKind::NewMempoolTxs)
+            | (EventStreamTopic::Mempool, EventStreamKind::DropMempoolTxs) => Some(payload.clone()),
+            (EventStreamTopic::ContractEvents(contract_id), EventStreamKind::NewBlock)
+            | (EventStreamTopic::ContractEvents(contract_id), EventStreamKind::NewMicroblocks) => {
+                let contract_id = contract_id.to_string();
+                let events: Vec<serde_json::Value> = payload
+                    .get("events")
+                    .and_then(|events| events.as_array())
+                    .map(|events| {
+                        events
+                            .iter()
+                            .filter(|event| {
+                                event
+                                    .get("contract_event")
+                                    .and_then(|data| data.get("contract_identifier"))
+                                    .and_then(|id| id.as_str())
+                                    == Some(contract_id.as_str())
+                            })
+                            .cloned()
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                if events.is_empty() {
+                    return None;
+                }
+
+                let mut payload = payload.clone();
+                payload["events"] = serde_json::Value::Array(events);
+                Some(payload)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EventStreamBuffer {
+    /// sequence number that the next published event will get
+    next_seq: u64,
+    /// the most recently-published events, oldest first
+    events: VecDeque<(u64, EventStreamKind, Arc<serde_json::Value>)>,
+    /// number of live cursors
+    num_subscribers: usize,
+}
+
+/// Shared between the event dispatcher, which publishes into it, and the RPC server, which hands
+/// out cursors into it.
+#[derive(Debug, Clone)]
+pub struct EventStreamHub {
+    max_subscribers: usize,
+    buffer_len: usize,
+    inner: Arc<Mutex<EventStreamBuffer>>,
+}
+
+impl EventStreamHub {
+    pub fn new(max_subscribers: usize, buffer_len: usize) -> EventStreamHub {
+        EventStreamHub {
+            max_subscribers,
+            buffer_len,
+            inner: Arc::new(Mutex::new(EventStreamBuffer {
+                next_seq: 0,
+                events: VecDeque::new(),
+                num_subscribers: 0,
+            })),
+        }
+    }
+
+    pub fn num_subscribers(&self) -> usize {
+        self.inner
+            .lock()
+            .expect("FATAL: event stream lock poisoned")
+            .num_subscribers
+    }
+
+    /// Does anyone want published events?  Publishers can skip building payloads if not.
+    pub fn has_subscribers(&self) -> bool {
+        self.num_subscribers() > 0
+    }
+
+    /// Publish an event to all current subscribers.  Dropped if there are none.
+    pub fn publish(&self, kind: EventStreamKind, payload: serde_json::Value) {
+        let mut inner = self
+            .inner
+            .lock()
+            .expect("FATAL: event stream lock poisoned");
+        if inner.num_subscribers == 0 {
+            return;
+        }
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        inner.events.push_back((seq, kind, Arc::new(payload)));
+        while inner.events.len() > self.buffer_len {
+            inner.events.pop_front();
+        }
+    }
+
+    /// Subscribe to events published from now on.  `heartbeat` is how many seconds the cursor
+    /// can go without writing before it writes a keep-alive comment.
+    /// Returns None if the hub already has as many subscribers as it allows.
+    pub fn subscribe(
+        &self,
+        topic: EventStreamTopic,
+        heartbeat: u64,
+        now: u64,
+    ) -> Option<EventStreamCursor> {
+        let mut inner = self
+            .inner
+            .lock()
+            .expect("FATAL: event stream lock poisoned");
+        if inner.num_subscribers >= self.max_subscribers {
+            return None;
+        }
+        inner.num_subscribers += 1;
+        Some(EventStreamCursor {
+            hub: self.clone(),
+            topic,
+            next_seq: inner.next_seq,
+            heartbeat,
+            last_write: now,
+        })
+    }
+
+    /// Get all buffered events from `seq` onwards.
+    /// Returns Err(..) if some of them have already been dropped from the buffer.
+    fn events_since(
+        &self,
+        seq: u64,
+    ) -> Result<Vec<(u64, EventStreamKind, Arc<serde_json::Value>)>, ()> {
+        let inner = self
+            .inner
+            .lock()
+            .expect("FATAL: event stream lock poisoned");
+        let oldest_seq = inner
+            .events
+            .front()
+            .map(|(seq, ..)| *seq)
+            .unwrap_or(inner.next_seq);
+        if seq < oldest_seq {
+            return Err(());
+        }
+        Ok(inner
+            .events
+            .iter()
+            .filter(|(event_seq, ..)| *event_seq >= seq)
+            .cloned()
+            .collect())
+    }
+}
+
+/// One subscriber's position in the hub
+#[derive(Debug)]
+pub struct EventStreamCursor {
+    hub: EventStreamHub,
+    topic: EventStreamTopic,
+    /// sequence number of the next event to consider sending
+    next_seq: u64,
+    heartbeat: u64,
+    /// last time we wrote anything
+    last_write: u64,
+}
+
+impl EventStreamCursor {
+    pub fn topic(&self) -> &EventStreamTopic {
+        &self.topic
+    }
+
+    /// Write out every event on this cursor's topic that was published since the last call, as
+    /// server-sent events.  If there are none and nothing has been written for a heartbeat
+    /// interval, write a keep-alive comment instead.
+    /// Returns the number of bytes written.  Fails with ConnectionBroken if the subscriber fell
+    /// so far behind that events it hasn't been sent were dropped from the hub.
+    pub fn stream_to<W: Write>(&mut self, fd: &mut W, now: u64) -> Result<u64, net_error> {
+        let events = self.hub.events_since(self.next_seq).map_err(|_| {
+            warn!(
+                "Event stream subscriber to {:?} fell behind; disconnecting",
+                &self.topic
+            );
+            net_error::ConnectionBroken
+        })?;
+
+        let mut written = 0;
+        for (seq, kind, payload) in events.iter() {
+            self.next_seq = *seq + 1;
+            if let Some(payload) = self.topic.select(*kind, payload) {
+                let data = serde_json::to_string(&payload)
+                    .map_err(|e| net_error::SerializeError(format!("{:?}", &e)))?;
+                let message = format!("id: {}\nevent: {}\ndata: {}\n\n", seq, kind.as_str(), data);
+                fd.write_all(message.as_bytes())
+                    .map_err(net_error::WriteError)?;
+                written += message.len() as u64;
+            }
+        }
+
+        if written == 0 && self.last_write + self.heartbeat <= now {
+            let message = ": keep-alive\n\n";
+            fd.write_all(message.as_bytes())
+                .map_err(net_error::WriteError)?;
+            written += message.len() as u64;
+        }
+
+        if written > 0 {
+            self.last_write = now;
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for EventStreamCursor {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.hub.inner.lock() {
+            inner.num_subscribers = inner.num_subscribers.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block_payload(events: Vec<serde_json::Value>) -> serde_json::Value {
+        json!({
+            "block_hash": "0x1111111111111111111111111111111111111111111111111111111111111111",
+            "block_height": 12,
+            "events": events,
+        })
+    }
+
+    fn contract_event(contract_id: &str, event_index: usize) -> serde_json::Value {
+        json!({
+            "txid": "0x2222222222222222222222222222222222222222222222222222222222222222",
+            "event_index": event_index,
+            "committed": true,
+            "type": "contract_event",
+            "contract_event": {
+                "contract_identifier": contract_id,
+                "topic": "print",
+                "value": null,
+                "raw_value": "0x09",
+            }
+        })
+    }
+
+    fn stx_event(event_index: usize) -> serde_json::Value {
+        json!({
+            "txid": "0x2222222222222222222222222222222222222222222222222222222222222222",
+            "event_index": event_index,
+            "committed": true,
+            "type": "stx_transfer_event",
+            "stx_transfer_event": {},
+        })
+    }
+
+    #[test]
+    fn test_event_stream_topic_select() {
+        let contract_id = QualifiedContractIdentifier::parse(
+            "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.hello-world",
+        )
+        .unwrap();
+        let other_id = "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.other";
+
+        let payload = block_payload(vec![
+            contract_event(&contract_id.to_string(), 0),
+            stx_event(1),
+            contract_event(other_id, 2),
+        ]);
+
+        assert_eq!(
+            EventStreamTopic::Blocks.select(EventStreamKind::NewBlock, &payload),
+            Some(payload.clone())
+        );
+        assert_eq!(
+            EventStreamTopic::Blocks.select(EventStreamKind::NewMicroblocks, &payload),
+            None
+        );
+        assert_eq!(
+            EventStreamTopic::Microblocks.select(EventStreamKind::NewMicroblocks, &payload),
+            Some(payload.clone())
+        );
+        assert_eq!(
+            EventStreamTopic::Mempool.select(EventStreamKind::DropMempoolTxs, &json!([])),
+            Some(json!([]))
+        );
+        assert_eq!(
+            EventStreamTopic::Mempool.select(EventStreamKind::NewBlock, &payload),
+            None
+        );
+
+        // only this contract's events survive
+        let topic = EventStreamTopic::ContractEvents(contract_id.clone());
+        let selected = topic
+            .select(EventStreamKind::NewMicroblocks, &payload)
+            .unwrap();
+        assert_eq!(
+            selected["events"],
+            json!([contract_event(&contract_id.to_string(), 0)])
+        );
+        assert_eq!(selected["block_hash"], payload["block_hash"]);
+
+        // nothing to send if none of the events are from this contract
+        let payload = block_payload(vec![stx_event(0), contract_event(other_id, 1)]);
+        assert_eq!(topic.select(EventStreamKind::NewBlock, &payload), None);
+        assert_eq!(
+            topic.select(EventStreamKind::NewMempoolTxs, &json!([])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_event_stream_hub_subscribe_and_stream() {
+        let hub = EventStreamHub::new(2, EVENT_STREAM_BUFFER_LEN);
+
+        // nobody's listening, so this is dropped
+        hub.publish(EventStreamKind::NewMempoolTxs, json!(["0x00"]));
+        assert!(!hub.has_subscribers());
+
+        let mut blocks = hub.subscribe(EventStreamTopic::Blocks, 10, 100).unwrap();
+        let mut mempool = hub.subscribe(EventStreamTopic::Mempool, 10, 100).unwrap();
+        assert_eq!(hub.num_subscribers(), 2);
+
+        // at capacity
+        assert!(hub.subscribe(EventStreamTopic::Blocks, 10, 100).is_none());
+
+        hub.publish(EventStreamKind::NewMempoolTxs, json!(["0x01"]));
+        hub.publish(EventStreamKind::NewBlock, json!({"block_height": 1}));
+        hub.publish(
+            EventStreamKind::DropMempoolTxs,
+            json!({"dropped_txids": []}),
+        );
+
+        let mut buf = vec![];
+        let nw = blocks.stream_to(&mut buf, 101).unwrap();
+        assert_eq!(nw, buf.len() as u64);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "id: 1\nevent: new_block\ndata: {\"block_height\":1}\n\n"
+        );
+
+        let mut buf = vec![];
+        mempool.stream_to(&mut buf, 101).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "id: 0\nevent: new_mempool_tx\ndata: [\"0x01\"]\n\nid: 2\nevent: drop_mempool_tx\ndata: {\"dropped_txids\":[]}\n\n"
+        );
+
+        // nothing new, and not due for a heartbeat
+        let mut buf = vec![];
+        assert_eq!(blocks.stream_to(&mut buf, 105).unwrap(), 0);
+        assert!(buf.is_empty());
+
+        // due for a heartbeat
+        let mut buf = vec![];
+        assert!(blocks.stream_to(&mut buf, 111).unwrap() > 0);
+        assert_eq!(String::from_utf8(buf).unwrap(), ": keep-alive\n\n");
+
+        // dropping a cursor frees up its slot
+        drop(mempool);
+        assert_eq!(hub.num_subscribers(), 1);
+        assert!(hub.subscribe(EventStreamTopic::Mempool, 10, 100).is_some());
+        assert_eq!(hub.num_subscribers(), 1);
+    }
+
+    #[test]
+    fn test_event_stream_hub_lagging_subscriber() {
+        let hub = EventStreamHub::new(1, 2);
+        let mut cursor = hub.subscribe(EventStreamTopic::Blocks, 10, 100).unwrap();
+
+        hub.publish(EventStreamKind::NewBlock, json!({"block_height": 1}));
+        hub.publish(EventStreamKind::NewBlock, json!({"block_height": 2}));
+
+        let mut buf = vec![];
+        cursor.stream_to(&mut buf, 100).unwrap();
+
+        hub.publish(EventStreamKind::NewBlock, json!({"block_height": 3}));
+        hub.publish(EventStreamKind::NewBlock, json!({"block_height": 4}));
+        hub.publish(EventStreamKind::NewBlock, json!({"block_height": 5}));
+
+        // block 3 fell out of the buffer before the cursor got to it
+        let mut buf = vec![];
+        match cursor.stream_to(&mut buf, 100) {
+            Err(net_error::ConnectionBroken) => {}
+            res => panic!("Expected ConnectionBroken, got {:?}", res),
+        }
+    }
+}
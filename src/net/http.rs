@@ -29,6 +29,7 @@ use std::str;
 use std::str::FromStr;
 use std::time::SystemTime;
 
+use libflate::gzip;
 use percent_encoding::percent_decode_str;
 use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
@@ -43,6 +44,7 @@ use crate::chainstate::stacks::{
 };
 use crate::deps::httparse;
 use crate::net::atlas::Attachment;
+use crate::net::eventstream::EventStreamTopic;
 use crate::net::ClientError;
 use crate::net::Error as net_error;
 use crate::net::Error::ClarityError;
@@ -68,11 +70,14 @@ use crate::net::UnconfirmedTransactionStatus;
 use crate::net::HTTP_PREAMBLE_MAX_ENCODED_SIZE;
 use crate::net::HTTP_PREAMBLE_MAX_NUM_HEADERS;
 use crate::net::HTTP_REQUEST_ID_RESERVED;
+use crate::net::MAX_BATCH_REQUESTS;
 use crate::net::MAX_HEADERS;
+use crate::net::MAX_MARF_PROOF_KEYS;
 use crate::net::MAX_MICROBLOCKS_UNCONFIRMED;
-use crate::net::{CallReadOnlyRequestBody, TipRequest};
+use crate::net::{BatchRequestBody, BatchRequestItem, BatchResponseItem};
+use crate::net::{CallReadOnlyRequestBody, MarfProofsRequestBody, TipRequest};
 use crate::net::{GetAttachmentResponse, GetAttachmentsInvResponse, PostTransactionRequestBody};
-use clarity::vm::types::{StandardPrincipalData, TraitIdentifier};
+use clarity::vm::types::{QualifiedContractIdentifier, StandardPrincipalData, TraitIdentifier};
 use clarity::vm::{
     ast::parser::{
         CLARITY_NAME_REGEX, CONTRACT_NAME_REGEX, PRINCIPAL_DATA_REGEX, STANDARD_PRINCIPAL_REGEX,
@@ -156,6 +161,15 @@ lazy_static! {
         Regex::new(r#"^/v2/attachments/([0-9a-f]{40})$"#).unwrap();
     static ref PATH_POST_MEMPOOL_QUERY: Regex =
         Regex::new(r#"^/v2/mempool/query$"#).unwrap();
+    static ref PATH_POST_MARF_PROOFS: Regex = Regex::new(r#"^/v2/marf/proofs$"#).unwrap();
+    static ref PATH_POST_BATCH: Regex = Regex::new(r#"^/v2/batch$"#).unwrap();
+    static ref PATH_GET_EVENT_STREAM: Regex =
+        Regex::new(r#"^/v2/events/(blocks|microblocks|mempool)$"#).unwrap();
+    static ref PATH_GET_CONTRACT_EVENT_STREAM: Regex = Regex::new(&format!(
+        "^/v2/events/contract/(?P<address>{})/(?P<contract>{})$",
+        *STANDARD_PRINCIPAL_REGEX, *CONTRACT_NAME_REGEX
+    ))
+    .unwrap();
     static ref PATH_OPTIONS_WILDCARD: Regex = Regex::new("^/v2/.{0,4096}$").unwrap();
 }
 
@@ -167,6 +181,9 @@ pub(crate) enum HttpReservedHeader {
     XRequestID(u32),
     Host(PeerHost),
     CanonicalStacksTipHeight(u64),
+    ApiKey(String),
+    /// whether the client accepts gzip-compressed bodies
+    AcceptEncoding(bool),
 }
 
 /// Stacks block accepted struct
@@ -259,7 +276,9 @@ impl HttpReservedHeader {
             | "content-type"
             | "x-request-id"
             | "host"
-            | "x-canonical-stacks-tip-height" => true,
+            | "x-canonical-stacks-tip-height"
+            | "x-api-key"
+            | "accept-encoding" => true,
             _ => false,
         }
     }
@@ -287,6 +306,28 @@ impl HttpReservedHeader {
                 Ok(h) => Some(HttpReservedHeader::CanonicalStacksTipHeight(h)),
                 Err(_) => None,
             },
+            "x-api-key" => {
+                if !value.is_empty() {
+                    Some(HttpReservedHeader::ApiKey(value.to_string()))
+                } else {
+                    None
+                }
+            }
+            "accept-encoding" => Some(HttpReservedHeader::AcceptEncoding(value.split(',').any(
+                |coding| {
+                    // e.g. "gzip", "gzip;q=0.8", but not "gzip;q=0"
+                    let mut params = coding.split(';');
+                    let name = params.next().unwrap_or("").trim();
+                    let refused = params.any(|param| {
+                        param
+                            .trim()
+                            .strip_prefix("q=")
+                            .and_then(|q| q.parse::<f64>().ok())
+                            == Some(0.0)
+                    });
+                    name.eq_ignore_ascii_case("gzip") && !refused
+                },
+            ))),
             _ => None,
         }
     }
@@ -577,6 +618,8 @@ pub struct HttpChunkedTransferWriterState {
     chunk_size: usize,
     chunk_buf: Vec<u8>,
     corked: bool,
+    /// if set, the body is gzip-compressed before it gets chunked
+    gzip: Option<gzip::Encoder<Vec<u8>>>,
 }
 
 impl HttpChunkedTransferWriterState {
@@ -585,6 +628,28 @@ impl HttpChunkedTransferWriterState {
             chunk_size: chunk_size,
             chunk_buf: vec![],
             corked: false,
+            gzip: None,
+        }
+    }
+
+    /// Make a writer state that gzip-compresses the body.  The response preamble must say
+    /// `Content-Encoding: gzip`.
+    pub fn new_gzip(chunk_size: usize) -> HttpChunkedTransferWriterState {
+        let mut state = HttpChunkedTransferWriterState::new(chunk_size);
+        state.gzip =
+            Some(gzip::Encoder::new(vec![]).expect("FATAL: failed to write gzip header to memory"));
+        state
+    }
+
+    /// Make a writer state for a response body, which is compressed if the metadata says so
+    pub fn for_response(
+        chunk_size: usize,
+        md: &HttpResponseMetadata,
+    ) -> HttpChunkedTransferWriterState {
+        if md.gzip {
+            HttpChunkedTransferWriterState::new_gzip(chunk_size)
+        } else {
+            HttpChunkedTransferWriterState::new(chunk_size)
         }
     }
 }
@@ -647,10 +712,9 @@ impl<'a, 'state, W: Write> HttpChunkedTransferWriter<'a, 'state, W> {
     pub fn corked(&self) -> bool {
         self.state.corked
     }
-}
 
-impl<'a, 'state, W: Write> Write for HttpChunkedTransferWriter<'a, 'state, W> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    /// Chunk-encode and send (or buffer) the given bytes of the body
+    fn write_chunks(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut written = 0;
         while written < buf.len() && !self.state.corked {
             if self.state.chunk_buf.len() > 0 {
@@ -678,9 +742,41 @@ impl<'a, 'state, W: Write> Write for HttpChunkedTransferWriter<'a, 'state, W> {
         Ok(written)
     }
 
+    /// Send out any buffered chunk data without ending the chunked body, so a stream that stays
+    /// open (like an event stream) can deliver what it has written so far.
+    pub fn flush_chunk_data(&mut self) -> io::Result<()> {
+        if !self.state.corked && self.state.chunk_buf.len() > 0 {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'state, W: Write> Write for HttpChunkedTransferWriter<'a, 'state, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.state.corked {
+            return Ok(0);
+        }
+        match self.state.gzip.as_mut() {
+            Some(encoder) => {
+                // chunk whatever the encoder has produced so far
+                encoder.write_all(buf)?;
+                let compressed = mem::take(encoder.as_inner_mut());
+                self.write_chunks(&compressed)?;
+                Ok(buf.len())
+            }
+            None => self.write_chunks(buf),
+        }
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         // send out any buffered chunk data
         if !self.state.corked {
+            if let Some(encoder) = self.state.gzip.take() {
+                // the rest of the compressed body, and the gzip trailer
+                let compressed = encoder.finish().into_result()?;
+                self.write_chunks(&compressed)?;
+            }
             self.flush_chunk().and_then(|nw| {
                 if nw > 0 {
                     // send empty chunk
@@ -875,7 +971,7 @@ fn empty_headers<W: Write>(_fd: &mut W) -> Result<(), codec_error> {
     Ok(())
 }
 
-fn stacks_height_headers<W: Write>(
+fn request_metadata_headers<W: Write>(
     fd: &mut W,
     md: &HttpRequestMetadata,
 ) -> Result<(), codec_error> {
@@ -886,6 +982,14 @@ fn stacks_height_headers<W: Write>(
         }
         _ => {}
     }
+    if let Some(ref api_key) = md.api_key {
+        fd.write_all(format!("X-API-Key: {}\r\n", api_key).as_bytes())
+            .map_err(codec_error::WriteError)?;
+    }
+    if md.accept_gzip {
+        fd.write_all("Accept-Encoding: gzip\r\n".as_bytes())
+            .map_err(codec_error::WriteError)?;
+    }
     Ok(())
 }
 
@@ -916,9 +1020,31 @@ fn keep_alive_headers<W: Write>(fd: &mut W, md: &HttpResponseMetadata) -> Result
         }
         _ => {}
     }
+    if md.gzip && md.content_length.is_none() {
+        // only chunk-encoded bodies get compressed
+        fd.write_all("Content-Encoding: gzip\r\n".as_bytes())
+            .map_err(codec_error::WriteError)?;
+    }
     Ok(())
 }
 
+/// Decompress a gzip-encoded HTTP body, refusing to inflate it past `max_len` bytes
+fn decode_gzip_body(body: &[u8], max_len: u64) -> Result<Vec<u8>, net_error> {
+    let decoder = gzip::Decoder::new(body)
+        .map_err(|e| net_error::DeserializeError(format!("Invalid gzip-encoded body: {:?}", &e)))?;
+    let mut decoded = vec![];
+    decoder
+        .take(max_len + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| net_error::DeserializeError(format!("Invalid gzip-encoded body: {:?}", &e)))?;
+    if (decoded.len() as u64) > max_len {
+        return Err(net_error::DeserializeError(
+            "Invalid gzip-encoded body: too long".to_string(),
+        ));
+    }
+    Ok(decoded)
+}
+
 fn write_headers<W: Write>(
     fd: &mut W,
     headers: &HashMap<String, String>,
@@ -1290,6 +1416,14 @@ impl HttpResponsePreamble {
     pub fn is_chunked(&self) -> bool {
         self.content_length.is_none()
     }
+
+    // do we have Content-Encoding: gzip?
+    pub fn is_gzip(&self) -> bool {
+        self.headers
+            .get("content-encoding")
+            .map(|encoding| encoding.trim().eq_ignore_ascii_case("gzip"))
+            .unwrap_or(false)
+    }
 }
 
 /// Get an RFC 7231 date that represents the current time
@@ -1448,6 +1582,41 @@ impl StacksMessageCodec for HttpResponsePreamble {
     }
 }
 
+impl BatchResponseItem {
+    /// Turn a serialized HTTP response to one request of a batch into a batch response item.
+    /// JSON bodies are embedded as-is; anything else is embedded as a string.
+    pub fn from_http_response(bytes: &[u8]) -> Result<BatchResponseItem, net_error> {
+        let mut cursor = io::Cursor::new(bytes);
+        let preamble: HttpResponsePreamble = read_next(&mut cursor)?;
+
+        let mut body = vec![];
+        if preamble.is_chunked() {
+            let mut chunked_fd =
+                HttpChunkedTransferReader::from_reader(&mut cursor, MAX_MESSAGE_LEN as u64);
+            chunked_fd
+                .read_to_end(&mut body)
+                .map_err(net_error::ReadError)?;
+        } else {
+            cursor
+                .read_to_end(&mut body)
+                .map_err(net_error::ReadError)?;
+        }
+
+        let body = if preamble.content_type == HttpContentType::JSON {
+            serde_json::from_slice(&body).map_err(|e| {
+                net_error::DeserializeError(format!("Failed to parse JSON body: {:?}", &e))
+            })?
+        } else {
+            serde_json::Value::String(String::from_utf8_lossy(&body).to_string())
+        };
+
+        Ok(BatchResponseItem {
+            status: preamble.status_code,
+            body,
+        })
+    }
+}
+
 impl HttpRequestType {
     fn try_parse<R: Read, F>(
         protocol: &mut StacksHttp,
@@ -1600,6 +1769,22 @@ impl HttpRequestType {
                 &PATH_POST_MEMPOOL_QUERY,
                 &HttpRequestType::parse_post_mempool_query,
             ),
+            (
+                "POST",
+                &PATH_POST_MARF_PROOFS,
+                &HttpRequestType::parse_get_marf_proofs,
+            ),
+            ("POST", &PATH_POST_BATCH, &HttpRequestType::parse_batch),
+            (
+                "GET",
+                &PATH_GET_EVENT_STREAM,
+                &HttpRequestType::parse_get_event_stream,
+            ),
+            (
+                "GET",
+                &PATH_GET_CONTRACT_EVENT_STREAM,
+                &HttpRequestType::parse_get_contract_event_stream,
+            ),
         ];
 
         // use url::Url to parse path and query string
@@ -1896,6 +2081,140 @@ impl HttpRequestType {
         ))
     }
 
+    fn parse_get_marf_proofs<R: Read>(
+        _protocol: &mut StacksHttp,
+        preamble: &HttpRequestPreamble,
+        _captures: &Captures,
+        query: Option<&str>,
+        fd: &mut R,
+    ) -> Result<HttpRequestType, net_error> {
+        let content_len = preamble.get_content_length();
+        if !(content_len > 0 && content_len < MAX_PAYLOAD_LEN) {
+            return Err(net_error::DeserializeError(format!(
+                "Invalid Http request: invalid body length for GetMarfProofs ({})",
+                content_len
+            )));
+        }
+
+        if preamble.content_type != Some(HttpContentType::JSON) {
+            return Err(net_error::DeserializeError(
+                "Invalid content-type: expected application/json".into(),
+            ));
+        }
+
+        let body: MarfProofsRequestBody = serde_json::from_reader(fd)
+            .map_err(|_e| net_error::DeserializeError("Failed to parse JSON body".into()))?;
+
+        if body.keys.len() == 0 || body.keys.len() > MAX_MARF_PROOF_KEYS {
+            return Err(net_error::DeserializeError(format!(
+                "Invalid Http request: expected between 1 and {} keys",
+                MAX_MARF_PROOF_KEYS
+            )));
+        }
+
+        let tip = HttpRequestType::get_chain_tip_query(query);
+
+        Ok(HttpRequestType::GetMarfProofs(
+            HttpRequestMetadata::from_preamble(preamble),
+            body.keys,
+            tip,
+        ))
+    }
+
+    fn parse_batch<R: Read>(
+        protocol: &mut StacksHttp,
+        preamble: &HttpRequestPreamble,
+        _captures: &Captures,
+        query: Option<&str>,
+        fd: &mut R,
+    ) -> Result<HttpRequestType, net_error> {
+        let content_len = preamble.get_content_length();
+        if !(content_len > 0 && content_len < MAX_PAYLOAD_LEN) {
+            return Err(net_error::DeserializeError(format!(
+                "Invalid Http request: invalid body length for Batch ({})",
+                content_len
+            )));
+        }
+
+        if preamble.content_type != Some(HttpContentType::JSON) {
+            return Err(net_error::DeserializeError(
+                "Invalid content-type: expected application/json".into(),
+            ));
+        }
+
+        let body: BatchRequestBody = serde_json::from_reader(fd)
+            .map_err(|_e| net_error::DeserializeError("Failed to parse JSON body".into()))?;
+
+        if body.requests.len() == 0 || body.requests.len() > MAX_BATCH_REQUESTS {
+            return Err(net_error::DeserializeError(format!(
+                "Invalid Http request: expected between 1 and {} requests",
+                MAX_BATCH_REQUESTS
+            )));
+        }
+
+        let mut requests = Vec::with_capacity(body.requests.len());
+        for (i, item) in body.requests.into_iter().enumerate() {
+            let request =
+                HttpRequestType::parse_batch_item(protocol, preamble, item).map_err(|e| {
+                    let reason = match e {
+                        net_error::ClientError(client_err) => format!("{}", client_err),
+                        e => format!("{}", e),
+                    };
+                    net_error::ClientError(ClientError::Message(format!(
+                        "Invalid request {} in batch: {}",
+                        i, reason
+                    )))
+                })?;
+            requests.push(request);
+        }
+
+        let tip = HttpRequestType::get_chain_tip_query(query);
+
+        Ok(HttpRequestType::Batch(
+            HttpRequestMetadata::from_preamble(preamble),
+            requests,
+            tip,
+        ))
+    }
+
+    /// Parse one request of a batch as if it had been sent on its own, and check that it's a read
+    /// request that can be answered from the batch's chain tip.
+    fn parse_batch_item(
+        protocol: &mut StacksHttp,
+        preamble: &HttpRequestPreamble,
+        item: BatchRequestItem,
+    ) -> Result<HttpRequestType, net_error> {
+        let body_bytes = match item.body {
+            Some(body) => serde_json::to_vec(&body).map_err(|e| {
+                net_error::SerializeError(format!("Failed to serialize body: {:?}", &e))
+            })?,
+            None => vec![],
+        };
+        let item_preamble = HttpRequestPreamble {
+            version: preamble.version,
+            verb: item.method.to_uppercase(),
+            path: item.path,
+            host: preamble.host.clone(),
+            content_type: if body_bytes.len() > 0 {
+                Some(HttpContentType::JSON)
+            } else {
+                None
+            },
+            content_length: Some(body_bytes.len() as u32),
+            keep_alive: preamble.keep_alive,
+            headers: HashMap::new(),
+        };
+
+        let request = HttpRequestType::parse(protocol, &item_preamble, &mut &body_bytes[..])?;
+        if !request.is_batchable() {
+            return Err(net_error::ClientError(ClientError::Message(format!(
+                "{} requests can't be batched",
+                request.get_path()
+            ))));
+        }
+        Ok(request)
+    }
+
     fn parse_call_read_only<R: Read>(
         protocol: &mut StacksHttp,
         preamble: &HttpRequestPreamble,
@@ -2005,6 +2324,54 @@ impl HttpRequestType {
         )
     }
 
+    fn parse_get_event_stream<R: Read>(
+        _protocol: &mut StacksHttp,
+        preamble: &HttpRequestPreamble,
+        captures: &Captures,
+        _query: Option<&str>,
+        _fd: &mut R,
+    ) -> Result<HttpRequestType, net_error> {
+        if preamble.get_content_length() != 0 {
+            return Err(net_error::DeserializeError(
+                "Invalid Http request: expected 0-length body for GetEventStream".to_string(),
+            ));
+        }
+
+        let topic = match captures.get(1).map(|topic| topic.as_str()) {
+            Some("blocks") => EventStreamTopic::Blocks,
+            Some("microblocks") => EventStreamTopic::Microblocks,
+            Some("mempool") => EventStreamTopic::Mempool,
+            _ => {
+                return Err(net_error::DeserializeError(
+                    "Failed to match path to event stream topic".to_string(),
+                ));
+            }
+        };
+
+        Ok(HttpRequestType::GetEventStream(
+            HttpRequestMetadata::from_preamble(preamble),
+            topic,
+        ))
+    }
+
+    fn parse_get_contract_event_stream<R: Read>(
+        _protocol: &mut StacksHttp,
+        preamble: &HttpRequestPreamble,
+        captures: &Captures,
+        _query: Option<&str>,
+        _fd: &mut R,
+    ) -> Result<HttpRequestType, net_error> {
+        HttpRequestType::parse_get_contract_arguments(preamble, captures).map(
+            |(preamble, addr, name)| {
+                let contract_id = QualifiedContractIdentifier::new(addr.into(), name);
+                HttpRequestType::GetEventStream(
+                    preamble,
+                    EventStreamTopic::ContractEvents(contract_id),
+                )
+            },
+        )
+    }
+
     fn parse_get_is_trait_implemented<R: Read>(
         _protocol: &mut StacksHttp,
         preamble: &HttpRequestPreamble,
@@ -2692,6 +3059,9 @@ impl HttpRequestType {
             HttpRequestType::GetAttachmentsInv(ref md, ..) => md,
             HttpRequestType::GetAttachment(ref md, ..) => md,
             HttpRequestType::MemPoolQuery(ref md, ..) => md,
+            HttpRequestType::GetMarfProofs(ref md, ..) => md,
+            HttpRequestType::Batch(ref md, ..) => md,
+            HttpRequestType::GetEventStream(ref md, _) => md,
             HttpRequestType::FeeRateEstimate(ref md, _, _) => md,
             HttpRequestType::ClientError(ref md, ..) => md,
         }
@@ -2723,11 +3093,66 @@ impl HttpRequestType {
             HttpRequestType::GetAttachmentsInv(ref mut md, ..) => md,
             HttpRequestType::GetAttachment(ref mut md, ..) => md,
             HttpRequestType::MemPoolQuery(ref mut md, ..) => md,
+            HttpRequestType::GetMarfProofs(ref mut md, ..) => md,
+            HttpRequestType::Batch(ref mut md, ..) => md,
+            HttpRequestType::GetEventStream(ref mut md, _) => md,
             HttpRequestType::FeeRateEstimate(ref mut md, _, _) => md,
             HttpRequestType::ClientError(ref mut md, ..) => md,
         }
     }
 
+    /// Can this request be part of a /v2/batch request?  Only requests that read from a chain tip
+    /// can be.
+    pub fn is_batchable(&self) -> bool {
+        match self {
+            HttpRequestType::GetAccount(..)
+            | HttpRequestType::GetDataVar(..)
+            | HttpRequestType::GetMapEntry(..)
+            | HttpRequestType::GetMarfProofs(..)
+            | HttpRequestType::CallReadOnlyFunction(..)
+            | HttpRequestType::GetContractSrc(..)
+            | HttpRequestType::GetContractABI(..)
+            | HttpRequestType::GetIsTraitImplemented(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Should the response to this request be gzip-compressed?  Event streams never are, since
+    /// they're sent an event at a time.
+    pub fn wants_gzip(&self) -> bool {
+        match self {
+            HttpRequestType::GetEventStream(..) => false,
+            _ => self.metadata().accept_gzip,
+        }
+    }
+
+    /// Describe this request as one of the requests in a /v2/batch request
+    pub fn to_batch_item(&self, protocol: &mut StacksHttp) -> Result<BatchRequestItem, net_error> {
+        let mut request_bytes = vec![];
+        self.send(protocol, &mut request_bytes)?;
+
+        let mut cursor = io::Cursor::new(&request_bytes[..]);
+        let preamble: HttpRequestPreamble = read_next(&mut cursor)?;
+        let body_bytes = &request_bytes[(cursor.position() as usize)..];
+        let body = if body_bytes.len() > 0 {
+            Some(serde_json::from_slice(body_bytes).map_err(|e| {
+                net_error::SerializeError(format!(
+                    "Failed to describe {} as a batch request: {:?}",
+                    self.get_path(),
+                    &e
+                ))
+            })?)
+        } else {
+            None
+        };
+
+        Ok(BatchRequestItem {
+            method: preamble.verb,
+            path: preamble.path,
+            body,
+        })
+    }
+
     fn make_tip_query_string(tip_req: &TipRequest, with_proof: bool) -> String {
         match tip_req {
             TipRequest::UseLatestUnconfirmedTip => {
@@ -2892,6 +3317,24 @@ impl HttpRequestType {
                 }
                 None => "/v2/mempool/query".to_string(),
             },
+            HttpRequestType::GetMarfProofs(_md, _keys, tip_req) => format!(
+                "/v2/marf/proofs{}",
+                HttpRequestType::make_tip_query_string(tip_req, true)
+            ),
+            HttpRequestType::Batch(_md, _requests, tip_req) => format!(
+                "/v2/batch{}",
+                HttpRequestType::make_tip_query_string(tip_req, true)
+            ),
+            HttpRequestType::GetEventStream(_md, topic) => match topic {
+                EventStreamTopic::Blocks => "/v2/events/blocks".to_string(),
+                EventStreamTopic::Microblocks => "/v2/events/microblocks".to_string(),
+                EventStreamTopic::Mempool => "/v2/events/mempool".to_string(),
+                EventStreamTopic::ContractEvents(contract_id) => format!(
+                    "/v2/events/contract/{}/{}",
+                    StacksAddress::from(contract_id.issuer.clone()),
+                    contract_id.name.as_str()
+                ),
+            },
             HttpRequestType::FeeRateEstimate(_, _, _) => self.get_path().to_string(),
             HttpRequestType::ClientError(_md, e) => match e {
                 ClientError::NotFound(path) => path.to_string(),
@@ -2931,12 +3374,20 @@ impl HttpRequestType {
             HttpRequestType::GetAttachment(..) => "/v2/attachments/:hash",
             HttpRequestType::GetIsTraitImplemented(..) => "/v2/traits/:principal/:contract_name",
             HttpRequestType::MemPoolQuery(..) => "/v2/mempool/query",
+            HttpRequestType::GetMarfProofs(..) => "/v2/marf/proofs",
+            HttpRequestType::Batch(..) => "/v2/batch",
+            HttpRequestType::GetEventStream(_, topic) => match topic {
+                EventStreamTopic::ContractEvents(..) => {
+                    "/v2/events/contract/:principal/:contract_name"
+                }
+                _ => "/v2/events/:topic",
+            },
             HttpRequestType::FeeRateEstimate(_, _, _) => "/v2/fees/transaction",
             HttpRequestType::OptionsPreflight(..) | HttpRequestType::ClientError(..) => "/",
         }
     }
 
-    pub fn send<W: Write>(&self, _protocol: &mut StacksHttp, fd: &mut W) -> Result<(), net_error> {
+    pub fn send<W: Write>(&self, protocol: &mut StacksHttp, fd: &mut W) -> Result<(), net_error> {
         match self {
             HttpRequestType::PostTransaction(md, tx, attachment) => {
                 let mut tx_bytes = vec![];
@@ -2977,7 +3428,7 @@ impl HttpRequestType {
                     md.keep_alive,
                     Some(request_body_bytes.len() as u32),
                     content_type,
-                    |fd| stacks_height_headers(fd, md),
+                    |fd| request_metadata_headers(fd, md),
                 )?;
                 fd.write_all(&request_body_bytes)
                     .map_err(net_error::WriteError)?;
@@ -2995,7 +3446,7 @@ impl HttpRequestType {
                     md.keep_alive,
                     Some(block_bytes.len() as u32),
                     Some(&HttpContentType::Bytes),
-                    |fd| stacks_height_headers(fd, md),
+                    |fd| request_metadata_headers(fd, md),
                 )?;
                 fd.write_all(&block_bytes).map_err(net_error::WriteError)?;
             }
@@ -3012,7 +3463,7 @@ impl HttpRequestType {
                     md.keep_alive,
                     Some(mb_bytes.len() as u32),
                     Some(&HttpContentType::Bytes),
-                    |fd| stacks_height_headers(fd, md),
+                    |fd| request_metadata_headers(fd, md),
                 )?;
                 fd.write_all(&mb_bytes).map_err(net_error::WriteError)?;
             }
@@ -3038,7 +3489,7 @@ impl HttpRequestType {
                     md.keep_alive,
                     Some(request_json.as_bytes().len() as u32),
                     Some(&HttpContentType::JSON),
-                    |fd| stacks_height_headers(fd, md),
+                    |fd| request_metadata_headers(fd, md),
                 )?;
                 fd.write_all(&request_json.as_bytes())
                     .map_err(net_error::WriteError)?;
@@ -3082,7 +3533,61 @@ impl HttpRequestType {
                     md.keep_alive,
                     Some(request_body_bytes.len() as u32),
                     Some(&HttpContentType::JSON),
-                    |fd| stacks_height_headers(fd, md),
+                    |fd| request_metadata_headers(fd, md),
+                )?;
+                fd.write_all(&request_body_bytes)
+                    .map_err(net_error::WriteError)?;
+            }
+            HttpRequestType::GetMarfProofs(md, keys, ..) => {
+                let request_body = MarfProofsRequestBody { keys: keys.clone() };
+
+                let mut request_body_bytes = vec![];
+                serde_json::to_writer(&mut request_body_bytes, &request_body).map_err(|e| {
+                    net_error::SerializeError(format!(
+                        "Failed to serialize MARF proof request to JSON: {:?}",
+                        &e
+                    ))
+                })?;
+
+                HttpRequestPreamble::new_serialized(
+                    fd,
+                    &md.version,
+                    "POST",
+                    &self.request_path(),
+                    &md.peer,
+                    md.keep_alive,
+                    Some(request_body_bytes.len() as u32),
+                    Some(&HttpContentType::JSON),
+                    |fd| request_metadata_headers(fd, md),
+                )?;
+                fd.write_all(&request_body_bytes)
+                    .map_err(net_error::WriteError)?;
+            }
+            HttpRequestType::Batch(md, requests, ..) => {
+                let mut items = Vec::with_capacity(requests.len());
+                for request in requests.iter() {
+                    items.push(request.to_batch_item(protocol)?);
+                }
+                let request_body = BatchRequestBody { requests: items };
+
+                let mut request_body_bytes = vec![];
+                serde_json::to_writer(&mut request_body_bytes, &request_body).map_err(|e| {
+                    net_error::SerializeError(format!(
+                        "Failed to serialize batch request to JSON: {:?}",
+                        &e
+                    ))
+                })?;
+
+                HttpRequestPreamble::new_serialized(
+                    fd,
+                    &md.version,
+                    "POST",
+                    &self.request_path(),
+                    &md.peer,
+                    md.keep_alive,
+                    Some(request_body_bytes.len() as u32),
+                    Some(&HttpContentType::JSON),
+                    |fd| request_metadata_headers(fd, md),
                 )?;
                 fd.write_all(&request_body_bytes)
                     .map_err(net_error::WriteError)?;
@@ -3115,7 +3620,7 @@ impl HttpRequestType {
                     md.keep_alive,
                     None,
                     None,
-                    |fd| stacks_height_headers(fd, md),
+                    |fd| request_metadata_headers(fd, md),
                 )?;
             }
         }
@@ -3183,6 +3688,14 @@ impl HttpResponseType {
             402 => HttpResponseType::PaymentRequired(md, error_text),
             403 => HttpResponseType::Forbidden(md, error_text),
             404 => HttpResponseType::NotFound(md, error_text),
+            429 => {
+                let retry_after = preamble
+                    .headers
+                    .get("retry-after")
+                    .and_then(|value| value.parse::<u64>().ok())
+                    .unwrap_or(0);
+                HttpResponseType::TooManyRequests(md, retry_after, error_text)
+            }
             500 => HttpResponseType::ServerError(md, error_text),
             503 => HttpResponseType::ServiceUnavailable(md, error_text),
             _ => HttpResponseType::Error(md, preamble.status_code, error_text),
@@ -3355,6 +3868,11 @@ impl HttpResponseType {
             (&PATH_GETBLOCK, &HttpResponseType::parse_block),
             (&PATH_GET_DATA_VAR, &HttpResponseType::parse_get_data_var),
             (&PATH_GET_MAP_ENTRY, &HttpResponseType::parse_get_map_entry),
+            (
+                &PATH_POST_MARF_PROOFS,
+                &HttpResponseType::parse_get_marf_proofs,
+            ),
+            (&PATH_POST_BATCH, &HttpResponseType::parse_batch),
             (
                 &PATH_GETMICROBLOCKS_INDEXED,
                 &HttpResponseType::parse_microblocks,
@@ -3592,6 +4110,35 @@ impl HttpResponseType {
         ))
     }
 
+    fn parse_get_marf_proofs<R: Read>(
+        _protocol: &mut StacksHttp,
+        request_version: HttpVersion,
+        preamble: &HttpResponsePreamble,
+        fd: &mut R,
+        len_hint: Option<usize>,
+    ) -> Result<HttpResponseType, net_error> {
+        let marf_proofs =
+            HttpResponseType::parse_json(preamble, fd, len_hint, MAX_MESSAGE_LEN as u64)?;
+        Ok(HttpResponseType::GetMarfProofs(
+            HttpResponseMetadata::from_preamble(request_version, preamble),
+            marf_proofs,
+        ))
+    }
+
+    fn parse_batch<R: Read>(
+        _protocol: &mut StacksHttp,
+        request_version: HttpVersion,
+        preamble: &HttpResponsePreamble,
+        fd: &mut R,
+        len_hint: Option<usize>,
+    ) -> Result<HttpResponseType, net_error> {
+        let items = HttpResponseType::parse_json(preamble, fd, len_hint, MAX_MESSAGE_LEN as u64)?;
+        Ok(HttpResponseType::Batch(
+            HttpResponseMetadata::from_preamble(request_version, preamble),
+            items,
+        ))
+    }
+
     fn parse_get_contract_src<R: Read>(
         _protocol: &mut StacksHttp,
         request_version: HttpVersion,
@@ -3951,6 +4498,7 @@ impl HttpResponseType {
             402 => "Payment Required",
             403 => "Forbidden",
             404 => "Not Found",
+            429 => "Too Many Requests",
             500 => "Internal Server Error",
             503 => "Service Temporarily Unavailable",
             _ => "Error",
@@ -3963,15 +4511,19 @@ impl HttpResponseType {
         code: u16,
         message: &str,
     ) -> Result<(), net_error> {
-        let md = self.metadata();
+        // error bodies are sent with a known length, and so are never compressed
+        let md = HttpResponseMetadata {
+            content_length: Some(message.len() as u32),
+            ..self.metadata().clone()
+        };
         HttpResponsePreamble::new_serialized(
             fd,
             code,
             HttpResponseType::error_reason(code),
-            Some(message.len() as u32),
+            md.content_length.clone(),
             &HttpContentType::Text,
             md.request_id,
-            |ref mut fd| keep_alive_headers(fd, md),
+            |ref mut fd| keep_alive_headers(fd, &md),
         )?;
         fd.write_all(message.as_bytes())
             .map_err(net_error::WriteError)?;
@@ -3995,6 +4547,8 @@ impl HttpResponseType {
             HttpResponseType::TokenTransferCost(ref md, _) => md,
             HttpResponseType::GetDataVar(ref md, _) => md,
             HttpResponseType::GetMapEntry(ref md, _) => md,
+            HttpResponseType::GetMarfProofs(ref md, _) => md,
+            HttpResponseType::Batch(ref md, _) => md,
             HttpResponseType::GetAccount(ref md, _) => md,
             HttpResponseType::GetContractABI(ref md, _) => md,
             HttpResponseType::GetContractSrc(ref md, _) => md,
@@ -4004,6 +4558,7 @@ impl HttpResponseType {
             HttpResponseType::GetAttachment(ref md, _) => md,
             HttpResponseType::GetAttachmentsInv(ref md, _) => md,
             HttpResponseType::MemPoolTxStream(ref md) => md,
+            HttpResponseType::EventStream(ref md) => md,
             HttpResponseType::MemPoolTxs(ref md, ..) => md,
             HttpResponseType::OptionsPreflight(ref md) => md,
             HttpResponseType::TransactionFeeEstimation(ref md, _) => md,
@@ -4015,6 +4570,7 @@ impl HttpResponseType {
             HttpResponseType::Forbidden(ref md, _) => md,
             HttpResponseType::NotFound(ref md, _) => md,
             HttpResponseType::ServerError(ref md, _) => md,
+            HttpResponseType::TooManyRequests(ref md, _, _) => md,
             HttpResponseType::ServiceUnavailable(ref md, _) => md,
             HttpResponseType::Error(ref md, _, _) => md,
         }
@@ -4031,7 +4587,8 @@ impl HttpResponseType {
             write_next(fd, message)
         } else {
             // no content-length, so send as chunk-encoded
-            let mut write_state = HttpChunkedTransferWriterState::new(protocol.chunk_size as usize);
+            let mut write_state =
+                HttpChunkedTransferWriterState::for_response(protocol.chunk_size as usize, md);
             let mut encoder = HttpChunkedTransferWriter::from_writer_state(fd, &mut write_state);
             write_next(&mut encoder, message)?;
             encoder.flush().map_err(codec_error::WriteError)?;
@@ -4050,7 +4607,8 @@ impl HttpResponseType {
             fd.write_all(text).map_err(net_error::WriteError)
         } else {
             // no content-length, so send as chunk-encoded
-            let mut write_state = HttpChunkedTransferWriterState::new(protocol.chunk_size as usize);
+            let mut write_state =
+                HttpChunkedTransferWriterState::for_response(protocol.chunk_size as usize, md);
             let mut encoder = HttpChunkedTransferWriter::from_writer_state(fd, &mut write_state);
             encoder.write_all(text).map_err(net_error::WriteError)?;
             encoder.flush().map_err(net_error::WriteError)?;
@@ -4070,7 +4628,8 @@ impl HttpResponseType {
                 .map_err(|e| net_error::SerializeError(format!("Failed to send as JSON: {:?}", &e)))
         } else {
             // no content-length, so send as chunk-encoded
-            let mut write_state = HttpChunkedTransferWriterState::new(protocol.chunk_size as usize);
+            let mut write_state =
+                HttpChunkedTransferWriterState::for_response(protocol.chunk_size as usize, md);
             let mut encoder = HttpChunkedTransferWriter::from_writer_state(fd, &mut write_state);
             serde_json::to_writer(&mut encoder, message).map_err(|e| {
                 net_error::SerializeError(format!("Failed to send as chunk-encoded JSON: {:?}", &e))
@@ -4118,6 +4677,14 @@ impl HttpResponseType {
                 HttpResponsePreamble::ok_JSON_from_md(fd, md)?;
                 HttpResponseType::send_json(protocol, md, fd, map_data)?;
             }
+            HttpResponseType::GetMarfProofs(ref md, ref marf_proofs) => {
+                HttpResponsePreamble::ok_JSON_from_md(fd, md)?;
+                HttpResponseType::send_json(protocol, md, fd, marf_proofs)?;
+            }
+            HttpResponseType::Batch(ref md, ref items) => {
+                HttpResponsePreamble::ok_JSON_from_md(fd, md)?;
+                HttpResponseType::send_json(protocol, md, fd, items)?;
+            }
             HttpResponseType::PeerInfo(ref md, ref peer_info) => {
                 HttpResponsePreamble::ok_JSON_from_md(fd, md)?;
                 HttpResponseType::send_json(protocol, md, fd, peer_info)?;
@@ -4272,6 +4839,23 @@ impl HttpResponseType {
                     |ref mut fd| keep_alive_headers(fd, md),
                 )?;
             }
+            HttpResponseType::EventStream(ref md) => {
+                // only send the preamble.  The caller streams the events themselves, for as long
+                // as the client stays connected.
+                HttpResponsePreamble::new_serialized(
+                    fd,
+                    200,
+                    "OK",
+                    None,
+                    &HttpContentType::EventStream,
+                    md.request_id,
+                    |ref mut fd| {
+                        fd.write_all("Cache-Control: no-cache\r\n".as_bytes())
+                            .map_err(codec_error::WriteError)?;
+                        keep_alive_headers(fd, md)
+                    },
+                )?;
+            }
             HttpResponseType::MemPoolTxs(ref md, ref page_id, ref txs) => {
                 HttpResponsePreamble::new_serialized(
                     fd,
@@ -4291,8 +4875,10 @@ impl HttpResponseType {
                             Ok(())
                         } else {
                             // no content-length, so send as chunk-encoded
-                            let mut write_state =
-                                HttpChunkedTransferWriterState::new(protocol.chunk_size as usize);
+                            let mut write_state = HttpChunkedTransferWriterState::for_response(
+                                protocol.chunk_size as usize,
+                                md,
+                            );
                             let mut encoder =
                                 HttpChunkedTransferWriter::from_writer_state(fd, &mut write_state);
                             write_next(&mut encoder, txs)?;
@@ -4334,6 +4920,27 @@ impl HttpResponseType {
             HttpResponseType::Forbidden(_, ref msg) => self.error_response(fd, 403, msg)?,
             HttpResponseType::NotFound(_, ref msg) => self.error_response(fd, 404, msg)?,
             HttpResponseType::ServerError(_, ref msg) => self.error_response(fd, 500, msg)?,
+            HttpResponseType::TooManyRequests(ref md, ref retry_after, ref msg) => {
+                let md = HttpResponseMetadata {
+                    content_length: Some(msg.len() as u32),
+                    ..md.clone()
+                };
+                HttpResponsePreamble::new_serialized(
+                    fd,
+                    429,
+                    HttpResponseType::error_reason(429),
+                    md.content_length.clone(),
+                    &HttpContentType::Text,
+                    md.request_id,
+                    |ref mut fd| {
+                        fd.write_all(format!("Retry-After: {}\r\n", retry_after).as_bytes())
+                            .map_err(codec_error::WriteError)?;
+                        keep_alive_headers(fd, &md)
+                    },
+                )?;
+                fd.write_all(msg.as_bytes())
+                    .map_err(net_error::WriteError)?;
+            }
             HttpResponseType::ServiceUnavailable(_, ref msg) => {
                 self.error_response(fd, 503, msg)?
             }
@@ -4421,6 +5028,9 @@ impl MessageSequence for StacksHttpMessage {
                 HttpRequestType::GetAttachment(..) => "HTTP(GetAttachment)",
                 HttpRequestType::GetAttachmentsInv(..) => "HTTP(GetAttachmentsInv)",
                 HttpRequestType::MemPoolQuery(..) => "HTTP(MemPoolQuery)",
+                HttpRequestType::GetMarfProofs(..) => "HTTP(GetMarfProofs)",
+                HttpRequestType::Batch(..) => "HTTP(Batch)",
+                HttpRequestType::GetEventStream(..) => "HTTP(GetEventStream)",
                 HttpRequestType::OptionsPreflight(..) => "HTTP(OptionsPreflight)",
                 HttpRequestType::ClientError(..) => "HTTP(ClientError)",
                 HttpRequestType::FeeRateEstimate(_, _, _) => "HTTP(FeeRateEstimate)",
@@ -4429,6 +5039,8 @@ impl MessageSequence for StacksHttpMessage {
                 HttpResponseType::TokenTransferCost(_, _) => "HTTP(TokenTransferCost)",
                 HttpResponseType::GetDataVar(_, _) => "HTTP(GetDataVar)",
                 HttpResponseType::GetMapEntry(_, _) => "HTTP(GetMapEntry)",
+                HttpResponseType::GetMarfProofs(_, _) => "HTTP(GetMarfProofs)",
+                HttpResponseType::Batch(_, _) => "HTTP(Batch)",
                 HttpResponseType::GetAccount(_, _) => "HTTP(GetAccount)",
                 HttpResponseType::GetContractABI(..) => "HTTP(GetContractABI)",
                 HttpResponseType::GetContractSrc(..) => "HTTP(GetContractSrc)",
@@ -4450,6 +5062,7 @@ impl MessageSequence for StacksHttpMessage {
                 HttpResponseType::MicroblockHash(_, _) => "HTTP(MicroblockHash)",
                 HttpResponseType::UnconfirmedTransaction(_, _) => "HTTP(UnconfirmedTransaction)",
                 HttpResponseType::MemPoolTxStream(..) => "HTTP(MemPoolTxStream)",
+                HttpResponseType::EventStream(..) => "HTTP(EventStream)",
                 HttpResponseType::MemPoolTxs(..) => "HTTP(MemPoolTxs)",
                 HttpResponseType::OptionsPreflight(_) => "HTTP(OptionsPreflight)",
                 HttpResponseType::BadRequestJSON(..) | HttpResponseType::BadRequest(..) => {
@@ -4460,6 +5073,7 @@ impl MessageSequence for StacksHttpMessage {
                 HttpResponseType::Forbidden(_, _) => "HTTP(403)",
                 HttpResponseType::NotFound(_, _) => "HTTP(404)",
                 HttpResponseType::ServerError(_, _) => "HTTP(500)",
+                HttpResponseType::TooManyRequests(_, _, _) => "HTTP(429)",
                 HttpResponseType::ServiceUnavailable(_, _) => "HTTP(503)",
                 HttpResponseType::Error(_, _, _) => "HTTP(other)",
                 HttpResponseType::TransactionFeeEstimation(_, _) => {
@@ -4832,6 +5446,17 @@ impl ProtocolFamily for StacksHttp {
                             &request_path
                         );
 
+                        let message_bytes = if http_response_preamble.is_gzip() {
+                            decode_gzip_body(&message_bytes, MAX_MESSAGE_LEN as u64).map_err(
+                                |e| {
+                                    self.reset();
+                                    e
+                                },
+                            )?
+                        } else {
+                            message_bytes
+                        };
+
                         // we now know the content-length, so pass it into the parser.
                         let len_hint = message_bytes.len();
                         let parse_res = HttpResponseType::parse(
@@ -4923,6 +5548,22 @@ impl ProtocolFamily for StacksHttp {
                     &request_path
                 );
 
+                if http_response_preamble.is_gzip() {
+                    // parse the decompressed body as if it had been sent as-is
+                    let decoded = decode_gzip_body(buf, MAX_MESSAGE_LEN as u64)?;
+                    let mut decoded_preamble = http_response_preamble.clone();
+                    decoded_preamble.content_length = Some(decoded.len() as u32);
+                    let data_response = HttpResponseType::parse(
+                        self,
+                        request_version,
+                        &decoded_preamble,
+                        request_path,
+                        &mut &decoded[..],
+                        None,
+                    )?;
+                    return Ok((StacksHttpMessage::Response(data_response), buf.len()));
+                }
+
                 let mut cursor = io::Cursor::new(buf);
                 match HttpResponseType::parse(
                     self,
@@ -4996,6 +5637,7 @@ mod test {
     use crate::chainstate::stacks::TransactionVersion;
     use crate::net::codec::test::check_codec_and_corruption;
     use crate::net::test::*;
+    use crate::net::MapEntryResponse;
     use crate::net::RPCNeighbor;
     use crate::net::RPCNeighborsInfo;
     use stacks_common::util::hash::to_hex;
@@ -5322,6 +5964,41 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_http_chunked_encode_decode_gzip_roundtrip() {
+        let mut rng = rand::thread_rng();
+        for i in 0..100 {
+            let mut data = vec![0u8; 256];
+            rng.fill_bytes(&mut data[0..128]);
+
+            let mut encoded_data = vec![];
+            {
+                let mut write_state = HttpChunkedTransferWriterState::new_gzip(i + 1);
+                let mut encoder = HttpChunkedTransferWriter::from_writer_state(
+                    &mut encoded_data,
+                    &mut write_state,
+                );
+                // written in two parts, like a stream sent over several passes
+                encoder.write_all(&data[0..100]).unwrap();
+                encoder.write_all(&data[100..]).unwrap();
+                encoder.flush().unwrap();
+            }
+
+            let mut compressed_data = vec![];
+            {
+                let mut cursor = io::Cursor::new(&encoded_data);
+                let mut decoder = HttpChunkedTransferReader::from_reader(&mut cursor, 1024);
+                decoder.read_to_end(&mut compressed_data).unwrap();
+            }
+
+            let decoded_data = decode_gzip_body(&compressed_data, 256).unwrap();
+            assert_eq!(data, decoded_data);
+
+            // won't inflate past the limit
+            assert!(decode_gzip_body(&compressed_data, 255).is_err());
+        }
+    }
+
     #[test]
     fn test_parse_reserved_header() {
         let tests = vec![
@@ -5350,6 +6027,31 @@ mod test {
                 "123",
                 Some(HttpReservedHeader::XRequestID(123)),
             ),
+            (
+                "X-API-Key",
+                "hello",
+                Some(HttpReservedHeader::ApiKey("hello".to_string())),
+            ),
+            (
+                "Accept-Encoding",
+                "gzip",
+                Some(HttpReservedHeader::AcceptEncoding(true)),
+            ),
+            (
+                "Accept-Encoding",
+                "deflate, GZIP;q=0.5",
+                Some(HttpReservedHeader::AcceptEncoding(true)),
+            ),
+            (
+                "Accept-Encoding",
+                "gzip;q=0, br",
+                Some(HttpReservedHeader::AcceptEncoding(false)),
+            ),
+            (
+                "Accept-Encoding",
+                "identity",
+                Some(HttpReservedHeader::AcceptEncoding(false)),
+            ),
             (
                 "Host",
                 "foo:123",
@@ -5377,6 +6079,7 @@ mod test {
             ("X-Request-Id", "-1", None),
             ("X-Request-Id", "asdf", None),
             ("X-Request-Id", "4294967296", None),
+            ("X-API-Key", "", None),
             ("Unrecognized", "header", None),
         ];
 
@@ -5386,6 +6089,218 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_http_request_api_key() {
+        let mut md = HttpRequestMetadata::from_host(
+            PeerHost::from_host_port("127.0.0.1".to_string(), 20443),
+            None,
+        );
+        md.api_key = Some("hello-api-key".to_string());
+        let request = HttpRequestType::GetInfo(md.clone());
+
+        let request_bytes = StacksHttp::serialize_request(&request).unwrap();
+        let request_str = String::from_utf8(request_bytes.clone()).unwrap();
+        assert!(request_str.find("X-API-Key: hello-api-key\r\n").is_some());
+
+        let preamble = HttpRequestPreamble::consensus_deserialize(&mut &request_bytes[..]).unwrap();
+        assert_eq!(HttpRequestMetadata::from_preamble(&preamble), md);
+    }
+
+    #[test]
+    fn test_http_response_too_many_requests() {
+        let response = HttpResponseType::TooManyRequests(
+            HttpResponseMetadata::new(HttpVersion::Http11, 123, Some(4), true, None),
+            17,
+            "slow".to_string(),
+        );
+
+        let mut response_bytes = vec![];
+        let mut http = StacksHttp::new("127.0.0.1:20443".parse().unwrap());
+        response.send(&mut http, &mut response_bytes).unwrap();
+
+        let response_str = String::from_utf8(response_bytes.clone()).unwrap();
+        assert!(response_str.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(response_str.find("Retry-After: 17\r\n").is_some());
+
+        match StacksHttp::parse_response("/v2/info", &response_bytes).unwrap() {
+            StacksHttpMessage::Response(parsed) => assert_eq!(parsed, response),
+            x => panic!("Did not get a response: {:?}", &x),
+        }
+    }
+
+    #[test]
+    fn test_http_gzip_response() {
+        let mut md = HttpRequestMetadata::from_host(
+            PeerHost::from_host_port("127.0.0.1".to_string(), 20443),
+            None,
+        );
+        md.accept_gzip = true;
+        let request = HttpRequestType::GetBlock(md, StacksBlockId([2u8; 32]));
+        assert!(request.wants_gzip());
+
+        let request_bytes = StacksHttp::serialize_request(&request).unwrap();
+        let request_str = String::from_utf8(request_bytes.clone()).unwrap();
+        assert!(request_str.find("Accept-Encoding: gzip\r\n").is_some());
+
+        let preamble = HttpRequestPreamble::consensus_deserialize(&mut &request_bytes[..]).unwrap();
+        assert!(HttpRequestMetadata::from_preamble(&preamble).accept_gzip);
+
+        let block = make_codec_test_block(100);
+        let response_md = HttpResponseMetadata::from_http_request_type(&request, None);
+        assert!(response_md.gzip);
+
+        let mut response_bytes = vec![];
+        let mut http = StacksHttp::new("127.0.0.1:20443".parse().unwrap());
+        HttpResponseType::Block(response_md.clone(), block.clone())
+            .send(&mut http, &mut response_bytes)
+            .unwrap();
+        assert!(response_bytes.len() < block.serialize_to_vec().len());
+
+        let mut cursor = io::Cursor::new(&response_bytes);
+        let response_preamble: HttpResponsePreamble = read_next(&mut cursor).unwrap();
+        assert!(response_preamble.is_gzip());
+
+        match StacksHttp::parse_response(&request.request_path(), &response_bytes).unwrap() {
+            StacksHttpMessage::Response(HttpResponseType::Block(_, parsed_block)) => {
+                assert_eq!(parsed_block, block)
+            }
+            x => panic!("Did not get a block: {:?}", &x),
+        }
+
+        // errors have a known length, so they're never compressed
+        let mut response_bytes = vec![];
+        HttpResponseType::NotFound(response_md, "no block".to_string())
+            .send(&mut http, &mut response_bytes)
+            .unwrap();
+        let response_str = String::from_utf8(response_bytes).unwrap();
+        assert!(response_str.find("Content-Encoding").is_none());
+        assert!(response_str.ends_with("no block"));
+    }
+
+    #[test]
+    fn test_http_batch_request_codec() {
+        let md = HttpRequestMetadata::from_host(
+            PeerHost::from_host_port("127.0.0.1".to_string(), 20443),
+            None,
+        );
+        let contract_addr = StacksAddress {
+            version: 1,
+            bytes: Hash160([0x11; 20]),
+        };
+        let requests = vec![
+            HttpRequestType::GetMapEntry(
+                md.clone(),
+                contract_addr.clone(),
+                "hello-world".into(),
+                "unit-map".into(),
+                Value::Int(1),
+                TipRequest::UseLatestAnchoredTip,
+                true,
+            ),
+            HttpRequestType::CallReadOnlyFunction(
+                md.clone(),
+                contract_addr.clone(),
+                "hello-world".into(),
+                contract_addr.clone().into(),
+                "ro-test".into(),
+                vec![Value::UInt(2)],
+                TipRequest::UseLatestAnchoredTip,
+            ),
+        ];
+        let request = HttpRequestType::Batch(
+            md.clone(),
+            requests.clone(),
+            TipRequest::SpecificTip(StacksBlockId([3u8; 32])),
+        );
+
+        let request_bytes = StacksHttp::serialize_request(&request).unwrap();
+        let mut http = StacksHttp::new("127.0.0.1:20443".parse().unwrap());
+        let (preamble, offset) = http.read_preamble(&request_bytes).unwrap();
+        let (parsed, _) = http
+            .read_payload(&preamble, &request_bytes[offset..])
+            .unwrap();
+        assert_eq!(parsed, StacksHttpMessage::Request(request));
+
+        // only read requests can be batched
+        let bad_requests = vec![
+            HttpRequestType::GetBlock(md.clone(), StacksBlockId([2u8; 32])),
+            HttpRequestType::Batch(
+                md.clone(),
+                requests.clone(),
+                TipRequest::UseLatestAnchoredTip,
+            ),
+        ];
+        for bad_request in bad_requests {
+            let request = HttpRequestType::Batch(
+                md.clone(),
+                vec![requests[0].clone(), bad_request],
+                TipRequest::UseLatestAnchoredTip,
+            );
+            let request_bytes = StacksHttp::serialize_request(&request).unwrap();
+            let mut http = StacksHttp::new("127.0.0.1:20443".parse().unwrap());
+            let (preamble, offset) = http.read_preamble(&request_bytes).unwrap();
+            match http.read_payload(&preamble, &request_bytes[offset..]) {
+                Ok((
+                    StacksHttpMessage::Request(HttpRequestType::ClientError(
+                        _,
+                        ClientError::Message(msg),
+                    )),
+                    _,
+                )) => assert!(msg.starts_with("Invalid request 1 in batch")),
+                x => panic!("Did not get a client error: {:?}", &x),
+            }
+        }
+
+        // too many requests
+        let request = HttpRequestType::Batch(
+            md.clone(),
+            vec![requests[0].clone(); MAX_BATCH_REQUESTS + 1],
+            TipRequest::UseLatestAnchoredTip,
+        );
+        let request_bytes = StacksHttp::serialize_request(&request).unwrap();
+        let mut http = StacksHttp::new("127.0.0.1:20443".parse().unwrap());
+        let (preamble, offset) = http.read_preamble(&request_bytes).unwrap();
+        assert!(http
+            .read_payload(&preamble, &request_bytes[offset..])
+            .is_err());
+    }
+
+    #[test]
+    fn test_http_batch_response_item() {
+        let mut http = StacksHttp::new("127.0.0.1:20443".parse().unwrap());
+        let md = HttpResponseMetadata::new(HttpVersion::Http11, 123, None, true, None);
+
+        let mut response_bytes = vec![];
+        HttpResponseType::GetMapEntry(
+            md.clone(),
+            MapEntryResponse {
+                data: "0x09".to_string(),
+                marf_proof: None,
+            },
+        )
+        .send(&mut http, &mut response_bytes)
+        .unwrap();
+        assert_eq!(
+            BatchResponseItem::from_http_response(&response_bytes).unwrap(),
+            BatchResponseItem {
+                status: 200,
+                body: serde_json::json!({ "data": "0x09" }),
+            }
+        );
+
+        let mut response_bytes = vec![];
+        HttpResponseType::NotFound(md, "Chain tip not found".to_string())
+            .send(&mut http, &mut response_bytes)
+            .unwrap();
+        assert_eq!(
+            BatchResponseItem::from_http_response(&response_bytes).unwrap(),
+            BatchResponseItem {
+                status: 404,
+                body: serde_json::Value::String("Chain tip not found".to_string()),
+            }
+        );
+    }
+
     #[test]
     fn test_parse_http_request_preamble_ok() {
         let tests = vec![
@@ -5942,12 +6857,16 @@ mod test {
             ),
             keep_alive: true,
             canonical_stacks_tip_height: None,
+            api_key: None,
+            accept_gzip: false,
         };
         let http_request_metadata_dns = HttpRequestMetadata {
             version: HttpVersion::Http11,
             peer: PeerHost::DNS("www.foo.com".to_string(), 80),
             keep_alive: true,
             canonical_stacks_tip_height: None,
+            api_key: None,
+            accept_gzip: false,
         };
 
         let tests = vec![
@@ -6111,6 +7030,7 @@ mod test {
                     )
                     .unwrap(),
                     authenticated: true,
+                    reputation: 0,
                 },
                 RPCNeighbor {
                     network_id: 3,
@@ -6125,6 +7045,7 @@ mod test {
                     )
                     .unwrap(),
                     authenticated: false,
+                    reputation: -15,
                 },
             ],
             inbound: vec![],
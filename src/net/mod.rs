@@ -57,6 +57,7 @@ use crate::clarity_vm::clarity::Error as clarity_error;
 use crate::core::mempool::*;
 use crate::core::POX_REWARD_CYCLE_LENGTH;
 use crate::net::atlas::{Attachment, AttachmentInstance};
+use crate::net::eventstream::EventStreamTopic;
 use crate::net::http::HttpReservedHeader;
 pub use crate::net::http::StacksBlockAcceptedData;
 use crate::util_lib::bloom::{BloomFilter, BloomNodeHasher};
@@ -83,6 +84,7 @@ use stacks_common::util::secp256k1::Secp256k1PublicKey;
 use stacks_common::util::secp256k1::MESSAGE_SIGNATURE_ENCODED_SIZE;
 
 use crate::chainstate::stacks::StacksBlockHeader;
+use crate::chainstate::stacks::MAX_BLOCK_LEN;
 
 use crate::codec::BURNCHAIN_HEADER_HASH_ENCODED_SIZE;
 use crate::cost_estimates::FeeRateEstimate;
@@ -114,12 +116,20 @@ pub mod chat;
 /// Implements serialization and deserialization for `StacksMessage` types.
 /// Also has functionality to sign, verify, and ensure well-formedness of messages.
 pub mod codec;
+/// Implements compact block relay: pushing blocks as short transaction IDs, and reconstructing
+/// them from the mempool.
+pub mod compact;
 pub mod connection;
 pub mod db;
 /// Implements `DNSResolver`, a simple DNS resolver state machine. Also implements `DNSClient`,
 /// which serves as an API for `DNSResolver`.  
 pub mod dns;
 pub mod download;
+/// Implements opt-in authenticated encryption of P2P message payloads, advertised in the
+/// handshake via `ServiceFlags::ENCRYPTION` and keyed by a `SessionKey` exchange.
+pub mod encryption;
+/// Implements the hub that the RPC server's server-sent event streams read chain events from.
+pub mod eventstream;
 pub mod http;
 pub mod inv;
 pub mod neighbors;
@@ -131,8 +141,18 @@ pub mod p2p;
 pub mod poll;
 pub mod prune;
 pub mod relay;
+/// Implements persistent peer reputation scores, which decide which peers get banned, pruned,
+/// walked and asked for blocks.
+pub mod reputation;
 pub mod rpc;
 pub mod server;
+/// Test-only simulator that runs `TestPeer`s over simulated links, with a virtual clock,
+/// configurable latency and loss, and partitions.
+#[cfg(test)]
+pub mod simulator;
+/// Implements a SOCKS5 client, through which outbound p2p, HTTP and burnchain connections can be
+/// made.
+pub mod socks;
 
 #[derive(Debug)]
 pub enum Error {
@@ -242,6 +262,8 @@ pub enum Error {
     Transient(String),
     /// Expected end-of-stream, but had more data
     ExpectedEndOfStream,
+    /// SOCKS5 proxy refused or failed to connect us
+    ProxyError(String),
 }
 
 impl From<codec_error> for Error {
@@ -340,6 +362,7 @@ impl fmt::Display for Error {
             Error::NotFoundError => write!(f, "Requested data not found"),
             Error::Transient(ref s) => write!(f, "Transient network error: {}", s),
             Error::ExpectedEndOfStream => write!(f, "Expected end-of-stream"),
+            Error::ProxyError(ref s) => write!(f, "SOCKS5 proxy error: {}", s),
         }
     }
 }
@@ -400,6 +423,7 @@ impl error::Error for Error {
             Error::NotFoundError => None,
             Error::Transient(ref _s) => None,
             Error::ExpectedEndOfStream => None,
+            Error::ProxyError(ref _s) => None,
         }
     }
 }
@@ -635,6 +659,7 @@ pub enum HttpContentType {
     Bytes,
     Text,
     JSON,
+    EventStream,
 }
 
 impl fmt::Display for HttpContentType {
@@ -649,6 +674,7 @@ impl HttpContentType {
             HttpContentType::Bytes => "application/octet-stream",
             HttpContentType::Text => "text/plain",
             HttpContentType::JSON => "application/json",
+            HttpContentType::EventStream => "text/event-stream",
         }
     }
 }
@@ -664,6 +690,8 @@ impl FromStr for HttpContentType {
             Ok(HttpContentType::Text)
         } else if s == "application/json" {
             Ok(HttpContentType::JSON)
+        } else if s == "text/event-stream" {
+            Ok(HttpContentType::EventStream)
         } else {
             Err(codec_error::DeserializeError(
                 "Unsupported HTTP content type".to_string(),
@@ -764,6 +792,41 @@ pub struct MicroblocksData {
     pub microblocks: Vec<StacksMicroblock>,
 }
 
+/// A transaction sent along with a compact block, and its position in the block
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefilledTransaction {
+    pub index: u32,
+    pub tx: StacksTransaction,
+}
+
+/// Block pushed as its header and the short IDs of its transactions.
+/// The short IDs are `TxTag`s seeded with the block hash, and stand in for every transaction
+/// that isn't prefilled, in block order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactBlockData {
+    pub consensus_hash: ConsensusHash,
+    pub header: StacksBlockHeader,
+    pub short_ids: Vec<TxTag>,
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+
+/// Request for the transactions of a compact block that the requester could not find in its
+/// mempool.  The indexes are positions in the block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetCompactBlockTxsData {
+    pub consensus_hash: ConsensusHash,
+    pub block_hash: BlockHeaderHash,
+    pub indexes: Vec<u32>,
+}
+
+/// The transactions asked for by a GetCompactBlockTxs, in the order they were asked for
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactBlockTxsData {
+    pub consensus_hash: ConsensusHash,
+    pub block_hash: BlockHeaderHash,
+    pub txs: Vec<StacksTransaction>,
+}
+
 /// Block available hint
 #[derive(Debug, Clone, PartialEq)]
 pub struct BlocksAvailableData {
@@ -842,6 +905,8 @@ pub struct HandshakeData {
 pub enum ServiceFlags {
     RELAY = 0x01,
     RPC = 0x02,
+    ENCRYPTION = 0x04,
+    COMPACT_BLOCKS = 0x08,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -860,6 +925,7 @@ pub mod NackErrorCodes {
     pub const Throttled: u32 = 3;
     pub const InvalidPoxFork: u32 = 4;
     pub const InvalidMessage: u32 = 5;
+    pub const NoSuchBlock: u32 = 6;
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -879,6 +945,14 @@ pub struct NatPunchData {
     pub nonce: u32,
 }
 
+/// An ephemeral public key for deriving a conversation's payload encryption keys.  Sent (and
+/// signed with the node's long-lived key) only after both peers have handshaked and advertised
+/// `ServiceFlags::ENCRYPTION`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionKeyData {
+    pub public_key: StacksPublicKeyBuffer,
+}
+
 define_u8_enum!(MemPoolSyncDataID {
     BloomFilter = 0x01,
     TxTags = 0x02
@@ -918,6 +992,10 @@ pub enum StacksMessageType {
     Pong(PongData),
     NatPunchRequest(u32),
     NatPunchReply(NatPunchData),
+    CompactBlock(CompactBlockData),
+    GetCompactBlockTxs(GetCompactBlockTxsData),
+    CompactBlockTxs(CompactBlockTxsData),
+    SessionKey(SessionKeyData),
 }
 
 /// Peer address variants
@@ -1194,6 +1272,10 @@ pub struct HttpRequestMetadata {
     pub peer: PeerHost,
     pub keep_alive: bool,
     pub canonical_stacks_tip_height: Option<u64>,
+    /// API key the client identified itself with, for rate limiting
+    pub api_key: Option<String>,
+    /// whether the client can take a gzip-compressed response body
+    pub accept_gzip: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -1214,6 +1296,38 @@ pub struct MapEntryResponse {
     pub marf_proof: Option<String>,
 }
 
+/// One read request in a /v2/batch request.  `path` includes any query string, and `body` is
+/// the JSON body the request would have on its own, if any.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchRequestItem {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchRequestBody {
+    pub requests: Vec<BatchRequestItem>,
+}
+
+/// The response to one request in a /v2/batch request: its HTTP status code, and its JSON body
+/// (or its text body, as a JSON string)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchResponseItem {
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+/// Values of a batch of MARF keys, in the order they were requested (None if the key is absent),
+/// and the hex-encoded batch proof of inclusion or non-inclusion for each key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarfProofsResponse {
+    pub values: Vec<Option<String>>,
+    pub proof: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ContractSrcResponse {
     pub source: String,
@@ -1326,6 +1440,8 @@ impl HttpRequestMetadata {
             peer: PeerHost::from_host_port(host, port),
             keep_alive: true,
             canonical_stacks_tip_height,
+            api_key: None,
+            accept_gzip: false,
         }
     }
 
@@ -1338,17 +1454,27 @@ impl HttpRequestMetadata {
             peer: peer_host,
             keep_alive: true,
             canonical_stacks_tip_height,
+            api_key: None,
+            accept_gzip: false,
         }
     }
 
     pub fn from_preamble(preamble: &HttpRequestPreamble) -> HttpRequestMetadata {
         let mut canonical_stacks_tip_height = None;
+        let mut api_key = None;
+        let mut accept_gzip = false;
         for header in &preamble.headers {
-            if let Some(HttpReservedHeader::CanonicalStacksTipHeight(h)) =
-                HttpReservedHeader::try_from_str(&header.0, &header.1)
-            {
-                canonical_stacks_tip_height = Some(h);
-                break;
+            match HttpReservedHeader::try_from_str(&header.0, &header.1) {
+                Some(HttpReservedHeader::CanonicalStacksTipHeight(h)) => {
+                    canonical_stacks_tip_height = Some(h);
+                }
+                Some(HttpReservedHeader::ApiKey(key)) => {
+                    api_key = Some(key);
+                }
+                Some(HttpReservedHeader::AcceptEncoding(gzip)) => {
+                    accept_gzip = gzip;
+                }
+                _ => {}
             }
         }
         HttpRequestMetadata {
@@ -1356,6 +1482,8 @@ impl HttpRequestMetadata {
             peer: preamble.host.clone(),
             keep_alive: preamble.keep_alive,
             canonical_stacks_tip_height,
+            api_key,
+            accept_gzip,
         }
     }
 }
@@ -1366,6 +1494,11 @@ pub struct CallReadOnlyRequestBody {
     pub arguments: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct MarfProofsRequestBody {
+    pub keys: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct FeeRateEstimateRequestBody {
     #[serde(default)]
@@ -1383,10 +1516,17 @@ pub struct RPCNeighbor {
     pub port: u16,
     pub public_key_hash: Hash160,
     pub authenticated: bool,
+    #[serde(default)]
+    pub reputation: i64,
 }
 
 impl RPCNeighbor {
-    pub fn from_neighbor_key_and_pubkh(nk: NeighborKey, pkh: Hash160, auth: bool) -> RPCNeighbor {
+    pub fn from_neighbor_key_and_pubkh(
+        nk: NeighborKey,
+        pkh: Hash160,
+        auth: bool,
+        reputation: i64,
+    ) -> RPCNeighbor {
         RPCNeighbor {
             network_id: nk.network_id,
             peer_version: nk.peer_version,
@@ -1394,6 +1534,7 @@ impl RPCNeighbor {
             port: nk.port,
             public_key_hash: pkh,
             authenticated: auth,
+            reputation: reputation,
         }
     }
 }
@@ -1476,6 +1617,10 @@ pub enum HttpRequestType {
         TipRequest,
     ),
     MemPoolQuery(HttpRequestMetadata, MemPoolSyncData, Option<Txid>),
+    GetMarfProofs(HttpRequestMetadata, Vec<String>, TipRequest),
+    /// read requests to run against one chain tip, and reply to in one response
+    Batch(HttpRequestMetadata, Vec<HttpRequestType>, TipRequest),
+    GetEventStream(HttpRequestMetadata, EventStreamTopic),
     /// catch-all for any errors we should surface from parsing
     ClientError(HttpRequestMetadata, ClientError),
 }
@@ -1488,6 +1633,8 @@ pub struct HttpResponseMetadata {
     pub request_id: u32,
     pub content_length: Option<u32>,
     pub canonical_stacks_tip_height: Option<u64>,
+    /// compress the body with gzip (only done for chunk-encoded bodies)
+    pub gzip: bool,
 }
 
 impl HttpResponseMetadata {
@@ -1513,6 +1660,7 @@ impl HttpResponseMetadata {
             request_id: request_id,
             content_length: content_length,
             canonical_stacks_tip_height: canonical_stacks_tip_height,
+            gzip: false,
         }
     }
 
@@ -1535,6 +1683,7 @@ impl HttpResponseMetadata {
             request_id: preamble.request_id,
             content_length: preamble.content_length.clone(),
             canonical_stacks_tip_height: canonical_stacks_tip_height,
+            gzip: preamble.is_gzip(),
         }
     }
 
@@ -1545,6 +1694,7 @@ impl HttpResponseMetadata {
             request_id: HttpResponseMetadata::make_request_id(),
             content_length: Some(0),
             canonical_stacks_tip_height: None,
+            gzip: false,
         }
     }
 
@@ -1553,13 +1703,15 @@ impl HttpResponseMetadata {
         canonical_stacks_tip_height: Option<u64>,
     ) -> HttpResponseMetadata {
         let metadata = req.metadata();
-        HttpResponseMetadata::new(
+        let mut md = HttpResponseMetadata::new(
             metadata.version,
             HttpResponseMetadata::make_request_id(),
             None,
             metadata.keep_alive,
             canonical_stacks_tip_height,
-        )
+        );
+        md.gzip = req.wants_gzip();
+        md
     }
 }
 
@@ -1581,6 +1733,8 @@ pub enum HttpResponseType {
     TokenTransferCost(HttpResponseMetadata, u64),
     GetDataVar(HttpResponseMetadata, DataVarResponse),
     GetMapEntry(HttpResponseMetadata, MapEntryResponse),
+    GetMarfProofs(HttpResponseMetadata, MarfProofsResponse),
+    Batch(HttpResponseMetadata, Vec<BatchResponseItem>),
     CallReadOnlyFunction(HttpResponseMetadata, CallReadOnlyResponse),
     GetAccount(HttpResponseMetadata, AccountEntryResponse),
     GetContractABI(HttpResponseMetadata, ContractInterface),
@@ -1590,6 +1744,7 @@ pub enum HttpResponseType {
     GetAttachment(HttpResponseMetadata, GetAttachmentResponse),
     GetAttachmentsInv(HttpResponseMetadata, GetAttachmentsInvResponse),
     MemPoolTxStream(HttpResponseMetadata),
+    EventStream(HttpResponseMetadata),
     MemPoolTxs(HttpResponseMetadata, Option<Txid>, Vec<StacksTransaction>),
     OptionsPreflight(HttpResponseMetadata),
     TransactionFeeEstimation(HttpResponseMetadata, RPCFeeEstimateResponse),
@@ -1601,6 +1756,8 @@ pub enum HttpResponseType {
     Forbidden(HttpResponseMetadata, String),
     NotFound(HttpResponseMetadata, String),
     ServerError(HttpResponseMetadata, String),
+    /// the client is over its rate limit, and should retry after this many seconds
+    TooManyRequests(HttpResponseMetadata, u64, String),
     ServiceUnavailable(HttpResponseMetadata, String),
     Error(HttpResponseMetadata, u16, String),
 }
@@ -1633,6 +1790,10 @@ pub enum StacksMessageID {
     Pong = 16,
     NatPunchRequest = 17,
     NatPunchReply = 18,
+    CompactBlock = 19,
+    GetCompactBlockTxs = 20,
+    CompactBlockTxs = 21,
+    SessionKey = 22,
     // reserved
     Reserved = 255,
 }
@@ -1717,7 +1878,13 @@ pub trait ProtocolFamily {
 
 // these implement the ProtocolFamily trait
 #[derive(Debug, Clone, PartialEq)]
-pub struct StacksP2P {}
+pub struct StacksP2P {
+    /// Session keys, if payload encryption was negotiated after the handshake
+    session: Option<encryption::P2PSession>,
+    /// The last payload decrypted by `verify_payload_bytes()`, keyed by its wire preamble, so
+    /// `read_payload()` does not need to decrypt it again
+    opened: Option<(Preamble, Preamble, Vec<u8>)>,
+}
 
 // an array in our protocol can't exceed this many items
 pub const ARRAY_MAX_LEN: u32 = u32::MAX;
@@ -1743,6 +1910,10 @@ pub const GETPOXINV_MAX_BITLEN: u64 = 8;
 // message.
 pub const BLOCKS_PUSHED_MAX: u32 = 32;
 
+// maximum number of transactions in a compact block, or asked for from one.  A block can't be
+// bigger than MAX_BLOCK_LEN, and no transaction is smaller than this many bytes.
+pub const COMPACT_BLOCK_MAX_TXS: u32 = MAX_BLOCK_LEN / 64;
+
 impl_byte_array_message_codec!(PeerAddress, 16);
 impl_byte_array_message_codec!(Txid, 32);
 
@@ -1873,6 +2044,12 @@ pub const NUM_NEIGHBORS: usize = 32;
 // maximum number of unconfirmed microblocks can get streamed to us
 pub const MAX_MICROBLOCKS_UNCONFIRMED: usize = 1024;
 
+// maximum number of keys that can be proven in one MARF batch proof request
+pub const MAX_MARF_PROOF_KEYS: usize = 64;
+
+// maximum number of requests in one /v2/batch request
+pub const MAX_BATCH_REQUESTS: usize = 64;
+
 // maximum number of block headers we'll get streamed to us
 pub const MAX_HEADERS: usize = 2100;
 
@@ -2075,6 +2252,7 @@ pub mod test {
     use crate::chainstate::burn::*;
     use crate::chainstate::coordinator::tests::*;
     use crate::chainstate::coordinator::*;
+    use crate::chainstate::stacks::address::PoxAddress;
     use crate::chainstate::stacks::boot::*;
     use crate::chainstate::stacks::db::StacksChainState;
     use crate::chainstate::stacks::db::*;
@@ -2094,6 +2272,7 @@ pub mod test {
     use crate::net::poll::*;
     use crate::net::relay::*;
     use crate::net::rpc::RPCHandlerArgs;
+    use crate::net::simulator::MemNetwork;
     use crate::net::Error as net_error;
     use crate::util_lib::strings::*;
     use clarity::vm::costs::ExecutionCost;
@@ -2123,7 +2302,8 @@ pub mod test {
                 BlockstackOperationType::UserBurnSupport(ref op) => op.consensus_serialize(fd),
                 BlockstackOperationType::TransferStx(_)
                 | BlockstackOperationType::PreStx(_)
-                | BlockstackOperationType::StackStx(_) => Ok(()),
+                | BlockstackOperationType::StackStx(_)
+                | BlockstackOperationType::DelegateStx(_) => Ok(()),
             }
         }
 
@@ -2365,9 +2545,9 @@ pub mod test {
             &self,
             _burn_block: &BurnchainHeaderHash,
             _burn_block_height: u64,
-            _rewards: Vec<(StacksAddress, u64)>,
+            _rewards: Vec<(PoxAddress, u64)>,
             _burns: u64,
-            _reward_recipients: Vec<StacksAddress>,
+            _reward_recipients: Vec<PoxAddress>,
         ) {
             // pass
         }
@@ -2402,6 +2582,8 @@ pub mod test {
         pub spending_account: TestMiner,
         pub setup_code: String,
         pub epochs: Option<Vec<StacksEpoch>>,
+        /// if set, the peer talks over this simulated network instead of TCP sockets
+        pub mem_network: Option<MemNetwork>,
     }
 
     impl TestPeerConfig {
@@ -2448,6 +2630,7 @@ pub mod test {
                 spending_account: spending_account,
                 setup_code: "".into(),
                 epochs: None,
+                mem_network: None,
             }
         }
 
@@ -2782,7 +2965,16 @@ pub mod test {
                 epochs.clone(),
             );
 
-            peer_network.bind(&local_addr, &http_local_addr).unwrap();
+            match config.mem_network {
+                Some(ref mem_network) => peer_network
+                    .bind_with_transport(
+                        &local_addr,
+                        &http_local_addr,
+                        Box::new(mem_network.transport()),
+                    )
+                    .unwrap(),
+                None => peer_network.bind(&local_addr, &http_local_addr).unwrap(),
+            }
             let relayer = Relayer::from_p2p(&mut peer_network);
             let mempool = MemPoolDB::open_test(false, config.network_id, &chainstate_path).unwrap();
 
@@ -3482,9 +3674,9 @@ pub mod test {
                                 .recipients
                                 .into_iter()
                                 .map(|x| x.0)
-                                .collect::<Vec<StacksAddress>>();
+                                .collect::<Vec<PoxAddress>>();
                             if recipients.len() == 1 {
-                                recipients.push(StacksAddress::burn_address(false));
+                                recipients.push(PoxAddress::standard_burn_address(false));
                             }
                             recipients
                         }
@@ -32,6 +32,7 @@ use crate::net::connection::ReplyHandleP2P;
 use crate::net::db::LocalPeer;
 
 use crate::net::p2p::*;
+use crate::net::socks::is_hostname_peer_address;
 
 use crate::util_lib::db::DBConn;
 use crate::util_lib::db::DBTx;
@@ -561,8 +562,11 @@ impl NeighborWalk {
                                 // if the neighbor accidentally gave us a private IP address, then
                                 // just use the one we used to contact it.  This can happen if the
                                 // node is behind a load-balancer, or is doing port-forwarding,
-                                // etc.
-                                if neighbor_from_handshake.addr.addrbytes.is_in_private_range() {
+                                // etc.  Likewise, a node we reached by hostname through our SOCKS5
+                                // proxy (e.g. an onion service) can't know what address we know it by.
+                                if neighbor_from_handshake.addr.addrbytes.is_in_private_range()
+                                    || is_hostname_peer_address(&self.cur_neighbor.addr.addrbytes)
+                                {
                                     debug!("{:?}: outbound neighbor gave private IP address {:?}; assuming it meant {:?}", &self.local_peer, &neighbor_from_handshake.addr, &self.cur_neighbor.addr);
                                     neighbor_from_handshake.addr.addrbytes =
                                         self.cur_neighbor.addr.addrbytes.clone();
@@ -3232,6 +3236,132 @@ mod test {
         })
     }
 
+    #[test]
+    fn test_step_walk_1_neighbor_encrypted() {
+        with_timeout(600, || {
+            let mut peer_1_config = TestPeerConfig::from_port(32800);
+            let peer_2_config = TestPeerConfig::from_port(32802);
+
+            peer_1_config.add_neighbor(&peer_2_config.to_neighbor());
+
+            let mut peer_1 = TestPeer::new(peer_1_config);
+            let mut peer_2 = TestPeer::new(peer_2_config);
+
+            // both peers support payload encryption
+            for peer in [&mut peer_1, &mut peer_2] {
+                let services = peer.network.local_peer.services | (ServiceFlags::ENCRYPTION as u16);
+                let mut tx = peer.network.peerdb.tx_begin().unwrap();
+                PeerDB::set_local_services(&mut tx, services).unwrap();
+                tx.commit().unwrap();
+                peer.network.local_peer.services = services;
+            }
+
+            let mut walk_1_count = 0;
+            let mut walk_2_count = 0;
+            while walk_1_count < 20
+                || walk_2_count < 20
+                || peer_1
+                    .network
+                    .get_neighbor_stats(&peer_2.to_neighbor().addr)
+                    .is_none()
+            {
+                let _ = peer_1.step();
+                let _ = peer_2.step();
+
+                walk_1_count = peer_1.network.walk_total_step_count;
+                walk_2_count = peer_2.network.walk_total_step_count;
+
+                if let Some(ref w) = peer_1.network.walk {
+                    assert_eq!(w.result.broken_connections.len(), 0);
+                }
+            }
+
+            // peer 1's conversation with peer 2 is encrypted in both directions
+            let convo = peer_1
+                .network
+                .get_convo(&peer_2.to_neighbor().addr)
+                .unwrap();
+            assert!(convo.is_encrypted());
+            assert!(convo
+                .connection
+                .protocol
+                .get_session()
+                .unwrap()
+                .send_encrypted());
+
+            let stats_1 = peer_1
+                .network
+                .get_neighbor_stats(&peer_2.to_neighbor().addr)
+                .unwrap();
+            assert!(stats_1.last_handshake_time > 0);
+            assert_eq!(stats_1.msgs_err, 0);
+        })
+    }
+
+    #[test]
+    fn test_step_walk_1_neighbor_socks5_proxy() {
+        with_timeout(600, || {
+            let mut peer_1_config = TestPeerConfig::from_port(32840);
+            let peer_2_config = TestPeerConfig::from_port(32842);
+
+            // peer 1 only knows peer 2 by hostname, and must reach it through the proxy
+            let mut peer_2_neighbor = peer_2_config.to_neighbor();
+            peer_2_neighbor.addr.addrbytes = socks::hostname_peer_address("localhost");
+            peer_1_config.add_neighbor(&peer_2_neighbor);
+
+            peer_1_config.connection_opts.socks5_proxy = Some(socks::Socks5Proxy {
+                addr: socks::test::spawn_test_socks5_proxy(false),
+                auth: None,
+            });
+            peer_1_config.connection_opts.socks5_hostnames.insert(
+                peer_2_neighbor.addr.addrbytes.clone(),
+                "localhost".to_string(),
+            );
+
+            let mut peer_1 = TestPeer::new(peer_1_config);
+            let mut peer_2 = TestPeer::new(peer_2_config);
+
+            let mut walk_1_count = 0;
+            let mut walk_2_count = 0;
+            while walk_1_count < 20
+                || walk_2_count < 20
+                || peer_1
+                    .network
+                    .get_neighbor_stats(&peer_2_neighbor.addr)
+                    .is_none()
+            {
+                let _ = peer_1.step();
+                let _ = peer_2.step();
+
+                walk_1_count = peer_1.network.walk_total_step_count;
+                walk_2_count = peer_2.network.walk_total_step_count;
+
+                if let Some(ref w) = peer_1.network.walk {
+                    assert_eq!(w.result.broken_connections.len(), 0);
+                }
+            }
+
+            // peer 1 talked to peer 2 under its placeholder address
+            let stats_1 = peer_1
+                .network
+                .get_neighbor_stats(&peer_2_neighbor.addr)
+                .unwrap();
+            assert!(stats_1.last_handshake_time > 0);
+            assert_eq!(stats_1.msgs_err, 0);
+
+            let neighbor_2 = PeerDB::get_peer(
+                peer_1.network.peerdb.conn(),
+                peer_2_neighbor.addr.network_id,
+                &peer_2_neighbor.addr.addrbytes,
+                peer_2_neighbor.addr.port,
+            )
+            .unwrap()
+            .unwrap();
+            assert!(neighbor_2.last_contact_time > 0);
+            assert!(peer_1.network.proxy_handshakes.is_empty());
+        })
+    }
+
     #[test]
     #[ignore]
     fn test_step_walk_1_neighbor_plain_no_natpunch() {
@@ -47,28 +47,41 @@ use crate::chainstate::burn::db::sortdb::{BlockHeaderCache, SortitionDB};
 use crate::chainstate::burn::BlockSnapshot;
 use crate::chainstate::stacks::db::StacksChainState;
 use crate::chainstate::stacks::{MAX_BLOCK_LEN, MAX_TRANSACTION_LEN};
-use crate::monitoring::{update_inbound_neighbors, update_outbound_neighbors};
+use crate::codec::PREAMBLE_ENCODED_SIZE;
+use crate::monitoring::{
+    increment_bandwidth_deferrals, remove_peer_bandwidth, update_inbound_neighbors,
+    update_outbound_neighbors, update_p2p_message_bandwidth, update_peer_bandwidth,
+};
 use crate::net::asn::ASEntry4;
 use crate::net::atlas::AtlasDB;
 use crate::net::atlas::{AttachmentInstance, AttachmentsDownloader};
 use crate::net::chat::ConversationP2P;
 use crate::net::chat::NeighborStats;
+use crate::net::compact::{make_compact_block_payload, PendingCompactBlock};
 use crate::net::connection::ConnectionOptions;
 use crate::net::connection::NetworkReplyHandle;
 use crate::net::connection::ReplyHandleP2P;
 use crate::net::db::LocalPeer;
 use crate::net::db::PeerDB;
 use crate::net::download::BlockDownloader;
+use crate::net::eventstream::EventStreamHub;
 use crate::net::inv::*;
 use crate::net::neighbors::*;
+use crate::net::poll::NetSocket;
 use crate::net::poll::NetworkPollState;
 use crate::net::poll::NetworkState;
+use crate::net::poll::NetworkTransport;
 use crate::net::prune::*;
 use crate::net::relay::RelayerStats;
 use crate::net::relay::*;
 use crate::net::relay::*;
+use crate::net::reputation::{
+    PeerReputation, ReputationEvent, REPUTATION_BAN_THRESHOLD, REPUTATION_DECAY_INTERVAL,
+};
 use crate::net::rpc::RPCHandlerArgs;
+use crate::net::rpc::RPCRateLimiter;
 use crate::net::server::*;
+use crate::net::socks::Socks5Handshake;
 use crate::net::Error as net_error;
 use crate::net::Neighbor;
 use crate::net::NeighborKey;
@@ -225,6 +238,351 @@ pub enum MempoolSyncState {
 
 pub type PeerMap = HashMap<usize, ConversationP2P>;
 
+/// How many seconds of traffic the bandwidth manager looks at when checking a budget
+pub const BANDWIDTH_WINDOW_SECS: u64 = 10;
+
+/// How long a peer can go without sending or receiving anything before the bandwidth manager
+/// forgets about it
+pub const BANDWIDTH_PEER_IDLE_SECS: u64 = 3600;
+
+/// How many blocks behind our Stacks tip a peer can say it is before we treat it as catching up
+/// (i.e. in initial block download) when serving it blocks
+pub const BANDWIDTH_IBD_HEIGHT_LAG: u64 = 6;
+
+/// How many blocks, microblocks and transactions we hold back from each peer while we're out of
+/// download bandwidth.  Any more get dropped; the peer's inventories will tell us about them again.
+pub const MAX_DEFERRED_MESSAGES_PER_PEER: usize = 16;
+
+/// Bytes a conversation has moved since the bandwidth manager last collected them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BandwidthSample {
+    /// bytes written to and read from the socket
+    pub bytes_tx: u64,
+    pub bytes_rx: u64,
+    /// encoded sizes of the P2P messages sent and received, by message type
+    pub msg_bytes_tx: HashMap<StacksMessageID, u64>,
+    pub msg_bytes_rx: HashMap<StacksMessageID, u64>,
+}
+
+impl BandwidthSample {
+    fn message_size(msg: &StacksMessage) -> u64 {
+        (PREAMBLE_ENCODED_SIZE as u64) + (msg.preamble.payload_len as u64)
+    }
+
+    pub fn add_message_tx(&mut self, msg: &StacksMessage) -> () {
+        *self
+            .msg_bytes_tx
+            .entry(msg.payload.get_message_id())
+            .or_insert(0) += BandwidthSample::message_size(msg);
+    }
+
+    pub fn add_message_rx(&mut self, msg: &StacksMessage) -> () {
+        *self
+            .msg_bytes_rx
+            .entry(msg.payload.get_message_id())
+            .or_insert(0) += BandwidthSample::message_size(msg);
+    }
+}
+
+/// Bytes moved over the last `BANDWIDTH_WINDOW_SECS` seconds, in one-second buckets
+#[derive(Debug, Clone, Default)]
+struct BandwidthWindow {
+    buckets: VecDeque<(u64, u64)>, // (timestamp, num bytes)
+}
+
+impl BandwidthWindow {
+    fn add(&mut self, now: u64, num_bytes: u64) -> () {
+        if num_bytes == 0 {
+            return;
+        }
+        match self.buckets.back_mut() {
+            Some((timestamp, bucket_bytes)) if *timestamp == now => {
+                *bucket_bytes += num_bytes;
+            }
+            _ => {
+                self.buckets.push_back((now, num_bytes));
+            }
+        }
+        self.expire(now);
+    }
+
+    fn expire(&mut self, now: u64) -> () {
+        while let Some((timestamp, _)) = self.buckets.front() {
+            if *timestamp + BANDWIDTH_WINDOW_SECS > now {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    fn total(&self, now: u64) -> u64 {
+        self.buckets
+            .iter()
+            .filter(|(timestamp, _)| *timestamp + BANDWIDTH_WINDOW_SECS > now)
+            .map(|(_, num_bytes)| *num_bytes)
+            .sum()
+    }
+
+    /// Average bytes/sec over the window
+    fn rate(&self, now: u64) -> u64 {
+        self.total(now) / BANDWIDTH_WINDOW_SECS
+    }
+
+    /// Is this window's usage under a budget of `limit` bytes/sec?  0 means no limit.
+    fn within_budget(&self, limit: u64, now: u64) -> bool {
+        limit == 0 || self.total(now) < limit.saturating_mul(BANDWIDTH_WINDOW_SECS)
+    }
+}
+
+/// Lifetime traffic to and from one peer IP address
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerBandwidth {
+    pub bytes_tx: u64,
+    pub bytes_rx: u64,
+    pub last_active: u64,
+}
+
+/// Tracks bytes sent and received per peer and per P2P message type, and enforces global and
+/// per-peer upload and download budgets.  Peers are identified by IP address, so a neighbor's
+/// P2P traffic and HTTP traffic (i.e. blocks fetched from its data URL) share one budget.
+///
+/// Traffic needed to keep the node in the network (handshakes, pings, inventories, neighbor
+/// walks) is always sent and received, but counts towards the budgets.  When an upload budget is
+/// used up, block and microblock serving over HTTP to peers that are catching up (see
+/// `BANDWIDTH_IBD_HEIGHT_LAG`) is deferred, and block, microblock and transaction pushes are
+/// skipped (the peers can still fetch them once they see our inventories).  When a download
+/// budget is used up, we stop reading the block downloads we started from the affected peers.
+/// The blocks, microblocks and transactions they push to us have already been read off the socket
+/// by the time we see them, so holding them back saves no bandwidth; it only postpones the work
+/// of processing and relaying them until the budget frees up.
+/// Peers we held traffic back from recently are not penalized for slow responses.
+#[derive(Debug, Clone)]
+pub struct BandwidthManager {
+    max_upload: u64,
+    max_download: u64,
+    max_peer_upload: u64,
+    max_peer_download: u64,
+
+    upload: BandwidthWindow,
+    download: BandwidthWindow,
+    peer_upload: HashMap<PeerAddress, BandwidthWindow>,
+    peer_download: HashMap<PeerAddress, BandwidthWindow>,
+    peer_totals: HashMap<PeerAddress, PeerBandwidth>,
+
+    msg_bytes_tx: HashMap<StacksMessageID, u64>,
+    msg_bytes_rx: HashMap<StacksMessageID, u64>,
+
+    /// events whose reads or requests are waiting for bandwidth
+    deferred_events: HashSet<usize>,
+    /// peers we last held traffic back from, and when
+    throttled: HashMap<PeerAddress, u64>,
+    pub num_deferred_uploads: u64,
+    pub num_deferred_downloads: u64,
+}
+
+impl BandwidthManager {
+    pub fn new(opts: &ConnectionOptions) -> BandwidthManager {
+        BandwidthManager {
+            max_upload: opts.max_upload_bandwidth,
+            max_download: opts.max_download_bandwidth,
+            max_peer_upload: opts.max_peer_upload_bandwidth,
+            max_peer_download: opts.max_peer_download_bandwidth,
+            upload: BandwidthWindow::default(),
+            download: BandwidthWindow::default(),
+            peer_upload: HashMap::new(),
+            peer_download: HashMap::new(),
+            peer_totals: HashMap::new(),
+            msg_bytes_tx: HashMap::new(),
+            msg_bytes_rx: HashMap::new(),
+            deferred_events: HashSet::new(),
+            throttled: HashMap::new(),
+            num_deferred_uploads: 0,
+            num_deferred_downloads: 0,
+        }
+    }
+
+    /// Is this a message we can hold back when we're out of upload or download bandwidth?
+    pub fn is_deferrable(payload: &StacksMessageType) -> bool {
+        match payload {
+            StacksMessageType::Blocks(..)
+            | StacksMessageType::Microblocks(..)
+            | StacksMessageType::Transaction(..)
+            | StacksMessageType::CompactBlock(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Account for traffic to and from a peer
+    pub fn record(&mut self, addr: &PeerAddress, sample: BandwidthSample) -> () {
+        let now = get_epoch_time_secs();
+        if sample.bytes_tx > 0 || sample.bytes_rx > 0 {
+            self.upload.add(now, sample.bytes_tx);
+            self.download.add(now, sample.bytes_rx);
+            self.peer_upload
+                .entry(*addr)
+                .or_default()
+                .add(now, sample.bytes_tx);
+            self.peer_download
+                .entry(*addr)
+                .or_default()
+                .add(now, sample.bytes_rx);
+
+            let totals = self.peer_totals.entry(*addr).or_default();
+            totals.bytes_tx += sample.bytes_tx;
+            totals.bytes_rx += sample.bytes_rx;
+            totals.last_active = now;
+        }
+
+        for (msg_id, num_bytes) in sample.msg_bytes_tx.into_iter() {
+            *self.msg_bytes_tx.entry(msg_id).or_insert(0) += num_bytes;
+            update_p2p_message_bandwidth(&format!("{:?}", msg_id), "outbound", num_bytes);
+        }
+        for (msg_id, num_bytes) in sample.msg_bytes_rx.into_iter() {
+            *self.msg_bytes_rx.entry(msg_id).or_insert(0) += num_bytes;
+            update_p2p_message_bandwidth(&format!("{:?}", msg_id), "inbound", num_bytes);
+        }
+    }
+
+    /// Can we send deferrable traffic to this peer?
+    pub fn can_upload(&self, addr: &PeerAddress) -> bool {
+        let now = get_epoch_time_secs();
+        self.upload.within_budget(self.max_upload, now)
+            && self
+                .peer_upload
+                .get(addr)
+                .map(|window| window.within_budget(self.max_peer_upload, now))
+                .unwrap_or(true)
+    }
+
+    /// Can we read from this peer?
+    pub fn can_download(&self, addr: &PeerAddress) -> bool {
+        let now = get_epoch_time_secs();
+        self.download.within_budget(self.max_download, now)
+            && self
+                .peer_download
+                .get(addr)
+                .map(|window| window.within_budget(self.max_peer_download, now))
+                .unwrap_or(true)
+    }
+
+    /// Note that we held back traffic to a peer.  If `event_id` is given, then that event has work
+    /// waiting on bandwidth, and will be retried on a later pass.
+    pub fn defer_upload(&mut self, addr: &PeerAddress, event_id: Option<usize>) -> () {
+        self.num_deferred_uploads += 1;
+        increment_bandwidth_deferrals("outbound");
+        self.throttled.insert(*addr, get_epoch_time_secs());
+        if let Some(event_id) = event_id {
+            self.deferred_events.insert(event_id);
+        }
+    }
+
+    /// Note that we held back traffic from a peer.  Its event will be retried on a later pass.
+    pub fn defer_download(&mut self, addr: &PeerAddress, event_id: usize) -> () {
+        self.num_deferred_downloads += 1;
+        increment_bandwidth_deferrals("inbound");
+        self.throttled.insert(*addr, get_epoch_time_secs());
+        self.deferred_events.insert(event_id);
+    }
+
+    /// Did we hold back traffic to or from this peer recently enough that it may be why the peer
+    /// seems slow?
+    pub fn is_throttled(&self, addr: &PeerAddress) -> bool {
+        self.throttled
+            .get(addr)
+            .map(|last_deferral| *last_deferral + BANDWIDTH_WINDOW_SECS > get_epoch_time_secs())
+            .unwrap_or(false)
+    }
+
+    /// Get the deferred events that belong to one of the given conversations, so they can be
+    /// processed again.  Sockets are edge-triggered, so they won't come up as ready on their own.
+    pub fn take_deferred_events<T>(&mut self, convos: &HashMap<usize, T>) -> Vec<usize> {
+        let mut taken = vec![];
+        self.deferred_events.retain(|event_id| {
+            if convos.contains_key(event_id) {
+                taken.push(*event_id);
+                false
+            } else {
+                true
+            }
+        });
+        taken
+    }
+
+    /// Lifetime traffic to and from this peer
+    pub fn get_peer_bandwidth(&self, addr: &PeerAddress) -> Option<PeerBandwidth> {
+        self.peer_totals.get(addr).cloned()
+    }
+
+    /// Current (upload, download) rate to and from this peer, in bytes/sec
+    pub fn get_peer_rates(&self, addr: &PeerAddress) -> (u64, u64) {
+        let now = get_epoch_time_secs();
+        (
+            self.peer_upload
+                .get(addr)
+                .map(|window| window.rate(now))
+                .unwrap_or(0),
+            self.peer_download
+                .get(addr)
+                .map(|window| window.rate(now))
+                .unwrap_or(0),
+        )
+    }
+
+    /// Current total (upload, download) rate, in bytes/sec
+    pub fn get_rates(&self) -> (u64, u64) {
+        let now = get_epoch_time_secs();
+        (self.upload.rate(now), self.download.rate(now))
+    }
+
+    /// Lifetime (sent, received) bytes of this P2P message type
+    pub fn get_message_bandwidth(&self, msg_id: StacksMessageID) -> (u64, u64) {
+        (
+            *self.msg_bytes_tx.get(&msg_id).unwrap_or(&0),
+            *self.msg_bytes_rx.get(&msg_id).unwrap_or(&0),
+        )
+    }
+
+    /// Drop idle peers, and report each active peer's rates
+    pub fn prune(&mut self) -> () {
+        let now = get_epoch_time_secs();
+        self.upload.expire(now);
+        self.download.expire(now);
+
+        self.throttled
+            .retain(|_, last_deferral| *last_deferral + BANDWIDTH_WINDOW_SECS > now);
+
+        let mut idle = vec![];
+        for (addr, totals) in self.peer_totals.iter() {
+            if totals.last_active + BANDWIDTH_PEER_IDLE_SECS <= now {
+                idle.push(*addr);
+            }
+        }
+        for addr in idle.into_iter() {
+            self.peer_totals.remove(&addr);
+            self.peer_upload.remove(&addr);
+            self.peer_download.remove(&addr);
+            remove_peer_bandwidth(&format!("{}", addr.to_socketaddr(0).ip()));
+        }
+
+        for (addr, window) in self.peer_upload.iter_mut() {
+            window.expire(now);
+            update_peer_bandwidth(
+                &format!("{}", addr.to_socketaddr(0).ip()),
+                "outbound",
+                window.rate(now),
+            );
+        }
+        for (addr, window) in self.peer_download.iter_mut() {
+            window.expire(now);
+            update_peer_bandwidth(
+                &format!("{}", addr.to_socketaddr(0).ip()),
+                "inbound",
+                window.rate(now),
+            );
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PeerNetwork {
     // constants
@@ -246,10 +604,13 @@ pub struct PeerNetwork {
 
     // ongoing p2p conversations (either they reached out to us, or we to them)
     pub peers: PeerMap,
-    pub sockets: HashMap<usize, mio_net::TcpStream>,
+    pub sockets: HashMap<usize, NetSocket>,
     pub events: HashMap<NeighborKey, usize>,
-    pub connecting: HashMap<usize, (mio_net::TcpStream, bool, u64)>, // (socket, outbound?, connection sent timestamp)
+    pub connecting: HashMap<usize, (NetSocket, bool, u64)>, // (socket, outbound?, connection sent timestamp)
+    pub proxy_handshakes: HashMap<usize, (SocketAddr, Socks5Handshake)>, // (peer address, SOCKS5 handshake) for connecting sockets that go through the proxy
     pub bans: HashSet<usize>,
+    pub last_reputation_refresh: u64, // last time we re-read our peers' (decaying) reputations
+    pub pending_compact_blocks: HashMap<StacksBlockId, PendingCompactBlock>, // compact blocks we're reconstructing
 
     // ongoing messages the network is sending via the p2p interface (not bound to a specific
     // conversation).
@@ -319,6 +680,17 @@ pub struct PeerNetwork {
     // http endpoint, used for driving HTTP conversations (some of which we initiate)
     pub http: Option<HttpPeer>,
 
+    // chain events published by the node, for the RPC server's `/v2/events/...` streams
+    pub event_streams: Option<EventStreamHub>,
+
+    // per-client rate limits for the RPC server
+    pub rpc_rate_limiter: RPCRateLimiter,
+
+    // per-peer and per-message bandwidth accounting, and upload/download budgets
+    pub bandwidth: BandwidthManager,
+    // blocks, microblocks and transactions held back for lack of download bandwidth, by event
+    deferred_messages: HashMap<usize, Vec<StacksMessage>>,
+
     // our own neighbor address that we bind on
     bind_nk: NeighborKey,
 
@@ -368,6 +740,8 @@ impl PeerNetwork {
         epochs: Vec<StacksEpoch>,
     ) -> PeerNetwork {
         let http = HttpPeer::new(connection_opts.clone(), 0);
+        let rpc_rate_limiter = RPCRateLimiter::new(&connection_opts);
+        let bandwidth = BandwidthManager::new(&connection_opts);
         let pub_ip = connection_opts.public_ip_address.clone();
         let pub_ip_learned = pub_ip.is_none();
         local_peer.public_ip_address = pub_ip.clone();
@@ -404,7 +778,10 @@ impl PeerNetwork {
             sockets: HashMap::new(),
             events: HashMap::new(),
             connecting: HashMap::new(),
+            proxy_handshakes: HashMap::new(),
             bans: HashSet::new(),
+            last_reputation_refresh: 0,
+            pending_compact_blocks: HashMap::new(),
 
             relay_handles: HashMap::new(),
             relayer_stats: RelayerStats::new(),
@@ -448,6 +825,10 @@ impl PeerNetwork {
             prune_inbound_counts: HashMap::new(),
 
             http: Some(http),
+            event_streams: None,
+            rpc_rate_limiter,
+            bandwidth,
+            deferred_messages: HashMap::new(),
             bind_nk: NeighborKey {
                 network_id: 0,
                 peer_version: 0,
@@ -511,8 +892,27 @@ impl PeerNetwork {
 
     /// start serving.
     pub fn bind(&mut self, my_addr: &SocketAddr, http_addr: &SocketAddr) -> Result<(), net_error> {
-        let mut net = NetworkState::new(self.connection_opts.max_sockets)?;
+        let net = NetworkState::new(self.connection_opts.max_sockets)?;
+        self.bind_network_state(net, my_addr, http_addr)
+    }
+
+    /// start serving, on sockets from `transport` instead of TCP sockets.
+    pub fn bind_with_transport(
+        &mut self,
+        my_addr: &SocketAddr,
+        http_addr: &SocketAddr,
+        transport: Box<dyn NetworkTransport>,
+    ) -> Result<(), net_error> {
+        let net = NetworkState::with_transport(self.connection_opts.max_sockets, transport);
+        self.bind_network_state(net, my_addr, http_addr)
+    }
 
+    fn bind_network_state(
+        &mut self,
+        mut net: NetworkState,
+        my_addr: &SocketAddr,
+        http_addr: &SocketAddr,
+    ) -> Result<(), net_error> {
         let p2p_handle = net.bind(my_addr)?;
         let http_handle = net.bind(http_addr)?;
 
@@ -590,7 +990,7 @@ impl PeerNetwork {
     /// Return (number of bytes sent, whether or not there's more to send)
     fn do_saturate_p2p_socket(
         convo: &mut ConversationP2P,
-        client_sock: &mut mio::net::TcpStream,
+        client_sock: &mut NetSocket,
         handle: &mut ReplyHandleP2P,
     ) -> Result<(usize, bool), net_error> {
         let mut total_sent = 0;
@@ -700,13 +1100,36 @@ impl PeerNetwork {
             *(event_id_opt.unwrap())
         };
 
+        self.relay_signed_message_to_event(event_id, message)
+    }
+
+    /// Relay a signed message to a peer, given its conversation's event ID.
+    /// The peer network will take care of sending the data; no need to deal with a reply handle.
+    /// Called from _within_ the p2p thread.
+    pub fn relay_signed_message_to_event(
+        &mut self,
+        event_id: usize,
+        message: StacksMessage,
+    ) -> Result<(), net_error> {
         let convo_opt = self.peers.get_mut(&event_id);
         if convo_opt.is_none() {
-            info!("No ongoing conversation with {:?}", &neighbor_key);
+            info!("No ongoing conversation on event {}", event_id);
             return Err(net_error::PeerNotConnected);
         }
 
         let convo = convo_opt.unwrap();
+        if BandwidthManager::is_deferrable(&message.payload)
+            && !self.bandwidth.can_upload(&convo.peer_addrbytes)
+        {
+            debug!(
+                "{:?}: Out of upload bandwidth; will not push {} to {:?}",
+                &self.local_peer,
+                message.payload.get_message_description(),
+                &convo
+            );
+            self.bandwidth.defer_upload(&convo.peer_addrbytes, None);
+            return Ok(());
+        }
         let mut reply_handle = convo.relay_signed_message(message)?;
 
         let (num_sent, flushed) = self.saturate_p2p_socket(event_id, &mut reply_handle)?;
@@ -755,12 +1178,32 @@ impl PeerNetwork {
                         );
                         continue;
                     }
+                    if BandwidthManager::is_deferrable(&message_payload)
+                        && !self.bandwidth.can_upload(&convo.peer_addrbytes)
+                    {
+                        debug!(
+                            "{:?}: Out of upload bandwidth; will not broadcast '{}' to {:?}",
+                            &self.local_peer,
+                            message_payload.get_message_description(),
+                            &nk
+                        );
+                        self.bandwidth.defer_upload(&convo.peer_addrbytes, None);
+                        continue;
+                    }
+
+                    // push blocks as compact blocks if we can
+                    let payload = make_compact_block_payload(
+                        self.local_peer.services,
+                        convo.peer_services,
+                        &message_payload,
+                    )
+                    .unwrap_or_else(|| message_payload.clone());
 
                     match convo.sign_and_forward(
                         &self.local_peer,
                         &self.chain_view,
                         relay_hints.clone(),
-                        message_payload.clone(),
+                        payload,
                     ) {
                         Ok(rh) => {
                             debug!(
@@ -814,10 +1257,7 @@ impl PeerNetwork {
     }
 
     /// Count how many connections to a given IP address we have
-    pub fn count_ip_connections(
-        ipaddr: &SocketAddr,
-        sockets: &HashMap<usize, mio_net::TcpStream>,
-    ) -> u64 {
+    pub fn count_ip_connections(ipaddr: &SocketAddr, sockets: &HashMap<usize, NetSocket>) -> u64 {
         let mut ret = 0;
         for (_, socket) in sockets.iter() {
             match socket.peer_addr() {
@@ -889,13 +1329,24 @@ impl PeerNetwork {
                 return Err(net_error::NotConnected);
             }
             Some(ref mut network) => {
-                let sock = NetworkState::connect(&neighbor.addrbytes.to_socketaddr(neighbor.port))?;
+                let (sock, proxy_handshake) = network.connect_proxied(
+                    &neighbor.addrbytes,
+                    neighbor.port,
+                    self.connection_opts.socks5_proxy.as_ref(),
+                    &self.connection_opts.socks5_hostnames,
+                )?;
                 let hint_event_id = network.next_event_id()?;
                 let registered_event_id =
                     network.register(self.p2p_network_handle, hint_event_id, &sock)?;
 
                 self.connecting
                     .insert(registered_event_id, (sock, true, get_epoch_time_secs()));
+                if let Some(handshake) = proxy_handshake {
+                    self.proxy_handshakes.insert(
+                        registered_event_id,
+                        (neighbor.addrbytes.to_socketaddr(neighbor.port), handshake),
+                    );
+                }
                 registered_event_id
             }
         };
@@ -1033,7 +1484,8 @@ impl PeerNetwork {
                     match self.events.get(neighbor_key) {
                         Some(event_id) => {
                             debug!("Will ban {:?} (event {})", neighbor_key, event_id);
-                            self.bans.insert(*event_id);
+                            let event_id = *event_id;
+                            self.add_reputation_event(event_id, ReputationEvent::InvalidBlock);
                         }
                         None => {}
                     }
@@ -1153,6 +1605,131 @@ impl PeerNetwork {
         }
     }
 
+    /// Record something a peer did that affects its reputation.
+    /// It will be written to the peer DB in the next call to process_reputations(), but if it
+    /// makes the peer's reputation bad enough, the peer will be banned right away.
+    pub fn add_reputation_event(&mut self, event_id: usize, event: ReputationEvent) -> () {
+        if let Some(convo) = self.peers.get_mut(&event_id) {
+            debug!(
+                "{:?}: Reputation event for {:?}: {:?}",
+                &self.local_peer,
+                &convo.to_neighbor_key(),
+                &event
+            );
+            convo.stats.add_reputation_event(event);
+            if convo.stats.get_reputation() <= REPUTATION_BAN_THRESHOLD {
+                debug!(
+                    "{:?}: Will ban {:?} (event {}) -- reputation is {}",
+                    &self.local_peer,
+                    &convo.to_neighbor_key(),
+                    event_id,
+                    convo.stats.get_reputation()
+                );
+                self.bans.insert(event_id);
+            }
+        }
+    }
+
+    /// Get the reputation of a peer we're connected to.  Peers we're not connected to (or whose
+    /// reputation we haven't loaded yet) have a neutral reputation of 0.
+    pub fn get_neighbor_reputation(&self, neighbor_key: &NeighborKey) -> i64 {
+        self.events
+            .get(neighbor_key)
+            .and_then(|event_id| self.peers.get(event_id))
+            .map(|convo| convo.stats.get_reputation())
+            .unwrap_or(0)
+    }
+
+    /// Write a single peer's pending reputation changes to the peer DB.
+    /// Only authenticated peers have a reputation -- until then, we don't know their p2p port.
+    fn flush_peer_reputation(&mut self, event_id: usize) -> Result<(), net_error> {
+        let convo = match self.peers.get_mut(&event_id) {
+            Some(convo) => convo,
+            None => {
+                return Ok(());
+            }
+        };
+        if !convo.is_authenticated() || convo.stats.reputation_delta == 0 {
+            return Ok(());
+        }
+
+        let nk = convo.to_neighbor_key();
+        let now = get_epoch_time_secs();
+        let mut tx = self.peerdb.tx_begin()?;
+        let reputation = PeerDB::update_peer_reputation(
+            &mut tx,
+            nk.network_id,
+            &nk.addrbytes,
+            nk.port,
+            convo.stats.reputation_delta,
+            now,
+        )?;
+        tx.commit()?;
+
+        convo.stats.reputation = Some(reputation.score_at(now));
+        convo.stats.reputation_delta = 0;
+        Ok(())
+    }
+
+    /// Write our peers' pending reputation changes to the peer DB, and refresh their decayed
+    /// scores every so often.  Peers whose reputation is bad enough get queued up to be banned.
+    fn process_reputations(&mut self) -> Result<(), net_error> {
+        let now = get_epoch_time_secs();
+        let refresh = self.last_reputation_refresh + REPUTATION_DECAY_INTERVAL <= now;
+        let needs_update = |convo: &ConversationP2P| {
+            convo.is_authenticated()
+                && (refresh
+                    || convo.stats.reputation_delta != 0
+                    || convo.stats.reputation.is_none())
+        };
+        if !self.peers.values().any(needs_update) {
+            // nothing to write
+            return Ok(());
+        }
+
+        let mut tx = self.peerdb.tx_begin()?;
+        for (event_id, convo) in self.peers.iter_mut() {
+            if !needs_update(convo) {
+                continue;
+            }
+
+            let nk = convo.to_neighbor_key();
+            let reputation = if convo.stats.reputation_delta != 0 {
+                PeerDB::update_peer_reputation(
+                    &mut tx,
+                    nk.network_id,
+                    &nk.addrbytes,
+                    nk.port,
+                    convo.stats.reputation_delta,
+                    now,
+                )?
+            } else {
+                PeerDB::get_peer_reputation(&tx, nk.network_id, &nk.addrbytes, nk.port)?
+                    .unwrap_or(PeerReputation::new(now))
+            };
+
+            convo.stats.reputation = Some(reputation.score_at(now));
+            convo.stats.reputation_delta = 0;
+
+            if reputation.is_banned(now) {
+                debug!(
+                    "{:?}: Will ban {:?} (event {}) -- reputation is {}",
+                    &self.local_peer,
+                    &nk,
+                    event_id,
+                    reputation.score_at(now)
+                );
+                self.bans.insert(*event_id);
+            }
+        }
+        tx.commit()?;
+
+        if refresh {
+            self.last_reputation_refresh = now;
+        }
+        Ok(())
+    }
+
     /// Process ban requests.  Update the deny in the peer database.  Return the vec of event IDs to disconnect from.
     fn process_bans(&mut self) -> Result<Vec<usize>, net_error> {
         if cfg!(test) && self.connection_opts.disable_network_bans {
@@ -1391,13 +1968,15 @@ impl PeerNetwork {
     /// connection events).  If this method fails for some reason, it'll de-register the socket
     /// from the poller.
     /// outbound is true if we are the peer that started the connection (otherwise it's false)
+    /// proxied_addr is the remote peer's address, if the socket is connected to it via a proxy
     fn register_peer(
         &mut self,
         event_id: usize,
-        socket: mio_net::TcpStream,
+        socket: NetSocket,
         outbound: bool,
+        proxied_addr: Option<SocketAddr>,
     ) -> Result<(), net_error> {
-        let client_addr = match socket.peer_addr() {
+        let client_addr = match proxied_addr.map(Ok).unwrap_or_else(|| socket.peer_addr()) {
             Ok(addr) => addr,
             Err(e) => {
                 debug!(
@@ -1502,7 +2081,7 @@ impl PeerNetwork {
     }
 
     /// Deregister a socket from our p2p network instance.
-    fn deregister_socket(&mut self, event_id: usize, socket: mio_net::TcpStream) -> () {
+    fn deregister_socket(&mut self, event_id: usize, socket: NetSocket) -> () {
         match self.network {
             Some(ref mut network) => {
                 let _ = network.deregister(event_id, &socket);
@@ -1515,6 +2094,19 @@ impl PeerNetwork {
     pub fn deregister_peer(&mut self, event_id: usize) -> () {
         debug!("{:?}: Disconnect event {}", &self.local_peer, event_id);
 
+        // don't lose track of what this peer did while we were talking to it
+        if let Err(e) = self.flush_peer_reputation(event_id) {
+            warn!(
+                "{:?}: Failed to store reputation of event {}: {:?}",
+                &self.local_peer, event_id, &e
+            );
+        }
+        if let Some(convo) = self.peers.get_mut(&event_id) {
+            let sample = convo.stats.take_bandwidth();
+            self.bandwidth.record(&convo.peer_addrbytes, sample);
+        }
+        self.deferred_messages.remove(&event_id);
+
         let mut nk_remove: Vec<NeighborKey> = vec![];
         for (neighbor_key, ev_id) in self.events.iter() {
             if *ev_id == event_id {
@@ -1552,6 +2144,7 @@ impl PeerNetwork {
                 }
             }
         }
+        self.proxy_handshakes.remove(&event_id);
 
         self.relay_handles.remove(&event_id);
         self.peers.remove(&event_id);
@@ -1654,7 +2247,7 @@ impl PeerNetwork {
             };
 
             // start tracking it
-            if let Err(_e) = self.register_peer(event_id, client_sock, false) {
+            if let Err(_e) = self.register_peer(event_id, client_sock, false, None) {
                 // NOTE: register_peer will deregister the socket for us
                 continue;
             }
@@ -1675,7 +2268,7 @@ impl PeerNetwork {
         header_cache: &mut BlockHeaderCache,
         chain_view: &BurnchainView,
         event_id: usize,
-        client_sock: &mut mio_net::TcpStream,
+        client_sock: &mut NetSocket,
         convo: &mut ConversationP2P,
     ) -> Result<(Vec<StacksMessage>, bool), net_error> {
         // get incoming bytes and update the state of this conversation.
@@ -1750,9 +2343,46 @@ impl PeerNetwork {
     fn process_connecting_sockets(&mut self, poll_state: &mut NetworkPollState) -> () {
         for event_id in poll_state.ready.iter() {
             if self.connecting.contains_key(event_id) {
+                // if we're connecting through a proxy, then the proxy must connect us first
+                let proxied_addr = match self.proxy_handshakes.get_mut(event_id) {
+                    Some((peer_addr, handshake)) => {
+                        let (socket, ..) = self
+                            .connecting
+                            .get_mut(event_id)
+                            .expect("BUG: proxy handshake for non-connecting socket");
+                        match handshake.step(socket) {
+                            Ok(true) => {
+                                debug!(
+                                    "{:?}: Proxy connected event {} to {}",
+                                    &self.local_peer,
+                                    event_id,
+                                    handshake.target()
+                                );
+                                Some(peer_addr.clone())
+                            }
+                            Ok(false) => {
+                                // wait for the proxy
+                                continue;
+                            }
+                            Err(e) => {
+                                info!(
+                                    "{:?}: Failed to connect to {} through SOCKS5 proxy: {:?}",
+                                    &self.local_peer,
+                                    handshake.target(),
+                                    &e
+                                );
+                                self.deregister_peer(*event_id);
+                                continue;
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                self.proxy_handshakes.remove(event_id);
+
                 let (socket, outbound, _) = self.connecting.remove(event_id).unwrap();
                 let sock_str = format!("{:?}", &socket);
-                if let Err(_e) = self.register_peer(*event_id, socket, outbound) {
+                if let Err(_e) = self.register_peer(*event_id, socket, outbound, proxied_addr) {
                     debug!(
                         "{:?}: Failed to register connecting socket on event {} ({}): {:?}",
                         &self.local_peer, event_id, sock_str, &_e
@@ -1799,7 +2429,7 @@ impl PeerNetwork {
                 Some(ref mut convo) => {
                     // activity on a p2p socket
                     debug!("{:?}: process p2p data from {:?}", &self.local_peer, convo);
-                    let mut convo_unhandled = match PeerNetwork::process_p2p_conversation(
+                    let convo_unhandled = match PeerNetwork::process_p2p_conversation(
                         &self.local_peer,
                         &mut self.peerdb,
                         sortdb,
@@ -1825,6 +2455,11 @@ impl PeerNetwork {
                         }
                     };
 
+                    // hold back bulk data if we're out of download bandwidth for this peer
+                    let addr = convo.peer_addrbytes;
+                    let mut convo_unhandled =
+                        self.defer_unhandled_messages(*event_id, &addr, convo_unhandled);
+
                     // forward along unhandled messages from this peer
                     if unhandled.contains_key(event_id) {
                         unhandled
@@ -1845,6 +2480,65 @@ impl PeerNetwork {
         (to_remove, unhandled)
     }
 
+    /// Hold back the blocks, microblocks and transactions a peer sent us while we're out of
+    /// download bandwidth for it, and let everything else through.  Once there's bandwidth
+    /// again, the held-back messages are passed along ahead of the new ones.
+    /// The messages were already received, so this doesn't reduce what the peer sends us; it
+    /// keeps a peer that is over its budget from also taking up our block and transaction
+    /// processing.
+    fn defer_unhandled_messages(
+        &mut self,
+        event_id: usize,
+        addr: &PeerAddress,
+        messages: Vec<StacksMessage>,
+    ) -> Vec<StacksMessage> {
+        let mut held = self.deferred_messages.remove(&event_id).unwrap_or(vec![]);
+        held.extend(messages);
+        if self.bandwidth.can_download(addr) {
+            return held;
+        }
+
+        let (mut deferred, passed): (Vec<_>, Vec<_>) = held
+            .into_iter()
+            .partition(|msg| BandwidthManager::is_deferrable(&msg.payload));
+        if !deferred.is_empty() {
+            debug!(
+                "{:?}: Out of download bandwidth; holding back {} message(s) from event {}",
+                &self.local_peer,
+                deferred.len(),
+                event_id
+            );
+            if deferred.len() > MAX_DEFERRED_MESSAGES_PER_PEER {
+                debug!(
+                    "{:?}: Dropping {} held-back message(s) from event {}",
+                    &self.local_peer,
+                    deferred.len() - MAX_DEFERRED_MESSAGES_PER_PEER,
+                    event_id
+                );
+                deferred.truncate(MAX_DEFERRED_MESSAGES_PER_PEER);
+            }
+            self.bandwidth.defer_download(addr, event_id);
+            self.deferred_messages.insert(event_id, deferred);
+        }
+        passed
+    }
+
+    /// Hand each conversation's traffic since the last pass to the bandwidth manager
+    fn update_bandwidth(&mut self) -> () {
+        for convo in self.peers.values_mut() {
+            let sample = convo.stats.take_bandwidth();
+            self.bandwidth.record(&convo.peer_addrbytes, sample);
+        }
+        if let Some(http) = self.http.as_mut() {
+            for convo in http.peers.values_mut() {
+                let addr = PeerAddress::from_socketaddr(convo.get_peer_addr());
+                let sample = convo.take_bandwidth();
+                self.bandwidth.record(&addr, sample);
+            }
+        }
+        self.bandwidth.prune();
+    }
+
     /// Get stats for a neighbor
     pub fn get_neighbor_stats(&self, nk: &NeighborKey) -> Option<NeighborStats> {
         match self.events.get(&nk) {
@@ -4099,10 +4793,16 @@ impl PeerNetwork {
                                 "blocks"
                             }
                         );
-                        self.bans.insert(event_id);
+                        self.add_reputation_event(event_id, ReputationEvent::InvalidBlock);
 
                         if let Some(outbound_event_id) = self.events.get(&outbound_neighbor_key) {
-                            self.bans.insert(*outbound_event_id);
+                            let outbound_event_id = *outbound_event_id;
+                            if outbound_event_id != event_id {
+                                self.add_reputation_event(
+                                    outbound_event_id,
+                                    ReputationEvent::InvalidBlock,
+                                );
+                            }
                         }
                         return Ok(None);
                     }
@@ -4206,7 +4906,7 @@ impl PeerNetwork {
     }
 
     /// Do we need a block or microblock stream, given its sortition's consensus hash?
-    fn need_block_or_microblock_stream(
+    pub fn need_block_or_microblock_stream(
         sortdb: &SortitionDB,
         chainstate: &StacksChainState,
         consensus_hash: &ConsensusHash,
@@ -4308,6 +5008,8 @@ impl PeerNetwork {
             );
 
             if need_block {
+                self.add_reputation_event(event_id, ReputationEvent::UsefulInventory);
+
                 // have the downloader request this block if it's new and we don't have it
                 match self.block_downloader {
                     Some(ref mut downloader) => {
@@ -4406,6 +5108,8 @@ impl PeerNetwork {
             );
 
             if need_microblock_stream {
+                self.add_reputation_event(event_id, ReputationEvent::UsefulInventory);
+
                 // have the downloader request this microblock stream if it's new to us
                 match self.block_downloader {
                     Some(ref mut downloader) => {
@@ -4537,6 +5241,12 @@ impl PeerNetwork {
                 continue;
             }
 
+            if sn.block_height >= self.chain_view.burn_block_height {
+                // this block is for the burnchain tip's sortition -- the peer is helping us stay
+                // up to date
+                self.add_reputation_event(event_id, ReputationEvent::TimelyBlockPush);
+            }
+
             // only bother updating the inventory for this event's peer if we have an outbound
             // connection to it.
             if let Some(outbound_neighbor_key) = outbound_neighbor_key_opt.as_ref() {
@@ -4691,7 +5401,7 @@ impl PeerNetwork {
     /// Return messages that we couldn't handle here, but key them by neighbor, not event.
     /// Drop invalid messages.
     /// If buffer is true, then re-try handling this message once the burnchain view advances.
-    fn handle_unsolicited_messages(
+    pub fn handle_unsolicited_messages(
         &mut self,
         sortdb: &SortitionDB,
         chainstate: &StacksChainState,
@@ -4721,6 +5431,16 @@ impl PeerNetwork {
             debug!("{:?}: Process {} unsolicited messages from {:?}", &self.local_peer, messages.len(), &neighbor_key; "buffer" => %buffer);
 
             for message in messages.into_iter() {
+                match message.payload {
+                    StacksMessageType::CompactBlock(_) | StacksMessageType::CompactBlockTxs(_) => {
+                        // reconstructed in process_compact_blocks()
+                        self.handle_unsolicited_compact_block_message(
+                            sortdb, chainstate, event_id, message,
+                        );
+                        continue;
+                    }
+                    _ => {}
+                }
                 if !buffer {
                     debug!(
                         "{:?}: Re-try handling buffered message {} from {:?}",
@@ -5010,6 +5730,14 @@ impl PeerNetwork {
         // find out who is inbound and unauthenticated
         let unauthenticated_inbounds = self.find_unauthenticated_inbound_convos();
 
+        // collect the last pass's traffic, and retry the reads we put off for lack of bandwidth
+        self.update_bandwidth();
+        for event_id in self.bandwidth.take_deferred_events(&self.peers) {
+            if !poll_state.ready.contains(&event_id) {
+                poll_state.ready.push(event_id);
+            }
+        }
+
         // run existing conversations, clear out broken ones, and get back messages forwarded to us
         let (error_events, unsolicited_messages) =
             self.process_ready_sockets(sortdb, chainstate, &mut poll_state);
@@ -5024,6 +5752,9 @@ impl PeerNetwork {
             self.handle_unsolicited_messages(sortdb, chainstate, unsolicited_messages, ibd, true);
         network_result.consume_unsolicited(unhandled_messages);
 
+        // hand off compact blocks we've finished reconstructing
+        self.process_compact_blocks(sortdb, chainstate, mempool, ibd, network_result);
+
         // schedule now-authenticated inbound convos for pingback
         self.schedule_network_pingbacks(unauthenticated_inbounds);
 
@@ -5069,9 +5800,19 @@ impl PeerNetwork {
         // download attachments
         self.do_attachment_downloads(mempool, chainstate, dns_client_opt, network_result);
 
-        // remove timed-out requests from other threads
+        // remove timed-out requests from other threads, but don't blame the peers we've been
+        // throttling for being slow
         for (_, convo) in self.peers.iter_mut() {
-            convo.clear_timeouts();
+            let penalize = !self.bandwidth.is_throttled(&convo.peer_addrbytes);
+            convo.clear_timeouts(penalize);
+        }
+
+        // store what our peers have been up to, and queue up bans for the worst of them
+        if let Err(e) = self.process_reputations() {
+            warn!(
+                "{:?}: Failed to process peer reputations: {:?}",
+                &self.local_peer, &e
+            );
         }
 
         // clear out peers that we haven't heard from in our heartbeat interval
@@ -5727,6 +6468,330 @@ mod test {
         })
     }
 
+    #[test]
+    fn test_peer_reputation_ban() {
+        with_timeout(600, || {
+            let mut peer_1_config = TestPeerConfig::from_port(32710);
+            let mut peer_2_config = TestPeerConfig::from_port(32712);
+
+            peer_1_config.add_neighbor(&peer_2_config.to_neighbor());
+            peer_2_config.add_neighbor(&peer_1_config.to_neighbor());
+
+            let mut peer_1 = TestPeer::new(peer_1_config);
+            let mut peer_2 = TestPeer::new(peer_2_config);
+
+            let peer_2_nk = peer_2.to_neighbor().addr;
+
+            // wait for peer 1 to authenticate peer 2
+            let mut event_id = None;
+            for _ in 0..100 {
+                let _ = peer_1.step();
+                let _ = peer_2.step();
+
+                if let Some(eid) = peer_1.network.events.get(&peer_2_nk) {
+                    if let Some(convo) = peer_1.network.peers.get(eid) {
+                        if convo.is_authenticated() {
+                            event_id = Some(*eid);
+                            break;
+                        }
+                    }
+                }
+            }
+            let event_id = event_id.expect("peer 1 never authenticated peer 2");
+
+            // good behavior is remembered
+            peer_1
+                .network
+                .add_reputation_event(event_id, ReputationEvent::TimelyBlockPush);
+            assert_eq!(peer_1.network.get_neighbor_reputation(&peer_2_nk), 5);
+            peer_1.network.process_reputations().unwrap();
+
+            let reputation = PeerDB::get_peer_reputation(
+                peer_1.network.peerdb.conn(),
+                peer_2_nk.network_id,
+                &peer_2_nk.addrbytes,
+                peer_2_nk.port,
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(reputation.score, 5);
+            assert_eq!(peer_1.network.get_neighbor_reputation(&peer_2_nk), 5);
+            assert!(peer_1.network.bans.is_empty());
+
+            // bad behavior adds up, and eventually gets the peer banned
+            peer_1
+                .network
+                .add_reputation_event(event_id, ReputationEvent::InvalidData);
+            peer_1.network.process_reputations().unwrap();
+            assert!(peer_1.network.bans.is_empty());
+
+            peer_1
+                .network
+                .add_reputation_event(event_id, ReputationEvent::InvalidData);
+            peer_1
+                .network
+                .add_reputation_event(event_id, ReputationEvent::SlowResponse);
+            peer_1
+                .network
+                .add_reputation_event(event_id, ReputationEvent::SlowResponse);
+            assert!(peer_1.network.bans.contains(&event_id));
+
+            // peer 2 is a bootstrap peer, so it's always allowed (and can't be banned) until we
+            // say otherwise
+            {
+                let mut tx = peer_1.network.peerdb.tx_begin().unwrap();
+                PeerDB::set_allow_peer(
+                    &mut tx,
+                    peer_2_nk.network_id,
+                    &peer_2_nk.addrbytes,
+                    peer_2_nk.port,
+                    0,
+                )
+                .unwrap();
+                tx.commit().unwrap();
+            }
+
+            // disconnecting stores the reputation
+            let banned = peer_1.network.process_bans().unwrap();
+            assert_eq!(banned, vec![event_id]);
+            peer_1.network.deregister_peer(event_id);
+
+            let reputation = PeerDB::get_peer_reputation(
+                peer_1.network.peerdb.conn(),
+                peer_2_nk.network_id,
+                &peer_2_nk.addrbytes,
+                peer_2_nk.port,
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(reputation.score, -105);
+            assert!(PeerDB::is_peer_denied(
+                peer_1.network.peerdb.conn(),
+                peer_2_nk.network_id,
+                &peer_2_nk.addrbytes,
+                peer_2_nk.port
+            )
+            .unwrap());
+        })
+    }
+
+    #[test]
+    fn test_bandwidth_budgets() {
+        let mut opts = ConnectionOptions::default();
+        opts.max_upload_bandwidth = 1000;
+        opts.max_peer_download_bandwidth = 100;
+
+        let mut bandwidth = BandwidthManager::new(&opts);
+        let addr_1 = make_random_peer_address();
+        let addr_2 = make_random_peer_address();
+
+        assert!(bandwidth.can_upload(&addr_1));
+        assert!(bandwidth.can_download(&addr_1));
+
+        // peer 1 uses up its download budget, but not the global upload budget
+        bandwidth.record(
+            &addr_1,
+            BandwidthSample {
+                bytes_tx: 5000,
+                bytes_rx: 1000,
+                ..BandwidthSample::default()
+            },
+        );
+        assert!(bandwidth.can_upload(&addr_1));
+        assert!(!bandwidth.can_download(&addr_1));
+        assert!(bandwidth.can_download(&addr_2));
+
+        // peer 2 uses up the global upload budget, which affects peer 1 too
+        bandwidth.record(
+            &addr_2,
+            BandwidthSample {
+                bytes_tx: 5000,
+                bytes_rx: 10,
+                ..BandwidthSample::default()
+            },
+        );
+        assert!(!bandwidth.can_upload(&addr_1));
+        assert!(!bandwidth.can_upload(&addr_2));
+        assert!(bandwidth.can_download(&addr_2));
+
+        assert_eq!(bandwidth.get_rates(), (1000, 101));
+        assert_eq!(bandwidth.get_peer_rates(&addr_1), (500, 100));
+        assert_eq!(
+            bandwidth.get_peer_bandwidth(&addr_2).unwrap().bytes_tx,
+            5000
+        );
+
+        // deferred events are handed back only once, and only if they're still around
+        let mut convos = HashMap::new();
+        convos.insert(1usize, ());
+        assert!(!bandwidth.is_throttled(&addr_1));
+        bandwidth.defer_download(&addr_1, 1);
+        bandwidth.defer_upload(&addr_1, Some(2));
+        bandwidth.defer_upload(&addr_1, None);
+        assert!(bandwidth.is_throttled(&addr_1));
+        assert!(!bandwidth.is_throttled(&addr_2));
+        assert_eq!(bandwidth.num_deferred_uploads, 2);
+        assert_eq!(bandwidth.num_deferred_downloads, 1);
+        assert_eq!(bandwidth.take_deferred_events(&convos), vec![1]);
+        assert_eq!(bandwidth.take_deferred_events(&convos), Vec::<usize>::new());
+
+        // only bulk data can be held back
+        assert!(BandwidthManager::is_deferrable(
+            &StacksMessageType::Transaction(make_contract_tx(
+                &StacksPrivateKey::new(),
+                0,
+                1000,
+                "hello-world",
+                "(define-public (hello) (ok 1))"
+            ))
+        ));
+        assert!(!BandwidthManager::is_deferrable(&StacksMessageType::Ping(
+            PingData::new()
+        )));
+    }
+
+    #[test]
+    fn test_bandwidth_defer_unhandled_messages() {
+        let mut p2p = make_test_p2p_network(&vec![]);
+        let mut opts = ConnectionOptions::default();
+        opts.max_peer_download_bandwidth = 100;
+        p2p.bandwidth = BandwidthManager::new(&opts);
+
+        let addr = make_random_peer_address();
+        let make_msg = |p2p: &PeerNetwork, payload: StacksMessageType| {
+            StacksMessage::new(
+                p2p.peer_version,
+                p2p.local_peer.network_id,
+                p2p.chain_view.burn_block_height,
+                &p2p.chain_view.burn_block_hash,
+                p2p.chain_view.burn_stable_block_height,
+                &p2p.chain_view.burn_stable_block_hash,
+                payload,
+            )
+        };
+        let tx = make_contract_tx(
+            &StacksPrivateKey::new(),
+            0,
+            1000,
+            "hello-world",
+            "(define-public (hello) (ok 1))",
+        );
+
+        // within budget, everything passes through
+        let msgs = vec![
+            make_msg(&p2p, StacksMessageType::Ping(PingData::new())),
+            make_msg(&p2p, StacksMessageType::Transaction(tx.clone())),
+        ];
+        assert_eq!(p2p.defer_unhandled_messages(1, &addr, msgs).len(), 2);
+        assert!(!p2p.bandwidth.is_throttled(&addr));
+
+        // out of budget, pings still pass but pushed transactions are held back
+        p2p.bandwidth.record(
+            &addr,
+            BandwidthSample {
+                bytes_rx: 5000,
+                ..BandwidthSample::default()
+            },
+        );
+        let mut msgs = vec![make_msg(&p2p, StacksMessageType::Ping(PingData::new()))];
+        for _ in 0..(MAX_DEFERRED_MESSAGES_PER_PEER + 1) {
+            msgs.push(make_msg(&p2p, StacksMessageType::Transaction(tx.clone())));
+        }
+        let passed = p2p.defer_unhandled_messages(1, &addr, msgs);
+        assert_eq!(passed.len(), 1);
+        assert_eq!(passed[0].payload.get_message_id(), StacksMessageID::Ping);
+        assert!(p2p.bandwidth.is_throttled(&addr));
+        assert_eq!(p2p.bandwidth.num_deferred_downloads, 1);
+
+        // once there's bandwidth again, the held-back messages (up to the cap) come out first
+        p2p.bandwidth = BandwidthManager::new(&opts);
+        let msgs = vec![make_msg(&p2p, StacksMessageType::Ping(PingData::new()))];
+        let passed = p2p.defer_unhandled_messages(1, &addr, msgs);
+        assert_eq!(passed.len(), MAX_DEFERRED_MESSAGES_PER_PEER + 1);
+        assert_eq!(
+            passed[0].payload.get_message_id(),
+            StacksMessageID::Transaction
+        );
+        assert!(p2p.deferred_messages.is_empty());
+    }
+
+    #[test]
+    fn test_bandwidth_window_expiry() {
+        let mut window = BandwidthWindow::default();
+        window.add(100, 500);
+        window.add(100, 500);
+        window.add(105, 1000);
+        assert_eq!(window.total(105), 2000);
+        assert_eq!(window.rate(105), 200);
+        assert!(window.within_budget(0, 105));
+        assert!(window.within_budget(201, 105));
+        assert!(!window.within_budget(200, 105));
+
+        // the first bucket falls out of the window
+        assert_eq!(window.total(110), 1000);
+        window.expire(110);
+        assert_eq!(window.buckets.len(), 1);
+
+        window.expire(115);
+        assert_eq!(window.total(115), 0);
+        assert!(window.buckets.is_empty());
+    }
+
+    #[test]
+    fn test_bandwidth_accounting() {
+        with_timeout(600, || {
+            let mut peer_1_config = TestPeerConfig::from_port(34200);
+            let mut peer_2_config = TestPeerConfig::from_port(34202);
+
+            peer_1_config.add_neighbor(&peer_2_config.to_neighbor());
+            peer_2_config.add_neighbor(&peer_1_config.to_neighbor());
+
+            let mut peer_1 = TestPeer::new(peer_1_config);
+            let mut peer_2 = TestPeer::new(peer_2_config);
+
+            let peer_2_nk = peer_2.to_neighbor().addr;
+
+            // wait for the peers to talk to each other
+            let mut authenticated = false;
+            for _ in 0..100 {
+                let _ = peer_1.step();
+                let _ = peer_2.step();
+
+                if let Some(eid) = peer_1.network.events.get(&peer_2_nk) {
+                    if let Some(convo) = peer_1.network.peers.get(eid) {
+                        if convo.is_authenticated() {
+                            authenticated = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            assert!(authenticated, "peer 1 never authenticated peer 2");
+
+            // one more pass to collect the latest traffic
+            let _ = peer_1.step();
+
+            let (handshake_tx, _) = peer_1
+                .network
+                .bandwidth
+                .get_message_bandwidth(StacksMessageID::Handshake);
+            let (_, accept_rx) = peer_1
+                .network
+                .bandwidth
+                .get_message_bandwidth(StacksMessageID::HandshakeAccept);
+            assert!(handshake_tx > 0);
+            assert!(accept_rx > 0);
+
+            let totals = peer_1
+                .network
+                .bandwidth
+                .get_peer_bandwidth(&peer_2_nk.addrbytes)
+                .unwrap();
+            assert!(totals.bytes_tx >= handshake_tx);
+            assert!(totals.bytes_rx >= accept_rx);
+        })
+    }
+
     #[test]
     #[ignore]
     fn test_mempool_sync_2_peers() {
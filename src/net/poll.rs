@@ -19,11 +19,15 @@ use crate::net::Neighbor;
 use crate::net::NeighborKey;
 use crate::net::PeerAddress;
 
+use crate::net::socks::{is_hostname_peer_address, Socks5Handshake, Socks5Proxy, Socks5Target};
+
 use crate::util_lib::db::DBConn;
 use crate::util_lib::db::Error as db_error;
 
+use std::any::Any;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
 use std::io;
 use std::io::Error as io_error;
 use std::io::ErrorKind;
@@ -50,41 +54,62 @@ use rand::RngCore;
 
 const SERVER: Token = mio::Token(0);
 
-pub struct NetworkPollState {
-    pub new: HashMap<usize, mio_net::TcpStream>,
-    pub ready: Vec<usize>,
+/// A stream socket handed out by a `NetworkTransport`.  Reads and writes never block; they fail
+/// with `ErrorKind::WouldBlock` instead.
+pub trait NetworkSocket: Read + Write + fmt::Debug + Send {
+    /// Address of the remote end
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    /// Close both directions of the socket
+    fn shutdown(&self) -> io::Result<()>;
+    /// For transports that need to find their own socket type again
+    fn as_any(&self) -> &dyn Any;
 }
 
-impl NetworkPollState {
-    pub fn new() -> NetworkPollState {
-        NetworkPollState {
-            new: HashMap::new(),
-            ready: vec![],
-        }
+pub type NetSocket = Box<dyn NetworkSocket>;
+
+impl NetworkSocket for mio_net::TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        mio_net::TcpStream::peer_addr(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        mio_net::TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
-// state for a single network server
-#[derive(Debug)]
-pub struct NetworkServerState {
-    addr: SocketAddr,
-    server_socket: mio_net::TcpListener,
-    server_event: mio::Token,
+/// The sockets and poller underneath a `NetworkState`.  Listeners and sockets are registered
+/// under tokens, and `poll()` reports which tokens have something to do.
+pub trait NetworkTransport: fmt::Debug + Send {
+    /// Listen for connections on `addr`.  `poll()` reports `token` when there are connections to
+    /// accept.
+    fn bind(&mut self, addr: &SocketAddr, token: usize) -> Result<(), net_error>;
+    /// Accept the next pending connection on the listener bound under `token`, if there is one
+    fn accept(&mut self, token: usize) -> Result<Option<(NetSocket, SocketAddr)>, net_error>;
+    /// Start connecting to `addr`.  The connection completes asynchronously: once registered,
+    /// the socket is reported by `poll()` when it is connected (or has failed to connect).
+    fn connect(&mut self, addr: &SocketAddr) -> Result<NetSocket, net_error>;
+    /// Report readiness of `sock` under `token`
+    fn register(&mut self, sock: &NetSocket, token: usize) -> Result<(), net_error>;
+    /// Stop reporting readiness of `sock`
+    fn deregister(&mut self, sock: &NetSocket) -> Result<(), net_error>;
+    /// Wait up to `timeout` milliseconds for activity, and return the tokens that have some
+    fn poll(&mut self, timeout: u64) -> Result<Vec<usize>, net_error>;
 }
 
-// state for the entire network
+/// TCP sockets, polled with mio
 #[derive(Debug)]
-pub struct NetworkState {
+pub struct MioTransport {
     poll: mio::Poll,
     events: mio::Events,
-    event_capacity: usize,
-    servers: Vec<NetworkServerState>,
-    count: usize,
-    event_map: HashMap<usize, usize>, // map socket events to their registered server socket (including server sockets)
+    listeners: HashMap<usize, mio_net::TcpListener>,
 }
 
-impl NetworkState {
-    pub fn new(event_capacity: usize) -> Result<NetworkState, net_error> {
+impl MioTransport {
+    pub fn new(event_capacity: usize) -> Result<MioTransport, net_error> {
         let poll = mio::Poll::new().map_err(|e| {
             error!("Failed to initialize poller: {:?}", e);
             net_error::BindError
@@ -92,20 +117,13 @@ impl NetworkState {
 
         let events = mio::Events::with_capacity(event_capacity);
 
-        Ok(NetworkState {
+        Ok(MioTransport {
             poll: poll,
             events: events,
-            event_capacity: event_capacity,
-            servers: vec![],
-            count: 1,
-            event_map: HashMap::new(),
+            listeners: HashMap::new(),
         })
     }
 
-    pub fn num_events(&self) -> usize {
-        self.event_map.len()
-    }
-
     fn bind_address(addr: &SocketAddr) -> Result<mio_net::TcpListener, net_error> {
         if !cfg!(test) {
             mio_net::TcpListener::bind(addr).map_err(|e| {
@@ -142,28 +160,185 @@ impl NetworkState {
         }
     }
 
-    /// Bind to the given socket address.
-    /// Returns the handle to the poll state, used to key network poll events.
-    pub fn bind(&mut self, addr: &SocketAddr) -> Result<usize, net_error> {
-        let server = NetworkState::bind_address(addr)?;
-        let next_server_event = self.next_event_id()?;
+    fn mio_stream(sock: &NetSocket) -> Result<&mio_net::TcpStream, net_error> {
+        sock.as_any()
+            .downcast_ref::<mio_net::TcpStream>()
+            .ok_or_else(|| {
+                error!("Not a TCP socket: {:?}", sock);
+                net_error::RegisterError
+            })
+    }
+}
 
+impl NetworkTransport for MioTransport {
+    fn bind(&mut self, addr: &SocketAddr, token: usize) -> Result<(), net_error> {
+        let server = MioTransport::bind_address(addr)?;
         self.poll
-            .register(
-                &server,
-                mio::Token(next_server_event),
-                Ready::all(),
-                PollOpt::edge(),
-            )
+            .register(&server, mio::Token(token), Ready::all(), PollOpt::edge())
             .map_err(|e| {
                 error!("Failed to register server socket: {:?}", &e);
                 net_error::BindError
             })?;
+        self.listeners.insert(token, server);
+        Ok(())
+    }
+
+    fn accept(&mut self, token: usize) -> Result<Option<(NetSocket, SocketAddr)>, net_error> {
+        let server = self.listeners.get(&token).ok_or(net_error::AcceptError)?;
+        match server.accept() {
+            Ok((client_sock, client_addr)) => Ok(Some((Box::new(client_sock), client_addr))),
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock => Ok(None),
+                _ => {
+                    error!("Network error: {}", e);
+                    Err(net_error::AcceptError)
+                }
+            },
+        }
+    }
+
+    fn connect(&mut self, addr: &SocketAddr) -> Result<NetSocket, net_error> {
+        let stream = mio_net::TcpStream::connect(addr).map_err(|_e| {
+            test_debug!("Failed to convert to mio stream: {:?}", &_e);
+            net_error::ConnectionError
+        })?;
+
+        // set some helpful defaults
+        // Don't go crazy on TIME_WAIT states; have them all die after 5 seconds
+        stream
+            .set_linger(Some(time::Duration::from_millis(5000)))
+            .map_err(|_e| {
+                test_debug!("Failed to set SO_LINGER: {:?}", &_e);
+                net_error::ConnectionError
+            })?;
+
+        // Disable Nagle algorithm
+        stream.set_nodelay(true).map_err(|_e| {
+            test_debug!("Failed to set TCP_NODELAY: {:?}", &_e);
+            net_error::ConnectionError
+        })?;
+
+        // Make sure keep-alive is on, since at least in p2p messages, we keep sockets around
+        // for a while.  Linux default is 7200 seconds, so make sure we keep it here.
+        stream
+            .set_keepalive(Some(time::Duration::from_millis(7200 * 1000)))
+            .map_err(|_e| {
+                test_debug!("Failed to set TCP_KEEPALIVE and/or SO_KEEPALIVE: {:?}", &_e);
+                net_error::ConnectionError
+            })?;
+
+        if cfg!(test) {
+            // edge-trigger torture test
+            stream.set_send_buffer_size(32).unwrap();
+            stream.set_recv_buffer_size(32).unwrap();
+        }
+
+        test_debug!("New socket connected to {:?}: {:?}", addr, &stream);
+        Ok(Box::new(stream))
+    }
+
+    fn register(&mut self, sock: &NetSocket, token: usize) -> Result<(), net_error> {
+        let stream = MioTransport::mio_stream(sock)?;
+        self.poll
+            .register(stream, mio::Token(token), Ready::all(), PollOpt::edge())
+            .map_err(|e| {
+                error!("Failed to register socket {:?}: {:?}", stream, &e);
+                net_error::RegisterError
+            })
+    }
+
+    fn deregister(&mut self, sock: &NetSocket) -> Result<(), net_error> {
+        let stream = MioTransport::mio_stream(sock)?;
+        self.poll.deregister(stream).map_err(|e| {
+            warn!("Failed to deregister socket {:?}: {:?}", stream, &e);
+            net_error::RegisterError
+        })
+    }
+
+    fn poll(&mut self, timeout: u64) -> Result<Vec<usize>, net_error> {
+        self.events.clear();
+        self.poll
+            .poll(&mut self.events, Some(Duration::from_millis(timeout)))
+            .map_err(|e| {
+                error!("Failed to poll: {:?}", &e);
+                net_error::PollError
+            })?;
+
+        Ok(self
+            .events
+            .iter()
+            .map(|event| usize::from(event.token()))
+            .collect())
+    }
+}
+
+pub struct NetworkPollState {
+    pub new: HashMap<usize, NetSocket>,
+    pub ready: Vec<usize>,
+}
+
+impl NetworkPollState {
+    pub fn new() -> NetworkPollState {
+        NetworkPollState {
+            new: HashMap::new(),
+            ready: vec![],
+        }
+    }
+}
+
+// state for a single network server
+#[derive(Debug)]
+pub struct NetworkServerState {
+    addr: SocketAddr,
+    server_event: usize,
+}
+
+// state for the entire network
+#[derive(Debug)]
+pub struct NetworkState {
+    transport: Box<dyn NetworkTransport>,
+    event_capacity: usize,
+    servers: Vec<NetworkServerState>,
+    count: usize,
+    event_map: HashMap<usize, usize>, // map socket events to their registered server socket (including server sockets)
+}
+
+impl NetworkState {
+    pub fn new(event_capacity: usize) -> Result<NetworkState, net_error> {
+        let transport = MioTransport::new(event_capacity)?;
+        Ok(NetworkState::with_transport(
+            event_capacity,
+            Box::new(transport),
+        ))
+    }
+
+    /// Use sockets from `transport` instead of TCP sockets
+    pub fn with_transport(
+        event_capacity: usize,
+        transport: Box<dyn NetworkTransport>,
+    ) -> NetworkState {
+        NetworkState {
+            transport: transport,
+            event_capacity: event_capacity,
+            servers: vec![],
+            count: 1,
+            event_map: HashMap::new(),
+        }
+    }
+
+    pub fn num_events(&self) -> usize {
+        self.event_map.len()
+    }
+
+    /// Bind to the given socket address.
+    /// Returns the handle to the poll state, used to key network poll events.
+    pub fn bind(&mut self, addr: &SocketAddr) -> Result<usize, net_error> {
+        let next_server_event = self.next_event_id()?;
+        self.transport.bind(addr, next_server_event)?;
 
         let network_server = NetworkServerState {
             addr: addr.clone(),
-            server_socket: server,
-            server_event: mio::Token(next_server_event),
+            server_event: next_server_event,
         };
 
         assert!(
@@ -185,7 +360,7 @@ impl NetworkState {
         &mut self,
         server_event_id: usize,
         hint_event_id: usize,
-        sock: &mio_net::TcpStream,
+        sock: &NetSocket,
     ) -> Result<usize, net_error> {
         let hint_event_id = hint_event_id % (self.event_capacity + self.servers.len());
         if let Some(x) = self.event_map.get(&server_event_id) {
@@ -217,15 +392,13 @@ impl NetworkState {
             self.servers.len()
         );
 
-        self.poll
-            .register(sock, mio::Token(event_id), Ready::all(), PollOpt::edge())
-            .map_err(|e| {
-                error!(
-                    "Failed to register socket on server {} event ID {} ({}): {:?}",
-                    server_event_id, event_id, hint_event_id, &e
-                );
-                net_error::RegisterError
-            })?;
+        self.transport.register(sock, event_id).map_err(|e| {
+            error!(
+                "Failed to register socket on server {} event ID {} ({}): {:?}",
+                server_event_id, event_id, hint_event_id, &e
+            );
+            net_error::RegisterError
+        })?;
 
         self.event_map.insert(event_id, server_event_id);
 
@@ -242,11 +415,7 @@ impl NetworkState {
     }
 
     /// Deregister a socket event
-    pub fn deregister(
-        &mut self,
-        event_id: usize,
-        sock: &mio_net::TcpStream,
-    ) -> Result<(), net_error> {
+    pub fn deregister(&mut self, event_id: usize, sock: &NetSocket) -> Result<(), net_error> {
         assert!(
             self.event_map.contains_key(&event_id),
             "BUG: no such socket {}",
@@ -254,7 +423,7 @@ impl NetworkState {
         );
         self.event_map.remove(&event_id);
 
-        if let Err(e) = self.poll.deregister(sock) {
+        if let Err(e) = self.transport.deregister(sock) {
             warn!("Failed to deregister socket {}: {:?}", event_id, &e);
         };
 
@@ -266,7 +435,7 @@ impl NetworkState {
             self.event_capacity
         );
 
-        if let Err(e) = sock.shutdown(Shutdown::Both) {
+        if let Err(e) = sock.shutdown() {
             debug!("Failed to shut down socket {}: {:?}", event_id, &e);
         }
 
@@ -312,134 +481,97 @@ impl NetworkState {
     /// Connect to a remote peer, but don't register it with the poll handle.
     /// The underlying connect(2) is _asynchronous_, so the caller will need to register it with a
     /// poll handle and wait for it to be connected.
-    pub fn connect(addr: &SocketAddr) -> Result<mio_net::TcpStream, net_error> {
-        let stream = mio_net::TcpStream::connect(addr).map_err(|_e| {
-            test_debug!("Failed to convert to mio stream: {:?}", &_e);
-            net_error::ConnectionError
-        })?;
-
-        // set some helpful defaults
-        // Don't go crazy on TIME_WAIT states; have them all die after 5 seconds
-        stream
-            .set_linger(Some(time::Duration::from_millis(5000)))
-            .map_err(|_e| {
-                test_debug!("Failed to set SO_LINGER: {:?}", &_e);
-                net_error::ConnectionError
-            })?;
-
-        // Disable Nagle algorithm
-        stream.set_nodelay(true).map_err(|_e| {
-            test_debug!("Failed to set TCP_NODELAY: {:?}", &_e);
-            net_error::ConnectionError
-        })?;
-
-        // Make sure keep-alive is on, since at least in p2p messages, we keep sockets around
-        // for a while.  Linux default is 7200 seconds, so make sure we keep it here.
-        stream
-            .set_keepalive(Some(time::Duration::from_millis(7200 * 1000)))
-            .map_err(|_e| {
-                test_debug!("Failed to set TCP_KEEPALIVE and/or SO_KEEPALIVE: {:?}", &_e);
-                net_error::ConnectionError
-            })?;
+    pub fn connect(&mut self, addr: &SocketAddr) -> Result<NetSocket, net_error> {
+        self.transport.connect(addr)
+    }
 
-        if cfg!(test) {
-            // edge-trigger torture test
-            stream.set_send_buffer_size(32).unwrap();
-            stream.set_recv_buffer_size(32).unwrap();
+    /// Connect to a remote peer at `addrbytes:port`, either directly or through `proxy`.  With a
+    /// proxy, the returned handshake must run to completion (see `Socks5Handshake::step()`)
+    /// before the socket can carry the remote peer's data.  `hostnames` maps placeholder peer
+    /// addresses to the hostnames the proxy should connect to instead.
+    pub fn connect_proxied(
+        &mut self,
+        addrbytes: &PeerAddress,
+        port: u16,
+        proxy: Option<&Socks5Proxy>,
+        hostnames: &HashMap<PeerAddress, String>,
+    ) -> Result<(NetSocket, Option<Socks5Handshake>), net_error> {
+        match proxy {
+            Some(proxy) => {
+                let target = Socks5Target::from_peer_address(addrbytes, port, hostnames);
+                let stream = self.connect(&proxy.addr)?;
+                test_debug!("Connecting to {} through {:?}", &target, proxy);
+                Ok((stream, Some(Socks5Handshake::new(proxy, target))))
+            }
+            None => {
+                if is_hostname_peer_address(addrbytes) {
+                    debug!(
+                        "Cannot connect to {:?} without a SOCKS5 proxy",
+                        &addrbytes.to_socketaddr(port)
+                    );
+                    return Err(net_error::ConnectionError);
+                }
+                let stream = self.connect(&addrbytes.to_socketaddr(port))?;
+                Ok((stream, None))
+            }
         }
-
-        test_debug!("New socket connected to {:?}: {:?}", addr, &stream);
-        Ok(stream)
     }
 
     /// Poll all server sockets.
     /// Returns a map between network server handles (returned by bind()) and their new polling state
     pub fn poll(&mut self, timeout: u64) -> Result<HashMap<usize, NetworkPollState>, net_error> {
-        self.events.clear();
-        self.poll
-            .poll(&mut self.events, Some(Duration::from_millis(timeout)))
-            .map_err(|e| {
-                error!("Failed to poll: {:?}", &e);
-                net_error::PollError
-            })?;
+        let tokens = self.transport.poll(timeout)?;
 
         let mut poll_states = HashMap::new();
         for server in self.servers.iter() {
             // pre-populate with server tokens
-            let server_event_id = usize::from(server.server_event);
-            poll_states.insert(server_event_id, NetworkPollState::new());
+            poll_states.insert(server.server_event, NetworkPollState::new());
         }
 
         let mut new_events = HashSet::new();
 
-        for event in &self.events {
-            let token = event.token();
-            let mut is_server_event = false;
-
-            for server in self.servers.iter() {
-                // server token?
-                if token == server.server_event {
-                    // new inbound connection(s)
-                    let poll_state = poll_states.get_mut(&usize::from(token)).expect(&format!(
-                        "BUG: FATAL: no poll state registered for server {}",
-                        usize::from(token)
-                    ));
-
-                    loop {
-                        let (client_sock, client_addr) = match server.server_socket.accept() {
-                            Ok((client_sock, client_addr)) => (client_sock, client_addr),
-                            Err(e) => match e.kind() {
-                                ErrorKind::WouldBlock => {
-                                    break;
-                                }
-                                _ => {
-                                    error!("Network error: {}", e);
-                                    return Err(net_error::AcceptError);
-                                }
-                            },
-                        };
-
-                        // this does the same thing as next_event_id(), but we can't borrow self
-                        // mutably here (so we'll just do the increment-mod directly).
-                        let next_event_id = match self.make_next_event_id(self.count, &new_events) {
-                            Some(eid) => eid,
-                            None => {
-                                // no poll slots available. Close the socket and carry on.
-                                info!("Too many peers on {:?}, closing {:?} (events: {}, in-flight: {}, capacity: {})", &server.server_socket, &client_sock, self.event_map.len(), new_events.len(), self.event_capacity);
-                                let _ = client_sock.shutdown(Shutdown::Both);
-                                continue;
-                            }
-                        };
-
-                        self.count =
-                            (next_event_id + 1) % (self.event_capacity + self.servers.len());
-
-                        new_events.insert(next_event_id);
-
-                        debug!(
-                            "New socket event: {}, {:?} addr={:?} (Events total: {}, max: {}) on server {:?}",
-                            next_event_id,
-                            &client_sock,
-                            &client_addr,
-                            self.event_map.len(),
-                            self.event_capacity,
-                            &server.server_socket
-                        );
+        for token in tokens.into_iter() {
+            if let Some(server) = self.servers.iter().find(|s| s.server_event == token) {
+                // new inbound connection(s)
+                let poll_state = poll_states.get_mut(&token).expect(&format!(
+                    "BUG: FATAL: no poll state registered for server {}",
+                    token
+                ));
+
+                while let Some((client_sock, client_addr)) = self.transport.accept(token)? {
+                    // this does the same thing as next_event_id(), but we can't borrow self
+                    // mutably here (so we'll just do the increment-mod directly).
+                    let next_event_id = match self.make_next_event_id(self.count, &new_events) {
+                        Some(eid) => eid,
+                        None => {
+                            // no poll slots available. Close the socket and carry on.
+                            info!("Too many peers on {:?}, closing {:?} (events: {}, in-flight: {}, capacity: {})", &server.addr, &client_sock, self.event_map.len(), new_events.len(), self.event_capacity);
+                            let _ = client_sock.shutdown();
+                            continue;
+                        }
+                    };
 
-                        poll_state.new.insert(next_event_id, client_sock);
-                    }
+                    self.count = (next_event_id + 1) % (self.event_capacity + self.servers.len());
 
-                    is_server_event = true;
-                    break;
-                }
-            }
+                    new_events.insert(next_event_id);
+
+                    debug!(
+                        "New socket event: {}, {:?} addr={:?} (Events total: {}, max: {}) on server {:?}",
+                        next_event_id,
+                        &client_sock,
+                        &client_addr,
+                        self.event_map.len(),
+                        self.event_capacity,
+                        &server.addr
+                    );
 
-            if is_server_event {
+                    poll_state.new.insert(next_event_id, client_sock);
+                }
                 continue;
             }
 
             // event for a client of one of our servers.  which one?
-            let event_id = usize::from(token);
+            let event_id = token;
             match self.event_map.get(&event_id) {
                 Some(server_event_id) => {
                     if let Some(poll_state) = poll_states.get_mut(server_event_id) {
@@ -508,7 +640,7 @@ mod test {
             let addr = format!("127.0.0.1:{}", &port)
                 .parse::<SocketAddr>()
                 .unwrap();
-            let sock = NetworkState::connect(&addr).unwrap();
+            let sock = ns.connect(&addr).unwrap();
 
             let event_id = ns.register(server_events[port - 49010], 1, &sock).unwrap();
             assert!(event_id != 0);
@@ -545,7 +677,7 @@ mod test {
             let addr = format!("127.0.0.1:{}", &port)
                 .parse::<SocketAddr>()
                 .unwrap();
-            let sock = NetworkState::connect(&addr).unwrap();
+            let sock = ns.connect(&addr).unwrap();
 
             // can't use non-server events
             assert_eq!(
@@ -571,7 +703,7 @@ mod test {
                 .unwrap();
             event_ids.insert(server_event_id);
 
-            let sock = NetworkState::connect(&addr).unwrap();
+            let sock = ns.connect(&addr).unwrap();
 
             // register 10 client events
             let event_id = ns.register(server_event_id, 11, &sock).unwrap();
@@ -580,7 +712,7 @@ mod test {
 
         // the 21st socket should fail
         let addr = "127.0.0.1:49031".parse::<SocketAddr>().unwrap();
-        let sock = NetworkState::connect(&addr).unwrap();
+        let sock = ns.connect(&addr).unwrap();
         let res = ns.register(server_event_id, 11, &sock);
         assert_eq!(Err(net_error::TooManyPeers), res);
     }
@@ -115,12 +115,19 @@ impl PeerNetwork {
         Ok(org_neighbor)
     }
 
-    /// Sort function for a neighbor list in order to compare by by uptime and health.
-    /// Bucket uptime geometrically by powers of 2 -- a node that's been up for X seconds is
-    /// likely to be up for X more seconds, so we only really want to distinguish between nodes that
-    /// have wildly different uptimes.
-    /// Within uptime buckets, sort by health.
+    /// Sort function for a neighbor list in order to compare by by reputation, uptime and health.
+    /// Peers with negative reputations sort first, worst first.  Among the rest, a few points of
+    /// reputation should not outweigh a long uptime, so bucket uptime geometrically by powers of 2
+    /// -- a node that's been up for X seconds is likely to be up for X more seconds, so we only
+    /// really want to distinguish between nodes that have wildly different uptimes.
+    /// Within uptime buckets, sort by health, and then by reputation.
     fn compare_neighbor_uptime_health(stats1: &NeighborStats, stats2: &NeighborStats) -> Ordering {
+        let reputation_1 = stats1.get_reputation();
+        let reputation_2 = stats2.get_reputation();
+        if reputation_1.min(0) != reputation_2.min(0) {
+            return reputation_1.min(0).cmp(&reputation_2.min(0));
+        }
+
         let now = get_epoch_time_secs();
         let uptime_1 = (now - stats1.first_contact_time) as f64;
         let uptime_2 = (now - stats2.first_contact_time) as f64;
@@ -146,6 +153,10 @@ impl PeerNetwork {
             return Ordering::Greater;
         }
 
+        if reputation_1 != reputation_2 {
+            return reputation_1.cmp(&reputation_2);
+        }
+
         // flip a coin
         let mut rng = thread_rng();
         if rng.next_u32() % 2 == 0 {
@@ -352,16 +363,21 @@ impl PeerNetwork {
             }
         }
 
-        // sort in order by first-contact time (oldest first)
+        // sort in order by negative reputation (least negative first), then by first-contact time
+        // (oldest first), and then by reputation (best first)
         for (_, stats_list) in ip_neighbor.iter_mut() {
             stats_list.sort_by(
                 |&(ref _e1, ref _nk1, ref stats1), &(ref _e2, ref _nk2, ref stats2)| {
-                    if stats1.first_contact_time < stats2.first_contact_time {
+                    let reputation_1 = stats1.get_reputation();
+                    let reputation_2 = stats2.get_reputation();
+                    if reputation_1.min(0) != reputation_2.min(0) {
+                        reputation_2.min(0).cmp(&reputation_1.min(0))
+                    } else if stats1.first_contact_time < stats2.first_contact_time {
                         Ordering::Less
                     } else if stats1.first_contact_time > stats2.first_contact_time {
                         Ordering::Greater
                     } else {
-                        Ordering::Equal
+                        reputation_2.cmp(&reputation_1)
                     }
                 },
             );
@@ -1864,10 +1864,14 @@ impl PeerNetwork {
     ) -> () {
         let blk_hash = block.block_hash();
         let ch = consensus_hash.clone();
-        let payload = BlocksData {
-            blocks: vec![BlocksDatum(consensus_hash, block)],
+        let payload = if self.can_push_compact_block(recipient) {
+            StacksMessageType::CompactBlock(CompactBlockData::from_block(consensus_hash, &block))
+        } else {
+            StacksMessageType::Blocks(BlocksData {
+                blocks: vec![BlocksDatum(consensus_hash, block)],
+            })
         };
-        let message = match self.sign_for_peer(recipient, StacksMessageType::Blocks(payload)) {
+        let message = match self.sign_for_peer(recipient, payload) {
             Ok(m) => m,
             Err(e) => {
                 warn!(
@@ -2937,6 +2941,26 @@ pub mod test {
         push_message(peer, dest, relay_hints, msg)
     }
 
+    fn push_compact_block(
+        peer: &mut TestPeer,
+        dest: &NeighborKey,
+        relay_hints: Vec<RelayData>,
+        consensus_hash: ConsensusHash,
+        block: StacksBlock,
+    ) -> bool {
+        test_debug!(
+            "{:?}: Push compact block {}/{} to {:?}",
+            peer.to_neighbor().addr,
+            &consensus_hash,
+            block.block_hash(),
+            dest
+        );
+
+        let msg =
+            StacksMessageType::CompactBlock(CompactBlockData::from_block(consensus_hash, &block));
+        push_message(peer, dest, relay_hints, msg)
+    }
+
     fn broadcast_block(
         peer: &mut TestPeer,
         relay_hints: Vec<RelayData>,
@@ -3103,6 +3127,7 @@ pub mod test {
     fn test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(
         outbound_test: bool,
         disable_push: bool,
+        compact_blocks: bool,
     ) {
         with_timeout(600, move || {
             let original_blocks_and_microblocks = RefCell::new(vec![]);
@@ -3276,13 +3301,23 @@ pub mod test {
                             let mut sent_microblocks = sent_microblocks.borrow_mut();
 
                             let pushed_block = if !*sent_blocks {
-                                push_block(
-                                    &mut peers[0],
-                                    &peer_1_nk,
-                                    vec![],
-                                    consensus_hash.clone(),
-                                    block,
-                                )
+                                if compact_blocks {
+                                    push_compact_block(
+                                        &mut peers[0],
+                                        &peer_1_nk,
+                                        vec![],
+                                        consensus_hash.clone(),
+                                        block,
+                                    )
+                                } else {
+                                    push_block(
+                                        &mut peers[0],
+                                        &peer_1_nk,
+                                        vec![],
+                                        consensus_hash.clone(),
+                                        block,
+                                    )
+                                }
                             } else {
                                 true
                             };
@@ -3353,7 +3388,7 @@ pub mod test {
     fn test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks_outbound() {
         // simulates node 0 pushing blocks to node 1, but node 0 is publicly routable.
         // nodes rely on blocksavailable/microblocksavailable to discover blocks
-        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(true, true)
+        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(true, true, false)
     }
 
     #[test]
@@ -3361,7 +3396,7 @@ pub mod test {
     fn test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks_inbound() {
         // simulates node 0 pushing blocks to node 1, where node 0 is behind a NAT
         // nodes rely on blocksavailable/microblocksavailable to discover blocks
-        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(false, true)
+        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(false, true, false)
     }
 
     #[test]
@@ -3369,7 +3404,7 @@ pub mod test {
     fn test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks_outbound_direct() {
         // simulates node 0 pushing blocks to node 1, but node 0 is publicly routable.
         // nodes may push blocks and microblocks directly to each other
-        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(true, false)
+        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(true, false, false)
     }
 
     #[test]
@@ -3377,7 +3412,16 @@ pub mod test {
     fn test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks_inbound_direct() {
         // simulates node 0 pushing blocks to node 1, where node 0 is behind a NAT
         // nodes may push blocks and microblocks directly to each other
-        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(false, false)
+        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(false, false, false)
+    }
+
+    #[test]
+    #[ignore]
+    fn test_get_blocks_and_microblocks_2_peers_push_compact_blocks_and_microblocks_outbound_direct()
+    {
+        // simulates node 0 pushing compact blocks to node 1, but node 0 is publicly routable.
+        // node 1 has none of the blocks' transactions, so it must ask node 0 for them.
+        test_get_blocks_and_microblocks_2_peers_push_blocks_and_microblocks(true, false, true)
     }
 
     #[test]
@@ -0,0 +1,178 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Peer reputation scores.
+//!
+//! Each peer we talk to has a persistent reputation score, stored in the peer DB.  Conversations
+//! record `ReputationEvent`s as they happen, and the `PeerNetwork` periodically folds them into
+//! the stored score.  Scores decay linearly towards zero over time, so old misbehavior is
+//! eventually forgiven and old good behavior eventually has to be re-earned.
+//!
+//! A peer whose score drops to `REPUTATION_BAN_THRESHOLD` or below is banned (using the same
+//! exponentially-growing ban durations as before).  Peers with negative scores are not selected
+//! for neighbor walks, are pruned first, and are asked for blocks last.
+
+use std::cmp;
+
+/// Highest score a peer can reach
+pub const REPUTATION_MAX: i64 = 100;
+/// Lowest score a peer can reach
+pub const REPUTATION_MIN: i64 = -1000;
+/// A peer at or below this score gets banned
+pub const REPUTATION_BAN_THRESHOLD: i64 = -100;
+/// Number of seconds it takes for a score to decay by one point towards zero
+pub const REPUTATION_DECAY_INTERVAL: u64 = 60;
+
+/// Things a peer can do that change its reputation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReputationEvent {
+    /// The peer sent us a block or microblock stream that failed validation.
+    InvalidBlock,
+    /// The peer sent us a malformed or invalid message.
+    InvalidData,
+    /// The peer pushed more data at us than we allow, and was throttled.
+    UnsolicitedData,
+    /// A request we sent to the peer timed out.
+    SlowResponse,
+    /// The peer told us about a block or microblock stream we did not have.
+    UsefulInventory,
+    /// The peer pushed us a block for the burnchain tip's sortition.
+    TimelyBlockPush,
+}
+
+impl ReputationEvent {
+    /// How much this event changes a peer's score.
+    /// An invalid block is always enough to get a peer banned.
+    pub fn score_delta(&self) -> i64 {
+        match *self {
+            ReputationEvent::InvalidBlock => -200,
+            ReputationEvent::InvalidData => -50,
+            ReputationEvent::UnsolicitedData => -10,
+            ReputationEvent::SlowResponse => -5,
+            ReputationEvent::UsefulInventory => 1,
+            ReputationEvent::TimelyBlockPush => 5,
+        }
+    }
+}
+
+/// A peer's stored reputation
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerReputation {
+    /// score as of `last_update`
+    pub score: i64,
+    /// when the score was last written
+    pub last_update: u64,
+}
+
+impl PeerReputation {
+    pub fn new(now: u64) -> PeerReputation {
+        PeerReputation {
+            score: 0,
+            last_update: now,
+        }
+    }
+
+    /// Get the score as of `now`, after decaying it towards zero
+    pub fn score_at(&self, now: u64) -> i64 {
+        let elapsed = now.saturating_sub(self.last_update);
+        let decay = cmp::min(elapsed / REPUTATION_DECAY_INTERVAL, i64::MAX as u64) as i64;
+        if self.score > 0 {
+            cmp::max(self.score - decay, 0)
+        } else {
+            cmp::min(self.score + decay, 0)
+        }
+    }
+
+    /// Decay the score up to `now`, and then apply `delta` to it.
+    pub fn apply(&mut self, delta: i64, now: u64) -> () {
+        let score = self.score_at(now).saturating_add(delta);
+        self.score = score.clamp(REPUTATION_MIN, REPUTATION_MAX);
+        self.last_update = cmp::max(self.last_update, now);
+    }
+
+    /// Is this reputation bad enough to warrant a ban?
+    pub fn is_banned(&self, now: u64) -> bool {
+        self.score_at(now) <= REPUTATION_BAN_THRESHOLD
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reputation_decay() {
+        let rep = PeerReputation {
+            score: -10,
+            last_update: 1000,
+        };
+        assert_eq!(rep.score_at(1000), -10);
+        assert_eq!(rep.score_at(999), -10);
+        assert_eq!(rep.score_at(1000 + REPUTATION_DECAY_INTERVAL - 1), -10);
+        assert_eq!(rep.score_at(1000 + REPUTATION_DECAY_INTERVAL), -9);
+        assert_eq!(rep.score_at(1000 + 10 * REPUTATION_DECAY_INTERVAL), 0);
+        assert_eq!(rep.score_at(1000 + 20 * REPUTATION_DECAY_INTERVAL), 0);
+
+        let rep = PeerReputation {
+            score: 10,
+            last_update: 1000,
+        };
+        assert_eq!(rep.score_at(1000 + 3 * REPUTATION_DECAY_INTERVAL), 7);
+        assert_eq!(rep.score_at(1000 + 20 * REPUTATION_DECAY_INTERVAL), 0);
+        assert_eq!(rep.score_at(u64::MAX), 0);
+    }
+
+    #[test]
+    fn test_reputation_apply() {
+        let mut rep = PeerReputation::new(1000);
+        rep.apply(ReputationEvent::TimelyBlockPush.score_delta(), 1000);
+        assert_eq!(rep.score, 5);
+        assert!(!rep.is_banned(1000));
+
+        // decays before the delta is applied
+        rep.apply(
+            ReputationEvent::SlowResponse.score_delta(),
+            1000 + 2 * REPUTATION_DECAY_INTERVAL,
+        );
+        assert_eq!(rep.score, -2);
+        assert_eq!(rep.last_update, 1000 + 2 * REPUTATION_DECAY_INTERVAL);
+
+        // clamped at both ends
+        for _ in 0..1000 {
+            rep.apply(
+                ReputationEvent::UsefulInventory.score_delta(),
+                rep.last_update,
+            );
+        }
+        assert_eq!(rep.score, REPUTATION_MAX);
+        for _ in 0..100 {
+            rep.apply(ReputationEvent::InvalidBlock.score_delta(), rep.last_update);
+        }
+        assert_eq!(rep.score, REPUTATION_MIN);
+
+        // a single invalid block gets even the best peer banned
+        let mut rep = PeerReputation {
+            score: REPUTATION_MAX,
+            last_update: 1000,
+        };
+        rep.apply(ReputationEvent::InvalidBlock.score_delta(), 1000);
+        assert!(rep.is_banned(1000));
+
+        // ...but it's eventually forgiven
+        assert!(!rep.is_banned(1000 + REPUTATION_DECAY_INTERVAL));
+        assert_eq!(rep.score_at(1000 + 100 * REPUTATION_DECAY_INTERVAL), 0);
+    }
+}
@@ -23,7 +23,8 @@ use std::collections::VecDeque;
 use std::io;
 use std::io::prelude::*;
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::net::SocketAddr;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Instant;
 use std::{convert::TryFrom, fmt};
 
@@ -44,6 +45,7 @@ use crate::chainstate::stacks::Error as chain_error;
 use crate::chainstate::stacks::*;
 use crate::clarity_vm::clarity::ClarityConnection;
 use crate::codec::StacksMessageCodec;
+use crate::codec::MAX_PAYLOAD_LEN;
 use crate::core::mempool::*;
 use crate::cost_estimates::metrics::CostMetric;
 use crate::cost_estimates::CostEstimator;
@@ -54,10 +56,14 @@ use crate::net::connection::ConnectionHttp;
 use crate::net::connection::ConnectionOptions;
 use crate::net::connection::ReplyHandleHttp;
 use crate::net::db::PeerDB;
+use crate::net::eventstream::{EventStreamCursor, EventStreamHub, EventStreamTopic};
 use crate::net::http::*;
+use crate::net::p2p::BandwidthSample;
 use crate::net::p2p::PeerMap;
 use crate::net::p2p::PeerNetwork;
+use crate::net::p2p::BANDWIDTH_IBD_HEIGHT_LAG;
 use crate::net::relay::Relayer;
+use crate::net::BatchResponseItem;
 use crate::net::BlocksDatum;
 use crate::net::Error as net_error;
 use crate::net::HttpRequestMetadata;
@@ -85,6 +91,7 @@ use crate::net::MAX_NEIGHBORS_DATA_LEN;
 use crate::net::{
     AccountEntryResponse, AttachmentPage, CallReadOnlyResponse, ContractSrcResponse,
     DataVarResponse, GetAttachmentResponse, GetAttachmentsInvResponse, MapEntryResponse,
+    MarfProofsResponse,
 };
 use crate::net::{BlocksData, GetIsTraitImplementedResponse};
 use crate::net::{ClientError, TipRequest};
@@ -108,6 +115,7 @@ use clarity::vm::{
     types::{PrincipalData, QualifiedContractIdentifier, StandardPrincipalData},
     ClarityName, ContractName, SymbolicExpression, Value,
 };
+use stacks_common::util::get_epoch_time_ms;
 use stacks_common::util::get_epoch_time_secs;
 use stacks_common::util::hash::Hash160;
 use stacks_common::util::hash::{hex_bytes, to_hex};
@@ -139,6 +147,260 @@ pub struct RPCHandlerArgs<'a> {
     pub cost_metric: Option<&'a dyn CostMetric>,
 }
 
+/// Which of a client's rate limit budgets an RPC request is charged to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RPCRateLimitClass {
+    Cheap,
+    /// requests that run Clarity code, read a lot from the chainstate, or send a lot of data
+    Expensive,
+}
+
+impl RPCRateLimitClass {
+    pub fn from_request(req: &HttpRequestType) -> RPCRateLimitClass {
+        match req {
+            HttpRequestType::Batch(..)
+            | HttpRequestType::CallReadOnlyFunction(..)
+            | HttpRequestType::FeeRateEstimate(..)
+            | HttpRequestType::GetMarfProofs(..)
+            | HttpRequestType::MemPoolQuery(..)
+            | HttpRequestType::GetBlock(..)
+            | HttpRequestType::GetMicroblocksIndexed(..)
+            | HttpRequestType::GetMicroblocksConfirmed(..)
+            | HttpRequestType::GetMicroblocksUnconfirmed(..) => RPCRateLimitClass::Expensive,
+            _ => RPCRateLimitClass::Cheap,
+        }
+    }
+
+    /// How many tokens of each class a request costs.  A batch costs one token per request it
+    /// carries, each charged to that request's own class.
+    pub fn charges(req: &HttpRequestType) -> Vec<(RPCRateLimitClass, u64)> {
+        match req {
+            HttpRequestType::Batch(_, requests, _) => {
+                let num_expensive = requests
+                    .iter()
+                    .filter(|r| RPCRateLimitClass::from_request(r) == RPCRateLimitClass::Expensive)
+                    .count() as u64;
+                let num_cheap = (requests.len() as u64) - num_expensive;
+                vec![
+                    (RPCRateLimitClass::Cheap, num_cheap),
+                    (RPCRateLimitClass::Expensive, num_expensive),
+                ]
+                .into_iter()
+                .filter(|(_, num_tokens)| *num_tokens > 0)
+                .collect()
+            }
+            _ => vec![(RPCRateLimitClass::from_request(req), 1)],
+        }
+    }
+}
+
+/// Why a request was turned away by the rate limiter
+#[derive(Debug, Clone, PartialEq)]
+pub enum RPCRateLimitError {
+    /// the client presented an API key that we don't know
+    UnknownApiKey,
+    /// the client is out of budget, and should try again in this many seconds
+    Exceeded(u64),
+    /// the request (i.e. a batch) costs more than the client's budget can ever hold
+    TooExpensive,
+}
+
+/// Who a rate limit budget belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RPCClientId {
+    Addr(IpAddr),
+    ApiKey(String),
+}
+
+/// A token bucket holding up to a minute's worth of requests
+#[derive(Debug, Clone)]
+struct RPCTokenBucket {
+    per_minute: u64,
+    tokens: f64,
+    last_refill_ms: u128,
+}
+
+impl RPCTokenBucket {
+    fn new(per_minute: u64, now_ms: u128) -> RPCTokenBucket {
+        RPCTokenBucket {
+            per_minute,
+            tokens: per_minute as f64,
+            last_refill_ms: now_ms,
+        }
+    }
+
+    fn tokens_per_ms(&self) -> f64 {
+        (self.per_minute as f64) / 60_000.0
+    }
+
+    fn refill(&mut self, now_ms: u128) {
+        let elapsed_ms = now_ms.saturating_sub(self.last_refill_ms) as f64;
+        self.tokens = (self.tokens + elapsed_ms * self.tokens_per_ms()).min(self.per_minute as f64);
+        self.last_refill_ms = now_ms;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.per_minute as f64
+    }
+
+    /// Check that there are `num_tokens` tokens to take.  If there aren't, return how many
+    /// seconds until there will be.
+    fn check(&mut self, num_tokens: u64, now_ms: u128) -> Result<(), u64> {
+        self.refill(now_ms);
+        if self.tokens >= num_tokens as f64 {
+            Ok(())
+        } else {
+            let wait_ms = (num_tokens as f64 - self.tokens) / self.tokens_per_ms();
+            Err(((wait_ms / 1000.0).ceil() as u64).max(1))
+        }
+    }
+
+    /// Take `num_tokens` tokens.  If there aren't enough, take none, and return how many seconds
+    /// until there will be.
+    fn take(&mut self, num_tokens: u64, now_ms: u128) -> Result<(), u64> {
+        self.check(num_tokens, now_ms)?;
+        self.tokens -= num_tokens as f64;
+        Ok(())
+    }
+}
+
+/// Per-client token bucket rate limiting of RPC requests.  Clients are identified by their API key
+/// if they present one, and by their IP address otherwise.
+#[derive(Debug, Clone)]
+pub struct RPCRateLimiter {
+    rate_limit_cheap: u64,
+    rate_limit_expensive: u64,
+    api_keys: HashSet<String>,
+    api_key_rate_limit_cheap: u64,
+    api_key_rate_limit_expensive: u64,
+    buckets: HashMap<(RPCClientId, RPCRateLimitClass), RPCTokenBucket>,
+    last_prune_ms: u128,
+}
+
+impl RPCRateLimiter {
+    /// How often to forget about clients whose buckets have refilled, in milliseconds
+    const PRUNE_INTERVAL_MS: u128 = 60_000;
+
+    pub fn new(opts: &ConnectionOptions) -> RPCRateLimiter {
+        RPCRateLimiter {
+            rate_limit_cheap: opts.rpc_rate_limit_cheap,
+            rate_limit_expensive: opts.rpc_rate_limit_expensive,
+            api_keys: opts.rpc_api_keys.clone(),
+            api_key_rate_limit_cheap: opts.rpc_api_key_rate_limit_cheap,
+            api_key_rate_limit_expensive: opts.rpc_api_key_rate_limit_expensive,
+            buckets: HashMap::new(),
+            last_prune_ms: 0,
+        }
+    }
+
+    /// Charge a request of the given class to a client.  API keys are ignored if none are
+    /// configured.
+    pub fn admit(
+        &mut self,
+        addr: &IpAddr,
+        api_key: Option<&String>,
+        class: RPCRateLimitClass,
+        now_ms: u128,
+    ) -> Result<(), RPCRateLimitError> {
+        self.admit_charges(addr, api_key, &[(class, 1)], now_ms)
+    }
+
+    /// Charge a request to a client.  A batch is only admitted if the client can afford all of
+    /// the requests in it, and nothing is charged if it can't.
+    pub fn admit_request(
+        &mut self,
+        addr: &IpAddr,
+        api_key: Option<&String>,
+        req: &HttpRequestType,
+        now_ms: u128,
+    ) -> Result<(), RPCRateLimitError> {
+        self.admit_charges(addr, api_key, &RPCRateLimitClass::charges(req), now_ms)
+    }
+
+    /// Take the given number of tokens of each class from a client's buckets, or none at all if
+    /// any of them can't cover its share.
+    fn admit_charges(
+        &mut self,
+        addr: &IpAddr,
+        api_key: Option<&String>,
+        charges: &[(RPCRateLimitClass, u64)],
+        now_ms: u128,
+    ) -> Result<(), RPCRateLimitError> {
+        let (client_id, per_minute_cheap, per_minute_expensive) = match api_key {
+            Some(api_key) if !self.api_keys.is_empty() => {
+                if !self.api_keys.contains(api_key) {
+                    return Err(RPCRateLimitError::UnknownApiKey);
+                }
+                (
+                    RPCClientId::ApiKey(api_key.clone()),
+                    self.api_key_rate_limit_cheap,
+                    self.api_key_rate_limit_expensive,
+                )
+            }
+            _ => (
+                RPCClientId::Addr(*addr),
+                self.rate_limit_cheap,
+                self.rate_limit_expensive,
+            ),
+        };
+
+        // classes with a per-minute limit of 0 are unlimited
+        let charges: Vec<_> = charges
+            .iter()
+            .map(|(class, num_tokens)| {
+                let per_minute = match class {
+                    RPCRateLimitClass::Cheap => per_minute_cheap,
+                    RPCRateLimitClass::Expensive => per_minute_expensive,
+                };
+                (*class, *num_tokens, per_minute)
+            })
+            .filter(|(_, _, per_minute)| *per_minute > 0)
+            .collect();
+
+        if charges
+            .iter()
+            .any(|(_, num_tokens, per_minute)| num_tokens > per_minute)
+        {
+            return Err(RPCRateLimitError::TooExpensive);
+        }
+
+        self.prune(now_ms);
+        for (class, num_tokens, per_minute) in charges.iter() {
+            self.buckets
+                .entry((client_id.clone(), *class))
+                .or_insert_with(|| RPCTokenBucket::new(*per_minute, now_ms))
+                .check(*num_tokens, now_ms)
+                .map_err(RPCRateLimitError::Exceeded)?;
+        }
+        for (class, num_tokens, _) in charges.iter() {
+            if let Some(bucket) = self.buckets.get_mut(&(client_id.clone(), *class)) {
+                bucket
+                    .take(*num_tokens, now_ms)
+                    .map_err(RPCRateLimitError::Exceeded)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget clients that have not been heard from in long enough for their buckets to fill back
+    /// up, so the table doesn't grow without bound.
+    fn prune(&mut self, now_ms: u128) {
+        if self.last_prune_ms + RPCRateLimiter::PRUNE_INTERVAL_MS > now_ms {
+            return;
+        }
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now_ms);
+            !bucket.is_full()
+        });
+        self.last_prune_ms = now_ms;
+    }
+
+    #[cfg(test)]
+    fn num_clients(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
 pub struct ConversationHttp {
     connection: ConnectionHttp,
     conn_id: usize,
@@ -162,10 +424,24 @@ pub struct ConversationHttp {
         bool,
     )>,
 
+    // ongoing server-sent event stream.  It's sent once all other replies are done, and it
+    // doesn't end until the connection does.
+    event_stream: Option<(
+        ReplyHandleHttp,
+        HttpChunkedTransferWriterState,
+        EventStreamCursor,
+    )>,
+
     // our outstanding request/response to the remote peer, if any
     pending_request: Option<ReplyHandleHttp>,
     pending_response: Option<HttpResponseType>,
     pending_error_response: Option<HttpResponseType>,
+
+    // a request we're holding until we have the upload bandwidth to serve it
+    deferred_request: Option<HttpRequestType>,
+
+    // traffic not yet collected by the PeerNetwork's bandwidth manager
+    bandwidth: BandwidthSample,
 }
 
 impl fmt::Display for ConversationHttp {
@@ -454,16 +730,26 @@ impl RPCNeighborsInfo {
         )
         .map_err(net_error::DBError)?;
 
-        let sample: Vec<RPCNeighbor> = neighbor_sample
-            .into_iter()
-            .map(|n| {
-                RPCNeighbor::from_neighbor_key_and_pubkh(
-                    n.addr.clone(),
-                    Hash160::from_node_public_key(&n.public_key),
-                    true,
-                )
-            })
-            .collect();
+        let now = get_epoch_time_secs();
+        let mut sample = vec![];
+        for n in neighbor_sample.into_iter() {
+            let reputation = PeerDB::get_peer_reputation(
+                peerdb.conn(),
+                n.addr.network_id,
+                &n.addr.addrbytes,
+                n.addr.port,
+            )
+            .map_err(net_error::DBError)?
+            .map(|rep| rep.score_at(now))
+            .unwrap_or(0);
+
+            sample.push(RPCNeighbor::from_neighbor_key_and_pubkh(
+                n.addr.clone(),
+                Hash160::from_node_public_key(&n.public_key),
+                true,
+                reputation,
+            ));
+        }
 
         let mut inbound = vec![];
         let mut outbound = vec![];
@@ -475,12 +761,14 @@ impl RPCNeighborsInfo {
                     nk,
                     naddr.public_key_hash,
                     convo.is_authenticated(),
+                    convo.stats.get_reputation(),
                 ));
             } else {
                 inbound.push(RPCNeighbor::from_neighbor_key_and_pubkh(
                     nk,
                     naddr.public_key_hash,
                     convo.is_authenticated(),
+                    convo.stats.get_reputation(),
                 ));
             }
         }
@@ -508,6 +796,7 @@ impl ConversationHttp {
             conn_id: conn_id,
             timeout: conn_opts.timeout,
             reply_streams: VecDeque::new(),
+            event_stream: None,
             peer_addr: peer_addr,
             outbound_url: outbound_url,
             peer_host: peer_host,
@@ -515,6 +804,8 @@ impl ConversationHttp {
             pending_request: None,
             pending_response: None,
             pending_error_response: None,
+            deferred_request: None,
+            bandwidth: BandwidthSample::default(),
             keep_alive: true,
             total_request_count: 0,
             total_reply_count: 0,
@@ -539,6 +830,35 @@ impl ConversationHttp {
         &self.peer_addr
     }
 
+    /// Take the traffic moved since the last call, for the bandwidth manager
+    pub fn take_bandwidth(&mut self) -> BandwidthSample {
+        mem::take(&mut self.bandwidth)
+    }
+
+    /// Does this request serve blocks or microblocks, and so wait when we're out of upload
+    /// bandwidth?
+    fn is_deferrable_request(req: &HttpRequestType) -> bool {
+        match req {
+            HttpRequestType::GetBlock(..)
+            | HttpRequestType::GetMicroblocksIndexed(..)
+            | HttpRequestType::GetMicroblocksConfirmed(..)
+            | HttpRequestType::GetMicroblocksUnconfirmed(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Is the peer that sent this request still catching up to our chain tip?  Peers that don't
+    /// say how far along they are are assumed to be.
+    fn is_requester_in_ibd(network: &PeerNetwork, req: &HttpRequestType) -> bool {
+        match req.metadata().canonical_stacks_tip_height {
+            Some(height) => {
+                height.saturating_add(BANDWIDTH_IBD_HEIGHT_LAG)
+                    < network.burnchain_tip.canonical_stacks_tip_height
+            }
+            None => true,
+        }
+    }
+
     /// Is a request in-progress?
     pub fn is_request_inflight(&self) -> bool {
         self.pending_request.is_some()
@@ -1319,6 +1639,55 @@ impl ConversationHttp {
         response.send(http, fd).map(|_| ())
     }
 
+    /// Handle a POST to look up a batch of MARF keys at the given chain tip, and prove each key's
+    /// value or absence.  Returns a MarfProofsResponse on success.
+    fn handle_get_marf_proofs<W: Write>(
+        http: &mut StacksHttp,
+        fd: &mut W,
+        req: &HttpRequestType,
+        sortdb: &SortitionDB,
+        chainstate: &mut StacksChainState,
+        tip: &StacksBlockId,
+        keys: &[String],
+        canonical_stacks_tip_height: u64,
+    ) -> Result<(), net_error> {
+        let response_metadata =
+            HttpResponseMetadata::from_http_request_type(req, Some(canonical_stacks_tip_height));
+
+        let response =
+            match chainstate.maybe_read_only_clarity_tx(&sortdb.index_conn(), tip, |clarity_tx| {
+                clarity_tx
+                    .with_clarity_db_readonly(|clarity_db| clarity_db.get_batch_with_proof(keys))
+            }) {
+                Ok(Some(Ok(Some((values, proof))))) => HttpResponseType::GetMarfProofs(
+                    response_metadata,
+                    MarfProofsResponse {
+                        values,
+                        proof: format!("0x{}", to_hex(&proof)),
+                    },
+                ),
+                Ok(Some(Ok(None))) | Ok(None) => {
+                    HttpResponseType::NotFound(response_metadata, "Chain tip not found".into())
+                }
+                Ok(Some(Err(e))) => {
+                    warn!("Failed to get MARF proofs at {}: {:?}", tip, &e);
+                    HttpResponseType::ServerError(
+                        response_metadata,
+                        format!("Failed to get MARF proofs at {}", tip),
+                    )
+                }
+                Err(e) => {
+                    warn!("Failed to get MARF proofs at {}: {:?}", tip, &e);
+                    HttpResponseType::ServerError(
+                        response_metadata,
+                        format!("Failed to get MARF proofs at {}", tip),
+                    )
+                }
+            };
+
+        response.send(http, fd).map(|_| ())
+    }
+
     /// Handle a POST to run a read-only function call with the given parameters on the given chain
     /// tip.  Returns the result of the function call.  Returns a CallReadOnlyResponse on success.
     fn handle_readonly_function_call<W: Write>(
@@ -2239,6 +2608,299 @@ impl ConversationHttp {
         response.send(http, fd).and_then(|_| Ok(stream))
     }
 
+    /// Handle a request to subscribe to an event stream.
+    /// Returns the cursor to stream events from once the preamble is sent, or None if event
+    /// streams are disabled or there are already as many subscribers as we allow (in which case
+    /// an error reply is sent instead).
+    fn handle_get_event_stream<W: Write>(
+        http: &mut StacksHttp,
+        fd: &mut W,
+        req: &HttpRequestType,
+        event_streams: Option<&EventStreamHub>,
+        topic: &EventStreamTopic,
+        heartbeat: u64,
+        canonical_stacks_tip_height: u64,
+    ) -> Result<Option<EventStreamCursor>, net_error> {
+        let response_metadata =
+            HttpResponseMetadata::from_http_request_type(req, Some(canonical_stacks_tip_height));
+        let hub = match event_streams {
+            Some(hub) => hub,
+            None => {
+                let response = HttpResponseType::NotFound(
+                    response_metadata,
+                    "Event streams are not enabled".to_string(),
+                );
+                return response.send(http, fd).and_then(|_| Ok(None));
+            }
+        };
+
+        match hub.subscribe(topic.clone(), heartbeat, get_epoch_time_secs()) {
+            Some(cursor) => {
+                debug!("Begin event stream"; "topic" => ?topic);
+                let response = HttpResponseType::EventStream(response_metadata);
+                response.send(http, fd).and_then(|_| Ok(Some(cursor)))
+            }
+            None => {
+                let response = HttpResponseType::ServiceUnavailable(
+                    response_metadata,
+                    "Too many event streams".to_string(),
+                );
+                response.send(http, fd).and_then(|_| Ok(None))
+            }
+        }
+    }
+
+    /// Turn away a request that the rate limiter did not admit
+    fn handle_rate_limited<W: Write>(
+        http: &mut StacksHttp,
+        fd: &mut W,
+        req: &HttpRequestType,
+        error: RPCRateLimitError,
+        canonical_stacks_tip_height: u64,
+    ) -> Result<(), net_error> {
+        let response_metadata =
+            HttpResponseMetadata::from_http_request_type(req, Some(canonical_stacks_tip_height));
+        let response = match error {
+            RPCRateLimitError::UnknownApiKey => {
+                HttpResponseType::Unauthorized(response_metadata, "Unknown API key".to_string())
+            }
+            RPCRateLimitError::TooExpensive => HttpResponseType::BadRequest(
+                response_metadata,
+                "Request costs more than the rate limit allows".to_string(),
+            ),
+            RPCRateLimitError::Exceeded(retry_after) => {
+                debug!("Rate-limited RPC request"; "path" => %req.request_path(), "retry_after" => retry_after);
+                HttpResponseType::TooManyRequests(
+                    response_metadata,
+                    retry_after,
+                    "Too many requests".to_string(),
+                )
+            }
+        };
+        response.send(http, fd)
+    }
+
+    /// Handle a POST of a batch of read requests.  Every request in the batch is answered from the
+    /// same chain tip, and the answers are sent back together as a list of status codes and JSON
+    /// bodies.  Once the answers reach MAX_PAYLOAD_LEN bytes, the remaining requests are turned
+    /// away with a 503 rather than answered.
+    fn handle_batch<W: Write>(
+        http: &mut StacksHttp,
+        fd: &mut W,
+        req: &HttpRequestType,
+        sortdb: &SortitionDB,
+        chainstate: &mut StacksChainState,
+        tip: &StacksBlockId,
+        requests: &[HttpRequestType],
+        options: &ConnectionOptions,
+        canonical_stacks_tip_height: u64,
+    ) -> Result<(), net_error> {
+        let response_metadata =
+            HttpResponseMetadata::from_http_request_type(req, Some(canonical_stacks_tip_height));
+
+        let mut items = Vec::with_capacity(requests.len());
+        let mut total_len = 0;
+        for request in requests.iter() {
+            if total_len >= MAX_PAYLOAD_LEN as usize {
+                items.push(BatchResponseItem {
+                    status: 503,
+                    body: serde_json::Value::String("batch response limit reached".to_string()),
+                });
+                continue;
+            }
+
+            let mut response_bytes = vec![];
+            let item = ConversationHttp::handle_batch_request(
+                http,
+                &mut response_bytes,
+                request,
+                sortdb,
+                chainstate,
+                tip,
+                options,
+                canonical_stacks_tip_height,
+            )
+            .and_then(|_| BatchResponseItem::from_http_response(&response_bytes))
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Failed to handle batched request {}: {:?}",
+                    request.get_path(),
+                    &e
+                );
+                BatchResponseItem {
+                    status: 500,
+                    body: serde_json::Value::String(format!("Failed to handle request: {}", e)),
+                }
+            });
+
+            total_len += response_bytes.len();
+            items.push(item);
+        }
+
+        let response = HttpResponseType::Batch(response_metadata, items);
+        response.send(http, fd)
+    }
+
+    /// Answer one request of a batch from the batch's chain tip.  Only requests for which
+    /// HttpRequestType::is_batchable() is true are ever passed here.
+    fn handle_batch_request<W: Write>(
+        http: &mut StacksHttp,
+        fd: &mut W,
+        req: &HttpRequestType,
+        sortdb: &SortitionDB,
+        chainstate: &mut StacksChainState,
+        tip: &StacksBlockId,
+        options: &ConnectionOptions,
+        canonical_stacks_tip_height: u64,
+    ) -> Result<(), net_error> {
+        match req {
+            HttpRequestType::GetAccount(_, principal, _, with_proof) => {
+                ConversationHttp::handle_get_account_entry(
+                    http,
+                    fd,
+                    req,
+                    sortdb,
+                    chainstate,
+                    tip,
+                    principal,
+                    *with_proof,
+                    canonical_stacks_tip_height,
+                )
+            }
+            HttpRequestType::GetDataVar(
+                _,
+                contract_addr,
+                contract_name,
+                var_name,
+                _,
+                with_proof,
+            ) => ConversationHttp::handle_get_data_var(
+                http,
+                fd,
+                req,
+                sortdb,
+                chainstate,
+                tip,
+                contract_addr,
+                contract_name,
+                var_name,
+                *with_proof,
+                canonical_stacks_tip_height,
+            ),
+            HttpRequestType::GetMapEntry(
+                _,
+                contract_addr,
+                contract_name,
+                map_name,
+                key,
+                _,
+                with_proof,
+            ) => ConversationHttp::handle_get_map_entry(
+                http,
+                fd,
+                req,
+                sortdb,
+                chainstate,
+                tip,
+                contract_addr,
+                contract_name,
+                map_name,
+                key,
+                *with_proof,
+                canonical_stacks_tip_height,
+            ),
+            HttpRequestType::GetMarfProofs(_, keys, _) => ConversationHttp::handle_get_marf_proofs(
+                http,
+                fd,
+                req,
+                sortdb,
+                chainstate,
+                tip,
+                keys,
+                canonical_stacks_tip_height,
+            ),
+            HttpRequestType::CallReadOnlyFunction(
+                _,
+                ctrct_addr,
+                ctrct_name,
+                as_sender,
+                func_name,
+                args,
+                _,
+            ) => ConversationHttp::handle_readonly_function_call(
+                http,
+                fd,
+                req,
+                sortdb,
+                chainstate,
+                tip,
+                ctrct_addr,
+                ctrct_name,
+                func_name,
+                as_sender,
+                args,
+                options,
+                canonical_stacks_tip_height,
+            ),
+            HttpRequestType::GetContractSrc(_, contract_addr, contract_name, _, with_proof) => {
+                ConversationHttp::handle_get_contract_src(
+                    http,
+                    fd,
+                    req,
+                    sortdb,
+                    chainstate,
+                    tip,
+                    contract_addr,
+                    contract_name,
+                    *with_proof,
+                    canonical_stacks_tip_height,
+                )
+            }
+            HttpRequestType::GetContractABI(_, contract_addr, contract_name, _) => {
+                ConversationHttp::handle_get_contract_abi(
+                    http,
+                    fd,
+                    req,
+                    sortdb,
+                    chainstate,
+                    tip,
+                    contract_addr,
+                    contract_name,
+                    canonical_stacks_tip_height,
+                )
+            }
+            HttpRequestType::GetIsTraitImplemented(
+                _,
+                contract_addr,
+                contract_name,
+                trait_id,
+                _,
+            ) => ConversationHttp::handle_get_is_trait_implemented(
+                http,
+                fd,
+                req,
+                sortdb,
+                chainstate,
+                tip,
+                contract_addr,
+                contract_name,
+                trait_id,
+                canonical_stacks_tip_height,
+            ),
+            _ => {
+                let response_metadata = HttpResponseMetadata::from_http_request_type(
+                    req,
+                    Some(canonical_stacks_tip_height),
+                );
+                let response = HttpResponseType::BadRequest(
+                    response_metadata,
+                    format!("{} requests can't be batched", req.get_path()),
+                );
+                response.send(http, fd)
+            }
+        }
+    }
+
     /// Handle an external HTTP request.
     /// Some requests, such as those for blocks, will create new reply streams.  This method adds
     /// those new streams into the `reply_streams` set.
@@ -2255,7 +2917,26 @@ impl ConversationHttp {
     ) -> Result<Option<StacksMessageType>, net_error> {
         let mut reply = self.connection.make_relay_handle(self.conn_id)?;
         let keep_alive = req.metadata().keep_alive;
+        let gzip = req.wants_gzip();
         let mut ret = None;
+        let mut event_stream_opt = None;
+
+        if let Err(e) = network.rpc_rate_limiter.admit_request(
+            &self.peer_addr.ip(),
+            req.metadata().api_key.as_ref(),
+            &req,
+            get_epoch_time_ms(),
+        ) {
+            ConversationHttp::handle_rate_limited(
+                &mut self.connection.protocol,
+                &mut reply,
+                &req,
+                e,
+                network.burnchain_tip.canonical_stacks_tip_height,
+            )?;
+            self.reply_streams.push_back((reply, None, keep_alive));
+            return Ok(ret);
+        }
 
         let stream_opt = match req {
             HttpRequestType::GetInfo(ref _md) => {
@@ -2473,6 +3154,29 @@ impl ConversationHttp {
                 }
                 None
             }
+            HttpRequestType::GetMarfProofs(ref _md, ref keys, ref tip_req) => {
+                if let Some(tip) = ConversationHttp::handle_load_stacks_chain_tip(
+                    &mut self.connection.protocol,
+                    &mut reply,
+                    &req,
+                    tip_req,
+                    sortdb,
+                    chainstate,
+                    network.burnchain_tip.canonical_stacks_tip_height,
+                )? {
+                    ConversationHttp::handle_get_marf_proofs(
+                        &mut self.connection.protocol,
+                        &mut reply,
+                        &req,
+                        sortdb,
+                        chainstate,
+                        &tip,
+                        keys,
+                        network.burnchain_tip.canonical_stacks_tip_height,
+                    )?;
+                }
+                None
+            }
             HttpRequestType::GetTransferCost(ref _md) => {
                 ConversationHttp::handle_token_transfer_cost(
                     &mut self.connection.protocol,
@@ -2747,13 +3451,38 @@ impl ConversationHttp {
                     .map(|_| ())?;
                 None
             }
-            HttpRequestType::GetIsTraitImplemented(
-                ref _md,
-                ref contract_addr,
-                ref contract_name,
-                ref trait_id,
-                ref tip_req,
-            ) => {
+            HttpRequestType::GetIsTraitImplemented(
+                ref _md,
+                ref contract_addr,
+                ref contract_name,
+                ref trait_id,
+                ref tip_req,
+            ) => {
+                if let Some(tip) = ConversationHttp::handle_load_stacks_chain_tip(
+                    &mut self.connection.protocol,
+                    &mut reply,
+                    &req,
+                    tip_req,
+                    sortdb,
+                    chainstate,
+                    network.burnchain_tip.canonical_stacks_tip_height,
+                )? {
+                    ConversationHttp::handle_get_is_trait_implemented(
+                        &mut self.connection.protocol,
+                        &mut reply,
+                        &req,
+                        sortdb,
+                        chainstate,
+                        &tip,
+                        contract_addr,
+                        contract_name,
+                        trait_id,
+                        network.burnchain_tip.canonical_stacks_tip_height,
+                    )?;
+                }
+                None
+            }
+            HttpRequestType::Batch(ref _md, ref requests, ref tip_req) => {
                 if let Some(tip) = ConversationHttp::handle_load_stacks_chain_tip(
                     &mut self.connection.protocol,
                     &mut reply,
@@ -2763,16 +3492,42 @@ impl ConversationHttp {
                     chainstate,
                     network.burnchain_tip.canonical_stacks_tip_height,
                 )? {
-                    ConversationHttp::handle_get_is_trait_implemented(
+                    ConversationHttp::handle_batch(
                         &mut self.connection.protocol,
                         &mut reply,
                         &req,
                         sortdb,
                         chainstate,
                         &tip,
-                        contract_addr,
-                        contract_name,
-                        trait_id,
+                        requests,
+                        &self.connection.options,
+                        network.burnchain_tip.canonical_stacks_tip_height,
+                    )?;
+                }
+                None
+            }
+            HttpRequestType::GetEventStream(ref _md, ref topic) => {
+                if self.event_stream.is_some() {
+                    let response_metadata = HttpResponseMetadata::from_http_request_type(
+                        &req,
+                        Some(network.burnchain_tip.canonical_stacks_tip_height),
+                    );
+                    let response = HttpResponseType::BadRequest(
+                        response_metadata,
+                        "Connection already has an event stream".to_string(),
+                    );
+                    response
+                        .send(&mut self.connection.protocol, &mut reply)
+                        .map(|_| ())?;
+                } else {
+                    event_stream_opt = ConversationHttp::handle_get_event_stream(
+                        &mut self.connection.protocol,
+                        &mut reply,
+                        &req,
+                        network.event_streams.as_ref(),
+                        topic,
+                        // stay well clear of the idle timeout
+                        network.connection_opts.idle_timeout / 2,
                         network.burnchain_tip.canonical_stacks_tip_height,
                     )?;
                 }
@@ -2800,19 +3555,28 @@ impl ConversationHttp {
             }
         };
 
+        if let Some(cursor) = event_stream_opt {
+            self.event_stream = Some((
+                reply,
+                HttpChunkedTransferWriterState::new(STREAM_CHUNK_SIZE as usize),
+                cursor,
+            ));
+            return Ok(ret);
+        }
+
         match stream_opt {
             None => {
                 self.reply_streams.push_back((reply, None, keep_alive));
             }
             Some(stream) => {
-                self.reply_streams.push_back((
-                    reply,
-                    Some((
-                        HttpChunkedTransferWriterState::new(STREAM_CHUNK_SIZE as usize),
-                        stream,
-                    )),
-                    keep_alive,
-                ));
+                // the preamble already told the client whether or not the body is compressed
+                let write_state = if gzip {
+                    HttpChunkedTransferWriterState::new_gzip(STREAM_CHUNK_SIZE as usize)
+                } else {
+                    HttpChunkedTransferWriterState::new(STREAM_CHUNK_SIZE as usize)
+                };
+                self.reply_streams
+                    .push_back((reply, Some((write_state, stream)), keep_alive));
             }
         }
         Ok(ret)
@@ -2942,6 +3706,33 @@ impl ConversationHttp {
                 self.keep_alive = false;
             }
         }
+
+        if self.reply_streams.len() == 0 {
+            self.send_event_stream()?;
+        }
+        Ok(())
+    }
+
+    /// Make progress on our event stream, if we have one.
+    /// New events are only written once the ones before them have been moved along to the
+    /// connection, so a slow client falls behind the hub rather than buffering events here.
+    fn send_event_stream(&mut self) -> Result<(), net_error> {
+        let (reply, chunk_state, cursor) = match self.event_stream.as_mut() {
+            Some(event_stream) => event_stream,
+            None => {
+                return Ok(());
+            }
+        };
+
+        if !reply.try_flush_buffered()? {
+            return Ok(());
+        }
+
+        let mut encoder = HttpChunkedTransferWriter::from_writer_state(reply, chunk_state);
+        let nw = cursor.stream_to(&mut encoder, get_epoch_time_secs())?;
+        if nw > 0 {
+            encoder.flush_chunk_data().map_err(net_error::WriteError)?;
+        }
         Ok(())
     }
 
@@ -3022,9 +3813,16 @@ impl ConversationHttp {
     /// Is the connection idle?
     pub fn is_idle(&self) -> bool {
         self.pending_response.is_none()
+            && self.deferred_request.is_none()
             && self.connection.inbox_len() == 0
             && self.connection.outbox_len() == 0
             && self.reply_streams.len() == 0
+            && self.event_stream.is_none()
+    }
+
+    /// Are we streaming events to the remote peer?
+    pub fn is_event_stream(&self) -> bool {
+        self.event_stream.is_some()
     }
 
     /// Is the conversation out of pending data?
@@ -3070,21 +3868,43 @@ impl ConversationHttp {
             return Ok(vec![]);
         }
 
-        // handle in-bound HTTP request(s)
-        let num_inbound = self.connection.inbox_len();
+        // handle in-bound HTTP request(s), starting with any we deferred
+        let mut deferred_request = self.deferred_request.take();
+        let num_inbound = self.connection.inbox_len() + (deferred_request.is_some() as usize);
         let mut ret = vec![];
         test_debug!("{:?}: {} HTTP requests pending", &self, num_inbound);
 
         for _i in 0..num_inbound {
-            let msg = match self.connection.next_inbox_message() {
-                None => {
-                    continue;
-                }
-                Some(m) => m,
+            let msg = match deferred_request.take() {
+                Some(req) => StacksHttpMessage::Request(req),
+                None => match self.connection.next_inbox_message() {
+                    None => {
+                        continue;
+                    }
+                    Some(m) => m,
+                },
             };
 
             match msg {
                 StacksHttpMessage::Request(req) => {
+                    let addr = PeerAddress::from_socketaddr(&self.peer_addr);
+                    if ConversationHttp::is_deferrable_request(&req)
+                        && ConversationHttp::is_requester_in_ibd(network, &req)
+                        && !network.bandwidth.can_upload(&addr)
+                    {
+                        // out of upload bandwidth, and this peer is doing its initial block
+                        // download.  Hold this request (and the ones behind it) until there's
+                        // room.
+                        debug!(
+                            "{:?}: out of upload bandwidth; deferring {}",
+                            &self,
+                            req.request_path()
+                        );
+                        network.bandwidth.defer_upload(&addr, Some(self.conn_id));
+                        self.deferred_request = Some(req);
+                        break;
+                    }
+
                     // new request
                     self.total_request_count += 1;
                     self.last_request_timestamp = get_epoch_time_secs();
@@ -3158,6 +3978,7 @@ impl ConversationHttp {
             total_recv += nrecv;
             if nrecv > 0 {
                 self.last_request_timestamp = get_epoch_time_secs();
+                self.bandwidth.bytes_rx += nrecv as u64;
             } else {
                 break;
             }
@@ -3189,11 +4010,12 @@ impl ConversationHttp {
             total_sz += sz;
             if sz > 0 {
                 self.last_response_timestamp = get_epoch_time_secs();
+                self.bandwidth.bytes_tx += sz as u64;
             } else {
                 break;
             }
         }
-        monitoring::update_inbound_rpc_bandwidth(total_sz as i64);
+        monitoring::update_outbound_rpc_bandwidth(total_sz as i64);
         Ok(total_sz)
     }
 
@@ -3364,6 +4186,28 @@ impl ConversationHttp {
         )
     }
 
+    /// Make a new request to prove a batch of MARF keys
+    pub fn new_getmarfproofs(&self, keys: Vec<String>, tip_req: TipRequest) -> HttpRequestType {
+        HttpRequestType::GetMarfProofs(
+            HttpRequestMetadata::from_host(self.peer_host.clone(), None),
+            keys,
+            tip_req,
+        )
+    }
+
+    /// Make a new request to run a batch of read requests against one chain tip
+    pub fn new_batch(
+        &self,
+        requests: Vec<HttpRequestType>,
+        tip_req: TipRequest,
+    ) -> HttpRequestType {
+        HttpRequestType::Batch(
+            HttpRequestMetadata::from_host(self.peer_host.clone(), None),
+            requests,
+            tip_req,
+        )
+    }
+
     /// Make a new request to get a contract's source
     pub fn new_getcontractsrc(
         &self,
@@ -3465,6 +4309,7 @@ mod test {
     use crate::net::http::*;
     use crate::net::test::*;
     use crate::net::*;
+    use clarity::vm::database::{ClarityDatabase, ClaritySerializable, StoreType};
     use clarity::vm::types::*;
     use stacks_common::address::*;
     use stacks_common::util::get_epoch_time_secs;
@@ -3540,6 +4385,185 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_rpc_rate_limiter() {
+        let mut opts = ConnectionOptions::default();
+        opts.rpc_rate_limit_cheap = 2;
+        opts.rpc_rate_limit_expensive = 1;
+
+        let addr_1: IpAddr = "1.2.3.4".parse().unwrap();
+        let addr_2: IpAddr = "5.6.7.8".parse().unwrap();
+        let api_key = "hello-api-key".to_string();
+
+        // no API keys configured, so API keys are ignored
+        let mut limiter = RPCRateLimiter::new(&opts);
+        let now = 1_000_000;
+        assert!(limiter
+            .admit(&addr_1, None, RPCRateLimitClass::Cheap, now)
+            .is_ok());
+        assert!(limiter
+            .admit(&addr_1, Some(&api_key), RPCRateLimitClass::Cheap, now)
+            .is_ok());
+
+        // out of cheap requests; one refills every 30 seconds
+        assert_eq!(
+            limiter.admit(&addr_1, None, RPCRateLimitClass::Cheap, now),
+            Err(RPCRateLimitError::Exceeded(30))
+        );
+        assert_eq!(
+            limiter.admit(&addr_1, None, RPCRateLimitClass::Cheap, now + 20_000),
+            Err(RPCRateLimitError::Exceeded(10))
+        );
+        assert!(limiter
+            .admit(&addr_1, None, RPCRateLimitClass::Cheap, now + 30_000)
+            .is_ok());
+
+        // expensive requests have their own budget
+        assert!(limiter
+            .admit(&addr_1, None, RPCRateLimitClass::Expensive, now)
+            .is_ok());
+        assert_eq!(
+            limiter.admit(&addr_1, None, RPCRateLimitClass::Expensive, now),
+            Err(RPCRateLimitError::Exceeded(60))
+        );
+
+        // so do other clients
+        assert!(limiter
+            .admit(&addr_2, None, RPCRateLimitClass::Expensive, now)
+            .is_ok());
+        assert_eq!(limiter.num_clients(), 3);
+
+        // idle clients are forgotten once their buckets refill
+        assert!(limiter
+            .admit(&addr_2, None, RPCRateLimitClass::Cheap, now + 120_000)
+            .is_ok());
+        assert_eq!(limiter.num_clients(), 1);
+
+        // API key holders get their own budgets, and unknown keys are refused
+        opts.rpc_api_keys.insert(api_key.clone());
+        opts.rpc_api_key_rate_limit_cheap = 0;
+        opts.rpc_api_key_rate_limit_expensive = 3;
+        let mut limiter = RPCRateLimiter::new(&opts);
+
+        assert_eq!(
+            limiter.admit(
+                &addr_1,
+                Some(&"bad-api-key".to_string()),
+                RPCRateLimitClass::Cheap,
+                now
+            ),
+            Err(RPCRateLimitError::UnknownApiKey)
+        );
+        for _ in 0..100 {
+            assert!(limiter
+                .admit(&addr_1, Some(&api_key), RPCRateLimitClass::Cheap, now)
+                .is_ok());
+        }
+        for _ in 0..3 {
+            assert!(limiter
+                .admit(&addr_1, Some(&api_key), RPCRateLimitClass::Expensive, now)
+                .is_ok());
+        }
+        assert_eq!(
+            limiter.admit(&addr_1, Some(&api_key), RPCRateLimitClass::Expensive, now),
+            Err(RPCRateLimitError::Exceeded(20))
+        );
+
+        // the key's budget is separate from its address's
+        assert!(limiter
+            .admit(&addr_1, None, RPCRateLimitClass::Expensive, now)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_rpc_rate_limit_class() {
+        let md = HttpRequestMetadata::new("127.0.0.1".to_string(), 20443, None);
+        assert_eq!(
+            RPCRateLimitClass::from_request(&HttpRequestType::GetInfo(md.clone())),
+            RPCRateLimitClass::Cheap
+        );
+        assert_eq!(
+            RPCRateLimitClass::from_request(&HttpRequestType::GetBlock(
+                md.clone(),
+                StacksBlockId([0x11; 32])
+            )),
+            RPCRateLimitClass::Expensive
+        );
+    }
+
+    #[test]
+    fn test_rpc_rate_limit_batch() {
+        let mut opts = ConnectionOptions::default();
+        opts.rpc_rate_limit_cheap = 4;
+        opts.rpc_rate_limit_expensive = 2;
+
+        let addr = IpAddr::from([127, 0, 0, 1]);
+        let now = 1_000_000;
+        let md = HttpRequestMetadata::new("127.0.0.1".to_string(), 20443, None);
+        let cheap = HttpRequestType::GetInfo(md.clone());
+        let expensive = HttpRequestType::GetBlock(md.clone(), StacksBlockId([0x11; 32]));
+        let batch = |requests: Vec<HttpRequestType>| {
+            HttpRequestType::Batch(md.clone(), requests, TipRequest::UseLatestAnchoredTip)
+        };
+
+        // each batched request is charged to its own class
+        assert_eq!(
+            RPCRateLimitClass::charges(&batch(vec![
+                cheap.clone(),
+                expensive.clone(),
+                cheap.clone()
+            ])),
+            vec![
+                (RPCRateLimitClass::Cheap, 2),
+                (RPCRateLimitClass::Expensive, 1)
+            ]
+        );
+        assert_eq!(
+            RPCRateLimitClass::charges(&expensive),
+            vec![(RPCRateLimitClass::Expensive, 1)]
+        );
+
+        let mut limiter = RPCRateLimiter::new(&opts);
+        assert!(limiter
+            .admit_request(
+                &addr,
+                None,
+                &batch(vec![cheap.clone(), cheap.clone(), expensive.clone()]),
+                now
+            )
+            .is_ok());
+
+        // a batch the client can't cover in full is turned away, and costs nothing
+        assert_eq!(
+            limiter.admit_request(
+                &addr,
+                None,
+                &batch(vec![cheap.clone(), expensive.clone(), expensive.clone()]),
+                now
+            ),
+            Err(RPCRateLimitError::Exceeded(30))
+        );
+        assert!(limiter
+            .admit_request(&addr, None, &batch(vec![cheap.clone(), cheap.clone()]), now)
+            .is_ok());
+        assert!(limiter.admit_request(&addr, None, &expensive, now).is_ok());
+        assert_eq!(
+            limiter.admit_request(&addr, None, &cheap, now),
+            Err(RPCRateLimitError::Exceeded(15))
+        );
+
+        // a batch bigger than a full bucket can never be admitted
+        assert_eq!(
+            limiter.admit_request(
+                &IpAddr::from([127, 0, 0, 2]),
+                None,
+                &batch(vec![expensive.clone(); 3]),
+                now
+            ),
+            Err(RPCRateLimitError::TooExpensive)
+        );
+    }
+
     /// General testing function to test RPC calls.
     /// This function sets up two peers, a client and a server.
     /// It takes in a function of type F that generates the request to be sent to the server
@@ -4511,6 +5535,76 @@ mod test {
         );
     }
 
+    #[test]
+    #[ignore]
+    fn test_rpc_confirmed_getblock_gzip() {
+        let server_block_cell = RefCell::new(None);
+
+        test_rpc(
+            "test_rpc_confirmed_getblock_gzip",
+            40210,
+            40211,
+            50210,
+            50211,
+            true,
+            |ref mut peer_client,
+             ref mut convo_client,
+             ref mut peer_server,
+             ref mut convo_server| {
+                // have "server" peer store a block to staging
+                let peer_server_block = make_codec_test_block(25);
+                let peer_server_consensus_hash = ConsensusHash([0x02; 20]);
+                let index_block_hash = StacksBlockHeader::make_index_block_hash(
+                    &peer_server_consensus_hash,
+                    &peer_server_block.block_hash(),
+                );
+
+                store_staging_block(
+                    peer_server.chainstate(),
+                    &peer_server_consensus_hash,
+                    &peer_server_block,
+                    &ConsensusHash([0x03; 20]),
+                    456,
+                    123,
+                );
+                set_block_processed(
+                    peer_server.chainstate(),
+                    &peer_server_consensus_hash,
+                    &peer_server_block.block_hash(),
+                    true,
+                );
+
+                *server_block_cell.borrow_mut() = Some(peer_server_block);
+
+                // now ask for it, compressed
+                let mut request = convo_client.new_getblock(index_block_hash);
+                request.metadata_mut().accept_gzip = true;
+                request
+            },
+            |ref http_request,
+             ref http_response,
+             ref mut peer_client,
+             ref mut peer_server,
+             ref convo_client,
+             ref convo_server| {
+                match http_response {
+                    HttpResponseType::Block(response_md, block_info) => {
+                        assert!(response_md.gzip);
+                        assert_eq!(
+                            block_info.block_hash(),
+                            (*server_block_cell.borrow()).as_ref().unwrap().block_hash()
+                        );
+                        true
+                    }
+                    _ => {
+                        error!("Invalid response: {:?}", &http_response);
+                        false
+                    }
+                }
+            },
+        );
+    }
+
     #[test]
     #[ignore]
     fn test_rpc_get_indexed_microblocks() {
@@ -5805,6 +6899,200 @@ mod test {
         );
     }
 
+    #[test]
+    #[ignore]
+    fn test_rpc_batch() {
+        // Test /v2/batch endpoint.
+        // In this test, we ask for a map entry and run a read-only function call in one batch,
+        // and expect both to be answered from the same chain tip.
+        test_rpc(
+            "test_rpc_batch",
+            40200,
+            40201,
+            50200,
+            50201,
+            true,
+            |ref mut peer_client,
+             ref mut convo_client,
+             ref mut peer_server,
+             ref mut convo_server| {
+                let principal =
+                    StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R")
+                        .unwrap()
+                        .to_account_principal();
+                let requests = vec![
+                    convo_client.new_getmapentry(
+                        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R")
+                            .unwrap(),
+                        "hello-world".try_into().unwrap(),
+                        "unit-map".try_into().unwrap(),
+                        Value::Tuple(
+                            TupleData::from_data(vec![(
+                                "account".into(),
+                                Value::Principal(principal.clone()),
+                            )])
+                            .unwrap(),
+                        ),
+                        TipRequest::UseLatestAnchoredTip,
+                        false,
+                    ),
+                    convo_client.new_callreadonlyfunction(
+                        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R")
+                            .unwrap(),
+                        "hello-world".try_into().unwrap(),
+                        principal,
+                        "get-bar".try_into().unwrap(),
+                        vec![],
+                        TipRequest::UseLatestAnchoredTip,
+                    ),
+                ];
+                convo_client.new_batch(requests, TipRequest::UseLatestAnchoredTip)
+            },
+            |ref http_request,
+             ref http_response,
+             ref mut peer_client,
+             ref mut peer_server,
+             ref convo_client,
+             ref convo_server| {
+                match http_response {
+                    HttpResponseType::Batch(response_md, items) => {
+                        assert_eq!(items.len(), 2);
+
+                        assert_eq!(items[0].status, 200);
+                        let map_entry: MapEntryResponse =
+                            serde_json::from_value(items[0].body.clone()).unwrap();
+                        assert_eq!(
+                            Value::try_deserialize_hex_untyped(&map_entry.data).unwrap(),
+                            Value::some(Value::Tuple(
+                                TupleData::from_data(vec![("units".into(), Value::Int(123))])
+                                    .unwrap()
+                            ))
+                            .unwrap()
+                        );
+
+                        assert_eq!(items[1].status, 200);
+                        let call: CallReadOnlyResponse =
+                            serde_json::from_value(items[1].body.clone()).unwrap();
+                        assert!(call.okay);
+                        assert_eq!(
+                            Value::try_deserialize_hex_untyped(&call.result.unwrap()).unwrap(),
+                            Value::okay(Value::Int(0)).unwrap()
+                        );
+                        true
+                    }
+                    _ => {
+                        error!("Invalid response; {:?}", &http_response);
+                        false
+                    }
+                }
+            },
+        );
+    }
+
+    #[test]
+    fn test_rpc_get_marf_proofs() {
+        // Test /v2/marf/proofs (aka GetMarfProofs) endpoint.
+        // In this test, we ask for a data var that exists and one that doesn't, and expect the
+        // returned batch proof to check out with the standalone verifier.
+        let contract_id = QualifiedContractIdentifier::parse(
+            "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.hello-world",
+        )
+        .unwrap();
+        let keys = vec![
+            ClarityDatabase::make_key_for_trip(&contract_id, StoreType::Variable, "bar"),
+            ClarityDatabase::make_key_for_trip(&contract_id, StoreType::Variable, "nope"),
+        ];
+        let request_keys = keys.clone();
+        test_rpc(
+            "test_rpc_get_marf_proofs",
+            40210,
+            40211,
+            50210,
+            50211,
+            true,
+            |ref mut peer_client,
+             ref mut convo_client,
+             ref mut peer_server,
+             ref mut convo_server| {
+                convo_client
+                    .new_getmarfproofs(request_keys.clone(), TipRequest::UseLatestAnchoredTip)
+            },
+            |ref http_request,
+             ref http_response,
+             ref mut peer_client,
+             ref mut peer_server,
+             ref convo_client,
+             ref convo_server| {
+                match http_response {
+                    HttpResponseType::GetMarfProofs(response_md, marf_proofs) => {
+                        assert_eq!(marf_proofs.values.len(), 2);
+                        assert_eq!(
+                            Value::try_deserialize_hex_untyped(
+                                marf_proofs.values[0].as_ref().unwrap()
+                            )
+                            .unwrap(),
+                            Value::Int(0)
+                        );
+                        assert!(marf_proofs.values[1].is_none());
+
+                        let (consensus_hash, block_hash) =
+                            SortitionDB::get_canonical_stacks_chain_tip_hash(
+                                peer_server.sortdb.as_ref().unwrap().conn(),
+                            )
+                            .unwrap();
+                        let tip =
+                            StacksBlockHeader::make_index_block_hash(&consensus_hash, &block_hash);
+                        let (root_hash, root_to_block) =
+                            peer_server.chainstate().with_clarity_marf(|marf| {
+                                let root_hash = marf.get_root_hash_at(&tip).unwrap();
+                                let root_to_block = marf
+                                    .borrow_storage_backend()
+                                    .read_root_to_block_table()
+                                    .unwrap();
+                                (root_hash, root_to_block)
+                            });
+                        let root_to_block: HashMap<[u8; 32], [u8; 32]> = root_to_block
+                            .iter()
+                            .map(|(root, block)| (root.0, block.0))
+                            .collect();
+
+                        let proof = marf_verifier::MARFBatchProof::from_hex(
+                            marf_proofs.proof.trim_start_matches("0x"),
+                        )
+                        .unwrap();
+                        let claims: Vec<_> = keys
+                            .iter()
+                            .zip(marf_proofs.values.iter())
+                            .map(|(key, value)| {
+                                (
+                                    marf_verifier::path_from_key(key),
+                                    value
+                                        .as_ref()
+                                        .map(|value| marf_verifier::value_from_data(value)),
+                                )
+                            })
+                            .collect();
+                        proof.verify(&claims, &root_hash.0, &root_to_block).unwrap();
+
+                        // the proof doesn't vouch for some other value
+                        let mut bad_claims = claims.clone();
+                        bad_claims[0].1 = Some(marf_verifier::value_from_data(
+                            &ClaritySerializable::serialize(&Value::Int(1)),
+                        ));
+                        assert!(proof
+                            .verify(&bad_claims, &root_hash.0, &root_to_block)
+                            .is_err());
+                        true
+                    }
+                    _ => {
+                        error!("Invalid response; {:?}", &http_response);
+                        false
+                    }
+                }
+            },
+        );
+    }
+
     #[test]
     #[ignore]
     fn test_rpc_get_contract_abi() {
@@ -38,6 +38,7 @@ use crate::net::http::*;
 use crate::net::p2p::{PeerMap, PeerNetwork};
 use crate::net::poll::*;
 use crate::net::rpc::*;
+use crate::net::socks::Socks5Handshake;
 use crate::net::Error as net_error;
 use crate::net::*;
 
@@ -57,18 +58,13 @@ use crate::core::mempool::*;
 pub struct HttpPeer {
     // ongoing http conversations (either they reached out to us, or we to them)
     pub peers: HashMap<usize, ConversationHttp>,
-    pub sockets: HashMap<usize, mio_net::TcpStream>,
+    pub sockets: HashMap<usize, NetSocket>,
 
     // outbound connections that are pending connection
-    pub connecting: HashMap<
-        usize,
-        (
-            mio_net::TcpStream,
-            Option<UrlString>,
-            Option<HttpRequestType>,
-            u64,
-        ),
-    >,
+    pub connecting: HashMap<usize, (NetSocket, Option<UrlString>, Option<HttpRequestType>, u64)>,
+
+    // (peer address, SOCKS5 handshake) for outbound connections that go through the proxy
+    pub proxy_handshakes: HashMap<usize, (SocketAddr, Socks5Handshake)>,
 
     // server network handle
     pub http_server_handle: usize,
@@ -84,6 +80,7 @@ impl HttpPeer {
             sockets: HashMap::new(),
 
             connecting: HashMap::new(),
+            proxy_handshakes: HashMap::new(),
             http_server_handle: server_handle,
 
             connection_opts: conn_opts,
@@ -115,10 +112,7 @@ impl HttpPeer {
     pub fn get_conversation_and_socket(
         &mut self,
         event_id: usize,
-    ) -> (
-        Option<&mut ConversationHttp>,
-        Option<&mut mio::net::TcpStream>,
-    ) {
+    ) -> (Option<&mut ConversationHttp>, Option<&mut NetSocket>) {
         (
             self.peers.get_mut(&event_id),
             self.sockets.get_mut(&event_id),
@@ -147,7 +141,12 @@ impl HttpPeer {
             return Err(net_error::AlreadyConnected(event_id, http_nk));
         }
 
-        let sock = NetworkState::connect(&addr)?;
+        let (sock, proxy_handshake) = network_state.connect_proxied(
+            &PeerAddress::from_socketaddr(&addr),
+            addr.port(),
+            self.connection_opts.socks5_proxy.as_ref(),
+            &self.connection_opts.socks5_hostnames,
+        )?;
         let hint_event_id = network_state.next_event_id()?;
         let next_event_id =
             network_state.register(self.http_server_handle, hint_event_id, &sock)?;
@@ -156,6 +155,10 @@ impl HttpPeer {
             next_event_id,
             (sock, Some(data_url), request, get_epoch_time_secs()),
         );
+        if let Some(handshake) = proxy_handshake {
+            self.proxy_handshakes
+                .insert(next_event_id, (addr, handshake));
+        }
         Ok(next_event_id)
     }
 
@@ -218,11 +221,12 @@ impl HttpPeer {
         mempool: &MemPoolDB,
         chainstate: &mut StacksChainState,
         event_id: usize,
-        mut socket: mio_net::TcpStream,
+        mut socket: NetSocket,
         outbound_url: Option<UrlString>,
         initial_request: Option<HttpRequestType>,
+        proxied_addr: Option<SocketAddr>,
     ) -> Result<(), net_error> {
-        let client_addr = match socket.peer_addr() {
+        let client_addr = match proxied_addr.map(Ok).unwrap_or_else(|| socket.peer_addr()) {
             Ok(addr) => addr,
             Err(e) => {
                 warn!("Failed to get peer address of {:?}: {:?}", &socket, &e);
@@ -300,6 +304,7 @@ impl HttpPeer {
                 let _ = network_state.deregister(event_id, &sock);
             }
         }
+        self.proxy_handshakes.remove(&event_id);
     }
 
     /// Remove slow/unresponsive peers
@@ -343,7 +348,7 @@ impl HttpPeer {
     /// Saturate a conversation's socket -- either sends the whole request, or fills the socket
     /// buffer.
     pub fn saturate_http_socket(
-        client_sock: &mut mio::net::TcpStream,
+        client_sock: &mut NetSocket,
         convo: &mut ConversationHttp,
         mempool: &MemPoolDB,
         chainstate: &mut StacksChainState,
@@ -413,6 +418,7 @@ impl HttpPeer {
                 client_sock,
                 None,
                 None,
+                None,
             ) {
                 // NOTE: register_http will deregister the socket for us
                 continue;
@@ -432,7 +438,7 @@ impl HttpPeer {
         chainstate: &mut StacksChainState,
         mempool: &mut MemPoolDB,
         event_id: usize,
-        client_sock: &mut mio_net::TcpStream,
+        client_sock: &mut NetSocket,
         convo: &mut ConversationHttp,
         handler_args: &RPCHandlerArgs,
     ) -> Result<(bool, Vec<StacksMessageType>), net_error> {
@@ -547,6 +553,35 @@ impl HttpPeer {
     ) -> () {
         for event_id in poll_state.ready.iter() {
             if self.connecting.contains_key(event_id) {
+                // if we're connecting through a proxy, then the proxy must connect us first
+                let proxied_addr = match self.proxy_handshakes.get_mut(event_id) {
+                    Some((peer_addr, handshake)) => {
+                        let (socket, ..) = self
+                            .connecting
+                            .get_mut(event_id)
+                            .expect("BUG: proxy handshake for non-connecting socket");
+                        match handshake.step(socket) {
+                            Ok(true) => Some(peer_addr.clone()),
+                            Ok(false) => {
+                                // wait for the proxy
+                                continue;
+                            }
+                            Err(e) => {
+                                info!(
+                                    "Failed to connect HTTP event {} to {} through SOCKS5 proxy: {:?}",
+                                    event_id,
+                                    handshake.target(),
+                                    &e
+                                );
+                                self.deregister_http(network_state, *event_id);
+                                continue;
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                self.proxy_handshakes.remove(event_id);
+
                 let (socket, data_url, initial_request_opt, _) =
                     self.connecting.remove(event_id).unwrap();
 
@@ -560,6 +595,7 @@ impl HttpPeer {
                     socket,
                     data_url.clone(),
                     initial_request_opt,
+                    proxied_addr,
                 ) {
                     debug!(
                         "Failed to register HTTP connection ({}, {:?})",
@@ -602,6 +638,18 @@ impl HttpPeer {
 
             match self.peers.get_mut(event_id) {
                 Some(ref mut convo) => {
+                    // only hold back the downloads we asked for; requests from clients are
+                    // always read
+                    let addr = PeerAddress::from_socketaddr(convo.get_peer_addr());
+                    if convo.get_url().is_some() && !network.bandwidth.can_download(&addr) {
+                        debug!(
+                            "Out of download bandwidth; will read from HTTP {:?} later",
+                            convo
+                        );
+                        network.bandwidth.defer_download(&addr, *event_id);
+                        continue;
+                    }
+
                     // activity on a http socket
                     test_debug!("Process HTTP data from {:?}", convo);
                     match HttpPeer::process_http_conversation(
@@ -660,6 +708,17 @@ impl HttpPeer {
                 debug!("Close drained HTTP connection {:?}", convo);
                 close.push(*event_id);
             }
+            if convo.is_event_stream() {
+                // new events don't make the socket ready, so push them out ourselves
+                if let Some(client_sock) = self.sockets.get_mut(event_id) {
+                    if let Err(_e) =
+                        HttpPeer::saturate_http_socket(client_sock, convo, mempool, chainstate)
+                    {
+                        debug!("Broken HTTP event stream {:?}: {:?}", convo, &_e);
+                        close.push(*event_id);
+                    }
+                }
+            }
         }
 
         close
@@ -687,6 +746,13 @@ impl HttpPeer {
         // set up connected sockets
         self.process_connecting_sockets(network_state, mempool, chainstate, &mut poll_state);
 
+        // retry reads and requests that were waiting on bandwidth
+        for event_id in network.bandwidth.take_deferred_events(&self.peers) {
+            if !poll_state.ready.contains(&event_id) {
+                poll_state.ready.push(event_id);
+            }
+        }
+
         // run existing conversations, clear out broken ones, and get back messages forwarded to us
         let (stacks_msgs, error_events) = self.process_ready_sockets(
             &mut poll_state,
@@ -1330,6 +1396,150 @@ mod test {
         assert_eq!(num_events, 2);
     }
 
+    #[test]
+    fn test_http_rate_limit() {
+        let mut conn_opts = ConnectionOptions::default();
+        conn_opts.rpc_rate_limit_cheap = 1;
+
+        let num_ok = RefCell::new(0);
+        let num_limited = RefCell::new(0);
+
+        test_http_server(
+            "test_http_rate_limit",
+            51094,
+            51095,
+            conn_opts,
+            3,
+            0,
+            |client_id, _| {
+                let mut request = HttpRequestType::GetInfo(HttpRequestMetadata::from_host(
+                    PeerHost::from_host_port("127.0.0.1".to_string(), 51095),
+                    None,
+                ));
+                request.metadata_mut().keep_alive = false;
+
+                let request_bytes = StacksHttp::serialize_request(&request).unwrap();
+                request_bytes
+            },
+            |client_id, http_response_bytes_res| {
+                // all clients share 127.0.0.1's budget of one request per minute
+                let http_response_bytes = http_response_bytes_res.unwrap();
+                match StacksHttp::parse_response("/v2/info", &http_response_bytes).unwrap() {
+                    StacksHttpMessage::Response(HttpResponseType::PeerInfo(..)) => {
+                        *num_ok.borrow_mut() += 1;
+                    }
+                    StacksHttpMessage::Response(HttpResponseType::TooManyRequests(
+                        _,
+                        retry_after,
+                        _,
+                    )) => {
+                        assert!(retry_after > 0 && retry_after <= 60);
+                        *num_limited.borrow_mut() += 1;
+                    }
+                    x => {
+                        eprintln!("Unexpected response: {:?}", &x);
+                        return false;
+                    }
+                }
+                true
+            },
+        );
+
+        assert_eq!(*num_ok.borrow(), 1);
+        assert_eq!(*num_limited.borrow(), 2);
+    }
+
+    #[test]
+    fn test_http_event_stream_disabled() {
+        test_http_server(
+            "test_http_event_stream_disabled",
+            51092,
+            51093,
+            ConnectionOptions::default(),
+            1,
+            0,
+            |client_id, _| {
+                let request_txt =
+                    "GET /v2/events/blocks HTTP/1.1\r\nConnection: close\r\nHost: 127.0.0.1:51093\r\n\r\n";
+                request_txt.as_bytes().to_vec()
+            },
+            |client_id, http_response_bytes_res| {
+                // no event stream hub, so this should be a HTTP 404 error
+                let http_response_bytes = http_response_bytes_res.unwrap();
+                let http_response_str = String::from_utf8(http_response_bytes).unwrap();
+                eprintln!("HTTP response\n{}", http_response_str);
+                assert!(http_response_str.find("404 Not Found").is_some());
+                true
+            },
+        );
+    }
+
+    #[test]
+    fn test_http_event_stream() {
+        use crate::net::eventstream::{EventStreamHub, EventStreamKind};
+
+        let mut peer_config = TestPeerConfig::new("test_http_event_stream", 51090, 51091);
+        peer_config.connection_opts.max_event_streams = 1;
+
+        let hub = EventStreamHub::new(1, 16);
+        let mut peer = TestPeer::new(peer_config);
+        peer.network.event_streams = Some(hub.clone());
+
+        let (http_sx, http_rx) = sync_channel(1);
+        let http_thread = thread::spawn(move || loop {
+            peer.step().unwrap();
+
+            // asked to yield?
+            if http_rx.try_recv().is_ok() {
+                break;
+            }
+        });
+
+        let client = thread::spawn(move || {
+            let mut sock =
+                TcpStream::connect(&"127.0.0.1:51091".parse::<SocketAddr>().unwrap()).unwrap();
+            sock.set_read_timeout(Some(std::time::Duration::from_secs(60)))
+                .unwrap();
+
+            let request_txt = "GET /v2/events/blocks HTTP/1.1\r\nHost: 127.0.0.1:51091\r\n\r\n";
+            sock.write_all(request_txt.as_bytes()).unwrap();
+
+            let mut resp = vec![];
+            let mut buf = [0u8; 4096];
+            loop {
+                let nr = sock.read(&mut buf).unwrap();
+                assert!(nr > 0, "event stream closed early");
+                resp.extend_from_slice(&buf[0..nr]);
+
+                let resp_str = String::from_utf8_lossy(&resp).to_string();
+                if resp_str.find("hello-event-stream").is_some() {
+                    return resp_str;
+                }
+            }
+        });
+
+        // wait for the client to subscribe
+        let deadline = get_epoch_time_secs() + 60;
+        while hub.num_subscribers() == 0 {
+            assert!(get_epoch_time_secs() < deadline, "client never subscribed");
+            sleep_ms(100);
+        }
+
+        hub.publish(
+            EventStreamKind::NewBlock,
+            serde_json::json!({ "block_hash": "hello-event-stream", "events": [] }),
+        );
+
+        let resp_str = client.join().unwrap();
+        eprintln!("HTTP response\n{}", resp_str);
+        assert!(resp_str.find("200 OK").is_some());
+        assert!(resp_str.find("text/event-stream").is_some());
+        assert!(resp_str.find("event: new_block").is_some());
+
+        http_sx.send(true).unwrap();
+        http_thread.join().unwrap();
+    }
+
     #[test]
     fn test_http_noop() {
         if std::env::var("BLOCKSTACK_HTTP_TEST") != Ok("1".to_string()) {
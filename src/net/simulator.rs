@@ -0,0 +1,1416 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2022 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! In-process network simulator for P2P tests.
+//!
+//! A `SimNetwork` owns a set of `TestPeer`s and steps them in a fixed order, one round at a
+//! time.  Each round advances a virtual clock, which the peers read through
+//! `get_epoch_time_secs()` / `get_epoch_time_ms()` instead of the system clock, so timeouts,
+//! walk intervals and inventory sync intervals are all measured in simulated time.
+//!
+//! The peers do not open real sockets.  Each one is bound to a `MemTransport` on a shared
+//! `MemNetwork`, an in-memory stand-in for TCP that carries both P2P and HTTP traffic (so block
+//! downloads and mempool sync are simulated too).  Bytes written to a socket arrive after the
+//! latency of the link from the sender to the receiver.  On a lossy link, a write can be lost, in
+//! which case it is retransmitted a `RETRANSMIT_TIMEOUT_MS` later, as it would be over TCP; bytes
+//! are always delivered in order.  Peers that are partitioned from one another cannot connect,
+//! and any connections open between them when the partition starts are reset.  Losses are drawn
+//! from a seeded RNG, so a scenario replays the same way every time it is run.
+//!
+//! Scenarios can be scripted as a list of `SimEvent`s to apply at given rounds (see
+//! `SimNetwork::run_script()`).
+//!
+//! Only one `SimNetwork` can be active per thread, since the virtual clock is per-thread.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use crate::net::dns::DNSClient;
+use crate::net::poll::NetSocket;
+use crate::net::poll::NetworkSocket;
+use crate::net::poll::NetworkTransport;
+use crate::net::test::dns_thread_shutdown;
+use crate::net::test::dns_thread_start;
+use crate::net::test::TestPeer;
+use crate::net::Error as net_error;
+use crate::net::*;
+
+use stacks_common::util::get_epoch_time_ms;
+use stacks_common::util::set_mock_epoch_time_ms;
+
+/// How long it takes for a lost write to be sent again, in (simulated) milliseconds
+pub const RETRANSMIT_TIMEOUT_MS: u64 = 1000;
+
+/// First port handed out to the connecting end of a simulated connection
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// How a one-way link between two simulated peers behaves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    /// how long each write takes to arrive, in (simulated) milliseconds
+    pub latency_ms: u64,
+    /// how many out of every 1000 transmissions get lost (and have to be retransmitted)
+    pub loss_per_mille: u32,
+}
+
+impl LinkConfig {
+    pub fn new(latency_ms: u64, loss_per_mille: u32) -> LinkConfig {
+        assert!(
+            loss_per_mille < 1000,
+            "BUG: a link that loses everything is a partition"
+        );
+        LinkConfig {
+            latency_ms,
+            loss_per_mille,
+        }
+    }
+
+    /// A link that delivers every write as soon as it is made
+    pub fn perfect() -> LinkConfig {
+        LinkConfig::new(0, 0)
+    }
+}
+
+/// Counts of what the simulated network did
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimStats {
+    /// writes sent over a link
+    pub sent: u64,
+    /// transmissions lost (and retransmitted) due to link loss
+    pub lost: u64,
+    /// connections refused or reset because the two ends were partitioned
+    pub partitioned: u64,
+}
+
+/// Scripted changes to the simulated network
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimEvent {
+    /// Split the peers into groups that cannot reach one another.  Peers not named in any group
+    /// form one more group.
+    Partition(Vec<Vec<usize>>),
+    /// Remove all partitions
+    Heal,
+    /// Set the link from one peer to another
+    SetLink(usize, usize, LinkConfig),
+    /// Set the link in both directions between two peers
+    SetLinkBoth(usize, usize, LinkConfig),
+    /// Set the link for every pair of peers that has no link of its own
+    SetDefaultLink(LinkConfig),
+    /// Make every link to and from this peer use this config (i.e. a slow or lossy peer)
+    SetPeerLinks(usize, LinkConfig),
+}
+
+/// Bytes on their way to a socket
+#[derive(Debug)]
+struct Segment {
+    deliver_at_ms: u128,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MemSocketState {
+    /// Waiting for the connection to be set up, which happens at this time
+    Connecting(u128),
+    Connected,
+    Refused,
+    Reset,
+}
+
+/// One end of a simulated connection
+#[derive(Debug)]
+struct MemEndpoint {
+    host: usize,
+    peer_addr: SocketAddr,
+    /// the other end, if the connection was accepted
+    peer: Option<u64>,
+    state: MemSocketState,
+    inbox: VecDeque<Segment>,
+    /// when the other end's shutdown arrives here, if it has shut down
+    peer_closed_at_ms: Option<u128>,
+    /// whether this end has been shut down
+    closed: bool,
+    /// whether this end has been reported as connected by poll()
+    connect_reported: bool,
+}
+
+impl MemEndpoint {
+    /// Bring the socket's state up to date, and fail if it can't carry data now
+    fn check_state(&mut self, now_ms: u128) -> io::Result<()> {
+        if let MemSocketState::Connecting(connected_at_ms) = self.state {
+            if connected_at_ms <= now_ms {
+                self.state = MemSocketState::Connected;
+            }
+        }
+        match self.state {
+            MemSocketState::Connected => Ok(()),
+            MemSocketState::Connecting(_) => Err(io::ErrorKind::WouldBlock.into()),
+            MemSocketState::Refused => Err(io::ErrorKind::ConnectionRefused.into()),
+            MemSocketState::Reset => Err(io::ErrorKind::ConnectionReset.into()),
+        }
+    }
+
+    fn has_data(&self, now_ms: u128) -> bool {
+        self.inbox
+            .front()
+            .map(|segment| segment.deliver_at_ms <= now_ms)
+            .unwrap_or(false)
+    }
+
+    fn is_peer_closed(&self, now_ms: u128) -> bool {
+        self.inbox.is_empty()
+            && self
+                .peer_closed_at_ms
+                .map(|closed_at_ms| closed_at_ms <= now_ms)
+                .unwrap_or(false)
+    }
+}
+
+#[derive(Debug)]
+struct MemListener {
+    host: usize,
+    addr: SocketAddr,
+    /// accepted connections, and when their connection requests arrive
+    backlog: VecDeque<(u128, u64)>,
+}
+
+#[derive(Debug)]
+struct MemNetworkState {
+    rng: StdRng,
+    num_hosts: usize,
+    next_socket_id: u64,
+    next_port: u16,
+    /// port --> listener on that port
+    listeners: HashMap<u16, MemListener>,
+    sockets: HashMap<u64, MemEndpoint>,
+    /// (sender, receiver) --> link
+    links: HashMap<(usize, usize), LinkConfig>,
+    default_link: LinkConfig,
+    /// host --> partition group, if partitioned
+    groups: Option<HashMap<usize, usize>>,
+    stats: SimStats,
+}
+
+impl MemNetworkState {
+    fn link(&self, sender: usize, receiver: usize) -> LinkConfig {
+        self.links
+            .get(&(sender, receiver))
+            .cloned()
+            .unwrap_or(self.default_link)
+    }
+
+    fn is_partitioned(&self, host_1: usize, host_2: usize) -> bool {
+        match self.groups {
+            Some(ref groups) => groups.get(&host_1) != groups.get(&host_2),
+            None => false,
+        }
+    }
+
+    /// How long a write from `sender` takes to reach `receiver`, including retransmissions
+    fn transit_ms(&mut self, sender: usize, receiver: usize) -> u128 {
+        let link = self.link(sender, receiver);
+        let mut transit_ms = link.latency_ms as u128;
+        while link.loss_per_mille > 0 && self.rng.gen_range(0, 1000) < link.loss_per_mille {
+            self.stats.lost += 1;
+            transit_ms += RETRANSMIT_TIMEOUT_MS as u128;
+        }
+        transit_ms
+    }
+
+    fn next_ephemeral_port(&mut self) -> u16 {
+        let port = self.next_port;
+        self.next_port = self
+            .next_port
+            .checked_add(1)
+            .unwrap_or(FIRST_EPHEMERAL_PORT);
+        port
+    }
+
+    fn partition(&mut self, groups: HashMap<usize, usize>) {
+        self.groups = Some(groups);
+
+        // reset every connection that now crosses the partition
+        let mut reset = vec![];
+        for (socket_id, socket) in self.sockets.iter() {
+            if let Some(peer_id) = socket.peer {
+                if let Some(peer) = self.sockets.get(&peer_id) {
+                    if *socket_id < peer_id && self.is_partitioned(socket.host, peer.host) {
+                        reset.push((*socket_id, peer_id));
+                    }
+                }
+            }
+        }
+        for (socket_id, peer_id) in reset.into_iter() {
+            for id in [socket_id, peer_id].iter() {
+                if let Some(socket) = self.sockets.get_mut(id) {
+                    socket.state = MemSocketState::Reset;
+                    socket.inbox.clear();
+                }
+            }
+            self.stats.partitioned += 1;
+        }
+    }
+
+    /// Shut down a socket, and tell the other end once everything sent before has arrived
+    fn close(&mut self, socket_id: u64) {
+        let now_ms = get_epoch_time_ms();
+        let (host, peer_id) = match self.sockets.get_mut(&socket_id) {
+            Some(socket) if !socket.closed => {
+                socket.closed = true;
+                socket.inbox.clear();
+                (socket.host, socket.peer)
+            }
+            _ => {
+                return;
+            }
+        };
+        let peer_host = match peer_id.and_then(|peer_id| self.sockets.get(&peer_id)) {
+            Some(peer) => peer.host,
+            None => {
+                return;
+            }
+        };
+        let closed_at_ms = now_ms + self.transit_ms(host, peer_host);
+        if let Some(peer) = peer_id.and_then(|peer_id| self.sockets.get_mut(&peer_id)) {
+            let last_segment_ms = peer.inbox.back().map(|segment| segment.deliver_at_ms);
+            peer.peer_closed_at_ms = Some(cmp::max(closed_at_ms, last_segment_ms.unwrap_or(0)));
+        }
+    }
+}
+
+/// An in-memory network that `MemTransport`s connect over.  Cloning it yields a handle to the same
+/// network.
+#[derive(Clone)]
+pub struct MemNetwork {
+    state: Arc<Mutex<MemNetworkState>>,
+}
+
+impl fmt::Debug for MemNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MemNetwork")
+    }
+}
+
+fn lock_network(state: &Mutex<MemNetworkState>) -> MutexGuard<'_, MemNetworkState> {
+    state
+        .lock()
+        .expect("BUG: simulated network state lock poisoned")
+}
+
+impl MemNetwork {
+    /// A network with perfect links.  Link loss is drawn from an RNG seeded with `seed`.
+    pub fn new(seed: u64) -> MemNetwork {
+        MemNetwork {
+            state: Arc::new(Mutex::new(MemNetworkState {
+                rng: StdRng::seed_from_u64(seed),
+                num_hosts: 0,
+                next_socket_id: 0,
+                next_port: FIRST_EPHEMERAL_PORT,
+                listeners: HashMap::new(),
+                sockets: HashMap::new(),
+                links: HashMap::new(),
+                default_link: LinkConfig::perfect(),
+                groups: None,
+                stats: SimStats::default(),
+            })),
+        }
+    }
+
+    /// Add a host to the network, and get its transport
+    pub fn transport(&self) -> MemTransport {
+        let mut state = lock_network(&self.state);
+        let host = state.num_hosts;
+        state.num_hosts += 1;
+        MemTransport {
+            host,
+            network: self.state.clone(),
+            listeners: BTreeMap::new(),
+            sockets: BTreeMap::new(),
+        }
+    }
+
+    /// The host listening on this port, if any
+    pub fn host_at(&self, port: u16) -> Option<usize> {
+        lock_network(&self.state)
+            .listeners
+            .get(&port)
+            .map(|listener| listener.host)
+    }
+
+    pub fn stats(&self) -> SimStats {
+        lock_network(&self.state).stats.clone()
+    }
+
+    /// Set the link from one host to another
+    pub fn set_link(&self, sender: usize, receiver: usize, link: LinkConfig) {
+        lock_network(&self.state)
+            .links
+            .insert((sender, receiver), link);
+    }
+
+    /// Set the link for every pair of hosts that has no link of its own
+    pub fn set_default_link(&self, link: LinkConfig) {
+        lock_network(&self.state).default_link = link;
+    }
+
+    /// Split the hosts into groups that cannot reach one another.  Hosts not named in any group
+    /// form one more group.
+    pub fn partition(&self, groups: &[Vec<usize>]) {
+        let mut state = lock_network(&self.state);
+        let mut host_groups = HashMap::new();
+        for host in 0..state.num_hosts {
+            host_groups.insert(host, groups.len());
+        }
+        for (group_id, group) in groups.iter().enumerate() {
+            for host in group.iter() {
+                host_groups.insert(*host, group_id);
+            }
+        }
+        state.partition(host_groups);
+    }
+
+    /// Remove all partitions
+    pub fn heal(&self) {
+        lock_network(&self.state).groups = None;
+    }
+}
+
+/// A socket on a `MemNetwork`
+pub struct MemSocket {
+    id: u64,
+    network: Arc<Mutex<MemNetworkState>>,
+}
+
+impl fmt::Debug for MemSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MemSocket({})", self.id)
+    }
+}
+
+impl Read for MemSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now_ms = get_epoch_time_ms();
+        let mut state = lock_network(&self.network);
+        let socket = state
+            .sockets
+            .get_mut(&self.id)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        socket.check_state(now_ms)?;
+
+        let mut num_read = 0;
+        while num_read < buf.len() && socket.has_data(now_ms) {
+            let segment = socket
+                .inbox
+                .front_mut()
+                .expect("BUG: socket has data but no segments");
+            let count = cmp::min(buf.len() - num_read, segment.data.len());
+            buf[num_read..(num_read + count)].copy_from_slice(&segment.data[0..count]);
+            segment.data.drain(0..count);
+            num_read += count;
+            if segment.data.is_empty() {
+                socket.inbox.pop_front();
+            }
+        }
+
+        if num_read > 0 || socket.is_peer_closed(now_ms) {
+            Ok(num_read)
+        } else {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+impl Write for MemSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now_ms = get_epoch_time_ms();
+        let mut state = lock_network(&self.network);
+        let (host, peer_id) = {
+            let socket = state
+                .sockets
+                .get_mut(&self.id)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+            socket.check_state(now_ms)?;
+            if socket.closed {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            (socket.host, socket.peer)
+        };
+        let peer_host = match peer_id.and_then(|peer_id| state.sockets.get(&peer_id)) {
+            Some(peer) if !peer.closed => peer.host,
+            _ => {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+        };
+
+        let arrive_at_ms = now_ms + state.transit_ms(host, peer_host);
+        state.stats.sent += 1;
+        let peer = peer_id
+            .and_then(|peer_id| state.sockets.get_mut(&peer_id))
+            .expect("BUG: peer socket disappeared");
+
+        // bytes arrive in order, even if an earlier write was retransmitted
+        let last_segment_ms = peer.inbox.back().map(|segment| segment.deliver_at_ms);
+        peer.inbox.push_back(Segment {
+            deliver_at_ms: cmp::max(arrive_at_ms, last_segment_ms.unwrap_or(0)),
+            data: buf.to_vec(),
+        });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkSocket for MemSocket {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        lock_network(&self.network)
+            .sockets
+            .get(&self.id)
+            .map(|socket| socket.peer_addr)
+            .ok_or_else(|| io::ErrorKind::NotConnected.into())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        lock_network(&self.network).close(self.id);
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Drop for MemSocket {
+    fn drop(&mut self) {
+        let mut state = lock_network(&self.network);
+        state.close(self.id);
+        state.sockets.remove(&self.id);
+    }
+}
+
+/// One host's view of a `MemNetwork`.  Polling never blocks: it reports whatever is ready at the
+/// current (usually simulated) time.
+pub struct MemTransport {
+    host: usize,
+    network: Arc<Mutex<MemNetworkState>>,
+    /// token --> port of each listener
+    listeners: BTreeMap<usize, u16>,
+    /// socket ID --> token of each registered socket
+    sockets: BTreeMap<u64, usize>,
+}
+
+impl fmt::Debug for MemTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MemTransport({})", self.host)
+    }
+}
+
+impl MemTransport {
+    fn socket_id(sock: &NetSocket) -> Result<u64, net_error> {
+        sock.as_any()
+            .downcast_ref::<MemSocket>()
+            .map(|sock| sock.id)
+            .ok_or_else(|| {
+                error!("Not a simulated socket: {:?}", sock);
+                net_error::RegisterError
+            })
+    }
+}
+
+impl NetworkTransport for MemTransport {
+    fn bind(&mut self, addr: &SocketAddr, token: usize) -> Result<(), net_error> {
+        let mut state = lock_network(&self.network);
+        if state.listeners.contains_key(&addr.port()) {
+            debug!("Simulated port {} is already bound", addr.port());
+            return Err(net_error::BindError);
+        }
+        state.listeners.insert(
+            addr.port(),
+            MemListener {
+                host: self.host,
+                addr: *addr,
+                backlog: VecDeque::new(),
+            },
+        );
+        self.listeners.insert(token, addr.port());
+        Ok(())
+    }
+
+    fn accept(&mut self, token: usize) -> Result<Option<(NetSocket, SocketAddr)>, net_error> {
+        let port = *self.listeners.get(&token).ok_or(net_error::AcceptError)?;
+        let now_ms = get_epoch_time_ms();
+        let mut state = lock_network(&self.network);
+        let socket_id = {
+            let listener = state
+                .listeners
+                .get_mut(&port)
+                .ok_or(net_error::AcceptError)?;
+            match listener.backlog.front() {
+                Some((arrive_at_ms, socket_id)) if *arrive_at_ms <= now_ms => {
+                    let socket_id = *socket_id;
+                    listener.backlog.pop_front();
+                    socket_id
+                }
+                _ => {
+                    return Ok(None);
+                }
+            }
+        };
+        let peer_addr = match state.sockets.get(&socket_id) {
+            Some(socket) => socket.peer_addr,
+            None => {
+                return Ok(None);
+            }
+        };
+        let sock = MemSocket {
+            id: socket_id,
+            network: self.network.clone(),
+        };
+        Ok(Some((Box::new(sock), peer_addr)))
+    }
+
+    fn connect(&mut self, addr: &SocketAddr) -> Result<NetSocket, net_error> {
+        let now_ms = get_epoch_time_ms();
+        let mut state = lock_network(&self.network);
+        let socket_id = state.next_socket_id;
+        let server_id = socket_id + 1;
+        state.next_socket_id += 2;
+
+        let local_addr = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            state.next_ephemeral_port(),
+        );
+        let mut socket = MemEndpoint {
+            host: self.host,
+            peer_addr: *addr,
+            peer: None,
+            state: MemSocketState::Refused,
+            inbox: VecDeque::new(),
+            peer_closed_at_ms: None,
+            closed: false,
+            connect_reported: false,
+        };
+
+        let server_host = state
+            .listeners
+            .get(&addr.port())
+            .filter(|listener| {
+                listener.addr.ip().is_unspecified() || listener.addr.ip() == addr.ip()
+            })
+            .map(|listener| listener.host);
+
+        match server_host {
+            None => {
+                test_debug!("Simulated connection to {:?} refused", addr);
+            }
+            Some(server_host) if state.is_partitioned(self.host, server_host) => {
+                test_debug!("Simulated connection to {:?} is partitioned", addr);
+                state.stats.partitioned += 1;
+            }
+            Some(server_host) => {
+                // the connection is set up after one round trip
+                let request_ms = state.transit_ms(self.host, server_host);
+                let reply_ms = state.transit_ms(server_host, self.host);
+                socket.peer = Some(server_id);
+                socket.state = MemSocketState::Connecting(now_ms + request_ms + reply_ms);
+
+                state.sockets.insert(
+                    server_id,
+                    MemEndpoint {
+                        host: server_host,
+                        peer_addr: local_addr,
+                        peer: Some(socket_id),
+                        state: MemSocketState::Connected,
+                        inbox: VecDeque::new(),
+                        peer_closed_at_ms: None,
+                        closed: false,
+                        connect_reported: true,
+                    },
+                );
+                state
+                    .listeners
+                    .get_mut(&addr.port())
+                    .expect("BUG: listener disappeared")
+                    .backlog
+                    .push_back((now_ms + request_ms, server_id));
+            }
+        }
+
+        state.sockets.insert(socket_id, socket);
+        Ok(Box::new(MemSocket {
+            id: socket_id,
+            network: self.network.clone(),
+        }))
+    }
+
+    fn register(&mut self, sock: &NetSocket, token: usize) -> Result<(), net_error> {
+        let socket_id = MemTransport::socket_id(sock)?;
+        self.sockets.insert(socket_id, token);
+        Ok(())
+    }
+
+    fn deregister(&mut self, sock: &NetSocket) -> Result<(), net_error> {
+        let socket_id = MemTransport::socket_id(sock)?;
+        self.sockets.remove(&socket_id);
+        Ok(())
+    }
+
+    fn poll(&mut self, _timeout: u64) -> Result<Vec<usize>, net_error> {
+        let now_ms = get_epoch_time_ms();
+        let mut state = lock_network(&self.network);
+        let mut ready = vec![];
+        for (token, port) in self.listeners.iter() {
+            let pending = state
+                .listeners
+                .get(port)
+                .and_then(|listener| listener.backlog.front())
+                .map(|(arrive_at_ms, _)| *arrive_at_ms <= now_ms)
+                .unwrap_or(false);
+            if pending {
+                ready.push(*token);
+            }
+        }
+        for (socket_id, token) in self.sockets.iter() {
+            let socket = match state.sockets.get_mut(socket_id) {
+                Some(socket) => socket,
+                None => {
+                    continue;
+                }
+            };
+            let is_ready = match socket.check_state(now_ms) {
+                Ok(()) => {
+                    let newly_connected = !socket.connect_reported;
+                    socket.connect_reported = true;
+                    newly_connected || socket.has_data(now_ms) || socket.is_peer_closed(now_ms)
+                }
+                Err(e) => e.kind() != io::ErrorKind::WouldBlock,
+            };
+            if is_ready {
+                ready.push(*token);
+            }
+        }
+        Ok(ready)
+    }
+}
+
+thread_local!(static SIM_ACTIVE: Cell<bool> = const { Cell::new(false) });
+
+/// A set of `TestPeer`s talking over a simulated network
+pub struct SimNetwork<'a> {
+    pub peers: Vec<TestPeer<'a>>,
+    /// how much simulated time passes in each round
+    pub round_ms: u64,
+    /// number of rounds run so far
+    pub round: u64,
+    now_ms: u128,
+    network: MemNetwork,
+    /// peer index --> the peer's host on `network`
+    hosts: Vec<usize>,
+    /// one DNS resolver per peer, so the peers can resolve data URLs and download blocks
+    dns: Vec<Option<(DNSClient, thread::JoinHandle<()>)>>,
+}
+
+impl<'a> SimNetwork<'a> {
+    /// Run these peers, which must have been instantiated on `network` (see
+    /// `TestPeerConfig::mem_network`).
+    /// Panics if there's already a simulated network on this thread.
+    pub fn new(network: MemNetwork, peers: Vec<TestPeer<'a>>) -> SimNetwork<'a> {
+        let hosts = peers
+            .iter()
+            .map(|peer| {
+                network
+                    .host_at(peer.config.server_port)
+                    .expect("BUG: peer is not on the simulated network")
+            })
+            .collect();
+
+        SIM_ACTIVE.with(|active| {
+            assert!(
+                !active.get(),
+                "BUG: there is already a simulated network on this thread"
+            );
+            active.set(true);
+        });
+
+        // start the virtual clock at the current time, so nothing the peers stored when they
+        // were instantiated looks like it's from the future
+        let now_ms = get_epoch_time_ms();
+        set_mock_epoch_time_ms(Some(now_ms));
+
+        let dns = peers.iter().map(|_| Some(dns_thread_start(100))).collect();
+        SimNetwork {
+            peers,
+            round_ms: 1000,
+            round: 0,
+            now_ms,
+            network,
+            hosts,
+            dns,
+        }
+    }
+
+    /// Current simulated time, in milliseconds
+    pub fn now_ms(&self) -> u128 {
+        self.now_ms
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.network.stats()
+    }
+
+    /// Change the simulated network
+    pub fn apply(&mut self, event: SimEvent) -> () {
+        test_debug!("Sim: round {}: {:?}", self.round, &event);
+        match event {
+            SimEvent::Partition(groups) => {
+                let host_groups: Vec<Vec<usize>> = groups
+                    .iter()
+                    .map(|group| group.iter().map(|peer_idx| self.hosts[*peer_idx]).collect())
+                    .collect();
+                self.network.partition(&host_groups);
+            }
+            SimEvent::Heal => {
+                self.network.heal();
+            }
+            SimEvent::SetLink(sender, receiver, link) => {
+                self.network
+                    .set_link(self.hosts[sender], self.hosts[receiver], link);
+            }
+            SimEvent::SetLinkBoth(peer_1, peer_2, link) => {
+                self.network
+                    .set_link(self.hosts[peer_1], self.hosts[peer_2], link);
+                self.network
+                    .set_link(self.hosts[peer_2], self.hosts[peer_1], link);
+            }
+            SimEvent::SetDefaultLink(link) => {
+                self.network.set_default_link(link);
+            }
+            SimEvent::SetPeerLinks(peer_idx, link) => {
+                for i in 0..self.peers.len() {
+                    if i != peer_idx {
+                        self.network
+                            .set_link(self.hosts[peer_idx], self.hosts[i], link);
+                        self.network
+                            .set_link(self.hosts[i], self.hosts[peer_idx], link);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Advance the clock by one round, and step each peer once, in order.
+    /// Returns each peer's network result.
+    pub fn step(&mut self) -> Vec<Result<NetworkResult, net_error>> {
+        self.now_ms += self.round_ms as u128;
+        set_mock_epoch_time_ms(Some(self.now_ms));
+
+        let results = self
+            .peers
+            .iter_mut()
+            .zip(self.dns.iter_mut())
+            .map(|(peer, dns)| {
+                let (dns_client, _) = dns.as_mut().expect("BUG: DNS resolver already shut down");
+                peer.step_dns(dns_client)
+            })
+            .collect();
+        self.round += 1;
+        results
+    }
+
+    /// Run until `done` returns true, or until `max_rounds` rounds have passed.
+    /// Returns whether or not `done` returned true.
+    pub fn run_until<F>(&mut self, max_rounds: u64, mut done: F) -> bool
+    where
+        F: FnMut(&mut SimNetwork<'a>) -> bool,
+    {
+        for _ in 0..max_rounds {
+            if done(self) {
+                return true;
+            }
+            self.step();
+        }
+        done(self)
+    }
+
+    /// Run a scripted scenario for `num_rounds` rounds.  Each scripted event is applied before the
+    /// round with its number is run (so events for round 0 are applied before the first step).
+    /// `check` is called after every round, and may assert on the peers' state.
+    pub fn run_script<F>(&mut self, script: Vec<(u64, SimEvent)>, num_rounds: u64, mut check: F)
+    where
+        F: FnMut(&mut SimNetwork<'a>) -> (),
+    {
+        let start = self.round;
+        let mut script = script;
+        script.sort_by_key(|(round, _)| *round);
+        let mut script = VecDeque::from(script);
+        for i in 0..num_rounds {
+            while script
+                .front()
+                .map(|(round, _)| *round <= i)
+                .unwrap_or(false)
+            {
+                let (_, event) = script.pop_front().expect("BUG: script is non-empty");
+                self.apply(event);
+            }
+            self.step();
+            check(self);
+        }
+        assert_eq!(self.round, start + num_rounds);
+    }
+}
+
+impl<'a> Drop for SimNetwork<'a> {
+    fn drop(&mut self) {
+        for (dns_client, thread_handle) in self.dns.iter_mut().filter_map(|dns| dns.take()) {
+            dns_thread_shutdown(dns_client, thread_handle);
+        }
+        set_mock_epoch_time_ms(None);
+        SIM_ACTIVE.with(|active| active.set(false));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use clarity::vm::types::StacksAddressExtensions;
+
+    use crate::chainstate::stacks::*;
+    use crate::core::mempool::MemPoolDB;
+    use crate::net::test::*;
+    use crate::util_lib::test::with_timeout;
+    use stacks_common::address::C32_ADDRESS_VERSION_TESTNET_SINGLESIG;
+    use stacks_common::codec::StacksMessageCodec;
+    use stacks_common::types::chainstate::{
+        BlockHeaderHash, ConsensusHash, StacksAddress, StacksPrivateKey,
+    };
+    use stacks_common::util::hash::Hash160;
+
+    /// Configure `num_peers` peers on `network` that all know about each other
+    fn make_sim_configs(
+        test_name: &str,
+        base_port: u16,
+        num_peers: usize,
+        network: &MemNetwork,
+    ) -> Vec<TestPeerConfig> {
+        let mut configs = vec![];
+        for i in 0..num_peers {
+            let port = base_port + 2 * (i as u16);
+            let mut config = TestPeerConfig::new(test_name, port, port + 1);
+            config.mem_network = Some(network.clone());
+            configs.push(config);
+        }
+        for i in 0..num_peers {
+            for j in 0..num_peers {
+                if i != j {
+                    let neighbor = configs[j].to_neighbor();
+                    configs[i].add_neighbor(&neighbor);
+                }
+            }
+        }
+        configs
+    }
+
+    fn make_sim_peers<'a>(
+        test_name: &str,
+        base_port: u16,
+        num_peers: usize,
+        network: &MemNetwork,
+    ) -> Vec<TestPeer<'a>> {
+        make_sim_configs(test_name, base_port, num_peers, network)
+            .into_iter()
+            .map(|config| TestPeer::new(config))
+            .collect()
+    }
+
+    /// Mine `num_blocks` blocks on one peer, and make sure every other peer sees the same
+    /// burnchain (but not the Stacks blocks)
+    fn mine_blocks(peers: &mut Vec<TestPeer>, miner_idx: usize, num_blocks: usize) -> () {
+        for _ in 0..num_blocks {
+            let (mut burn_ops, stacks_block, microblocks) = peers[miner_idx].make_default_tenure();
+            let (_, burn_header_hash, _) = peers[miner_idx].next_burnchain_block(burn_ops.clone());
+            peers[miner_idx].process_stacks_epoch_at_tip(&stacks_block, &microblocks);
+
+            TestPeer::set_ops_burn_header_hash(&mut burn_ops, &burn_header_hash);
+            for i in 0..peers.len() {
+                if i != miner_idx {
+                    peers[i].next_burnchain_block_raw(burn_ops.clone());
+                }
+            }
+        }
+    }
+
+    /// How many of `peer_idx`'s blocks does `viewer_idx` know about?
+    fn inv_num_blocks(sim: &SimNetwork, viewer_idx: usize, peer_idx: usize) -> u64 {
+        let nk = sim.peers[peer_idx].to_neighbor().addr;
+        match sim.peers[viewer_idx].network.inv_state {
+            Some(ref inv) => inv.get_inv_num_blocks(&nk),
+            None => 0,
+        }
+    }
+
+    fn is_authenticated(sim: &SimNetwork, viewer_idx: usize, peer_idx: usize) -> bool {
+        let port = sim.peers[peer_idx].config.server_port;
+        sim.peers[viewer_idx]
+            .network
+            .peers
+            .values()
+            .any(|convo| convo.is_authenticated() && convo.to_neighbor_key().port == port)
+    }
+
+    fn read_all(sock: &mut NetSocket) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        let mut buf = [0u8; 3];
+        loop {
+            match sock.read(&mut buf) {
+                Ok(0) => {
+                    return Ok(data);
+                }
+                Ok(count) => data.extend_from_slice(&buf[0..count]),
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(data);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sim_mock_clock() {
+        let now = get_epoch_time_ms();
+        set_mock_epoch_time_ms(Some(1_234_567));
+        assert_eq!(get_epoch_time_ms(), 1_234_567);
+        assert_eq!(stacks_common::util::get_epoch_time_secs(), 1_234);
+        set_mock_epoch_time_ms(None);
+        assert!(get_epoch_time_ms() >= now);
+    }
+
+    #[test]
+    fn test_mem_transport() {
+        set_mock_epoch_time_ms(Some(1_000_000));
+        let network = MemNetwork::new(0);
+        let mut client = network.transport();
+        let mut server = network.transport();
+        network.set_link(0, 1, LinkConfig::new(100, 0));
+        network.set_link(1, 0, LinkConfig::new(200, 0));
+
+        let server_addr: SocketAddr = "0.0.0.0:20000".parse().unwrap();
+        server.bind(&server_addr, 1).unwrap();
+        assert_eq!(server.bind(&server_addr, 2), Err(net_error::BindError));
+        assert_eq!(network.host_at(20000), Some(1));
+
+        // nothing listening
+        let mut refused = client.connect(&"127.0.0.1:20002".parse().unwrap()).unwrap();
+        client.register(&refused, 10).unwrap();
+        assert_eq!(client.poll(0).unwrap(), vec![10]);
+        assert_eq!(
+            refused.read(&mut [0u8; 1]).unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+        client.deregister(&refused).unwrap();
+
+        // the connection request takes 100ms to arrive, and the reply 200ms more
+        let mut sock = client.connect(&"127.0.0.1:20000".parse().unwrap()).unwrap();
+        client.register(&sock, 11).unwrap();
+        assert_eq!(client.poll(0).unwrap(), Vec::<usize>::new());
+        assert_eq!(server.poll(0).unwrap(), Vec::<usize>::new());
+        assert!(server.accept(1).unwrap().is_none());
+
+        set_mock_epoch_time_ms(Some(1_000_100));
+        assert_eq!(server.poll(0).unwrap(), vec![1]);
+        let (mut accepted, client_addr) = server.accept(1).unwrap().unwrap();
+        assert!(server.accept(1).unwrap().is_none());
+        assert_eq!(accepted.peer_addr().unwrap(), client_addr);
+        server.register(&accepted, 12).unwrap();
+        assert_eq!(
+            sock.write(&[1]).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        set_mock_epoch_time_ms(Some(1_000_300));
+        assert_eq!(client.poll(0).unwrap(), vec![11]);
+        assert_eq!(client.poll(0).unwrap(), Vec::<usize>::new());
+
+        // bytes arrive after the link's latency, in order
+        sock.write_all(&[1, 2, 3, 4]).unwrap();
+        set_mock_epoch_time_ms(Some(1_000_350));
+        sock.write_all(&[5, 6]).unwrap();
+        assert_eq!(server.poll(0).unwrap(), Vec::<usize>::new());
+        assert_eq!(read_all(&mut accepted).unwrap(), Vec::<u8>::new());
+
+        set_mock_epoch_time_ms(Some(1_000_400));
+        assert_eq!(server.poll(0).unwrap(), vec![12]);
+        assert_eq!(read_all(&mut accepted).unwrap(), vec![1, 2, 3, 4]);
+
+        set_mock_epoch_time_ms(Some(1_000_450));
+        assert_eq!(read_all(&mut accepted).unwrap(), vec![5, 6]);
+        assert_eq!(server.poll(0).unwrap(), Vec::<usize>::new());
+        assert_eq!(network.stats().sent, 2);
+
+        // a shutdown arrives after the data sent before it
+        accepted.write_all(&[7]).unwrap();
+        accepted.shutdown().unwrap();
+        set_mock_epoch_time_ms(Some(1_000_650));
+        assert_eq!(client.poll(0).unwrap(), vec![11]);
+        assert_eq!(read_all(&mut sock).unwrap(), vec![7]);
+        assert_eq!(sock.read(&mut [0u8; 1]).unwrap(), 0);
+        assert_eq!(
+            sock.write(&[8]).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        client.deregister(&sock).unwrap();
+        server.deregister(&accepted).unwrap();
+        drop(sock);
+        drop(accepted);
+
+        // partitions reset open connections, and refuse new ones
+        let mut sock = client.connect(&"127.0.0.1:20000".parse().unwrap()).unwrap();
+        client.register(&sock, 13).unwrap();
+        set_mock_epoch_time_ms(Some(1_001_000));
+        let (accepted, _) = server.accept(1).unwrap().unwrap();
+        server.register(&accepted, 14).unwrap();
+        sock.write_all(&[9]).unwrap();
+
+        network.partition(&[vec![0], vec![1]]);
+        assert_eq!(server.poll(0).unwrap(), vec![14]);
+        assert_eq!(
+            sock.write(&[10]).unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+        let mut partitioned = client.connect(&"127.0.0.1:20000".parse().unwrap()).unwrap();
+        assert_eq!(
+            partitioned.read(&mut [0u8; 1]).unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+        assert_eq!(network.stats().partitioned, 2);
+
+        network.heal();
+        let mut healed = client.connect(&"127.0.0.1:20000".parse().unwrap()).unwrap();
+        assert_eq!(
+            healed.read(&mut [0u8; 1]).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        set_mock_epoch_time_ms(None);
+    }
+
+    #[test]
+    fn test_mem_transport_lossy_link() {
+        // lost writes are retransmitted, so they arrive late but in order
+        let run = |seed| {
+            set_mock_epoch_time_ms(Some(1_000_000));
+            let network = MemNetwork::new(seed);
+            let mut client = network.transport();
+            let mut server = network.transport();
+            server.bind(&"0.0.0.0:20000".parse().unwrap(), 1).unwrap();
+            let mut sock = client.connect(&"127.0.0.1:20000".parse().unwrap()).unwrap();
+            let (mut accepted, _) = server.accept(1).unwrap().unwrap();
+            network.set_default_link(LinkConfig::new(10, 500));
+
+            for i in 0..100 {
+                sock.write_all(&[i]).unwrap();
+            }
+            set_mock_epoch_time_ms(Some(1_000_000 + 100 * RETRANSMIT_TIMEOUT_MS as u128));
+            let data = read_all(&mut accepted).unwrap();
+            set_mock_epoch_time_ms(None);
+            (data, network.stats())
+        };
+
+        let (data, stats) = run(1);
+        assert_eq!(data, (0..100).collect::<Vec<u8>>());
+        assert_eq!(stats.sent, 100);
+        assert!(stats.lost > 0);
+
+        assert_eq!(run(1).1, stats);
+        assert!(run(2).1 != stats);
+    }
+
+    #[test]
+    fn test_sim_partition_heal_inv_sync() {
+        with_timeout(600, || {
+            let network = MemNetwork::new(0);
+            let mut peers = make_sim_peers("test_sim_partition_heal_inv_sync", 34100, 2, &network);
+            let num_blocks = 5;
+            mine_blocks(&mut peers, 0, num_blocks);
+
+            let mut sim = SimNetwork::new(network, peers);
+
+            // while partitioned, peer 1 learns nothing about peer 0's blocks
+            sim.run_script(
+                vec![(0, SimEvent::Partition(vec![vec![0], vec![1]]))],
+                30,
+                |sim| {
+                    assert!(!is_authenticated(sim, 0, 1));
+                    assert!(!is_authenticated(sim, 1, 0));
+                    assert_eq!(inv_num_blocks(sim, 1, 0), 0);
+                },
+            );
+            assert!(sim.stats().partitioned > 0);
+
+            // once healed, it syncs
+            sim.apply(SimEvent::Heal);
+            let synced = sim.run_until(600, |sim| inv_num_blocks(sim, 1, 0) >= num_blocks as u64);
+            assert!(synced, "peer 1 never learned peer 0's inventory");
+        })
+    }
+
+    #[test]
+    fn test_sim_partition_heal_block_download() {
+        with_timeout(600, || {
+            let network = MemNetwork::new(0);
+            let mut peers =
+                make_sim_peers("test_sim_partition_heal_block_download", 34140, 2, &network);
+            let num_blocks = 5;
+            mine_blocks(&mut peers, 0, num_blocks);
+
+            let mut sim = SimNetwork::new(network, peers);
+            let mut downloaded = HashSet::new();
+
+            // nothing gets downloaded while the peers are partitioned
+            sim.apply(SimEvent::Partition(vec![vec![0], vec![1]]));
+            for _ in 0..30 {
+                let results = sim.step();
+                if let Ok(ref result) = results[1] {
+                    assert_eq!(result.blocks.len(), 0);
+                }
+            }
+
+            // once healed, peer 1 downloads all of peer 0's blocks over slow links
+            sim.apply(SimEvent::Heal);
+            sim.apply(SimEvent::SetDefaultLink(LinkConfig::new(500, 0)));
+            for _ in 0..600 {
+                let results = sim.step();
+                if let Ok(ref result) = results[1] {
+                    for (consensus_hash, block, _) in result.blocks.iter() {
+                        downloaded.insert((consensus_hash.clone(), block.block_hash()));
+                    }
+                }
+                if downloaded.len() >= num_blocks {
+                    break;
+                }
+            }
+            assert_eq!(downloaded.len(), num_blocks);
+        })
+    }
+
+    #[test]
+    fn test_sim_partition_heal_mempool_sync() {
+        with_timeout(600, || {
+            let network = MemNetwork::new(0);
+            let num_txs = 5;
+            let pks: Vec<_> = (0..num_txs).map(|_| StacksPrivateKey::new()).collect();
+            let initial_balances: Vec<_> = pks
+                .iter()
+                .map(|pk| (to_addr(pk).to_account_principal(), 1000000000))
+                .collect();
+
+            let mut peers: Vec<_> =
+                make_sim_configs("test_sim_partition_heal_mempool_sync", 34150, 2, &network)
+                    .into_iter()
+                    .map(|mut config| {
+                        config.connection_opts.mempool_sync_interval = 1;
+                        config.initial_balances = initial_balances.clone();
+                        TestPeer::new(config)
+                    })
+                    .collect();
+            let num_blocks = 5;
+            mine_blocks(&mut peers, 0, num_blocks);
+
+            // peer 0 has some transactions that peer 1 doesn't
+            let recipient = StacksAddress {
+                version: C32_ADDRESS_VERSION_TESTNET_SINGLESIG,
+                bytes: Hash160([0xff; 20]),
+            };
+            let mut txids = HashSet::new();
+            let mut mempool = peers[0].mempool.take().unwrap();
+            let mut mempool_tx = mempool.tx_begin().unwrap();
+            for pk in pks.iter() {
+                let mut tx = StacksTransaction::new(
+                    TransactionVersion::Testnet,
+                    TransactionAuth::from_p2pkh(pk).unwrap(),
+                    TransactionPayload::TokenTransfer(
+                        recipient.to_account_principal(),
+                        123,
+                        TokenTransferMemo([0u8; 34]),
+                    ),
+                );
+                tx.chain_id = 0x80000000;
+                tx.set_tx_fee(1000);
+                tx.set_origin_nonce(0);
+                let mut tx_signer = StacksTransactionSigner::new(&tx);
+                tx_signer.sign_origin(pk).unwrap();
+                let tx = tx_signer.get_tx().unwrap();
+
+                let origin_addr = tx.origin_address();
+                MemPoolDB::try_add_tx(
+                    &mut mempool_tx,
+                    peers[0].chainstate(),
+                    &ConsensusHash([0x1; 20]),
+                    &BlockHeaderHash([0x2; 32]),
+                    tx.txid(),
+                    tx.serialize_to_vec(),
+                    tx.get_tx_fee(),
+                    num_blocks as u64,
+                    &origin_addr,
+                    0,
+                    &origin_addr,
+                    0,
+                    None,
+                )
+                .unwrap();
+                txids.insert(tx.txid());
+            }
+            mempool_tx.commit().unwrap();
+            peers[0].mempool = Some(mempool);
+
+            let mut sim = SimNetwork::new(network, peers);
+            let mut synced = HashSet::new();
+
+            // nothing gets synced while the peers are partitioned
+            sim.apply(SimEvent::Partition(vec![vec![0], vec![1]]));
+            for _ in 0..30 {
+                let results = sim.step();
+                if let Ok(ref result) = results[1] {
+                    assert_eq!(result.synced_transactions.len(), 0);
+                }
+            }
+
+            // once healed, peer 1 gets all of peer 0's transactions over lossy links
+            sim.apply(SimEvent::Heal);
+            sim.apply(SimEvent::SetDefaultLink(LinkConfig::new(100, 100)));
+            for _ in 0..600 {
+                let results = sim.step();
+                if let Ok(ref result) = results[1] {
+                    for tx in result.synced_transactions.iter() {
+                        synced.insert(tx.txid());
+                    }
+                }
+                if synced.len() >= num_txs {
+                    break;
+                }
+            }
+            assert_eq!(synced, txids);
+            assert!(sim.stats().lost > 0);
+        })
+    }
+
+    #[test]
+    fn test_sim_slow_peer_inv_sync() {
+        with_timeout(600, || {
+            let network = MemNetwork::new(0);
+            let mut peers = make_sim_peers("test_sim_slow_peer_inv_sync", 34110, 2, &network);
+            let num_blocks = 5;
+            mine_blocks(&mut peers, 0, num_blocks);
+
+            let mut sim = SimNetwork::new(network, peers);
+
+            // peer 0 is slow, but not too slow to talk to
+            sim.apply(SimEvent::SetPeerLinks(0, LinkConfig::new(3000, 0)));
+            let synced = sim.run_until(600, |sim| inv_num_blocks(sim, 1, 0) >= num_blocks as u64);
+            assert!(synced, "peer 1 never learned peer 0's inventory");
+
+            let stats = sim.stats();
+            assert!(stats.sent > 0);
+            assert_eq!(stats.lost, 0);
+            assert_eq!(stats.partitioned, 0);
+        })
+    }
+
+    #[test]
+    fn test_sim_lossy_links_deterministic() {
+        with_timeout(600, || {
+            // two runs of the same scenario with the same seed lose the same number of
+            // transmissions before the peers connect
+            let mut lost = vec![];
+            for run in 0..2 {
+                let network = MemNetwork::new(12345);
+                let peers = make_sim_peers(
+                    &format!("test_sim_lossy_links_deterministic_{}", run),
+                    34120,
+                    2,
+                    &network,
+                );
+                let mut sim = SimNetwork::new(network, peers);
+                sim.apply(SimEvent::SetDefaultLink(LinkConfig::new(0, 500)));
+                let connected = sim.run_until(600, |sim| {
+                    is_authenticated(sim, 0, 1) && is_authenticated(sim, 1, 0)
+                });
+                assert!(connected, "peers never connected over a lossy link");
+                lost.push((sim.round, sim.stats()));
+            }
+            assert!(lost[0].1.lost > 0);
+            assert_eq!(lost[0], lost[1]);
+        })
+    }
+
+    #[test]
+    fn test_sim_eclipse_attempt() {
+        with_timeout(600, || {
+            // peer 0 is the victim, peer 1 is honest and has the blocks, and peers 2 and 3 are
+            // attackers who have cut the victim off from peer 1.
+            let network = MemNetwork::new(0);
+            let mut peers = make_sim_peers("test_sim_eclipse_attempt", 34130, 4, &network);
+            let num_blocks = 5;
+            mine_blocks(&mut peers, 1, num_blocks);
+
+            let mut sim = SimNetwork::new(network, peers);
+
+            // the victim only gets to talk to the attackers, who don't relay the honest peer's
+            // data
+            sim.run_script(
+                vec![(0, SimEvent::Partition(vec![vec![0, 2, 3], vec![1]]))],
+                30,
+                |sim| {
+                    assert!(!is_authenticated(sim, 0, 1));
+                    assert_eq!(inv_num_blocks(sim, 0, 1), 0);
+                },
+            );
+            let eclipsed = sim.run_until(600, |sim| {
+                assert!(!is_authenticated(sim, 0, 1));
+                is_authenticated(sim, 0, 2) || is_authenticated(sim, 0, 3)
+            });
+            assert!(eclipsed, "victim never connected to the attackers");
+            assert_eq!(inv_num_blocks(&sim, 0, 1), 0);
+
+            // the victim finds the honest peer once the eclipse ends
+            sim.apply(SimEvent::Heal);
+            let synced = sim.run_until(600, |sim| inv_num_blocks(sim, 0, 1) >= num_blocks as u64);
+            assert!(synced, "victim never learned the honest peer's inventory");
+        })
+    }
+}
@@ -0,0 +1,353 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bech32 (BIP-173) and bech32m (BIP-350) encoder and decoder, and the segwit
+//! address encoding built on top of them.
+
+use crate::address::Error;
+
+static BECH32_CHARS: &'static [u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+/// Longest string a bech32 decoder must accept
+const BECH32_MAX_LEN: usize = 90;
+
+/// Which checksum constant a bech32 string uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// BIP-173; used by witness version 0
+    Bech32,
+    /// BIP-350; used by witness versions 1 through 16
+    Bech32m,
+}
+
+impl Variant {
+    fn constant(&self) -> u32 {
+        match self {
+            Variant::Bech32 => BECH32_CONST,
+            Variant::Bech32m => BECH32M_CONST,
+        }
+    }
+
+    fn from_constant(c: u32) -> Option<Variant> {
+        match c {
+            BECH32_CONST => Some(Variant::Bech32),
+            BECH32M_CONST => Some(Variant::Bech32m),
+            _ => None,
+        }
+    }
+
+    /// Checksum variant that a segwit address of the given witness version must use
+    pub fn for_witness_version(version: u8) -> Variant {
+        if version == 0 {
+            Variant::Bech32
+        } else {
+            Variant::Bech32m
+        }
+    }
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    const GEN: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for v in values.iter() {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ (*v as u32);
+        for (i, g) in GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(hrp.len() * 2 + 1);
+    ret.extend(hrp.iter().map(|c| c >> 5));
+    ret.push(0);
+    ret.extend(hrp.iter().map(|c| c & 0x1f));
+    ret
+}
+
+fn create_checksum(hrp: &[u8], data: &[u8], variant: Variant) -> [u8; 6] {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0u8; 6]);
+    let pm = polymod(&values) ^ variant.constant();
+    let mut ret = [0u8; 6];
+    for (i, r) in ret.iter_mut().enumerate() {
+        *r = ((pm >> (5 * (5 - i))) & 0x1f) as u8;
+    }
+    ret
+}
+
+/// Regroup a sequence of `from`-bit values into `to`-bit values.
+/// When decoding (`pad == false`), leftover bits must be fewer than `from` and all zero.
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Error> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let maxv: u32 = (1 << to) - 1;
+    let mut ret = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for value in data.iter() {
+        let v = *value as u32;
+        if (v >> from) != 0 {
+            return Err(Error::BadByte(*value));
+        }
+        acc = (acc << from) | v;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            ret.push(((acc >> bits) & maxv) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            ret.push(((acc << (to - bits)) & maxv) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & maxv) != 0 {
+        return Err(Error::Other("Invalid bech32 padding".to_string()));
+    }
+    Ok(ret)
+}
+
+/// Encode a human-readable part and a sequence of 5-bit values.
+/// The hrp must be lowercase ASCII.
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> String {
+    let checksum = create_checksum(hrp.as_bytes(), data, variant);
+    let mut ret = String::with_capacity(hrp.len() + 1 + data.len() + checksum.len());
+    ret.push_str(hrp);
+    ret.push('1');
+    for d in data.iter().chain(checksum.iter()) {
+        ret.push(BECH32_CHARS[*d as usize] as char);
+    }
+    ret
+}
+
+/// Decode a bech32 or bech32m string into its (lowercased) human-readable part, its 5-bit data
+/// values (without the checksum), and the checksum variant it used.
+pub fn decode(s: &str) -> Result<(String, Vec<u8>, Variant), Error> {
+    if s.len() > BECH32_MAX_LEN {
+        return Err(Error::InvalidLength(s.len()));
+    }
+    let has_lower = s.bytes().any(|c| c.is_ascii_lowercase());
+    let has_upper = s.bytes().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(Error::Other("Mixed-case bech32 string".to_string()));
+    }
+    if let Some(c) = s.bytes().find(|c| *c < 33 || *c > 126) {
+        return Err(Error::BadByte(c));
+    }
+
+    let s = s.to_ascii_lowercase();
+    let sep = s
+        .rfind('1')
+        .ok_or_else(|| Error::Other("No bech32 separator".to_string()))?;
+    if sep == 0 || sep + 7 > s.len() {
+        return Err(Error::TooShort(s.len()));
+    }
+
+    let (hrp, rest) = s.split_at(sep);
+    let mut data = Vec::with_capacity(rest.len() - 1);
+    for c in rest.bytes().skip(1) {
+        let idx = BECH32_CHARS
+            .iter()
+            .position(|x| *x == c)
+            .ok_or(Error::BadByte(c))?;
+        data.push(idx as u8);
+    }
+
+    let mut values = hrp_expand(hrp.as_bytes());
+    values.extend_from_slice(&data);
+    let pm = polymod(&values);
+    let variant = Variant::from_constant(pm).ok_or(Error::BadChecksum(BECH32_CONST, pm))?;
+
+    data.truncate(data.len() - 6);
+    Ok((hrp.to_string(), data, variant))
+}
+
+/// Encode a segwit address from its witness version and witness program
+pub fn encode_segwit_address(hrp: &str, version: u8, program: &[u8]) -> Result<String, Error> {
+    check_witness_program(version, program)?;
+    let mut data = vec![version];
+    data.extend(convert_bits(program, 8, 5, true)?);
+    Ok(encode(hrp, &data, Variant::for_witness_version(version)))
+}
+
+/// Decode a segwit address into its (lowercased) human-readable part, witness version, and
+/// witness program.  The checksum variant must match the witness version.
+pub fn decode_segwit_address(addr: &str) -> Result<(String, u8, Vec<u8>), Error> {
+    let (hrp, data, variant) = decode(addr)?;
+    if data.is_empty() {
+        return Err(Error::EmptyData);
+    }
+    let version = data[0];
+    if Variant::for_witness_version(version) != variant {
+        return Err(Error::InvalidVersion(version));
+    }
+    let program = convert_bits(&data[1..], 5, 8, false)?;
+    check_witness_program(version, &program)?;
+    Ok((hrp, version, program))
+}
+
+fn check_witness_program(version: u8, program: &[u8]) -> Result<(), Error> {
+    if version > 16 {
+        return Err(Error::InvalidVersion(version));
+    }
+    if program.len() < 2 || program.len() > 40 {
+        return Err(Error::InvalidLength(program.len()));
+    }
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return Err(Error::InvalidLength(program.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::hash::{hex_bytes, to_hex};
+
+    /// Turn a decoded segwit address back into its scriptPubKey
+    fn to_scriptpubkey(version: u8, program: &[u8]) -> Vec<u8> {
+        let mut ret = vec![if version == 0 { 0 } else { version + 0x50 }];
+        ret.push(program.len() as u8);
+        ret.extend_from_slice(program);
+        ret
+    }
+
+    #[test]
+    fn test_bech32_checksums() {
+        let valid = vec![
+            ("A12UEL5L", Variant::Bech32),
+            ("a12uel5l", Variant::Bech32),
+            (
+                "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+                Variant::Bech32,
+            ),
+            ("a1lqfn3a", Variant::Bech32m),
+            (
+                "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+                Variant::Bech32m,
+            ),
+            ("?1v759aa", Variant::Bech32m),
+        ];
+        for (s, variant) in valid {
+            let (hrp, data, v) = decode(s).unwrap();
+            assert_eq!(v, variant);
+            assert_eq!(encode(&hrp, &data, v), s.to_ascii_lowercase());
+        }
+
+        let invalid = vec![
+            // hrp character out of range
+            "\x201nwldj5",
+            // no separator
+            "pzry9x0s0muk",
+            // empty hrp
+            "1pzry9x0s0muk",
+            // invalid data character
+            "x1b4n0q5v",
+            // checksum too short
+            "li1dgmt3",
+            // bad checksum
+            "A12UEL5M",
+            // mixed case
+            "A12uEL5L",
+            // too long
+            "an84characterslonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11d6pts4",
+        ];
+        for s in invalid {
+            assert!(decode(s).is_err(), "decoded invalid string {}", s);
+        }
+    }
+
+    #[test]
+    fn test_segwit_address_roundtrip() {
+        let valid = vec![
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            (
+                "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+                "5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            ("BC1SW50QGDZ25J", "6002751e"),
+            (
+                "bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs",
+                "5210751e76e8199196d454941c45d1b3a323",
+            ),
+            (
+                "tb1qqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesrxh6hy",
+                "0020000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+            ),
+            (
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                "5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+            ),
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+        ];
+        for (addr, script_hex) in valid {
+            let (hrp, version, program) = decode_segwit_address(addr).unwrap();
+            assert_eq!(to_hex(&to_scriptpubkey(version, &program)), script_hex);
+            assert_eq!(
+                encode_segwit_address(&hrp, version, &program).unwrap(),
+                addr.to_ascii_lowercase()
+            );
+        }
+
+        let invalid = vec![
+            // v1 program with a bech32 checksum
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd",
+            // v0 program with a bech32m checksum
+            "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KEMEUP6",
+            // witness version 17
+            "bc1r0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqjjdd7c",
+            // program too short
+            "bc1pw5dgrnzv",
+            // program too long
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v8n0nx0muaewav253zgeav",
+            // v0 program of length 16
+            "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P",
+            // mixed case
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7",
+            // more than 4 padding bits
+            "bc1zw508d6qejxtdg4y5r3zarvaryvqyzf3du",
+            // empty data section
+            "bc1gmk9yu",
+        ];
+        for addr in invalid {
+            assert!(
+                decode_segwit_address(addr).is_err(),
+                "decoded invalid address {}",
+                addr
+            );
+        }
+
+        let program = hex_bytes("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        assert!(encode_segwit_address("bc", 17, &program).is_err());
+        assert!(encode_segwit_address("bc", 0, &program[0..16]).is_err());
+    }
+}
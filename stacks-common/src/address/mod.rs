@@ -30,6 +30,7 @@ use sha2::Sha256;
 use std::convert::TryFrom;
 
 pub mod b58;
+pub mod bech32;
 pub mod c32;
 #[cfg(test)]
 pub mod c32_old;
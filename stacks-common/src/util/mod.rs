@@ -32,7 +32,30 @@ use std::thread;
 use std::time;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+#[cfg(any(test, feature = "testing"))]
+thread_local!(static MOCK_EPOCH_TIME_MS: std::cell::Cell<Option<u128>> = std::cell::Cell::new(None));
+
+/// Make `get_epoch_time_secs()` and `get_epoch_time_ms()` on this thread read a virtual clock
+/// instead of the system clock.  Pass `None` to go back to the system clock.
+#[cfg(any(test, feature = "testing"))]
+pub fn set_mock_epoch_time_ms(time_ms: Option<u128>) -> () {
+    MOCK_EPOCH_TIME_MS.with(|mock_time| mock_time.set(time_ms));
+}
+
+#[cfg(any(test, feature = "testing"))]
+fn get_mock_epoch_time_ms() -> Option<u128> {
+    MOCK_EPOCH_TIME_MS.with(|mock_time| mock_time.get())
+}
+
+#[cfg(not(any(test, feature = "testing")))]
+fn get_mock_epoch_time_ms() -> Option<u128> {
+    None
+}
+
 pub fn get_epoch_time_secs() -> u64 {
+    if let Some(time_ms) = get_mock_epoch_time_ms() {
+        return (time_ms / 1000) as u64;
+    }
     let start = SystemTime::now();
     let since_the_epoch = start
         .duration_since(UNIX_EPOCH)
@@ -41,6 +64,9 @@ pub fn get_epoch_time_secs() -> u64 {
 }
 
 pub fn get_epoch_time_ms() -> u128 {
+    if let Some(time_ms) = get_mock_epoch_time_ms() {
+        return time_ms;
+    }
     let start = SystemTime::now();
     let since_the_epoch = start
         .duration_since(UNIX_EPOCH)
@@ -406,6 +406,26 @@ pub fn secp256k1_verify(
     })
 }
 
+/// Verify a DER-encoded signature, as found in Bitcoin scriptSigs.
+/// Like Bitcoin's standardness rules, this rejects high-S signatures.
+pub fn secp256k1_verify_der(
+    message_arr: &[u8],
+    der_signature_arr: &[u8],
+    pubkey_arr: &[u8],
+) -> Result<(), LibSecp256k1Error> {
+    _secp256k1.with(|ctx| {
+        let message = LibSecp256k1Message::from_slice(message_arr)?;
+        let sig = LibSecp256k1Signature::from_der(der_signature_arr)?;
+        let mut sig_low_s = sig.clone();
+        sig_low_s.normalize_s();
+        if sig_low_s != sig {
+            return Err(LibSecp256k1Error::IncorrectSignature);
+        }
+        let pubkey = LibSecp256k1PublicKey::from_slice(pubkey_arr)?;
+        ctx.verify_ecdsa(&message, &sig, &pubkey)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
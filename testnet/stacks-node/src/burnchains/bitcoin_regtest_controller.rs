@@ -3,12 +3,13 @@ use async_std::io::ReadExt;
 use async_std::net::TcpStream;
 use base64::encode;
 use http_types::{Method, Request, Url};
+use std::collections::HashMap;
 use std::io::Cursor;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, Mutex,
 };
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use serde::Serialize;
 use serde_json::value::RawValue;
@@ -17,8 +18,12 @@ use std::cmp;
 
 use super::super::operations::BurnchainOpSigner;
 use super::super::Config;
+use super::fee_estimator::{btc_per_kvb_to_sats_per_vb, FeeRateEstimator, FeeRateSource};
+use super::signer::{make_tx_signer, sign_transaction, BurnchainTxSigner};
+use super::utxo_manager::UTXOManager;
 use super::{BurnchainController, BurnchainTip, Error as BurnchainControllerError};
 
+use stacks::burnchains::bitcoin::blockfile::{BlockFileIndexer, BlockFileIndexerConfig};
 use stacks::burnchains::bitcoin::indexer::{
     BitcoinIndexer, BitcoinIndexerConfig, BitcoinIndexerRuntime,
 };
@@ -31,19 +36,20 @@ use stacks::burnchains::Error as burnchain_error;
 use stacks::burnchains::PoxConstants;
 use stacks::burnchains::PublicKey;
 use stacks::burnchains::{
-    bitcoin::address::{BitcoinAddress, BitcoinAddressType},
+    bitcoin::address::{BitcoinAddressType, LegacyBitcoinAddress},
     Txid,
 };
 use stacks::burnchains::{Burnchain, BurnchainParameters};
 use stacks::chainstate::burn::db::sortdb::SortitionDB;
 use stacks::chainstate::burn::operations::{
-    BlockstackOperationType, LeaderBlockCommitOp, LeaderKeyRegisterOp, PreStxOp, TransferStxOp,
-    UserBurnSupportOp,
+    BlockstackOperationType, DelegateStxOp, LeaderBlockCommitOp, LeaderKeyRegisterOp, PreStxOp,
+    TransferStxOp, UserBurnSupportOp,
 };
 use stacks::chainstate::coordinator::comm::CoordinatorChannels;
 use stacks::chainstate::stacks::address::StacksAddressExtensions;
 use stacks::codec::StacksMessageCodec;
 use stacks::core::StacksEpoch;
+use stacks::net::socks::{socks5_connect, Socks5Target};
 use stacks::util::hash::{hex_bytes, Hash160};
 use stacks::util::secp256k1::Secp256k1PublicKey;
 use stacks::util::sleep_ms;
@@ -53,10 +59,13 @@ use stacks_common::deps_common::bitcoin::blockdata::transaction::{
     OutPoint, Transaction, TxIn, TxOut,
 };
 use stacks_common::deps_common::bitcoin::network::encodable::ConsensusEncodable;
-use stacks_common::deps_common::bitcoin::network::serialize::RawEncoder;
+use stacks_common::deps_common::bitcoin::network::serialize::{deserialize, RawEncoder};
 use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
 
-use stacks::monitoring::{increment_btc_blocks_received_counter, increment_btc_ops_sent_counter};
+use stacks::monitoring::{
+    increment_btc_blocks_received_counter, increment_btc_ops_sent_counter,
+    increment_miner_utxo_consolidations_counter, update_miner_utxo_health,
+};
 
 #[cfg(test)]
 use stacks::chainstate::burn::Opcodes;
@@ -66,11 +75,15 @@ use stacks::types::chainstate::BurnchainHeaderHash;
 ///  passed since the UTXO cache was last refreshed before
 ///  the cache is force-reset.
 const UTXO_CACHE_STALENESS_LIMIT: u64 = 6;
-const DUST_UTXO_LIMIT: u64 = 5500;
+pub const DUST_UTXO_LIMIT: u64 = 5500;
+/// Largest DER-encoded signature, plus the sighash byte
+const MAX_SIGNATURE_LEN: usize = 73;
 
 pub struct BitcoinRegtestController {
     config: Config,
     indexer: BitcoinIndexer,
+    /// If set, burnchain blocks are read from local block files instead of the bitcoin peer
+    block_file_indexer: Option<BlockFileIndexer>,
     db: Option<SortitionDB>,
     burnchain_db: Option<BurnchainDB>,
     chain_tip: Option<BurnchainTip>,
@@ -78,6 +91,9 @@ pub struct BitcoinRegtestController {
     burnchain_config: Option<Burnchain>,
     ongoing_block_commit: Option<OngoingBlockCommit>,
     should_keep_running: Option<Arc<AtomicBool>>,
+    tx_signer: Box<dyn BurnchainTxSigner>,
+    utxo_manager: Arc<Mutex<UTXOManager>>,
+    fee_rates: FeeRateEstimator,
 }
 
 #[derive(Clone)]
@@ -92,6 +108,19 @@ impl OngoingBlockCommit {
     fn sum_utxos(&self) -> u64 {
         self.utxos.total_available()
     }
+
+    /// Should this block-commit be replaced even though its payload is unchanged?  Only if
+    /// bitcoind's fee estimate has risen past its fee rate, and its UTXOs can pay for the
+    /// replacement at the bumped fee rate.
+    fn should_bump_fee(&self, config: &Config, fee_rates: &FeeRateEstimator) -> bool {
+        if !fee_rates.is_underpaying(self.fees.fee_rate) {
+            return false;
+        }
+        let bumped_fees = self
+            .fees
+            .fees_from_previous_tx(&self.payload, config, fee_rates);
+        bumped_fees.estimated_amount_required() <= self.sum_utxos()
+    }
 }
 
 #[derive(Clone)]
@@ -111,11 +140,14 @@ impl LeaderBlockCommitFees {
         &self,
         payload: &LeaderBlockCommitOp,
         config: &Config,
+        fee_rates: &FeeRateEstimator,
     ) -> LeaderBlockCommitFees {
-        let mut fees = LeaderBlockCommitFees::estimated_fees_from_payload(payload, config);
+        let mut fees =
+            LeaderBlockCommitFees::estimated_fees_from_payload(payload, config, fee_rates);
         fees.spent_in_attempts = cmp::max(1, self.spent_in_attempts);
         fees.final_size = self.final_size;
-        fees.fee_rate = self.fee_rate + config.burnchain.rbf_fee_increment;
+        fees.fee_rate =
+            fee_rates.replacement_fee_rate(self.fee_rate, config.burnchain.rbf_fee_increment);
         fees.is_rbf_enabled = true;
         fees
     }
@@ -123,6 +155,7 @@ impl LeaderBlockCommitFees {
     pub fn estimated_fees_from_payload(
         payload: &LeaderBlockCommitOp,
         config: &Config,
+        fee_rates: &FeeRateEstimator,
     ) -> LeaderBlockCommitFees {
         let sunset_fee = if payload.sunset_burn > 0 {
             cmp::max(payload.sunset_burn, DUST_UTXO_LIMIT)
@@ -134,7 +167,7 @@ impl LeaderBlockCommitFees {
         let value_per_transfer = payload.burn_fee / number_of_transfers;
         let sortition_fee = value_per_transfer * number_of_transfers;
         let spent_in_attempts = 0;
-        let fee_rate = config.burnchain.satoshis_per_byte;
+        let fee_rate = fee_rates.fee_rate();
         let default_tx_size = config.burnchain.block_commit_tx_estimated_size;
 
         LeaderBlockCommitFees {
@@ -243,6 +276,8 @@ impl BitcoinRegtestController {
                 first_block: burnchain_params.first_block_height,
                 magic_bytes: burnchain_config.magic_bytes,
                 epochs: burnchain_config.epochs,
+                socks5_proxy: burnchain_config.socks5_proxy,
+                spv_headers_bundle: burnchain_config.spv_headers_bundle,
             }
         };
 
@@ -253,16 +288,39 @@ impl BitcoinRegtestController {
             runtime: indexer_runtime,
         };
 
+        let block_file_indexer = config.burnchain.blocks_dir.as_ref().map(|blocks_dir| {
+            BlockFileIndexer::new(
+                BlockFileIndexerConfig {
+                    blocks_dir: blocks_dir.clone(),
+                    spv_headers_path: indexer_config.spv_headers_path.clone(),
+                    first_block: indexer_config.first_block,
+                    magic_bytes: indexer_config.magic_bytes,
+                    epochs: indexer_config.epochs.clone(),
+                },
+                network_type,
+            )
+        });
+
+        let tx_signer = make_tx_signer(&config.burnchain.signer, config.burnchain.timeout.into());
+        let fee_rates = FeeRateEstimator::new(
+            config.burnchain.get_fee_rate_settings(),
+            Box::new(BitcoindFeeRateSource::new(config.clone())),
+        );
+
         Self {
             use_coordinator: coordinator_channel,
             config,
             indexer: burnchain_indexer,
+            block_file_indexer,
             db: None,
             burnchain_db: None,
             chain_tip: None,
             burnchain_config,
             ongoing_block_commit: None,
             should_keep_running,
+            tx_signer,
+            utxo_manager: Arc::new(Mutex::new(UTXOManager::new())),
+            fee_rates,
         }
     }
 
@@ -287,6 +345,8 @@ impl BitcoinRegtestController {
                 first_block: burnchain_params.first_block_height,
                 magic_bytes: burnchain_config.magic_bytes,
                 epochs: burnchain_config.epochs,
+                socks5_proxy: burnchain_config.socks5_proxy,
+                spv_headers_bundle: burnchain_config.spv_headers_bundle,
             }
         };
 
@@ -297,16 +357,26 @@ impl BitcoinRegtestController {
             runtime: indexer_runtime,
         };
 
+        let tx_signer = make_tx_signer(&config.burnchain.signer, config.burnchain.timeout.into());
+        let fee_rates = FeeRateEstimator::new(
+            config.burnchain.get_fee_rate_settings(),
+            Box::new(BitcoindFeeRateSource::new(config.clone())),
+        );
+
         Self {
             use_coordinator: None,
             config,
             indexer: burnchain_indexer,
+            block_file_indexer: None,
             db: None,
             burnchain_db: None,
             chain_tip: None,
             burnchain_config: None,
             ongoing_block_commit: None,
             should_keep_running: None,
+            tx_signer,
+            utxo_manager: Arc::new(Mutex::new(UTXOManager::new())),
+            fee_rates,
         }
     }
 
@@ -327,6 +397,16 @@ impl BitcoinRegtestController {
         self.ongoing_block_commit = ongoing;
     }
 
+    /// Get a handle to the miner's UTXO bookkeeping, to share with another controller
+    pub fn get_utxo_manager(&self) -> Arc<Mutex<UTXOManager>> {
+        self.utxo_manager.clone()
+    }
+
+    /// Share another controller's UTXO bookkeeping
+    pub fn set_utxo_manager(&mut self, utxo_manager: Arc<Mutex<UTXOManager>>) {
+        self.utxo_manager = utxo_manager;
+    }
+
     fn default_burnchain(&self) -> Burnchain {
         let (network_name, _network_type) = self.config.burnchain.get_bitcoin_network();
         match &self.burnchain_config {
@@ -434,13 +514,23 @@ impl BitcoinRegtestController {
             if !self.should_keep_running() {
                 return Err(BurnchainControllerError::CoordinatorClosed);
             }
-            match burnchain.sync_with_indexer(
-                &mut self.indexer,
-                coordinator_comms.clone(),
-                target_block_height_opt,
-                Some(burnchain.pox_constants.reward_cycle_length as u64),
-                self.should_keep_running.clone(),
-            ) {
+            let sync_result = match self.block_file_indexer.as_mut() {
+                Some(block_file_indexer) => burnchain.sync_with_indexer(
+                    block_file_indexer,
+                    coordinator_comms.clone(),
+                    target_block_height_opt,
+                    Some(burnchain.pox_constants.reward_cycle_length as u64),
+                    self.should_keep_running.clone(),
+                ),
+                None => burnchain.sync_with_indexer(
+                    &mut self.indexer,
+                    coordinator_comms.clone(),
+                    target_block_height_opt,
+                    Some(burnchain.pox_constants.reward_cycle_length as u64),
+                    self.should_keep_running.clone(),
+                ),
+            };
+            match sync_result {
                 Ok(x) => {
                     increment_btc_blocks_received_counter();
 
@@ -523,7 +613,7 @@ impl BitcoinRegtestController {
             .to_vec();
         let (_, network_id) = self.config.burnchain.get_bitcoin_network();
         let address =
-            BitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
+            LegacyBitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
                 .expect("Public key incorrect");
         let filter_addresses = vec![address.to_b58()];
         let _result = BitcoinRPCRequest::import_public_key(&self.config, &public_key);
@@ -623,13 +713,45 @@ impl BitcoinRegtestController {
             return None;
         }
 
+        let utxos = self.refresh_utxos(public_key, block_height)?;
+
+        let selected = self
+            .utxo_manager
+            .lock()
+            .expect("FATAL: UTXO manager mutex poisoned")
+            .select_coins(
+                total_required,
+                self.fee_rates.fee_rate(),
+                DUST_UTXO_LIMIT,
+                utxos_to_exclude.as_ref(),
+            );
+        match selected {
+            Some(selected) => Some(UTXOSet {
+                bhh: utxos.bhh,
+                utxos: selected,
+            }),
+            None => {
+                warn!(
+                    "Total unspent {} < {} for {:?}",
+                    utxos.total_available(),
+                    total_required,
+                    &public_key.to_hex()
+                );
+                None
+            }
+        }
+    }
+
+    /// Ask bitcoind for all of the miner's UTXOs, and update the UTXO manager (and its metrics)
+    /// with them.  Returns None if the miner has no UTXOs.
+    fn refresh_utxos(&self, public_key: &Secp256k1PublicKey, block_height: u64) -> Option<UTXOSet> {
         // Configure UTXO filter
         let pkh = Hash160::from_data(&public_key.to_bytes())
             .to_bytes()
             .to_vec();
         let (_, network_id) = self.config.burnchain.get_bitcoin_network();
         let address =
-            BitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
+            LegacyBitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
                 .expect("Public key incorrect");
         let filter_addresses = vec![address.to_b58()];
 
@@ -638,8 +760,8 @@ impl BitcoinRegtestController {
                 &self.config,
                 filter_addresses.clone(),
                 false,
-                total_required,
-                &utxos_to_exclude,
+                1,
+                &None,
                 block_height,
             );
 
@@ -672,8 +794,8 @@ impl BitcoinRegtestController {
                     &self.config,
                     filter_addresses.clone(),
                     false,
-                    total_required,
-                    &utxos_to_exclude,
+                    1,
+                    &None,
                     block_height,
                 );
 
@@ -686,28 +808,120 @@ impl BitcoinRegtestController {
                     }
                 };
 
-                if utxos.is_empty() {
-                    return None;
-                } else {
-                    break utxos;
-                }
+                break utxos;
             }
         } else {
             utxos
         };
 
-        let total_unspent = utxos.total_available();
-        if total_unspent < total_required {
-            warn!(
-                "Total unspent {} < {} for {:?}",
-                total_unspent,
-                total_required,
-                &public_key.to_hex()
-            );
+        let mut utxo_manager = self
+            .utxo_manager
+            .lock()
+            .expect("FATAL: UTXO manager mutex poisoned");
+        utxo_manager.refresh(utxos.utxos.clone());
+        let health = utxo_manager.health(self.fee_rates.fee_rate());
+        update_miner_utxo_health(
+            health.count as i64,
+            health.total_sats as i64,
+            health.uneconomic as i64,
+            health.locked as i64,
+        );
+
+        if utxos.is_empty() {
+            None
+        } else {
+            Some(utxos)
+        }
+    }
+
+    /// Sweep the miner's small UTXOs into one, if the configured consolidation schedule says
+    /// it is time to.  Returns the consolidation transaction's ID if one was sent.
+    pub fn consolidate_utxos(
+        &mut self,
+        signer: &mut BurnchainOpSigner,
+        block_height: u64,
+    ) -> Option<Txid> {
+        if self.config.node.mock_mining {
+            return None;
+        }
+        let settings = self.config.burnchain.get_utxo_consolidation_settings();
+        if !self
+            .utxo_manager
+            .lock()
+            .expect("FATAL: UTXO manager mutex poisoned")
+            .consolidation_due(&settings, block_height)
+        {
             return None;
         }
 
-        Some(utxos)
+        let public_key = signer.get_public_key();
+        self.refresh_utxos(&public_key, block_height)?;
+        let inputs = self
+            .utxo_manager
+            .lock()
+            .expect("FATAL: UTXO manager mutex poisoned")
+            .plan_consolidation(&settings, block_height, DUST_UTXO_LIMIT)?;
+
+        let total: u64 = inputs.iter().map(|utxo| utxo.amount).sum();
+        let fee = UTXOManager::consolidation_size(inputs.len() as u64) * settings.fee_rate;
+        let change_address_hash = Hash160::from_data(&public_key.to_bytes());
+        let tx = Transaction {
+            input: inputs
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: OutPoint {
+                        txid: utxo.txid,
+                        vout: utxo.vout,
+                    },
+                    script_sig: Script::new(),
+                    sequence: 0xFFFFFFFD, // allow RBF
+                    witness: vec![],
+                })
+                .collect(),
+            output: vec![LegacyBitcoinAddress::to_p2pkh_tx_out(
+                &change_address_hash,
+                total - fee,
+            )],
+            version: 1,
+            lock_time: 0,
+        };
+        let previous_txs = match self.get_previous_transactions(&inputs) {
+            Some(txs) => txs,
+            None => {
+                signer.dispose();
+                return None;
+            }
+        };
+        let res = sign_transaction(self.tx_signer.as_mut(), tx, previous_txs, signer);
+        signer.dispose();
+        let tx = match res {
+            Ok(tx) => tx,
+            Err(e) => {
+                warn!("Failed to sign UTXO consolidation transaction: {}", &e);
+                return None;
+            }
+        };
+
+        if !self.send_transaction(SerializedTx::new(tx.clone())) {
+            return None;
+        }
+        self.utxo_manager
+            .lock()
+            .expect("FATAL: UTXO manager mutex poisoned")
+            .add_consolidation(&tx);
+        increment_miner_utxo_consolidations_counter();
+
+        let mut txid = tx.txid().as_bytes().to_vec();
+        txid.reverse();
+        let txid = Txid::from_bytes(&txid[..]).unwrap();
+        info!(
+            "Miner node: consolidated UTXOs (txid: {}, inputs: {}, value: {}, fee: {})",
+            txid.to_hex(),
+            inputs.len(),
+            total - fee,
+            fee
+        );
+        Some(txid)
     }
 
     fn build_leader_key_register_tx(
@@ -718,8 +932,8 @@ impl BitcoinRegtestController {
     ) -> Option<Transaction> {
         let public_key = signer.get_public_key();
 
-        let btc_miner_fee = self.config.burnchain.leader_key_tx_estimated_size
-            * self.config.burnchain.satoshis_per_byte;
+        let btc_miner_fee =
+            self.config.burnchain.leader_key_tx_estimated_size * self.fee_rates.fee_rate();
         let budget_for_outputs = DUST_UTXO_LIMIT;
         let total_required = btc_miner_fee + budget_for_outputs;
 
@@ -747,11 +961,12 @@ impl BitcoinRegtestController {
         tx.output = vec![consensus_output];
 
         let address_hash = Hash160::from_data(&public_key.to_bytes());
-        let identifier_output = BitcoinAddress::to_p2pkh_tx_out(&address_hash, DUST_UTXO_LIMIT);
+        let identifier_output =
+            LegacyBitcoinAddress::to_p2pkh_tx_out(&address_hash, DUST_UTXO_LIMIT);
 
         tx.output.push(identifier_output);
 
-        let fee_rate = self.config.burnchain.satoshis_per_byte;
+        let fee_rate = self.fee_rates.fee_rate();
 
         self.finalize_tx(
             &mut tx,
@@ -803,6 +1018,9 @@ impl BitcoinRegtestController {
             BlockstackOperationType::TransferStx(payload) => {
                 self.build_transfer_stacks_tx(payload, op_signer, utxo)
             }
+            BlockstackOperationType::DelegateStx(payload) => {
+                self.build_delegate_stacks_tx(payload, op_signer, utxo)
+            }
         }?;
 
         let ser_transaction = SerializedTx::new(transaction.clone());
@@ -846,7 +1064,7 @@ impl BitcoinRegtestController {
         } else {
             self.prepare_tx(
                 &public_key,
-                DUST_UTXO_LIMIT + max_tx_size * self.config.burnchain.satoshis_per_byte,
+                DUST_UTXO_LIMIT + max_tx_size * self.fee_rates.fee_rate(),
                 None,
                 None,
                 0,
@@ -877,7 +1095,7 @@ impl BitcoinRegtestController {
             DUST_UTXO_LIMIT,
             0,
             max_tx_size,
-            self.config.burnchain.satoshis_per_byte,
+            self.fee_rates.fee_rate(),
             &mut utxos,
             signer,
         )?;
@@ -892,6 +1110,113 @@ impl BitcoinRegtestController {
         Some(tx)
     }
 
+    #[cfg(not(test))]
+    fn build_delegate_stacks_tx(
+        &mut self,
+        _payload: DelegateStxOp,
+        _signer: &mut BurnchainOpSigner,
+        _utxo: Option<UTXO>,
+    ) -> Option<Transaction> {
+        warn!("Miner node: submitting delegate stacks ops is not supported");
+        None
+    }
+
+    #[cfg(test)]
+    /// Build a delegate stacks tx.
+    ///   Like `build_transfer_stacks_tx`, this *only* works if the only existant UTXO is from
+    ///   a PreStx Op.
+    ///   The delegate's address is output 1.  If the payload has a reward address, it must be
+    ///   in output 2.
+    fn build_delegate_stacks_tx(
+        &mut self,
+        payload: DelegateStxOp,
+        signer: &mut BurnchainOpSigner,
+        utxo_to_use: Option<UTXO>,
+    ) -> Option<Transaction> {
+        let public_key = signer.get_public_key();
+        let max_tx_size = 264;
+
+        if let Some((index, _)) = payload.reward_addr {
+            if index != 2 {
+                warn!(
+                    "Miner node: delegate stacks op reward address must be output 2, not {}",
+                    index
+                );
+                return None;
+            }
+        }
+        let output_value = if payload.reward_addr.is_some() {
+            2 * DUST_UTXO_LIMIT
+        } else {
+            DUST_UTXO_LIMIT
+        };
+
+        let (mut tx, mut utxos) = if let Some(utxo) = utxo_to_use {
+            (
+                Transaction {
+                    input: vec![],
+                    output: vec![],
+                    version: 1,
+                    lock_time: 0,
+                },
+                UTXOSet {
+                    bhh: BurnchainHeaderHash::zero(),
+                    utxos: vec![utxo],
+                },
+            )
+        } else {
+            self.prepare_tx(
+                &public_key,
+                output_value + max_tx_size * self.fee_rates.fee_rate(),
+                None,
+                None,
+                0,
+            )?
+        };
+
+        // Serialize the payload
+        let op_bytes = {
+            let mut bytes = self.config.burnchain.magic_bytes.as_bytes().to_vec();
+            payload.consensus_serialize(&mut bytes).ok()?;
+            bytes
+        };
+
+        let consensus_output = TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::All::OP_RETURN)
+                .push_slice(&op_bytes)
+                .into_script(),
+        };
+
+        tx.output = vec![consensus_output];
+        tx.output
+            .push(payload.delegate_to.to_bitcoin_tx_out(DUST_UTXO_LIMIT));
+        if let Some((_, ref reward_addr)) = payload.reward_addr {
+            tx.output
+                .push(reward_addr.to_bitcoin_tx_out(DUST_UTXO_LIMIT));
+        }
+
+        self.finalize_tx(
+            &mut tx,
+            output_value,
+            0,
+            max_tx_size,
+            self.fee_rates.fee_rate(),
+            &mut utxos,
+            signer,
+        )?;
+
+        increment_btc_ops_sent_counter();
+
+        info!(
+            "Miner node: submitting stacks delegate op - {}",
+            public_key.to_hex()
+        );
+
+        Some(tx)
+    }
+
     #[cfg(not(test))]
     fn build_pre_stacks_tx(
         &mut self,
@@ -910,7 +1235,7 @@ impl BitcoinRegtestController {
         let public_key = signer.get_public_key();
         let max_tx_size = 280;
 
-        let output_amt = DUST_UTXO_LIMIT + max_tx_size * self.config.burnchain.satoshis_per_byte;
+        let output_amt = DUST_UTXO_LIMIT + max_tx_size * self.fee_rates.fee_rate();
         let (mut tx, mut utxos) = self.prepare_tx(&public_key, output_amt, None, None, 0)?;
 
         // Serialize the payload
@@ -936,7 +1261,7 @@ impl BitcoinRegtestController {
             output_amt,
             0,
             max_tx_size,
-            self.config.burnchain.satoshis_per_byte,
+            self.fee_rates.fee_rate(),
             &mut utxos,
             signer,
         )?;
@@ -961,8 +1286,12 @@ impl BitcoinRegtestController {
         previous_txids: &Vec<Txid>,
     ) -> Option<Transaction> {
         let mut estimated_fees = match previous_fees {
-            Some(fees) => fees.fees_from_previous_tx(&payload, &self.config),
-            None => LeaderBlockCommitFees::estimated_fees_from_payload(&payload, &self.config),
+            Some(fees) => fees.fees_from_previous_tx(&payload, &self.config, &self.fee_rates),
+            None => LeaderBlockCommitFees::estimated_fees_from_payload(
+                &payload,
+                &self.config,
+                &self.fee_rates,
+            ),
         };
 
         let public_key = signer.get_public_key();
@@ -1104,7 +1433,9 @@ impl BitcoinRegtestController {
 
         // Stop as soon as the fee_rate is ${self.config.burnchain.max_rbf} percent higher, stop RBF
         if ongoing_op.fees.fee_rate
-            > (self.config.burnchain.satoshis_per_byte * self.config.burnchain.max_rbf / 100)
+            > self
+                .fee_rates
+                .max_replacement_fee_rate(self.config.burnchain.max_rbf)
         {
             warn!(
                 "RBF'd block commits reached {}% satoshi per byte fee rate, not resubmitting",
@@ -1122,11 +1453,19 @@ impl BitcoinRegtestController {
         //    b) If we have some other UTXOs, drop the ongoing operation, and track the new one.
         //  ii) If UTXOs initially used are sufficient for paying for a fee bump, then RBF
 
-        // Let's start by early returning 1)
+        // Let's start by early returning 1), unless bitcoind's fee estimate has risen past the
+        // ongoing operation's fee rate and its UTXOs can pay for a bump
         if payload == ongoing_op.payload {
-            info!("Abort attempt to re-submit identical LeaderBlockCommit");
-            self.ongoing_block_commit = Some(ongoing_op);
-            return None;
+            if !ongoing_op.should_bump_fee(&self.config, &self.fee_rates) {
+                info!("Abort attempt to re-submit identical LeaderBlockCommit");
+                self.ongoing_block_commit = Some(ongoing_op);
+                return None;
+            }
+            info!(
+                "Attempt to replace by fee an underpaying leader block commit";
+                "fee_rate" => ongoing_op.fees.fee_rate,
+                "estimated_fee_rate" => self.fee_rates.fee_rate(),
+            );
         }
 
         // Let's proceed and early return 2) i)
@@ -1206,22 +1545,15 @@ impl BitcoinRegtestController {
         utxos_set: &mut UTXOSet,
         signer: &mut BurnchainOpSigner,
     ) -> Option<()> {
-        // spend UTXOs in order by confirmations.  Spend the least-confirmed UTXO first, and in the
-        // event of a tie, spend the smallest-value UTXO first.
-        utxos_set.utxos.sort_by(|u1, u2| {
-            if u1.confirmations != u2.confirmations {
-                u1.confirmations.cmp(&u2.confirmations)
-            } else {
-                // for block-commits, the smaller value is likely the UTXO-chained value, so
-                // continue to prioritize it as the first spend in order to avoid breaking the
-                // miner commit chain.
-                u1.amount.cmp(&u2.amount)
-            }
-        });
-
+        // spend UTXOs in the order they were selected in.  `UTXOManager::select_coins` puts the
+        // last block-commit's change output first, which is what links a miner's block-commits
+        // together, and an RBF keeps the input order of the transaction it replaces.
+        let public_key = signer.get_public_key();
         let tx_size = {
-            // We will be calling 2 times serialize_tx, the first time with an estimated size,
+            // We will be calling 2 times fund_tx, the first time with an estimated size,
             // Second time with the actual size, computed thanks to the 1st attempt.
+            // The first attempt is never signed -- each scriptSig gets a placeholder of the
+            // largest size a signature can be, so the signer only ever sees the real transaction.
             let estimated_rbf = if spent_in_rbf == 0 {
                 0
             } else {
@@ -1229,12 +1561,18 @@ impl BitcoinRegtestController {
             };
             let mut tx_cloned = tx.clone();
             let mut utxos_cloned = utxos_set.clone();
-            self.serialize_tx(
+            self.fund_tx(
                 &mut tx_cloned,
                 spent_in_outputs + min_tx_size * fee_rate + estimated_rbf,
                 &mut utxos_cloned,
-                signer,
+                &public_key,
             );
+            for input in tx_cloned.input.iter_mut() {
+                input.script_sig = Builder::new()
+                    .push_slice(&[0u8; MAX_SIGNATURE_LEN])
+                    .push_slice(&public_key.to_bytes())
+                    .into_script();
+            }
             let serialized_tx = SerializedTx::new(tx_cloned);
             cmp::max(min_tx_size, serialized_tx.bytes.len() as u64)
         };
@@ -1244,24 +1582,72 @@ impl BitcoinRegtestController {
         } else {
             spent_in_rbf + tx_size // we're spending 1 sat / byte in RBF
         };
-        self.serialize_tx(
+        if !self.fund_tx(
             tx,
             spent_in_outputs + tx_size * fee_rate + rbf_fee,
             utxos_set,
-            signer,
-        );
+            &public_key,
+        ) {
+            signer.dispose();
+            return None;
+        }
+
+        let previous_txs = match self.get_previous_transactions(&utxos_set.utxos) {
+            Some(txs) => txs,
+            None => {
+                signer.dispose();
+                return None;
+            }
+        };
+        let unsigned_tx = tx.clone();
+        let res = sign_transaction(self.tx_signer.as_mut(), unsigned_tx, previous_txs, signer);
         signer.dispose();
-        Some(())
+        match res {
+            Ok(signed_tx) => {
+                *tx = signed_tx;
+                Some(())
+            }
+            Err(e) => {
+                warn!("Failed to sign burnchain transaction: {}", &e);
+                None
+            }
+        }
     }
 
-    fn serialize_tx(
+    /// Fetch the transaction each of `utxos` comes from, so the signer can see what it's spending
+    fn get_previous_transactions(&self, utxos: &[UTXO]) -> Option<Vec<Transaction>> {
+        let mut fetched: HashMap<Sha256dHash, Transaction> = HashMap::new();
+        let mut previous_txs = Vec::with_capacity(utxos.len());
+        for utxo in utxos.iter() {
+            if !fetched.contains_key(&utxo.txid) {
+                match BitcoinRPCRequest::get_raw_transaction(&self.config, &utxo.txid) {
+                    Ok(tx) => {
+                        fetched.insert(utxo.txid, tx);
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to fetch transaction {} spent by the miner: {:?}",
+                            utxo.txid.be_hex_string(),
+                            &e
+                        );
+                        return None;
+                    }
+                }
+            }
+            previous_txs.push(fetched[&utxo.txid].clone());
+        }
+        Some(previous_txs)
+    }
+
+    /// Add inputs spending enough of `utxos_set` to cover `total_to_spend`, and a change output
+    /// back to `public_key`.  The inputs are left unsigned.
+    fn fund_tx(
         &mut self,
         tx: &mut Transaction,
         total_to_spend: u64,
         utxos_set: &mut UTXOSet,
-        signer: &mut BurnchainOpSigner,
+        public_key: &Secp256k1PublicKey,
     ) -> bool {
-        let public_key = signer.get_public_key();
         let mut total_consumed = 0;
 
         // select UTXOs until we have enough to cover the cost
@@ -1292,14 +1678,14 @@ impl BitcoinRegtestController {
             value, total_consumed, total_to_spend
         );
         if value >= DUST_UTXO_LIMIT {
-            let change_output = BitcoinAddress::to_p2pkh_tx_out(&change_address_hash, value);
+            let change_output = LegacyBitcoinAddress::to_p2pkh_tx_out(&change_address_hash, value);
             tx.output.push(change_output);
         } else {
             // Instead of leaving that change to the BTC miner, we could / should bump the sortition fee
             debug!("Not enough change to clear dust limit. Not adding change address.");
         }
 
-        for (i, utxo) in utxos_set.utxos.iter().enumerate() {
+        for utxo in utxos_set.utxos.iter() {
             let input = TxIn {
                 previous_output: OutPoint {
                     txid: utxo.txid,
@@ -1310,26 +1696,6 @@ impl BitcoinRegtestController {
                 witness: vec![],
             };
             tx.input.push(input);
-
-            let script_pub_key = utxo.script_pub_key.clone();
-            let sig_hash_all = 0x01;
-            let sig_hash = tx.signature_hash(i, &script_pub_key, sig_hash_all);
-
-            let sig1_der = {
-                let message = signer
-                    .sign_message(sig_hash.as_bytes())
-                    .expect("Unable to sign message");
-                message
-                    .to_secp256k1_recoverable()
-                    .expect("Unable to get recoverable signature")
-                    .to_standard()
-                    .serialize_der()
-            };
-
-            tx.input[i].script_sig = Builder::new()
-                .push_slice(&[&*sig1_der, &[sig_hash_all as u8][..]].concat())
-                .push_slice(&public_key.to_bytes())
-                .into_script();
         }
         true
     }
@@ -1416,7 +1782,7 @@ impl BitcoinRegtestController {
         let pkh = Hash160::from_data(&public_key).to_bytes().to_vec();
         let (_, network_id) = self.config.burnchain.get_bitcoin_network();
         let address =
-            BitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
+            LegacyBitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
                 .expect("Public key incorrect");
 
         let result =
@@ -1584,6 +1950,12 @@ impl BurnchainController for BitcoinRegtestController {
         op_signer: &mut BurnchainOpSigner,
         attempt: u64,
     ) -> bool {
+        let num_commit_outs = match operation {
+            BlockstackOperationType::LeaderBlockCommit(ref payload) => {
+                Some(payload.commit_outs.len())
+            }
+            _ => None,
+        };
         let transaction = match operation {
             BlockstackOperationType::LeaderBlockCommit(payload) => {
                 self.build_leader_block_commit_tx(payload, op_signer, attempt)
@@ -1601,14 +1973,34 @@ impl BurnchainController for BitcoinRegtestController {
                 self.build_transfer_stacks_tx(payload, op_signer, None)
             }
             BlockstackOperationType::StackStx(_payload) => unimplemented!(),
+            BlockstackOperationType::DelegateStx(payload) => {
+                self.build_delegate_stacks_tx(payload, op_signer, None)
+            }
         };
 
         let transaction = match transaction {
-            Some(tx) => SerializedTx::new(tx),
+            Some(tx) => tx,
             _ => return false,
         };
 
-        self.send_transaction(transaction)
+        if !self.send_transaction(SerializedTx::new(transaction.clone())) {
+            return false;
+        }
+        let mut utxo_manager = self
+            .utxo_manager
+            .lock()
+            .expect("FATAL: UTXO manager mutex poisoned");
+        utxo_manager.lock_inputs(&transaction);
+        if let Some(num_commit_outs) = num_commit_outs {
+            // a block-commit's outputs are the OP_RETURN, the commit outputs, and then change
+            let change_vout = if transaction.output.len() > num_commit_outs + 1 {
+                Some((transaction.output.len() - 1) as u32)
+            } else {
+                None
+            };
+            utxo_manager.set_commit_change(&transaction, change_vout);
+        }
+        true
     }
 
     #[cfg(test)]
@@ -1617,9 +2009,12 @@ impl BurnchainController for BitcoinRegtestController {
             let pk = hex_bytes(&local_mining_pubkey).expect("Invalid byte sequence");
             let pkh = Hash160::from_data(&pk).to_bytes().to_vec();
             let (_, network_id) = self.config.burnchain.get_bitcoin_network();
-            let address =
-                BitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
-                    .expect("Public key incorrect");
+            let address = LegacyBitcoinAddress::from_bytes(
+                network_id,
+                BitcoinAddressType::PublicKeyHash,
+                &pkh,
+            )
+            .expect("Public key incorrect");
 
             let _result = BitcoinRPCRequest::import_public_key(
                 &self.config,
@@ -1647,8 +2042,8 @@ impl BurnchainController for BitcoinRegtestController {
 
 #[derive(Debug, Clone)]
 pub struct UTXOSet {
-    bhh: BurnchainHeaderHash,
-    utxos: Vec<UTXO>,
+    pub bhh: BurnchainHeaderHash,
+    pub utxos: Vec<UTXO>,
 }
 
 impl UTXOSet {
@@ -1771,6 +2166,31 @@ impl ParsedUTXO {
     }
 }
 
+/// Fee rates from the bitcoind that the controller is connected to
+pub struct BitcoindFeeRateSource {
+    config: Config,
+}
+
+impl BitcoindFeeRateSource {
+    pub fn new(config: Config) -> BitcoindFeeRateSource {
+        BitcoindFeeRateSource { config }
+    }
+}
+
+impl FeeRateSource for BitcoindFeeRateSource {
+    fn estimate_smart_fee(&self, target_blocks: u64) -> Result<Option<f64>, String> {
+        BitcoinRPCRequest::estimate_smart_fee(&self.config, target_blocks)
+            .map(|fee_rate| fee_rate.map(btc_per_kvb_to_sats_per_vb))
+            .map_err(|e| format!("{:?}", &e))
+    }
+
+    fn mempool_min_fee(&self) -> Result<Option<f64>, String> {
+        BitcoinRPCRequest::get_mempool_min_fee(&self.config)
+            .map(|fee_rate| fee_rate.map(btc_per_kvb_to_sats_per_vb))
+            .map_err(|e| format!("{:?}", &e))
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct BitcoinRPCRequest {
     /// The name of the RPC call
@@ -1974,7 +2394,7 @@ impl BitcoinRPCRequest {
             .to_vec();
         let (_, network_id) = config.burnchain.get_bitcoin_network();
         let address =
-            BitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
+            LegacyBitcoinAddress::from_bytes(network_id, BitcoinAddressType::PublicKeyHash, &pkh)
                 .expect("Public key incorrect");
 
         let payload = BitcoinRPCRequest {
@@ -1988,6 +2408,88 @@ impl BitcoinRPCRequest {
         Ok(())
     }
 
+    /// Calls `estimatesmartfee` and returns the fee rate in BTC/kvB, or None if bitcoind doesn't
+    /// have enough data to estimate one
+    pub fn estimate_smart_fee(config: &Config, target_blocks: u64) -> RPCResult<Option<f64>> {
+        let payload = BitcoinRPCRequest {
+            method: "estimatesmartfee".to_string(),
+            params: vec![target_blocks.into()],
+            id: "stacks".to_string(),
+            jsonrpc: "2.0".to_string(),
+        };
+
+        let res = BitcoinRPCRequest::send(config, payload)?;
+        if let Some(errors) = res.get("result").and_then(|result| result.get("errors")) {
+            debug!("bitcoind could not estimate a fee rate: {}", errors);
+        }
+        Ok(res
+            .get("result")
+            .and_then(|result| result.get("feerate"))
+            .and_then(|feerate| feerate.as_f64()))
+    }
+
+    /// Calls `getmempoolinfo` and returns the mempool's minimum fee rate in BTC/kvB
+    pub fn get_mempool_min_fee(config: &Config) -> RPCResult<Option<f64>> {
+        let payload = BitcoinRPCRequest {
+            method: "getmempoolinfo".to_string(),
+            params: vec![],
+            id: "stacks".to_string(),
+            jsonrpc: "2.0".to_string(),
+        };
+
+        let res = BitcoinRPCRequest::send(config, payload)?;
+        Ok(res
+            .get("result")
+            .and_then(|result| result.get("mempoolminfee"))
+            .and_then(|fee| fee.as_f64()))
+    }
+
+    /// Fetch a whole transaction by its txid.  This uses `getrawtransaction`, which only finds
+    /// confirmed transactions if bitcoind runs with `-txindex`, and falls back to the wallet's
+    /// `gettransaction`, which finds the transactions of the miner's (watch-only) address.
+    pub fn get_raw_transaction(config: &Config, txid: &Sha256dHash) -> RPCResult<Transaction> {
+        let txid_hex = txid.be_hex_string();
+        let payload = BitcoinRPCRequest {
+            method: "getrawtransaction".to_string(),
+            params: vec![txid_hex.clone().into(), false.into()],
+            id: "stacks".to_string(),
+            jsonrpc: "2.0".to_string(),
+        };
+        let tx_hex = match BitcoinRPCRequest::send(config, payload)
+            .ok()
+            .and_then(|res| res.get("result")?.as_str().map(|s| s.to_string()))
+        {
+            Some(tx_hex) => tx_hex,
+            None => {
+                let payload = BitcoinRPCRequest {
+                    method: "gettransaction".to_string(),
+                    params: vec![txid_hex.clone().into(), true.into()],
+                    id: "stacks".to_string(),
+                    jsonrpc: "2.0".to_string(),
+                };
+                let res = BitcoinRPCRequest::send(config, payload)?;
+                res.get("result")
+                    .and_then(|result| result.get("hex"))
+                    .and_then(|tx_hex| tx_hex.as_str())
+                    .ok_or_else(|| {
+                        RPCError::Bitcoind(format!("Transaction {} not found", &txid_hex))
+                    })?
+                    .to_string()
+            }
+        };
+        let tx_bytes = hex_bytes(&tx_hex)
+            .map_err(|e| RPCError::Parsing(format!("Invalid transaction hex: {:?}", &e)))?;
+        let tx: Transaction = deserialize(&tx_bytes)
+            .map_err(|e| RPCError::Parsing(format!("Invalid transaction: {:?}", &e)))?;
+        if tx.txid() != *txid {
+            return Err(RPCError::Bitcoind(format!(
+                "Got the wrong transaction for {}",
+                &txid_hex
+            )));
+        }
+        Ok(tx)
+    }
+
     /// Calls `listwallets` method through RPC call and returns wallet names as a vector of Strings
     pub fn list_wallets(config: &Config) -> RPCResult<Vec<String>> {
         let payload = BitcoinRPCRequest {
@@ -2052,11 +2554,26 @@ impl BitcoinRPCRequest {
         request.set_body(body);
 
         let mut response = async_std::task::block_on(async move {
-            let stream = match TcpStream::connect(config.burnchain.get_rpc_socket_addr()).await {
+            let stream_res = match config.burnchain.socks5_proxy {
+                Some(ref proxy) => {
+                    let target = Socks5Target::from_host_port(
+                        &config.burnchain.peer_host,
+                        config.burnchain.rpc_port,
+                    );
+                    let timeout = Duration::from_secs(config.burnchain.timeout as u64);
+                    socks5_connect(proxy, &target, timeout)
+                        .map(TcpStream::from)
+                        .map_err(|e| format!("{:?}", &e))
+                }
+                None => TcpStream::connect(config.burnchain.get_rpc_socket_addr())
+                    .await
+                    .map_err(|e| format!("{:?}", &e)),
+            };
+            let stream = match stream_res {
                 Ok(stream) => stream,
                 Err(err) => {
                     return Err(RPCError::Network(format!(
-                        "Bitcoin RPC: connection failed - {:?}",
+                        "Bitcoin RPC: connection failed - {}",
                         err
                     )))
                 }
@@ -2106,3 +2623,94 @@ impl BitcoinRPCRequest {
         Ok(payload)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use stacks::burnchains::{BurnchainSigner, Txid};
+    use stacks::chainstate::stacks::address::PoxAddress;
+    use stacks::types::chainstate::{BlockHeaderHash, VRFSeed};
+    use stacks_common::address::AddressHashMode;
+
+    use super::super::fee_estimator::test::{settings, MockFeeRateSource};
+    use super::*;
+
+    fn estimator(fee_rate: f64) -> FeeRateEstimator {
+        FeeRateEstimator::new(
+            settings(true),
+            Box::new(MockFeeRateSource::new(Some(fee_rate), None)),
+        )
+    }
+
+    /// A block-commit sent at 10 sats/vbyte, funded by one UTXO of `amount` sats
+    fn ongoing_commit(config: &Config, amount: u64) -> OngoingBlockCommit {
+        let payload = LeaderBlockCommitOp {
+            sunset_burn: 0,
+            block_header_hash: BlockHeaderHash([1; 32]),
+            new_seed: VRFSeed([1; 32]),
+            parent_block_ptr: 100,
+            parent_vtxindex: 1,
+            key_block_ptr: 50,
+            key_vtxindex: 1,
+            memo: vec![],
+            commit_outs: vec![
+                PoxAddress::standard_burn_address(false),
+                PoxAddress::standard_burn_address(false),
+            ],
+            burn_fee: 20_000,
+            input: (Txid([0; 32]), 0),
+            apparent_sender: BurnchainSigner {
+                hash_mode: AddressHashMode::SerializeP2PKH,
+                num_sigs: 1,
+                public_keys: vec![],
+            },
+            txid: Txid([2; 32]),
+            vtxindex: 0,
+            block_height: 0,
+            burn_parent_modulus: 0,
+            burn_header_hash: BurnchainHeaderHash([0; 32]),
+        };
+        let mut fees =
+            LeaderBlockCommitFees::estimated_fees_from_payload(&payload, config, &estimator(10.0));
+        fees.register_replacement(config.burnchain.block_commit_tx_estimated_size);
+        OngoingBlockCommit {
+            payload,
+            utxos: UTXOSet {
+                bhh: BurnchainHeaderHash([0; 32]),
+                utxos: vec![UTXO {
+                    txid: Sha256dHash([3; 32]),
+                    vout: 0,
+                    script_pub_key: Script::new(),
+                    amount,
+                    confirmations: 1,
+                }],
+            },
+            fees,
+            txids: vec![Txid([2; 32])],
+        }
+    }
+
+    #[test]
+    fn test_should_bump_fee() {
+        let config = Config::default();
+        let rising = estimator(40.0);
+        let template = ongoing_commit(&config, 0);
+        let original = template.fees.estimated_amount_required();
+        let bumped = template
+            .fees
+            .fees_from_previous_tx(&template.payload, &config, &rising)
+            .estimated_amount_required();
+        assert!(bumped > original);
+
+        // the estimate hasn't risen past the commit's fee rate
+        let ongoing = ongoing_commit(&config, bumped);
+        assert!(!ongoing.should_bump_fee(&config, &estimator(10.0)));
+
+        // it has, and the UTXOs pay for the replacement at the bumped fee rate
+        assert!(ongoing.should_bump_fee(&config, &rising));
+
+        // the UTXOs would have covered the commit again at its old fee rate, but not the bump
+        let ongoing = ongoing_commit(&config, bumped - 1);
+        assert!(ongoing.sum_utxos() >= original);
+        assert!(!ongoing.should_bump_fee(&config, &rising));
+    }
+}
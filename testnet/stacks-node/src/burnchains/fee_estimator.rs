@@ -0,0 +1,292 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fee rates for the miner's burnchain transactions.
+//!
+//! By default the controller pays `burnchain.satoshis_per_byte` for every transaction.  With
+//! `burnchain.fee_rate_estimation` enabled, it asks bitcoind instead: the fee rate is
+//! `estimatesmartfee` for `burnchain.fee_rate_target_blocks`, raised to the mempool's
+//! minimum fee from `getmempoolinfo`, and clamped between `burnchain.fee_rate_min` and
+//! `burnchain.fee_rate_max`.  If bitcoind has no estimate or can't be reached, the static rate is
+//! used.  The estimate also drives RBF: a replacement commit pays at least the current estimate,
+//! and an unchanged commit whose fee rate has fallen below it is bumped.
+
+use std::cell::Cell;
+use std::cmp;
+
+use stacks::util::get_epoch_time_ms;
+
+/// How long a fee estimate is reused before bitcoind is asked again
+const FEE_ESTIMATE_TTL_MS: u128 = 30_000;
+
+/// How the controller picks fee rates
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeRateSettings {
+    /// Whether to ask bitcoind at all
+    pub enabled: bool,
+    /// Confirmation target, in blocks, to pass to `estimatesmartfee`
+    pub target_blocks: u64,
+    /// Lowest fee rate, in sats/vbyte, an estimate may yield
+    pub min_fee_rate: u64,
+    /// Highest fee rate, in sats/vbyte, an estimate or a replacement may yield
+    pub max_fee_rate: u64,
+    /// Fee rate, in sats/vbyte, to use without an estimate (`burnchain.satoshis_per_byte`)
+    pub default_fee_rate: u64,
+}
+
+/// The bitcoind RPCs behind fee estimation.  Rates are in sats/vbyte.
+pub trait FeeRateSource: Send {
+    /// `estimatesmartfee` for this confirmation target, or None if bitcoind has no estimate
+    fn estimate_smart_fee(&self, target_blocks: u64) -> Result<Option<f64>, String>;
+    /// `mempoolminfee` from `getmempoolinfo`
+    fn mempool_min_fee(&self) -> Result<Option<f64>, String>;
+}
+
+/// Convert a bitcoind fee rate in BTC/kvB to sats/vbyte
+pub fn btc_per_kvb_to_sats_per_vb(rate: f64) -> f64 {
+    rate * 100_000.0
+}
+
+pub struct FeeRateEstimator {
+    settings: FeeRateSettings,
+    source: Box<dyn FeeRateSource>,
+    /// Last estimate, and when it was fetched (epoch time in millis)
+    cached: Cell<Option<(u64, u128)>>,
+}
+
+impl FeeRateEstimator {
+    pub fn new(settings: FeeRateSettings, source: Box<dyn FeeRateSource>) -> FeeRateEstimator {
+        FeeRateEstimator {
+            settings,
+            source,
+            cached: Cell::new(None),
+        }
+    }
+
+    pub fn settings(&self) -> &FeeRateSettings {
+        &self.settings
+    }
+
+    /// Fee rate, in sats/vbyte, for a new transaction
+    pub fn fee_rate(&self) -> u64 {
+        if !self.settings.enabled {
+            return self.settings.default_fee_rate;
+        }
+        if let Some((fee_rate, fetched_at)) = self.cached.get() {
+            if get_epoch_time_ms() < fetched_at + FEE_ESTIMATE_TTL_MS {
+                return fee_rate;
+            }
+        }
+        let fee_rate = self.fetch_fee_rate();
+        self.cached.set(Some((fee_rate, get_epoch_time_ms())));
+        fee_rate
+    }
+
+    fn fetch_fee_rate(&self) -> u64 {
+        let estimate = match self.source.estimate_smart_fee(self.settings.target_blocks) {
+            Ok(Some(estimate)) => estimate,
+            Ok(None) => {
+                debug!(
+                    "No fee estimate from bitcoind for {} blocks; using {} sats/vbyte",
+                    self.settings.target_blocks, self.settings.default_fee_rate
+                );
+                self.settings.default_fee_rate as f64
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to get fee estimate from bitcoind; using {} sats/vbyte: {}",
+                    self.settings.default_fee_rate, &e
+                );
+                self.settings.default_fee_rate as f64
+            }
+        };
+        let mempool_min_fee = match self.source.mempool_min_fee() {
+            Ok(fee) => fee.unwrap_or(0.0),
+            Err(e) => {
+                warn!("Failed to get mempool info from bitcoind: {}", &e);
+                0.0
+            }
+        };
+        let fee_rate = estimate.max(mempool_min_fee).ceil() as u64;
+        let clamped = fee_rate.clamp(self.settings.min_fee_rate, self.settings.max_fee_rate);
+        debug!(
+            "Burnchain fee rate is {} sats/vbyte (estimate {}, mempool minimum {})",
+            clamped, estimate, mempool_min_fee
+        );
+        clamped
+    }
+
+    /// Fee rate for a transaction replacing one that paid `previous_fee_rate`: a bump of at least
+    /// `increment`, or up to the current fee rate if that is higher
+    pub fn replacement_fee_rate(&self, previous_fee_rate: u64, increment: u64) -> u64 {
+        cmp::max(previous_fee_rate + increment, self.fee_rate())
+    }
+
+    /// Highest fee rate a chain of replacements may reach: `max_rbf` percent of the current fee
+    /// rate, and no more than `fee_rate_max` when estimating
+    pub fn max_replacement_fee_rate(&self, max_rbf: u64) -> u64 {
+        let max_fee_rate = self.fee_rate() * max_rbf / 100;
+        if self.settings.enabled {
+            cmp::min(max_fee_rate, self.settings.max_fee_rate)
+        } else {
+            max_fee_rate
+        }
+    }
+
+    /// Is an unconfirmed transaction paying `fee_rate` below the current estimate, so that it
+    /// should be bumped even if nothing else about it changed?
+    pub fn is_underpaying(&self, fee_rate: u64) -> bool {
+        self.settings.enabled && fee_rate < self.fee_rate()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// What the mock bitcoind reports
+    #[derive(Clone, Debug, Default)]
+    pub struct MockFeeRates {
+        pub smart_fee: Option<f64>,
+        pub mempool_min_fee: Option<f64>,
+        /// If true, every call fails
+        pub offline: bool,
+        /// Number of `estimatesmartfee` calls made
+        pub calls: u64,
+        /// Confirmation target of the last `estimatesmartfee` call
+        pub last_target: Option<u64>,
+    }
+
+    /// Stands in for bitcoind in fee estimation tests
+    #[derive(Clone, Default)]
+    pub struct MockFeeRateSource {
+        pub rates: Arc<Mutex<MockFeeRates>>,
+    }
+
+    impl MockFeeRateSource {
+        pub fn new(smart_fee: Option<f64>, mempool_min_fee: Option<f64>) -> MockFeeRateSource {
+            let source = MockFeeRateSource::default();
+            {
+                let mut rates = source.rates.lock().unwrap();
+                rates.smart_fee = smart_fee;
+                rates.mempool_min_fee = mempool_min_fee;
+            }
+            source
+        }
+    }
+
+    impl FeeRateSource for MockFeeRateSource {
+        fn estimate_smart_fee(&self, target_blocks: u64) -> Result<Option<f64>, String> {
+            let mut rates = self.rates.lock().unwrap();
+            rates.calls += 1;
+            rates.last_target = Some(target_blocks);
+            if rates.offline {
+                return Err("connection refused".to_string());
+            }
+            Ok(rates.smart_fee)
+        }
+
+        fn mempool_min_fee(&self) -> Result<Option<f64>, String> {
+            let rates = self.rates.lock().unwrap();
+            if rates.offline {
+                return Err("connection refused".to_string());
+            }
+            Ok(rates.mempool_min_fee)
+        }
+    }
+
+    pub fn settings(enabled: bool) -> FeeRateSettings {
+        FeeRateSettings {
+            enabled,
+            target_blocks: 3,
+            min_fee_rate: 2,
+            max_fee_rate: 100,
+            default_fee_rate: 50,
+        }
+    }
+
+    #[test]
+    fn test_fee_rate_disabled() {
+        let source = MockFeeRateSource::new(Some(10.0), None);
+        let estimator = FeeRateEstimator::new(settings(false), Box::new(source.clone()));
+        assert_eq!(estimator.fee_rate(), 50);
+        assert_eq!(source.rates.lock().unwrap().calls, 0);
+
+        assert_eq!(estimator.replacement_fee_rate(50, 5), 55);
+        assert_eq!(estimator.max_replacement_fee_rate(150), 75);
+        assert!(!estimator.is_underpaying(1));
+    }
+
+    #[test]
+    fn test_fee_rate_estimated() {
+        let source = MockFeeRateSource::new(Some(10.4), Some(1.0));
+        let estimator = FeeRateEstimator::new(settings(true), Box::new(source.clone()));
+        assert_eq!(estimator.fee_rate(), 11);
+        assert_eq!(source.rates.lock().unwrap().last_target, Some(3));
+
+        // cached
+        source.rates.lock().unwrap().smart_fee = Some(20.0);
+        assert_eq!(estimator.fee_rate(), 11);
+        assert_eq!(source.rates.lock().unwrap().calls, 1);
+    }
+
+    #[test]
+    fn test_fee_rate_bounds_and_fallbacks() {
+        let fee_rate = |smart_fee, mempool_min_fee, offline| {
+            let source = MockFeeRateSource::new(smart_fee, mempool_min_fee);
+            source.rates.lock().unwrap().offline = offline;
+            FeeRateEstimator::new(settings(true), Box::new(source)).fee_rate()
+        };
+
+        // clamped to the bounds
+        assert_eq!(fee_rate(Some(1.0), None, false), 2);
+        assert_eq!(fee_rate(Some(500.0), None, false), 100);
+
+        // raised to the mempool minimum
+        assert_eq!(fee_rate(Some(3.0), Some(7.5), false), 8);
+
+        // no estimate, or no bitcoind
+        assert_eq!(fee_rate(None, None, false), 50);
+        assert_eq!(fee_rate(None, Some(70.0), false), 70);
+        assert_eq!(fee_rate(Some(3.0), None, true), 50);
+    }
+
+    #[test]
+    fn test_replacement_fee_rate() {
+        let source = MockFeeRateSource::new(Some(40.0), None);
+        let estimator = FeeRateEstimator::new(settings(true), Box::new(source));
+
+        // the estimate rose past the usual bump
+        assert_eq!(estimator.replacement_fee_rate(20, 5), 40);
+        // the usual bump is still the minimum
+        assert_eq!(estimator.replacement_fee_rate(40, 5), 45);
+
+        assert!(estimator.is_underpaying(39));
+        assert!(!estimator.is_underpaying(40));
+
+        // 150% of 40, but capped at fee_rate_max
+        assert_eq!(estimator.max_replacement_fee_rate(150), 60);
+        assert_eq!(estimator.max_replacement_fee_rate(300), 100);
+    }
+
+    #[test]
+    fn test_btc_per_kvb_to_sats_per_vb() {
+        assert_eq!(btc_per_kvb_to_sats_per_vb(0.00001), 1.0);
+        assert_eq!(btc_per_kvb_to_sats_per_vb(0.00025), 25.0);
+    }
+}
@@ -7,8 +7,9 @@ use stacks::burnchains::{
 };
 use stacks::chainstate::burn::db::sortdb::{SortitionDB, SortitionHandleTx};
 use stacks::chainstate::burn::operations::{
-    leader_block_commit::BURN_BLOCK_MINED_AT_MODULUS, BlockstackOperationType, LeaderBlockCommitOp,
-    LeaderKeyRegisterOp, PreStxOp, StackStxOp, TransferStxOp, UserBurnSupportOp,
+    leader_block_commit::BURN_BLOCK_MINED_AT_MODULUS, BlockstackOperationType, DelegateStxOp,
+    LeaderBlockCommitOp, LeaderKeyRegisterOp, PreStxOp, StackStxOp, TransferStxOp,
+    UserBurnSupportOp,
 };
 use stacks::chainstate::burn::BlockSnapshot;
 use stacks::core::{StacksEpoch, StacksEpochId, PEER_VERSION_EPOCH_2_0, STACKS_EPOCH_MAX};
@@ -248,6 +249,15 @@ impl BurnchainController for MocknetController {
                         ..payload
                     })
                 }
+                BlockstackOperationType::DelegateStx(payload) => {
+                    BlockstackOperationType::DelegateStx(DelegateStxOp {
+                        txid,
+                        vtxindex,
+                        block_height: next_block_header.block_height,
+                        burn_header_hash: next_block_header.block_hash,
+                        ..payload
+                    })
+                }
             };
             ops.push(op);
             vtxindex += 1;
@@ -1,5 +1,9 @@
 pub mod bitcoin_regtest_controller;
+pub mod fee_estimator;
 pub mod mocknet_controller;
+pub mod psbt;
+pub mod signer;
+pub mod utxo_manager;
 
 pub use self::bitcoin_regtest_controller::BitcoinRegtestController;
 pub use self::mocknet_controller::MocknetController;
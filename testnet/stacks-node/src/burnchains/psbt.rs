@@ -0,0 +1,600 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A minimal BIP-174 partially-signed Bitcoin transaction (PSBT).
+//!
+//! This only knows the fields the miner needs in order to hand a transaction to a signer and get
+//! it back: the unsigned transaction, the transaction (or, for segwit inputs, the output) each
+//! input spends, partial signatures, the sighash type, and the final scriptSig.  All other key-value pairs are carried through untouched, so a
+//! signer can add its own records without us dropping them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+use stacks_common::deps_common::bitcoin::blockdata::transaction::{Transaction, TxOut};
+use stacks_common::deps_common::bitcoin::network::serialize::{deserialize, serialize};
+
+/// "psbt" followed by the 0xff separator
+pub const PSBT_MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xff];
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Did not start with the PSBT magic bytes
+    BadMagic,
+    /// Ran out of bytes
+    Truncated,
+    /// The same key appeared twice in one map
+    DuplicateKey(Vec<u8>),
+    /// No unsigned transaction in the global map
+    MissingUnsignedTx,
+    /// The unsigned transaction has scriptSigs or witnesses
+    UnsignedTxHasSignatures,
+    /// A known key had a malformed key or value
+    InvalidField(&'static str),
+    /// Trailing bytes after the last output map
+    TrailingBytes,
+    /// An input has no final scriptSig, so the transaction cannot be extracted
+    NotFinalized(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadMagic => write!(f, "Not a PSBT: bad magic bytes"),
+            Error::Truncated => write!(f, "PSBT is truncated"),
+            Error::DuplicateKey(key) => write!(f, "Duplicate PSBT key {:?}", key),
+            Error::MissingUnsignedTx => write!(f, "PSBT has no unsigned transaction"),
+            Error::UnsignedTxHasSignatures => {
+                write!(f, "PSBT unsigned transaction has scriptSigs or witnesses")
+            }
+            Error::InvalidField(field) => write!(f, "Invalid PSBT field: {}", field),
+            Error::TrailingBytes => write!(f, "Trailing bytes after PSBT"),
+            Error::NotFinalized(i) => write!(f, "PSBT input {} is not finalized", i),
+        }
+    }
+}
+
+/// Per-input PSBT records
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PsbtInput {
+    /// The whole transaction a legacy (non-segwit) input spends from, so the signer can check the
+    /// amount it is spending against the txid
+    pub non_witness_utxo: Option<Transaction>,
+    /// The output a segwit input spends
+    pub witness_utxo: Option<TxOut>,
+    /// Signatures, keyed by the public key that made them.  Each value is a DER signature
+    /// followed by the sighash byte.
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub sighash_type: Option<u32>,
+    pub final_script_sig: Option<Script>,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// Per-output PSBT records.  We do not use any of them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PsbtOutput {
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Psbt {
+    pub unsigned_tx: Transaction,
+    pub inputs: Vec<PsbtInput>,
+    pub outputs: Vec<PsbtOutput>,
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+fn write_compact_size(buf: &mut Vec<u8>, n: u64) {
+    if n < 0xfd {
+        buf.push(n as u8);
+    } else if n <= 0xffff {
+        buf.push(0xfd);
+        buf.extend_from_slice(&(n as u16).to_le_bytes());
+    } else if n <= 0xffff_ffff {
+        buf.push(0xfe);
+        buf.extend_from_slice(&(n as u32).to_le_bytes());
+    } else {
+        buf.push(0xff);
+        buf.extend_from_slice(&n.to_le_bytes());
+    }
+}
+
+fn write_pair(buf: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    write_compact_size(buf, key.len() as u64);
+    buf.extend_from_slice(key);
+    write_compact_size(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+struct PsbtReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PsbtReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() - self.pos < len {
+            return Err(Error::Truncated);
+        }
+        let ret = &self.bytes[self.pos..(self.pos + len)];
+        self.pos += len;
+        Ok(ret)
+    }
+
+    fn read_compact_size(&mut self) -> Result<u64, Error> {
+        let first = self.take(1)?[0];
+        let n = match first {
+            0xfd => {
+                let mut b = [0u8; 2];
+                b.copy_from_slice(self.take(2)?);
+                u16::from_le_bytes(b) as u64
+            }
+            0xfe => {
+                let mut b = [0u8; 4];
+                b.copy_from_slice(self.take(4)?);
+                u32::from_le_bytes(b) as u64
+            }
+            0xff => {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(self.take(8)?);
+                u64::from_le_bytes(b)
+            }
+            n => n as u64,
+        };
+        Ok(n)
+    }
+
+    fn read_len_prefixed(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.read_compact_size()?;
+        if len > (self.bytes.len() - self.pos) as u64 {
+            return Err(Error::Truncated);
+        }
+        self.take(len as usize)
+    }
+
+    /// Read one key-value map, up to and including its 0x00 separator
+    fn read_map(&mut self) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, Error> {
+        let mut map = BTreeMap::new();
+        loop {
+            let key = self.read_len_prefixed()?;
+            if key.is_empty() {
+                return Ok(map);
+            }
+            let value = self.read_len_prefixed()?;
+            if map.insert(key.to_vec(), value.to_vec()).is_some() {
+                return Err(Error::DuplicateKey(key.to_vec()));
+            }
+        }
+    }
+}
+
+impl PsbtInput {
+    fn from_map(map: BTreeMap<Vec<u8>, Vec<u8>>) -> Result<PsbtInput, Error> {
+        let mut input = PsbtInput::default();
+        for (key, value) in map.into_iter() {
+            match key[0] {
+                PSBT_IN_NON_WITNESS_UTXO if key.len() == 1 => {
+                    let tx: Transaction =
+                        deserialize(&value).map_err(|_| Error::InvalidField("non-witness utxo"))?;
+                    input.non_witness_utxo = Some(tx);
+                }
+                PSBT_IN_WITNESS_UTXO if key.len() == 1 => {
+                    let txout: TxOut =
+                        deserialize(&value).map_err(|_| Error::InvalidField("witness utxo"))?;
+                    input.witness_utxo = Some(txout);
+                }
+                PSBT_IN_PARTIAL_SIG => {
+                    let pubkey = &key[1..];
+                    if pubkey.len() != 33 && pubkey.len() != 65 {
+                        return Err(Error::InvalidField("partial signature public key"));
+                    }
+                    input.partial_sigs.insert(pubkey.to_vec(), value);
+                }
+                PSBT_IN_SIGHASH_TYPE if key.len() == 1 => {
+                    if value.len() != 4 {
+                        return Err(Error::InvalidField("sighash type"));
+                    }
+                    let mut b = [0u8; 4];
+                    b.copy_from_slice(&value);
+                    input.sighash_type = Some(u32::from_le_bytes(b));
+                }
+                PSBT_IN_FINAL_SCRIPTSIG if key.len() == 1 => {
+                    input.final_script_sig = Some(Script::from(value));
+                }
+                _ => {
+                    input.unknown.insert(key, value);
+                }
+            }
+        }
+        Ok(input)
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        // keep the map in key order, as BIP-174 readers will re-serialize it that way
+        let mut map: BTreeMap<Vec<u8>, Vec<u8>> = self.unknown.clone();
+        if let Some(ref tx) = self.non_witness_utxo {
+            map.insert(
+                vec![PSBT_IN_NON_WITNESS_UTXO],
+                serialize(tx).expect("BUG: failed to serialize Transaction"),
+            );
+        }
+        if let Some(ref txout) = self.witness_utxo {
+            map.insert(
+                vec![PSBT_IN_WITNESS_UTXO],
+                serialize(txout).expect("BUG: failed to serialize TxOut"),
+            );
+        }
+        for (pubkey, sig) in self.partial_sigs.iter() {
+            let mut key = vec![PSBT_IN_PARTIAL_SIG];
+            key.extend_from_slice(pubkey);
+            map.insert(key, sig.clone());
+        }
+        if let Some(sighash_type) = self.sighash_type {
+            map.insert(
+                vec![PSBT_IN_SIGHASH_TYPE],
+                sighash_type.to_le_bytes().to_vec(),
+            );
+        }
+        if let Some(ref script_sig) = self.final_script_sig {
+            map.insert(vec![PSBT_IN_FINAL_SCRIPTSIG], script_sig.to_bytes());
+        }
+        for (key, value) in map.iter() {
+            write_pair(buf, key, value);
+        }
+        buf.push(0x00);
+    }
+}
+
+impl Psbt {
+    /// Wrap an unsigned transaction whose inputs all spend legacy outputs.  `previous_txs[i]` is
+    /// the transaction whose output input `i` spends.
+    pub fn from_unsigned_tx(
+        unsigned_tx: Transaction,
+        previous_txs: Vec<Transaction>,
+        sighash_type: u32,
+    ) -> Result<Psbt, Error> {
+        if unsigned_tx
+            .input
+            .iter()
+            .any(|inp| !inp.script_sig.is_empty() || !inp.witness.is_empty())
+        {
+            return Err(Error::UnsignedTxHasSignatures);
+        }
+        if previous_txs.len() != unsigned_tx.input.len() {
+            return Err(Error::InvalidField("previous transactions"));
+        }
+        for (input, previous_tx) in unsigned_tx.input.iter().zip(previous_txs.iter()) {
+            if previous_tx.txid() != input.previous_output.txid
+                || previous_tx.output.len() <= input.previous_output.vout as usize
+            {
+                return Err(Error::InvalidField("previous transactions"));
+            }
+        }
+        let inputs = previous_txs
+            .into_iter()
+            .map(|tx| PsbtInput {
+                non_witness_utxo: Some(tx),
+                sighash_type: Some(sighash_type),
+                ..PsbtInput::default()
+            })
+            .collect();
+        let outputs = vec![PsbtOutput::default(); unsigned_tx.output.len()];
+        Ok(Psbt {
+            unsigned_tx,
+            inputs,
+            outputs,
+            unknown: BTreeMap::new(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = PSBT_MAGIC.to_vec();
+
+        let mut global = self.unknown.clone();
+        global.insert(
+            vec![PSBT_GLOBAL_UNSIGNED_TX],
+            serialize(&self.unsigned_tx).expect("BUG: failed to serialize transaction"),
+        );
+        for (key, value) in global.iter() {
+            write_pair(&mut buf, key, value);
+        }
+        buf.push(0x00);
+
+        for input in self.inputs.iter() {
+            input.write(&mut buf);
+        }
+        for output in self.outputs.iter() {
+            for (key, value) in output.unknown.iter() {
+                write_pair(&mut buf, key, value);
+            }
+            buf.push(0x00);
+        }
+        buf
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Psbt, Error> {
+        if bytes.len() < PSBT_MAGIC.len() || bytes[0..PSBT_MAGIC.len()] != PSBT_MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let mut reader = PsbtReader {
+            bytes,
+            pos: PSBT_MAGIC.len(),
+        };
+
+        let mut global = reader.read_map()?;
+        let unsigned_tx: Transaction = match global.remove(&vec![PSBT_GLOBAL_UNSIGNED_TX]) {
+            Some(tx_bytes) => {
+                deserialize(&tx_bytes).map_err(|_| Error::InvalidField("unsigned tx"))?
+            }
+            None => {
+                return Err(Error::MissingUnsignedTx);
+            }
+        };
+        if unsigned_tx
+            .input
+            .iter()
+            .any(|inp| !inp.script_sig.is_empty() || !inp.witness.is_empty())
+        {
+            return Err(Error::UnsignedTxHasSignatures);
+        }
+
+        let mut inputs = Vec::with_capacity(unsigned_tx.input.len());
+        for _ in 0..unsigned_tx.input.len() {
+            inputs.push(PsbtInput::from_map(reader.read_map()?)?);
+        }
+        let mut outputs = Vec::with_capacity(unsigned_tx.output.len());
+        for _ in 0..unsigned_tx.output.len() {
+            outputs.push(PsbtOutput {
+                unknown: reader.read_map()?,
+            });
+        }
+        if reader.pos != bytes.len() {
+            return Err(Error::TrailingBytes);
+        }
+
+        Ok(Psbt {
+            unsigned_tx,
+            inputs,
+            outputs,
+            unknown: global,
+        })
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.serialize())
+    }
+
+    pub fn from_base64(s: &str) -> Result<Psbt, Error> {
+        let bytes = base64::decode(s).map_err(|_| Error::InvalidField("base64"))?;
+        Psbt::deserialize(&bytes)
+    }
+
+    /// The output spent by input `i`, from its witness UTXO or its non-witness UTXO, whichever it
+    /// has.  A non-witness UTXO that isn't the transaction the input spends from is ignored.
+    pub fn spent_output(&self, i: usize) -> Option<&TxOut> {
+        let input = self.inputs.get(i)?;
+        if let Some(ref txout) = input.witness_utxo {
+            return Some(txout);
+        }
+        let prevout = &self.unsigned_tx.input.get(i)?.previous_output;
+        match input.non_witness_utxo {
+            Some(ref tx) if tx.txid() == prevout.txid => tx.output.get(prevout.vout as usize),
+            _ => None,
+        }
+    }
+
+    /// Build the final transaction from each input's final scriptSig
+    pub fn extract_tx(&self) -> Result<Transaction, Error> {
+        let mut tx = self.unsigned_tx.clone();
+        for (i, input) in self.inputs.iter().enumerate() {
+            match input.final_script_sig {
+                Some(ref script_sig) => {
+                    tx.input[i].script_sig = script_sig.clone();
+                }
+                None => {
+                    return Err(Error::NotFinalized(i));
+                }
+            }
+        }
+        Ok(tx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stacks::util::hash::hex_bytes;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{OutPoint, TxIn};
+    use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
+    fn make_previous_tx(outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Sha256dHash([0x11; 32]),
+                    vout: 0,
+                },
+                script_sig: Script::from(vec![0x01, 0x02]),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: outputs,
+        }
+    }
+
+    fn make_previous_txs() -> Vec<Transaction> {
+        let script_pubkey =
+            Script::from(hex_bytes("76a9140be3e286a15ea85882761618e366586b5574100d88ac").unwrap());
+        vec![
+            make_previous_tx(vec![
+                TxOut {
+                    value: 5000,
+                    script_pubkey: Script::new(),
+                },
+                TxOut {
+                    value: 10000,
+                    script_pubkey: script_pubkey.clone(),
+                },
+            ]),
+            make_previous_tx(vec![TxOut {
+                value: 20000,
+                script_pubkey,
+            }]),
+        ]
+    }
+
+    fn make_unsigned_tx(previous_txs: &[Transaction]) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    previous_output: OutPoint {
+                        txid: previous_txs[0].txid(),
+                        vout: 1,
+                    },
+                    script_sig: Script::new(),
+                    sequence: 0xFFFFFFFD,
+                    witness: vec![],
+                },
+                TxIn {
+                    previous_output: OutPoint {
+                        txid: previous_txs[1].txid(),
+                        vout: 0,
+                    },
+                    script_sig: Script::new(),
+                    sequence: 0xFFFFFFFD,
+                    witness: vec![],
+                },
+            ],
+            output: vec![TxOut {
+                value: 12345,
+                script_pubkey: Script::from(
+                    hex_bytes("76a914000000000000000000000000000000000000000088ac").unwrap(),
+                ),
+            }],
+        }
+    }
+
+    fn make_psbt() -> Psbt {
+        let previous_txs = make_previous_txs();
+        let tx = make_unsigned_tx(&previous_txs);
+        Psbt::from_unsigned_tx(tx, previous_txs, 0x01).unwrap()
+    }
+
+    #[test]
+    fn test_psbt_spent_outputs() {
+        let previous_txs = make_previous_txs();
+        let mut psbt = make_psbt();
+        assert_eq!(
+            psbt.inputs[0].non_witness_utxo.as_ref(),
+            Some(&previous_txs[0])
+        );
+        assert!(psbt.inputs[0].witness_utxo.is_none());
+        assert_eq!(psbt.spent_output(0), Some(&previous_txs[0].output[1]));
+        assert_eq!(psbt.spent_output(1), Some(&previous_txs[1].output[0]));
+        assert_eq!(psbt.spent_output(2), None);
+
+        // a previous transaction that isn't the one the input spends from is no good
+        psbt.inputs[0].non_witness_utxo = Some(previous_txs[1].clone());
+        assert_eq!(psbt.spent_output(0), None);
+
+        let tx = make_unsigned_tx(&previous_txs);
+        assert_eq!(
+            Psbt::from_unsigned_tx(
+                tx.clone(),
+                vec![previous_txs[1].clone(), previous_txs[0].clone()],
+                0x01
+            ),
+            Err(Error::InvalidField("previous transactions"))
+        );
+        assert_eq!(
+            Psbt::from_unsigned_tx(tx, vec![previous_txs[0].clone()], 0x01),
+            Err(Error::InvalidField("previous transactions"))
+        );
+    }
+
+    #[test]
+    fn test_psbt_roundtrip() {
+        let mut psbt = make_psbt();
+        let bytes = psbt.serialize();
+        assert_eq!(&bytes[0..5], &PSBT_MAGIC);
+        assert_eq!(Psbt::deserialize(&bytes).unwrap(), psbt);
+
+        // signatures, final scriptSigs, and records we don't know about all survive
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(vec![0x02; 33], vec![0x30, 0x01, 0x01]);
+        psbt.inputs[0].final_script_sig = Some(Script::from(vec![0x01, 0x02]));
+        psbt.inputs[1]
+            .unknown
+            .insert(vec![0xfc, 0x01], vec![0xaa; 300]);
+        psbt.outputs[0].unknown.insert(vec![0x02], vec![0xbb]);
+        psbt.unknown
+            .insert(vec![0xfb], vec![0x00, 0x00, 0x00, 0x00]);
+
+        let b64 = psbt.to_base64();
+        assert_eq!(Psbt::from_base64(&b64).unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_psbt_invalid() {
+        let psbt = make_psbt();
+        let bytes = psbt.serialize();
+
+        assert_eq!(Psbt::deserialize(&bytes[1..]), Err(Error::BadMagic));
+        assert_eq!(
+            Psbt::deserialize(&bytes[0..bytes.len() - 1]),
+            Err(Error::Truncated)
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0x00);
+        assert_eq!(Psbt::deserialize(&trailing), Err(Error::TrailingBytes));
+
+        // no global unsigned tx
+        let mut no_tx = PSBT_MAGIC.to_vec();
+        no_tx.push(0x00);
+        assert_eq!(Psbt::deserialize(&no_tx), Err(Error::MissingUnsignedTx));
+
+        // an "unsigned" transaction that has a scriptSig
+        let mut signed_tx = psbt.unsigned_tx.clone();
+        signed_tx.input[0].script_sig = Script::from(vec![0x01, 0x02]);
+        assert_eq!(
+            Psbt::from_unsigned_tx(signed_tx, make_previous_txs(), 0x01),
+            Err(Error::UnsignedTxHasSignatures)
+        );
+
+        // can't extract until every input is finalized
+        let mut partly_final = psbt.clone();
+        partly_final.inputs[0].final_script_sig = Some(Script::from(vec![0x01, 0x02]));
+        assert_eq!(partly_final.extract_tx(), Err(Error::NotFinalized(1)));
+        partly_final.inputs[1].final_script_sig = Some(Script::from(vec![0x03]));
+        let tx = partly_final.extract_tx().unwrap();
+        assert_eq!(tx.input[0].script_sig, Script::from(vec![0x01, 0x02]));
+        assert_eq!(tx.input[1].script_sig, Script::from(vec![0x03]));
+    }
+}
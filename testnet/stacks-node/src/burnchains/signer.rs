@@ -0,0 +1,864 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Signers for the miner's burnchain transactions.
+//!
+//! The controller builds each transaction unsigned, wraps it in a PSBT along with the transactions
+//! whose outputs it spends, and hands it to a `BurnchainTxSigner`.  The signer adds a partial signature for the
+//! op signer's public key to each input, and the controller checks the signatures, builds the
+//! scriptSigs, and broadcasts the transaction.
+//!
+//! External signers speak a one-shot JSON protocol.  The request is
+//!
+//! ```json
+//! { "psbt": "<base64 PSBT>", "public_key": "<hex public key>" }
+//! ```
+//!
+//! and the response is either `{ "psbt": "<base64 PSBT>" }` with the signatures added, or
+//! `{ "error": "<reason>" }`.  A command signer gets the request on stdin and writes the response
+//! to stdout; an HTTP signer gets the request as the body of a POST.  Either one has
+//! `burnchain.timeout` seconds to answer, after which a command signer is killed.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use async_h1::client;
+use async_std::io::ReadExt;
+use async_std::net::TcpStream;
+use http_types::{Method, Request, Url};
+
+use stacks::burnchains::bitcoin::address::LegacyBitcoinAddress;
+use stacks::burnchains::PublicKey;
+use stacks::util::hash::{hex_bytes, Hash160};
+use stacks::util::secp256k1::{secp256k1_verify_der, Secp256k1PublicKey};
+use stacks_common::deps_common::bitcoin::blockdata::script::Builder;
+use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
+
+use super::psbt::{self, Psbt};
+use crate::config::BurnchainSignerConfig;
+use crate::operations::BurnchainOpSigner;
+
+pub const SIGHASH_ALL: u32 = 0x01;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The PSBT could not be encoded or decoded
+    Psbt(psbt::Error),
+    /// The signer refused to sign, or could not
+    Signer(String),
+    /// Could not reach the signer
+    Transport(String),
+    /// The signer returned a different transaction than the one we asked it to sign
+    TransactionChanged,
+    /// No signature for our public key on this input
+    MissingSignature(usize),
+    /// We don't have the output this input spends
+    MissingPreviousOutput(usize),
+    /// The signature on this input does not verify
+    BadSignature(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Psbt(e) => write!(f, "PSBT error: {}", e),
+            Error::Signer(msg) => write!(f, "Signer error: {}", msg),
+            Error::Transport(msg) => write!(f, "Signer transport error: {}", msg),
+            Error::TransactionChanged => write!(f, "Signer changed the unsigned transaction"),
+            Error::MissingSignature(i) => write!(f, "No signature for input {}", i),
+            Error::MissingPreviousOutput(i) => write!(f, "No spent output for input {}", i),
+            Error::BadSignature(i) => write!(f, "Invalid signature for input {}", i),
+        }
+    }
+}
+
+impl From<psbt::Error> for Error {
+    fn from(e: psbt::Error) -> Error {
+        Error::Psbt(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignRequest {
+    pub psbt: String,
+    pub public_key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignResponse {
+    #[serde(default)]
+    pub psbt: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl SignResponse {
+    fn into_psbt(self) -> Result<Psbt, Error> {
+        match (self.psbt, self.error) {
+            (_, Some(error)) => Err(Error::Signer(error)),
+            (Some(psbt), None) => Ok(Psbt::from_base64(&psbt)?),
+            (None, None) => Err(Error::Signer("empty response".to_string())),
+        }
+    }
+}
+
+pub trait BurnchainTxSigner: Send {
+    /// Add a signature by `op_signer`'s key to each input of `psbt` that it can sign
+    fn sign_psbt(&mut self, psbt: Psbt, op_signer: &mut BurnchainOpSigner) -> Result<Psbt, Error>;
+}
+
+/// Instantiate the signer named by the node config
+pub fn make_tx_signer(config: &BurnchainSignerConfig, timeout: u64) -> Box<dyn BurnchainTxSigner> {
+    match config {
+        BurnchainSignerConfig::Local => Box::new(LocalTxSigner),
+        BurnchainSignerConfig::Command { command, .. } => {
+            Box::new(CommandTxSigner::new(command.clone(), timeout))
+        }
+        BurnchainSignerConfig::Http { url, .. } => {
+            Box::new(HttpTxSigner::new(url.clone(), timeout))
+        }
+    }
+}
+
+/// The p2pkh scriptPubKey for a public key, which is what all of the miner's UTXOs pay to
+fn p2pkh_script_pubkey(public_key: &Secp256k1PublicKey) -> Vec<u8> {
+    let hash = Hash160::from_data(&public_key.to_bytes());
+    LegacyBitcoinAddress::to_p2pkh_tx_out(&hash, 0)
+        .script_pubkey
+        .to_bytes()
+}
+
+/// Sign every input of `psbt` that spends a p2pkh output of `op_signer`'s key.
+/// Inputs that spend anything else are left alone.
+pub fn sign_psbt_with_key(psbt: &mut Psbt, op_signer: &mut BurnchainOpSigner) -> Result<(), Error> {
+    let public_key = op_signer.get_public_key();
+    let our_script = p2pkh_script_pubkey(&public_key);
+    for i in 0..psbt.inputs.len() {
+        let script_pubkey = match psbt.spent_output(i) {
+            Some(txout) if txout.script_pubkey.as_bytes() == &our_script[..] => {
+                txout.script_pubkey.clone()
+            }
+            _ => {
+                continue;
+            }
+        };
+        let sighash_type = psbt.inputs[i].sighash_type.unwrap_or(SIGHASH_ALL);
+        let sighash = psbt
+            .unsigned_tx
+            .signature_hash(i, &script_pubkey, sighash_type);
+        let sig = op_signer
+            .sign_message(sighash.as_bytes())
+            .ok_or_else(|| Error::Signer("op signer cannot sign".to_string()))?;
+        let mut sig_der = sig
+            .to_secp256k1_recoverable()
+            .ok_or_else(|| Error::Signer("unrecoverable signature".to_string()))?
+            .to_standard()
+            .serialize_der()
+            .to_vec();
+        sig_der.push(sighash_type as u8);
+        psbt.inputs[i]
+            .partial_sigs
+            .insert(public_key.to_bytes(), sig_der);
+    }
+    Ok(())
+}
+
+/// Check that each input of a signed PSBT has a valid SIGHASH_ALL signature from `public_key`, and
+/// build the signed transaction.  `previous_txs[i]` is the transaction whose output input `i`
+/// spends.  The signatures are checked against these, and not against the spent outputs or
+/// sighash types in the PSBT, since the signer could have changed those.
+pub fn finalize_p2pkh(
+    psbt: &mut Psbt,
+    previous_txs: &[Transaction],
+    public_key: &Secp256k1PublicKey,
+) -> Result<Transaction, Error> {
+    let public_key_bytes = public_key.to_bytes();
+    for i in 0..psbt.inputs.len() {
+        let sig = psbt.inputs[i]
+            .partial_sigs
+            .get(&public_key_bytes)
+            .ok_or(Error::MissingSignature(i))?
+            .clone();
+        let (sighash_byte, sig_der) = sig.split_last().ok_or(Error::BadSignature(i))?;
+        if *sighash_byte as u32 != SIGHASH_ALL {
+            return Err(Error::BadSignature(i));
+        }
+        let prevout = &psbt.unsigned_tx.input[i].previous_output;
+        let script_pubkey = previous_txs
+            .get(i)
+            .filter(|tx| tx.txid() == prevout.txid)
+            .and_then(|tx| tx.output.get(prevout.vout as usize))
+            .ok_or(Error::MissingPreviousOutput(i))?
+            .script_pubkey
+            .clone();
+        let sighash = psbt
+            .unsigned_tx
+            .signature_hash(i, &script_pubkey, SIGHASH_ALL);
+        secp256k1_verify_der(sighash.as_bytes(), sig_der, &public_key_bytes)
+            .map_err(|_| Error::BadSignature(i))?;
+
+        psbt.inputs[i].final_script_sig = Some(
+            Builder::new()
+                .push_slice(&sig)
+                .push_slice(&public_key_bytes)
+                .into_script(),
+        );
+    }
+    Ok(psbt.extract_tx()?)
+}
+
+/// Have `signer` sign `unsigned_tx`, whose inputs spend outputs of `previous_txs` that pay to
+/// `op_signer`'s key, and return the signed transaction.
+pub fn sign_transaction(
+    signer: &mut dyn BurnchainTxSigner,
+    unsigned_tx: Transaction,
+    previous_txs: Vec<Transaction>,
+    op_signer: &mut BurnchainOpSigner,
+) -> Result<Transaction, Error> {
+    let psbt = Psbt::from_unsigned_tx(unsigned_tx, previous_txs.clone(), SIGHASH_ALL)?;
+    let unsigned_tx = psbt.unsigned_tx.clone();
+    let mut signed = signer.sign_psbt(psbt, op_signer)?;
+    if signed.unsigned_tx != unsigned_tx {
+        return Err(Error::TransactionChanged);
+    }
+    finalize_p2pkh(&mut signed, &previous_txs, &op_signer.get_public_key())
+}
+
+/// Signs with the op signer's own key
+pub struct LocalTxSigner;
+
+impl BurnchainTxSigner for LocalTxSigner {
+    fn sign_psbt(
+        &mut self,
+        mut psbt: Psbt,
+        op_signer: &mut BurnchainOpSigner,
+    ) -> Result<Psbt, Error> {
+        sign_psbt_with_key(&mut psbt, op_signer)?;
+        Ok(psbt)
+    }
+}
+
+fn make_request(psbt: &Psbt, op_signer: &mut BurnchainOpSigner) -> SignRequest {
+    SignRequest {
+        psbt: psbt.to_base64(),
+        public_key: op_signer.get_public_key().to_hex(),
+    }
+}
+
+/// Runs a program for each transaction
+pub struct CommandTxSigner {
+    command: Vec<String>,
+    timeout: Duration,
+}
+
+impl CommandTxSigner {
+    pub fn new(command: Vec<String>, timeout_secs: u64) -> CommandTxSigner {
+        CommandTxSigner {
+            command,
+            timeout: Duration::from_secs(timeout_secs),
+        }
+    }
+}
+
+/// Read all of a child process's output on another thread, so a chatty child can't block on a
+/// full pipe while we wait for it to exit
+fn read_in_background<R: Read + Send + 'static>(
+    pipe: Option<R>,
+) -> thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buf)?;
+        }
+        Ok(buf)
+    })
+}
+
+impl BurnchainTxSigner for CommandTxSigner {
+    fn sign_psbt(&mut self, psbt: Psbt, op_signer: &mut BurnchainOpSigner) -> Result<Psbt, Error> {
+        let request = serde_json::to_vec(&make_request(&psbt, op_signer))
+            .map_err(|e| Error::Transport(format!("{:?}", &e)))?;
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| Error::Transport("empty signer command".to_string()))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Transport(format!("failed to run {}: {:?}", program, &e)))?;
+
+        let mut stdin = child.stdin.take().expect("BUG: no stdin for child process");
+        let writer = thread::spawn(move || stdin.write_all(&request));
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    break status;
+                }
+                Ok(None) => {
+                    if Instant::now() >= deadline {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(Error::Transport(format!(
+                            "{} timed out after {}s",
+                            program,
+                            self.timeout.as_secs()
+                        )));
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => {
+                    let _ = child.kill();
+                    return Err(Error::Transport(format!(
+                        "failed to wait for {}: {:?}",
+                        program, &e
+                    )));
+                }
+            }
+        };
+
+        let stderr = stderr
+            .join()
+            .expect("BUG: signer output reader panicked")
+            .unwrap_or_default();
+        if !status.success() {
+            return Err(Error::Signer(format!(
+                "{} exited with {}: {}",
+                program,
+                status,
+                String::from_utf8_lossy(&stderr)
+            )));
+        }
+        writer
+            .join()
+            .expect("BUG: signer request writer panicked")
+            .map_err(|e| Error::Transport(format!("failed to write request: {:?}", &e)))?;
+        let stdout = stdout
+            .join()
+            .expect("BUG: signer output reader panicked")
+            .map_err(|e| Error::Transport(format!("failed to read response: {:?}", &e)))?;
+
+        let response: SignResponse = serde_json::from_slice(&stdout)
+            .map_err(|e| Error::Transport(format!("failed to parse response: {:?}", &e)))?;
+        response.into_psbt()
+    }
+}
+
+/// POSTs each transaction to a signing service
+pub struct HttpTxSigner {
+    url: String,
+    timeout: Duration,
+}
+
+impl HttpTxSigner {
+    pub fn new(url: String, timeout_secs: u64) -> HttpTxSigner {
+        HttpTxSigner {
+            url,
+            timeout: Duration::from_secs(timeout_secs),
+        }
+    }
+}
+
+impl BurnchainTxSigner for HttpTxSigner {
+    fn sign_psbt(&mut self, psbt: Psbt, op_signer: &mut BurnchainOpSigner) -> Result<Psbt, Error> {
+        let body = serde_json::to_vec(&make_request(&psbt, op_signer))
+            .map_err(|e| Error::Transport(format!("{:?}", &e)))?;
+        let url = Url::parse(&self.url)
+            .map_err(|e| Error::Transport(format!("invalid signer URL: {:?}", &e)))?;
+        let addr = url
+            .socket_addrs(|| Some(80))
+            .map_err(|e| Error::Transport(format!("failed to resolve signer: {:?}", &e)))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Transport("failed to resolve signer".to_string()))?;
+
+        let mut request = Request::new(Method::Post, url);
+        request.append_header("Content-Type", "application/json");
+        request.set_body(body);
+
+        let timeout = self.timeout;
+        let (status, buffer) = async_std::task::block_on(async move {
+            async_std::future::timeout(timeout, async move {
+                let stream = TcpStream::connect(addr)
+                    .await
+                    .map_err(|e| Error::Transport(format!("connection failed: {:?}", &e)))?;
+                let mut response = client::connect(stream, request)
+                    .await
+                    .map_err(|e| Error::Transport(format!("request failed: {:?}", &e)))?;
+                let mut buffer = vec![];
+                response
+                    .take_body()
+                    .read_to_end(&mut buffer)
+                    .await
+                    .map_err(|e| Error::Transport(format!("failed to read response: {:?}", &e)))?;
+                Ok::<_, Error>((response.status(), buffer))
+            })
+            .await
+            .map_err(|_| Error::Transport("timed out".to_string()))?
+        })?;
+
+        if !status.is_success() {
+            return Err(Error::Signer(format!(
+                "signer returned {}: {}",
+                status,
+                String::from_utf8_lossy(&buffer)
+            )));
+        }
+        let response: SignResponse = serde_json::from_slice(&buffer)
+            .map_err(|e| Error::Transport(format!("failed to parse response: {:?}", &e)))?;
+        response.into_psbt()
+    }
+}
+
+/// Parse a signer's public key from the config file
+pub fn parse_signer_public_key(hex: &str) -> Result<Secp256k1PublicKey, String> {
+    let bytes = hex_bytes(hex).map_err(|_| format!("Invalid signer public key: {}", hex))?;
+    Secp256k1PublicKey::from_slice(&bytes).map_err(|e| format!("Invalid signer public key: {}", e))
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use stacks::util::secp256k1::Secp256k1PrivateKey;
+    use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{OutPoint, TxIn, TxOut};
+    use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
+    use super::*;
+
+    /// A stand-in for an external signer.  It holds its own key, and answers requests in the
+    /// external signer protocol.
+    pub struct MockTxSigner {
+        signer: BurnchainOpSigner,
+        pub requests: Vec<SignRequest>,
+    }
+
+    impl MockTxSigner {
+        pub fn new(secret_key: Secp256k1PrivateKey) -> MockTxSigner {
+            MockTxSigner {
+                signer: BurnchainOpSigner::new(secret_key, false),
+                requests: vec![],
+            }
+        }
+
+        pub fn handle_request(&mut self, request: SignRequest) -> SignResponse {
+            self.requests.push(request.clone());
+            if request.public_key != self.signer.get_public_key().to_hex() {
+                return SignResponse {
+                    psbt: None,
+                    error: Some("unknown key".to_string()),
+                };
+            }
+            let result = Psbt::from_base64(&request.psbt)
+                .map_err(Error::from)
+                .and_then(|mut psbt| {
+                    sign_psbt_with_key(&mut psbt, &mut self.signer)?;
+                    Ok(psbt)
+                });
+            match result {
+                Ok(psbt) => SignResponse {
+                    psbt: Some(psbt.to_base64()),
+                    error: None,
+                },
+                Err(e) => SignResponse {
+                    psbt: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        }
+    }
+
+    impl BurnchainTxSigner for MockTxSigner {
+        fn sign_psbt(
+            &mut self,
+            psbt: Psbt,
+            op_signer: &mut BurnchainOpSigner,
+        ) -> Result<Psbt, Error> {
+            let request = make_request(&psbt, op_signer);
+            self.handle_request(request).into_psbt()
+        }
+    }
+
+    /// Make a transaction whose `i`th input spends output `i` of the `i`th previous transaction,
+    /// which pays to `spent_scripts[i]`
+    fn make_unsigned_tx_spending(spent_scripts: &[Script]) -> (Transaction, Vec<Transaction>) {
+        let mut tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: spent_scripts[0].clone(),
+            }],
+        };
+        let mut previous_txs = vec![];
+        for (i, script_pubkey) in spent_scripts.iter().enumerate() {
+            let mut previous_tx = Transaction {
+                version: 1,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: OutPoint {
+                        txid: Sha256dHash([i as u8; 32]),
+                        vout: 0,
+                    },
+                    script_sig: Script::from(vec![0x01, 0x02]),
+                    sequence: 0xFFFFFFFF,
+                    witness: vec![],
+                }],
+                output: vec![TxOut::default(); i],
+            };
+            previous_tx.output.push(TxOut {
+                value: 10000,
+                script_pubkey: script_pubkey.clone(),
+            });
+            tx.input.push(TxIn {
+                previous_output: OutPoint {
+                    txid: previous_tx.txid(),
+                    vout: i as u32,
+                },
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFD,
+                witness: vec![],
+            });
+            previous_txs.push(previous_tx);
+        }
+        (tx, previous_txs)
+    }
+
+    fn make_unsigned_tx(
+        public_key: &Secp256k1PublicKey,
+        num_inputs: usize,
+    ) -> (Transaction, Vec<Transaction>) {
+        let script_pubkey = Script::from(p2pkh_script_pubkey(public_key));
+        make_unsigned_tx_spending(&vec![script_pubkey; num_inputs])
+    }
+
+    #[test]
+    fn test_local_signer() {
+        let sk = Secp256k1PrivateKey::new();
+        let mut op_signer = BurnchainOpSigner::new(sk, false);
+        let public_key = op_signer.get_public_key();
+        let (tx, spent) = make_unsigned_tx(&public_key, 2);
+
+        let signed = sign_transaction(
+            &mut LocalTxSigner,
+            tx.clone(),
+            spent.clone(),
+            &mut op_signer,
+        )
+        .unwrap();
+        assert_eq!(signed.output, tx.output);
+        for (i, input) in signed.input.iter().enumerate() {
+            assert_eq!(input.previous_output, tx.input[i].previous_output);
+            // <sig> <pubkey>
+            let script_bytes = input.script_sig.to_bytes();
+            let sig_len = script_bytes[0] as usize;
+            assert_eq!(script_bytes[sig_len], SIGHASH_ALL as u8);
+            assert_eq!(&script_bytes[(sig_len + 2)..], &public_key.to_bytes()[..]);
+        }
+
+        // a disposed one-off signer cannot sign again
+        let mut one_off = BurnchainOpSigner::new(sk, true);
+        let (tx, spent) = make_unsigned_tx(&public_key, 2);
+        match sign_transaction(&mut LocalTxSigner, tx, spent, &mut one_off) {
+            Err(Error::Signer(_)) => {}
+            x => panic!("Expected a signer error, got {:?}", &x),
+        }
+
+        // won't sign inputs that spend someone else's outputs
+        let other_sk = Secp256k1PrivateKey::new();
+        let other_pk = Secp256k1PublicKey::from_private(&other_sk);
+        let (tx, spent) = make_unsigned_tx_spending(&[
+            Script::from(p2pkh_script_pubkey(&public_key)),
+            Script::from(p2pkh_script_pubkey(&other_pk)),
+        ]);
+        match sign_transaction(&mut LocalTxSigner, tx, spent, &mut op_signer) {
+            Err(Error::MissingSignature(1)) => {}
+            x => panic!("Expected a missing signature, got {:?}", &x),
+        }
+    }
+
+    #[test]
+    fn test_remote_signer() {
+        // the node only knows the public key
+        let sk = Secp256k1PrivateKey::new();
+        let public_key = Secp256k1PublicKey::from_private(&sk);
+        let mut op_signer = BurnchainOpSigner::from_public_key(public_key, false);
+        assert!(op_signer.sign_message(&[0u8; 32]).is_none());
+
+        let mut mock = MockTxSigner::new(sk);
+        let (tx, spent) = make_unsigned_tx(&public_key, 3);
+        let signed =
+            sign_transaction(&mut mock, tx.clone(), spent.clone(), &mut op_signer).unwrap();
+        assert_eq!(mock.requests.len(), 1);
+        assert_eq!(mock.requests[0].public_key, public_key.to_hex());
+        assert_eq!(signed.input.len(), 3);
+
+        // signing locally gives scriptSigs with the same shape
+        let mut local_signer = BurnchainOpSigner::new(sk, false);
+        let local = sign_transaction(&mut LocalTxSigner, tx, spent, &mut local_signer).unwrap();
+        for (remote_in, local_in) in signed.input.iter().zip(local.input.iter()) {
+            let remote_bytes = remote_in.script_sig.to_bytes();
+            let local_bytes = local_in.script_sig.to_bytes();
+            assert_eq!(
+                &remote_bytes[(remote_bytes[0] as usize + 1)..],
+                &local_bytes[(local_bytes[0] as usize + 1)..]
+            );
+        }
+
+        // a signer with the wrong key is refused
+        let mut wrong = MockTxSigner::new(Secp256k1PrivateKey::new());
+        let (tx, spent) = make_unsigned_tx(&public_key, 1);
+        match sign_transaction(&mut wrong, tx, spent, &mut op_signer) {
+            Err(Error::Signer(msg)) => assert_eq!(msg, "unknown key"),
+            x => panic!("Expected a signer error, got {:?}", &x),
+        }
+    }
+
+    /// Ways a signer can tamper with what it was given
+    enum Tamper {
+        ChangeTx,
+        FlipSignatureBit,
+        /// sign input 0 with SIGHASH_NONE, which would let anyone redirect the outputs
+        SighashNone,
+        /// claim that input 0 spends a different output, and sign over that
+        SpentOutput(TxOut),
+    }
+
+    /// A signer that tampers with what it was given
+    struct BadTxSigner {
+        inner: MockTxSigner,
+        tamper: Tamper,
+    }
+
+    impl BurnchainTxSigner for BadTxSigner {
+        fn sign_psbt(
+            &mut self,
+            mut psbt: Psbt,
+            op_signer: &mut BurnchainOpSigner,
+        ) -> Result<Psbt, Error> {
+            match self.tamper {
+                Tamper::SighashNone => {
+                    psbt.inputs[0].sighash_type = Some(0x02);
+                }
+                Tamper::SpentOutput(ref txout) => {
+                    psbt.inputs[0].witness_utxo = Some(txout.clone());
+                }
+                _ => {}
+            }
+            let mut psbt = self.inner.sign_psbt(psbt, op_signer)?;
+            match self.tamper {
+                Tamper::ChangeTx => {
+                    psbt.unsigned_tx.output[0].value += 1;
+                }
+                Tamper::FlipSignatureBit => {
+                    let sig = psbt.inputs[0].partial_sigs.values_mut().next().unwrap();
+                    sig[10] ^= 0x01;
+                }
+                _ => {}
+            }
+            Ok(psbt)
+        }
+    }
+
+    #[test]
+    fn test_remote_signer_misbehaves() {
+        let sk = Secp256k1PrivateKey::new();
+        let public_key = Secp256k1PublicKey::from_private(&sk);
+        let mut op_signer = BurnchainOpSigner::from_public_key(public_key, false);
+
+        let mut changes_tx = BadTxSigner {
+            inner: MockTxSigner::new(sk),
+            tamper: Tamper::ChangeTx,
+        };
+        let (tx, spent) = make_unsigned_tx(&public_key, 1);
+        match sign_transaction(&mut changes_tx, tx, spent, &mut op_signer) {
+            Err(Error::TransactionChanged) => {}
+            x => panic!("Expected a changed transaction, got {:?}", &x),
+        }
+
+        let mut bad_sig = BadTxSigner {
+            inner: MockTxSigner::new(sk),
+            tamper: Tamper::FlipSignatureBit,
+        };
+        let (tx, spent) = make_unsigned_tx(&public_key, 1);
+        match sign_transaction(&mut bad_sig, tx, spent, &mut op_signer) {
+            Err(Error::BadSignature(0)) => {}
+            x => panic!("Expected a bad signature, got {:?}", &x),
+        }
+
+        // the signature is valid for the sighash type the signer claims, but isn't SIGHASH_ALL
+        let mut sighash_none = BadTxSigner {
+            inner: MockTxSigner::new(sk),
+            tamper: Tamper::SighashNone,
+        };
+        let (tx, spent) = make_unsigned_tx(&public_key, 2);
+        match sign_transaction(&mut sighash_none, tx, spent, &mut op_signer) {
+            Err(Error::BadSignature(0)) => {}
+            x => panic!("Expected a bad signature, got {:?}", &x),
+        }
+
+        // the signature is valid for the spent output the signer claims, but not for the one
+        // the input actually spends
+        let mut wrong_utxo = BadTxSigner {
+            inner: MockTxSigner::new(sk),
+            tamper: Tamper::SpentOutput(TxOut {
+                value: 10000,
+                script_pubkey: Script::from(p2pkh_script_pubkey(&public_key)),
+            }),
+        };
+        let other_pk = Secp256k1PublicKey::from_private(&Secp256k1PrivateKey::new());
+        let (tx, spent) =
+            make_unsigned_tx_spending(&[Script::from(p2pkh_script_pubkey(&other_pk))]);
+        match sign_transaction(&mut wrong_utxo, tx, spent, &mut op_signer) {
+            Err(Error::BadSignature(0)) => {}
+            x => panic!("Expected a bad signature, got {:?}", &x),
+        }
+    }
+
+    #[test]
+    fn test_http_signer() {
+        let sk = Secp256k1PrivateKey::new();
+        let public_key = Secp256k1PublicKey::from_private(&sk);
+        let mut op_signer = BurnchainOpSigner::from_public_key(public_key, false);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut mock = MockTxSigner::new(sk);
+            let (sock, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(sock.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(len) = line.strip_prefix("content-length:") {
+                    content_length = len.trim().parse::<usize>().unwrap();
+                }
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let request: SignRequest = serde_json::from_slice(&body).unwrap();
+            let response = serde_json::to_vec(&mock.handle_request(request)).unwrap();
+
+            let mut sock = sock;
+            write!(
+                sock,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                response.len()
+            )
+            .unwrap();
+            sock.write_all(&response).unwrap();
+            sock.flush().unwrap();
+        });
+
+        let mut http_signer = HttpTxSigner::new(format!("http://127.0.0.1:{}/sign", port), 30);
+        let (tx, spent) = make_unsigned_tx(&public_key, 2);
+        let signed = sign_transaction(&mut http_signer, tx, spent, &mut op_signer).unwrap();
+        assert_eq!(signed.input.len(), 2);
+        assert!(signed.input.iter().all(|inp| !inp.script_sig.is_empty()));
+        server.join().unwrap();
+
+        // nobody listening
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let mut http_signer = HttpTxSigner::new(format!("http://127.0.0.1:{}/sign", port), 30);
+        let (tx, spent) = make_unsigned_tx(&public_key, 1);
+        match sign_transaction(&mut http_signer, tx, spent, &mut op_signer) {
+            Err(Error::Transport(_)) => {}
+            x => panic!("Expected a transport error, got {:?}", &x),
+        }
+    }
+
+    #[test]
+    fn test_command_signer_failure() {
+        let public_key = Secp256k1PublicKey::from_private(&Secp256k1PrivateKey::new());
+        let mut op_signer = BurnchainOpSigner::from_public_key(public_key, false);
+
+        // the command refuses
+        let mut signer = CommandTxSigner::new(
+            vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "cat > /dev/null; echo '{\"error\": \"declined\"}'".to_string(),
+            ],
+            30,
+        );
+        let (tx, spent) = make_unsigned_tx(&public_key, 1);
+        match sign_transaction(&mut signer, tx, spent, &mut op_signer) {
+            Err(Error::Signer(msg)) => assert_eq!(msg, "declined"),
+            x => panic!("Expected a signer error, got {:?}", &x),
+        }
+
+        // the command fails
+        let mut signer = CommandTxSigner::new(
+            vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "cat > /dev/null; exit 3".to_string(),
+            ],
+            30,
+        );
+        let (tx, spent) = make_unsigned_tx(&public_key, 1);
+        match sign_transaction(&mut signer, tx, spent, &mut op_signer) {
+            Err(Error::Signer(_)) => {}
+            x => panic!("Expected a signer error, got {:?}", &x),
+        }
+
+        // the command echoes back the unsigned PSBT
+        let mut signer = CommandTxSigner::new(vec!["cat".to_string()], 30);
+        let (tx, spent) = make_unsigned_tx(&public_key, 1);
+        match sign_transaction(&mut signer, tx, spent, &mut op_signer) {
+            Err(Error::MissingSignature(0)) => {}
+            x => panic!("Expected a missing signature, got {:?}", &x),
+        }
+
+        // the command hangs, and is killed once it runs out of time
+        let mut signer = CommandTxSigner::new(
+            vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "cat > /dev/null; exec sleep 60".to_string(),
+            ],
+            1,
+        );
+        let (tx, spent) = make_unsigned_tx(&public_key, 1);
+        let start = Instant::now();
+        match sign_transaction(&mut signer, tx, spent, &mut op_signer) {
+            Err(Error::Transport(msg)) => assert!(msg.contains("timed out")),
+            x => panic!("Expected a timeout, got {:?}", &x),
+        }
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,755 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Local bookkeeping for the miner's UTXOs.
+//!
+//! The controller refreshes the manager from `listunspent` whenever it needs coins.  The manager
+//! remembers which UTXOs our own broadcast transactions spend, so they are not selected again
+//! before bitcoind's wallet notices, picks coins for each transaction, and decides when the
+//! miner's small UTXOs should be swept into one.
+//!
+//! The sortition DB links a miner's block-commits together by the UTXO each one spends first (see
+//! `LinkedCommitIdentifier`), so the manager also remembers the change output of the last
+//! block-commit we sent.  Coin selection always spends it first, and consolidation never sweeps
+//! it.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+use stacks::util::get_epoch_time_secs;
+use stacks_common::deps_common::bitcoin::blockdata::transaction::{OutPoint, Transaction};
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
+use super::bitcoin_regtest_controller::{UTXOSet, UTXO};
+
+/// Serialized size of an input spending a p2pkh output, with a maximum-size signature and an
+/// uncompressed public key (which is what keys derived from the node seed use)
+pub const P2PKH_INPUT_SIZE: u64 = 181;
+/// Serialized size of a p2pkh output
+pub const P2PKH_OUTPUT_SIZE: u64 = 34;
+/// Serialized size of a transaction's version, locktime, and input and output counts
+pub const TX_OVERHEAD_SIZE: u64 = 10;
+/// How long a UTXO spent by one of our transactions stays locked if bitcoind keeps reporting it
+/// as unspent (e.g. because the transaction was dropped from the mempool)
+const LOCK_TIMEOUT_SECS: u64 = 3600;
+/// Upper bound on the subsets the branch-and-bound search will visit
+const MAX_SELECTION_TRIES: usize = 100_000;
+
+/// When and how to sweep the miner's small UTXOs into one
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsolidationSettings {
+    /// Consolidate once the miner has more than this many spendable UTXOs.  0 disables
+    /// consolidation.
+    pub threshold: u64,
+    /// Minimum number of burnchain blocks between consolidation transactions
+    pub interval: u64,
+    /// Fee rate, in sats/vbyte, to pay for consolidation transactions
+    pub fee_rate: u64,
+    /// Most UTXOs to spend in one consolidation transaction
+    pub max_inputs: u64,
+}
+
+/// Summary of the miner's UTXOs, as last reported by bitcoind
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UTXOHealth {
+    /// Number of unspent outputs
+    pub count: u64,
+    /// Total value of the unspent outputs
+    pub total_sats: u64,
+    /// Number of outputs that cost more to spend than they are worth at the current fee rate
+    pub uneconomic: u64,
+    /// Number of outputs spent by a transaction of ours that bitcoind has not seen yet
+    pub locked: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct UTXOLock {
+    spent_by: Sha256dHash,
+    locked_at: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct UTXOManager {
+    /// Last UTXO set reported by bitcoind
+    utxos: Vec<UTXO>,
+    /// UTXOs spent by transactions we broadcast
+    locked: HashMap<OutPoint, UTXOLock>,
+    /// Consolidation transactions whose outputs are not yet confirmed
+    pending_consolidations: HashSet<Sha256dHash>,
+    /// Burnchain height of the last consolidation attempt
+    last_consolidation_height: Option<u64>,
+    /// Change output of the last block-commit we broadcast
+    commit_change: Option<OutPoint>,
+}
+
+fn outpoint(utxo: &UTXO) -> OutPoint {
+    OutPoint {
+        txid: utxo.txid,
+        vout: utxo.vout,
+    }
+}
+
+/// Value of a UTXO less what it costs to spend it at `fee_rate`
+fn effective_value(utxo: &UTXO, fee_rate: u64) -> i128 {
+    (utxo.amount as i128) - ((P2PKH_INPUT_SIZE * fee_rate) as i128)
+}
+
+/// Look for a subset of `candidates` (sorted by descending effective value) whose effective
+/// value lands in `[target, target + window]`, which is to say one that needs no change output.
+/// Returns the subset that overshoots `target` the least.
+fn select_without_change(
+    candidates: &[(i128, &UTXO)],
+    target: i128,
+    window: i128,
+) -> Option<Vec<UTXO>> {
+    // suffix sums, so we can prune branches that cannot reach the target
+    let mut remaining = vec![0i128; candidates.len() + 1];
+    for i in (0..candidates.len()).rev() {
+        remaining[i] = remaining[i + 1] + candidates[i].0;
+    }
+    if remaining[0] < target {
+        return None;
+    }
+
+    let mut best: Option<(i128, Vec<usize>)> = None;
+    let mut selected: Vec<usize> = vec![];
+    let mut tries = 0;
+
+    // depth-first search; at each depth, first include the candidate, then exclude it
+    fn search(
+        candidates: &[(i128, &UTXO)],
+        remaining: &[i128],
+        target: i128,
+        window: i128,
+        depth: usize,
+        sum: i128,
+        selected: &mut Vec<usize>,
+        best: &mut Option<(i128, Vec<usize>)>,
+        tries: &mut usize,
+    ) {
+        *tries += 1;
+        if *tries > MAX_SELECTION_TRIES {
+            return;
+        }
+        if sum > target + window {
+            return;
+        }
+        if sum >= target {
+            let waste = sum - target;
+            if best.as_ref().map(|(w, _)| waste < *w).unwrap_or(true) {
+                *best = Some((waste, selected.clone()));
+            }
+            return;
+        }
+        if depth >= candidates.len() || sum + remaining[depth] < target {
+            return;
+        }
+        selected.push(depth);
+        search(
+            candidates,
+            remaining,
+            target,
+            window,
+            depth + 1,
+            sum + candidates[depth].0,
+            selected,
+            best,
+            tries,
+        );
+        selected.pop();
+        search(
+            candidates,
+            remaining,
+            target,
+            window,
+            depth + 1,
+            sum,
+            selected,
+            best,
+            tries,
+        );
+    }
+
+    search(
+        candidates,
+        &remaining,
+        target,
+        window,
+        0,
+        0,
+        &mut selected,
+        &mut best,
+        &mut tries,
+    );
+    best.map(|(_, indexes)| {
+        indexes
+            .into_iter()
+            .map(|i| candidates[i].1.clone())
+            .collect()
+    })
+}
+
+impl UTXOManager {
+    pub fn new() -> UTXOManager {
+        UTXOManager::default()
+    }
+
+    /// Replace our view of the miner's UTXOs with what bitcoind just reported.  Locks on UTXOs
+    /// that are gone (because our transaction was seen) or that have timed out are released.
+    pub fn refresh(&mut self, utxos: Vec<UTXO>) {
+        let now = get_epoch_time_secs();
+        let unspent: HashSet<OutPoint> = utxos.iter().map(outpoint).collect();
+        self.locked.retain(|outpoint, lock| {
+            if !unspent.contains(outpoint) {
+                return false;
+            }
+            if lock.locked_at + LOCK_TIMEOUT_SECS <= now {
+                debug!(
+                    "Unlocking UTXO {}:{}, since bitcoind never saw transaction {} spend it",
+                    outpoint.txid.be_hex_string(),
+                    outpoint.vout,
+                    lock.spent_by.be_hex_string()
+                );
+                return false;
+            }
+            true
+        });
+        self.pending_consolidations.retain(|txid| {
+            utxos
+                .iter()
+                .any(|utxo| &utxo.txid == txid && utxo.confirmations == 0)
+        });
+        self.utxos = utxos;
+    }
+
+    /// Remember that `tx`, which we just broadcast, spends its inputs
+    pub fn lock_inputs(&mut self, tx: &Transaction) {
+        let txid = tx.txid();
+        let now = get_epoch_time_secs();
+        for input in tx.input.iter() {
+            self.locked.insert(
+                input.previous_output,
+                UTXOLock {
+                    spent_by: txid,
+                    locked_at: now,
+                },
+            );
+        }
+    }
+
+    pub fn is_locked(&self, utxo: &UTXO) -> bool {
+        self.locked.contains_key(&outpoint(utxo))
+    }
+
+    /// Remember the change output of `tx`, a block-commit we just broadcast, so that the next
+    /// block-commit spends it first.  `change_vout` is None if `tx` has no change output, in
+    /// which case the next block-commit cannot be linked to it.
+    pub fn set_commit_change(&mut self, tx: &Transaction, change_vout: Option<u32>) {
+        self.commit_change = change_vout.map(|vout| OutPoint {
+            txid: tx.txid(),
+            vout,
+        });
+    }
+
+    /// Is `utxo` the change output of the last block-commit we broadcast?
+    pub fn is_commit_change(&self, utxo: &UTXO) -> bool {
+        self.commit_change.as_ref() == Some(&outpoint(utxo))
+    }
+
+    /// UTXOs that are not spent by one of our transactions, and not in `exclude`
+    fn spendable(&self, exclude: Option<&UTXOSet>) -> Vec<&UTXO> {
+        let excluded: HashSet<Sha256dHash> = exclude
+            .map(|set| set.utxos.iter().map(|utxo| utxo.txid).collect())
+            .unwrap_or_default();
+        self.utxos
+            .iter()
+            .filter(|utxo| !self.is_locked(utxo) && !excluded.contains(&utxo.txid))
+            .collect()
+    }
+
+    /// Choose UTXOs whose value, less the cost of spending them at `fee_rate`, covers `target`.
+    ///
+    /// In order of preference:
+    /// * the set of UTXOs that needs no change output and overpays the least,
+    /// * the smallest single UTXO that leaves a change output above the dust limit,
+    /// * the largest UTXOs, until the target is covered.
+    ///
+    /// Unconfirmed outputs of our own consolidation transactions are only used if nothing else
+    /// covers the target, so a block-commit does not get stuck behind a low-fee parent.
+    ///
+    /// If the last block-commit's change output is spendable, it is always selected, and comes
+    /// first.  The rest are ordered by confirmations, then by value, least first.
+    pub fn select_coins(
+        &self,
+        target: u64,
+        fee_rate: u64,
+        dust_limit: u64,
+        exclude: Option<&UTXOSet>,
+    ) -> Option<Vec<UTXO>> {
+        let (chained, spendable): (Vec<&UTXO>, Vec<&UTXO>) = self
+            .spendable(exclude)
+            .into_iter()
+            .partition(|utxo| self.is_commit_change(utxo));
+        let mut selected: Vec<UTXO> = chained.into_iter().cloned().collect();
+        let chained_value: i128 = selected
+            .iter()
+            .map(|utxo| effective_value(utxo, fee_rate))
+            .sum();
+        if chained_value >= target as i128 {
+            return Some(selected);
+        }
+        let target = target - cmp::max(chained_value, 0) as u64;
+
+        let (settled, unsettled): (Vec<&UTXO>, Vec<&UTXO>) =
+            spendable.into_iter().partition(|utxo| {
+                utxo.confirmations > 0 || !self.pending_consolidations.contains(&utxo.txid)
+            });
+
+        let mut rest = match Self::select_from(&settled, target, fee_rate, dust_limit) {
+            Some(rest) => rest,
+            None => {
+                if unsettled.is_empty() {
+                    return None;
+                }
+                let mut all = settled;
+                all.extend(unsettled);
+                Self::select_from(&all, target, fee_rate, dust_limit)?
+            }
+        };
+        rest.sort_by(|u1, u2| {
+            u1.confirmations
+                .cmp(&u2.confirmations)
+                .then_with(|| u1.amount.cmp(&u2.amount))
+        });
+        selected.append(&mut rest);
+        Some(selected)
+    }
+
+    fn select_from(
+        utxos: &[&UTXO],
+        target: u64,
+        fee_rate: u64,
+        dust_limit: u64,
+    ) -> Option<Vec<UTXO>> {
+        let target = target as i128;
+        let mut candidates: Vec<(i128, &UTXO)> = utxos
+            .iter()
+            .map(|utxo| (effective_value(utxo, fee_rate), *utxo))
+            .filter(|(value, _)| *value > 0)
+            .collect();
+        candidates.sort_by(|(v1, u1), (v2, u2)| {
+            v2.cmp(v1)
+                .then_with(|| u1.txid.cmp(&u2.txid))
+                .then_with(|| u1.vout.cmp(&u2.vout))
+        });
+
+        let total: i128 = candidates.iter().map(|(value, _)| *value).sum();
+        if total < target {
+            return None;
+        }
+
+        // a change output costs its own size, and is only worth creating above the dust limit
+        let cost_of_change = ((P2PKH_OUTPUT_SIZE * fee_rate) + dust_limit) as i128;
+        if let Some(selected) = select_without_change(&candidates, target, cost_of_change) {
+            return Some(selected);
+        }
+
+        if let Some((_, utxo)) = candidates
+            .iter()
+            .rev()
+            .find(|(value, _)| *value >= target + cost_of_change)
+        {
+            return Some(vec![(*utxo).clone()]);
+        }
+
+        let mut selected = vec![];
+        let mut sum = 0;
+        for (value, utxo) in candidates.iter() {
+            selected.push((*utxo).clone());
+            sum += value;
+            if sum >= target {
+                break;
+            }
+        }
+        Some(selected)
+    }
+
+    /// Would a consolidation be due at `block_height`?  Cheap enough to check before asking
+    /// bitcoind for the current UTXO set.
+    pub fn consolidation_due(&self, settings: &ConsolidationSettings, block_height: u64) -> bool {
+        if settings.threshold == 0 {
+            return false;
+        }
+        match self.last_consolidation_height {
+            Some(last) => last + settings.interval <= block_height,
+            None => true,
+        }
+    }
+
+    /// Choose the UTXOs to sweep into one at `block_height`, if a consolidation is due.
+    /// Only confirmed UTXOs are swept, the smallest first, and the largest one is always left
+    /// alone so the next block-commit can spend a confirmed output.  The last block-commit's
+    /// change output is never swept, since the next block-commit must spend it.
+    pub fn plan_consolidation(
+        &mut self,
+        settings: &ConsolidationSettings,
+        block_height: u64,
+        dust_limit: u64,
+    ) -> Option<Vec<UTXO>> {
+        if !self.consolidation_due(settings, block_height) {
+            return None;
+        }
+        self.last_consolidation_height = Some(block_height);
+
+        let mut candidates: Vec<&UTXO> = self
+            .spendable(None)
+            .into_iter()
+            .filter(|utxo| {
+                utxo.confirmations > 0
+                    && effective_value(utxo, settings.fee_rate) > 0
+                    && !self.is_commit_change(utxo)
+            })
+            .collect();
+        if (candidates.len() as u64) <= settings.threshold {
+            return None;
+        }
+
+        candidates.sort_by(|u1, u2| {
+            u1.amount
+                .cmp(&u2.amount)
+                .then_with(|| u1.txid.cmp(&u2.txid))
+                .then_with(|| u1.vout.cmp(&u2.vout))
+        });
+        candidates.pop();
+        candidates.truncate(settings.max_inputs as usize);
+        if candidates.len() < 2 {
+            return None;
+        }
+
+        let total: u64 = candidates.iter().map(|utxo| utxo.amount).sum();
+        let fee = Self::consolidation_size(candidates.len() as u64) * settings.fee_rate;
+        if total < fee + dust_limit {
+            return None;
+        }
+        Some(candidates.into_iter().cloned().collect())
+    }
+
+    /// Size of a transaction spending `num_inputs` p2pkh outputs into one p2pkh output
+    pub fn consolidation_size(num_inputs: u64) -> u64 {
+        TX_OVERHEAD_SIZE + num_inputs * P2PKH_INPUT_SIZE + P2PKH_OUTPUT_SIZE
+    }
+
+    /// Remember a consolidation transaction we broadcast
+    pub fn add_consolidation(&mut self, tx: &Transaction) {
+        self.lock_inputs(tx);
+        self.pending_consolidations.insert(tx.txid());
+    }
+
+    pub fn health(&self, fee_rate: u64) -> UTXOHealth {
+        UTXOHealth {
+            count: self.utxos.len() as u64,
+            total_sats: self.utxos.iter().map(|utxo| utxo.amount).sum(),
+            uneconomic: self
+                .utxos
+                .iter()
+                .filter(|utxo| effective_value(utxo, fee_rate) <= 0)
+                .count() as u64,
+            locked: self.locked.len() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use stacks::types::chainstate::BurnchainHeaderHash;
+    use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::TxIn;
+
+    use super::*;
+
+    fn utxo(id: u8, amount: u64, confirmations: u32) -> UTXO {
+        UTXO {
+            txid: Sha256dHash([id; 32]),
+            vout: 0,
+            script_pub_key: Script::new(),
+            amount,
+            confirmations,
+        }
+    }
+
+    fn spend(utxos: &[UTXO]) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: utxos
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: outpoint(utxo),
+                    script_sig: Script::new(),
+                    sequence: 0xFFFFFFFD,
+                    witness: vec![],
+                })
+                .collect(),
+            output: vec![],
+        }
+    }
+
+    fn amounts(utxos: &[UTXO]) -> Vec<u64> {
+        let mut amounts: Vec<u64> = utxos.iter().map(|utxo| utxo.amount).collect();
+        amounts.sort();
+        amounts
+    }
+
+    #[test]
+    fn test_select_coins() {
+        let fee_rate = 1;
+        let dust = 5500;
+        // amounts are chosen by what they are worth once the cost of spending them is paid
+        let c = P2PKH_INPUT_SIZE * fee_rate;
+        let mut mgr = UTXOManager::new();
+        mgr.refresh(vec![
+            utxo(1, 10_000 + c, 1),
+            utxo(2, 20_000 + c, 1),
+            utxo(3, 29_852 + c, 1),
+            utxo(4, 99_852 + c, 1),
+            utxo(5, 100, 1),
+        ]);
+
+        // one UTXO matches exactly once its input is paid for -- no change needed
+        let selected = mgr.select_coins(20_000, fee_rate, dust, None).unwrap();
+        assert_eq!(amounts(&selected), vec![20_000 + c]);
+
+        // a pair matches within the cost of a change output
+        let selected = mgr.select_coins(29_900, fee_rate, dust, None).unwrap();
+        assert_eq!(amounts(&selected), vec![10_000 + c, 20_000 + c]);
+
+        // no change-free match, so use the smallest UTXO that leaves non-dust change
+        let selected = mgr.select_coins(50_000, fee_rate, dust, None).unwrap();
+        assert_eq!(amounts(&selected), vec![99_852 + c]);
+
+        // nothing big enough on its own, so take the largest first
+        let selected = mgr.select_coins(152_000, fee_rate, dust, None).unwrap();
+        assert_eq!(
+            amounts(&selected),
+            vec![10_000 + c, 20_000 + c, 29_852 + c, 99_852 + c]
+        );
+
+        // the 100-sat UTXO costs more than it is worth, so it can't help
+        let total: u64 = 10_000 + 20_000 + 29_852 + 99_852;
+        assert!(mgr.select_coins(total, fee_rate, dust, None).is_some());
+        assert!(mgr.select_coins(total + 1, fee_rate, dust, None).is_none());
+
+        // excluded UTXOs are skipped
+        let exclude = UTXOSet {
+            bhh: BurnchainHeaderHash([0u8; 32]),
+            utxos: vec![utxo(4, 99_852 + c, 1)],
+        };
+        let selected = mgr
+            .select_coins(50_000, fee_rate, dust, Some(&exclude))
+            .unwrap();
+        assert_eq!(amounts(&selected), vec![10_000 + c, 20_000 + c, 29_852 + c]);
+    }
+
+    #[test]
+    fn test_locks() {
+        let mut mgr = UTXOManager::new();
+        let utxos = vec![utxo(1, 10_000, 1), utxo(2, 50_000, 0)];
+        mgr.refresh(utxos.clone());
+
+        let selected = mgr.select_coins(40_000, 1, 5500, None).unwrap();
+        assert_eq!(amounts(&selected), vec![50_000]);
+        mgr.lock_inputs(&spend(&selected));
+        assert!(mgr.is_locked(&utxos[1]));
+        assert_eq!(mgr.health(1).locked, 1);
+
+        // can't double-spend it
+        assert!(mgr.select_coins(40_000, 1, 5500, None).is_none());
+        assert_eq!(
+            amounts(&mgr.select_coins(5_000, 1, 5500, None).unwrap()),
+            vec![10_000]
+        );
+
+        // bitcoind still reports it as unspent, so it stays locked
+        mgr.refresh(utxos.clone());
+        assert!(mgr.is_locked(&utxos[1]));
+
+        // bitcoind saw the spend, so the lock goes away
+        mgr.refresh(vec![utxos[0].clone()]);
+        assert!(!mgr.is_locked(&utxos[1]));
+        assert_eq!(mgr.health(1).locked, 0);
+
+        // if the spend disappears and bitcoind reports the UTXO again, the lock is gone for good
+        mgr.refresh(utxos.clone());
+        assert_eq!(
+            amounts(&mgr.select_coins(40_000, 1, 5500, None).unwrap()),
+            vec![50_000]
+        );
+
+        // stale locks time out
+        mgr.lock_inputs(&spend(&utxos[1..]));
+        mgr.locked.get_mut(&outpoint(&utxos[1])).unwrap().locked_at -= LOCK_TIMEOUT_SECS;
+        mgr.refresh(utxos.clone());
+        assert!(!mgr.is_locked(&utxos[1]));
+    }
+
+    #[test]
+    fn test_commit_chaining() {
+        let mut mgr = UTXOManager::new();
+        let utxos = vec![
+            utxo(1, 10_000, 3),
+            utxo(2, 50_000, 1),
+            utxo(3, 80_000, 2),
+            utxo(4, 200_000, 1),
+        ];
+        mgr.refresh(utxos.clone());
+
+        // nothing to chain to yet
+        let selected = mgr.select_coins(40_000, 1, 5500, None).unwrap();
+        assert_eq!(amounts(&selected), vec![50_000]);
+
+        // the last block-commit's change output is always spent, and spent first
+        let mut commit = spend(&[utxos[3].clone()]);
+        commit.output.push(
+            stacks_common::deps_common::bitcoin::blockdata::transaction::TxOut {
+                value: 30_000,
+                script_pubkey: Script::new(),
+            },
+        );
+        mgr.set_commit_change(&commit, Some(0));
+        let change = UTXO {
+            txid: commit.txid(),
+            vout: 0,
+            script_pub_key: Script::new(),
+            amount: 30_000,
+            confirmations: 1,
+        };
+        let mut utxos = utxos[..3].to_vec();
+        utxos.push(change.clone());
+        mgr.refresh(utxos.clone());
+        assert!(mgr.is_commit_change(&change));
+
+        // covers the target on its own
+        let selected = mgr.select_coins(20_000, 1, 5500, None).unwrap();
+        assert_eq!(selected, vec![change.clone()]);
+
+        // topped up by the least-confirmed, then smallest, UTXOs
+        let selected = mgr.select_coins(100_000, 1, 5500, None).unwrap();
+        assert_eq!(selected[0], change);
+        assert_eq!(amounts(&selected[1..]), vec![80_000]);
+        let selected = mgr.select_coins(160_000, 1, 5500, None).unwrap();
+        assert_eq!(selected[0], change);
+        assert_eq!(
+            selected[1..]
+                .iter()
+                .map(|utxo| utxo.amount)
+                .collect::<Vec<_>>(),
+            vec![50_000, 80_000, 10_000]
+        );
+
+        // never swept into a consolidation
+        let settings = ConsolidationSettings {
+            threshold: 2,
+            interval: 10,
+            fee_rate: 1,
+            max_inputs: 10,
+        };
+        let planned = mgr.plan_consolidation(&settings, 100, 5500).unwrap();
+        assert_eq!(amounts(&planned), vec![10_000, 50_000]);
+
+        // once it's spent, there is nothing to chain to until the next block-commit
+        mgr.lock_inputs(&spend(std::slice::from_ref(&change)));
+        let selected = mgr.select_coins(20_000, 1, 5500, None).unwrap();
+        assert_eq!(amounts(&selected), vec![50_000]);
+
+        // a block-commit with no change output can't be chained to
+        mgr.set_commit_change(&commit, None);
+        assert!(!mgr.is_commit_change(&change));
+    }
+
+    #[test]
+    fn test_consolidation() {
+        let settings = ConsolidationSettings {
+            threshold: 3,
+            interval: 10,
+            fee_rate: 2,
+            max_inputs: 3,
+        };
+        let mut mgr = UTXOManager::new();
+        let mut utxos = vec![
+            utxo(1, 10_000, 1),
+            utxo(2, 11_000, 1),
+            utxo(3, 12_000, 1),
+            utxo(4, 13_000, 0),
+            utxo(5, 1_000_000, 1),
+        ];
+        mgr.refresh(utxos.clone());
+
+        // only 4 confirmed UTXOs, and one is kept back -- sweep the 3 smallest
+        let planned = mgr.plan_consolidation(&settings, 100, 5500).unwrap();
+        assert_eq!(amounts(&planned), vec![10_000, 11_000, 12_000]);
+
+        // not due again until the interval passes
+        assert!(!mgr.consolidation_due(&settings, 109));
+        assert!(mgr.plan_consolidation(&settings, 105, 5500).is_none());
+        assert!(mgr.consolidation_due(&settings, 110));
+
+        // broadcast it; its output is unconfirmed, so block-commits avoid it while they can
+        let mut tx = spend(&planned);
+        tx.output.push(
+            stacks_common::deps_common::bitcoin::blockdata::transaction::TxOut {
+                value: 33_000 - UTXOManager::consolidation_size(3) * 2,
+                script_pubkey: Script::new(),
+            },
+        );
+        mgr.add_consolidation(&tx);
+        let consolidated = UTXO {
+            txid: tx.txid(),
+            vout: 0,
+            script_pub_key: Script::new(),
+            amount: tx.output[0].value,
+            confirmations: 0,
+        };
+        utxos = vec![utxos[3].clone(), utxos[4].clone(), consolidated.clone()];
+        mgr.refresh(utxos.clone());
+        assert_eq!(
+            amounts(&mgr.select_coins(12_000, 1, 5500, None).unwrap()),
+            vec![13_000]
+        );
+        assert_eq!(
+            amounts(&mgr.select_coins(1_020_000, 1, 5500, None).unwrap()),
+            vec![consolidated.amount, 1_000_000]
+        );
+
+        // too few UTXOs to bother
+        assert!(mgr.plan_consolidation(&settings, 110, 5500).is_none());
+
+        // disabled
+        let disabled = ConsolidationSettings {
+            threshold: 0,
+            ..settings.clone()
+        };
+        let mut mgr = UTXOManager::new();
+        mgr.refresh((1..10).map(|i| utxo(i, 10_000, 1)).collect());
+        assert!(!mgr.consolidation_due(&disabled, 100));
+        assert!(mgr.plan_consolidation(&disabled, 100, 5500).is_none());
+
+        // too small to be worth sweeping at this fee rate
+        let mut mgr = UTXOManager::new();
+        mgr.refresh((1..10).map(|i| utxo(i, 200, 1)).collect());
+        assert!(mgr.plan_consolidation(&settings, 100, 5500).is_none());
+        assert_eq!(mgr.health(2).uneconomic, 9);
+    }
+}
@@ -0,0 +1,561 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Block-commit strategies.
+//!
+//! Before the relayer thread starts a tenure, it asks the node's `CommitStrategy` how many sats
+//! to commit in the next block-commit, or whether to sit this burnchain block out.  The strategy
+//! sees the recent block-commits and missed commits in the mining commitment window, the burn
+//! distribution of the last sortition, this miner's own median commitment, the block reward, and
+//! (if the operator supplied one) the price of STX in sats.
+//!
+//! Three strategies are built in:
+//! * `fixed` always commits `burnchain.burn_fee_cap` (the node's historical behavior).
+//! * `median` commits the median of what the other miners committed in the last sortition.
+//! * Either one can be wrapped in a budget cap by setting `miner.commit_budget`, which stops
+//!   committing once the budget is spent and skips blocks whose expected reward is worth less than
+//!   the commit.  The amount spent is only kept in memory, so the budget applies to each run of
+//!   the node and starts over when it restarts.
+
+use std::fs;
+
+use stacks::burnchains::{Burnchain, BurnchainSigner};
+use stacks::chainstate::burn::db::sortdb::SortitionDB;
+use stacks::chainstate::burn::distribution::{BurnSamplePoint, CommitWindow};
+use stacks::chainstate::burn::operations::leader_block_commit::{
+    MissedBlockCommit, OUTPUTS_PER_COMMIT,
+};
+use stacks::chainstate::burn::operations::LeaderBlockCommitOp;
+use stacks::chainstate::burn::BlockSnapshot;
+use stacks::chainstate::stacks::db::StacksChainState;
+use stacks::util_lib::db::Error as DBError;
+
+use crate::burnchains::bitcoin_regtest_controller::DUST_UTXO_LIMIT;
+use crate::config::{CommitStrategyName, MinerConfig};
+
+/// Everything a strategy may consult when sizing the next block-commit
+#[derive(Debug, Clone)]
+pub struct CommitContext {
+    /// Burnchain block height in which the block-commit will be mined
+    pub burn_block_height: u64,
+    /// The most the operator is willing to commit in one block (`burnchain.burn_fee_cap`)
+    pub burn_fee_cap: u64,
+    /// This miner's burnchain signer, to tell its commits apart from everyone else's
+    pub signer: BurnchainSigner,
+    /// Block-commits in the commitment window ending at the burnchain tip, in ascending height
+    /// order
+    pub recent_commits: Vec<Vec<LeaderBlockCommitOp>>,
+    /// Missed block-commits intended for each height in the window except the tip
+    pub missed_commits: Vec<Vec<MissedBlockCommit>>,
+    /// The burn distribution of the burnchain tip's sortition
+    pub distribution: Vec<BurnSamplePoint>,
+    /// This miner's median commitment over the window, counting heights where it did not commit
+    /// as 1 sat (as `MINER_CURRENT_MEDIAN_COMMITMENT` does).  None if it has not committed in the
+    /// window at all.
+    pub median_commitment: Option<u64>,
+    /// Coinbase reward, in uSTX, for winning the sortition at `burn_block_height`
+    pub block_reward_ustx: u128,
+    /// Price of 1 STX in sats, if the operator supplied one
+    pub stx_price_sats: Option<f64>,
+}
+
+impl CommitContext {
+    /// Build the context for a block-commit that will be mined on top of `tip`.
+    pub fn load(
+        sortdb: &SortitionDB,
+        burnchain: &Burnchain,
+        tip: &BlockSnapshot,
+        signer: BurnchainSigner,
+        burn_fee_cap: u64,
+        stx_price_sats: Option<f64>,
+    ) -> Result<CommitContext, DBError> {
+        let window = CommitWindow::load(sortdb, burnchain, tip)?;
+        let distribution = window.make_distribution();
+        let median_commitment = Self::median_commitment_of(&window.block_commits, &signer);
+        let burn_block_height = tip.block_height + 1;
+        Ok(CommitContext {
+            burn_block_height,
+            burn_fee_cap,
+            signer,
+            recent_commits: window.block_commits,
+            missed_commits: window.missed_commits,
+            distribution,
+            median_commitment,
+            block_reward_ustx: StacksChainState::get_coinbase_reward(
+                burn_block_height,
+                burnchain.first_block_height,
+            ),
+            stx_price_sats,
+        })
+    }
+
+    /// Median of `signer`'s total commitment at each height of the window.
+    fn median_commitment_of(
+        block_commits: &[Vec<LeaderBlockCommitOp>],
+        signer: &BurnchainSigner,
+    ) -> Option<u64> {
+        let mut committed = false;
+        let mut burns: Vec<u64> = block_commits
+            .iter()
+            .map(|commits| {
+                let mine: u64 = commits
+                    .iter()
+                    .filter(|op| op.apparent_sender == *signer)
+                    .map(|op| op.burn_fee)
+                    .sum();
+                if mine > 0 {
+                    committed = true;
+                    mine
+                } else {
+                    1
+                }
+            })
+            .collect();
+        if !committed {
+            return None;
+        }
+        burns.sort();
+        let mid = burns.len() / 2;
+        if burns.len() % 2 == 0 {
+            Some((burns[mid - 1] + burns[mid]) / 2)
+        } else {
+            Some(burns[mid])
+        }
+    }
+
+    /// Effective burns of the other miners in the last sortition
+    pub fn other_burns(&self) -> Vec<u128> {
+        self.distribution
+            .iter()
+            .filter(|point| point.candidate.apparent_sender != self.signer)
+            .map(|point| point.burns)
+            .collect()
+    }
+
+    /// Burn fees the other miners actually paid in the last sortition's block-commits
+    pub fn other_burn_fees(&self) -> Vec<u64> {
+        self.distribution
+            .iter()
+            .filter(|point| point.candidate.apparent_sender != self.signer)
+            .map(|point| point.candidate.burn_fee)
+            .collect()
+    }
+
+    /// Chance of winning with a commitment of `amount`, assuming the other miners commit what
+    /// they did in the last sortition
+    pub fn win_probability(&self, amount: u64) -> f64 {
+        let others: u128 = self.other_burns().iter().sum();
+        let total = others + (amount as u128);
+        if total == 0 {
+            return 0.0;
+        }
+        (amount as f64) / (total as f64)
+    }
+
+    /// Expected value, in sats, of the block reward when committing `amount`.  None if no STX
+    /// price is known.
+    pub fn expected_reward_sats(&self, amount: u64) -> Option<f64> {
+        let price = self.stx_price_sats?;
+        let reward_stx = (self.block_reward_ustx as f64) / 1_000_000.0;
+        Some(self.win_probability(amount) * reward_stx * price)
+    }
+}
+
+/// Decides how much the miner commits in each block-commit
+pub trait CommitStrategy: Send {
+    /// Name of the strategy, for logging
+    fn name(&self) -> &'static str;
+
+    /// Number of sats to commit in the next block-commit, or None to not mine in this burnchain
+    /// block.  The relayer caps the result at `ctx.burn_fee_cap`.
+    fn commit_amount(&mut self, ctx: &CommitContext) -> Option<u64>;
+
+    /// Called once a block-commit of `amount` sats for `burn_block_height` has been sent.  A
+    /// later commit for the same height replaces (RBFs) the earlier one.
+    fn record_commit(&mut self, _burn_block_height: u64, _amount: u64) {}
+}
+
+/// Always commit `burnchain.burn_fee_cap`
+pub struct FixedCommitStrategy;
+
+impl CommitStrategy for FixedCommitStrategy {
+    fn name(&self) -> &'static str {
+        "fixed"
+    }
+
+    fn commit_amount(&mut self, ctx: &CommitContext) -> Option<u64> {
+        Some(ctx.burn_fee_cap)
+    }
+}
+
+/// Commit the median of the other miners' burn fees in the last sortition, so this miner keeps
+/// an average miner's share.  Their effective burns are not used, since the min-median rule
+/// discounts a miner who has not committed throughout the window, and tracking that would
+/// drag this miner's commitment down with theirs.  Never commits less than it takes for each
+/// commit output to clear the dust limit, and commits `burnchain.burn_fee_cap` if no one else
+/// mined.
+pub struct MedianTrackingStrategy;
+
+impl CommitStrategy for MedianTrackingStrategy {
+    fn name(&self) -> &'static str {
+        "median"
+    }
+
+    fn commit_amount(&mut self, ctx: &CommitContext) -> Option<u64> {
+        let mut burns = ctx.other_burn_fees();
+        if burns.len() == 0 {
+            return Some(ctx.burn_fee_cap);
+        }
+        burns.sort();
+        let mid = burns.len() / 2;
+        let median = if burns.len() % 2 == 0 {
+            (burns[mid - 1] + burns[mid]) / 2
+        } else {
+            burns[mid]
+        };
+        let floor = (OUTPUTS_PER_COMMIT as u64) * DUST_UTXO_LIMIT;
+        // not `clamp()`, which panics if `burn_fee_cap` is below the floor
+        Some(median.max(floor).min(ctx.burn_fee_cap))
+    }
+}
+
+/// Wrap another strategy, committing no more than `budget` sats in total.  Also skips blocks
+/// whose expected reward is worth less than the commit, if the STX price is known.
+///
+/// The amount spent is not persisted: the budget applies per process, and a restarted node may
+/// commit up to `budget` sats again.
+pub struct BudgetCappedStrategy {
+    inner: Box<dyn CommitStrategy>,
+    budget: u64,
+    spent: u64,
+    /// Height and amount of the last commit sent, which a later RBF would replace
+    last_commit: Option<(u64, u64)>,
+}
+
+impl BudgetCappedStrategy {
+    pub fn new(inner: Box<dyn CommitStrategy>, budget: u64) -> BudgetCappedStrategy {
+        BudgetCappedStrategy {
+            inner,
+            budget,
+            spent: 0,
+            last_commit: None,
+        }
+    }
+
+    /// Sats committed so far
+    pub fn spent(&self) -> u64 {
+        self.spent
+    }
+
+    /// Amount already counted against the budget for a commit at this height
+    fn replaced_amount(&self, burn_block_height: u64) -> u64 {
+        match self.last_commit {
+            Some((height, amount)) if height == burn_block_height => amount,
+            _ => 0,
+        }
+    }
+}
+
+impl CommitStrategy for BudgetCappedStrategy {
+    fn name(&self) -> &'static str {
+        "budget"
+    }
+
+    fn commit_amount(&mut self, ctx: &CommitContext) -> Option<u64> {
+        let amount = self.inner.commit_amount(ctx)?;
+        let remaining =
+            self.budget.saturating_sub(self.spent) + self.replaced_amount(ctx.burn_block_height);
+        let amount = amount.min(remaining);
+        if amount == 0 {
+            info!(
+                "Commit budget of {} sats is spent; not mining at burn height {}",
+                self.budget, ctx.burn_block_height
+            );
+            return None;
+        }
+        if let Some(expected_reward) = ctx.expected_reward_sats(amount) {
+            if expected_reward < amount as f64 {
+                info!(
+                    "Expected reward of {:.0} sats is less than the {} sats commit; not mining at burn height {}",
+                    expected_reward, amount, ctx.burn_block_height
+                );
+                return None;
+            }
+        }
+        Some(amount)
+    }
+
+    fn record_commit(&mut self, burn_block_height: u64, amount: u64) {
+        let replaced = self.replaced_amount(burn_block_height);
+        if amount > replaced {
+            self.spent = self.spent.saturating_add(amount - replaced);
+            self.last_commit = Some((burn_block_height, amount));
+        }
+        self.inner.record_commit(burn_block_height, amount);
+    }
+}
+
+/// Instantiate the strategy described by the miner config
+pub fn make_commit_strategy(config: &MinerConfig) -> Box<dyn CommitStrategy> {
+    let strategy: Box<dyn CommitStrategy> = match config.commit_strategy {
+        CommitStrategyName::Fixed => Box::new(FixedCommitStrategy),
+        CommitStrategyName::MedianTracking => Box::new(MedianTrackingStrategy),
+    };
+    match config.commit_budget {
+        Some(budget) => Box::new(BudgetCappedStrategy::new(strategy, budget)),
+        None => strategy,
+    }
+}
+
+/// Get the price of STX in sats.  `miner.stx_price_file` is re-read on every call so an external
+/// price feed can keep it current; if it is missing or malformed, fall back to
+/// `miner.stx_price_sats`.
+pub fn get_stx_price_sats(config: &MinerConfig) -> Option<f64> {
+    if let Some(ref path) = config.stx_price_file {
+        match fs::read_to_string(path) {
+            Ok(contents) => match contents.trim().parse::<f64>() {
+                Ok(price) if price.is_finite() && price > 0.0 => return Some(price),
+                _ => {
+                    warn!(
+                        "Ignoring malformed STX price in {}: {:?}",
+                        path,
+                        contents.trim()
+                    );
+                }
+            },
+            Err(e) => {
+                warn!("Failed to read STX price from {}: {:?}", path, &e);
+            }
+        }
+    }
+    config.stx_price_sats
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use stacks::burnchains::Txid;
+    use stacks::chainstate::burn::distribution::BurnSamplePoint;
+    use stacks::chainstate::stacks::address::PoxAddress;
+    use stacks::types::chainstate::{BlockHeaderHash, BurnchainHeaderHash, VRFSeed};
+    use stacks::util::secp256k1::{Secp256k1PrivateKey, Secp256k1PublicKey};
+    use stacks::util::uint::{BitArray, Uint256};
+    use stacks_common::address::AddressHashMode;
+
+    fn signer(n: u8) -> BurnchainSigner {
+        let mut seed = [1u8; 32];
+        seed[0] = n + 1;
+        let privk = Secp256k1PrivateKey::from_slice(&seed).unwrap();
+        BurnchainSigner {
+            hash_mode: AddressHashMode::SerializeP2PKH,
+            num_sigs: 1,
+            public_keys: vec![Secp256k1PublicKey::from_private(&privk)],
+        }
+    }
+
+    fn commit(sender: u8, burn_fee: u64, block_height: u64) -> LeaderBlockCommitOp {
+        LeaderBlockCommitOp {
+            sunset_burn: 0,
+            block_header_hash: BlockHeaderHash([sender; 32]),
+            new_seed: VRFSeed([sender; 32]),
+            parent_block_ptr: 0,
+            parent_vtxindex: 0,
+            key_block_ptr: 0,
+            key_vtxindex: 0,
+            memo: vec![],
+            commit_outs: vec![PoxAddress::standard_burn_address(false)],
+            burn_fee,
+            input: (Txid([0; 32]), 0),
+            apparent_sender: signer(sender),
+            txid: Txid([sender; 32]),
+            vtxindex: sender as u32,
+            block_height,
+            burn_parent_modulus: 0,
+            burn_header_hash: BurnchainHeaderHash([0; 32]),
+        }
+    }
+
+    fn point(sender: u8, burns: u128) -> BurnSamplePoint {
+        discounted_point(sender, burns as u64, burns)
+    }
+
+    /// A sample point whose effective burn is less than the commit's burn fee
+    fn discounted_point(sender: u8, burn_fee: u64, burns: u128) -> BurnSamplePoint {
+        BurnSamplePoint {
+            burns,
+            range_start: Uint256::zero(),
+            range_end: Uint256::zero(),
+            candidate: commit(sender, burn_fee, 100),
+            user_burns: vec![],
+        }
+    }
+
+    fn context(distribution: Vec<BurnSamplePoint>) -> CommitContext {
+        CommitContext {
+            burn_block_height: 101,
+            burn_fee_cap: 20_000,
+            signer: signer(0),
+            recent_commits: vec![],
+            missed_commits: vec![],
+            distribution,
+            median_commitment: None,
+            block_reward_ustx: 1000 * 1_000_000,
+            stx_price_sats: None,
+        }
+    }
+
+    #[test]
+    fn test_median_commitment_of() {
+        let window = vec![
+            vec![commit(0, 100, 96), commit(1, 5000, 96)],
+            vec![commit(1, 5000, 97)],
+            vec![commit(0, 300, 98)],
+            vec![commit(0, 200, 99), commit(0, 200, 99)],
+            vec![commit(0, 500, 100)],
+        ];
+        // burns by height are 100, 1, 300, 400, 500
+        assert_eq!(
+            CommitContext::median_commitment_of(&window, &signer(0)),
+            Some(300)
+        );
+        assert_eq!(
+            CommitContext::median_commitment_of(&window[1..], &signer(0)),
+            Some(350)
+        );
+        assert_eq!(
+            CommitContext::median_commitment_of(&window, &signer(2)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fixed_strategy() {
+        let ctx = context(vec![point(1, 1000)]);
+        assert_eq!(FixedCommitStrategy.commit_amount(&ctx), Some(20_000));
+    }
+
+    #[test]
+    fn test_median_tracking_strategy() {
+        // no other miners
+        let ctx = context(vec![point(0, 1000)]);
+        assert_eq!(MedianTrackingStrategy.commit_amount(&ctx), Some(20_000));
+
+        // our own burn is ignored
+        let ctx = context(vec![
+            point(0, 1),
+            point(1, 12_000),
+            point(2, 14_000),
+            point(3, 19_000),
+        ]);
+        assert_eq!(MedianTrackingStrategy.commit_amount(&ctx), Some(14_000));
+
+        let ctx = context(vec![point(1, 12_000), point(2, 14_000)]);
+        assert_eq!(MedianTrackingStrategy.commit_amount(&ctx), Some(13_000));
+
+        // tracks what the others committed, not their min-median discounted burns
+        let ctx = context(vec![
+            discounted_point(1, 15_000, 1),
+            discounted_point(2, 16_000, 1),
+            discounted_point(3, 17_000, 1),
+        ]);
+        assert_eq!(MedianTrackingStrategy.commit_amount(&ctx), Some(16_000));
+
+        // never below what it takes for every commit output to clear the dust limit
+        let floor = (OUTPUTS_PER_COMMIT as u64) * DUST_UTXO_LIMIT;
+        let ctx = context(vec![point(1, 1000), point(2, 3000)]);
+        assert_eq!(MedianTrackingStrategy.commit_amount(&ctx), Some(floor));
+
+        // capped at burn_fee_cap
+        let ctx = context(vec![point(1, 100_000)]);
+        assert_eq!(MedianTrackingStrategy.commit_amount(&ctx), Some(20_000));
+
+        // even if it is below the floor
+        let mut ctx = context(vec![point(1, 12_000)]);
+        ctx.burn_fee_cap = 0;
+        assert_eq!(MedianTrackingStrategy.commit_amount(&ctx), Some(0));
+    }
+
+    #[test]
+    fn test_budget_capped_strategy() {
+        let mut strategy = BudgetCappedStrategy::new(Box::new(FixedCommitStrategy), 50_000);
+        let mut ctx = context(vec![point(1, 20_000)]);
+
+        assert_eq!(strategy.commit_amount(&ctx), Some(20_000));
+        strategy.record_commit(ctx.burn_block_height, 20_000);
+        assert_eq!(strategy.spent(), 20_000);
+
+        // an RBF at the same height only counts the difference
+        assert_eq!(strategy.commit_amount(&ctx), Some(20_000));
+        strategy.record_commit(ctx.burn_block_height, 20_000);
+        assert_eq!(strategy.spent(), 20_000);
+
+        ctx.burn_block_height += 1;
+        assert_eq!(strategy.commit_amount(&ctx), Some(20_000));
+        strategy.record_commit(ctx.burn_block_height, 20_000);
+        assert_eq!(strategy.spent(), 40_000);
+
+        // only 10_000 left
+        ctx.burn_block_height += 1;
+        assert_eq!(strategy.commit_amount(&ctx), Some(10_000));
+        strategy.record_commit(ctx.burn_block_height, 10_000);
+        assert_eq!(strategy.spent(), 50_000);
+
+        // but an RBF of that commit may still use what it already counted
+        assert_eq!(strategy.commit_amount(&ctx), Some(10_000));
+
+        ctx.burn_block_height += 1;
+        assert_eq!(strategy.commit_amount(&ctx), None);
+    }
+
+    #[test]
+    fn test_budget_capped_strategy_expected_value() {
+        let mut strategy = BudgetCappedStrategy::new(Box::new(FixedCommitStrategy), 1_000_000);
+        let mut ctx = context(vec![point(1, 20_000)]);
+
+        // 50% chance of 1000 STX at 30 sats/STX is worth 15_000 sats
+        ctx.stx_price_sats = Some(30.0);
+        assert_eq!(ctx.win_probability(20_000), 0.5);
+        assert_eq!(ctx.expected_reward_sats(20_000), Some(15_000.0));
+        assert_eq!(strategy.commit_amount(&ctx), None);
+
+        // at 50 sats/STX it is worth 25_000 sats
+        ctx.stx_price_sats = Some(50.0);
+        assert_eq!(strategy.commit_amount(&ctx), Some(20_000));
+    }
+
+    #[test]
+    fn test_get_stx_price_sats() {
+        let path = format!(
+            "/tmp/stacks-node-test-stx-price-{}",
+            stacks::util::get_epoch_time_ms()
+        );
+        let mut config = MinerConfig::default();
+        assert_eq!(get_stx_price_sats(&config), None);
+
+        config.stx_price_sats = Some(40.0);
+        config.stx_price_file = Some(path.clone());
+        assert_eq!(get_stx_price_sats(&config), Some(40.0));
+
+        fs::write(&path, "55.5\n").unwrap();
+        assert_eq!(get_stx_price_sats(&config), Some(55.5));
+
+        fs::write(&path, "not a price").unwrap();
+        assert_eq!(get_stx_price_sats(&config), Some(40.0));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
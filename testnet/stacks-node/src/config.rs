@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs;
 use std::net::{SocketAddr, ToSocketAddrs};
@@ -28,6 +29,7 @@ use stacks::cost_estimates::CostEstimator;
 use stacks::cost_estimates::FeeEstimator;
 use stacks::cost_estimates::PessimisticEstimator;
 use stacks::net::connection::ConnectionOptions;
+use stacks::net::socks::{hostname_peer_address, is_onion_hostname, Socks5Proxy};
 use stacks::net::{Neighbor, NeighborKey, PeerAddress};
 use stacks::util::get_epoch_time_ms;
 use stacks::util::hash::hex_bytes;
@@ -35,12 +37,22 @@ use stacks::util::secp256k1::Secp256k1PrivateKey;
 use stacks::util::secp256k1::Secp256k1PublicKey;
 use stacks::vm::types::{AssetIdentifier, PrincipalData, QualifiedContractIdentifier};
 
+use crate::burnchains::fee_estimator::FeeRateSettings;
+use crate::burnchains::signer::parse_signer_public_key;
+use crate::burnchains::utxo_manager::ConsolidationSettings;
+
 const DEFAULT_SATS_PER_VB: u64 = 50;
 const DEFAULT_MAX_RBF_RATE: u64 = 150; // 1.5x
 const DEFAULT_RBF_FEE_RATE_INCREMENT: u64 = 5;
 const LEADER_KEY_TX_ESTIM_SIZE: u64 = 290;
 const BLOCK_COMMIT_TX_ESTIM_SIZE: u64 = 350;
 const INV_REWARD_CYCLES_TESTNET: u64 = 6;
+const DEFAULT_UTXO_CONSOLIDATION_INTERVAL: u64 = 144;
+const DEFAULT_UTXO_CONSOLIDATION_FEE_RATE: u64 = 2;
+const DEFAULT_UTXO_CONSOLIDATION_MAX_INPUTS: u64 = 50;
+const DEFAULT_FEE_RATE_TARGET_BLOCKS: u64 = 2;
+const DEFAULT_FEE_RATE_MIN: u64 = 1;
+const DEFAULT_FEE_RATE_MAX: u64 = 500;
 
 #[derive(Clone, Deserialize, Default, Debug)]
 pub struct ConfigFile {
@@ -125,6 +137,385 @@ mod tests {
         assert!(Config::from_config_file(ConfigFile::from_str("").unwrap()).is_ok());
     }
 
+    #[test]
+    fn test_config_socks5_proxy() {
+        let onion = "abcdefghijklmnopqrstuvwxyz234567abcdefghijklmnopqrstuvwx.onion";
+        let pubkey = "047435c194e9b01b3d7f7a2802d6684a3af68d05bbf4ec8f17021980d777691f1d51651f7f1d566532c804da506c117bbf79ad62eea81213ba58f8808b4d9504ad";
+        let config = Config::from_config_file(
+            ConfigFile::from_str(&format!(
+                r#"
+                [node]
+                bootstrap_node = "{}@{}:20444"
+
+                [burnchain]
+                peer_host = "bitcoind.example.onion"
+                socks5_proxy = "127.0.0.1:9050"
+
+                [connection_options]
+                socks5_proxy = "alice:hunter2@127.0.0.1:9050"
+                socks5_proxy_event_observers = true
+                "#,
+                pubkey, onion
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+
+        // the bitcoind hostname is left for the proxy to resolve
+        assert_eq!(config.burnchain.peer_host, "bitcoind.example.onion");
+        assert_eq!(
+            config.burnchain.socks5_proxy.unwrap().addr,
+            "127.0.0.1:9050".parse().unwrap()
+        );
+
+        let proxy = config.connection_options.socks5_proxy.unwrap();
+        assert_eq!(
+            proxy.auth,
+            Some(("alice".to_string(), "hunter2".to_string()))
+        );
+        assert_eq!(config.events_observers_socks5_proxy, Some(proxy));
+
+        // the onion bootstrap node is known by a placeholder address
+        let bootstrap = &config.node.bootstrap_node[0];
+        assert_eq!(bootstrap.addr.addrbytes, hostname_peer_address(onion));
+        assert_eq!(bootstrap.addr.port, 20444);
+        assert_eq!(
+            config
+                .connection_options
+                .socks5_hostnames
+                .get(&bootstrap.addr.addrbytes),
+            Some(&onion.to_string())
+        );
+
+        // onion bootstrap nodes need a proxy
+        assert_eq!(
+            Config::from_config_file(
+                ConfigFile::from_str(&format!(
+                    r#"
+                    [node]
+                    bootstrap_node = "{}@{}:20444"
+                    "#,
+                    pubkey, onion
+                ))
+                .unwrap()
+            )
+            .unwrap_err(),
+            "Bootstrap nodes with .onion addresses require connection_options.socks5_proxy"
+        );
+    }
+
+    #[test]
+    fn test_config_burnchain_signer() {
+        let pubkey = "03ef2340518b5867b23598a9cf74611f8b98064f7d55cdb8c107c67b5efcbc5c77";
+        let config = Config::from_config_file(ConfigFile::from_str("").unwrap()).unwrap();
+        assert_eq!(config.burnchain.signer, BurnchainSignerConfig::Local);
+
+        let config = Config::from_config_file(
+            ConfigFile::from_str(&format!(
+                r#"
+                [burnchain]
+                signer_command = ["/usr/local/bin/sign-psbt", "--key", "miner"]
+                signer_public_key = "{}"
+                "#,
+                pubkey
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            config.burnchain.signer,
+            BurnchainSignerConfig::Command {
+                command: vec![
+                    "/usr/local/bin/sign-psbt".to_string(),
+                    "--key".to_string(),
+                    "miner".to_string()
+                ],
+                public_key: Secp256k1PublicKey::from_hex(pubkey).unwrap(),
+            }
+        );
+
+        let config = Config::from_config_file(
+            ConfigFile::from_str(&format!(
+                r#"
+                [burnchain]
+                signer_url = "http://127.0.0.1:9999/sign"
+                signer_public_key = "{}"
+                "#,
+                pubkey
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            config.burnchain.signer.public_key(),
+            Some(&Secp256k1PublicKey::from_hex(pubkey).unwrap())
+        );
+
+        for (conf, err) in [
+            (
+                format!(r#"signer_url = "http://127.0.0.1:9999/sign""#),
+                "An external burnchain signer requires burnchain.signer_public_key",
+            ),
+            (
+                format!(r#"signer_public_key = "{}""#, pubkey),
+                "burnchain.signer_public_key requires burnchain.signer_command or burnchain.signer_url",
+            ),
+            (
+                format!(
+                    "signer_url = \"http://127.0.0.1:9999/sign\"\nsigner_command = [\"sign\"]\nsigner_public_key = \"{}\"",
+                    pubkey
+                ),
+                "burnchain.signer_command and burnchain.signer_url are mutually exclusive",
+            ),
+            (
+                format!(
+                    "signer_url = \"https://127.0.0.1:9999/sign\"\nsigner_public_key = \"{}\"",
+                    pubkey
+                ),
+                "Invalid burnchain.signer_url: only http:// URLs are supported",
+            ),
+            (
+                format!("signer_command = []\nsigner_public_key = \"{}\"", pubkey),
+                "burnchain.signer_command cannot be empty",
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                &Config::from_config_file(
+                    ConfigFile::from_str(&format!("[burnchain]\n{}", conf)).unwrap()
+                )
+                .unwrap_err(),
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_utxo_consolidation() {
+        let config = Config::from_config_file(ConfigFile::from_str("").unwrap()).unwrap();
+        assert_eq!(
+            config.burnchain.get_utxo_consolidation_settings().threshold,
+            0
+        );
+
+        let config = Config::from_config_file(
+            ConfigFile::from_str(
+                r#"
+                [burnchain]
+                utxo_consolidation_threshold = 20
+                utxo_consolidation_interval = 6
+                utxo_consolidation_fee_rate = 1
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            config.burnchain.get_utxo_consolidation_settings(),
+            ConsolidationSettings {
+                threshold: 20,
+                interval: 6,
+                fee_rate: 1,
+                max_inputs: 50,
+            }
+        );
+
+        assert_eq!(
+            Config::from_config_file(
+                ConfigFile::from_str(
+                    r#"
+                    [burnchain]
+                    utxo_consolidation_max_inputs = 1
+                    "#,
+                )
+                .unwrap()
+            )
+            .unwrap_err(),
+            "burnchain.utxo_consolidation_max_inputs must be at least 2"
+        );
+        assert_eq!(
+            Config::from_config_file(
+                ConfigFile::from_str(
+                    r#"
+                    [burnchain]
+                    utxo_consolidation_fee_rate = 0
+                    "#,
+                )
+                .unwrap()
+            )
+            .unwrap_err(),
+            "burnchain.utxo_consolidation_fee_rate must be at least 1"
+        );
+    }
+
+    #[test]
+    fn test_config_fee_rate_estimation() {
+        let config = Config::from_config_file(ConfigFile::from_str("").unwrap()).unwrap();
+        assert!(!config.burnchain.get_fee_rate_settings().enabled);
+
+        let config = Config::from_config_file(
+            ConfigFile::from_str(
+                r#"
+                [burnchain]
+                satoshis_per_byte = 20
+                fee_rate_estimation = true
+                fee_rate_target_blocks = 6
+                fee_rate_max = 80
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            config.burnchain.get_fee_rate_settings(),
+            FeeRateSettings {
+                enabled: true,
+                target_blocks: 6,
+                min_fee_rate: 1,
+                max_fee_rate: 80,
+                default_fee_rate: 20,
+            }
+        );
+
+        for (toml, err) in [
+            (
+                "fee_rate_target_blocks = 0",
+                "burnchain.fee_rate_target_blocks must be between 1 and 1008",
+            ),
+            (
+                "fee_rate_min = 0",
+                "burnchain.fee_rate_min must be at least 1",
+            ),
+            (
+                "fee_rate_min = 10\nfee_rate_max = 5",
+                "burnchain.fee_rate_max must be at least burnchain.fee_rate_min",
+            ),
+        ] {
+            assert_eq!(
+                Config::from_config_file(
+                    ConfigFile::from_str(&format!("[burnchain]\n{}", toml)).unwrap()
+                )
+                .unwrap_err(),
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_commit_strategy() {
+        let config = Config::from_config_file(ConfigFile::from_str("").unwrap()).unwrap();
+        assert_eq!(config.miner.commit_strategy, CommitStrategyName::Fixed);
+        assert_eq!(config.miner.commit_budget, None);
+
+        let config = Config::from_config_file(
+            ConfigFile::from_str(
+                r#"
+                [miner]
+                commit_strategy = "median"
+                commit_budget = 1000000
+                stx_price_sats = 2500.5
+                stx_price_file = "/tmp/stx-price"
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            config.miner.commit_strategy,
+            CommitStrategyName::MedianTracking
+        );
+        assert_eq!(config.miner.commit_budget, Some(1000000));
+        assert_eq!(config.miner.stx_price_sats, Some(2500.5));
+        assert_eq!(
+            config.miner.stx_price_file,
+            Some("/tmp/stx-price".to_string())
+        );
+
+        assert!(Config::from_config_file(
+            ConfigFile::from_str(
+                r#"
+                [miner]
+                commit_strategy = "yolo"
+                "#,
+            )
+            .unwrap()
+        )
+        .is_err());
+        assert_eq!(
+            Config::from_config_file(
+                ConfigFile::from_str(
+                    r#"
+                    [miner]
+                    commit_budget = 0
+                    "#,
+                )
+                .unwrap()
+            )
+            .unwrap_err(),
+            "miner.commit_budget must be at least 1"
+        );
+        assert_eq!(
+            Config::from_config_file(
+                ConfigFile::from_str(
+                    r#"
+                    [miner]
+                    stx_price_sats = -1.0
+                    "#,
+                )
+                .unwrap()
+            )
+            .unwrap_err(),
+            "miner.stx_price_sats must be positive"
+        );
+    }
+
+    #[test]
+    fn test_config_rpc_rate_limits() {
+        let config = Config::from_config_file(
+            ConfigFile::from_str(
+                r#"
+                [connection_options]
+                rpc_rate_limit_cheap = 600
+                rpc_rate_limit_expensive = 60
+                rpc_api_keys = ["key-1", "key-2"]
+                rpc_api_key_rate_limit_expensive = 6000
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let opts = &config.connection_options;
+        assert_eq!(opts.rpc_rate_limit_cheap, 600);
+        assert_eq!(opts.rpc_rate_limit_expensive, 60);
+        assert_eq!(opts.rpc_api_keys.len(), 2);
+        assert!(opts.rpc_api_keys.contains("key-1"));
+        assert_eq!(opts.rpc_api_key_rate_limit_cheap, 0);
+        assert_eq!(opts.rpc_api_key_rate_limit_expensive, 6000);
+    }
+
+    #[test]
+    fn test_config_bandwidth_budgets() {
+        let config = Config::from_config_file(
+            ConfigFile::from_str(
+                r#"
+                [connection_options]
+                max_upload_bandwidth = 1000000
+                max_peer_download_bandwidth = 250000
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let opts = &config.connection_options;
+        assert_eq!(opts.max_upload_bandwidth, 1000000);
+        assert_eq!(opts.max_download_bandwidth, 0);
+        assert_eq!(opts.max_peer_upload_bandwidth, 0);
+        assert_eq!(opts.max_peer_download_bandwidth, 250000);
+    }
+
     #[test]
     fn should_load_legacy_mstx_balances_toml() {
         let config = ConfigFile::from_str(
@@ -358,6 +749,8 @@ pub struct Config {
     pub node: NodeConfig,
     pub initial_balances: Vec<InitialBalance>,
     pub events_observers: Vec<EventObserverConfig>,
+    /// SOCKS5 proxy through which to reach the event observers, if any
+    pub events_observers_socks5_proxy: Option<Socks5Proxy>,
     pub connection_options: ConnectionOptions,
     pub miner: MinerConfig,
     pub estimation: FeeEstimationConfig,
@@ -412,6 +805,7 @@ impl Config {
                     p2p_bind: node.p2p_bind.unwrap_or(default_node_config.p2p_bind),
                     p2p_address: node.p2p_address.unwrap_or(rpc_bind.clone()),
                     bootstrap_node: vec![],
+                    bootstrap_node_hostnames: HashMap::new(),
                     deny_nodes: vec![],
                     data_url: match node.data_url {
                         Some(data_url) => data_url,
@@ -493,6 +887,102 @@ impl Config {
                     }
                 }
 
+                let socks5_proxy = match burnchain.socks5_proxy {
+                    Some(ref proxy) => Some(
+                        proxy
+                            .parse::<Socks5Proxy>()
+                            .map_err(|e| format!("Invalid burnchain.socks5_proxy: {}", &e))?,
+                    ),
+                    None => None,
+                };
+
+                let utxo_consolidation_max_inputs = burnchain
+                    .utxo_consolidation_max_inputs
+                    .unwrap_or(default_burnchain_config.utxo_consolidation_max_inputs);
+                if utxo_consolidation_max_inputs < 2 {
+                    return Err(format!(
+                        "burnchain.utxo_consolidation_max_inputs must be at least 2"
+                    ));
+                }
+                let utxo_consolidation_fee_rate = burnchain
+                    .utxo_consolidation_fee_rate
+                    .unwrap_or(default_burnchain_config.utxo_consolidation_fee_rate);
+                if utxo_consolidation_fee_rate == 0 {
+                    return Err(format!(
+                        "burnchain.utxo_consolidation_fee_rate must be at least 1"
+                    ));
+                }
+
+                let fee_rate_target_blocks = burnchain
+                    .fee_rate_target_blocks
+                    .unwrap_or(default_burnchain_config.fee_rate_target_blocks);
+                if !(1..=1008).contains(&fee_rate_target_blocks) {
+                    return Err(format!(
+                        "burnchain.fee_rate_target_blocks must be between 1 and 1008"
+                    ));
+                }
+                let fee_rate_min = burnchain
+                    .fee_rate_min
+                    .unwrap_or(default_burnchain_config.fee_rate_min);
+                let fee_rate_max = burnchain
+                    .fee_rate_max
+                    .unwrap_or(default_burnchain_config.fee_rate_max);
+                if fee_rate_min == 0 {
+                    return Err(format!("burnchain.fee_rate_min must be at least 1"));
+                }
+                if fee_rate_max < fee_rate_min {
+                    return Err(format!(
+                        "burnchain.fee_rate_max must be at least burnchain.fee_rate_min"
+                    ));
+                }
+
+                let signer_public_key = match burnchain.signer_public_key {
+                    Some(ref hex) => Some(
+                        parse_signer_public_key(hex)
+                            .map_err(|e| format!("Invalid burnchain.signer_public_key: {}", &e))?,
+                    ),
+                    None => None,
+                };
+                let signer = match (
+                    burnchain.signer_command,
+                    burnchain.signer_url,
+                    signer_public_key,
+                ) {
+                    (None, None, None) => BurnchainSignerConfig::Local,
+                    (None, None, Some(_)) => {
+                        return Err(format!(
+                            "burnchain.signer_public_key requires burnchain.signer_command or burnchain.signer_url"
+                        ));
+                    }
+                    (Some(_), Some(_), _) => {
+                        return Err(format!(
+                            "burnchain.signer_command and burnchain.signer_url are mutually exclusive"
+                        ));
+                    }
+                    (_, _, None) => {
+                        return Err(format!(
+                            "An external burnchain signer requires burnchain.signer_public_key"
+                        ));
+                    }
+                    (Some(command), None, Some(public_key)) => {
+                        if command.is_empty() {
+                            return Err(format!("burnchain.signer_command cannot be empty"));
+                        }
+                        BurnchainSignerConfig::Command {
+                            command,
+                            public_key,
+                        }
+                    }
+                    (None, Some(url), Some(public_key)) => {
+                        if !url.starts_with("http://") {
+                            return Err(format!(
+                                "Invalid burnchain.signer_url: only http:// URLs are supported"
+                            ));
+                        }
+                        BurnchainSignerConfig::Http { url, public_key }
+                    }
+                };
+
                 BurnchainConfig {
                     chain: burnchain.chain.unwrap_or(default_burnchain_config.chain),
                     chain_id: if &burnchain_mode == "mainnet" {
@@ -513,6 +1003,10 @@ impl Config {
                         .commit_anchor_block_within
                         .unwrap_or(default_burnchain_config.commit_anchor_block_within),
                     peer_host: match burnchain.peer_host {
+                        Some(peer_host) if socks5_proxy.is_some() => {
+                            // the proxy resolves it
+                            peer_host
+                        }
                         Some(peer_host) => {
                             // Using std::net::LookupHost would be preferable, but it's
                             // unfortunately unstable at this point.
@@ -580,6 +1074,24 @@ impl Config {
                         None => default_burnchain_config.epochs,
                     },
                     ast_precheck_size_height: burnchain.ast_precheck_size_height,
+                    socks5_proxy,
+                    signer,
+                    utxo_consolidation_threshold: burnchain
+                        .utxo_consolidation_threshold
+                        .unwrap_or(default_burnchain_config.utxo_consolidation_threshold),
+                    utxo_consolidation_interval: burnchain
+                        .utxo_consolidation_interval
+                        .unwrap_or(default_burnchain_config.utxo_consolidation_interval),
+                    utxo_consolidation_fee_rate,
+                    utxo_consolidation_max_inputs,
+                    fee_rate_estimation: burnchain
+                        .fee_rate_estimation
+                        .unwrap_or(default_burnchain_config.fee_rate_estimation),
+                    fee_rate_target_blocks,
+                    fee_rate_min,
+                    fee_rate_max,
+                    spv_headers_bundle: burnchain.spv_headers_bundle.clone(),
+                    blocks_dir: burnchain.blocks_dir.clone(),
                 }
             }
             None => default_burnchain_config,
@@ -587,28 +1099,46 @@ impl Config {
 
         let miner_default_config = MinerConfig::default();
         let miner = match config_file.miner {
-            Some(ref miner) => MinerConfig {
-                min_tx_fee: miner.min_tx_fee.unwrap_or(miner_default_config.min_tx_fee),
-                first_attempt_time_ms: miner
-                    .first_attempt_time_ms
-                    .unwrap_or(miner_default_config.first_attempt_time_ms),
-                subsequent_attempt_time_ms: miner
-                    .subsequent_attempt_time_ms
-                    .unwrap_or(miner_default_config.subsequent_attempt_time_ms),
-                microblock_attempt_time_ms: miner
-                    .microblock_attempt_time_ms
-                    .unwrap_or(miner_default_config.microblock_attempt_time_ms),
-                probability_pick_no_estimate_tx: miner
-                    .probability_pick_no_estimate_tx
-                    .unwrap_or(miner_default_config.probability_pick_no_estimate_tx),
-                wait_for_block_download: miner_default_config.wait_for_block_download,
-                nonce_cache_size: miner
-                    .nonce_cache_size
-                    .unwrap_or(miner_default_config.nonce_cache_size),
-                candidate_retry_cache_size: miner
-                    .candidate_retry_cache_size
-                    .unwrap_or(miner_default_config.candidate_retry_cache_size),
-            },
+            Some(ref miner) => {
+                let commit_strategy = match miner.commit_strategy {
+                    Some(ref name) => CommitStrategyName::parse(name)?,
+                    None => miner_default_config.commit_strategy.clone(),
+                };
+                if miner.commit_budget == Some(0) {
+                    return Err(format!("miner.commit_budget must be at least 1"));
+                }
+                if let Some(price) = miner.stx_price_sats {
+                    if !(price.is_finite() && price > 0.0) {
+                        return Err(format!("miner.stx_price_sats must be positive"));
+                    }
+                }
+                MinerConfig {
+                    min_tx_fee: miner.min_tx_fee.unwrap_or(miner_default_config.min_tx_fee),
+                    first_attempt_time_ms: miner
+                        .first_attempt_time_ms
+                        .unwrap_or(miner_default_config.first_attempt_time_ms),
+                    subsequent_attempt_time_ms: miner
+                        .subsequent_attempt_time_ms
+                        .unwrap_or(miner_default_config.subsequent_attempt_time_ms),
+                    microblock_attempt_time_ms: miner
+                        .microblock_attempt_time_ms
+                        .unwrap_or(miner_default_config.microblock_attempt_time_ms),
+                    probability_pick_no_estimate_tx: miner
+                        .probability_pick_no_estimate_tx
+                        .unwrap_or(miner_default_config.probability_pick_no_estimate_tx),
+                    wait_for_block_download: miner_default_config.wait_for_block_download,
+                    nonce_cache_size: miner
+                        .nonce_cache_size
+                        .unwrap_or(miner_default_config.nonce_cache_size),
+                    candidate_retry_cache_size: miner
+                        .candidate_retry_cache_size
+                        .unwrap_or(miner_default_config.candidate_retry_cache_size),
+                    commit_strategy,
+                    commit_budget: miner.commit_budget,
+                    stx_price_sats: miner.stx_price_sats,
+                    stx_price_file: miner.stx_price_file.clone(),
+                }
+            }
             None => miner_default_config,
         };
 
@@ -691,8 +1221,22 @@ impl Config {
             _ => (),
         };
 
-        let connection_options = match config_file.connection_options {
+        let mut events_observers_socks5_proxy = None;
+        let mut connection_options = match config_file.connection_options {
             Some(opts) => {
+                let socks5_proxy =
+                    match opts.socks5_proxy {
+                        Some(ref proxy) => Some(proxy.parse::<Socks5Proxy>().map_err(|e| {
+                            format!("Invalid connection_options.socks5_proxy: {}", &e)
+                        })?),
+                        None => None,
+                    };
+                if opts.socks5_proxy_event_observers.unwrap_or(false) {
+                    if socks5_proxy.is_none() {
+                        return Err(format!("connection_options.socks5_proxy_event_observers requires connection_options.socks5_proxy"));
+                    }
+                    events_observers_socks5_proxy = socks5_proxy.clone();
+                }
                 let ip_addr = match opts.public_ip_address {
                     Some(public_ip_address) => {
                         let addr = public_ip_address.parse::<SocketAddr>().unwrap();
@@ -835,12 +1379,33 @@ impl Config {
                     handshake_timeout: opts.connect_timeout.unwrap_or(5),
                     max_sockets: opts.max_sockets.unwrap_or(800) as usize,
                     antientropy_public: opts.antientropy_public.unwrap_or(true),
+                    p2p_encryption: opts.p2p_encryption.unwrap_or(false),
+                    max_event_streams: opts.max_event_streams.unwrap_or(0),
+                    rpc_rate_limit_cheap: opts.rpc_rate_limit_cheap.unwrap_or(0),
+                    rpc_rate_limit_expensive: opts.rpc_rate_limit_expensive.unwrap_or(0),
+                    rpc_api_keys: opts.rpc_api_keys.unwrap_or_default().into_iter().collect(),
+                    rpc_api_key_rate_limit_cheap: opts.rpc_api_key_rate_limit_cheap.unwrap_or(0),
+                    rpc_api_key_rate_limit_expensive: opts
+                        .rpc_api_key_rate_limit_expensive
+                        .unwrap_or(0),
+                    max_upload_bandwidth: opts.max_upload_bandwidth.unwrap_or(0),
+                    max_download_bandwidth: opts.max_download_bandwidth.unwrap_or(0),
+                    max_peer_upload_bandwidth: opts.max_peer_upload_bandwidth.unwrap_or(0),
+                    max_peer_download_bandwidth: opts.max_peer_download_bandwidth.unwrap_or(0),
+                    socks5_proxy,
                     ..ConnectionOptions::default()
                 }
             }
             None => HELIUM_DEFAULT_CONNECTION_OPTIONS.clone(),
         };
 
+        if node.bootstrap_node_hostnames.len() > 0 && connection_options.socks5_proxy.is_none() {
+            return Err(format!(
+                "Bootstrap nodes with .onion addresses require connection_options.socks5_proxy"
+            ));
+        }
+        connection_options.socks5_hostnames = node.bootstrap_node_hostnames.clone();
+
         let estimation = match config_file.fee_estimation {
             Some(f) => FeeEstimationConfig::from(f),
             None => FeeEstimationConfig::default(),
@@ -851,6 +1416,7 @@ impl Config {
             burnchain,
             initial_balances,
             events_observers,
+            events_observers_socks5_proxy,
             connection_options,
             estimation,
             miner,
@@ -1011,6 +1577,7 @@ impl std::default::Default for Config {
             node,
             initial_balances: vec![],
             events_observers: vec![],
+            events_observers_socks5_proxy: None,
             connection_options,
             estimation,
             miner: MinerConfig::default(),
@@ -1046,6 +1613,66 @@ pub struct BurnchainConfig {
     /// regtest nodes.
     pub epochs: Option<Vec<StacksEpoch>>,
     pub ast_precheck_size_height: Option<u64>,
+    /// SOCKS5 proxy through which to connect to bitcoind's P2P and RPC ports
+    #[serde(skip)]
+    pub socks5_proxy: Option<Socks5Proxy>,
+    /// What signs the miner's block-commits and leader key registrations
+    #[serde(skip)]
+    pub signer: BurnchainSignerConfig,
+    /// Sweep the miner's UTXOs into one once there are more than this many.  0 disables it.
+    pub utxo_consolidation_threshold: u64,
+    /// Minimum number of burnchain blocks between UTXO consolidations
+    pub utxo_consolidation_interval: u64,
+    /// Fee rate, in sats/vbyte, for UTXO consolidation transactions
+    pub utxo_consolidation_fee_rate: u64,
+    /// Most UTXOs to spend in one consolidation transaction
+    pub utxo_consolidation_max_inputs: u64,
+    /// Ask bitcoind for fee rates instead of always paying `satoshis_per_byte`
+    pub fee_rate_estimation: bool,
+    /// Confirmation target, in blocks, for bitcoind's fee estimates
+    pub fee_rate_target_blocks: u64,
+    /// Lowest fee rate, in sats/vbyte, to take from bitcoind's estimates
+    pub fee_rate_min: u64,
+    /// Highest fee rate, in sats/vbyte, to take from bitcoind's estimates or pay in an RBF
+    pub fee_rate_max: u64,
+    /// Bundle of pre-validated bitcoin headers to load on first sync
+    pub spv_headers_bundle: Option<String>,
+    /// Read burnchain blocks from the block files in this directory instead of the bitcoin peer
+    pub blocks_dir: Option<String>,
+}
+
+/// Where the miner's burnchain transactions get signed
+#[derive(Clone, Debug, PartialEq)]
+pub enum BurnchainSignerConfig {
+    /// With the key derived from the node seed
+    Local,
+    /// By running this command for each transaction
+    Command {
+        command: Vec<String>,
+        public_key: Secp256k1PublicKey,
+    },
+    /// By POSTing each transaction to this URL
+    Http {
+        url: String,
+        public_key: Secp256k1PublicKey,
+    },
+}
+
+impl Default for BurnchainSignerConfig {
+    fn default() -> BurnchainSignerConfig {
+        BurnchainSignerConfig::Local
+    }
+}
+
+impl BurnchainSignerConfig {
+    /// The public key of an external signer, if there is one
+    pub fn public_key(&self) -> Option<&Secp256k1PublicKey> {
+        match self {
+            BurnchainSignerConfig::Local => None,
+            BurnchainSignerConfig::Command { public_key, .. }
+            | BurnchainSignerConfig::Http { public_key, .. } => Some(public_key),
+        }
+    }
 }
 
 impl BurnchainConfig {
@@ -1075,6 +1702,37 @@ impl BurnchainConfig {
             rbf_fee_increment: DEFAULT_RBF_FEE_RATE_INCREMENT,
             epochs: None,
             ast_precheck_size_height: None,
+            socks5_proxy: None,
+            signer: BurnchainSignerConfig::Local,
+            utxo_consolidation_threshold: 0,
+            utxo_consolidation_interval: DEFAULT_UTXO_CONSOLIDATION_INTERVAL,
+            utxo_consolidation_fee_rate: DEFAULT_UTXO_CONSOLIDATION_FEE_RATE,
+            utxo_consolidation_max_inputs: DEFAULT_UTXO_CONSOLIDATION_MAX_INPUTS,
+            fee_rate_estimation: false,
+            fee_rate_target_blocks: DEFAULT_FEE_RATE_TARGET_BLOCKS,
+            fee_rate_min: DEFAULT_FEE_RATE_MIN,
+            fee_rate_max: DEFAULT_FEE_RATE_MAX,
+            spv_headers_bundle: None,
+            blocks_dir: None,
+        }
+    }
+
+    pub fn get_utxo_consolidation_settings(&self) -> ConsolidationSettings {
+        ConsolidationSettings {
+            threshold: self.utxo_consolidation_threshold,
+            interval: self.utxo_consolidation_interval,
+            fee_rate: self.utxo_consolidation_fee_rate,
+            max_inputs: self.utxo_consolidation_max_inputs,
+        }
+    }
+
+    pub fn get_fee_rate_settings(&self) -> FeeRateSettings {
+        FeeRateSettings {
+            enabled: self.fee_rate_estimation,
+            target_blocks: self.fee_rate_target_blocks,
+            min_fee_rate: self.fee_rate_min,
+            max_fee_rate: self.fee_rate_max,
+            default_fee_rate: self.satoshis_per_byte,
         }
     }
 
@@ -1130,6 +1788,25 @@ pub struct BurnchainConfigFile {
     pub max_rbf: Option<u64>,
     pub epochs: Option<Vec<StacksEpoch>>,
     pub ast_precheck_size_height: Option<u64>,
+    pub socks5_proxy: Option<String>,
+    /// Program and arguments to run to sign burnchain transactions
+    pub signer_command: Option<Vec<String>>,
+    /// URL of a service that signs burnchain transactions
+    pub signer_url: Option<String>,
+    /// Hex-encoded public key that the external signer signs with
+    pub signer_public_key: Option<String>,
+    pub utxo_consolidation_threshold: Option<u64>,
+    pub utxo_consolidation_interval: Option<u64>,
+    pub utxo_consolidation_fee_rate: Option<u64>,
+    pub utxo_consolidation_max_inputs: Option<u64>,
+    pub fee_rate_estimation: Option<bool>,
+    pub fee_rate_target_blocks: Option<u64>,
+    pub fee_rate_min: Option<u64>,
+    pub fee_rate_max: Option<u64>,
+    /// Path to a headers bundle made with `stacks-inspect export-spv-headers`
+    pub spv_headers_bundle: Option<String>,
+    /// A bitcoind `blocks/` directory, or a directory of serialized blocks
+    pub blocks_dir: Option<String>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -1143,6 +1820,9 @@ pub struct NodeConfig {
     pub p2p_address: String,
     pub local_peer_seed: Vec<u8>,
     pub bootstrap_node: Vec<Neighbor>,
+    /// Hostnames of bootstrap nodes that can only be reached through the SOCKS5 proxy (i.e.
+    /// `.onion` addresses), keyed by the placeholder address they are known by
+    pub bootstrap_node_hostnames: HashMap<PeerAddress, String>,
     pub deny_nodes: Vec<Neighbor>,
     pub miner: bool,
     pub mock_mining: bool,
@@ -1174,6 +1854,31 @@ pub enum CostMetricName {
     ProportionDotProduct,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommitStrategyName {
+    Fixed,
+    MedianTracking,
+}
+
+impl Default for CommitStrategyName {
+    fn default() -> Self {
+        CommitStrategyName::Fixed
+    }
+}
+
+impl CommitStrategyName {
+    fn parse(s: &str) -> Result<CommitStrategyName, String> {
+        match s.to_lowercase().as_str() {
+            "fixed" => Ok(CommitStrategyName::Fixed),
+            "median" => Ok(CommitStrategyName::MedianTracking),
+            _ => Err(format!(
+                "Bad miner.commit_strategy supplied in configuration file: {} (should be: fixed, median)",
+                s
+            )),
+        }
+    }
+}
+
 impl Default for CostEstimatorName {
     fn default() -> Self {
         CostEstimatorName::NaivePessimistic
@@ -1416,6 +2121,7 @@ impl NodeConfig {
             data_url: format!("http://127.0.0.1:{}", rpc_port),
             p2p_address: format!("127.0.0.1:{}", rpc_port),
             bootstrap_node: vec![],
+            bootstrap_node_hostnames: HashMap::new(),
             deny_nodes: vec![],
             local_peer_seed: local_peer_seed.to_vec(),
             miner: false,
@@ -1469,8 +2175,22 @@ impl NodeConfig {
         let (pubkey_str, hostport) = (parts[0], parts[1]);
         let pubkey = Secp256k1PublicKey::from_hex(pubkey_str)
             .expect(&format!("Invalid public key '{}'", pubkey_str));
-        debug!("Resolve '{}'", &hostport);
-        let sockaddr = hostport.to_socket_addrs().unwrap().next().unwrap();
+        let sockaddr = match hostport.rsplit_once(':') {
+            Some((host, port)) if is_onion_hostname(host) => {
+                // can't be resolved; the SOCKS5 proxy will connect to it by name
+                let port = port
+                    .parse::<u16>()
+                    .expect(&format!("Invalid port in bootstrap node '{}'", hostport));
+                let addrbytes = hostname_peer_address(host);
+                self.bootstrap_node_hostnames
+                    .insert(addrbytes.clone(), host.to_string());
+                addrbytes.to_socketaddr(port)
+            }
+            _ => {
+                debug!("Resolve '{}'", &hostport);
+                hostport.to_socket_addrs().unwrap().next().unwrap()
+            }
+        };
         let neighbor = NodeConfig::default_neighbor(sockaddr, pubkey, chain_id, peer_version);
         self.bootstrap_node.push(neighbor);
     }
@@ -1539,6 +2259,15 @@ pub struct MinerConfig {
     pub wait_for_block_download: bool,
     pub nonce_cache_size: u64,
     pub candidate_retry_cache_size: u64,
+    /// How to size each block-commit
+    pub commit_strategy: CommitStrategyName,
+    /// If set, the most sats the miner will commit in total since the node started
+    pub commit_budget: Option<u64>,
+    /// Price of 1 STX in sats, used to weigh commits against the block reward
+    pub stx_price_sats: Option<f64>,
+    /// File holding the price of 1 STX in sats, re-read before each tenure.  Overrides
+    /// `stx_price_sats` when it can be read.
+    pub stx_price_file: Option<String>,
 }
 
 impl MinerConfig {
@@ -1552,6 +2281,10 @@ impl MinerConfig {
             wait_for_block_download: true,
             nonce_cache_size: 10_000,
             candidate_retry_cache_size: 10_000,
+            commit_strategy: CommitStrategyName::default(),
+            commit_budget: None,
+            stx_price_sats: None,
+            stx_price_file: None,
         }
     }
 }
@@ -1597,6 +2330,19 @@ pub struct ConnectionOptionsFile {
     pub disable_block_download: Option<bool>,
     pub force_disconnect_interval: Option<u64>,
     pub antientropy_public: Option<bool>,
+    pub p2p_encryption: Option<bool>,
+    pub max_event_streams: Option<u64>,
+    pub rpc_rate_limit_cheap: Option<u64>,
+    pub rpc_rate_limit_expensive: Option<u64>,
+    pub rpc_api_keys: Option<Vec<String>>,
+    pub rpc_api_key_rate_limit_cheap: Option<u64>,
+    pub rpc_api_key_rate_limit_expensive: Option<u64>,
+    pub max_upload_bandwidth: Option<u64>,
+    pub max_download_bandwidth: Option<u64>,
+    pub max_peer_upload_bandwidth: Option<u64>,
+    pub max_peer_download_bandwidth: Option<u64>,
+    pub socks5_proxy: Option<String>,
+    pub socks5_proxy_event_observers: Option<bool>,
 }
 
 #[derive(Clone, Deserialize, Default, Debug)]
@@ -1659,6 +2405,10 @@ pub struct MinerConfigFile {
     pub probability_pick_no_estimate_tx: Option<u8>,
     pub nonce_cache_size: Option<u64>,
     pub candidate_retry_cache_size: Option<u64>,
+    pub commit_strategy: Option<String>,
+    pub commit_budget: Option<u64>,
+    pub stx_price_sats: Option<f64>,
+    pub stx_price_file: Option<String>,
 }
 
 #[derive(Clone, Deserialize, Default, Debug)]
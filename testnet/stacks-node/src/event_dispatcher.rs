@@ -13,7 +13,7 @@ use serde_json::json;
 
 use stacks::burnchains::Txid;
 use stacks::chainstate::coordinator::BlockEventDispatcher;
-use stacks::chainstate::stacks::address::StacksAddressExtensions;
+use stacks::chainstate::stacks::address::PoxAddress;
 use stacks::chainstate::stacks::db::StacksHeaderInfo;
 use stacks::chainstate::stacks::events::{
     StacksTransactionEvent, StacksTransactionReceipt, TransactionOrigin,
@@ -25,9 +25,9 @@ use stacks::chainstate::stacks::{StacksBlock, StacksMicroblock};
 use stacks::codec::StacksMessageCodec;
 use stacks::core::mempool::{MemPoolDropReason, MemPoolEventDispatcher};
 use stacks::net::atlas::{Attachment, AttachmentInstance};
-use stacks::types::chainstate::{
-    BlockHeaderHash, BurnchainHeaderHash, StacksAddress, StacksBlockId,
-};
+use stacks::net::eventstream::{EventStreamHub, EventStreamKind};
+use stacks::net::socks::{socks5_connect, Socks5Proxy, Socks5Target};
+use stacks::types::chainstate::{BlockHeaderHash, BurnchainHeaderHash, StacksBlockId};
 use stacks::util::hash::bytes_to_hex;
 use stacks::vm::analysis::contract_interface_builder::build_contract_interface;
 use stacks::vm::costs::ExecutionCost;
@@ -43,6 +43,7 @@ use stacks::chainstate::stacks::TransactionPayload;
 #[derive(Debug, Clone)]
 struct EventObserver {
     endpoint: String,
+    socks5_proxy: Option<Socks5Proxy>,
 }
 
 struct ReceiptPayloadInfo<'a> {
@@ -67,6 +68,9 @@ pub const PATH_BURN_BLOCK_SUBMIT: &str = "new_burn_block";
 pub const PATH_BLOCK_PROCESSED: &str = "new_block";
 pub const PATH_ATTACHMENT_PROCESSED: &str = "attachments/new";
 
+/// How long to wait on the SOCKS5 proxy when connecting to an observer through it
+const EVENT_OBSERVER_PROXY_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MinedBlockEvent {
     pub target_burn_height: u64,
@@ -88,6 +92,29 @@ pub struct MinedMicroblockEvent {
 }
 
 impl EventObserver {
+    /// Connect to the observer, through the SOCKS5 proxy if there is one
+    async fn connect(&self) -> Result<TcpStream, String> {
+        match self.socks5_proxy {
+            Some(ref proxy) => {
+                let target = self
+                    .endpoint
+                    .rsplit_once(':')
+                    .and_then(|(host, port)| {
+                        port.parse::<u16>()
+                            .ok()
+                            .map(|port| Socks5Target::from_host_port(host, port))
+                    })
+                    .ok_or_else(|| format!("invalid endpoint '{}'", &self.endpoint))?;
+                socks5_connect(proxy, &target, EVENT_OBSERVER_PROXY_TIMEOUT)
+                    .map(TcpStream::from)
+                    .map_err(|e| format!("{:?}", &e))
+            }
+            None => TcpStream::connect(self.endpoint.clone())
+                .await
+                .map_err(|e| format!("{:?}", &e)),
+        }
+    }
+
     fn send_payload(&self, payload: &serde_json::Value, path: &str) {
         let body = match serde_json::to_vec(&payload) {
             Ok(body) => body,
@@ -118,10 +145,10 @@ impl EventObserver {
             req.set_body(body);
 
             let response = async_std::task::block_on(async {
-                let stream = match TcpStream::connect(self.endpoint.clone()).await {
+                let stream = match self.connect().await {
                     Ok(stream) => stream,
                     Err(err) => {
-                        warn!("Event dispatcher: connection failed  - {:?}", err);
+                        warn!("Event dispatcher: connection failed  - {}", err);
                         return None;
                     }
                 };
@@ -165,15 +192,15 @@ impl EventObserver {
     fn make_new_burn_block_payload(
         burn_block: &BurnchainHeaderHash,
         burn_block_height: u64,
-        rewards: Vec<(StacksAddress, u64)>,
+        rewards: Vec<(PoxAddress, u64)>,
         burns: u64,
-        slot_holders: Vec<StacksAddress>,
+        slot_holders: Vec<PoxAddress>,
     ) -> serde_json::Value {
         let reward_recipients = rewards
             .into_iter()
-            .map(|(stx_addr, amt)| {
+            .map(|(pox_addr, amt)| {
                 json!({
-                    "recipient": stx_addr.to_b58(),
+                    "recipient": pox_addr.to_burnchain_repr(),
                     "amt": amt,
                 })
             })
@@ -181,7 +208,7 @@ impl EventObserver {
 
         let reward_slot_holders = slot_holders
             .into_iter()
-            .map(|stx_addr| json!(stx_addr.to_b58()))
+            .map(|pox_addr| json!(pox_addr.to_burnchain_repr()))
             .collect();
 
         json!({
@@ -291,16 +318,15 @@ impl EventObserver {
         self.send_payload(payload, PATH_MEMPOOL_TX_SUBMIT);
     }
 
-    /// Serializes new microblocks data into a JSON payload and sends it off to the correct path
-    fn send_new_microblocks(
-        &self,
+    /// Serializes new microblocks data into a JSON payload
+    fn make_new_microblocks_payload(
         parent_index_block_hash: StacksBlockId,
         filtered_events: Vec<(usize, &(bool, Txid, &StacksTransactionEvent))>,
         serialized_txs: &Vec<serde_json::Value>,
         burn_block_hash: BurnchainHeaderHash,
         burn_block_height: u32,
         burn_block_timestamp: u64,
-    ) {
+    ) -> serde_json::Value {
         // Serialize events to JSON
         let serialized_events: Vec<serde_json::Value> = filtered_events
             .iter()
@@ -309,16 +335,18 @@ impl EventObserver {
             })
             .collect();
 
-        let payload = json!({
+        json!({
             "parent_index_block_hash": format!("0x{}", parent_index_block_hash),
             "events": serialized_events,
             "transactions": serialized_txs,
             "burn_block_hash": format!("0x{}", burn_block_hash),
             "burn_block_height": burn_block_height,
             "burn_block_timestamp": burn_block_timestamp,
-        });
+        })
+    }
 
-        self.send_payload(&payload, PATH_MICROBLOCK_SUBMIT);
+    fn send_new_microblocks(&self, payload: &serde_json::Value) {
+        self.send_payload(payload, PATH_MICROBLOCK_SUBMIT);
     }
 
     fn send_dropped_mempool_txs(&self, payload: &serde_json::Value) {
@@ -337,8 +365,8 @@ impl EventObserver {
         self.send_payload(payload, PATH_BURN_BLOCK_SUBMIT);
     }
 
-    fn send(
-        &self,
+    /// Serializes a processed block, its receipts and the given events into a JSON payload
+    fn make_new_block_processed_payload(
         filtered_events: Vec<(usize, &(bool, Txid, &StacksTransactionEvent))>,
         block: &StacksBlock,
         metadata: &StacksHeaderInfo,
@@ -352,7 +380,7 @@ impl EventObserver {
         parent_burn_block_timestamp: u64,
         anchored_consumed: &ExecutionCost,
         mblock_confirmed_consumed: &ExecutionCost,
-    ) {
+    ) -> serde_json::Value {
         // Serialize events to JSON
         let serialized_events: Vec<serde_json::Value> = filtered_events
             .iter()
@@ -371,7 +399,7 @@ impl EventObserver {
         }
 
         // Wrap events
-        let payload = json!({
+        json!({
             "block_hash": format!("0x{}", block.block_hash()),
             "block_height": metadata.stacks_block_height,
             "burn_block_hash": format!("0x{}", metadata.burn_header_hash),
@@ -391,10 +419,11 @@ impl EventObserver {
             "parent_burn_block_timestamp": parent_burn_block_timestamp,
             "anchored_cost": anchored_consumed,
             "confirmed_microblocks_cost": mblock_confirmed_consumed,
-        });
+        })
+    }
 
-        // Send payload
-        self.send_payload(&payload, PATH_BLOCK_PROCESSED);
+    fn send_new_block_processed(&self, payload: &serde_json::Value) {
+        self.send_payload(payload, PATH_BLOCK_PROCESSED);
     }
 }
 
@@ -411,6 +440,9 @@ pub struct EventDispatcher {
     miner_observers_lookup: HashSet<u16>,
     mined_microblocks_observers_lookup: HashSet<u16>,
     boot_receipts: Arc<Mutex<Option<Vec<StacksTransactionReceipt>>>>,
+    /// if set, block, microblock and mempool events are also published to the RPC server's
+    /// `/v2/events/...` subscribers
+    event_stream_hub: Option<EventStreamHub>,
 }
 
 impl MemPoolEventDispatcher for EventDispatcher {
@@ -491,9 +523,9 @@ impl BlockEventDispatcher for EventDispatcher {
         &self,
         burn_block: &BurnchainHeaderHash,
         burn_block_height: u64,
-        rewards: Vec<(StacksAddress, u64)>,
+        rewards: Vec<(PoxAddress, u64)>,
         burns: u64,
-        recipient_info: Vec<StacksAddress>,
+        recipient_info: Vec<PoxAddress>,
     ) {
         self.process_burn_block(
             burn_block,
@@ -523,16 +555,33 @@ impl EventDispatcher {
             boot_receipts: Arc::new(Mutex::new(None)),
             miner_observers_lookup: HashSet::new(),
             mined_microblocks_observers_lookup: HashSet::new(),
+            event_stream_hub: None,
         }
     }
 
+    /// Publish events to the RPC server's event streams through this hub
+    pub fn register_event_stream_hub(&mut self, hub: EventStreamHub) {
+        self.event_stream_hub = Some(hub);
+    }
+
+    pub fn get_event_stream_hub(&self) -> Option<EventStreamHub> {
+        self.event_stream_hub.clone()
+    }
+
+    /// Get the event stream hub, but only if someone is listening to it
+    fn active_event_stream_hub(&self) -> Option<&EventStreamHub> {
+        self.event_stream_hub
+            .as_ref()
+            .filter(|hub| hub.has_subscribers())
+    }
+
     pub fn process_burn_block(
         &self,
         burn_block: &BurnchainHeaderHash,
         burn_block_height: u64,
-        rewards: Vec<(StacksAddress, u64)>,
+        rewards: Vec<(PoxAddress, u64)>,
         burns: u64,
-        recipient_info: Vec<StacksAddress>,
+        recipient_info: Vec<PoxAddress>,
     ) {
         // lazily assemble payload only if we have observers
         let interested_observers: Vec<_> = self
@@ -695,8 +744,9 @@ impl EventDispatcher {
             .collect();
 
         let (dispatch_matrix, events) = self.create_dispatch_matrix_and_event_vector(&all_receipts);
+        let event_stream_hub = self.active_event_stream_hub();
 
-        if dispatch_matrix.len() > 0 {
+        if dispatch_matrix.len() > 0 || event_stream_hub.is_some() {
             let mature_rewards_vec = if let Some(rewards_info) = mature_rewards_info {
                 mature_rewards
                     .iter()
@@ -725,7 +775,7 @@ impl EventDispatcher {
                     .map(|event_id| (*event_id, &events[*event_id]))
                     .collect();
 
-                self.registered_observers[observer_id].send(
+                let payload = EventObserver::make_new_block_processed_payload(
                     filtered_events,
                     block,
                     metadata,
@@ -740,6 +790,28 @@ impl EventDispatcher {
                     anchored_consumed,
                     mblock_confirmed_consumed,
                 );
+
+                self.registered_observers[observer_id].send_new_block_processed(&payload);
+            }
+
+            if let Some(hub) = event_stream_hub {
+                // stream subscribers get every event, and filter them by topic themselves
+                let payload = EventObserver::make_new_block_processed_payload(
+                    events.iter().enumerate().collect(),
+                    block,
+                    metadata,
+                    receipts,
+                    parent_index_hash,
+                    &boot_receipts,
+                    &winner_txid,
+                    &mature_rewards,
+                    parent_burn_block_hash,
+                    parent_burn_block_height,
+                    parent_burn_block_timestamp,
+                    anchored_consumed,
+                    mblock_confirmed_consumed,
+                );
+                hub.publish(EventStreamKind::NewBlock, payload);
             }
         }
     }
@@ -762,7 +834,8 @@ impl EventDispatcher {
                     || self.any_event_observers_lookup.contains(&(*obs_id as u16))
             })
             .collect();
-        if interested_observers.len() < 1 {
+        let event_stream_hub = self.active_event_stream_hub();
+        if interested_observers.len() < 1 && event_stream_hub.is_none() {
             return;
         }
         let flattened_receipts = processed_unconfirmed_state
@@ -793,7 +866,7 @@ impl EventDispatcher {
                 .map(|event_id| (*event_id, &events[*event_id]))
                 .collect();
 
-            observer.send_new_microblocks(
+            let payload = EventObserver::make_new_microblocks_payload(
                 parent_index_block_hash,
                 filtered_events,
                 &serialized_txs,
@@ -801,6 +874,20 @@ impl EventDispatcher {
                 processed_unconfirmed_state.burn_block_height,
                 processed_unconfirmed_state.burn_block_timestamp,
             );
+
+            observer.send_new_microblocks(&payload);
+        }
+
+        if let Some(hub) = event_stream_hub {
+            let payload = EventObserver::make_new_microblocks_payload(
+                parent_index_block_hash,
+                events.iter().enumerate().collect(),
+                &serialized_txs,
+                processed_unconfirmed_state.burn_block_hash,
+                processed_unconfirmed_state.burn_block_height,
+                processed_unconfirmed_state.burn_block_timestamp,
+            );
+            hub.publish(EventStreamKind::NewMicroblocks, payload);
         }
     }
 
@@ -815,7 +902,8 @@ impl EventDispatcher {
                     || self.any_event_observers_lookup.contains(&(*obs_id as u16))
             })
             .collect();
-        if interested_observers.len() < 1 {
+        let event_stream_hub = self.active_event_stream_hub();
+        if interested_observers.len() < 1 && event_stream_hub.is_none() {
             return;
         }
 
@@ -824,6 +912,10 @@ impl EventDispatcher {
         for (_, observer) in interested_observers.iter() {
             observer.send_new_mempool_txs(&payload);
         }
+
+        if let Some(hub) = event_stream_hub {
+            hub.publish(EventStreamKind::NewMempoolTxs, payload);
+        }
     }
 
     pub fn process_mined_block_event(
@@ -906,7 +998,8 @@ impl EventDispatcher {
                     || self.any_event_observers_lookup.contains(&(*obs_id as u16))
             })
             .collect();
-        if interested_observers.len() < 1 {
+        let event_stream_hub = self.active_event_stream_hub();
+        if interested_observers.len() < 1 && event_stream_hub.is_none() {
             return;
         }
 
@@ -923,6 +1016,10 @@ impl EventDispatcher {
         for (_, observer) in interested_observers.iter() {
             observer.send_dropped_mempool_txs(&payload);
         }
+
+        if let Some(hub) = event_stream_hub {
+            hub.publish(EventStreamKind::DropMempoolTxs, payload);
+        }
     }
 
     pub fn process_new_attachments(&self, attachments: &Vec<(AttachmentInstance, Attachment)>) {
@@ -959,10 +1056,15 @@ impl EventDispatcher {
         }
     }
 
-    pub fn register_observer(&mut self, conf: &EventObserverConfig) {
+    pub fn register_observer(
+        &mut self,
+        conf: &EventObserverConfig,
+        socks5_proxy: Option<&Socks5Proxy>,
+    ) {
         info!("Registering event observer at: {}", conf.endpoint);
         let event_observer = EventObserver {
             endpoint: conf.endpoint.clone(),
+            socks5_proxy: socks5_proxy.cloned(),
         };
 
         let observer_index = self.registered_observers.len() as u16;
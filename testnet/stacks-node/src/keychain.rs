@@ -10,6 +10,7 @@ use stacks::util::hash::{Hash160, Sha256Sum};
 use stacks::util::vrf::{VRFPrivateKey, VRFProof, VRFPublicKey, VRF};
 
 use super::operations::BurnchainOpSigner;
+use crate::config::BurnchainSignerConfig;
 
 #[derive(Clone)]
 pub struct Keychain {
@@ -20,6 +21,8 @@ pub struct Keychain {
     microblocks_secret_keys: Vec<StacksPrivateKey>,
     vrf_secret_keys: Vec<VRFPrivateKey>,
     vrf_map: HashMap<VRFPublicKey, VRFPrivateKey>,
+    /// Public key of the external signer for burnchain operations, if there is one
+    burnchain_public_key: Option<StacksPublicKey>,
 }
 
 impl Keychain {
@@ -47,9 +50,17 @@ impl Keychain {
             threshold,
             vrf_secret_keys: vec![],
             vrf_map: HashMap::new(),
+            burnchain_public_key: None,
         }
     }
 
+    /// Sign burnchain operations with the external signer in `signer`, if there is one, instead
+    /// of with this keychain's own key.
+    pub fn with_burnchain_signer(mut self, signer: &BurnchainSignerConfig) -> Keychain {
+        self.burnchain_public_key = signer.public_key().cloned();
+        self
+    }
+
     pub fn default(seed: Vec<u8>) -> Keychain {
         let mut re_hashed_seed = seed;
         let secret_key = loop {
@@ -199,6 +210,13 @@ impl Keychain {
     }
 
     pub fn get_burnchain_signer(&self) -> BurnchainSigner {
+        if let Some(ref public_key) = self.burnchain_public_key {
+            return BurnchainSigner {
+                hash_mode: AddressHashMode::SerializeP2PKH,
+                num_sigs: 1,
+                public_keys: vec![*public_key],
+            };
+        }
         let public_keys = self
             .secret_keys
             .iter()
@@ -239,6 +257,9 @@ impl Keychain {
     }
 
     pub fn generate_op_signer(&self) -> BurnchainOpSigner {
-        BurnchainOpSigner::new(self.secret_keys[0], false)
+        match self.burnchain_public_key {
+            Some(ref public_key) => BurnchainOpSigner::from_public_key(*public_key, false),
+            None => BurnchainOpSigner::new(self.secret_keys[0], false),
+        }
     }
 }
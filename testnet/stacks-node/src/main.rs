@@ -23,6 +23,7 @@ use stacks::util::hash::hex_bytes;
 pub mod monitoring;
 
 pub mod burnchains;
+pub mod commit_strategy;
 pub mod config;
 pub mod event_dispatcher;
 pub mod genesis_data;
@@ -167,11 +168,17 @@ fn main() {
             let keychain = Keychain::default(seed);
             println!(
                 "Hex formatted secret key: {}",
-                keychain.generate_op_signer().get_sk_as_hex()
+                keychain
+                    .generate_op_signer()
+                    .get_sk_as_hex()
+                    .expect("BUG: seed-derived op signer has no secret key")
             );
             println!(
                 "WIF formatted secret key: {}",
-                keychain.generate_op_signer().get_sk_as_wif()
+                keychain
+                    .generate_op_signer()
+                    .get_sk_as_wif()
+                    .expect("BUG: seed-derived op signer has no secret key")
             );
             return;
         }
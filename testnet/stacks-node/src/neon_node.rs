@@ -158,6 +158,7 @@ use stacks::chainstate::burn::BlockSnapshot;
 use stacks::chainstate::burn::ConsensusHash;
 use stacks::chainstate::coordinator::comm::CoordinatorChannels;
 use stacks::chainstate::coordinator::{get_next_recipients, OnChainRewardSetProvider};
+use stacks::chainstate::stacks::address::PoxAddress;
 use stacks::chainstate::stacks::db::unconfirmed::UnconfirmedTxMap;
 use stacks::chainstate::stacks::db::{StacksChainState, MINER_REWARD_MATURITY};
 use stacks::chainstate::stacks::Error as ChainstateError;
@@ -203,6 +204,10 @@ use stacks::{burnchains::BurnchainSigner, chainstate::stacks::db::StacksHeaderIn
 
 use crate::burnchains::bitcoin_regtest_controller::BitcoinRegtestController;
 use crate::burnchains::bitcoin_regtest_controller::OngoingBlockCommit;
+use crate::burnchains::utxo_manager::UTXOManager;
+use crate::commit_strategy::{
+    get_stx_price_sats, make_commit_strategy, CommitContext, CommitStrategy,
+};
 use crate::run_loop::neon::Counters;
 use crate::run_loop::neon::RunLoop;
 use crate::run_loop::RegisteredKey;
@@ -255,6 +260,8 @@ struct AssembledAnchorBlock {
     attempt: u64,
     /// Epoch timestamp in milliseconds when we started producing the block.
     tenure_begin: u128,
+    /// Sats committed by this block's block-commit, if it was sent
+    commit_amount: Option<u64>,
 }
 
 /// Command types for the relayer thread, issued to it by other threads
@@ -593,6 +600,8 @@ pub struct RelayerThread {
     /// if true, then the last time the miner thread was launched, it was used to mine a Stacks
     /// block (used to alternate between mining microblocks and Stacks blocks that confirm them)
     mined_stacks_block: bool,
+    /// decides how much to commit in each block-commit
+    commit_strategy: Box<dyn CommitStrategy>,
 }
 
 struct BlockMinerThread {
@@ -610,10 +619,14 @@ struct BlockMinerThread {
     last_mined_blocks: MinedBlocks,
     /// Copy of the node's last ongoing block commit from the last time this thread was run
     ongoing_commit: Option<OngoingBlockCommit>,
+    /// Handle to the miner's UTXO bookkeeping, shared with the RelayerThread
+    utxo_manager: Arc<Mutex<UTXOManager>>,
     /// Copy of the node's registered VRF key
     registered_key: RegisteredKey,
     /// Burnchain block snapshot at the time this thread was initialized
     burn_block: BlockSnapshot,
+    /// Sats to commit in this tenure's block-commit, as chosen by the relayer's commit strategy
+    commit_amount: u64,
     /// Handle to the node's event dispatcher
     event_dispatcher: EventDispatcher,
 }
@@ -1066,6 +1079,7 @@ impl BlockMinerThread {
         rt: &RelayerThread,
         registered_key: RegisteredKey,
         burn_block: BlockSnapshot,
+        commit_amount: u64,
     ) -> BlockMinerThread {
         BlockMinerThread {
             config: rt.config.clone(),
@@ -1074,8 +1088,10 @@ impl BlockMinerThread {
             burnchain: rt.burnchain.clone(),
             last_mined_blocks: rt.last_mined_blocks.clone(),
             ongoing_commit: rt.bitcoin_controller.get_ongoing_commit(),
+            utxo_manager: rt.bitcoin_controller.get_utxo_manager(),
             registered_key,
             burn_block,
+            commit_amount,
             event_dispatcher: rt.event_dispatcher.clone(),
         }
     }
@@ -1139,7 +1155,7 @@ impl BlockMinerThread {
         parent_burnchain_height: u32,
         parent_winning_vtx: u16,
         vrf_seed: VRFSeed,
-        commit_outs: Vec<StacksAddress>,
+        commit_outs: Vec<PoxAddress>,
         sunset_burn: u64,
         current_burn_height: u64,
     ) -> BlockstackOperationType {
@@ -1554,11 +1570,10 @@ impl BlockMinerThread {
             }
         };
 
-        let burn_fee_cap = self.config.burnchain.burn_fee_cap;
         let sunset_burn = self
             .burnchain
-            .expected_sunset_burn(self.burn_block.block_height + 1, burn_fee_cap);
-        let rest_commit = burn_fee_cap - sunset_burn;
+            .expected_sunset_burn(self.burn_block.block_height + 1, self.commit_amount);
+        let rest_commit = self.commit_amount - sunset_burn;
 
         let commit_outs = if self.burn_block.block_height + 1
             < self.burnchain.pox_constants.sunset_end
@@ -1568,7 +1583,7 @@ impl BlockMinerThread {
         {
             RewardSetInfo::into_commit_outs(recipients, self.config.is_mainnet())
         } else {
-            vec![StacksAddress::burn_address(self.config.is_mainnet())]
+            vec![PoxAddress::standard_burn_address(self.config.is_mainnet())]
         };
 
         // let's commit, but target the current burnchain tip with our modulus
@@ -1611,6 +1626,7 @@ impl BlockMinerThread {
             self.config.clone(),
             self.ongoing_commit.clone(),
         );
+        bitcoin_controller.set_utxo_manager(self.utxo_manager.clone());
 
         // NOTE: read-write access is needed in order to be able to query the recipient set.
         // This is an artifact of the way the MARF is built (see #1449)
@@ -1845,6 +1861,7 @@ impl BlockMinerThread {
                 anchored_block,
                 attempt,
                 tenure_begin,
+                commit_amount: if res { Some(self.commit_amount) } else { None },
             },
             self.keychain.clone(),
             microblock_private_key,
@@ -1890,7 +1907,8 @@ impl RelayerThread {
         )
         .expect("Database failure opening mempool");
 
-        let keychain = Keychain::default(config.node.seed.clone());
+        let keychain = Keychain::default(config.node.seed.clone())
+            .with_burnchain_signer(&config.burnchain.signer);
         let bitcoin_controller = BitcoinRegtestController::new_dummy(config.clone());
 
         RelayerThread {
@@ -1924,6 +1942,7 @@ impl RelayerThread {
 
             miner_thread: None,
             mined_stacks_block: false,
+            commit_strategy: make_commit_strategy(&config.miner),
         }
     }
 
@@ -2592,6 +2611,24 @@ impl RelayerThread {
             .submit_operation(op, &mut one_off_signer, 1)
     }
 
+    /// Sweep the miner's small UTXOs into one, if the consolidation schedule says it is time to.
+    /// This only happens while no miner thread is running, so the consolidation transaction
+    /// cannot race a block-commit for the same UTXOs.
+    fn consolidate_utxos(&mut self) {
+        if self.config.node.mock_mining || !self.miner_thread_try_join() {
+            return;
+        }
+        let burn_tip = match self.globals.get_last_sortition() {
+            Some(sn) => sn,
+            None => {
+                return;
+            }
+        };
+        let mut op_signer = self.keychain.generate_op_signer();
+        self.bitcoin_controller
+            .consolidate_utxos(&mut op_signer, burn_tip.block_height);
+    }
+
     /// Remove any block state we've mined for the given burnchain height.
     /// Return the filtered `last_mined_blocks`
     fn clear_stale_mined_blocks(burn_height: u64, last_mined_blocks: MinedBlocks) -> MinedBlocks {
@@ -2717,17 +2754,59 @@ impl RelayerThread {
             return None;
         }
 
+        let commit_amount = self.get_commit_amount(&last_burn_block)?;
+
         debug!(
             "Relayer: Spawn tenure thread";
             "height" => last_burn_block.block_height,
             "burn_header_hash" => %burn_header_hash,
+            "commit_amount" => commit_amount,
         );
 
-        let miner_thread_state =
-            BlockMinerThread::from_relayer_thread(self, registered_key, last_burn_block);
+        let miner_thread_state = BlockMinerThread::from_relayer_thread(
+            self,
+            registered_key,
+            last_burn_block,
+            commit_amount,
+        );
         Some(miner_thread_state)
     }
 
+    /// Ask the commit strategy how many sats to commit on top of `last_burn_block`, capped at
+    /// `burnchain.burn_fee_cap`.  Returns None if the strategy chose not to mine in this block.
+    /// Falls back to `burnchain.burn_fee_cap` if the strategy's inputs can't be loaded.
+    fn get_commit_amount(&mut self, last_burn_block: &BlockSnapshot) -> Option<u64> {
+        let burn_fee_cap = self.config.burnchain.burn_fee_cap;
+        let ctx = match CommitContext::load(
+            self.sortdb_ref(),
+            &self.burnchain,
+            last_burn_block,
+            self.keychain.get_burnchain_signer(),
+            burn_fee_cap,
+            get_stx_price_sats(&self.config.miner),
+        ) {
+            Ok(ctx) => ctx,
+            Err(e) => {
+                warn!(
+                    "Relayer: failed to load block-commit context; committing {}: {:?}",
+                    burn_fee_cap, &e
+                );
+                return Some(burn_fee_cap);
+            }
+        };
+        match self.commit_strategy.commit_amount(&ctx) {
+            Some(amount) => Some(amount.min(burn_fee_cap)),
+            None => {
+                info!(
+                    "Relayer: commit strategy '{}' chose not to mine at burn height {}",
+                    self.commit_strategy.name(),
+                    ctx.burn_block_height
+                );
+                None
+            }
+        }
+    }
+
     /// Try to start up a block miner thread with this given VRF key and current burnchain tip.
     /// Returns true if the thread was started; false if it was not (for any reason)
     pub fn block_miner_thread_try_start(
@@ -2937,6 +3016,11 @@ impl RelayerThread {
                     let orig_bhh = last_mined_block.orig_burn_hash.clone();
                     let tenure_begin = last_mined_block.tenure_begin;
 
+                    if let Some(commit_amount) = last_mined_block.commit_amount {
+                        self.commit_strategy
+                            .record_commit(last_mined_block.my_block_height + 1, commit_amount);
+                    }
+
                     // keep our keychain up-to-date with the miner's progress
                     self.keychain = modified_keychain;
 
@@ -3081,7 +3165,10 @@ impl RelayerThread {
                 true
             }
             RelayerDirective::ProcessTenure(consensus_hash, burn_hash, block_header_hash) => {
-                self.process_new_tenures(consensus_hash, burn_hash, block_header_hash)
+                let continue_running =
+                    self.process_new_tenures(consensus_hash, burn_hash, block_header_hash);
+                self.consolidate_utxos();
+                continue_running
             }
             RelayerDirective::RunTenure(registered_key, last_burn_block, issue_timestamp_ms) => {
                 self.block_miner_thread_try_start(
@@ -3675,14 +3762,17 @@ impl StacksNode {
             tx.commit().unwrap();
         }
 
-        // update services to indicate we can support mempool sync
+        // update services to indicate we can support mempool sync and compact blocks, and whether
+        // or not we can encrypt P2P payloads
         {
+            let mut services = (ServiceFlags::RPC as u16)
+                | (ServiceFlags::RELAY as u16)
+                | (ServiceFlags::COMPACT_BLOCKS as u16);
+            if config.connection_options.p2p_encryption {
+                services |= ServiceFlags::ENCRYPTION as u16;
+            }
             let mut tx = peerdb.tx_begin().unwrap();
-            PeerDB::set_local_services(
-                &mut tx,
-                (ServiceFlags::RPC as u16) | (ServiceFlags::RELAY as u16),
-            )
-            .unwrap();
+            PeerDB::set_local_services(&mut tx, services).unwrap();
             tx.commit().unwrap();
         }
 
@@ -3829,7 +3919,8 @@ impl StacksNode {
         let is_miner = runloop.is_miner();
         let burnchain = runloop.get_burnchain();
         let atlas_config = AtlasConfig::default(config.is_mainnet());
-        let mut keychain = Keychain::default(config.node.seed.clone());
+        let mut keychain = Keychain::default(config.node.seed.clone())
+            .with_burnchain_signer(&config.burnchain.signer);
 
         // we can call _open_ here rather than _connect_, since connect is first called in
         //   make_genesis_block
@@ -3841,6 +3932,7 @@ impl StacksNode {
         let _ = Self::setup_mempool_db(&config);
 
         let mut p2p_net = Self::setup_peer_network(&config, &atlas_config, burnchain.clone());
+        p2p_net.event_streams = runloop.get_event_dispatcher().get_event_stream_hub();
         let relayer = Relayer::from_p2p(&mut p2p_net);
 
         let local_peer = p2p_net.local_peer.clone();
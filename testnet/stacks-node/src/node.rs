@@ -10,6 +10,7 @@ use stacks::chainstate::burn::operations::{
     BlockstackOperationType, LeaderBlockCommitOp, LeaderKeyRegisterOp,
 };
 use stacks::chainstate::burn::ConsensusHash;
+use stacks::chainstate::stacks::address::PoxAddress;
 use stacks::chainstate::stacks::db::{
     ChainStateBootData, ClarityTx, StacksChainState, StacksHeaderInfo,
 };
@@ -25,6 +26,7 @@ use stacks::core::mempool::MemPoolDB;
 use stacks::cost_estimates::metrics::UnitMetric;
 use stacks::cost_estimates::UnitEstimator;
 use stacks::net::atlas::AttachmentInstance;
+use stacks::net::eventstream::{EventStreamHub, EVENT_STREAM_BUFFER_LEN};
 use stacks::net::{
     atlas::{AtlasConfig, AtlasDB},
     db::PeerDB,
@@ -287,7 +289,8 @@ impl Node {
             USE_TEST_GENESIS_CHAINSTATE
         };
 
-        let keychain = Keychain::default(config.node.seed.clone());
+        let keychain = Keychain::default(config.node.seed.clone())
+            .with_burnchain_signer(&config.burnchain.signer);
 
         let initial_balances = config
             .initial_balances
@@ -352,7 +355,14 @@ impl Node {
         let mut event_dispatcher = EventDispatcher::new();
 
         for observer in &config.events_observers {
-            event_dispatcher.register_observer(observer);
+            event_dispatcher
+                .register_observer(observer, config.events_observers_socks5_proxy.as_ref());
+        }
+        if config.connection_options.max_event_streams > 0 {
+            event_dispatcher.register_event_stream_hub(EventStreamHub::new(
+                config.connection_options.max_event_streams as usize,
+                EVENT_STREAM_BUFFER_LEN,
+            ));
         }
 
         event_dispatcher.process_boot_receipts(receipts);
@@ -378,12 +388,20 @@ impl Node {
     ) -> Node {
         let burnchain_tip = burnchain_controller.get_chain_tip();
 
-        let keychain = Keychain::default(config.node.seed.clone());
+        let keychain = Keychain::default(config.node.seed.clone())
+            .with_burnchain_signer(&config.burnchain.signer);
 
         let mut event_dispatcher = EventDispatcher::new();
 
         for observer in &config.events_observers {
-            event_dispatcher.register_observer(observer);
+            event_dispatcher
+                .register_observer(observer, config.events_observers_socks5_proxy.as_ref());
+        }
+        if config.connection_options.max_event_streams > 0 {
+            event_dispatcher.register_event_stream_hub(EventStreamHub::new(
+                config.connection_options.max_event_streams as usize,
+                EVENT_STREAM_BUFFER_LEN,
+            ));
         }
 
         let chainstate_path = config.get_chainstate_path_str();
@@ -530,7 +548,7 @@ impl Node {
         let event_dispatcher = self.event_dispatcher.clone();
         let exit_at_block_height = self.config.burnchain.process_exit_at_block_height.clone();
 
-        let p2p_net = PeerNetwork::new(
+        let mut p2p_net = PeerNetwork::new(
             peerdb,
             atlasdb,
             local_peer,
@@ -540,6 +558,7 @@ impl Node {
             self.config.connection_options.clone(),
             epochs,
         );
+        p2p_net.event_streams = event_dispatcher.get_event_stream_hub();
         let _join_handle = spawn_peer(
             self.config.is_mainnet(),
             self.config.burnchain.chain_id,
@@ -607,6 +626,7 @@ impl Node {
                 }
                 BlockstackOperationType::PreStx(_)
                 | BlockstackOperationType::StackStx(_)
+                | BlockstackOperationType::DelegateStx(_)
                 | BlockstackOperationType::TransferStx(_)
                 | BlockstackOperationType::UserBurnSupport(_) => {
                     // no-op, ops are not supported / produced at this point.
@@ -998,7 +1018,7 @@ impl Node {
         {
             RewardSetInfo::into_commit_outs(None, self.config.is_mainnet())
         } else {
-            vec![StacksAddress::burn_address(self.config.is_mainnet())]
+            vec![PoxAddress::standard_burn_address(self.config.is_mainnet())]
         };
         let burn_parent_modulus =
             (burnchain_tip.block_snapshot.block_height % BURN_BLOCK_MINED_AT_MODULUS) as u8;
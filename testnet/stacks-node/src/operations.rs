@@ -2,7 +2,9 @@ use stacks::util::secp256k1::{MessageSignature, Secp256k1PrivateKey, Secp256k1Pu
 use stacks::{burnchains::PrivateKey, util::hash::hex_bytes};
 
 pub struct BurnchainOpSigner {
-    secret_key: Secp256k1PrivateKey,
+    /// `None` if the key is held by an external signer
+    secret_key: Option<Secp256k1PrivateKey>,
+    public_key: Secp256k1PublicKey,
     is_one_off: bool,
     is_disposed: bool,
     usages: u8,
@@ -11,27 +13,39 @@ pub struct BurnchainOpSigner {
 impl BurnchainOpSigner {
     pub fn new(secret_key: Secp256k1PrivateKey, is_one_off: bool) -> BurnchainOpSigner {
         BurnchainOpSigner {
-            secret_key,
+            public_key: Secp256k1PublicKey::from_private(&secret_key),
+            secret_key: Some(secret_key),
             usages: 0,
             is_one_off,
             is_disposed: false,
         }
     }
 
-    pub fn get_sk_as_wif(&self) -> String {
-        let hex_encoded = self.secret_key.to_hex();
+    /// An op signer whose key is held by an external signer.  It can identify the miner, but it
+    /// cannot sign anything itself.
+    pub fn from_public_key(public_key: Secp256k1PublicKey, is_one_off: bool) -> BurnchainOpSigner {
+        BurnchainOpSigner {
+            secret_key: None,
+            public_key,
+            usages: 0,
+            is_one_off,
+            is_disposed: false,
+        }
+    }
+
+    pub fn get_sk_as_wif(&self) -> Option<String> {
+        let hex_encoded = self.secret_key.as_ref()?.to_hex();
         let mut as_bytes = hex_bytes(&hex_encoded).unwrap();
         as_bytes.insert(0, 0x80);
-        stacks::address::b58::check_encode_slice(&as_bytes)
+        Some(stacks::address::b58::check_encode_slice(&as_bytes))
     }
 
-    pub fn get_sk_as_hex(&self) -> String {
-        self.secret_key.to_hex()
+    pub fn get_sk_as_hex(&self) -> Option<String> {
+        self.secret_key.as_ref().map(|sk| sk.to_hex())
     }
 
     pub fn get_public_key(&mut self) -> Secp256k1PublicKey {
-        let public_key = Secp256k1PublicKey::from_private(&self.secret_key);
-        public_key
+        self.public_key
     }
 
     pub fn sign_message(&mut self, hash: &[u8]) -> Option<MessageSignature> {
@@ -39,7 +53,7 @@ impl BurnchainOpSigner {
             return None;
         }
 
-        let signature = match self.secret_key.sign(hash) {
+        let signature = match self.secret_key.as_ref()?.sign(hash) {
             Ok(r) => r,
             _ => return None,
         };
@@ -72,7 +86,7 @@ mod test {
         for (secret_key, expected_wif) in examples.iter() {
             let secp_k = Secp256k1PrivateKey::from_hex(secret_key).unwrap();
             let op_signer = BurnchainOpSigner::new(secp_k, false);
-            assert_eq!(expected_wif, &op_signer.get_sk_as_wif());
+            assert_eq!(expected_wif, &op_signer.get_sk_as_wif().unwrap());
         }
     }
 }
@@ -28,6 +28,7 @@ use stacks::chainstate::coordinator::{
 };
 use stacks::chainstate::stacks::db::{ChainStateBootData, StacksChainState};
 use stacks::net::atlas::{AtlasConfig, Attachment, AttachmentInstance, ATTACHMENTS_CHANNEL_SIZE};
+use stacks::net::eventstream::{EventStreamHub, EVENT_STREAM_BUFFER_LEN};
 use stacks::util_lib::db::Error as db_error;
 use stx_genesis::GenesisData;
 
@@ -172,7 +173,14 @@ impl RunLoop {
 
         let mut event_dispatcher = EventDispatcher::new();
         for observer in config.events_observers.iter() {
-            event_dispatcher.register_observer(observer);
+            event_dispatcher
+                .register_observer(observer, config.events_observers_socks5_proxy.as_ref());
+        }
+        if config.connection_options.max_event_streams > 0 {
+            event_dispatcher.register_event_stream_hub(EventStreamHub::new(
+                config.connection_options.max_event_streams as usize,
+                EVENT_STREAM_BUFFER_LEN,
+            ));
         }
 
         Self {
@@ -297,7 +305,8 @@ impl RunLoop {
     /// If there's a network error, then assume that we're not a miner.
     fn check_is_miner(&mut self, burnchain: &mut BitcoinRegtestController) -> bool {
         if self.config.node.miner {
-            let keychain = Keychain::default(self.config.node.seed.clone());
+            let keychain = Keychain::default(self.config.node.seed.clone())
+                .with_burnchain_signer(&self.config.burnchain.signer);
             let node_address = Keychain::address_from_burnchain_signer(
                 &keychain.get_burnchain_signer(),
                 self.config.is_mainnet(),
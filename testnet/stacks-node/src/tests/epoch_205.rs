@@ -9,6 +9,7 @@ use std::thread;
 use stacks::burnchains::Burnchain;
 use stacks::burnchains::Txid;
 use stacks::chainstate::burn::operations::BlockstackOperationType;
+use stacks::chainstate::stacks::address::PoxAddress;
 use stacks::chainstate::stacks::db::StacksChainState;
 use stacks::chainstate::stacks::StacksBlockHeader;
 use stacks::chainstate::stacks::StacksPrivateKey;
@@ -596,11 +597,11 @@ fn transition_empty_blocks() {
                 && !burnchain.is_in_prepare_phase(tip_info.burn_block_height + 1)
             {
                 vec![
-                    StacksAddress::burn_address(conf.is_mainnet()),
-                    StacksAddress::burn_address(conf.is_mainnet()),
+                    PoxAddress::standard_burn_address(conf.is_mainnet()),
+                    PoxAddress::standard_burn_address(conf.is_mainnet()),
                 ]
             } else {
-                vec![StacksAddress::burn_address(conf.is_mainnet())]
+                vec![PoxAddress::standard_burn_address(conf.is_mainnet())]
             };
 
             // let's commit
@@ -13,10 +13,14 @@ use std::{env, thread};
 
 use rusqlite::types::ToSql;
 
-use stacks::burnchains::bitcoin::address::{BitcoinAddress, BitcoinAddressType};
+use stacks::burnchains::bitcoin::address::{
+    BitcoinAddress, BitcoinAddressType, LegacyBitcoinAddress,
+};
 use stacks::burnchains::bitcoin::BitcoinNetworkType;
 use stacks::burnchains::Txid;
-use stacks::chainstate::burn::operations::{BlockstackOperationType, PreStxOp, TransferStxOp};
+use stacks::chainstate::burn::operations::{
+    BlockstackOperationType, DelegateStxOp, PreStxOp, TransferStxOp,
+};
 use stacks::chainstate::coordinator::comm::CoordinatorChannels;
 use stacks::clarity_cli::vm_execute as execute;
 use stacks::codec::StacksMessageCodec;
@@ -1552,6 +1556,120 @@ fn stx_transfer_btc_integration_test() {
     channel.stop_chains_coordinator();
 }
 
+#[test]
+#[ignore]
+fn delegate_stx_btc_integration_test() {
+    if env::var("BITCOIND_TEST") != Ok("1".into()) {
+        return;
+    }
+
+    let spender_sk = StacksPrivateKey::from_hex(SK_1).unwrap();
+    let spender_stx_addr: StacksAddress = to_addr(&spender_sk);
+    let spender_addr: PrincipalData = spender_stx_addr.into();
+
+    let delegate_sk = StacksPrivateKey::from_hex(SK_2).unwrap();
+    let delegate_stx_addr: StacksAddress = to_addr(&delegate_sk);
+
+    let (mut conf, _miner_account) = neon_integration_test_conf();
+
+    conf.initial_balances.push(InitialBalance {
+        address: spender_addr.clone(),
+        amount: 100300,
+    });
+
+    let mut btcd_controller = BitcoinCoreController::new(conf.clone());
+    btcd_controller
+        .start_bitcoind()
+        .map_err(|_e| ())
+        .expect("Failed starting bitcoind");
+
+    let mut btc_regtest_controller = BitcoinRegtestController::new(conf.clone(), None);
+
+    btc_regtest_controller.bootstrap_chain(201);
+
+    eprintln!("Chain bootstrapped...");
+
+    let mut run_loop = neon::RunLoop::new(conf.clone());
+    let blocks_processed = run_loop.get_blocks_processed_arc();
+
+    let channel = run_loop.get_coordinator_channel().unwrap();
+
+    thread::spawn(move || run_loop.start(None, 0));
+
+    // give the run loop some time to start up!
+    wait_for_runloop(&blocks_processed);
+
+    // first block wakes up the run loop
+    next_block_and_wait(&mut btc_regtest_controller, &blocks_processed);
+
+    // first block will hold our VRF registration
+    next_block_and_wait(&mut btc_regtest_controller, &blocks_processed);
+
+    // second block will be the first mined Stacks block
+    next_block_and_wait(&mut btc_regtest_controller, &blocks_processed);
+
+    // okay, let's send a pre-stx op.
+    let pre_stx_op = PreStxOp {
+        output: spender_stx_addr,
+        // to be filled in
+        txid: Txid([0u8; 32]),
+        vtxindex: 0,
+        block_height: 0,
+        burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+    };
+
+    let mut miner_signer = Keychain::default(conf.node.seed.clone()).generate_op_signer();
+
+    assert!(
+        btc_regtest_controller.submit_operation(
+            BlockstackOperationType::PreStx(pre_stx_op),
+            &mut miner_signer,
+            1
+        ),
+        "Pre-stx operation should submit successfully"
+    );
+
+    next_block_and_wait(&mut btc_regtest_controller, &blocks_processed);
+
+    // let's fire off our delegate op.
+    let delegate_stx_op = DelegateStxOp {
+        sender: spender_stx_addr,
+        delegate_to: delegate_stx_addr,
+        reward_addr: None,
+        delegated_ustx: 100_000,
+        until_burn_height: None,
+        // to be filled in
+        txid: Txid([0u8; 32]),
+        vtxindex: 0,
+        block_height: 0,
+        burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+    };
+
+    let mut spender_signer = BurnchainOpSigner::new(spender_sk, false);
+
+    assert!(
+        btc_regtest_controller.submit_operation(
+            BlockstackOperationType::DelegateStx(delegate_stx_op),
+            &mut spender_signer,
+            1
+        ),
+        "Delegate operation should submit successfully"
+    );
+
+    next_block_and_wait(&mut btc_regtest_controller, &blocks_processed);
+
+    let sortdb = btc_regtest_controller.sortdb_mut();
+    let tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn()).unwrap();
+    let delegate_ops =
+        SortitionDB::get_delegate_stx_ops(sortdb.conn(), &tip.burn_header_hash).unwrap();
+    assert_eq!(delegate_ops.len(), 1);
+    assert_eq!(delegate_ops[0].sender, spender_stx_addr);
+    assert_eq!(delegate_ops[0].delegate_to, delegate_stx_addr);
+    assert_eq!(delegate_ops[0].delegated_ustx, 100_000);
+
+    channel.stop_chains_coordinator();
+}
+
 #[test]
 #[ignore]
 fn bitcoind_resubmission_test() {
@@ -5293,7 +5411,7 @@ fn pox_integration_test() {
             .to_vec(),
     );
 
-    let pox_2_address = BitcoinAddress::from_bytes(
+    let pox_2_address = LegacyBitcoinAddress::from_bytes(
         BitcoinNetworkType::Testnet,
         BitcoinAddressType::PublicKeyHash,
         &Hash160::from_node_public_key(&pox_2_pubkey).to_bytes(),
@@ -5711,7 +5829,7 @@ fn pox_integration_test() {
         }
     }
 
-    let pox_1_address = BitcoinAddress::from_bytes(
+    let pox_1_address = LegacyBitcoinAddress::from_bytes(
         BitcoinNetworkType::Testnet,
         BitcoinAddressType::PublicKeyHash,
         &Hash160::from_node_public_key(&pox_pubkey).to_bytes(),